@@ -21,18 +21,27 @@ use strum_macros::EnumIter;
 
 use logging::detailed_json;
 use logging::file::FileAppenderBuilder;
+use networking::p2p::capture::DEFAULT_CAPTURE_FILE_MAX_SIZE;
+use networking::p2p::rate_limit::{parse_message_rates, Rate, RateLimitConfig};
 use shell::peer_manager::P2p;
 use shell::PeerConnectionThreshold;
 use storage::context::actions::action_file_storage::ActionFileStorage;
+use storage::context::actions::action_stream::{
+    ActionStreamConfiguration, ActionStreamOverflow, ActionStreamRecorder, ActionStreamStatsRef,
+    ActionStreamTarget,
+};
 use storage::context::actions::context_action_storage::ContextActionStorage;
 use storage::context::actions::ContextActionStoreBackend;
+use storage::context::gc::SupportedContextGarbageCollector;
 use storage::context::kv_store::SupportedContextKeyValueStore;
 use storage::context::ActionRecorder;
+use storage::history_mode::HistoryMode;
 use storage::initializer::{
     ContextActionsRocksDbTableInitializer, ContextKvStoreConfiguration,
     ContextRocksDbTableInitializer, DbsRocksDbTableInitializer, RocksDbConfig,
 };
-use storage::PersistentStorage;
+use storage::persistent::database::DatabasesTuning;
+use storage::{BlockMetaStorage, PersistentStorage};
 use tezos_api::environment;
 use tezos_api::environment::{TezosEnvironment, ZcashParams};
 use tezos_api::ffi::PatchContext;
@@ -158,11 +167,17 @@ pub struct Storage {
 
     // merkle cfg
     pub context_kv_store: ContextKvStoreConfiguration,
+    pub context_gc: SupportedContextGarbageCollector,
+    /// Capacity of the cache of decoded merkle entries in bytes
+    pub context_entry_cache_size: usize,
     // context actions cfg
     pub merkle_context_actions_store: Option<RocksDbConfig<ContextActionsRocksDbTableInitializer>>,
 
     // TODO: TE-447 - remove one_context when integration done
     pub one_context: bool,
+
+    // pruning cfg
+    pub history_mode: HistoryMode,
 }
 
 impl Storage {
@@ -178,7 +193,12 @@ impl Storage {
     const LRU_CACHE_SIZE_16MB: usize = 16 * 1024 * 1024;
 
     const DEFAULT_CONTEXT_KV_STORE_BACKEND: &'static str = storage::context::kv_store::ROCKSDB;
+    const DEFAULT_CONTEXT_GC: &'static str = storage::context::gc::NO_GC;
     const DEFAULT_CONTEXT_ACTIONS_RECORDER: &'static str = storage::context::actions::ROCKSDB;
+    const DEFAULT_HISTORY_MODE: &'static str = storage::history_mode::ARCHIVE;
+
+    /// Databases (directories in `tezos-data-dir`), which can be tuned by `--db-column-family-tuning`
+    const TUNED_DATABASES: &'static [&'static str] = &["db", "context", "context_actions"];
 }
 
 #[derive(Debug, Clone)]
@@ -337,6 +357,22 @@ pub fn tezos_app() -> App<'static, 'static> {
             .value_name("NUM")
             .help("Max number of threads used by database configuration. If not specified, then number of threads equal to CPU cores.")
             .validator(parse_validator_fn!(usize, "Value must be a valid number")))
+        .arg(Arg::with_name("db-column-family-tuning")
+            .long("db-column-family-tuning")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("STRING")
+            .help("Overrides RocksDB options of the column family of the database (db, context, context_actions), format: <database>/<column_family>:<option>=<value>[,<option>=<value>], options: compaction_style (level, universal), bloom_filter_bits, block_size, compression (none, snappy, lz4, zstd, zlib). Can be used multiple times, e.g. --db-column-family-tuning=db/block_meta_storage:bloom_filter_bits=10,compression=lz4")
+            .validator(|value| {
+                value
+                    .parse::<DatabasesTuning>()
+                    .map_err(|e| format!("Value must be a valid column family tuning <database>/<column_family>:<option>=<value>[,<option>=<value>], reason: {}", e))
+                    .and_then(|tuning| match tuning.databases().find(|database| !Storage::TUNED_DATABASES.contains(&database.as_str())) {
+                        Some(database) => Err(format!("Unknown database '{}', expecting one of {:?}", database, Storage::TUNED_DATABASES)),
+                        None => Ok(()),
+                    })
+            }))
         .arg(Arg::with_name("bootstrap-lookup-address")
             .long("bootstrap-lookup-address")
             .takes_value(true)
@@ -450,6 +486,41 @@ pub fn tezos_app() -> App<'static, 'static> {
             .value_name("NUM")
             .help("Maximal number of peers to connect to")
             .validator(parse_validator_fn!(usize, "Value must be a valid number")))
+        .arg(Arg::with_name("p2p-peer-bytes-limit")
+            .long("p2p-peer-bytes-limit")
+            .takes_value(true)
+            .value_name("RATE")
+            .help("Limit of bytes per second received from single peer, format: <per_sec>[/<burst>], e.g. 1048576/4194304. Default: unlimited")
+            .validator(parse_validator_fn!(Rate, "Value must be a valid rate <per_sec>[/<burst>]")))
+        .arg(Arg::with_name("p2p-global-bytes-limit")
+            .long("p2p-global-bytes-limit")
+            .takes_value(true)
+            .value_name("RATE")
+            .help("Limit of bytes per second received from all peers together, format: <per_sec>[/<burst>]. Default: unlimited")
+            .validator(parse_validator_fn!(Rate, "Value must be a valid rate <per_sec>[/<burst>]")))
+        .arg(Arg::with_name("p2p-peer-message-limits")
+            .long("p2p-peer-message-limits")
+            .takes_value(true)
+            .value_name("STRING")
+            .help("Limits of messages per second received from single peer (messages over limit are dropped and peer is penalized), format: <message_type>=<per_sec>[/<burst>][,<message_type>=<per_sec>[/<burst>]], e.g. operation=100/500,current_head=5. Default: unlimited")
+            .validator(|v| parse_message_rates(&v).map(|_| ()).map_err(|e| e.to_string())))
+        .arg(Arg::with_name("p2p-global-message-limits")
+            .long("p2p-global-message-limits")
+            .takes_value(true)
+            .value_name("STRING")
+            .help("Limits of messages per second received from all peers together (reading is slowed down over limit), format: <message_type>=<per_sec>[/<burst>][,<message_type>=<per_sec>[/<burst>]]. Default: unlimited")
+            .validator(|v| parse_message_rates(&v).map(|_| ()).map_err(|e| e.to_string())))
+        .arg(Arg::with_name("p2p-capture-file")
+            .long("p2p-capture-file")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("Path to a file, where decrypted p2p traffic of all peers is captured (file is truncated at startup), capture can be replayed without a network. Default: not captured"))
+        .arg(Arg::with_name("p2p-capture-file-max-size")
+            .long("p2p-capture-file-max-size")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Size of the p2p capture file in MB, when it is rotated (the last 3 rotated files are kept as <PATH>.1, <PATH>.2, <PATH>.3). Default: 1024")
+            .validator(parse_validator_fn!(u64, "Value must be a valid number")))
         .arg(Arg::with_name("protocol-runner")
             .long("protocol-runner")
             .takes_value(true)
@@ -563,6 +634,25 @@ pub fn tezos_app() -> App<'static, 'static> {
             .value_name("STRING")
             .possible_values(&ContextActionStoreBackend::possible_values())
             .help("Activate recording of context storage actions"))
+        .arg(Arg::with_name("actions-stream-target")
+            .long("actions-stream-target")
+            .takes_value(true)
+            .value_name("STRING")
+            .required_if("actions-store-backend", "stream")
+            .help("Where are the context actions streamed by 'stream' actions store backend - 'unix:<socket path>', 'tcp:<host:port>' or 'dir:<path>' (rotated segment files)")
+            .validator(parse_validator_fn!(ActionStreamTarget, "Value must be 'unix:<path>', 'tcp:<host:port>' or 'dir:<path>'")))
+        .arg(Arg::with_name("actions-stream-buffer-size")
+            .long("actions-stream-buffer-size")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Max count of context actions waiting to be streamed, default: 65536")
+            .validator(parse_validator_fn!(usize, "Value must be a valid number")))
+        .arg(Arg::with_name("actions-stream-overflow")
+            .long("actions-stream-overflow")
+            .takes_value(true)
+            .value_name("STRING")
+            .possible_values(&["block", "drop"])
+            .help("What to do when the stream buffer is full - 'block' waits for the consumer (default), 'drop' drops the actions"))
         .arg(Arg::with_name("one-context")
             .long("one-context")
             .takes_value(false)
@@ -572,7 +662,37 @@ pub fn tezos_app() -> App<'static, 'static> {
             .takes_value(true)
             .value_name("STRING")
             .possible_values(&SupportedContextKeyValueStore::possible_values())
-            .help("Choose the merkle storege backend - supported backends: 'rocksdb', 'sled', 'inmem', 'btree'"))
+            .help("Choose the merkle storege backend - supported backends: 'rocksdb', 'sled', 'inmem', 'btree', 'pack'"))
+        .arg(Arg::with_name("context-gc")
+            .long("context-gc")
+            .takes_value(true)
+            .value_name("STRING")
+            .possible_values(&SupportedContextGarbageCollector::possible_values())
+            .help("Choose the garbage collector of the merkle storage - supported collectors: 'none', 'incremental' (marks and sweeps in background thread), 'refcount' (persistent reference counts), pack file store collects whole packs itself for any collector except 'none'"))
+        .arg(Arg::with_name("context-gc-retained-cycles")
+            .long("context-gc-retained-cycles")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Number of cycles, which are not garbage collected from the merkle storage, default: 5")
+            .validator(parse_validator_fn!(usize, "Value must be a valid number")))
+        .arg(Arg::with_name("context-entry-cache-size")
+            .long("context-entry-cache-size")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Capacity (in MB) of the cache of decoded merkle storage entries shared by all readers, 0 disables the cache, default: 128")
+            .validator(parse_validator_fn!(usize, "Value must be a valid number")))
+        .arg(Arg::with_name("history-mode")
+            .long("history-mode")
+            .takes_value(true)
+            .value_name("STRING")
+            .possible_values(&HistoryMode::possible_values())
+            .help("Choose how much of the chain history is kept - supported modes: 'archive' (keep everything), 'full' (drop metadata of old blocks), 'rolling' (drop old blocks)"))
+        .arg(Arg::with_name("history-mode-additional-cycles")
+            .long("history-mode-additional-cycles")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Number of cycles bellow current head kept with all data in 'full' and 'rolling' history mode, default: 5")
+            .validator(parse_validator_fn!(u32, "Value must be a valid number")))
         .arg(Arg::with_name("compute-context-action-tree-hashes")
             .long("compute-context-action-tree-hashes")
             .takes_value(true)
@@ -869,6 +989,40 @@ impl Environment {
                     .parse::<bool>()
                     .expect("Provided value cannot be converted to bool"),
                 disable_mempool: args.is_present("disable-mempool"),
+                rate_limit: RateLimitConfig {
+                    peer_bytes: args.value_of("p2p-peer-bytes-limit").map(|v| {
+                        v.parse::<Rate>()
+                            .expect("Provided value cannot be converted to rate")
+                    }),
+                    global_bytes: args.value_of("p2p-global-bytes-limit").map(|v| {
+                        v.parse::<Rate>()
+                            .expect("Provided value cannot be converted to rate")
+                    }),
+                    peer_messages: args
+                        .value_of("p2p-peer-message-limits")
+                        .map(|v| {
+                            parse_message_rates(v)
+                                .expect("Provided value cannot be converted to message rates")
+                        })
+                        .unwrap_or_default(),
+                    global_messages: args
+                        .value_of("p2p-global-message-limits")
+                        .map(|v| {
+                            parse_message_rates(v)
+                                .expect("Provided value cannot be converted to message rates")
+                        })
+                        .unwrap_or_default(),
+                },
+                capture_file: args.value_of("p2p-capture-file").map(PathBuf::from),
+                capture_file_max_size: args
+                    .value_of("p2p-capture-file-max-size")
+                    .map(|v| {
+                        v.parse::<u64>()
+                            .expect("Provided value cannot be converted to number")
+                            * 1024
+                            * 1024
+                    })
+                    .unwrap_or(DEFAULT_CAPTURE_FILE_MAX_SIZE),
             },
             rpc: crate::configuration::Rpc {
                 listener_port: args
@@ -957,12 +1111,26 @@ impl Environment {
                             .expect("Provided value cannot be converted to number")
                     });
 
+                let databases_tuning: DatabasesTuning = args
+                    .values_of("db-column-family-tuning")
+                    .map(|values| {
+                        values
+                            .map(|value| {
+                                value.parse::<DatabasesTuning>().expect(
+                                    "Provided value cannot be converted to column family tuning",
+                                )
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
                 let db = RocksDbConfig {
                     cache_size: Storage::LRU_CACHE_SIZE_96MB,
                     expected_db_version: Storage::DB_STORAGE_VERSION,
                     db_path: db_path.join("db"),
                     columns: DbsRocksDbTableInitializer,
                     threads: db_threads_count,
+                    column_families_tuning: databases_tuning.database("db"),
                 };
 
                 let backends: HashSet<String> = match args.values_of("actions-store-backend") {
@@ -982,6 +1150,8 @@ impl Environment {
                                 db_path: db_path.join("context_actions"),
                                 columns: ContextActionsRocksDbTableInitializer,
                                 threads: db_context_actions_threads_count,
+                                column_families_tuning: databases_tuning
+                                    .database("context_actions"),
                             });
                             ContextActionStoreBackend::RocksDB
                         }
@@ -993,6 +1163,31 @@ impl Environment {
                                 path: db_path.join("actionfile.bin"),
                             }
                         }
+                        Ok(ContextActionStoreBackend::StreamStorage { configuration }) => {
+                            ContextActionStoreBackend::StreamStorage {
+                                configuration: ActionStreamConfiguration {
+                                    target: args
+                                        .value_of("actions-stream-target")
+                                        .unwrap()
+                                        .parse::<ActionStreamTarget>()
+                                        .expect("Provided value cannot be converted to actions stream target"),
+                                    buffer_size: args
+                                        .value_of("actions-stream-buffer-size")
+                                        .map_or(configuration.buffer_size, |size| {
+                                            size.parse::<usize>().expect(
+                                                "Provided value cannot be converted to number",
+                                            )
+                                        }),
+                                    overflow: args
+                                        .value_of("actions-stream-overflow")
+                                        .map_or(configuration.overflow, |overflow| {
+                                            overflow.parse::<ActionStreamOverflow>().expect(
+                                                "Provided value cannot be converted to overflow policy",
+                                            )
+                                        }),
+                                },
+                            }
+                        }
                         Err(e) => panic!(
                             "Invalid value: '{}', expecting one value from {:?}, error: {:?}",
                             v,
@@ -1014,6 +1209,7 @@ impl Environment {
                                 db_path: db_path.join("context"),
                                 columns: ContextRocksDbTableInitializer,
                                 threads: db_context_threads_count,
+                                column_families_tuning: databases_tuning.database("context"),
                             })
                         }
                         SupportedContextKeyValueStore::Sled { .. } => {
@@ -1025,6 +1221,11 @@ impl Environment {
                         SupportedContextKeyValueStore::BTreeMap => {
                             ContextKvStoreConfiguration::BTreeMap
                         }
+                        SupportedContextKeyValueStore::PackFile { .. } => {
+                            ContextKvStoreConfiguration::PackFile {
+                                path: db_path.join("context_pack"),
+                            }
+                        }
                     })
                     .unwrap_or_else(|e| {
                         panic!(
@@ -1034,6 +1235,60 @@ impl Environment {
                         )
                     });
 
+                let context_gc = args
+                    .value_of("context-gc")
+                    .unwrap_or(Storage::DEFAULT_CONTEXT_GC)
+                    .parse::<SupportedContextGarbageCollector>()
+                    .map(
+                        |context_gc| match args.value_of("context-gc-retained-cycles") {
+                            Some(cycles) => context_gc.with_retained_cycles(
+                                cycles
+                                    .parse::<usize>()
+                                    .expect("Provided value cannot be converted to number"),
+                            ),
+                            None => context_gc,
+                        },
+                    )
+                    .unwrap_or_else(|e| {
+                        panic!(
+                            "Expecting one value from {:?}, error: {:?}",
+                            SupportedContextGarbageCollector::possible_values(),
+                            e
+                        )
+                    });
+
+                let context_entry_cache_size = args
+                    .value_of("context-entry-cache-size")
+                    .map(|size| {
+                        size.parse::<usize>()
+                            .expect("Provided value cannot be converted to number")
+                            * 1024
+                            * 1024
+                    })
+                    .unwrap_or(storage::context::merkle::entry_cache::DEFAULT_ENTRY_CACHE_CAPACITY);
+
+                let history_mode = args
+                    .value_of("history-mode")
+                    .unwrap_or(Storage::DEFAULT_HISTORY_MODE)
+                    .parse::<HistoryMode>()
+                    .map(
+                        |history_mode| match args.value_of("history-mode-additional-cycles") {
+                            Some(cycles) => history_mode.with_additional_cycles(
+                                cycles
+                                    .parse::<u32>()
+                                    .expect("Provided value cannot be converted to number"),
+                            ),
+                            None => history_mode,
+                        },
+                    )
+                    .unwrap_or_else(|e| {
+                        panic!(
+                            "Expecting one value from {:?}, error: {:?}",
+                            HistoryMode::possible_values(),
+                            e
+                        )
+                    });
+
                 let compute_context_action_tree_hashes = args
                     .value_of("compute-context-action-tree-hashes")
                     .unwrap_or("false")
@@ -1047,6 +1302,8 @@ impl Environment {
                     compute_context_action_tree_hashes,
                     context_action_recorders,
                     context_kv_store,
+                    context_gc,
+                    context_entry_cache_size,
                     merkle_context_actions_store,
                     patch_context: {
                         match args.value_of("sandbox-patch-context-json-file") {
@@ -1090,6 +1347,7 @@ impl Environment {
                         }
                     },
                     one_context: args.is_present("one-context"),
+                    history_mode,
                 }
             },
             identity: crate::configuration::Identity {
@@ -1206,6 +1464,8 @@ impl Environment {
     pub(crate) fn build_recorders(
         &self,
         storage: &PersistentStorage,
+        action_stream_stats: &ActionStreamStatsRef,
+        log: &Logger,
     ) -> Result<Vec<Box<dyn ActionRecorder + Send>>, InvalidRecorderConfigurationError> {
         // filter all configurations and split to valid and ok
         let (oks, errors): (Vec<_>, Vec<_>) =
@@ -1215,10 +1475,12 @@ impl Environment {
                 .map(|backend| match backend {
                     storage::context::actions::ContextActionStoreBackend::RocksDB => {
                         match storage.merkle_context_actions() {
-                            Some(merkle_context_actions) => Ok(Some(Box::new(
-                                ContextActionStorage::new(merkle_context_actions, storage.seq()),
-                            )
-                                as Box<dyn ActionRecorder + Send>)),
+                            Some(merkle_context_actions) => {
+                                let recorder =
+                                    ContextActionStorage::new(merkle_context_actions, storage.seq())
+                                        .with_block_levels(BlockMetaStorage::new(storage));
+                                Ok(Some(Box::new(recorder) as Box<dyn ActionRecorder + Send>))
+                            }
                             None => Err(InvalidRecorderConfigurationError(
                                 "Missing RocksDB source 'storage.merkle_context_actions()'"
                                     .to_string(),
@@ -1226,9 +1488,17 @@ impl Environment {
                         }
                     }
                     storage::context::actions::ContextActionStoreBackend::FileStorage { path } => {
-                        Ok(Some(Box::new(ActionFileStorage::new(path.to_path_buf()))
-                            as Box<dyn ActionRecorder + Send>))
+                        let recorder = ActionFileStorage::new(path.to_path_buf())
+                            .with_block_levels(BlockMetaStorage::new(storage));
+                        Ok(Some(Box::new(recorder) as Box<dyn ActionRecorder + Send>))
                     }
+                    storage::context::actions::ContextActionStoreBackend::StreamStorage {
+                        configuration,
+                    } => Ok(Some(Box::new(ActionStreamRecorder::new(
+                        configuration.clone(),
+                        action_stream_stats.clone(),
+                        log.new(slog::o!("recorder" => "actions_stream")),
+                    )) as Box<dyn ActionRecorder + Send>)),
                     storage::context::actions::ContextActionStoreBackend::NoneBackend => Ok(None),
                 })
                 .partition(Result::is_ok);
@@ -10,7 +10,9 @@ use riker::actors::*;
 use slog::{debug, error, info, warn, Logger};
 
 use monitoring::{Monitor, WebsocketHandler};
+use networking::p2p::capture::TrafficRecorder;
 use networking::p2p::network_channel::NetworkChannel;
+use networking::p2p::rate_limit::RateLimiter;
 use networking::ShellCompatibilityVersion;
 use rpc::rpc_actor::RpcServer;
 use shell::chain_current_head_manager::ChainCurrentHeadManager;
@@ -19,18 +21,25 @@ use shell::chain_manager::ChainManager;
 use shell::context_listener::ContextListener;
 use shell::mempool::init_mempool_state_storage;
 use shell::mempool::mempool_prevalidator::MempoolPrevalidator;
-use shell::peer_manager::PeerManager;
+use shell::peer_manager::{P2pPeers, PeerManager};
 use shell::shell_channel::{ShellChannel, ShellChannelTopic, ShuttingDown};
 use shell::state::head_state::init_current_head_state;
 use shell::state::synchronization_state::init_synchronization_bootstrap_state_storage;
 use shell::stats::apply_block_stats::init_empty_apply_block_stats;
+use shell::stats::network_stats::init_empty_network_stats;
+use storage::context::actions::action_stream::ActionStreamStatsRef;
+use storage::context::actions::context_action_storage::backfill_key_prefix_index;
 use storage::context::TezedgeContext;
 use storage::initializer::{
-    initialize_merkle, initialize_rocksdb, GlobalRocksDbCacheHolder, MainChain, RocksDbCache,
+    initialize_merkle_with_db, initialize_rocksdb, recover_storage, GlobalRocksDbCacheHolder,
+    MainChain, RocksDbCache,
 };
 use storage::persistent::sequence::Sequences;
 use storage::persistent::{open_cl, CommitLogSchema};
-use storage::{resolve_storage_init_chain_data, BlockStorage, PersistentStorage, StorageInitInfo};
+use storage::{
+    resolve_storage_init_chain_data, BlockMetaStorage, BlockStorage, PeerStorage,
+    PersistentStorage, StorageInitInfo,
+};
 use tezos_api::environment;
 use tezos_api::environment::TezosEnvironmentConfiguration;
 use tezos_api::ffi::TezosRuntimeConfiguration;
@@ -178,10 +187,30 @@ fn block_on_actors(
         shell::SUPPORTED_P2P_VERSION.to_vec(),
     ));
 
+    let action_stream_stats = ActionStreamStatsRef::default();
     let context_action_recorders = env
-        .build_recorders(&persistent_storage)
+        .build_recorders(&persistent_storage, &action_stream_stats, &log)
         .expect("Failed to configure context action recorders");
 
+    // actions recorded without the key prefix index are indexed in the background
+    if let Some(merkle_context_actions) = persistent_storage.merkle_context_actions() {
+        let block_meta_storage = BlockMetaStorage::new(&persistent_storage);
+        let log = log.clone();
+        std::thread::Builder::new()
+            .name("ctx-actions-backfill".to_string())
+            .spawn(move || {
+                match backfill_key_prefix_index(merkle_context_actions, &block_meta_storage) {
+                    Ok(indexed_blocks) => {
+                        info!(log, "Context actions key prefix index backfilled"; "indexed_blocks" => indexed_blocks)
+                    }
+                    Err(e) => {
+                        warn!(log, "Failed to backfill context actions key prefix index"; "reason" => format!("{}", e))
+                    }
+                }
+            })
+            .expect("Failed to spawn context actions key prefix index backfill thread");
+    }
+
     info!(log, "Initializing protocol runners... (4/5)");
 
     // create pool for ffi protocol runner connections (used just for readonly context)
@@ -246,6 +275,19 @@ fn block_on_actors(
             .num_of_peers_for_bootstrap_threshold(),
     );
     let apply_block_stats = init_empty_apply_block_stats();
+    let network_stats = init_empty_network_stats();
+    let rate_limiter = Arc::new(RateLimiter::new(env.p2p.rate_limit.clone()));
+    let traffic_recorder = env.p2p.capture_file.as_ref().map(|capture_file| {
+        info!(log, "Capturing p2p traffic"; "capture_file" => capture_file.to_string_lossy().to_string());
+        Arc::new(
+            TrafficRecorder::create(capture_file, env.p2p.capture_file_max_size, log.clone())
+                .expect("Failed to create p2p capture file"),
+        )
+    });
+    let p2p_peers = Arc::new(P2pPeers::new(
+        env.p2p.peer_threshold,
+        PeerStorage::new(&persistent_storage),
+    ));
 
     // create tokio runtime
     let tokio_runtime = create_tokio_runtime(&env).expect("Failed to create tokio runtime");
@@ -285,6 +327,7 @@ fn block_on_actors(
         current_mempool_state_storage.clone(),
         bootstrap_state.clone(),
         apply_block_stats.clone(),
+        env.storage.history_mode,
     )
     .expect("Failed to create chain current head manager");
     let block_applier = ChainFeeder::actor(
@@ -345,6 +388,8 @@ fn block_on_actors(
         websocket_handler,
         shell_channel.clone(),
         persistent_storage.clone(),
+        network_stats.clone(),
+        rate_limiter.clone(),
         init_storage_data.chain_id.clone(),
     )
     .expect("Failed to create monitor actor");
@@ -363,6 +408,9 @@ fn block_on_actors(
         Arc::new(shell_compatibility_version.to_network_version()),
         &init_storage_data,
         is_sandbox,
+        p2p_peers.clone(),
+        network_stats,
+        action_stream_stats,
     )
     .expect("Failed to create RPC server");
 
@@ -374,7 +422,10 @@ fn block_on_actors(
         &actor_system,
         network_channel,
         shell_channel.clone(),
+        p2p_peers,
         tokio_runtime.handle().clone(),
+        rate_limiter,
+        traffic_recorder,
         identity,
         shell_compatibility_version,
         env.p2p,
@@ -524,15 +575,16 @@ fn main() {
     let sequences = Arc::new(Sequences::new(kv.clone(), 1000));
 
     // initialize merkle context
-    let merkle = Arc::new(RwLock::new(
-        initialize_merkle(
-            &env.storage.context_kv_store,
-            &main_chain,
-            &log,
-            &mut caches,
-        )
-        .expect("Failed to initialize merkle storage"),
-    ));
+    let (merkle, context_db) = initialize_merkle_with_db(
+        &env.storage.context_kv_store,
+        &env.storage.context_gc,
+        env.storage.context_entry_cache_size,
+        &main_chain,
+        &log,
+        &mut caches,
+    )
+    .expect("Failed to initialize merkle storage");
+    let merkle = Arc::new(RwLock::new(merkle));
 
     // context actions persistent db (optional)
     let merkle_context_actions_store = match env.storage.merkle_context_actions_store.as_ref() {
@@ -560,7 +612,16 @@ fn main() {
             sequences,
             merkle,
             merkle_context_actions_store,
-        );
+        )
+        .with_context_db(context_db);
+
+        // repair/roll back data partially written before shutdown
+        recover_storage(
+            &persistent_storage,
+            &tezos_env.main_chain_id().expect("Failed to decode chainId"),
+            &log,
+        )
+        .expect("Failed to recover storage");
 
         let tezedge_context = TezedgeContext::new(
             Some(BlockStorage::new(&persistent_storage)),
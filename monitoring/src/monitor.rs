@@ -9,7 +9,9 @@ use slog::{warn, Logger};
 
 use crypto::hash::ChainId;
 use networking::p2p::network_channel::{NetworkChannelMsg, NetworkChannelRef, PeerMessageReceived};
+use networking::p2p::rate_limit::RateLimiterRef;
 use shell::shell_channel::{ShellChannelMsg, ShellChannelRef};
+use shell::stats::network_stats::NetworkStatsRef;
 use shell::subscription::{
     subscribe_to_actor_terminated, subscribe_to_network_events, subscribe_to_shell_events,
     subscribe_to_shell_new_current_head,
@@ -40,6 +42,10 @@ pub struct Monitor {
     network_channel: NetworkChannelRef,
     shell_channel: ShellChannelRef,
     msg_channel: ActorRef<WebsocketHandlerMsg>,
+    /// Transfer stats of peers shared with RPC
+    network_stats: NetworkStatsRef,
+    /// Limits of the incoming p2p traffic with counters of received/dropped messages
+    rate_limiter: RateLimiterRef,
     // Monitors
     peer_monitors: HashMap<ActorUri, PeerMonitor>,
     bootstrap_monitor: BootstrapMonitor,
@@ -59,6 +65,8 @@ impl Monitor {
         msg_channel: ActorRef<WebsocketHandlerMsg>,
         shell_channel: ShellChannelRef,
         persistent_storage: PersistentStorage,
+        network_stats: NetworkStatsRef,
+        rate_limiter: RateLimiterRef,
         main_chain_id: ChainId,
     ) -> Result<MonitorRef, CreateError> {
         sys.actor_of_props::<Monitor>(
@@ -68,6 +76,8 @@ impl Monitor {
                 msg_channel,
                 shell_channel,
                 persistent_storage,
+                network_stats,
+                rate_limiter,
                 main_chain_id,
             )),
         )
@@ -96,6 +106,30 @@ impl Monitor {
             warn!(log, "Missing monitor for peer"; "peer" => msg.peer.name());
         }
     }
+
+    /// Updates shared transfer stats of all peers, must be called before peer monitors snapshot
+    fn update_network_stats(&self, log: &Logger) {
+        match self.network_stats.write() {
+            Ok(mut network_stats) => {
+                for (peer, monitor) in &self.peer_monitors {
+                    network_stats.update_peer(peer.clone(), monitor.transfer_stats());
+                }
+            }
+            Err(e) => {
+                warn!(log, "Failed to update network stats"; "reason" => format!("{}", e))
+            }
+        }
+    }
+
+    fn remove_peer_monitor(&mut self, peer: &ActorUri, log: &Logger) -> Option<PeerMonitor> {
+        match self.network_stats.write() {
+            Ok(mut network_stats) => network_stats.remove_peer(peer),
+            Err(e) => {
+                warn!(log, "Failed to remove peer from network stats"; "reason" => format!("{}", e))
+            }
+        }
+        self.peer_monitors.remove(peer)
+    }
 }
 
 impl
@@ -104,15 +138,27 @@ impl
         ActorRef<WebsocketHandlerMsg>,
         ShellChannelRef,
         PersistentStorage,
+        NetworkStatsRef,
+        RateLimiterRef,
         ChainId,
     )> for Monitor
 {
     fn create_args(
-        (event_channel, msg_channel, shell_channel, persistent_storage, main_chain_id): (
+        (
+            event_channel,
+            msg_channel,
+            shell_channel,
+            persistent_storage,
+            network_stats,
+            rate_limiter,
+            main_chain_id,
+        ): (
             NetworkChannelRef,
             ActorRef<WebsocketHandlerMsg>,
             ShellChannelRef,
             PersistentStorage,
+            NetworkStatsRef,
+            RateLimiterRef,
             ChainId,
         ),
     ) -> Self {
@@ -123,6 +169,8 @@ impl
             network_channel: event_channel,
             shell_channel,
             msg_channel,
+            network_stats,
+            rate_limiter,
             peer_monitors: HashMap::new(),
             bootstrap_monitor,
             blocks_monitor,
@@ -179,7 +227,7 @@ impl Receive<SystemEvent> for Monitor {
 
     fn receive(&mut self, ctx: &Context<Self::Msg>, msg: SystemEvent, _sender: Sender) {
         if let SystemEvent::ActorTerminated(evt) = msg {
-            if let Some(monitor) = self.peer_monitors.remove(evt.actor.uri()) {
+            if let Some(monitor) = self.remove_peer_monitor(evt.actor.uri(), &ctx.system.log()) {
                 ctx.myself.tell(
                     BroadcastSignal::PeerUpdate(PeerConnectionStatus::disconnected(
                         monitor.peer_address(),
@@ -197,8 +245,15 @@ impl Receive<BroadcastSignal> for Monitor {
     fn receive(&mut self, ctx: &Context<Self::Msg>, msg: BroadcastSignal, _sender: Sender) {
         match msg {
             BroadcastSignal::PublishPeerStatistics => {
+                self.update_network_stats(&ctx.system.log());
                 let peer_stats: HandlerMessage = self.peer_monitors.values_mut().collect();
                 self.msg_channel.tell(peer_stats, ctx.myself().into());
+
+                let payload = self.rate_limiter.stats().into();
+                self.msg_channel.tell(
+                    HandlerMessage::RateLimitStatus { payload },
+                    ctx.myself().into(),
+                );
             }
             BroadcastSignal::PublishBlocksStatistics => {
                 let bootstrap_stats: HandlerMessage = self.bootstrap_monitor.snapshot().into();
@@ -252,7 +307,7 @@ impl Receive<NetworkChannelMsg> for Monitor {
                 self.process_peer_message(msg, &ctx.system.log())
             }
             NetworkChannelMsg::PeerStalled(actor_uri) => {
-                let _ = self.peer_monitors.remove(&actor_uri);
+                let _ = self.remove_peer_monitor(&actor_uri, &ctx.system.log());
             }
             _ => (),
         }
@@ -3,6 +3,8 @@
 
 use std::{net::SocketAddr, time::Instant};
 
+use shell::stats::network_stats::PeerTransferStats;
+
 use crate::websocket::handler_messages::PeerMetrics;
 
 /// Peer specific details about transfer *FROM* peer.
@@ -42,6 +44,15 @@ impl PeerMonitor {
         self.current_transferred += incoming
     }
 
+    /// Transfer stats since the last snapshot
+    pub fn transfer_stats(&self) -> PeerTransferStats {
+        PeerTransferStats::new(
+            self.total_transferred as u64,
+            self.avg_speed(),
+            self.current_speed(),
+        )
+    }
+
     pub fn snapshot(&mut self) -> PeerMetrics {
         let ret = PeerMetrics::new(
             self.public_key.clone(),
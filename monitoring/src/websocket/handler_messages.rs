@@ -6,6 +6,8 @@ use std::iter::FromIterator;
 use serde::Serialize;
 use slog_derive::SerdeValue;
 
+use networking::p2p::rate_limit::RateLimitStats;
+
 use crate::monitors::ChainMonitor;
 use crate::monitors::PeerMonitor;
 
@@ -106,6 +108,45 @@ impl PeerMetrics {
     }
 }
 
+// -------------------------- RATE LIMIT STATS MESSAGE -------------------------- //
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageTypeMetrics {
+    message_type: &'static str,
+    received: u64,
+    dropped: u64,
+}
+
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimitMetrics {
+    received_bytes: u64,
+    received_messages: u64,
+    dropped_messages: u64,
+    throttled_ms: u64,
+    messages: Vec<MessageTypeMetrics>,
+}
+
+impl From<RateLimitStats> for RateLimitMetrics {
+    fn from(stats: RateLimitStats) -> Self {
+        Self {
+            received_bytes: stats.received_bytes,
+            received_messages: stats.received_messages,
+            dropped_messages: stats.dropped_messages,
+            throttled_ms: stats.throttled.as_millis() as u64,
+            messages: stats
+                .messages
+                .into_iter()
+                .map(|message| MessageTypeMetrics {
+                    message_type: message.message_type,
+                    received: message.received,
+                    dropped: message.dropped,
+                })
+                .collect(),
+        }
+    }
+}
+
 // -------------------------- PEER CONNECTING/DISCONNECTING MESSAGE -------------------------- //
 #[derive(Clone, Serialize, Debug)]
 #[serde(rename_all = "camelCase", tag = "status", content = "id")]
@@ -134,6 +175,7 @@ pub enum HandlerMessage {
     BlockStatus { payload: Vec<BlockMetrics> },
     BlockApplicationStatus { payload: BlockApplicationMessage },
     ChainStatus { payload: ChainMonitor },
+    RateLimitStatus { payload: RateLimitMetrics },
     NotImplemented(String),
 }
 
@@ -0,0 +1,752 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Capture of the decrypted p2p traffic, which can be replayed later (see [crate::p2p::replay]).
+//!
+//! Capture file starts with [CAPTURE_MAGIC] and version byte followed by records,
+//! all numbers are encoded as big endian:
+//! - timestamp (u64, micros since unix epoch)
+//! - kind (u8, see [CaptureEvent])
+//! - peer id marker (u16 length + utf8)
+//! - peer address (u16 length + utf8)
+//! - payload (u32 length + bytes)
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use failure::Fail;
+use slog::{debug, warn, Logger};
+
+use tezos_encoding::binary_reader::BinaryReaderError;
+use tezos_encoding::binary_writer::BinaryWriterError;
+use tezos_messages::p2p::binary_message::BinaryMessage;
+use tezos_messages::p2p::encoding::prelude::{MetadataMessage, NetworkVersion};
+
+pub const CAPTURE_MAGIC: &[u8] = b"TZCAPTURE";
+pub const CAPTURE_VERSION: u8 = 1;
+
+const KIND_CONNECTED: u8 = 0;
+const KIND_INCOMING_CHUNK: u8 = 1;
+const KIND_OUTGOING_CHUNK: u8 = 2;
+const KIND_DISCONNECTED: u8 = 3;
+
+#[derive(Debug, Fail)]
+pub enum CaptureError {
+    #[fail(display = "Capture I/O error: {}", error)]
+    IoError { error: io::Error },
+    #[fail(display = "Invalid capture header")]
+    InvalidHeader,
+    #[fail(display = "Unsupported capture version: {}", version)]
+    UnsupportedVersion { version: u8 },
+    #[fail(display = "Invalid capture record, reason: {}", reason)]
+    InvalidRecord { reason: String },
+    #[fail(display = "Capture writer is stopped")]
+    WriterStopped,
+}
+
+impl From<io::Error> for CaptureError {
+    fn from(error: io::Error) -> Self {
+        CaptureError::IoError { error }
+    }
+}
+
+impl From<BinaryReaderError> for CaptureError {
+    fn from(error: BinaryReaderError) -> Self {
+        CaptureError::InvalidRecord {
+            reason: format!("{}", error),
+        }
+    }
+}
+
+impl From<BinaryWriterError> for CaptureError {
+    fn from(error: BinaryWriterError) -> Self {
+        CaptureError::InvalidRecord {
+            reason: format!("{}", error),
+        }
+    }
+}
+
+/// Direction of the captured chunk
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    /// Received from the peer
+    Incoming,
+    /// Sent to the peer
+    Outgoing,
+}
+
+#[derive(Clone, Debug)]
+pub enum CaptureEvent {
+    /// Connection was successfully bootstrapped
+    Connected {
+        metadata: MetadataMessage,
+        network_version: NetworkVersion,
+    },
+    /// Decrypted content of the single chunk (message can be split to more chunks)
+    Chunk { direction: Direction, data: Vec<u8> },
+    /// Connection was closed
+    Disconnected,
+}
+
+#[derive(Clone, Debug)]
+pub struct CaptureRecord {
+    pub timestamp: SystemTime,
+    pub peer_id_marker: String,
+    pub peer_address: SocketAddr,
+    pub event: CaptureEvent,
+}
+
+impl CaptureRecord {
+    pub fn new(peer_id_marker: String, peer_address: SocketAddr, event: CaptureEvent) -> Self {
+        Self {
+            timestamp: SystemTime::now(),
+            peer_id_marker,
+            peer_address,
+            event,
+        }
+    }
+}
+
+/// Writes capture header
+pub fn write_header<W: Write>(writer: &mut W) -> Result<(), CaptureError> {
+    writer.write_all(CAPTURE_MAGIC)?;
+    writer.write_all(&[CAPTURE_VERSION])?;
+    Ok(())
+}
+
+/// Writes single capture record
+pub fn write_record<W: Write>(writer: &mut W, record: &CaptureRecord) -> Result<(), CaptureError> {
+    writer.write_all(&encode_record(record)?)?;
+    Ok(())
+}
+
+fn encode_record(record: &CaptureRecord) -> Result<Vec<u8>, CaptureError> {
+    let (kind, payload) = match &record.event {
+        CaptureEvent::Connected {
+            metadata,
+            network_version,
+        } => {
+            let metadata = metadata.as_bytes()?;
+            let mut payload = Vec::with_capacity(2 + metadata.len());
+            payload.extend_from_slice(&(metadata.len() as u16).to_be_bytes());
+            payload.extend_from_slice(&metadata);
+            payload.extend_from_slice(&network_version.as_bytes()?);
+            (KIND_CONNECTED, payload)
+        }
+        CaptureEvent::Chunk {
+            direction: Direction::Incoming,
+            data,
+        } => (KIND_INCOMING_CHUNK, data.clone()),
+        CaptureEvent::Chunk {
+            direction: Direction::Outgoing,
+            data,
+        } => (KIND_OUTGOING_CHUNK, data.clone()),
+        CaptureEvent::Disconnected => (KIND_DISCONNECTED, vec![]),
+    };
+    let timestamp = record
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64;
+    let peer_address = record.peer_address.to_string();
+
+    let mut bytes =
+        Vec::with_capacity(17 + record.peer_id_marker.len() + peer_address.len() + payload.len());
+    bytes.extend_from_slice(&timestamp.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend_from_slice(&(record.peer_id_marker.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(record.peer_id_marker.as_bytes());
+    bytes.extend_from_slice(&(peer_address.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(peer_address.as_bytes());
+    bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+/// Capacity of the queue of records waiting for the writer thread (chunk has at most 64kB)
+const CAPTURE_QUEUE_CAPACITY: usize = 1024;
+
+/// Default limit of the capture file size, when reached, the file is rotated
+pub const DEFAULT_CAPTURE_FILE_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Count of kept rotated capture files (`<capture_file>.1` is the most recent one)
+pub const CAPTURE_ROTATED_FILES: usize = 3;
+
+/// Records captured traffic of all peers to the single capture file.
+///
+/// Records are sent over a bounded queue to the dedicated writer thread, so network tasks never block on I/O,
+/// the writer flushes once per every batch of queued records. Records are dropped when the queue is full
+/// (dropped count is logged by the writer), so the capture may miss chunks of a slow disk.
+///
+/// When the file reaches its size limit, it is rotated (see [CAPTURE_ROTATED_FILES]), the new file starts
+/// with `Connected` records of all open connections, so every file can be replayed alone.
+pub struct TrafficRecorder {
+    sender: Option<SyncSender<CaptureRecord>>,
+    dropped: Arc<AtomicU64>,
+    writer_thread: Option<JoinHandle<()>>,
+}
+
+pub type TrafficRecorderRef = Arc<TrafficRecorder>;
+
+impl fmt::Debug for TrafficRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TrafficRecorder").finish()
+    }
+}
+
+impl TrafficRecorder {
+    /// Creates (or truncates) capture file, which is rotated when it reaches `max_file_size` bytes
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        max_file_size: u64,
+        log: Logger,
+    ) -> Result<Self, CaptureError> {
+        let path = path.as_ref().to_path_buf();
+        let writer = BufWriter::new(File::create(&path)?);
+        Self::start(
+            writer,
+            Some(FileRotation {
+                path,
+                max_file_size,
+            }),
+            log,
+        )
+    }
+
+    /// Creates recorder writing to the `writer` without any size limit
+    pub fn new<W: Write + Send + 'static>(writer: W, log: Logger) -> Result<Self, CaptureError> {
+        Self::start(writer, None, log)
+    }
+
+    fn start<W: Write + Send + 'static>(
+        mut writer: W,
+        rotation: Option<FileRotation>,
+        log: Logger,
+    ) -> Result<Self, CaptureError> {
+        write_header(&mut writer)?;
+        writer.flush()?;
+
+        let (sender, receiver) = sync_channel(CAPTURE_QUEUE_CAPACITY);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let mut capture_writer = CaptureWriter {
+            writer: Box::new(writer),
+            written: (CAPTURE_MAGIC.len() + 1) as u64,
+            file_start: (CAPTURE_MAGIC.len() + 1) as u64,
+            rotation,
+            connections: HashMap::new(),
+            dropped: dropped.clone(),
+            log,
+        };
+        let writer_thread = thread::Builder::new()
+            .name("p2p-capture-writer".to_string())
+            .spawn(move || capture_writer.run(receiver))?;
+
+        Ok(Self {
+            sender: Some(sender),
+            dropped,
+            writer_thread: Some(writer_thread),
+        })
+    }
+
+    /// Queues record for the writer thread, record is dropped, if the queue is full
+    pub fn record(&self, record: CaptureRecord) -> Result<(), CaptureError> {
+        let sender = self.sender.as_ref().ok_or(CaptureError::WriterStopped)?;
+        match sender.try_send(record) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => Err(CaptureError::WriterStopped),
+        }
+    }
+}
+
+impl Drop for TrafficRecorder {
+    /// Writes all queued records and waits for the writer thread
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(writer_thread) = self.writer_thread.take() {
+            let _ = writer_thread.join();
+        }
+    }
+}
+
+struct FileRotation {
+    path: PathBuf,
+    max_file_size: u64,
+}
+
+/// Writer thread of the [TrafficRecorder]
+struct CaptureWriter {
+    writer: Box<dyn Write + Send>,
+    /// Bytes written to the current file
+    written: u64,
+    /// Size of the header and connections written at the start of the current file
+    file_start: u64,
+    rotation: Option<FileRotation>,
+    /// Connected records of open connections by peer id marker
+    connections: HashMap<String, CaptureRecord>,
+    dropped: Arc<AtomicU64>,
+    log: Logger,
+}
+
+impl CaptureWriter {
+    fn run(&mut self, receiver: Receiver<CaptureRecord>) {
+        // wait for the first record, then write everything queued meanwhile and flush the whole batch
+        while let Ok(record) = receiver.recv() {
+            let mut result = self.write(record);
+            while result.is_ok() {
+                match receiver.try_recv() {
+                    Ok(record) => result = self.write(record),
+                    Err(_) => break,
+                }
+            }
+            if let Err(e) = result.and_then(|_| self.writer.flush().map_err(CaptureError::from)) {
+                warn!(self.log, "Failed to write p2p capture, capture is stopped"; "reason" => format!("{}", e));
+                // discard the rest, so network tasks are not bothered with the failure
+                receiver.iter().for_each(drop);
+                return;
+            }
+            let dropped = self.dropped.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                warn!(self.log, "P2p capture records dropped, writer is too slow"; "dropped" => dropped);
+            }
+        }
+    }
+
+    fn write(&mut self, record: CaptureRecord) -> Result<(), CaptureError> {
+        let bytes = encode_record(&record)?;
+        if let Some(rotation) = &self.rotation {
+            // record bigger than the limit is written to the file anyway
+            if self.written > self.file_start
+                && self.written + bytes.len() as u64 > rotation.max_file_size
+            {
+                self.rotate()?;
+            }
+        }
+        self.writer.write_all(&bytes)?;
+        self.written += bytes.len() as u64;
+
+        match &record.event {
+            CaptureEvent::Connected { .. } => {
+                self.connections
+                    .insert(record.peer_id_marker.clone(), record);
+            }
+            CaptureEvent::Disconnected => {
+                self.connections.remove(&record.peer_id_marker);
+            }
+            CaptureEvent::Chunk { .. } => (),
+        }
+        Ok(())
+    }
+
+    /// Shifts rotated files (the oldest one is removed) and starts a new capture file
+    fn rotate(&mut self) -> Result<(), CaptureError> {
+        let path = match &self.rotation {
+            Some(rotation) => rotation.path.clone(),
+            None => return Ok(()),
+        };
+        self.writer.flush()?;
+
+        let rotated_path = |index: usize| {
+            let mut rotated = path.clone().into_os_string();
+            rotated.push(format!(".{}", index));
+            PathBuf::from(rotated)
+        };
+        for index in (1..CAPTURE_ROTATED_FILES).rev() {
+            let from = rotated_path(index);
+            if from.exists() {
+                fs::rename(&from, rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&path, rotated_path(1))?;
+        debug!(self.log, "P2p capture file rotated"; "capture_file" => path.to_string_lossy().to_string());
+
+        let mut writer = BufWriter::new(File::create(&path)?);
+        write_header(&mut writer)?;
+        let mut written = (CAPTURE_MAGIC.len() + 1) as u64;
+        for record in self.connections.values() {
+            let bytes = encode_record(record)?;
+            writer.write_all(&bytes)?;
+            written += bytes.len() as u64;
+        }
+        self.writer = Box::new(writer);
+        self.written = written;
+        self.file_start = written;
+        Ok(())
+    }
+}
+
+/// Recorder of the single direction of the peer connection
+pub struct PeerTrafficRecorder {
+    recorder: TrafficRecorderRef,
+    peer_id_marker: String,
+    peer_address: SocketAddr,
+    direction: Direction,
+}
+
+impl PeerTrafficRecorder {
+    pub fn new(
+        recorder: TrafficRecorderRef,
+        peer_id_marker: String,
+        peer_address: SocketAddr,
+        direction: Direction,
+    ) -> Self {
+        Self {
+            recorder,
+            peer_id_marker,
+            peer_address,
+            direction,
+        }
+    }
+
+    pub fn connected(
+        &self,
+        metadata: MetadataMessage,
+        network_version: NetworkVersion,
+    ) -> Result<(), CaptureError> {
+        self.record(CaptureEvent::Connected {
+            metadata,
+            network_version,
+        })
+    }
+
+    pub fn chunk(&self, data: &[u8]) -> Result<(), CaptureError> {
+        self.record(CaptureEvent::Chunk {
+            direction: self.direction,
+            data: data.to_vec(),
+        })
+    }
+
+    fn record(&self, event: CaptureEvent) -> Result<(), CaptureError> {
+        self.recorder.record(CaptureRecord::new(
+            self.peer_id_marker.clone(),
+            self.peer_address,
+            event,
+        ))
+    }
+}
+
+impl Drop for PeerTrafficRecorder {
+    /// Incoming recorder is owned by the message reader, which lives as long as the connection
+    fn drop(&mut self) {
+        if self.direction == Direction::Incoming {
+            let _ = self.record(CaptureEvent::Disconnected);
+        }
+    }
+}
+
+/// Iterator over records of the capture
+pub struct CaptureReader<R> {
+    reader: R,
+}
+
+impl CaptureReader<io::BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, CaptureError> {
+        Self::new(io::BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Creates reader and validates capture header
+    pub fn new(mut reader: R) -> Result<Self, CaptureError> {
+        let mut magic = vec![0u8; CAPTURE_MAGIC.len()];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|_| CaptureError::InvalidHeader)?;
+        if magic != CAPTURE_MAGIC {
+            return Err(CaptureError::InvalidHeader);
+        }
+        let version = read_u8(&mut reader).map_err(|_| CaptureError::InvalidHeader)?;
+        if version != CAPTURE_VERSION {
+            return Err(CaptureError::UnsupportedVersion { version });
+        }
+        Ok(Self { reader })
+    }
+
+    fn read_record(&mut self, timestamp: u64) -> Result<CaptureRecord, CaptureError> {
+        let kind = read_u8(&mut self.reader)?;
+        let peer_id_marker = read_string(&mut self.reader)?;
+        let peer_address = read_string(&mut self.reader)?
+            .parse::<SocketAddr>()
+            .map_err(|e| CaptureError::InvalidRecord {
+                reason: format!("invalid peer address: {}", e),
+            })?;
+        let payload_len = read_u32(&mut self.reader)? as usize;
+        let mut payload = vec![0u8; payload_len];
+        self.reader.read_exact(&mut payload)?;
+
+        let event = match kind {
+            KIND_CONNECTED => {
+                if payload.len() < 2 {
+                    return Err(CaptureError::InvalidRecord {
+                        reason: "too short connected record".to_string(),
+                    });
+                }
+                let metadata_len = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+                if payload.len() < 2 + metadata_len {
+                    return Err(CaptureError::InvalidRecord {
+                        reason: "too short connected record".to_string(),
+                    });
+                }
+                CaptureEvent::Connected {
+                    metadata: MetadataMessage::from_bytes(&payload[2..2 + metadata_len])?,
+                    network_version: NetworkVersion::from_bytes(&payload[2 + metadata_len..])?,
+                }
+            }
+            KIND_INCOMING_CHUNK => CaptureEvent::Chunk {
+                direction: Direction::Incoming,
+                data: payload,
+            },
+            KIND_OUTGOING_CHUNK => CaptureEvent::Chunk {
+                direction: Direction::Outgoing,
+                data: payload,
+            },
+            KIND_DISCONNECTED => CaptureEvent::Disconnected,
+            kind => {
+                return Err(CaptureError::InvalidRecord {
+                    reason: format!("unknown record kind: {}", kind),
+                })
+            }
+        };
+
+        Ok(CaptureRecord {
+            timestamp: UNIX_EPOCH + Duration::from_micros(timestamp),
+            peer_id_marker,
+            peer_address,
+            event,
+        })
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<CaptureRecord, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // end of the capture is allowed just at the start of the record
+        let mut timestamp = [0u8; 8];
+        let mut read = 0;
+        while read < timestamp.len() {
+            match self.reader.read(&mut timestamp[read..]) {
+                Ok(0) if read == 0 => return None,
+                Ok(0) => {
+                    return Some(Err(CaptureError::InvalidRecord {
+                        reason: "unexpected end of capture".to_string(),
+                    }))
+                }
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+        Some(self.read_record(u64::from_be_bytes(timestamp)))
+    }
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8, io::Error> {
+    let mut bytes = [0u8; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> Result<u16, io::Error> {
+    let mut bytes = [0u8; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_be_bytes(bytes))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, io::Error> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_string<R: Read>(reader: &mut R) -> Result<String, CaptureError> {
+    let len = read_u16(reader)? as usize;
+    let mut bytes = vec![0u8; len];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| CaptureError::InvalidRecord {
+        reason: format!("invalid utf8 string: {}", e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn test_logger() -> Logger {
+        Logger::root(slog::Discard, slog::o!())
+    }
+
+    fn read_capture(path: &Path) -> Result<Vec<CaptureRecord>, CaptureError> {
+        CaptureReader::open(path)?.collect()
+    }
+
+    #[test]
+    fn test_capture_roundtrip() -> Result<(), failure::Error> {
+        let peer_address: SocketAddr = "127.0.0.1:9732".parse()?;
+        let records = vec![
+            CaptureRecord::new(
+                "idtJunqYgxTqMnAEvxGyz3PUa6CtPR".to_string(),
+                peer_address,
+                CaptureEvent::Connected {
+                    metadata: MetadataMessage::new(true, false),
+                    network_version: NetworkVersion::new("TEST_CHAIN".to_string(), 0, 1),
+                },
+            ),
+            CaptureRecord::new(
+                "idtJunqYgxTqMnAEvxGyz3PUa6CtPR".to_string(),
+                peer_address,
+                CaptureEvent::Chunk {
+                    direction: Direction::Incoming,
+                    data: vec![0, 2],
+                },
+            ),
+            CaptureRecord::new(
+                "idtJunqYgxTqMnAEvxGyz3PUa6CtPR".to_string(),
+                peer_address,
+                CaptureEvent::Chunk {
+                    direction: Direction::Outgoing,
+                    data: vec![1; 100],
+                },
+            ),
+            CaptureRecord::new(
+                "idtJunqYgxTqMnAEvxGyz3PUa6CtPR".to_string(),
+                peer_address,
+                CaptureEvent::Disconnected,
+            ),
+        ];
+
+        let mut capture = vec![];
+        write_header(&mut capture)?;
+        for record in &records {
+            write_record(&mut capture, record)?;
+        }
+
+        let read = CaptureReader::new(Cursor::new(capture))?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(records.len(), read.len());
+        for (expected, record) in records.iter().zip(read.iter()) {
+            assert_eq!(expected.peer_id_marker, record.peer_id_marker);
+            assert_eq!(expected.peer_address, record.peer_address);
+            assert_eq!(
+                expected.timestamp.duration_since(UNIX_EPOCH)?.as_micros(),
+                record.timestamp.duration_since(UNIX_EPOCH)?.as_micros()
+            );
+        }
+        match &read[0].event {
+            CaptureEvent::Connected {
+                metadata,
+                network_version,
+            } => {
+                assert!(metadata.disable_mempool());
+                assert!(!metadata.private_node());
+                assert_eq!("TEST_CHAIN", network_version.chain_name());
+                assert_eq!(1, *network_version.p2p_version());
+            }
+            event => panic!("Unexpected event: {:?}", event),
+        }
+        match &read[2].event {
+            CaptureEvent::Chunk { direction, data } => {
+                assert_eq!(Direction::Outgoing, *direction);
+                assert_eq!(&vec![1; 100], data);
+            }
+            event => panic!("Unexpected event: {:?}", event),
+        }
+        assert!(matches!(read[3].event, CaptureEvent::Disconnected));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_capture_invalid() {
+        assert!(matches!(
+            CaptureReader::new(Cursor::new(b"NOTACAPTURE".to_vec())),
+            Err(CaptureError::InvalidHeader)
+        ));
+
+        // truncated record
+        let mut capture = vec![];
+        write_header(&mut capture).unwrap();
+        capture.extend_from_slice(&[0, 0, 0]);
+        let mut reader = CaptureReader::new(Cursor::new(capture)).unwrap();
+        assert!(matches!(
+            reader.next(),
+            Some(Err(CaptureError::InvalidRecord { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_recorder_rotates_capture_file() -> Result<(), failure::Error> {
+        let dir = env::temp_dir().join(format!("tezedge_capture_rotation_{}", std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("p2p.capture");
+        let peer_address: SocketAddr = "127.0.0.1:9732".parse()?;
+
+        // every file has room for the connection and two chunks
+        let recorder = Arc::new(TrafficRecorder::create(&path, 350, test_logger())?);
+        let incoming = PeerTrafficRecorder::new(
+            recorder.clone(),
+            "idtJunqYgxTqMnAEvxGyz3PUa6CtPR".to_string(),
+            peer_address,
+            Direction::Incoming,
+        );
+        incoming.connected(
+            MetadataMessage::new(false, false),
+            NetworkVersion::new("TEST_CHAIN".to_string(), 0, 1),
+        )?;
+        for chunk in 0..10u8 {
+            incoming.chunk(&[chunk; 50])?;
+        }
+        drop(incoming);
+        // waits for the writer thread
+        drop(recorder);
+
+        let rotated_path = |index: usize| PathBuf::from(format!("{}.{}", path.display(), index));
+        assert!(rotated_path(CAPTURE_ROTATED_FILES).exists());
+        assert!(!rotated_path(CAPTURE_ROTATED_FILES + 1).exists());
+
+        // the oldest files were removed, every kept file starts with the connection
+        let mut chunks = vec![];
+        for path in (1..=CAPTURE_ROTATED_FILES)
+            .rev()
+            .map(rotated_path)
+            .chain(vec![path.clone()])
+        {
+            assert!(fs::metadata(&path)?.len() <= 350);
+            let records = read_capture(&path)?;
+            assert!(matches!(records[0].event, CaptureEvent::Connected { .. }));
+            for record in &records[1..] {
+                match &record.event {
+                    CaptureEvent::Chunk { data, .. } => chunks.push(data[0]),
+                    CaptureEvent::Disconnected => (),
+                    event => panic!("Unexpected event: {:?}", event),
+                }
+            }
+        }
+        assert!(matches!(
+            read_capture(&path)?.last().map(|record| &record.event),
+            Some(CaptureEvent::Disconnected)
+        ));
+        // just the last chunks are kept in order
+        assert_eq!(chunks, (10 - chunks.len() as u8..10).collect::<Vec<_>>());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
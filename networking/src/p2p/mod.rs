@@ -3,6 +3,9 @@
 
 //! This module handles low level p2p communication.
 
+pub mod capture;
 pub mod network_channel;
 pub mod peer;
+pub mod rate_limit;
+pub mod replay;
 pub mod stream;
@@ -11,6 +11,7 @@ use riker::actors::*;
 use tezos_messages::p2p::encoding::advertise::AdvertiseMessage;
 use tezos_messages::p2p::encoding::metadata::MetadataMessage;
 use tezos_messages::p2p::encoding::peer::PeerMessageResponse;
+use tezos_messages::p2p::encoding::swap::SwapMessage;
 
 use crate::PeerId;
 
@@ -32,6 +33,28 @@ pub struct PeerMessageReceived {
     pub message: Arc<PeerMessageResponse>,
 }
 
+/// Misbehaviour of the peer, which is scored by peer manager
+#[derive(Clone, Debug)]
+pub enum PeerPenalty {
+    /// Peer sent message, which was not requested
+    Unsolicited { message_type: &'static str },
+    /// Peer exceeded message limit, `dropped` messages were ignored since last report
+    Flooding {
+        message_type: &'static str,
+        dropped: u64,
+    },
+}
+
+impl PeerPenalty {
+    /// Penalty score added to the peer's misbehaviour score
+    pub fn score(&self) -> f64 {
+        match self {
+            PeerPenalty::Unsolicited { .. } => 10.0,
+            PeerPenalty::Flooding { dropped, .. } => 5.0 * *dropped as f64,
+        }
+    }
+}
+
 /// Network channel event message.
 #[derive(Clone, Debug)]
 pub enum NetworkChannelMsg {
@@ -46,6 +69,9 @@ pub enum NetworkChannelMsg {
     ProcessAdvertisedPeers(Arc<PeerId>, AdvertiseMessage),
     SendBootstrapPeers(Arc<PeerId>),
     ProcessFailedBootstrapAddress(PeerBootstrapFailed),
+    ProcessSwapRequest(Arc<PeerId>, SwapMessage),
+    ProcessSwapAck(Arc<PeerId>, SwapMessage),
+    PenalizePeer(Arc<PeerId>, PeerPenalty),
 }
 
 impl From<PeerMessageReceived> for NetworkChannelMsg {
@@ -5,7 +5,7 @@ use std::fmt;
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use failure::{Error, Fail};
 use futures::lock::Mutex;
@@ -33,7 +33,9 @@ use tezos_messages::p2p::binary_message::{BinaryChunk, BinaryChunkError, BinaryM
 use tezos_messages::p2p::encoding::ack::{NackInfo, NackMotive};
 use tezos_messages::p2p::encoding::prelude::*;
 
-use crate::p2p::network_channel::NetworkChannelMsg;
+use crate::p2p::capture::{Direction, PeerTrafficRecorder, TrafficRecorderRef};
+use crate::p2p::network_channel::{NetworkChannelMsg, PeerPenalty};
+use crate::p2p::rate_limit::{self, Admission, PeerRateLimiter, RateLimiterRef};
 use crate::{LocalPeerInfo, PeerId};
 
 use super::network_channel::{NetworkChannelRef, NetworkChannelTopic, PeerMessageReceived};
@@ -42,6 +44,8 @@ use super::stream::{EncryptedMessageReader, EncryptedMessageWriter, MessageStrea
 const IO_TIMEOUT: Duration = Duration::from_secs(6);
 /// There is a 90-second timeout for ping peers with GetCurrentHead
 const READ_TIMEOUT_LONG: Duration = Duration::from_secs(120);
+/// Flooding peer is reported to peer manager at most once per this interval
+const FLOODING_REPORT_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Fail)]
 pub enum PeerError {
@@ -143,6 +147,7 @@ pub struct Bootstrap {
     incoming: bool,
     disable_mempool: bool,
     private_node: bool,
+    traffic_recorder: Option<TrafficRecorderRef>,
 }
 
 impl Bootstrap {
@@ -158,6 +163,7 @@ impl Bootstrap {
             incoming: true,
             disable_mempool,
             private_node,
+            traffic_recorder: None,
         }
     }
 
@@ -173,8 +179,15 @@ impl Bootstrap {
             incoming: false,
             disable_mempool,
             private_node,
+            traffic_recorder: None,
         }
     }
+
+    /// Decrypted traffic of the bootstrapped connection will be captured by the recorder
+    pub fn with_traffic_recorder(mut self, traffic_recorder: Option<TrafficRecorderRef>) -> Self {
+        self.traffic_recorder = traffic_recorder;
+        self
+    }
 }
 
 /// Commands peer actor to send a p2p message to a remote peer.
@@ -188,6 +201,10 @@ impl SendMessage {
     pub fn new(message: Arc<PeerMessageResponse>) -> Self {
         SendMessage { message }
     }
+
+    pub fn message(&self) -> &Arc<PeerMessageResponse> {
+        &self.message
+    }
 }
 
 #[derive(Clone)]
@@ -214,6 +231,8 @@ pub struct Peer {
     net: Network,
     /// Tokio task executor
     tokio_executor: Handle,
+    /// Limits of the incoming traffic
+    rate_limiter: RateLimiterRef,
     /// bootstrap output
     peer_public_key_hash: CryptoboxPublicKeyHash,
     peer_id_marker: String,
@@ -228,18 +247,24 @@ impl Peer {
         sys: &impl ActorRefFactory,
         network_channel: NetworkChannelRef,
         tokio_executor: Handle,
+        rate_limiter: RateLimiterRef,
         info: BootstrapOutput,
     ) -> Result<PeerRef, CreateError> {
         sys.actor_of_props(
             peer_actor_name,
-            Props::new_args::<Peer, _>((network_channel, tokio_executor, info)),
+            Props::new_args::<Peer, _>((network_channel, tokio_executor, rate_limiter, info)),
         )
     }
 }
 
-impl ActorFactoryArgs<(NetworkChannelRef, Handle, BootstrapOutput)> for Peer {
+impl ActorFactoryArgs<(NetworkChannelRef, Handle, RateLimiterRef, BootstrapOutput)> for Peer {
     fn create_args(
-        (event_channel, tokio_executor, info): (NetworkChannelRef, Handle, BootstrapOutput),
+        (event_channel, tokio_executor, rate_limiter, info): (
+            NetworkChannelRef,
+            Handle,
+            RateLimiterRef,
+            BootstrapOutput,
+        ),
     ) -> Self {
         Peer {
             network_channel: event_channel,
@@ -250,6 +275,7 @@ impl ActorFactoryArgs<(NetworkChannelRef, Handle, BootstrapOutput)> for Peer {
                 socket_address: info.6,
             },
             tokio_executor,
+            rate_limiter,
             peer_public_key_hash: info.2,
             peer_id_marker: info.3,
             peer_metadata: info.4,
@@ -270,6 +296,7 @@ impl Actor for Peer {
         let system = ctx.system.clone();
         let net = self.net.clone();
         let network_channel = self.network_channel.clone();
+        let rate_limiter = self.rate_limiter.peer_limiter();
         let peer_public_key_hash = self.peer_public_key_hash.clone();
         let peer_id_marker = self.peer_id_marker.clone();
         let peer_metadata = self.peer_metadata.clone();
@@ -295,7 +322,7 @@ impl Actor for Peer {
             }, None);
 
             // begin to process incoming messages in a loop
-            begin_process_incoming(net, myself.clone(), network_channel, log).await;
+            begin_process_incoming(net, myself.clone(), peer_id, network_channel, rate_limiter, log).await;
 
             // connection to peer was closed, stop this actor
             system.stop(myself);
@@ -519,6 +546,27 @@ pub async fn bootstrap(
     match ack_received {
         AckMessage::Ack => {
             debug!(log, "Received ACK");
+            if let Some(traffic_recorder) = msg.traffic_recorder {
+                let incoming_recorder = PeerTrafficRecorder::new(
+                    traffic_recorder.clone(),
+                    peer_id_marker.clone(),
+                    msg.address,
+                    Direction::Incoming,
+                );
+                if let Err(e) = incoming_recorder.connected(
+                    metadata_received.clone(),
+                    compatible_network_version.clone(),
+                ) {
+                    warn!(log, "Failed to capture connection"; "reason" => format!("{}", e));
+                }
+                msg_rx.set_recorder(incoming_recorder);
+                msg_tx.set_recorder(PeerTrafficRecorder::new(
+                    traffic_recorder,
+                    peer_id_marker.clone(),
+                    msg.address,
+                    Direction::Outgoing,
+                ));
+            }
             Ok(BootstrapOutput(
                 Arc::new(Mutex::new(Some(msg_rx))),
                 Arc::new(Mutex::new(Some(msg_tx))),
@@ -556,7 +604,9 @@ fn generate_nonces(
 async fn begin_process_incoming(
     net: Network,
     myself: PeerRef,
+    peer_id: Arc<PeerId>,
     event_channel: NetworkChannelRef,
+    mut rate_limiter: PeerRateLimiter,
     log: Logger,
 ) {
     info!(log, "Starting to accept messages");
@@ -565,10 +615,57 @@ async fn begin_process_incoming(
     let mut rx = rx
         .take()
         .expect("Someone took ownership of the encrypted reader before the Peer");
+    let mut flooding_dropped = 0;
+    let mut flooding_reported: Option<Instant> = None;
     while net.rx_run.load(Ordering::Acquire) {
-        match timeout(READ_TIMEOUT_LONG, rx.read_message::<PeerMessageResponse>()).await {
+        match timeout(
+            READ_TIMEOUT_LONG,
+            rx.read_message_with_size::<PeerMessageResponse>(),
+        )
+        .await
+        {
             Ok(res) => match res {
-                Ok(msg) => {
+                Ok((msg, msg_size)) => {
+                    let message_type = rate_limit::message_type(msg.message());
+                    let now = Instant::now();
+                    let bytes_delay = rate_limiter.bytes_received(msg_size, now);
+                    let message_delay = match rate_limiter.message_received(message_type, now) {
+                        Admission::Accepted { delay } => delay,
+                        Admission::Dropped => {
+                            trace!(log, "Message dropped, peer exceeded message limit"; "message_type" => message_type);
+                            flooding_dropped += 1;
+                            if flooding_reported.map_or(true, |reported| {
+                                now.duration_since(reported) >= FLOODING_REPORT_INTERVAL
+                            }) {
+                                warn!(log, "Peer exceeded message limit"; "message_type" => message_type, "dropped" => flooding_dropped);
+                                event_channel.tell(
+                                    Publish {
+                                        msg: NetworkChannelMsg::PenalizePeer(
+                                            peer_id.clone(),
+                                            PeerPenalty::Flooding {
+                                                message_type,
+                                                dropped: flooding_dropped,
+                                            },
+                                        ),
+                                        topic: NetworkChannelTopic::NetworkCommands.into(),
+                                    },
+                                    Some(myself.clone().into()),
+                                );
+                                flooding_dropped = 0;
+                                flooding_reported = Some(now);
+                            }
+                            continue;
+                        }
+                    };
+
+                    // slow down reading from the peer, if any limit was exceeded
+                    let delay = std::cmp::max(bytes_delay, message_delay);
+                    if delay > Duration::from_secs(0) {
+                        trace!(log, "Reading from peer is throttled"; "delay_ms" => delay.as_millis() as u64);
+                        rate_limiter.throttled(delay);
+                        tokio::time::sleep(delay).await;
+                    }
+
                     let should_broadcast_message = net.rx_run.load(Ordering::Acquire);
                     if should_broadcast_message {
                         trace!(log, "Message parsed successfully"; "msg" => format!("{:?}", &msg));
@@ -0,0 +1,512 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Token bucket limits of the incoming p2p traffic - on received bytes and on received messages per message type,
+//! both per peer and global (shared by all peers).
+//!
+//! Exceeded byte limits and global message limits just slow down reading from the peer's socket,
+//! messages exceeding the peer's own message limit are dropped and the peer is reported as flooding.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use failure::Fail;
+
+use tezos_messages::p2p::encoding::peer::PeerMessage;
+
+/// Names of all message types, see [message_type]
+pub const MESSAGE_TYPES: [&str; 18] = [
+    "disconnect",
+    "advertise",
+    "swap_request",
+    "swap_ack",
+    "bootstrap",
+    "get_current_branch",
+    "current_branch",
+    "deactivate",
+    "get_current_head",
+    "current_head",
+    "get_block_headers",
+    "block_header",
+    "get_operations",
+    "operation",
+    "get_protocols",
+    "protocol",
+    "get_operations_for_blocks",
+    "operations_for_blocks",
+];
+
+/// Returns name of the message type, which is used by configuration of the limits and by counters
+pub fn message_type(message: &PeerMessage) -> &'static str {
+    match message {
+        PeerMessage::Disconnect => "disconnect",
+        PeerMessage::Advertise(_) => "advertise",
+        PeerMessage::SwapRequest(_) => "swap_request",
+        PeerMessage::SwapAck(_) => "swap_ack",
+        PeerMessage::Bootstrap => "bootstrap",
+        PeerMessage::GetCurrentBranch(_) => "get_current_branch",
+        PeerMessage::CurrentBranch(_) => "current_branch",
+        PeerMessage::Deactivate(_) => "deactivate",
+        PeerMessage::GetCurrentHead(_) => "get_current_head",
+        PeerMessage::CurrentHead(_) => "current_head",
+        PeerMessage::GetBlockHeaders(_) => "get_block_headers",
+        PeerMessage::BlockHeader(_) => "block_header",
+        PeerMessage::GetOperations(_) => "get_operations",
+        PeerMessage::Operation(_) => "operation",
+        PeerMessage::GetProtocols(_) => "get_protocols",
+        PeerMessage::Protocol(_) => "protocol",
+        PeerMessage::GetOperationsForBlocks(_) => "get_operations_for_blocks",
+        PeerMessage::OperationsForBlocks(_) => "operations_for_blocks",
+    }
+}
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum RateLimitError {
+    #[fail(
+        display = "Invalid rate '{}', expected <per_sec>[/<burst>] with non-zero values",
+        value
+    )]
+    InvalidRate { value: String },
+    #[fail(display = "Unknown message type '{}'", message_type)]
+    UnknownMessageType { message_type: String },
+    #[fail(
+        display = "Invalid message rate '{}', expected <message_type>=<per_sec>[/<burst>]",
+        value
+    )]
+    InvalidMessageRate { value: String },
+}
+
+/// Limit of `per_sec` tokens per second, with `burst` tokens available at once
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rate {
+    pub per_sec: u64,
+    pub burst: u64,
+}
+
+impl FromStr for Rate {
+    type Err = RateLimitError;
+
+    /// Parses `<per_sec>[/<burst>]`, burst defaults to `per_sec`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid_rate = || RateLimitError::InvalidRate {
+            value: s.to_string(),
+        };
+        let mut parts = s.trim().splitn(2, '/');
+        let per_sec = parts
+            .next()
+            .and_then(|per_sec| per_sec.trim().parse::<u64>().ok())
+            .ok_or_else(invalid_rate)?;
+        let burst = match parts.next() {
+            Some(burst) => burst.trim().parse::<u64>().map_err(|_| invalid_rate())?,
+            None => per_sec,
+        };
+        if per_sec == 0 || burst == 0 {
+            return Err(invalid_rate());
+        }
+        Ok(Rate { per_sec, burst })
+    }
+}
+
+/// Parses message limits `<message_type>=<per_sec>[/<burst>][,<message_type>=<per_sec>[/<burst>]]`
+pub fn parse_message_rates(s: &str) -> Result<HashMap<&'static str, Rate>, RateLimitError> {
+    let mut rates = HashMap::new();
+    for message_rate in s.split(',').filter(|value| !value.trim().is_empty()) {
+        let (name, rate) = match message_rate.find('=') {
+            Some(idx) => (message_rate[..idx].trim(), &message_rate[idx + 1..]),
+            None => {
+                return Err(RateLimitError::InvalidMessageRate {
+                    value: message_rate.to_string(),
+                })
+            }
+        };
+        let name = MESSAGE_TYPES
+            .iter()
+            .find(|message_type| **message_type == name)
+            .ok_or_else(|| RateLimitError::UnknownMessageType {
+                message_type: name.to_string(),
+            })?;
+        rates.insert(*name, rate.parse()?);
+    }
+    Ok(rates)
+}
+
+/// Configuration of the limits, missing limit means unlimited
+#[derive(Clone, Debug, Default)]
+pub struct RateLimitConfig {
+    /// Bytes received from single peer
+    pub peer_bytes: Option<Rate>,
+    /// Bytes received from all peers together
+    pub global_bytes: Option<Rate>,
+    /// Messages of the message type received from single peer
+    pub peer_messages: HashMap<&'static str, Rate>,
+    /// Messages of the message type received from all peers together
+    pub global_messages: HashMap<&'static str, Rate>,
+}
+
+/// Token bucket refilled by [Rate::per_sec] tokens per second up to [Rate::burst] tokens
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Creates full bucket
+    pub fn new(rate: &Rate, now: Instant) -> Self {
+        Self {
+            rate: rate.per_sec as f64,
+            burst: rate.burst as f64,
+            tokens: rate.burst as f64,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        self.last_refill = now;
+    }
+
+    /// Takes `amount` tokens, if there is enough of them
+    pub fn try_take(&mut self, amount: u64, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= amount as f64 {
+            self.tokens -= amount as f64;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Takes `amount` tokens even if there is not enough of them (bucket gets into debt),
+    /// returns how long to wait until the debt is refilled
+    pub fn take(&mut self, amount: u64, now: Instant) -> Duration {
+        self.refill(now);
+        self.tokens -= amount as f64;
+        if self.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Counters of the message type received from all peers
+#[derive(Default)]
+struct MessageCounters {
+    received: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Snapshot of the counters of the message type
+#[derive(Clone, Debug)]
+pub struct MessageTypeStats {
+    pub message_type: &'static str,
+    pub received: u64,
+    pub dropped: u64,
+}
+
+/// Snapshot of the counters of all peers since the node start
+#[derive(Clone, Debug)]
+pub struct RateLimitStats {
+    pub received_bytes: u64,
+    pub received_messages: u64,
+    pub dropped_messages: u64,
+    /// Total time of the delayed reading because of exceeded limits
+    pub throttled: Duration,
+    pub messages: Vec<MessageTypeStats>,
+}
+
+/// Global limits shared by all peers
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    global_bytes: Option<Mutex<TokenBucket>>,
+    global_messages: HashMap<&'static str, Mutex<TokenBucket>>,
+
+    received_bytes: AtomicU64,
+    throttled_micros: AtomicU64,
+    messages: HashMap<&'static str, MessageCounters>,
+}
+
+pub type RateLimiterRef = Arc<RateLimiter>;
+
+/// Result of the accounting of the received message
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Admission {
+    /// Message can be processed after `delay`
+    Accepted { delay: Duration },
+    /// Message exceeded the peer's limit and should be dropped
+    Dropped,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        let now = Instant::now();
+        Self {
+            global_bytes: config
+                .global_bytes
+                .as_ref()
+                .map(|rate| Mutex::new(TokenBucket::new(rate, now))),
+            global_messages: config
+                .global_messages
+                .iter()
+                .map(|(message_type, rate)| {
+                    (*message_type, Mutex::new(TokenBucket::new(rate, now)))
+                })
+                .collect(),
+            received_bytes: AtomicU64::new(0),
+            throttled_micros: AtomicU64::new(0),
+            messages: MESSAGE_TYPES
+                .iter()
+                .map(|message_type| (*message_type, MessageCounters::default()))
+                .collect(),
+            config,
+        }
+    }
+
+    /// Limiter without any limits, just with counters
+    pub fn unlimited() -> Self {
+        Self::new(RateLimitConfig::default())
+    }
+
+    /// Creates limiter for the new peer connection
+    pub fn peer_limiter(self: &Arc<Self>) -> PeerRateLimiter {
+        let now = Instant::now();
+        PeerRateLimiter {
+            bytes: self
+                .config
+                .peer_bytes
+                .as_ref()
+                .map(|rate| TokenBucket::new(rate, now)),
+            messages: self
+                .config
+                .peer_messages
+                .iter()
+                .map(|(message_type, rate)| (*message_type, TokenBucket::new(rate, now)))
+                .collect(),
+            global: self.clone(),
+        }
+    }
+
+    /// Records time of the delayed reading
+    pub fn throttled(&self, delay: Duration) {
+        self.throttled_micros
+            .fetch_add(delay.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> RateLimitStats {
+        let messages = MESSAGE_TYPES
+            .iter()
+            .filter_map(|message_type| {
+                self.messages
+                    .get(message_type)
+                    .map(|counters| MessageTypeStats {
+                        message_type,
+                        received: counters.received.load(Ordering::Relaxed),
+                        dropped: counters.dropped.load(Ordering::Relaxed),
+                    })
+            })
+            .collect::<Vec<_>>();
+        RateLimitStats {
+            received_bytes: self.received_bytes.load(Ordering::Relaxed),
+            received_messages: messages.iter().map(|stats| stats.received).sum(),
+            dropped_messages: messages.iter().map(|stats| stats.dropped).sum(),
+            throttled: Duration::from_micros(self.throttled_micros.load(Ordering::Relaxed)),
+            messages,
+        }
+    }
+
+    fn take_global(bucket: &Mutex<TokenBucket>, amount: u64, now: Instant) -> Duration {
+        match bucket.lock() {
+            Ok(mut bucket) => bucket.take(amount, now),
+            // limits are not critical, so poisoned lock just disables them
+            Err(_) => Duration::from_secs(0),
+        }
+    }
+}
+
+/// Limits of single peer connection (used just by the task reading from the peer)
+pub struct PeerRateLimiter {
+    global: RateLimiterRef,
+    bytes: Option<TokenBucket>,
+    messages: HashMap<&'static str, TokenBucket>,
+}
+
+impl PeerRateLimiter {
+    /// Accounts received bytes, returns how long to wait before next read
+    pub fn bytes_received(&mut self, bytes: usize, now: Instant) -> Duration {
+        self.global
+            .received_bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+
+        let peer_delay = self
+            .bytes
+            .as_mut()
+            .map_or(Duration::from_secs(0), |bucket| {
+                bucket.take(bytes as u64, now)
+            });
+        let global_delay = self
+            .global
+            .global_bytes
+            .as_ref()
+            .map_or(Duration::from_secs(0), |bucket| {
+                RateLimiter::take_global(bucket, bytes as u64, now)
+            });
+        std::cmp::max(peer_delay, global_delay)
+    }
+
+    /// Records time of the delayed reading
+    pub fn throttled(&self, delay: Duration) {
+        self.global.throttled(delay)
+    }
+
+    /// Accounts received message of the type, see [Admission]
+    pub fn message_received(&mut self, message_type: &'static str, now: Instant) -> Admission {
+        let counters = self.global.messages.get(message_type);
+        if let Some(counters) = counters {
+            counters.received.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if let Some(bucket) = self.messages.get_mut(message_type) {
+            if !bucket.try_take(1, now) {
+                if let Some(counters) = counters {
+                    counters.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                return Admission::Dropped;
+            }
+        }
+
+        let delay = self
+            .global
+            .global_messages
+            .get(message_type)
+            .map_or(Duration::from_secs(0), |bucket| {
+                RateLimiter::take_global(bucket, 1, now)
+            });
+        Admission::Accepted { delay }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rates() {
+        assert_eq!(
+            Ok(Rate {
+                per_sec: 10,
+                burst: 10
+            }),
+            "10".parse::<Rate>()
+        );
+        assert_eq!(
+            Ok(Rate {
+                per_sec: 10,
+                burst: 50
+            }),
+            "10/50".parse::<Rate>()
+        );
+        assert!("0".parse::<Rate>().is_err());
+        assert!("10/x".parse::<Rate>().is_err());
+
+        let rates = parse_message_rates("operation=100/200, current_head=5").unwrap();
+        assert_eq!(2, rates.len());
+        assert_eq!(
+            Some(&Rate {
+                per_sec: 5,
+                burst: 5
+            }),
+            rates.get("current_head")
+        );
+        assert_eq!(
+            Err(RateLimitError::UnknownMessageType {
+                message_type: "unknown".to_string()
+            }),
+            parse_message_rates("unknown=1")
+        );
+        assert!(parse_message_rates("operation").is_err());
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(
+            &Rate {
+                per_sec: 10,
+                burst: 20,
+            },
+            now,
+        );
+
+        // burst is available at once
+        assert!(bucket.try_take(20, now));
+        assert!(!bucket.try_take(1, now));
+
+        // refilled by rate, but up to burst
+        assert!(bucket.try_take(5, now + Duration::from_millis(500)));
+        assert!(!bucket.try_take(1, now + Duration::from_millis(500)));
+        assert!(bucket.try_take(20, now + Duration::from_secs(100)));
+
+        // debt has to be refilled
+        let later = now + Duration::from_secs(200);
+        assert_eq!(Duration::from_secs(0), bucket.take(20, later));
+        assert_eq!(Duration::from_secs(1), bucket.take(10, later));
+    }
+
+    #[test]
+    fn test_peer_limiter_drops_flooding_messages() {
+        let mut config = RateLimitConfig::default();
+        config.peer_messages = parse_message_rates("operations_for_blocks=1/2").unwrap();
+        config.global_bytes = Some("100".parse().unwrap());
+        let limiter = Arc::new(RateLimiter::new(config));
+
+        let now = Instant::now();
+        let mut peer_limiter = limiter.peer_limiter();
+        let accepted = Admission::Accepted {
+            delay: Duration::from_secs(0),
+        };
+        assert_eq!(
+            accepted,
+            peer_limiter.message_received("operations_for_blocks", now)
+        );
+        assert_eq!(
+            accepted,
+            peer_limiter.message_received("operations_for_blocks", now)
+        );
+        assert_eq!(
+            Admission::Dropped,
+            peer_limiter.message_received("operations_for_blocks", now)
+        );
+        // other message types are not limited
+        assert_eq!(accepted, peer_limiter.message_received("current_head", now));
+
+        // other peer has its own limit
+        assert_eq!(
+            accepted,
+            limiter
+                .peer_limiter()
+                .message_received("operations_for_blocks", now)
+        );
+
+        // global bytes limit is shared
+        assert_eq!(
+            Duration::from_secs(0),
+            peer_limiter.bytes_received(100, now)
+        );
+        assert_eq!(
+            Duration::from_secs(1),
+            limiter.peer_limiter().bytes_received(100, now)
+        );
+
+        let stats = limiter.stats();
+        assert_eq!(200, stats.received_bytes);
+        assert_eq!(5, stats.received_messages);
+        assert_eq!(1, stats.dropped_messages);
+    }
+}
@@ -0,0 +1,536 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Replay of the captured p2p traffic (see [crate::p2p::capture]) without a network.
+//!
+//! Every captured peer is represented by a fake peer actor, received messages are published
+//! to the network channel exactly as real peers do it, so actors subscribed to network events
+//! (e.g. chain manager) process them the same way. Messages sent by the node to the fake peers
+//! are just collected, so they can be checked by tests.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use failure::Fail;
+use riker::actors::*;
+use slog::{debug, info, warn, Logger};
+
+use crypto::hash::CryptoboxPublicKeyHash;
+use tezos_encoding::binary_reader::BinaryReaderErrorKind;
+use tezos_messages::p2p::binary_message::BinaryMessage;
+use tezos_messages::p2p::encoding::peer::PeerMessageResponse;
+use tezos_messages::p2p::encoding::prelude::{MetadataMessage, NetworkVersion};
+
+use crate::PeerId;
+
+use super::capture::{CaptureError, CaptureEvent, CaptureRecord, Direction};
+use super::network_channel::{
+    NetworkChannelMsg, NetworkChannelRef, NetworkChannelTopic, PeerMessageReceived,
+};
+use super::peer::{PeerMsg, PeerRef};
+
+/// Sequencer used for fake peer actor name generation
+static REPLAY_PEER_ID_GENERATOR: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Fail)]
+pub enum ReplayError {
+    #[fail(display = "Failed to read capture, reason: {}", error)]
+    CaptureError { error: CaptureError },
+    #[fail(
+        display = "Invalid captured peer id: {}, reason: {}",
+        peer_id_marker, reason
+    )]
+    InvalidPeerId {
+        peer_id_marker: String,
+        reason: String,
+    },
+    #[fail(display = "Failed to create fake peer actor, reason: {}", reason)]
+    CreateActorError { reason: String },
+}
+
+impl From<CaptureError> for ReplayError {
+    fn from(error: CaptureError) -> Self {
+        ReplayError::CaptureError { error }
+    }
+}
+
+/// Messages sent by the node to the fake peer
+#[derive(Default)]
+pub struct SentMessages {
+    messages: Mutex<Vec<Arc<PeerMessageResponse>>>,
+    sent: Condvar,
+}
+
+impl SentMessages {
+    fn push(&self, message: Arc<PeerMessageResponse>) {
+        if let Ok(mut messages) = self.messages.lock() {
+            messages.push(message);
+            self.sent.notify_all();
+        }
+    }
+
+    pub fn messages(&self) -> Vec<Arc<PeerMessageResponse>> {
+        self.messages
+            .lock()
+            .map(|messages| messages.clone())
+            .unwrap_or_default()
+    }
+
+    /// Waits until at least `count` messages are sent, returns all sent messages (less of them, if timeout elapsed)
+    pub fn wait_for(&self, count: usize, timeout: Duration) -> Vec<Arc<PeerMessageResponse>> {
+        let messages = match self.messages.lock() {
+            Ok(messages) => messages,
+            Err(_) => return vec![],
+        };
+        match self
+            .sent
+            .wait_timeout_while(messages, timeout, |messages| messages.len() < count)
+        {
+            Ok((messages, _)) => messages.clone(),
+            Err(_) => vec![],
+        }
+    }
+}
+
+pub type SentMessagesRef = Arc<SentMessages>;
+
+/// Fake peer actor, which just collects messages sent to it
+pub struct ReplayPeer {
+    sent_messages: SentMessagesRef,
+}
+
+impl ActorFactoryArgs<SentMessagesRef> for ReplayPeer {
+    fn create_args(sent_messages: SentMessagesRef) -> Self {
+        ReplayPeer { sent_messages }
+    }
+}
+
+impl Actor for ReplayPeer {
+    type Msg = PeerMsg;
+
+    fn recv(&mut self, _: &Context<Self::Msg>, msg: Self::Msg, _: Sender) {
+        match msg {
+            PeerMsg::SendMessage(msg) => self.sent_messages.push(msg.message().clone()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReplayOptions {
+    /// Replay speed relative to the captured timestamps (e.g. 2.0 is twice faster),
+    /// if not set, records are replayed without any delay
+    pub time_scale: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReplaySummary {
+    /// Count of the replayed connections
+    pub connections: usize,
+    /// Count of the messages published to network channel
+    pub incoming_messages: usize,
+    /// Count of the captured chunks sent by the node (not replayed)
+    pub outgoing_chunks: usize,
+    /// Count of the received messages, which could not be decoded
+    pub invalid_messages: usize,
+    /// Count of the records of the unknown (not connected) peers
+    pub skipped_records: usize,
+}
+
+/// State of the replayed connection
+struct ReplayConnection {
+    peer_id: Arc<PeerId>,
+    /// Received chunks of not yet complete message
+    input_data: Vec<u8>,
+    input_remaining: usize,
+}
+
+/// Feeds captured records to the network channel as fake peers
+pub struct ReplayDriver {
+    network_channel: NetworkChannelRef,
+    options: ReplayOptions,
+    /// Replayed connections by peer id marker
+    connections: HashMap<String, ReplayConnection>,
+    /// Messages sent by the node by peer id marker (kept also for disconnected peers)
+    sent_messages: HashMap<String, SentMessagesRef>,
+    /// Timestamp of the first record and time when it was replayed
+    started: Option<(SystemTime, Instant)>,
+    summary: ReplaySummary,
+    log: Logger,
+}
+
+impl ReplayDriver {
+    pub fn new(network_channel: NetworkChannelRef, options: ReplayOptions, log: Logger) -> Self {
+        Self {
+            network_channel,
+            options,
+            connections: HashMap::new(),
+            sent_messages: HashMap::new(),
+            started: None,
+            summary: ReplaySummary::default(),
+            log,
+        }
+    }
+
+    /// Replays all records, fake peers which are still connected at the end of the capture are not stopped
+    pub fn replay<I>(&mut self, sys: &ActorSystem, records: I) -> Result<ReplaySummary, ReplayError>
+    where
+        I: IntoIterator<Item = Result<CaptureRecord, CaptureError>>,
+    {
+        for record in records {
+            self.replay_record(sys, record?)?;
+        }
+        info!(self.log, "Capture replayed"; "summary" => format!("{:?}", &self.summary));
+        Ok(self.summary.clone())
+    }
+
+    /// Returns messages sent by the node to the fake peer
+    pub fn sent_messages(&self, peer_id_marker: &str) -> Vec<Arc<PeerMessageResponse>> {
+        self.sent_messages
+            .get(peer_id_marker)
+            .map(|sent_messages| sent_messages.messages())
+            .unwrap_or_default()
+    }
+
+    /// Waits until the node sends at least `count` messages to the fake peer (see [SentMessages::wait_for])
+    pub fn wait_for_sent_messages(
+        &self,
+        peer_id_marker: &str,
+        count: usize,
+        timeout: Duration,
+    ) -> Vec<Arc<PeerMessageResponse>> {
+        self.sent_messages
+            .get(peer_id_marker)
+            .map(|sent_messages| sent_messages.wait_for(count, timeout))
+            .unwrap_or_default()
+    }
+
+    /// Stops all fake peers, which are still connected
+    pub fn disconnect_all(&mut self, sys: &ActorSystem) {
+        for (_, connection) in self.connections.drain() {
+            sys.stop(connection.peer_id.peer_ref.clone());
+        }
+    }
+
+    pub fn replay_record(
+        &mut self,
+        sys: &ActorSystem,
+        record: CaptureRecord,
+    ) -> Result<(), ReplayError> {
+        self.wait_for(record.timestamp);
+
+        let CaptureRecord {
+            peer_id_marker,
+            peer_address,
+            event,
+            ..
+        } = record;
+        match event {
+            CaptureEvent::Connected {
+                metadata,
+                network_version,
+            } => self.connect(sys, peer_id_marker, peer_address, metadata, network_version),
+            CaptureEvent::Chunk {
+                direction: Direction::Incoming,
+                data,
+            } => {
+                self.receive_chunk(&peer_id_marker, data);
+                Ok(())
+            }
+            CaptureEvent::Chunk {
+                direction: Direction::Outgoing,
+                ..
+            } => {
+                self.summary.outgoing_chunks += 1;
+                Ok(())
+            }
+            CaptureEvent::Disconnected => {
+                match self.connections.remove(&peer_id_marker) {
+                    Some(connection) => {
+                        debug!(self.log, "Replaying peer disconnection"; "peer_id" => peer_id_marker);
+                        sys.stop(connection.peer_id.peer_ref.clone());
+                    }
+                    None => self.summary.skipped_records += 1,
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Waits until the captured time (scaled) elapses from the start of the replay
+    fn wait_for(&mut self, timestamp: SystemTime) {
+        let time_scale = match self.options.time_scale {
+            Some(time_scale) if time_scale > 0.0 => time_scale,
+            _ => return,
+        };
+        match self.started {
+            Some((first_timestamp, started_at)) => {
+                let captured_elapsed = timestamp
+                    .duration_since(first_timestamp)
+                    .unwrap_or_default();
+                let replay_at = started_at
+                    + Duration::from_secs_f64(captured_elapsed.as_secs_f64() / time_scale);
+                let now = Instant::now();
+                if replay_at > now {
+                    thread::sleep(replay_at - now);
+                }
+            }
+            None => self.started = Some((timestamp, Instant::now())),
+        }
+    }
+
+    fn connect(
+        &mut self,
+        sys: &ActorSystem,
+        peer_id_marker: String,
+        peer_address: SocketAddr,
+        metadata: MetadataMessage,
+        network_version: NetworkVersion,
+    ) -> Result<(), ReplayError> {
+        // peer reconnected
+        if let Some(connection) = self.connections.remove(&peer_id_marker) {
+            sys.stop(connection.peer_id.peer_ref.clone());
+        }
+
+        let peer_public_key_hash = CryptoboxPublicKeyHash::from_base58_check(&peer_id_marker)
+            .map_err(|e| ReplayError::InvalidPeerId {
+                peer_id_marker: peer_id_marker.clone(),
+                reason: format!("{}", e),
+            })?;
+        let sent_messages = self
+            .sent_messages
+            .entry(peer_id_marker.clone())
+            .or_insert_with(|| Arc::new(SentMessages::default()))
+            .clone();
+        let peer_ref: PeerRef = sys
+            .actor_of_props(
+                &format!(
+                    "replay-peer-{}",
+                    REPLAY_PEER_ID_GENERATOR.fetch_add(1, Ordering::SeqCst)
+                ),
+                Props::new_args::<ReplayPeer, _>(sent_messages),
+            )
+            .map_err(|e| ReplayError::CreateActorError {
+                reason: format!("{}", e),
+            })?;
+        let peer_id = Arc::new(PeerId::new(
+            peer_ref,
+            peer_public_key_hash,
+            peer_id_marker.clone(),
+            peer_address,
+        ));
+
+        debug!(self.log, "Replaying peer connection"; "peer_id" => peer_id_marker.clone(), "peer_ip" => peer_address.to_string());
+        self.network_channel.tell(
+            Publish {
+                msg: NetworkChannelMsg::PeerBootstrapped(
+                    peer_id.clone(),
+                    Arc::new(metadata),
+                    Arc::new(network_version),
+                ),
+                topic: NetworkChannelTopic::NetworkEvents.into(),
+            },
+            None,
+        );
+
+        self.connections.insert(
+            peer_id_marker,
+            ReplayConnection {
+                peer_id,
+                input_data: vec![],
+                input_remaining: 0,
+            },
+        );
+        self.summary.connections += 1;
+        Ok(())
+    }
+
+    /// Collects chunks until the whole message is received (the same way as the encrypted message reader)
+    fn receive_chunk(&mut self, peer_id_marker: &str, mut data: Vec<u8>) {
+        let connection = match self.connections.get_mut(peer_id_marker) {
+            Some(connection) => connection,
+            None => {
+                self.summary.skipped_records += 1;
+                return;
+            }
+        };
+
+        if connection.input_remaining >= data.len() {
+            connection.input_remaining -= data.len();
+        } else {
+            connection.input_remaining = 0;
+        }
+        connection.input_data.append(&mut data);
+        if connection.input_remaining > 0 {
+            return;
+        }
+
+        match PeerMessageResponse::from_bytes(&connection.input_data) {
+            Ok(message) => {
+                connection.input_data.clear();
+                let peer = connection.peer_id.peer_ref.clone();
+                self.network_channel.tell(
+                    Publish {
+                        msg: PeerMessageReceived {
+                            peer: peer.clone(),
+                            message: Arc::new(message),
+                        }
+                        .into(),
+                        topic: NetworkChannelTopic::NetworkEvents.into(),
+                    },
+                    Some(peer.into()),
+                );
+                self.summary.incoming_messages += 1;
+            }
+            Err(e) => match e.kind() {
+                BinaryReaderErrorKind::Underflow { bytes } => {
+                    connection.input_remaining += bytes;
+                }
+                _ => {
+                    warn!(self.log, "Failed to decode replayed message"; "peer_id" => peer_id_marker, "reason" => format!("{}", e));
+                    connection.input_data.clear();
+                    self.summary.invalid_messages += 1;
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use std::sync::mpsc;
+
+    use crypto::hash::{ChainId, HashType};
+    use tezos_messages::p2p::encoding::prelude::GetCurrentHeadMessage;
+
+    use super::*;
+    use crate::p2p::network_channel::NetworkChannel;
+    use crate::p2p::peer::SendMessage;
+
+    const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Collects descriptions of the received network events
+    struct Collector {
+        events: mpsc::Sender<String>,
+    }
+
+    impl ActorFactoryArgs<mpsc::Sender<String>> for Collector {
+        fn create_args(events: mpsc::Sender<String>) -> Self {
+            Collector { events }
+        }
+    }
+
+    impl Actor for Collector {
+        type Msg = NetworkChannelMsg;
+
+        fn recv(&mut self, _: &Context<Self::Msg>, msg: Self::Msg, _: Sender) {
+            let event = match msg {
+                NetworkChannelMsg::PeerBootstrapped(peer_id, ..) => {
+                    format!("bootstrapped {}", peer_id.peer_address)
+                }
+                NetworkChannelMsg::PeerMessageReceived(received) => {
+                    format!("received {:?}", received.message.message())
+                }
+                _ => return,
+            };
+            let _ = self.events.send(event);
+        }
+    }
+
+    #[test]
+    fn test_replay_capture() -> Result<(), failure::Error> {
+        let log = Logger::root(slog::Discard, slog::o!());
+        let sys = SystemBuilder::new()
+            .name("test_replay_capture")
+            .log(log.clone())
+            .create()?;
+        let network_channel = NetworkChannel::actor(&sys)?;
+        let (events, received_events) = mpsc::channel();
+        let collector = sys.actor_of_props("collector", Props::new_args::<Collector, _>(events))?;
+        network_channel.tell(
+            Subscribe {
+                actor: Box::new(collector),
+                topic: NetworkChannelTopic::NetworkEvents.into(),
+            },
+            None,
+        );
+
+        let peer_id_marker =
+            CryptoboxPublicKeyHash::try_from(vec![1; HashType::CryptoboxPublicKeyHash.size()])?
+                .to_base58_check();
+        let peer_address: SocketAddr = "127.0.0.1:9732".parse()?;
+        let message: PeerMessageResponse =
+            GetCurrentHeadMessage::new(ChainId::try_from(vec![1, 2, 3, 4])?).into();
+        let message_bytes = message.as_bytes()?;
+        let (first_chunk, second_chunk) = message_bytes.split_at(3);
+        let record = |event| CaptureRecord::new(peer_id_marker.clone(), peer_address, event);
+
+        let mut driver = ReplayDriver::new(network_channel, ReplayOptions::default(), log);
+        let summary = driver.replay(
+            &sys,
+            vec![
+                Ok(record(CaptureEvent::Connected {
+                    metadata: MetadataMessage::new(false, false),
+                    network_version: NetworkVersion::new("TEST_CHAIN".to_string(), 0, 1),
+                })),
+                // message split to two chunks
+                Ok(record(CaptureEvent::Chunk {
+                    direction: Direction::Incoming,
+                    data: first_chunk.to_vec(),
+                })),
+                Ok(record(CaptureEvent::Chunk {
+                    direction: Direction::Outgoing,
+                    data: vec![0, 2],
+                })),
+                Ok(record(CaptureEvent::Chunk {
+                    direction: Direction::Incoming,
+                    data: second_chunk.to_vec(),
+                })),
+                // message with unsupported tag
+                Ok(record(CaptureEvent::Chunk {
+                    direction: Direction::Incoming,
+                    data: vec![0, 0, 0, 2, 0xff, 0xff],
+                })),
+            ],
+        )?;
+
+        assert_eq!(
+            ReplaySummary {
+                connections: 1,
+                incoming_messages: 1,
+                outgoing_chunks: 1,
+                invalid_messages: 1,
+                skipped_records: 0,
+            },
+            summary
+        );
+
+        // messages sent by node are collected by fake peer
+        let peer_ref = driver.connections[&peer_id_marker].peer_id.peer_ref.clone();
+        peer_ref.tell(SendMessage::new(Arc::new(message)), None);
+
+        assert_eq!(
+            1,
+            driver
+                .wait_for_sent_messages(&peer_id_marker, 1, RECV_TIMEOUT)
+                .len()
+        );
+        // subscription is processed by the network channel before the published events
+        assert_eq!(
+            format!("bootstrapped {}", peer_address),
+            received_events.recv_timeout(RECV_TIMEOUT)?
+        );
+        assert!(received_events
+            .recv_timeout(RECV_TIMEOUT)?
+            .starts_with("received GetCurrentHead"));
+        // invalid message is not published
+        assert!(received_events.try_recv().is_err());
+
+        driver.disconnect_all(&sys);
+        Ok(())
+    }
+}
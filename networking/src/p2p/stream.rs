@@ -11,7 +11,7 @@ use std::io;
 use bytes::Buf;
 use failure::_core::time::Duration;
 use failure::{Error, Fail};
-use slog::{trace, FnValue, Logger};
+use slog::{trace, warn, FnValue, Logger};
 use tokio::io::{
     AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadHalf, WriteHalf,
 };
@@ -28,6 +28,8 @@ use tezos_messages::p2p::binary_message::{
     BinaryChunk, BinaryChunkError, BinaryMessage, CONTENT_LENGTH_FIELD_BYTES,
 };
 
+use super::capture::PeerTrafficRecorder;
+
 /// Max allowed content length in bytes when taking into account extra data added by encryption
 pub const CONTENT_LENGTH_MAX: usize =
     tezos_messages::p2p::binary_message::CONTENT_LENGTH_MAX - crypto::crypto_box::BOX_ZERO_BYTES;
@@ -222,6 +224,8 @@ pub struct EncryptedMessageWriterBase<W> {
     tx: MessageWriterBase<W>,
     /// To encrypt data
     crypto: Crypto,
+    /// Optional capture of the sent decrypted chunks
+    recorder: Option<PeerTrafficRecorder>,
     /// Logger
     log: Logger,
 }
@@ -239,10 +243,16 @@ impl<W: AsyncWrite + Unpin> EncryptedMessageWriterBase<W> {
                 precomputed_key,
                 nonce: nonce_local,
             },
+            recorder: None,
             log,
         }
     }
 
+    /// Starts to capture all sent chunks
+    pub fn set_recorder(&mut self, recorder: PeerTrafficRecorder) {
+        self.recorder = Some(recorder);
+    }
+
     pub async fn write_message<'a>(
         &'a mut self,
         message: &'a impl BinaryMessage,
@@ -251,6 +261,12 @@ impl<W: AsyncWrite + Unpin> EncryptedMessageWriterBase<W> {
         trace!(self.log, "Writing message"; "message" => FnValue(|_| hex::encode(&message_bytes)));
 
         for chunk_content_bytes in message_bytes.chunks(CONTENT_LENGTH_MAX) {
+            if let Some(recorder) = &self.recorder {
+                if let Err(e) = recorder.chunk(chunk_content_bytes) {
+                    warn!(self.log, "Failed to capture sent chunk"; "reason" => format!("{}", e));
+                }
+            }
+
             let message_bytes_encrypted = match self.crypto.encrypt(&chunk_content_bytes) {
                 Ok(msg) => msg,
                 Err(error) => return Err(StreamError::FailedToEncryptMessage { error }),
@@ -274,6 +290,8 @@ pub struct EncryptedMessageReaderBase<A> {
     crypto: Crypto,
     /// Incoming message reader
     rx: MessageReaderBase<A>,
+    /// Optional capture of the received decrypted chunks
+    recorder: Option<PeerTrafficRecorder>,
     /// Logger
     log: Logger,
 }
@@ -292,26 +310,50 @@ impl<A: AsyncRead + Unpin + Send> EncryptedMessageReaderBase<A> {
                 precomputed_key,
                 nonce: nonce_remote,
             },
+            recorder: None,
             log,
         }
     }
 
+    /// Starts to capture all received chunks
+    pub fn set_recorder(&mut self, recorder: PeerTrafficRecorder) {
+        self.recorder = Some(recorder);
+    }
+
     /// Consume content of inner message reader into specific message
     pub async fn read_message<M>(&mut self) -> Result<M, StreamError>
+    where
+        M: BinaryMessage,
+    {
+        self.read_message_with_size()
+            .await
+            .map(|(message, _)| message)
+    }
+
+    /// Consume content of inner message reader into specific message,
+    /// returns also count of the bytes read from the network stream
+    pub async fn read_message_with_size<M>(&mut self) -> Result<(M, usize), StreamError>
     where
         M: BinaryMessage,
     {
         let mut input_remaining = 0;
         let mut input_data = vec![];
+        let mut input_size = 0;
 
         loop {
             // read
             let message_encrypted = self.rx.read_message().await?;
+            input_size += message_encrypted.raw().len();
 
             // decrypt
             match self.crypto.decrypt(&message_encrypted.content()) {
                 Ok(mut message_decrypted) => {
                     trace!(self.log, "Message received"; "message" => FnValue(|_| hex::encode(&message_decrypted)));
+                    if let Some(recorder) = &self.recorder {
+                        if let Err(e) = recorder.chunk(&message_decrypted) {
+                            warn!(self.log, "Failed to capture received chunk"; "reason" => format!("{}", e));
+                        }
+                    }
                     if input_remaining >= message_decrypted.len() {
                         input_remaining -= message_decrypted.len();
                     } else {
@@ -322,7 +364,7 @@ impl<A: AsyncRead + Unpin + Send> EncryptedMessageReaderBase<A> {
 
                     if input_remaining == 0 {
                         match M::from_bytes(&input_data) {
-                            Ok(message) => break Ok(message),
+                            Ok(message) => break Ok((message, input_size)),
                             Err(e) => match e.kind() {
                                 BinaryReaderErrorKind::Underflow { bytes } => {
                                     input_remaining += bytes
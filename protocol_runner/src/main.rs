@@ -160,8 +160,8 @@ mod tezos {
         BeginConstructionRequest, CommitGenesisResult, ComputePathError, ComputePathRequest,
         ComputePathResponse, GenesisChain, GetDataError, HelpersPreapplyBlockRequest,
         HelpersPreapplyError, HelpersPreapplyResponse, InitProtocolContextResult, PatchContext,
-        PrevalidatorWrapper, ProtocolDataError, ProtocolOverrides, ProtocolRpcError,
-        ProtocolRpcRequest, ProtocolRpcResponse, TezosRuntimeConfiguration,
+        PrevalidatorWrapper, ProtocolDataError, ProtocolLoadError, ProtocolOverrides,
+        ProtocolRpcError, ProtocolRpcRequest, ProtocolRpcResponse, TezosRuntimeConfiguration,
         TezosRuntimeConfigurationError, TezosStorageInitError, ValidateOperationError,
         ValidateOperationRequest, ValidateOperationResponse,
     };
@@ -265,5 +265,12 @@ mod tezos {
         ) -> Result<(), ProtocolDataError> {
             assert_encoding_for_protocol_data(protocol_hash, protocol_data)
         }
+
+        fn load_protocol(
+            protocol_hash: ProtocolHash,
+            protocol: Vec<u8>,
+        ) -> Result<(), ProtocolLoadError> {
+            load_protocol(protocol_hash, protocol)
+        }
     }
 }
@@ -13,6 +13,8 @@ use serde_json::Value;
 use crypto::hash::{chain_id_to_b58_string, BlockHash, ChainId, ContextHash};
 use shell::mempool::mempool_prevalidator::MempoolPrevalidator;
 use storage::chain_meta_storage::ChainMetaStorageReader;
+use storage::context::merkle::{ContextProof, Node, NodeKind, TreeProof};
+use storage::context::{ContextDiff, ContextKey, ContextValue};
 use storage::{
     BlockHeaderWithHash, BlockJsonData, BlockMetaStorage, BlockMetaStorageReader, BlockStorage,
     BlockStorageReader, ChainMetaStorage,
@@ -275,6 +277,161 @@ where
     }
 }
 
+/// Difference of context between two blocks, keys are joined with '/' and values are hex encoded
+#[derive(Serialize, Debug, Clone)]
+pub struct ContextDiffInfo {
+    added: Vec<ContextKeyValueInfo>,
+    removed: Vec<ContextKeyValueInfo>,
+    modified: Vec<ModifiedContextKeyValueInfo>,
+    /// Cursor of the next page, if there are more changes
+    next_cursor: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ContextKeyValueInfo {
+    key: String,
+    value: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ModifiedContextKeyValueInfo {
+    key: String,
+    old_value: String,
+    new_value: String,
+}
+
+impl From<ContextDiff> for ContextDiffInfo {
+    fn from(diff: ContextDiff) -> Self {
+        let to_key_value_info = |(key, value): (ContextKey, ContextValue)| ContextKeyValueInfo {
+            key: key.join("/"),
+            value: hex::encode(value),
+        };
+        Self {
+            added: diff.added.into_iter().map(to_key_value_info).collect(),
+            removed: diff.removed.into_iter().map(to_key_value_info).collect(),
+            modified: diff
+                .modified
+                .into_iter()
+                .map(|(key, old_value, new_value)| ModifiedContextKeyValueInfo {
+                    key: key.join("/"),
+                    old_value: hex::encode(old_value),
+                    new_value: hex::encode(new_value),
+                })
+                .collect(),
+            next_cursor: diff.next_cursor.map(|key| key.join("/")),
+        }
+    }
+}
+
+/// Proof of context value, which can be verified against context hash of the block, all hashes and the value are hex encoded
+#[derive(Serialize, Debug, Clone)]
+pub struct ContextProofInfo {
+    context_hash: String,
+    key: String,
+    value: String,
+    /// Proofs of trees on the key path, from the root tree to the tree containing the value
+    trees: Vec<ContextTreeProofInfo>,
+    parent_commit_hash: Option<String>,
+    time: u64,
+    author: String,
+    message: String,
+}
+
+/// Proof of one tree, `inodes` are present only for trees with more than 256 entries
+#[derive(Serialize, Debug, Clone)]
+pub struct ContextTreeProofInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inodes: Option<Vec<ContextProofInodeInfo>>,
+    siblings: Vec<ContextProofNodeInfo>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ContextProofInodeInfo {
+    children: usize,
+    pointers: Vec<ContextProofPointerInfo>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ContextProofPointerInfo {
+    index: u8,
+    hash: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ContextProofNodeInfo {
+    name: String,
+    kind: String,
+    hash: String,
+}
+
+impl ContextProofNodeInfo {
+    fn new((name, node): (String, Node)) -> Self {
+        Self {
+            name,
+            kind: match node.node_kind {
+                NodeKind::Leaf => "leaf".to_string(),
+                NodeKind::NonLeaf => "non_leaf".to_string(),
+            },
+            hash: hex::encode(node.entry_hash.as_ref()),
+        }
+    }
+}
+
+impl ContextTreeProofInfo {
+    fn new(proof: TreeProof) -> Self {
+        match proof {
+            TreeProof::Short { siblings } => Self {
+                inodes: None,
+                siblings: siblings
+                    .into_iter()
+                    .map(ContextProofNodeInfo::new)
+                    .collect(),
+            },
+            TreeProof::Long { inodes, siblings } => Self {
+                inodes: Some(
+                    inodes
+                        .into_iter()
+                        .map(|inode| ContextProofInodeInfo {
+                            children: inode.children,
+                            pointers: inode
+                                .pointers
+                                .into_iter()
+                                .map(|(index, hash)| ContextProofPointerInfo {
+                                    index,
+                                    hash: hex::encode(hash),
+                                })
+                                .collect(),
+                        })
+                        .collect(),
+                ),
+                siblings: siblings
+                    .into_iter()
+                    .map(ContextProofNodeInfo::new)
+                    .collect(),
+            },
+        }
+    }
+}
+
+impl ContextProofInfo {
+    pub fn new(context_hash: &ContextHash, key: &ContextKey, proof: ContextProof) -> Self {
+        Self {
+            context_hash: context_hash.to_base58_check(),
+            key: key.join("/"),
+            value: hex::encode(proof.value),
+            trees: proof
+                .trees
+                .into_iter()
+                .map(ContextTreeProofInfo::new)
+                .collect(),
+            parent_commit_hash: proof.parent_commit_hash.map(hex::encode),
+            time: proof.time,
+            author: proof.author,
+            message: proof.message,
+        }
+    }
+}
+
 // TODO: refactor errors
 /// Struct is defining Error message response, there are different keys is these messages so only needed one are defined for each message
 #[derive(Serialize, Debug, Clone)]
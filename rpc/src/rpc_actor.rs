@@ -11,8 +11,11 @@ use tokio::runtime::Handle;
 
 use crypto::hash::ChainId;
 use shell::mempool::CurrentMempoolStateStorageRef;
+use shell::peer_manager::P2pPeersRef;
 use shell::shell_channel::{ShellChannelMsg, ShellChannelRef};
+use shell::stats::network_stats::NetworkStatsRef;
 use shell::subscription::subscribe_to_shell_new_current_head;
+use storage::context::actions::action_stream::ActionStreamStatsRef;
 use storage::context::TezedgeContext;
 use storage::PersistentStorage;
 use storage::{BlockHeaderWithHash, StorageInitInfo};
@@ -65,6 +68,9 @@ impl RpcServer {
         network_version: Arc<NetworkVersion>,
         init_storage_data: &StorageInitInfo,
         is_sandbox: bool,
+        p2p_peers: P2pPeersRef,
+        network_stats: NetworkStatsRef,
+        action_stream_stats: ActionStreamStatsRef,
     ) -> Result<RpcServerRef, CreateError> {
         let shared_state = Arc::new(RwLock::new(RpcCollectedState {
             current_head: load_current_head(
@@ -89,6 +95,9 @@ impl RpcServer {
                 network_version,
                 persistent_storage,
                 current_mempool_state_storage,
+                p2p_peers,
+                network_stats,
+                action_stream_stats,
                 tezedge_context,
                 tezos_readonly_api,
                 tezos_readonly_prevalidation_api,
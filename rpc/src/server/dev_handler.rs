@@ -5,11 +5,16 @@ use failure::format_err;
 use hyper::{Body, Request};
 use slog::warn;
 
+use tezos_messages::p2p::encoding::block_header::Level;
+
 use crate::helpers::{parse_block_hash, parse_chain_id, SlimBlockData, MAIN_CHAIN_ID};
 use crate::server::{HasSingleValue, Params, Query, RpcServiceEnvironment};
 use crate::services::{base_services, dev_services};
 use crate::{empty, make_json_response, required_param, result_to_json_response, ServiceResult};
 
+/// Default page size of `/dev/chains/main/actions/keys/*prefix`
+const DEFAULT_KEY_PREFIX_ACTIONS_LIMIT: usize = 50;
+
 pub async fn dev_blocks(
     _: Request<Body>,
     _: Params,
@@ -128,6 +133,39 @@ pub async fn dev_action_cursor(
     )
 }
 
+pub async fn dev_key_prefix_actions(
+    _: Request<Body>,
+    params: Params,
+    query: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let prefix = required_param!(params, "prefix")?;
+    let from_level = query
+        .get_str("from_level")
+        .map(str::parse::<Level>)
+        .transpose()?;
+    let to_level = query
+        .get_str("to_level")
+        .map(str::parse::<Level>)
+        .transpose()?;
+    let cursor_id = query.get_u64("cursor_id");
+    let limit = query
+        .get_usize("limit")
+        .unwrap_or(DEFAULT_KEY_PREFIX_ACTIONS_LIMIT);
+
+    result_to_json_response(
+        dev_services::get_key_prefix_actions(
+            prefix,
+            from_level,
+            to_level,
+            cursor_id,
+            limit,
+            env.persistent_storage(),
+        ),
+        env.log(),
+    )
+}
+
 pub async fn block_action_details(
     _: Request<Body>,
     params: Params,
@@ -143,12 +181,23 @@ pub async fn block_action_details(
     )
 }
 
-#[allow(dead_code)]
 pub async fn dev_stats_storage(
     _: Request<Body>,
     _: Params,
     _: Query,
     env: RpcServiceEnvironment,
+) -> ServiceResult {
+    result_to_json_response(
+        dev_services::get_stats_storage(env.persistent_storage()),
+        env.log(),
+    )
+}
+
+pub async fn dev_stats_context_actions(
+    _: Request<Body>,
+    _: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
 ) -> ServiceResult {
     result_to_json_response(
         crate::services::stats_services::compute_storage_stats(
@@ -160,6 +209,15 @@ pub async fn dev_stats_storage(
     )
 }
 
+pub async fn dev_stats_context_actions_stream(
+    _: Request<Body>,
+    _: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    make_json_response(&env.action_stream_stats().stats())
+}
+
 pub async fn dev_stats_memory(
     _: Request<Body>,
     _: Params,
@@ -202,6 +260,35 @@ pub async fn context_stats(
     )
 }
 
+pub async fn dev_context_diff(
+    _: Request<Body>,
+    params: Params,
+    query: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    // TODO: TE-221 - add optional chain_id to params mapping
+    let chain_id_param = MAIN_CHAIN_ID;
+    let chain_id = parse_chain_id(chain_id_param, &env)?;
+    let from_block_hash =
+        parse_block_hash(&chain_id, required_param!(params, "from_block_id")?, &env)?;
+    let to_block_hash = parse_block_hash(&chain_id, required_param!(params, "to_block_id")?, &env)?;
+    let prefix = query.get_str("prefix");
+    let cursor = query.get_str("cursor");
+    let limit = query.get_usize("limit").unwrap_or(1000);
+
+    result_to_json_response(
+        dev_services::get_context_diff(
+            &from_block_hash,
+            &to_block_hash,
+            prefix,
+            cursor,
+            limit,
+            &env,
+        ),
+        env.log(),
+    )
+}
+
 /// Get the version string
 pub async fn dev_version(
     _: Request<Body>,
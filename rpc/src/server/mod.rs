@@ -15,7 +15,10 @@ use slog::{error, Logger};
 
 use crypto::hash::{BlockHash, ChainId};
 use shell::mempool::CurrentMempoolStateStorageRef;
+use shell::peer_manager::P2pPeersRef;
 use shell::shell_channel::ShellChannelRef;
+use shell::stats::network_stats::NetworkStatsRef;
+use storage::context::actions::action_stream::ActionStreamStatsRef;
 use storage::context::TezedgeContext;
 use storage::PersistentStorage;
 use tezos_api::environment::TezosEnvironmentConfiguration;
@@ -26,6 +29,7 @@ use crate::rpc_actor::{RpcCollectedStateRef, RpcServerRef};
 use crate::{error_with_message, not_found, options};
 
 mod dev_handler;
+mod network_handler;
 mod protocol_handler;
 mod router;
 mod shell_handler;
@@ -42,6 +46,12 @@ pub struct RpcServiceEnvironment {
     #[get = "pub(crate)"]
     current_mempool_state_storage: CurrentMempoolStateStorageRef,
     #[get = "pub(crate)"]
+    p2p_peers: P2pPeersRef,
+    #[get = "pub(crate)"]
+    network_stats: NetworkStatsRef,
+    #[get = "pub(crate)"]
+    action_stream_stats: ActionStreamStatsRef,
+    #[get = "pub(crate)"]
     tezedge_context: TezedgeContext,
     #[get = "pub(crate)"]
     state: RpcCollectedStateRef,
@@ -79,6 +89,9 @@ impl RpcServiceEnvironment {
         network_version: Arc<NetworkVersion>,
         persistent_storage: &PersistentStorage,
         current_mempool_state_storage: CurrentMempoolStateStorageRef,
+        p2p_peers: P2pPeersRef,
+        network_stats: NetworkStatsRef,
+        action_stream_stats: ActionStreamStatsRef,
         tezedge_context: &TezedgeContext,
         tezos_readonly_api: Arc<TezosApiConnectionPool>,
         tezos_readonly_prevalidation_api: Arc<TezosApiConnectionPool>,
@@ -97,6 +110,9 @@ impl RpcServiceEnvironment {
             network_version,
             persistent_storage: persistent_storage.clone(),
             current_mempool_state_storage,
+            p2p_peers,
+            network_stats,
+            action_stream_stats,
             tezedge_context: tezedge_context.clone(),
             main_chain_id,
             main_chain_genesis_hash,
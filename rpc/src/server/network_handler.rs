@@ -0,0 +1,217 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::net::SocketAddr;
+
+use failure::format_err;
+use hyper::{Body, Request};
+
+use crate::server::{HasSingleValue, Params, Query, RpcServiceEnvironment};
+use crate::services::network_services;
+use crate::{
+    not_found, required_param, result_option_to_json_response, result_to_empty_json_response,
+    result_to_json_response, ServiceResult,
+};
+
+pub async fn network_stat(
+    _: Request<Body>,
+    _: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    result_to_json_response(network_services::get_network_stat(&env), env.log())
+}
+
+pub async fn network_connections(
+    _: Request<Body>,
+    _: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    result_to_json_response(network_services::get_connections(&env), env.log())
+}
+
+pub async fn network_connection(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let peer_id = required_param!(params, "peer_id")?;
+    result_option_to_json_response(network_services::get_connection(peer_id, &env), env.log())
+}
+
+pub async fn network_peers(
+    _: Request<Body>,
+    _: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    result_to_json_response(network_services::get_peers(&env), env.log())
+}
+
+pub async fn network_peer(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let peer_id = required_param!(params, "peer_id")?;
+    result_option_to_json_response(network_services::get_peer(peer_id, &env), env.log())
+}
+
+pub async fn network_points(
+    _: Request<Body>,
+    _: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    result_to_json_response(network_services::get_points(&env), env.log())
+}
+
+pub async fn network_point(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let point = parse_point(&params)?;
+    result_option_to_json_response(network_services::get_point(&point, &env), env.log())
+}
+
+pub async fn network_point_ban(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let point = parse_point(&params)?;
+    result_to_empty_json_response(network_services::ban_point(point, &env), env.log())
+}
+
+pub async fn network_point_unban(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let point = parse_point(&params)?;
+    result_to_empty_json_response(network_services::unban_point(point, &env), env.log())
+}
+
+pub async fn network_point_trust(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let point = parse_point(&params)?;
+    result_to_empty_json_response(network_services::trust_point(point, &env), env.log())
+}
+
+pub async fn network_point_untrust(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let point = parse_point(&params)?;
+    result_to_empty_json_response(network_services::untrust_point(point, &env), env.log())
+}
+
+pub async fn network_point_banned(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let point = parse_point(&params)?;
+    result_to_json_response(network_services::is_point_banned(&point, &env), env.log())
+}
+
+pub async fn network_peer_ban(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    match peer_point(&params, &env)? {
+        Some(point) => {
+            result_to_empty_json_response(network_services::ban_point(point, &env), env.log())
+        }
+        None => not_found(),
+    }
+}
+
+pub async fn network_peer_unban(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    match peer_point(&params, &env)? {
+        Some(point) => {
+            result_to_empty_json_response(network_services::unban_point(point, &env), env.log())
+        }
+        None => not_found(),
+    }
+}
+
+pub async fn network_peer_trust(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    match peer_point(&params, &env)? {
+        Some(point) => {
+            result_to_empty_json_response(network_services::trust_point(point, &env), env.log())
+        }
+        None => not_found(),
+    }
+}
+
+pub async fn network_peer_untrust(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    match peer_point(&params, &env)? {
+        Some(point) => {
+            result_to_empty_json_response(network_services::untrust_point(point, &env), env.log())
+        }
+        None => not_found(),
+    }
+}
+
+pub async fn network_peer_banned(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    match peer_point(&params, &env)? {
+        Some(point) => {
+            result_to_json_response(network_services::is_point_banned(&point, &env), env.log())
+        }
+        None => not_found(),
+    }
+}
+
+/// Parses point (ip:port) from params
+fn parse_point(params: &Params) -> Result<SocketAddr, failure::Error> {
+    let point = required_param!(params, "point")?;
+    point
+        .parse::<SocketAddr>()
+        .map_err(|e| format_err!("Invalid point '{}', reason: {}", point, e))
+}
+
+/// Returns point of the peer, disconnected peer is resolved to its last known point
+fn peer_point(
+    params: &Params,
+    env: &RpcServiceEnvironment,
+) -> Result<Option<SocketAddr>, failure::Error> {
+    let peer_id = required_param!(params, "peer_id")?;
+    network_services::get_peer_point(peer_id, env)
+}
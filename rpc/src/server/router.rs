@@ -8,7 +8,7 @@ use std::sync::Arc;
 use hyper::{Body, Method, Request};
 use path_tree::PathTree;
 
-use crate::server::{dev_handler, protocol_handler, shell_handler};
+use crate::server::{dev_handler, network_handler, protocol_handler, shell_handler};
 use crate::server::{HResult, MethodHandler, Params, Query, RpcServiceEnvironment};
 
 macro_rules! hash_set {
@@ -148,6 +148,11 @@ pub(crate) fn create_routes(is_sandbox: bool, one_context: bool) -> PathTree<Met
             "/chains/:chain_id/blocks/:block_id/context/raw/bytes/*any",
             shell_handler::context_raw_bytes,
         );
+        routes.handle(
+            hash_set![Method::GET],
+            "/chains/:chain_id/blocks/:block_id/context/proof/*any",
+            shell_handler::context_proof,
+        );
     }
     routes.handle(
         hash_set![Method::GET],
@@ -245,6 +250,16 @@ pub(crate) fn create_routes(is_sandbox: bool, one_context: bool) -> PathTree<Met
         "/dev/chains/main/actions/contracts/:contract_address",
         dev_handler::dev_action_cursor,
     );
+    routes.handle(
+        hash_set![Method::GET],
+        "/dev/chains/main/actions/keys/*prefix",
+        dev_handler::dev_key_prefix_actions,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/dev/chains/main/context/diff/:from_block_id/:to_block_id",
+        dev_handler::dev_context_diff,
+    );
     routes.handle(
         hash_set![Method::GET],
         "/dev/version",
@@ -265,7 +280,21 @@ pub(crate) fn create_routes(is_sandbox: bool, one_context: bool) -> PathTree<Met
         "/stats/context",
         dev_handler::context_stats,
     );
-    //routes.handle(hash_set![Method::GET], "/stats/storage", dev_handler::dev_stats_storage);
+    routes.handle(
+        hash_set![Method::GET],
+        "/stats/storage",
+        dev_handler::dev_stats_storage,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/stats/context_actions",
+        dev_handler::dev_stats_context_actions,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/stats/context_actions/stream",
+        dev_handler::dev_stats_context_actions_stream,
+    );
 
     // DEPRECATED in ocaml but still used by python tests
     routes.handle(
@@ -274,6 +303,93 @@ pub(crate) fn create_routes(is_sandbox: bool, one_context: bool) -> PathTree<Met
         shell_handler::node_version,
     );
 
+    // Network rpcs
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/stat",
+        network_handler::network_stat,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/connections",
+        network_handler::network_connections,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/connections/:peer_id",
+        network_handler::network_connection,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/peers",
+        network_handler::network_peers,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/peers/:peer_id",
+        network_handler::network_peer,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/peers/:peer_id/ban",
+        network_handler::network_peer_ban,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/peers/:peer_id/unban",
+        network_handler::network_peer_unban,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/peers/:peer_id/trust",
+        network_handler::network_peer_trust,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/peers/:peer_id/untrust",
+        network_handler::network_peer_untrust,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/peers/:peer_id/banned",
+        network_handler::network_peer_banned,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/points",
+        network_handler::network_points,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/points/:point",
+        network_handler::network_point,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/points/:point/ban",
+        network_handler::network_point_ban,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/points/:point/unban",
+        network_handler::network_point_unban,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/points/:point/trust",
+        network_handler::network_point_trust,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/points/:point/untrust",
+        network_handler::network_point_untrust,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/points/:point/banned",
+        network_handler::network_point_banned,
+    );
+
     routes
 }
 
@@ -266,6 +266,22 @@ pub async fn context_raw_bytes(
     )
 }
 
+pub async fn context_proof(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let chain_id = parse_chain_id(required_param!(params, "chain_id")?, &env)?;
+    let block_hash = parse_block_hash(&chain_id, required_param!(params, "block_id")?, &env)?;
+    let key = params.get_str("any");
+
+    result_to_json_response(
+        base_services::get_context_proof(&block_hash, key, &env),
+        env.log(),
+    )
+}
+
 pub async fn mempool_pending_operations(
     _: Request<Body>,
     params: Params,
@@ -16,7 +16,7 @@ use tezos_messages::p2p::encoding::version::NetworkVersion;
 
 use crate::helpers::{
     get_context_hash, BlockHeaderInfo, BlockHeaderShellInfo, BlockMetadata, BlockOperation,
-    BlockOperations, BlockValidationPass, FullBlockInfo, NodeVersion, Protocols,
+    BlockOperations, BlockValidationPass, ContextProofInfo, FullBlockInfo, NodeVersion, Protocols,
 };
 use crate::server::RpcServiceEnvironment;
 
@@ -137,6 +137,25 @@ pub(crate) fn get_context_raw_bytes(
         .get_context_tree_by_prefix(&ctx_hash, &key_prefix, depth)?)
 }
 
+/// Builds proof of context value under key (relative to "/data") for the block
+pub(crate) fn get_context_proof(
+    block_hash: &BlockHash,
+    key: Option<&str>,
+    env: &RpcServiceEnvironment,
+) -> Result<ContextProofInfo, failure::Error> {
+    // we assume that root is at "/data", the same as for raw bytes
+    let mut context_key = context_key!("data");
+    if let Some(key) = key {
+        context_key.extend(key.split('/').map(|s| s.to_string()));
+    };
+
+    let ctx_hash = get_context_hash(block_hash, env)?;
+    let proof = env
+        .tezedge_context()
+        .get_context_proof(&ctx_hash, &context_key)?;
+    Ok(ContextProofInfo::new(&ctx_hash, &context_key, proof))
+}
+
 /// Extract the current_protocol and the next_protocol from the block metadata
 pub(crate) fn get_block_protocols(
     chain_id: &ChainId,
@@ -1,21 +1,28 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::ops::Bound;
+
 use slog::Logger;
 
 use crypto::hash::BlockHash;
 use shell::stats::memory::{Memory, MemoryData, MemoryStatsResult};
 use storage::context::actions::context_action_storage::{
-    contract_id_to_contract_address_for_index, ContextActionBlockDetails, ContextActionFilters,
-    ContextActionJson, ContextActionRecordValue, ContextActionStorageReader, ContextActionType,
+    contract_id_to_contract_address_for_index, extract_block_hash, resolve_block_level,
+    ContextActionBlockDetails, ContextActionFilters, ContextActionJson, ContextActionRecordValue,
+    ContextActionStorageReader, ContextActionType,
 };
 use storage::context::merkle::merkle_storage_stats::MerkleStoragePerfReport;
-use storage::context::{ContextApi, TezedgeContext};
-use storage::PersistentStorage;
+use storage::context::{ContextApi, ContextKey, TezedgeContext};
+use storage::persistent::database::RocksDBStats;
+use storage::{BlockMetaStorage, PersistentStorage};
 use tezos_context::channel::ContextAction;
 use tezos_messages::base::rpc_support::UniversalValue;
+use tezos_messages::p2p::encoding::block_header::Level;
 
-use crate::helpers::PagedResult;
+use crate::helpers::{get_context_hash, ContextDiffInfo, PagedResult};
 use crate::server::RpcServiceEnvironment;
 use crate::services::protocol::get_context_protocol_params;
 
@@ -110,8 +117,11 @@ pub(crate) fn get_contract_actions(
 ) -> Result<PagedResult<Vec<ContextActionRecordValue>>, failure::Error> {
     let context_action_storage = ensure_context_action_storage(persistent_storage)?;
     let contract_address = contract_id_to_contract_address_for_index(contract_id)?;
-    let mut context_records =
-        context_action_storage.get_by_contract_address(&contract_address, from_id, limit + 1)?;
+    let mut context_records = context_action_storage.get_by_contract_address(
+        &contract_address,
+        from_id,
+        limit.saturating_add(1),
+    )?;
     let next_id = if context_records.len() > limit {
         context_records.last().map(|rec| rec.id())
     } else {
@@ -121,6 +131,93 @@ pub(crate) fn get_contract_actions(
     Ok(PagedResult::new(context_records, next_id, limit))
 }
 
+/// Get actions with a key under `prefix` (separated by '/') from blocks in levels `from_level..=to_level`,
+/// ordered by level. `cursor_id` is the `next_id` of the previous page.
+pub(crate) fn get_key_prefix_actions(
+    prefix: &str,
+    from_level: Option<Level>,
+    to_level: Option<Level>,
+    cursor_id: Option<u64>,
+    limit: usize,
+    persistent_storage: &PersistentStorage,
+) -> Result<PagedResult<Vec<ContextActionJson>>, failure::Error> {
+    let context_action_storage = ensure_context_action_storage(persistent_storage)?;
+    let prefix: ContextKey = prefix
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect();
+    if prefix.is_empty() {
+        return Err(failure::format_err!("Key prefix should not be empty"));
+    }
+
+    let cursor = match cursor_id {
+        Some(cursor_id) => Some((
+            get_action_level(&context_action_storage, cursor_id, persistent_storage)?,
+            cursor_id,
+        )),
+        None => None,
+    };
+    let levels = (
+        from_level.map_or(Bound::Unbounded, Bound::Included),
+        to_level.map_or(Bound::Unbounded, Bound::Included),
+    );
+
+    let mut context_records = context_action_storage.get_by_key_prefix(
+        &prefix,
+        levels,
+        cursor,
+        limit.saturating_add(1),
+    )?;
+    let next_id = if context_records.len() > limit {
+        context_records.last().map(|rec| rec.id())
+    } else {
+        None
+    };
+    context_records.truncate(std::cmp::min(context_records.len(), limit));
+    Ok(PagedResult::new(
+        context_records
+            .into_iter()
+            .map(ContextActionJson::from)
+            .collect(),
+        next_id,
+        limit,
+    ))
+}
+
+/// Level of the block, which the action with `id` belongs to
+fn get_action_level(
+    context_action_storage: &ContextActionStorageReader,
+    id: u64,
+    persistent_storage: &PersistentStorage,
+) -> Result<Level, failure::Error> {
+    let action = context_action_storage
+        .get_by_id(id)?
+        .ok_or_else(|| failure::format_err!("Context action {} not found", id))?;
+    let block_hash = extract_block_hash(&action.action)
+        .ok_or_else(|| failure::format_err!("Context action {} has no block", id))?;
+    let block_hash = BlockHash::try_from(&block_hash[..])
+        .map_err(|e| failure::format_err!("Invalid block hash of context action {}: {}", id, e))?;
+    resolve_block_level(&BlockMetaStorage::new(persistent_storage), &block_hash)?.ok_or_else(|| {
+        failure::format_err!("Level of the block of context action {} is not known", id)
+    })
+}
+
+/// RocksDB statistics of every column family by database
+pub(crate) fn get_stats_storage(
+    persistent_storage: &PersistentStorage,
+) -> Result<BTreeMap<&'static str, RocksDBStats>, failure::Error> {
+    let mut stats = BTreeMap::new();
+    stats.insert("db", RocksDBStats::collect(&persistent_storage.db())?);
+    if let Some(context) = persistent_storage.context_db() {
+        stats.insert("context", RocksDBStats::collect(&context)?);
+    }
+    if let Some(context_actions) = persistent_storage.merkle_context_actions() {
+        stats.insert("context_actions", RocksDBStats::collect(&context_actions)?);
+    }
+    Ok(stats)
+}
+
 pub(crate) fn get_stats_memory() -> MemoryStatsResult<MemoryData> {
     let memory = Memory::new();
     memory.get_memory_stats()
@@ -137,6 +234,39 @@ pub(crate) fn get_context_stats(
     Ok(context.get_merkle_stats()?)
 }
 
+/// Get difference of context between two blocks, `prefix` (separated by '/') filters compared keys,
+/// at most `limit` changes after the key `cursor` (separated by '/') are returned
+pub(crate) fn get_context_diff(
+    from_block_hash: &BlockHash,
+    to_block_hash: &BlockHash,
+    prefix: Option<&str>,
+    cursor: Option<&str>,
+    limit: usize,
+    env: &RpcServiceEnvironment,
+) -> Result<ContextDiffInfo, failure::Error> {
+    let from_context_hash = get_context_hash(from_block_hash, env)?;
+    let to_context_hash = get_context_hash(to_block_hash, env)?;
+    let to_context_key = |key: &str| -> ContextKey {
+        key.split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect()
+    };
+    let prefix: ContextKey = prefix.map(to_context_key).unwrap_or_default();
+    let cursor: Option<ContextKey> = cursor.map(to_context_key);
+
+    Ok(env
+        .tezedge_context()
+        .get_context_diff(
+            &from_context_hash,
+            &to_context_hash,
+            &prefix,
+            cursor.as_ref(),
+            Some(limit),
+        )?
+        .into())
+}
+
 pub(crate) fn get_cycle_length_for_block(
     block_hash: &BlockHash,
     env: &RpcServiceEnvironment,
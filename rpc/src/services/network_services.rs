@@ -0,0 +1,419 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Octez compatible `/network/*` rpcs, served from the state of peers shared by the peer manager
+//! and transfer stats collected by the monitoring.
+//!
+//! Differences from Octez:
+//! - history of peers is not kept, so `/network/peers` returns just connected peers, but ban/unban/trust/untrust/banned
+//!   of a peer work also for a disconnected peer, which is resolved to its last known point
+//! - only incoming traffic is measured, sent bytes and outflow are always zero
+
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::RwLockReadGuard;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use riker::actors::*;
+use serde::Serialize;
+
+use shell::peer_manager::P2pPeerState;
+use shell::stats::network_stats::{NetworkStats, PeerTransferStats};
+use storage::peer_storage::PointInfo;
+use tezos_messages::p2p::encoding::prelude::{MetadataMessage, NetworkVersion};
+use tezos_messages::ts_to_rfc3339;
+
+use crate::server::RpcServiceEnvironment;
+
+/// Reason stored with the ban requested by rpc
+const RPC_BAN_REASON: &str = "banned by rpc";
+
+#[derive(Serialize, Debug, Clone)]
+pub struct NetworkStat {
+    total_sent: String,
+    total_recv: String,
+    current_inflow: i64,
+    current_outflow: i64,
+}
+
+impl NetworkStat {
+    fn new(total_recv: u64, current_inflow: f32) -> Self {
+        Self {
+            total_sent: 0.to_string(),
+            total_recv: total_recv.to_string(),
+            current_inflow: current_inflow as i64,
+            current_outflow: 0,
+        }
+    }
+}
+
+impl From<&PeerTransferStats> for NetworkStat {
+    fn from(stats: &PeerTransferStats) -> Self {
+        NetworkStat::new(stats.total_recv(), stats.current_inflow())
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct IdPoint {
+    addr: String,
+    port: u16,
+}
+
+impl From<&SocketAddr> for IdPoint {
+    fn from(address: &SocketAddr) -> Self {
+        Self {
+            addr: address.ip().to_string(),
+            port: address.port(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AnnouncedVersion {
+    chain_name: String,
+    distributed_db_version: u16,
+    p2p_version: u16,
+}
+
+impl From<&NetworkVersion> for AnnouncedVersion {
+    fn from(version: &NetworkVersion) -> Self {
+        Self {
+            chain_name: version.chain_name().clone(),
+            distributed_db_version: *version.distributed_db_version(),
+            p2p_version: *version.p2p_version(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ConnectionMetadata {
+    disable_mempool: bool,
+    private_node: bool,
+}
+
+impl From<&MetadataMessage> for ConnectionMetadata {
+    fn from(metadata: &MetadataMessage) -> Self {
+        Self {
+            disable_mempool: metadata.disable_mempool(),
+            private_node: metadata.private_node(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Connection {
+    incoming: bool,
+    peer_id: String,
+    id_point: IdPoint,
+    remote_socket_port: u16,
+    announced_version: AnnouncedVersion,
+    private: bool,
+    local_metadata: ConnectionMetadata,
+    remote_metadata: ConnectionMetadata,
+}
+
+impl From<&P2pPeerState> for Connection {
+    fn from(peer: &P2pPeerState) -> Self {
+        let connection = &peer.connection;
+        Self {
+            incoming: connection.incoming,
+            peer_id: connection.peer_id_marker.clone(),
+            id_point: IdPoint::from(&peer.peer_address),
+            remote_socket_port: peer.peer_address.port(),
+            announced_version: AnnouncedVersion::from(&connection.network_version),
+            private: connection.remote_metadata.private_node(),
+            local_metadata: ConnectionMetadata::from(&connection.local_metadata),
+            remote_metadata: ConnectionMetadata::from(&connection.remote_metadata),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PeerInfo {
+    score: i32,
+    trusted: bool,
+    conn_metadata: ConnectionMetadata,
+    state: &'static str,
+    reachable_at: IdPoint,
+    stat: NetworkStat,
+    last_established_connection: (IdPoint, String),
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PointState {
+    event_kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p2p_peer_id: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PointInfoResponse {
+    trusted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    greylisted_until: Option<String>,
+    state: PointState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p2p_peer_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_failed_connection: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_established_connection: Option<(String, String)>,
+}
+
+impl PointInfoResponse {
+    fn new(
+        info: Option<&PointInfo>,
+        connected: Option<&P2pPeerState>,
+        requested: bool,
+        now: SystemTime,
+    ) -> Self {
+        let p2p_peer_id = connected.map(|peer| peer.connection.peer_id_marker.clone());
+        let event_kind = if connected.is_some() {
+            "running"
+        } else if requested {
+            "requested"
+        } else {
+            "disconnected"
+        };
+        Self {
+            trusted: info.map_or(false, |info| info.trusted()),
+            greylisted_until: info
+                .filter(|info| info.is_banned(now))
+                .and_then(|info| info.ban().as_ref())
+                .map(|ban| to_rfc3339(ban.until)),
+            state: PointState {
+                event_kind,
+                p2p_peer_id: p2p_peer_id.clone(),
+            },
+            last_failed_connection: info.and_then(|info| info.last_failed()).map(to_rfc3339),
+            last_established_connection: connected.map(|peer| {
+                (
+                    peer.connection.peer_id_marker.clone(),
+                    to_rfc3339(peer.connection.established_at),
+                )
+            }),
+            p2p_peer_id,
+        }
+    }
+}
+
+/// Returns global transfer stats
+pub(crate) fn get_network_stat(env: &RpcServiceEnvironment) -> Result<NetworkStat, failure::Error> {
+    let network_stats = read_network_stats(env)?;
+    Ok(NetworkStat::new(
+        network_stats.total_recv(),
+        network_stats.current_inflow(),
+    ))
+}
+
+pub(crate) fn get_connections(
+    env: &RpcServiceEnvironment,
+) -> Result<Vec<Connection>, failure::Error> {
+    Ok(env
+        .p2p_peers()
+        .connections()?
+        .iter()
+        .map(Connection::from)
+        .collect())
+}
+
+pub(crate) fn get_connection(
+    peer_id: &str,
+    env: &RpcServiceEnvironment,
+) -> Result<Option<Connection>, failure::Error> {
+    Ok(env
+        .p2p_peers()
+        .connection(peer_id)?
+        .as_ref()
+        .map(Connection::from))
+}
+
+pub(crate) fn get_peers(
+    env: &RpcServiceEnvironment,
+) -> Result<Vec<(String, PeerInfo)>, failure::Error> {
+    let network_stats = read_network_stats(env)?;
+    env.p2p_peers()
+        .connections()?
+        .iter()
+        .map(|peer| -> Result<_, failure::Error> {
+            let info = env.p2p_peers().point(&peer.peer_address)?;
+            Ok((
+                peer.connection.peer_id_marker.clone(),
+                peer_info(peer, info.as_ref(), &network_stats),
+            ))
+        })
+        .collect()
+}
+
+pub(crate) fn get_peer(
+    peer_id: &str,
+    env: &RpcServiceEnvironment,
+) -> Result<Option<PeerInfo>, failure::Error> {
+    let peer = match env.p2p_peers().connection(peer_id)? {
+        Some(peer) => peer,
+        None => return Ok(None),
+    };
+    let info = env.p2p_peers().point(&peer.peer_address)?;
+    let network_stats = read_network_stats(env)?;
+    Ok(Some(peer_info(&peer, info.as_ref(), &network_stats)))
+}
+
+/// Returns all known points - stored, connected and requested ones
+pub(crate) fn get_points(
+    env: &RpcServiceEnvironment,
+) -> Result<Vec<(String, PointInfoResponse)>, failure::Error> {
+    let points = env
+        .p2p_peers()
+        .points()?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let connected = env
+        .p2p_peers()
+        .connections()?
+        .into_iter()
+        .map(|peer| (peer.peer_address, peer))
+        .collect::<HashMap<_, _>>();
+    let requested = env
+        .p2p_peers()
+        .potential_peers()?
+        .into_iter()
+        .collect::<HashSet<_>>();
+
+    let mut addresses = points
+        .keys()
+        .chain(connected.keys())
+        .chain(requested.iter())
+        .cloned()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    addresses.sort();
+
+    let now = SystemTime::now();
+    Ok(addresses
+        .into_iter()
+        .map(|address| {
+            let info = PointInfoResponse::new(
+                points.get(&address),
+                connected.get(&address),
+                requested.contains(&address),
+                now,
+            );
+            (address.to_string(), info)
+        })
+        .collect())
+}
+
+pub(crate) fn get_point(
+    address: &SocketAddr,
+    env: &RpcServiceEnvironment,
+) -> Result<Option<PointInfoResponse>, failure::Error> {
+    let info = env.p2p_peers().point(address)?;
+    let connected = env
+        .p2p_peers()
+        .connections()?
+        .into_iter()
+        .find(|peer| peer.peer_address == *address);
+    let requested = env.p2p_peers().potential_peers()?.contains(address);
+    if info.is_none() && connected.is_none() && !requested {
+        return Ok(None);
+    }
+    Ok(Some(PointInfoResponse::new(
+        info.as_ref(),
+        connected.as_ref(),
+        requested,
+        SystemTime::now(),
+    )))
+}
+
+/// Bans the point and closes all connections with its IP address
+pub(crate) fn ban_point(
+    address: SocketAddr,
+    env: &RpcServiceEnvironment,
+) -> Result<(), failure::Error> {
+    env.p2p_peers()
+        .ban_point(address, RPC_BAN_REASON.to_string())?;
+    if env.p2p_peers().is_blacklisted(&address.ip())? {
+        env.p2p_peers()
+            .connections()?
+            .into_iter()
+            .filter(|peer| peer.peer_address.ip() == address.ip())
+            .for_each(|peer| env.sys().stop(peer.peer_ref));
+    }
+    Ok(())
+}
+
+pub(crate) fn unban_point(
+    address: SocketAddr,
+    env: &RpcServiceEnvironment,
+) -> Result<(), failure::Error> {
+    env.p2p_peers().unban_point(address).map_err(|e| e.into())
+}
+
+pub(crate) fn trust_point(
+    address: SocketAddr,
+    env: &RpcServiceEnvironment,
+) -> Result<(), failure::Error> {
+    env.p2p_peers().trust_point(address).map_err(|e| e.into())
+}
+
+pub(crate) fn untrust_point(
+    address: SocketAddr,
+    env: &RpcServiceEnvironment,
+) -> Result<(), failure::Error> {
+    env.p2p_peers().untrust_point(address).map_err(|e| e.into())
+}
+
+pub(crate) fn is_point_banned(
+    address: &SocketAddr,
+    env: &RpcServiceEnvironment,
+) -> Result<bool, failure::Error> {
+    env.p2p_peers()
+        .is_blacklisted(&address.ip())
+        .map_err(|e| e.into())
+}
+
+/// Returns point of the peer, also of the disconnected one, if its last point is known
+pub(crate) fn get_peer_point(
+    peer_id: &str,
+    env: &RpcServiceEnvironment,
+) -> Result<Option<SocketAddr>, failure::Error> {
+    env.p2p_peers().peer_point(peer_id).map_err(|e| e.into())
+}
+
+fn peer_info(
+    peer: &P2pPeerState,
+    info: Option<&PointInfo>,
+    network_stats: &NetworkStats,
+) -> PeerInfo {
+    PeerInfo {
+        score: info.map_or(0, |info| info.score()),
+        trusted: info.map_or(false, |info| info.trusted()),
+        conn_metadata: ConnectionMetadata::from(&peer.connection.remote_metadata),
+        state: "running",
+        reachable_at: IdPoint::from(&peer.peer_address),
+        stat: network_stats
+            .peer(peer.peer_ref.uri())
+            .map(NetworkStat::from)
+            .unwrap_or_else(|| NetworkStat::new(0, 0.0)),
+        last_established_connection: (
+            IdPoint::from(&peer.peer_address),
+            to_rfc3339(peer.connection.established_at),
+        ),
+    }
+}
+
+fn read_network_stats(
+    env: &RpcServiceEnvironment,
+) -> Result<RwLockReadGuard<NetworkStats>, failure::Error> {
+    env.network_stats()
+        .read()
+        .map_err(|e| failure::format_err!("Failed to lock network stats, reason: {}", e))
+}
+
+fn to_rfc3339(time: SystemTime) -> String {
+    let ts = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64);
+    ts_to_rfc3339(ts)
+}
@@ -192,9 +192,8 @@ pub(crate) fn compute_storage_stats<'a>(
     let fat_tail: TopN<ContextAction> = TopN::new(100);
 
     let blocks = block_storage.get_multiple_without_json(from_block, std::usize::MAX)?;
-    blocks.par_iter().for_each(|block| {
-        let actions = get_block_actions_by_hash(&context_action_storage, &block.hash)
-            .expect("Failed to extract actions from a block!");
+    blocks.par_iter().try_for_each(|block| {
+        let actions = get_block_actions_by_hash(&context_action_storage, &block.hash)?;
         {
             let mut stats = stats.lock().expect("Unable to lock mutex!");
             actions.iter().for_each(|action| match action {
@@ -279,7 +278,8 @@ pub(crate) fn compute_storage_stats<'a>(
         } // drop the stats mutex here
 
         actions.par_iter().for_each(|action| fat_tail.add(action));
-    });
+        Ok::<(), failure::Error>(())
+    })?;
 
     Ok(StatsResponse {
         fat_tail: remove_values(fat_tail_vec(fat_tail)),
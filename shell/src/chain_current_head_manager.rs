@@ -7,6 +7,7 @@
 //!
 //! Responsible for:
 //! -- managing attribute current head
+//! -- pruning of old blocks according to history mode
 //! -- ...
 
 use std::sync::Arc;
@@ -15,6 +16,7 @@ use riker::actors::*;
 use slog::{debug, info, warn};
 
 use crypto::hash::{BlockHash, ChainId};
+use storage::history_mode::{BlockPruner, BlockPrunerWorker, HistoryMode};
 use storage::PersistentStorage;
 use storage::{BlockStorage, BlockStorageReader, StorageInitInfo};
 
@@ -67,6 +69,13 @@ pub struct ChainCurrentHeadManager {
 
     /// Internal stats
     apply_block_stats: ApplyBlockStatsRef,
+
+    /// History mode of the node, old blocks are pruned accordingly
+    history_mode: HistoryMode,
+    /// Storage used to start the pruner
+    persistent_storage: PersistentStorage,
+    /// Removes old blocks according to history mode in the background (started in post_start, not for archive mode)
+    block_pruner: Option<BlockPrunerWorker>,
 }
 
 /// Reference to [chain manager](ChainManager) actor.
@@ -84,6 +93,7 @@ impl ChainCurrentHeadManager {
         current_mempool_state: CurrentMempoolStateStorageRef,
         current_bootstrap_state: SynchronizationBootstrapStateRef,
         apply_block_stats: ApplyBlockStatsRef,
+        history_mode: HistoryMode,
     ) -> Result<ChainCurrentHeadManagerRef, CreateError> {
         sys.actor_of_props::<ChainCurrentHeadManager>(
             ChainCurrentHeadManager::name(),
@@ -96,6 +106,7 @@ impl ChainCurrentHeadManager {
                 current_mempool_state,
                 current_bootstrap_state,
                 apply_block_stats,
+                history_mode,
             )),
         )
     }
@@ -113,6 +124,7 @@ impl ChainCurrentHeadManager {
     /// - broadcast new current head/branch to peers (if bootstrapped)
     /// - start test chain (if needed) (TODO: TE-123 - not implemented yet)
     /// - update checkpoint (TODO: TE-210 - not implemented yet)
+    /// - prune old blocks (according to history mode)
     /// - reset mempool_prevalidator
     /// ...
     fn process_applied_block(
@@ -198,7 +210,7 @@ impl ChainCurrentHeadManager {
                         self.shell_channel.tell(
                             Publish {
                                 msg: ShellChannelMsg::AdvertiseToP2pNewCurrentBranch(
-                                    chain_id,
+                                    chain_id.clone(),
                                     Arc::new(new_head.block_hash().clone()),
                                 ),
                                 topic: ShellChannelTopic::ShellCommands.into(),
@@ -210,7 +222,7 @@ impl ChainCurrentHeadManager {
                         self.shell_channel.tell(
                             Publish {
                                 msg: ShellChannelMsg::AdvertiseToP2pNewCurrentHead(
-                                    chain_id,
+                                    chain_id.clone(),
                                     Arc::new(new_head.block_hash().clone()),
                                 ),
                                 topic: ShellChannelTopic::ShellCommands.into(),
@@ -224,6 +236,11 @@ impl ChainCurrentHeadManager {
                 }
             }
 
+            // prune old data in the background (pruner decides, if it is needed)
+            if let Some(block_pruner) = self.block_pruner.as_ref() {
+                block_pruner.head_applied(chain_id.as_ref().clone(), new_head.clone());
+            }
+
             // update internal state
             let mut apply_block_stats = self.apply_block_stats.write()?;
             apply_block_stats.set_applied_block_level(*new_head.level());
@@ -276,6 +293,7 @@ impl
         CurrentMempoolStateStorageRef,
         SynchronizationBootstrapStateRef,
         ApplyBlockStatsRef,
+        HistoryMode,
     )> for ChainCurrentHeadManager
 {
     fn create_args(
@@ -288,6 +306,7 @@ impl
             current_mempool_state,
             current_bootstrap_state,
             apply_block_stats,
+            history_mode,
         ): (
             ShellChannelRef,
             PersistentStorage,
@@ -297,6 +316,7 @@ impl
             CurrentMempoolStateStorageRef,
             SynchronizationBootstrapStateRef,
             ApplyBlockStatsRef,
+            HistoryMode,
         ),
     ) -> Self {
         ChainCurrentHeadManager {
@@ -312,6 +332,9 @@ impl
             current_bootstrap_state,
             remote_current_head_state,
             apply_block_stats,
+            history_mode,
+            persistent_storage,
+            block_pruner: None,
         }
     }
 }
@@ -322,6 +345,18 @@ impl Actor for ChainCurrentHeadManager {
     fn post_start(&mut self, ctx: &Context<Self::Msg>) {
         // now we can hydrate state and read current head
         self.hydrate_current_head_state(ctx);
+
+        if self.history_mode != HistoryMode::Archive {
+            match BlockPrunerWorker::start(
+                BlockPruner::new(self.history_mode, &self.persistent_storage),
+                ctx.system.log(),
+            ) {
+                Ok(block_pruner) => self.block_pruner = Some(block_pruner),
+                Err(e) => {
+                    warn!(ctx.system.log(), "Failed to start block pruner, old blocks will not be pruned"; "reason" => format!("{}", e))
+                }
+            }
+        }
     }
 
     fn recv(&mut self, ctx: &Context<Self::Msg>, msg: Self::Msg, sender: Sender) {
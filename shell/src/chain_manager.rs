@@ -19,18 +19,21 @@ use failure::{format_err, Error};
 use riker::actors::*;
 use slog::{debug, info, trace, warn, Logger};
 
-use crypto::hash::{BlockHash, ChainId, CryptoboxPublicKeyHash, OperationHash};
+use crypto::hash::{BlockHash, ChainId, CryptoboxPublicKeyHash, OperationHash, ProtocolHash};
 use crypto::seeded_step::Seed;
-use networking::p2p::network_channel::{NetworkChannelMsg, NetworkChannelRef, NetworkChannelTopic};
+use networking::p2p::network_channel::{
+    NetworkChannelMsg, NetworkChannelRef, NetworkChannelTopic, PeerMessageReceived, PeerPenalty,
+};
+use networking::p2p::rate_limit;
 use storage::mempool_storage::MempoolOperationType;
 use storage::PersistentStorage;
 use storage::{
     BlockHeaderWithHash, BlockMetaStorage, BlockMetaStorageReader, BlockStorage,
-    BlockStorageReader, MempoolStorage, OperationsStorage, OperationsStorageReader, StorageError,
-    StorageInitInfo,
+    BlockStorageReader, MempoolStorage, OperationsStorage, OperationsStorageReader,
+    ProtocolStorage, StorageError, StorageInitInfo,
 };
 use tezos_identity::Identity;
-use tezos_messages::p2p::binary_message::MessageHash;
+use tezos_messages::p2p::binary_message::{BinaryMessage, MessageHash};
 use tezos_messages::p2p::encoding::block_header::Level;
 use tezos_messages::p2p::encoding::prelude::*;
 use tezos_messages::Head;
@@ -45,7 +48,7 @@ use crate::shell_channel::{
 };
 use crate::state::chain_state::{BlockAcceptanceResult, BlockchainState};
 use crate::state::head_state::CurrentHeadRef;
-use crate::state::peer_state::{tell_peer, PeerState};
+use crate::state::peer_state::{tell_peer, PeerState, RequestedData};
 use crate::state::synchronization_state::{
     PeerBranchSynchronizationDone, SynchronizationBootstrapStateRef,
 };
@@ -68,6 +71,8 @@ const CURRENT_HEAD_LEVEL_UPDATE_TIMEOUT: Duration = Duration::from_secs(60 * 2);
 const SILENT_PEER_TIMEOUT: Duration = Duration::from_secs(60);
 /// Maximum timeout duration in sandbox mode (do not disconnect peers in sandbox mode)
 const SILENT_PEER_TIMEOUT_SANDBOX: Duration = Duration::from_secs(31_536_000);
+/// After this time unknown protocol can be requested again (from other peer)
+const PROTOCOL_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
 
 /// Message commands [`ChainManager`] to disconnect stalled peers.
 #[derive(Clone, Debug)]
@@ -191,6 +196,12 @@ pub struct ChainManager {
     operations_storage: Box<dyn OperationsStorageReader>,
     /// Mempool operation storage
     mempool_storage: MempoolStorage,
+    /// Protocol sources downloaded from peers (loaded to the protocol runner and served to other peers)
+    protocol_storage: ProtocolStorage,
+    /// Unknown protocols requested from peers (with time of the last request)
+    requested_protocols: HashMap<ProtocolHash, Instant>,
+    /// Current heads (the last one per peer), which are validated again, when their unknown protocol is downloaded
+    pending_protocol_heads: HashMap<ProtocolHash, Vec<PeerMessageReceived>>,
     /// Holds state of the blockchain
     chain_state: BlockchainState,
 
@@ -291,6 +302,9 @@ impl ChainManager {
             operations_storage,
             stats,
             mempool_storage,
+            protocol_storage,
+            requested_protocols,
+            pending_protocol_heads,
             current_head,
             identity_peer_id,
             ..
@@ -418,6 +432,14 @@ impl ChainManager {
 
                                     // explicit drop (not needed)
                                     drop(requested_data);
+                                } else {
+                                    Self::penalize_unsolicited(
+                                        received.message.message(),
+                                        RequestedData::BlockHeader(block_header_with_hash.hash),
+                                        peer,
+                                        network_channel,
+                                        &log,
+                                    )?;
                                 }
                             }
                             PeerMessage::GetBlockHeaders(message) => {
@@ -495,6 +517,18 @@ impl ChainManager {
 
                                     // explicit drop (not needed)
                                     drop(requested_data)
+                                } else {
+                                    let operations_for_block = operations.operations_for_block();
+                                    Self::penalize_unsolicited(
+                                        received.message.message(),
+                                        RequestedData::BlockOperations(
+                                            operations_for_block.block_hash().clone(),
+                                            operations_for_block.validation_pass(),
+                                        ),
+                                        peer,
+                                        network_channel,
+                                        &log,
+                                    )?;
                                 }
                             }
                             PeerMessage::GetOperationsForBlocks(message) => {
@@ -613,6 +647,26 @@ impl ChainManager {
                                                 peer,
                                             );
                                         }
+                                        BlockAcceptanceResult::UnknownProtocol(protocol_hash) => {
+                                            // we cannot validate head now, so try to download protocol sources from peer
+                                            debug!(log, "Cannot validate current head with unknown protocol";
+                                                        "message_head_level" => message.current_block_header().level(),
+                                                        "protocol_hash" => protocol_hash.to_base58_check());
+                                            Self::request_protocol(
+                                                protocol_hash.clone(),
+                                                peer,
+                                                protocol_storage,
+                                                requested_protocols,
+                                                &log,
+                                            )?;
+                                            if requested_protocols.contains_key(&protocol_hash) {
+                                                Self::add_pending_protocol_head(
+                                                    protocol_hash,
+                                                    &received,
+                                                    pending_protocol_heads,
+                                                );
+                                            }
+                                        }
                                         BlockAcceptanceResult::MutlipassValidationError(error) => {
                                             warn!(log, "Mutlipass validation error detected - blacklisting peer";
                                                        "message_head_level" => message.current_block_header().level(),
@@ -715,7 +769,14 @@ impl ChainManager {
                                         );
                                     }
                                     None => {
-                                        debug!(log, "Unexpected mempool operation received")
+                                        debug!(log, "Unexpected mempool operation received");
+                                        Self::penalize_unsolicited(
+                                            received.message.message(),
+                                            RequestedData::Operation(operation_hash),
+                                            peer,
+                                            network_channel,
+                                            &log,
+                                        )?;
                                     }
                                 }
                             }
@@ -744,6 +805,55 @@ impl ChainManager {
                                     None,
                                 );
                             }
+                            PeerMessage::SwapRequest(_) | PeerMessage::SwapAck(_) => {
+                                Self::process_swap_message(
+                                    received.message.message(),
+                                    peer,
+                                    network_channel,
+                                );
+                            }
+                            PeerMessage::Deactivate(message) => {
+                                Self::process_deactivate(
+                                    message,
+                                    peer,
+                                    chain_state.get_chain_id(),
+                                    &ctx.system,
+                                    &log,
+                                );
+                            }
+                            PeerMessage::GetProtocols(message) => {
+                                for protocol_hash in message.get_protocols() {
+                                    if let Some(protocol) = protocol_storage.get(protocol_hash)? {
+                                        tell_peer(ProtocolMessage::new(protocol).into(), peer);
+                                    }
+                                }
+                            }
+                            PeerMessage::Protocol(_) => {
+                                let prevalidation_api = &self.tezos_readonly_prevalidation_api;
+                                let pending_heads = Self::process_protocol(
+                                    received.message.message(),
+                                    peer,
+                                    protocol_storage,
+                                    requested_protocols,
+                                    pending_protocol_heads,
+                                    |protocol_hash, protocol| {
+                                        prevalidation_api.pool.get()?.api.load_protocol(
+                                            protocol_hash.clone(),
+                                            protocol.as_bytes()?,
+                                        )?;
+                                        Ok(())
+                                    },
+                                    network_channel,
+                                    &log,
+                                )?;
+                                // heads, which waited for the protocol, are validated again (now with the loaded protocol)
+                                for pending_head in pending_heads.unwrap_or_default() {
+                                    ctx.myself().tell(
+                                        NetworkChannelMsg::PeerMessageReceived(pending_head),
+                                        None,
+                                    );
+                                }
+                            }
                             ignored_message => {
                                 trace!(log, "Ignored message"; "message" => format!("{:?}", ignored_message))
                             }
@@ -762,6 +872,183 @@ impl ChainManager {
         Ok(())
     }
 
+    /// Re-sends swap request/ack to the network layer, swap is handled by peer manager
+    fn process_swap_message(
+        message: &PeerMessage,
+        peer: &PeerState,
+        network_channel: &NetworkChannelRef,
+    ) {
+        let msg = match message {
+            PeerMessage::SwapRequest(msg) => {
+                NetworkChannelMsg::ProcessSwapRequest(peer.peer_id.clone(), msg.clone())
+            }
+            PeerMessage::SwapAck(msg) => {
+                NetworkChannelMsg::ProcessSwapAck(peer.peer_id.clone(), msg.clone())
+            }
+            _ => return,
+        };
+        network_channel.tell(
+            Publish {
+                msg,
+                topic: NetworkChannelTopic::NetworkCommands.into(),
+            },
+            None,
+        );
+    }
+
+    /// Disconnects the peer, which deactivated our chain, returns true, if the peer was disconnected
+    fn process_deactivate(
+        message: &DeactivateMessage,
+        peer: &mut PeerState,
+        chain_id: &ChainId,
+        sys: &ActorSystem,
+        log: &Logger,
+    ) -> bool {
+        if chain_id == message.deactivate() {
+            // peer does not follow our chain anymore, so the connection is useless for us
+            // (we follow just one chain, so we disconnect the peer instead of just deactivating it)
+            info!(log, "Peer deactivated our chain - disconnecting peer";
+                       "chain_id" => message.deactivate().to_base58_check());
+            peer.clear();
+            sys.stop(peer.peer_id.peer_ref.clone());
+            true
+        } else {
+            debug!(log, "Peer deactivated unknown chain"; "chain_id" => message.deactivate().to_base58_check());
+            false
+        }
+    }
+
+    /// Stores requested protocol sources and loads them with `load_protocol` (to the protocol runner),
+    /// unsolicited protocol penalizes the peer,
+    /// returns heads waiting for the loaded protocol or None, if the protocol was not stored
+    fn process_protocol<L>(
+        message: &PeerMessage,
+        peer: &PeerState,
+        protocol_storage: &ProtocolStorage,
+        requested_protocols: &mut HashMap<ProtocolHash, Instant>,
+        pending_protocol_heads: &mut HashMap<ProtocolHash, Vec<PeerMessageReceived>>,
+        load_protocol: L,
+        network_channel: &NetworkChannelRef,
+        log: &Logger,
+    ) -> Result<Option<Vec<PeerMessageReceived>>, Error>
+    where
+        L: FnOnce(&ProtocolHash, &Protocol) -> Result<(), Error>,
+    {
+        let protocol = match message {
+            PeerMessage::Protocol(protocol_message) => protocol_message.protocol(),
+            _ => return Ok(None),
+        };
+        let protocol_hash: ProtocolHash = protocol.message_typed_hash()?;
+
+        // check, if we requested protocol
+        if requested_protocols.remove(&protocol_hash).is_some() {
+            protocol_storage.put(&protocol_hash, protocol)?;
+            info!(log, "Protocol sources downloaded";
+                       "protocol_hash" => protocol_hash.to_base58_check(),
+                       "expected_env_version" => protocol.expected_env_version(),
+                       "components" => protocol.components().len());
+
+            let pending_heads = pending_protocol_heads
+                .remove(&protocol_hash)
+                .unwrap_or_default();
+            match load_protocol(&protocol_hash, protocol) {
+                Ok(()) => {
+                    info!(log, "Downloaded protocol loaded to protocol runner";
+                               "protocol_hash" => protocol_hash.to_base58_check(),
+                               "pending_heads" => pending_heads.len());
+                    Ok(Some(pending_heads))
+                }
+                Err(e) => {
+                    // sources are still served to other peers, but heads with this protocol cannot be validated
+                    warn!(log, "Failed to load downloaded protocol";
+                               "protocol_hash" => protocol_hash.to_base58_check(),
+                               "reason" => format!("{}", e));
+                    Ok(Some(Vec::new()))
+                }
+            }
+        } else {
+            debug!(log, "Unexpected protocol received"; "protocol_hash" => protocol_hash.to_base58_check());
+            Self::penalize_unsolicited(
+                message,
+                RequestedData::Protocol(protocol_hash),
+                peer,
+                network_channel,
+                log,
+            )?;
+            Ok(None)
+        }
+    }
+
+    /// Keeps the head to validate it again after its protocol is downloaded (only the last head of the peer is kept)
+    fn add_pending_protocol_head(
+        protocol_hash: ProtocolHash,
+        received: &PeerMessageReceived,
+        pending_protocol_heads: &mut HashMap<ProtocolHash, Vec<PeerMessageReceived>>,
+    ) {
+        let pending_heads = pending_protocol_heads
+            .entry(protocol_hash)
+            .or_insert_with(Vec::new);
+        pending_heads.retain(|pending_head| pending_head.peer.uri() != received.peer.uri());
+        pending_heads.push(received.clone());
+    }
+
+    /// Requests unknown protocol sources from the peer,
+    /// protocol is not requested, if already stored or requested (and request is not timed out yet)
+    fn request_protocol(
+        protocol_hash: ProtocolHash,
+        peer: &PeerState,
+        protocol_storage: &ProtocolStorage,
+        requested_protocols: &mut HashMap<ProtocolHash, Instant>,
+        log: &Logger,
+    ) -> Result<(), Error> {
+        if protocol_storage.contains(&protocol_hash)? {
+            return Ok(());
+        }
+        if let Some(requested_last) = requested_protocols.get(&protocol_hash) {
+            if requested_last.elapsed() <= PROTOCOL_REQUEST_TIMEOUT {
+                return Ok(());
+            }
+        }
+
+        info!(log, "Requesting unknown protocol from peer"; "protocol_hash" => protocol_hash.to_base58_check());
+        tell_peer(
+            GetProtocolsMessage::new(vec![protocol_hash.clone()]).into(),
+            peer,
+        );
+        peer.queues
+            .requested(vec![RequestedData::Protocol(protocol_hash.clone())]);
+        requested_protocols.insert(protocol_hash, Instant::now());
+        Ok(())
+    }
+
+    /// Reports peer, which sent message we did not request, to peer manager,
+    /// late response to our recent request (already received, re-requested or timed out) is not reported
+    fn penalize_unsolicited(
+        message: &PeerMessage,
+        data: RequestedData,
+        peer: &PeerState,
+        network_channel: &NetworkChannelRef,
+        log: &Logger,
+    ) -> Result<(), StateError> {
+        let message_type = rate_limit::message_type(message);
+        if peer.queues.was_requested(&data)? {
+            debug!(log, "Late response received"; "message_type" => message_type);
+            return Ok(());
+        }
+
+        network_channel.tell(
+            Publish {
+                msg: NetworkChannelMsg::PenalizePeer(
+                    peer.peer_id.clone(),
+                    PeerPenalty::Unsolicited { message_type },
+                ),
+                topic: NetworkChannelTopic::NetworkCommands.into(),
+            },
+            None,
+        );
+        Ok(())
+    }
+
     fn process_shell_channel_message(
         &mut self,
         ctx: &Context<ChainManagerMsg>,
@@ -1329,6 +1616,9 @@ impl
             block_meta_storage: Box::new(BlockMetaStorage::new(&persistent_storage)),
             operations_storage: Box::new(OperationsStorage::new(&persistent_storage)),
             mempool_storage: MempoolStorage::new(&persistent_storage),
+            protocol_storage: ProtocolStorage::new(&persistent_storage),
+            requested_protocols: HashMap::new(),
+            pending_protocol_heads: HashMap::new(),
             chain_state: BlockchainState::new(
                 block_applier,
                 &persistent_storage,
@@ -1748,3 +2038,420 @@ impl Receive<AskPeersAboutCurrentHead> for ChainManager {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::{TryFrom, TryInto};
+    use std::sync::mpsc;
+
+    use serial_test::serial;
+    use slog::Level;
+
+    use networking::p2p::network_channel::NetworkChannel;
+    use storage::tests_common::TmpStorage;
+
+    use crate::state::tests::prerequisites::{
+        create_logger, create_test_actor_system, create_test_tokio_runtime, test_peer,
+    };
+
+    use super::*;
+
+    const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Forwards received messages to the test
+    struct Collector<M: Message> {
+        sender: mpsc::Sender<M>,
+    }
+
+    impl<M: Message> ActorFactoryArgs<mpsc::Sender<M>> for Collector<M> {
+        fn create_args(sender: mpsc::Sender<M>) -> Self {
+            Collector { sender }
+        }
+    }
+
+    impl<M: Message> Actor for Collector<M> {
+        type Msg = M;
+
+        fn recv(&mut self, _: &Context<Self::Msg>, msg: Self::Msg, _: Sender) {
+            let _ = self.sender.send(msg);
+        }
+    }
+
+    fn collect_network_commands(
+        sys: &ActorSystem,
+        network_channel: &NetworkChannelRef,
+    ) -> Result<mpsc::Receiver<NetworkChannelMsg>, Error> {
+        let (sender, receiver) = mpsc::channel();
+        let collector = sys.actor_of_props(
+            "network_commands_collector",
+            Props::new_args::<Collector<NetworkChannelMsg>, _>(sender),
+        )?;
+        network_channel.tell(
+            Subscribe {
+                actor: Box::new(collector),
+                topic: NetworkChannelTopic::NetworkCommands.into(),
+            },
+            None,
+        );
+        Ok(receiver)
+    }
+
+    #[test]
+    #[serial]
+    fn test_process_swap_message() -> Result<(), Error> {
+        let log = create_logger(Level::Debug);
+        let tokio_runtime = create_test_tokio_runtime();
+        let actor_system = create_test_actor_system(log.clone());
+        let network_channel = NetworkChannel::actor(&actor_system)?;
+        let commands = collect_network_commands(&actor_system, &network_channel)?;
+        let peer = test_peer(&actor_system, network_channel.clone(), &tokio_runtime, 7777);
+
+        let swap = SwapMessage::new(
+            "127.0.0.1:9732".to_string(),
+            peer.peer_id.peer_public_key_hash.clone(),
+        );
+        ChainManager::process_swap_message(
+            &PeerMessage::SwapRequest(swap.clone()),
+            &peer,
+            &network_channel,
+        );
+        ChainManager::process_swap_message(&PeerMessage::SwapAck(swap), &peer, &network_channel);
+        // other messages are not re-sent, penalty is sent just to mark the end of the test
+        ChainManager::process_swap_message(&PeerMessage::Bootstrap, &peer, &network_channel);
+        ChainManager::penalize_unsolicited(
+            &PeerMessage::Bootstrap,
+            RequestedData::Operation(OperationHash::try_from(
+                "onvN8U6QJ6DGJKVYkHXYRtFm3tgBJScj9P5bbPjSZUuFaGzwFuJ",
+            )?),
+            &peer,
+            &network_channel,
+            &log,
+        )?;
+
+        match commands.recv_timeout(RECV_TIMEOUT)? {
+            NetworkChannelMsg::ProcessSwapRequest(peer_id, msg) => {
+                assert_eq!(peer_id.peer_ref.uri(), peer.peer_id.peer_ref.uri());
+                assert_eq!(msg.point(), "127.0.0.1:9732");
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+        assert!(matches!(
+            commands.recv_timeout(RECV_TIMEOUT)?,
+            NetworkChannelMsg::ProcessSwapAck(..)
+        ));
+        assert!(matches!(
+            commands.recv_timeout(RECV_TIMEOUT)?,
+            NetworkChannelMsg::PenalizePeer(..)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_process_deactivate() -> Result<(), Error> {
+        let log = create_logger(Level::Debug);
+        let tokio_runtime = create_test_tokio_runtime();
+        let actor_system = create_test_actor_system(log.clone());
+        let network_channel = NetworkChannel::actor(&actor_system)?;
+        let mut peer = test_peer(&actor_system, network_channel, &tokio_runtime, 7777);
+        let chain_id = ChainId::try_from(vec![1, 2, 3, 4])?;
+
+        // watch termination of the peer actor
+        let (sender, terminated) = mpsc::channel();
+        let watcher = actor_system.actor_of_props(
+            "terminated_peers_watcher",
+            Props::new_args::<Collector<SystemEvent>, _>(sender),
+        )?;
+        subscribe_to_actor_terminated(actor_system.sys_events(), watcher);
+
+        // deactivation of other chain is ignored
+        peer.missing_mempool_operations.push((
+            OperationHash::try_from("onvN8U6QJ6DGJKVYkHXYRtFm3tgBJScj9P5bbPjSZUuFaGzwFuJ")?,
+            MempoolOperationType::Pending,
+        ));
+        assert!(!ChainManager::process_deactivate(
+            &DeactivateMessage::new(ChainId::try_from(vec![4, 3, 2, 1])?),
+            &mut peer,
+            &chain_id,
+            &actor_system,
+            &log,
+        ));
+        assert_eq!(peer.missing_mempool_operations.len(), 1);
+
+        // deactivation of our chain disconnects the peer
+        assert!(ChainManager::process_deactivate(
+            &DeactivateMessage::new(chain_id.clone()),
+            &mut peer,
+            &chain_id,
+            &actor_system,
+            &log,
+        ));
+        assert!(peer.missing_mempool_operations.is_empty());
+        match terminated.recv_timeout(RECV_TIMEOUT)? {
+            SystemEvent::ActorTerminated(evt) => {
+                assert_eq!(evt.actor.uri(), peer.peer_id.peer_ref.uri())
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_protocol_download() -> Result<(), Error> {
+        let log = create_logger(Level::Debug);
+        let tokio_runtime = create_test_tokio_runtime();
+        let actor_system = create_test_actor_system(log.clone());
+        let network_channel = NetworkChannel::actor(&actor_system)?;
+        let commands = collect_network_commands(&actor_system, &network_channel)?;
+        let peer = test_peer(&actor_system, network_channel.clone(), &tokio_runtime, 7777);
+        let storage = TmpStorage::create_to_out_dir("__test_chain_manager_protocol_download")?;
+        let protocol_storage = ProtocolStorage::new(storage.storage());
+        let mut requested_protocols = HashMap::new();
+        let mut pending_protocol_heads = HashMap::new();
+
+        let protocol = |implementation: &str| {
+            Protocol::new(
+                0,
+                vec![Component::new(
+                    "Main".to_string(),
+                    None,
+                    implementation.to_string(),
+                )],
+            )
+        };
+        let protocol_hash: ProtocolHash = protocol("let x = 1").message_typed_hash()?;
+        let message = PeerMessage::Protocol(ProtocolMessage::new(protocol("let x = 1")));
+        let unsolicited_message =
+            PeerMessage::Protocol(ProtocolMessage::new(protocol("let x = 2")));
+
+        // unknown protocol is requested just once (until the request times out)
+        ChainManager::request_protocol(
+            protocol_hash.clone(),
+            &peer,
+            &protocol_storage,
+            &mut requested_protocols,
+            &log,
+        )?;
+        let requested_last = requested_protocols[&protocol_hash];
+        ChainManager::request_protocol(
+            protocol_hash.clone(),
+            &peer,
+            &protocol_storage,
+            &mut requested_protocols,
+            &log,
+        )?;
+        assert_eq!(requested_protocols[&protocol_hash], requested_last);
+
+        // requested protocol is stored
+        assert!(ChainManager::process_protocol(
+            &message,
+            &peer,
+            &protocol_storage,
+            &mut requested_protocols,
+            &mut pending_protocol_heads,
+            |_, _| Ok(()),
+            &network_channel,
+            &log,
+        )?
+        .is_some());
+        assert!(protocol_storage.contains(&protocol_hash)?);
+        assert!(requested_protocols.is_empty());
+
+        // stored protocol is not requested anymore
+        ChainManager::request_protocol(
+            protocol_hash,
+            &peer,
+            &protocol_storage,
+            &mut requested_protocols,
+            &log,
+        )?;
+        assert!(requested_protocols.is_empty());
+
+        // late response to the recent request does not penalize the peer (swap marks the end of this step)
+        assert!(!ChainManager::process_protocol(
+            &message,
+            &peer,
+            &protocol_storage,
+            &mut requested_protocols,
+            &mut pending_protocol_heads,
+            |_, _| Ok(()),
+            &network_channel,
+            &log,
+        )?
+        .is_none());
+        ChainManager::process_swap_message(
+            &PeerMessage::SwapRequest(SwapMessage::new(
+                "127.0.0.1:9732".to_string(),
+                peer.peer_id.peer_public_key_hash.clone(),
+            )),
+            &peer,
+            &network_channel,
+        );
+        assert!(matches!(
+            commands.recv_timeout(RECV_TIMEOUT)?,
+            NetworkChannelMsg::ProcessSwapRequest(..)
+        ));
+
+        // protocol, which was not requested, penalizes the peer
+        assert!(!ChainManager::process_protocol(
+            &unsolicited_message,
+            &peer,
+            &protocol_storage,
+            &mut requested_protocols,
+            &mut pending_protocol_heads,
+            |_, _| Ok(()),
+            &network_channel,
+            &log,
+        )?
+        .is_none());
+        assert!(matches!(
+            commands.recv_timeout(RECV_TIMEOUT)?,
+            NetworkChannelMsg::PenalizePeer(
+                _,
+                PeerPenalty::Unsolicited {
+                    message_type: "protocol"
+                }
+            )
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_pending_head_revalidated_after_protocol_loaded() -> Result<(), Error> {
+        let log = create_logger(Level::Debug);
+        let tokio_runtime = create_test_tokio_runtime();
+        let actor_system = create_test_actor_system(log.clone());
+        let network_channel = NetworkChannel::actor(&actor_system)?;
+        let peer = test_peer(&actor_system, network_channel.clone(), &tokio_runtime, 7778);
+        let storage = TmpStorage::create_to_out_dir("__test_chain_manager_pending_protocol_heads")?;
+        let protocol_storage = ProtocolStorage::new(storage.storage());
+        let mut requested_protocols = HashMap::new();
+        let mut pending_protocol_heads = HashMap::new();
+
+        let protocol = |implementation: &str| {
+            Protocol::new(
+                0,
+                vec![Component::new(
+                    "Main".to_string(),
+                    None,
+                    implementation.to_string(),
+                )],
+            )
+        };
+        let current_head = |level: i32| -> Result<PeerMessageReceived, Error> {
+            let header = BlockHeaderBuilder::default()
+                .level(level)
+                .proto(2)
+                .predecessor("BKyQ9EofHrgaZKENioHyP4FZNsTmiSEcVmcghgzCC9cGhE7oCET".try_into()?)
+                .timestamp(5_635_634)
+                .validation_pass(4)
+                .operations_hash(
+                    "LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc".try_into()?,
+                )
+                .fitness(vec![vec![1], vec![0, 0, 0, 0, 0, 0, 0, 1]])
+                .context("CoVmAcMV64uAQo8XvfLr9VDuz7HVZLT4cgK1w1qYmTjQNbGwQwDd".try_into()?)
+                .protocol_data(vec![0, 1, 2, 3])
+                .build()
+                .map_err(|e| format_err!("{}", e))?;
+            Ok(PeerMessageReceived {
+                peer: peer.peer_id.peer_ref.clone(),
+                message: Arc::new(
+                    PeerMessage::CurrentHead(CurrentHeadMessage::new(
+                        ChainId::try_from(vec![1, 2, 3, 4])?,
+                        header,
+                        Mempool::default(),
+                    ))
+                    .into(),
+                ),
+            })
+        };
+        let current_head_level = |received: &PeerMessageReceived| match received.message.message() {
+            PeerMessage::CurrentHead(message) => message.current_block_header().level(),
+            _ => panic!("unexpected message"),
+        };
+
+        // head with unknown protocol waits for the download (just the last head of the peer)
+        let new_protocol = protocol("let x = 3");
+        let new_protocol_hash: ProtocolHash = new_protocol.message_typed_hash()?;
+        ChainManager::request_protocol(
+            new_protocol_hash.clone(),
+            &peer,
+            &protocol_storage,
+            &mut requested_protocols,
+            &log,
+        )?;
+        ChainManager::add_pending_protocol_head(
+            new_protocol_hash.clone(),
+            &current_head(5)?,
+            &mut pending_protocol_heads,
+        );
+        ChainManager::add_pending_protocol_head(
+            new_protocol_hash.clone(),
+            &current_head(6)?,
+            &mut pending_protocol_heads,
+        );
+
+        // downloaded protocol is loaded and the head is returned to be validated again
+        let mut loaded = Vec::new();
+        let pending_heads = ChainManager::process_protocol(
+            &PeerMessage::Protocol(ProtocolMessage::new(new_protocol.clone())),
+            &peer,
+            &protocol_storage,
+            &mut requested_protocols,
+            &mut pending_protocol_heads,
+            |protocol_hash, protocol| {
+                loaded.push((protocol_hash.clone(), protocol.as_bytes()?));
+                Ok(())
+            },
+            &network_channel,
+            &log,
+        )?
+        .expect("protocol should be stored");
+        assert_eq!(loaded, vec![(new_protocol_hash, new_protocol.as_bytes()?)]);
+        assert_eq!(
+            pending_heads
+                .iter()
+                .map(current_head_level)
+                .collect::<Vec<_>>(),
+            vec![6]
+        );
+        assert!(pending_protocol_heads.is_empty());
+
+        // head is not validated again, when the protocol fails to load
+        let invalid_protocol = protocol("let x =");
+        let invalid_protocol_hash: ProtocolHash = invalid_protocol.message_typed_hash()?;
+        ChainManager::request_protocol(
+            invalid_protocol_hash.clone(),
+            &peer,
+            &protocol_storage,
+            &mut requested_protocols,
+            &log,
+        )?;
+        ChainManager::add_pending_protocol_head(
+            invalid_protocol_hash.clone(),
+            &current_head(7)?,
+            &mut pending_protocol_heads,
+        );
+        let pending_heads = ChainManager::process_protocol(
+            &PeerMessage::Protocol(ProtocolMessage::new(invalid_protocol)),
+            &peer,
+            &protocol_storage,
+            &mut requested_protocols,
+            &mut pending_protocol_heads,
+            |_, _| Err(format_err!("compilation failed")),
+            &network_channel,
+            &log,
+        )?
+        .expect("protocol should be stored");
+        assert!(pending_heads.is_empty());
+        assert!(protocol_storage.contains(&invalid_protocol_hash)?);
+        assert!(pending_protocol_heads.is_empty());
+
+        Ok(())
+    }
+}
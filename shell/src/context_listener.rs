@@ -6,12 +6,15 @@
 use failure::Error;
 use riker::actors::*;
 use slog::{crit, info, warn, Logger};
+use std::convert::TryFrom;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::thread::JoinHandle;
 use std::time::Duration;
 
+use crypto::hash::BlockHash;
+use storage::context::gc::cycle_tracker::CycleTracker;
 use storage::context::{ActionRecorder, ContextApi, TezedgeContext};
 use storage::BlockStorage;
 use storage::PersistentStorage;
@@ -66,6 +69,7 @@ impl ContextListener {
                 ));
 
                 let mut context_action_recorders = context_action_recorders;
+                let mut cycle_tracker = CycleTracker::new(&persistent_storage);
 
                 while listener_run.load(Ordering::Acquire) {
                     match listen_protocol_events(
@@ -74,6 +78,7 @@ impl ContextListener {
                         Self::IPC_ACCEPT_TIMEOUT,
                         &mut context_action_recorders,
                         &mut context,
+                        &mut cycle_tracker,
                         &log,
                     ) {
                         Ok(()) => info!(log, "Context listener finished"),
@@ -166,6 +171,7 @@ fn listen_protocol_events(
     event_server_accept_timeout: Duration,
     context_action_recorders: &mut Vec<Box<dyn ActionRecorder + Send>>,
     context: &mut Box<dyn ContextApi>,
+    cycle_tracker: &mut CycleTracker,
     log: &Logger,
 ) -> Result<(), Error> {
     info!(
@@ -195,8 +201,24 @@ fn listen_protocol_events(
                     }
                 }
 
+                let committed_block = match &action {
+                    ContextAction::Commit {
+                        block_hash: Some(block_hash),
+                        ..
+                    } => Some(BlockHash::try_from(block_hash.clone())?),
+                    _ => None,
+                };
+
                 // evaluate context
                 context.perform_context_action(action)?;
+
+                // announce stored commit (and new cycle) to context garbage collector
+                if let Some(block_hash) = committed_block {
+                    if let Err(error) = cycle_tracker.block_committed(context.as_ref(), &block_hash)
+                    {
+                        warn!(log, "Failed to notify context garbage collector"; "block_hash" => block_hash.to_base58_check(), "reason" => format!("{}", error));
+                    }
+                }
             }
             Err(err) => {
                 warn!(log, "Failed to receive event from protocol runner"; "reason" => format!("{:?}", err));
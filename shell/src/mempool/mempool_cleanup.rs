@@ -0,0 +1,530 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Mempool operations are persisted in [MempoolStorage], so they survive restart of the node.
+//! Stored operations become stale, when they are included in a block or when their branch gets too old,
+//! so they are filtered on restoration and periodically removed from the storage.
+
+use std::collections::{HashSet, VecDeque};
+use std::time::{Duration, Instant, SystemTime};
+
+use crypto::hash::{BlockHash, OperationHash};
+use storage::{
+    BlockMetaStorage, BlockStorage, BlockStorageReader, MempoolStorage, OperationsStorage,
+    OperationsStorageReader, PersistentStorage, StorageError,
+};
+use tezos_messages::p2p::binary_message::MessageHash;
+use tezos_messages::p2p::encoding::prelude::Operation;
+
+use crate::mempool::mempool_state::MempoolState;
+
+/// How often are stale operations removed from mempool storage
+pub const MEMPOOL_STORAGE_CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Last `max_operations_ttl` blocks of the head and operations included in them.
+///
+/// Mempool operation is live, if it is branched on one of the live blocks and is not included in any of them.
+#[derive(Debug, Default, PartialEq)]
+pub struct LiveBlocks {
+    blocks: HashSet<BlockHash>,
+    operations: HashSet<OperationHash>,
+    /// Live blocks with their operations, the head is the first one
+    chain: VecDeque<(BlockHash, Vec<OperationHash>)>,
+    /// True, if the last block of the chain is genesis (or its predecessor is unknown)
+    reached_genesis: bool,
+}
+
+impl LiveBlocks {
+    /// Walks predecessors of the `head` up to `max_operations_ttl` of the head
+    pub fn load(
+        head: &BlockHash,
+        block_storage: &BlockStorage,
+        block_meta_storage: &BlockMetaStorage,
+        operations_storage: &OperationsStorage,
+    ) -> Result<Self, StorageError> {
+        let max_operations_ttl = max_operations_ttl(head, block_storage)?;
+
+        let mut live_blocks = LiveBlocks::default();
+        let mut block_hash = head.clone();
+        for _ in 0..=max_operations_ttl {
+            let operations = block_operations(&block_hash, operations_storage)?;
+            live_blocks.push_back(block_hash.clone(), operations);
+
+            let predecessor = block_meta_storage
+                .get(&block_hash)?
+                .and_then(|meta| meta.predecessor().clone());
+            match predecessor {
+                // predecessor of the genesis is genesis itself
+                Some(predecessor) if predecessor != block_hash => block_hash = predecessor,
+                _ => {
+                    live_blocks.reached_genesis = true;
+                    break;
+                }
+            }
+        }
+
+        Ok(live_blocks)
+    }
+
+    /// Moves live blocks to the successor `head` of the current head, the oldest blocks over `max_operations_ttl`
+    /// of the new head are dropped.
+    ///
+    /// Returns false, if the `head` is not a successor of the current head or older blocks are needed
+    /// (`max_operations_ttl` was raised), so live blocks have to be loaded again.
+    pub fn advance(
+        &mut self,
+        head: &BlockHash,
+        block_storage: &BlockStorage,
+        block_meta_storage: &BlockMetaStorage,
+        operations_storage: &OperationsStorage,
+    ) -> Result<bool, StorageError> {
+        let current_head = match self.chain.front() {
+            Some((current_head, _)) => current_head,
+            None => return Ok(false),
+        };
+        if current_head == head {
+            return Ok(true);
+        }
+        let predecessor = block_meta_storage
+            .get(head)?
+            .and_then(|meta| meta.predecessor().clone());
+        if predecessor.as_ref() != Some(current_head) {
+            return Ok(false);
+        }
+        let live_blocks_count = max_operations_ttl(head, block_storage)? as usize + 1;
+        if live_blocks_count > self.chain.len() + 1 && !self.reached_genesis {
+            return Ok(false);
+        }
+
+        let operations = block_operations(head, operations_storage)?;
+        for operation_hash in &operations {
+            self.operations.insert(operation_hash.clone());
+        }
+        self.blocks.insert(head.clone());
+        self.chain.push_front((head.clone(), operations));
+
+        while self.chain.len() > live_blocks_count {
+            if let Some((block_hash, operations)) = self.chain.pop_back() {
+                self.blocks.remove(&block_hash);
+                for operation_hash in operations {
+                    self.operations.remove(&operation_hash);
+                }
+                self.reached_genesis = false;
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn is_live(&self, operation_hash: &OperationHash, operation: &Operation) -> bool {
+        self.blocks.contains(operation.branch()) && !self.operations.contains(operation_hash)
+    }
+
+    fn push_back(&mut self, block_hash: BlockHash, operations: Vec<OperationHash>) {
+        self.operations.extend(operations.iter().cloned());
+        self.blocks.insert(block_hash.clone());
+        self.chain.push_back((block_hash, operations));
+    }
+}
+
+fn max_operations_ttl(
+    block_hash: &BlockHash,
+    block_storage: &BlockStorage,
+) -> Result<u16, StorageError> {
+    Ok(block_storage
+        .get_with_additional_data(block_hash)?
+        .map(|(_, additional_data)| additional_data.max_operations_ttl())
+        .unwrap_or(0))
+}
+
+fn block_operations(
+    block_hash: &BlockHash,
+    operations_storage: &OperationsStorage,
+) -> Result<Vec<OperationHash>, StorageError> {
+    let mut result = Vec::new();
+    for operations in operations_storage.get_operations(block_hash)? {
+        for operation in operations.operations() {
+            result.push(operation.message_typed_hash()?);
+        }
+    }
+    Ok(result)
+}
+
+/// Count of operations removed from mempool storage by one cleanup
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MempoolCleanupStats {
+    /// Operations already included in a block or with too old branch
+    pub stale: usize,
+    /// Operations unknown to mempool state, whose time to live expired
+    pub expired: usize,
+}
+
+/// Operations of mempool storage found by [MempoolStorageCleaner::find_stale] to be removed
+#[derive(Debug, Default)]
+pub struct StaleOperations {
+    /// Operations already included in a block or with too old branch
+    stale: Vec<OperationHash>,
+    /// Operations, whose time to live expired, they are removed just if they are unknown to mempool state
+    expired: Vec<OperationHash>,
+}
+
+impl StaleOperations {
+    /// Removes stale operations from mempool state and keeps just expired operations unknown to the state,
+    /// it is the only step of the cleanup, which needs (write) access to the state
+    pub fn remove_from_state(&mut self, state: &mut MempoolState) {
+        for operation_hash in &self.stale {
+            state.remove_operation(operation_hash.clone());
+        }
+        self.expired
+            .retain(|operation_hash| !state.operations().contains_key(operation_hash));
+    }
+}
+
+/// Keeps mempool storage in sync with the current head
+pub struct MempoolStorageCleaner {
+    block_storage: BlockStorage,
+    block_meta_storage: BlockMetaStorage,
+    operations_storage: OperationsStorage,
+    mempool_storage: MempoolStorage,
+    interval: Duration,
+    last_cleanup: Instant,
+    /// Live blocks of the current head, updated by [MempoolStorageCleaner::set_head]
+    live_blocks: Option<LiveBlocks>,
+}
+
+impl MempoolStorageCleaner {
+    pub fn new(persistent_storage: &PersistentStorage, interval: Duration) -> Self {
+        Self {
+            block_storage: BlockStorage::new(persistent_storage),
+            block_meta_storage: BlockMetaStorage::new(persistent_storage),
+            operations_storage: OperationsStorage::new(persistent_storage),
+            mempool_storage: MempoolStorage::new(persistent_storage),
+            interval,
+            last_cleanup: Instant::now(),
+            live_blocks: None,
+        }
+    }
+
+    /// Updates live blocks for the new mempool head, successor of the previous head just extends them,
+    /// otherwise (e.g. after reorg) they are loaded again
+    pub fn set_head(&mut self, head: Option<&BlockHash>) -> Result<(), StorageError> {
+        let head = match head {
+            Some(head) => head,
+            None => {
+                self.live_blocks = None;
+                return Ok(());
+            }
+        };
+
+        if let Some(live_blocks) = self.live_blocks.as_mut() {
+            let advanced = live_blocks.advance(
+                head,
+                &self.block_storage,
+                &self.block_meta_storage,
+                &self.operations_storage,
+            )?;
+            if advanced {
+                return Ok(());
+            }
+        }
+        self.live_blocks = Some(LiveBlocks::load(
+            head,
+            &self.block_storage,
+            &self.block_meta_storage,
+            &self.operations_storage,
+        )?);
+        Ok(())
+    }
+
+    pub fn live_blocks(&self) -> Option<&LiveBlocks> {
+        self.live_blocks.as_ref()
+    }
+
+    /// Loads stored operations, which are still live for the `head`, stale operations are removed from the storage.
+    /// Without head, all stored operations are returned.
+    pub fn restore(
+        &mut self,
+        head: Option<&BlockHash>,
+    ) -> Result<(Vec<(OperationHash, Operation)>, MempoolCleanupStats), StorageError> {
+        self.set_head(head)?;
+
+        let mut restored = Vec::new();
+        let mut stats = MempoolCleanupStats::default();
+        for (operation_hash, operation) in self.mempool_storage.iter()? {
+            let operation: Operation = operation.into();
+            let is_live = self.live_blocks.as_ref().map_or(true, |live_blocks| {
+                live_blocks.is_live(&operation_hash, &operation)
+            });
+            if is_live {
+                restored.push((operation_hash, operation));
+            } else {
+                self.mempool_storage.delete(&operation_hash)?;
+                stats.stale += 1;
+            }
+        }
+        Ok((restored, stats))
+    }
+
+    /// Returns true, if cleanup interval elapsed since the last cleanup
+    pub fn is_cleanup_time(&self) -> bool {
+        self.last_cleanup.elapsed() >= self.interval
+    }
+
+    /// Finds stale operations of mempool storage (for the head of the last [MempoolStorageCleaner::set_head])
+    /// and expired operations, mempool state is not needed, so it is not locked for the scan of the storage.
+    ///
+    /// Cleanup continues with [StaleOperations::remove_from_state] and [MempoolStorageCleaner::remove].
+    pub fn find_stale(&mut self) -> Result<StaleOperations, StorageError> {
+        self.last_cleanup = Instant::now();
+        let now = SystemTime::now();
+
+        let mut stale_operations = StaleOperations::default();
+        for (operation_hash, operation, time_to_live) in self.mempool_storage.iter_with_ttl()? {
+            let is_stale = self.live_blocks.as_ref().map_or(false, |live_blocks| {
+                !live_blocks.is_live(&operation_hash, operation.operation())
+            });
+            if is_stale {
+                stale_operations.stale.push(operation_hash);
+            } else if time_to_live < now {
+                stale_operations.expired.push(operation_hash);
+            }
+        }
+        Ok(stale_operations)
+    }
+
+    /// Removes stale and expired operations from mempool storage
+    pub fn remove(
+        &self,
+        stale_operations: StaleOperations,
+    ) -> Result<MempoolCleanupStats, StorageError> {
+        for operation_hash in stale_operations
+            .stale
+            .iter()
+            .chain(stale_operations.expired.iter())
+        {
+            self.mempool_storage.delete(operation_hash)?;
+        }
+        Ok(MempoolCleanupStats {
+            stale: stale_operations.stale.len(),
+            expired: stale_operations.expired.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::{TryFrom, TryInto};
+
+    use crypto::hash::{ChainId, ContextHash, HashType};
+    use storage::tests_common::TmpStorage;
+    use storage::{BlockAdditionalDataBuilder, BlockHeaderWithHash, OperationsMetaStorage};
+    use tezos_api::ffi::PrevalidatorWrapper;
+    use tezos_messages::p2p::binary_message::BinaryMessage;
+    use tezos_messages::p2p::encoding::prelude::{
+        BlockHeaderBuilder, OperationMessage, OperationsForBlock, OperationsForBlocksMessage, Path,
+    };
+
+    use crate::state::tests::prerequisites::create_logger;
+
+    use super::*;
+
+    const OPERATION_DATA: &str = "000008c387fa065a181d45d47a9b78ddc77e92a881779ff2cbabbf9646eade4bf1405a08e00b725ed849eea46953b10b5cdebc518e6fd47e69b82d2ca18c4cf6d2f312dd08";
+
+    #[test]
+    fn test_restore_and_cleanup() -> Result<(), failure::Error> {
+        let log = create_logger(slog::Level::Debug);
+        let tmp_storage = TmpStorage::create_to_out_dir("__mempool_cleanup")?;
+        let persistent_storage = tmp_storage.storage();
+        let block_storage = BlockStorage::new(persistent_storage);
+        let block_meta_storage = BlockMetaStorage::new(persistent_storage);
+        let operations_storage = OperationsStorage::new(persistent_storage);
+        let operations_meta_storage = OperationsMetaStorage::new(persistent_storage);
+        let mut mempool_storage = MempoolStorage::new(persistent_storage);
+        let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+
+        // operation included in block 2
+        let included = operation(&block_hash(0), 1)?;
+
+        // chain of 5 blocks, max_operations_ttl of the head is 2, so live blocks are 2, 3, 4
+        let mut blocks: Vec<BlockHeaderWithHash> = Vec::new();
+        for level in 0..5 {
+            let predecessor = match blocks.last() {
+                Some(predecessor) => predecessor.hash.clone(),
+                None => block_hash(0),
+            };
+            let block = BlockHeaderWithHash::new(
+                BlockHeaderBuilder::default()
+                    .level(level)
+                    .proto(0)
+                    .predecessor(predecessor)
+                    .timestamp(5_635_634 + level as i64)
+                    .validation_pass(1)
+                    .operations_hash(
+                        "LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc".try_into()?,
+                    )
+                    .fitness(vec![])
+                    .context(ContextHash::try_from(vec![level as u8; 32])?)
+                    .protocol_data(vec![])
+                    .build()
+                    .unwrap(),
+            )?;
+            storage::store_block_header(
+                &block_storage,
+                &block_meta_storage,
+                &operations_meta_storage,
+                &block,
+                &chain_id,
+                &log,
+            )?;
+            let included_operations = if level == 2 {
+                vec![included.1.operation().clone()]
+            } else {
+                vec![]
+            };
+            operations_storage.put_operations(&OperationsForBlocksMessage::new(
+                OperationsForBlock::new(block.hash.clone(), 0),
+                Path::op(),
+                included_operations,
+            ))?;
+            blocks.push(block);
+        }
+        let head = &blocks[4].hash;
+        block_storage.put_block_additional_data(
+            head,
+            BlockAdditionalDataBuilder::default()
+                .max_operations_ttl(2)
+                .last_allowed_fork_level(0)
+                .block_metadata_hash(None)
+                .ops_metadata_hash(None)
+                .ops_metadata_hashes(None)
+                .build()
+                .unwrap(),
+        )?;
+
+        let live = operation(&blocks[3].hash, 2)?;
+        let outdated = operation(&blocks[1].hash, 3)?;
+        let expired = operation(&blocks[4].hash, 4)?;
+        let ttl = SystemTime::now() + Duration::from_secs(600);
+        mempool_storage.put_pending(live.1.clone(), ttl)?;
+        mempool_storage.put_pending(outdated.1.clone(), ttl)?;
+        mempool_storage.put_known_valid(included.1.clone(), ttl)?;
+
+        // restore drops included and outdated operations
+        let mut cleaner = MempoolStorageCleaner::new(persistent_storage, Duration::from_secs(0));
+        let (restored, stats) = cleaner.restore(Some(head))?;
+        assert_eq!(2, stats.stale);
+        assert_eq!(
+            vec![live.0.clone()],
+            restored.into_iter().map(|(oph, _)| oph).collect::<Vec<_>>()
+        );
+        assert!(mempool_storage.find(&live.0)?.is_some());
+        assert!(mempool_storage.find(&outdated.0)?.is_none());
+        assert!(mempool_storage.find(&included.0)?.is_none());
+
+        // expired operation, which is not in mempool state, is removed by cleanup
+        mempool_storage.put_pending(expired.1.clone(), SystemTime::now())?;
+        let mut state = MempoolState::default();
+        let _ = state.reinit(
+            Some(PrevalidatorWrapper {
+                chain_id: chain_id.clone(),
+                protocol: "PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb".try_into()?,
+                context_fitness: None,
+            }),
+            Some(head.clone()),
+        );
+        assert!(state.add_to_pending(&live.0, live.1.operation().clone()));
+        assert!(cleaner.is_cleanup_time());
+        let mut stale_operations = cleaner.find_stale()?;
+        stale_operations.remove_from_state(&mut state);
+        let stats = cleaner.remove(stale_operations)?;
+        assert_eq!(
+            MempoolCleanupStats {
+                stale: 0,
+                expired: 1
+            },
+            stats
+        );
+        assert!(mempool_storage.find(&live.0)?.is_some());
+        assert!(mempool_storage.find(&expired.0)?.is_none());
+
+        // new head just extends cached live blocks, block 2 with the included operation is not live anymore
+        let new_head = BlockHeaderWithHash::new(
+            BlockHeaderBuilder::default()
+                .level(5)
+                .proto(0)
+                .predecessor(head.clone())
+                .timestamp(5_635_639)
+                .validation_pass(1)
+                .operations_hash(
+                    "LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc".try_into()?,
+                )
+                .fitness(vec![])
+                .context(ContextHash::try_from(vec![5; 32])?)
+                .protocol_data(vec![])
+                .build()
+                .unwrap(),
+        )?;
+        storage::store_block_header(
+            &block_storage,
+            &block_meta_storage,
+            &operations_meta_storage,
+            &new_head,
+            &chain_id,
+            &log,
+        )?;
+        block_storage.put_block_additional_data(
+            &new_head.hash,
+            BlockAdditionalDataBuilder::default()
+                .max_operations_ttl(2)
+                .last_allowed_fork_level(0)
+                .block_metadata_hash(None)
+                .ops_metadata_hash(None)
+                .ops_metadata_hashes(None)
+                .build()
+                .unwrap(),
+        )?;
+        let live_blocks = |head: &BlockHash| {
+            LiveBlocks::load(
+                head,
+                &block_storage,
+                &block_meta_storage,
+                &operations_storage,
+            )
+        };
+        cleaner.set_head(Some(&new_head.hash))?;
+        assert_eq!(Some(&live_blocks(&new_head.hash)?), cleaner.live_blocks());
+        assert!(cleaner
+            .live_blocks()
+            .unwrap()
+            .is_live(&live.0, live.1.operation()));
+        assert!(!cleaner
+            .live_blocks()
+            .unwrap()
+            .blocks
+            .contains(&blocks[2].hash));
+        assert!(cleaner.live_blocks().unwrap().operations.is_empty());
+
+        // head, which is not a successor (reorg), is loaded again
+        cleaner.set_head(Some(&blocks[3].hash))?;
+        assert_eq!(Some(&live_blocks(&blocks[3].hash)?), cleaner.live_blocks());
+
+        Ok(())
+    }
+
+    fn block_hash(byte: u8) -> BlockHash {
+        vec![byte; HashType::BlockHash.size()]
+            .try_into()
+            .expect("Failed to create BlockHash")
+    }
+
+    /// Creates unique operation (by `nonce`) branched on `branch`
+    fn operation(
+        branch: &BlockHash,
+        nonce: u8,
+    ) -> Result<(OperationHash, OperationMessage), failure::Error> {
+        let mut bytes: Vec<u8> = branch.as_ref().clone();
+        bytes.extend(hex::decode(OPERATION_DATA)?);
+        bytes.push(nonce);
+        let operation = Operation::from_bytes(bytes)?;
+        Ok((operation.message_typed_hash()?, operation.into()))
+    }
+}
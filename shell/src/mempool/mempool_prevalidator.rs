@@ -9,6 +9,9 @@
 //! Actor validates received operations and result of validate as a new MempoolState is send back to shell channel, where:
 //!     - is used by rpc_actor to show current mempool state - pending_operations
 //!     - is used by chain_manager to send new current head with current mempool to inform other peers throught P2P
+//!
+//! Operations stored in mempool storage are restored on startup (see [hydrate_state]), operations already included in blocks
+//! or with outdated branch are dropped and stale operations are periodically removed from mempool storage (see [MempoolStorageCleaner]).
 
 use std::collections::HashSet;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -35,6 +38,7 @@ use tezos_wrapper::service::{
 };
 use tezos_wrapper::TezosApiConnectionPool;
 
+use crate::mempool::mempool_cleanup::{MempoolStorageCleaner, MEMPOOL_STORAGE_CLEANUP_INTERVAL};
 use crate::mempool::mempool_state::collect_mempool;
 use crate::mempool::CurrentMempoolStateStorageRef;
 use crate::shell_channel::{ShellChannelMsg, ShellChannelRef, ShellChannelTopic};
@@ -91,6 +95,10 @@ impl MempoolPrevalidator {
                 let block_storage = BlockStorage::new(&persistent_storage);
                 let chain_meta_storage = ChainMetaStorage::new(&persistent_storage);
                 let mempool_storage = MempoolStorage::new(&persistent_storage);
+                let mut mempool_storage_cleaner = MempoolStorageCleaner::new(
+                    &persistent_storage,
+                    MEMPOOL_STORAGE_CLEANUP_INTERVAL,
+                );
 
                 while validator_run.load(Ordering::Acquire) {
                     match tezos_readonly_api.pool.get() {
@@ -98,6 +106,7 @@ impl MempoolPrevalidator {
                             &block_storage,
                             &chain_meta_storage,
                             &mempool_storage,
+                            &mut mempool_storage_cleaner,
                             current_mempool_state_storage.clone(),
                             &chain_id,
                             &validator_run,
@@ -287,6 +296,7 @@ fn process_prevalidation(
     block_storage: &BlockStorage,
     chain_meta_storage: &ChainMetaStorage,
     mempool_storage: &MempoolStorage,
+    mempool_storage_cleaner: &mut MempoolStorageCleaner,
     current_mempool_state_storage: CurrentMempoolStateStorageRef,
     chain_id: &ChainId,
     validator_run: &AtomicBool,
@@ -302,7 +312,7 @@ fn process_prevalidation(
         &shell_channel,
         block_storage,
         chain_meta_storage,
-        mempool_storage,
+        mempool_storage_cleaner,
         current_mempool_state_storage.clone(),
         &api,
         &chain_id,
@@ -311,8 +321,8 @@ fn process_prevalidation(
 
     // start receiving event
     while validator_run.load(Ordering::Acquire) {
-        // 1. at first let's handle event
-        if let Ok(event) = validator_event_receiver.recv() {
+        // 1. at first let's handle event (wait at most cleanup interval, so cleanup is not blocked by missing events)
+        if let Ok(event) = validator_event_receiver.recv_timeout(MEMPOOL_STORAGE_CLEANUP_INTERVAL) {
             match event {
                 Event::NewHead(header_hash, header) => {
                     debug!(log, "Mempool - new head received, so begin construction a new context";
@@ -322,6 +332,9 @@ fn process_prevalidation(
                     let (prevalidator, head) =
                         begin_construction(&api, &chain_id, header_hash, header, &log)?;
 
+                    // live blocks of the new head are updated without lock of the mempool state
+                    mempool_storage_cleaner.set_head(head.as_ref())?;
+
                     // reinitialize state for new prevalidator and head
                     let operations_to_delete = current_mempool_state_storage
                         .write()?
@@ -392,6 +405,15 @@ fn process_prevalidation(
             current_mempool_state_storage.clone(),
             &log,
         )?;
+
+        // 3. remove stale operations from mempool storage (and mempool state)
+        if mempool_storage_cleaner.is_cleanup_time() {
+            // storage is scanned without lock, the write lock is held just to remove stale operations from the state
+            let mut stale_operations = mempool_storage_cleaner.find_stale()?;
+            stale_operations.remove_from_state(&mut *current_mempool_state_storage.write()?);
+            let stats = mempool_storage_cleaner.remove(stale_operations)?;
+            debug!(log, "Mempool - storage cleanup finished"; "stale" => stats.stale, "expired" => stats.expired);
+        }
     }
 
     Ok(())
@@ -401,7 +423,7 @@ fn hydrate_state(
     shell_channel: &ShellChannelRef,
     block_storage: &BlockStorage,
     chain_meta_storage: &ChainMetaStorage,
-    mempool_storage: &MempoolStorage,
+    mempool_storage_cleaner: &mut MempoolStorageCleaner,
     current_mempool_state_storage: CurrentMempoolStateStorageRef,
     api: &ProtocolController,
     chain_id: &ChainId,
@@ -421,8 +443,9 @@ fn hydrate_state(
         None => (None, None),
     };
 
-    // read from Mempool_storage (just pending) -> add to queue for validation -> pending
-    let pending = mempool_storage.iter()?;
+    // read from Mempool_storage -> drop operations already included or outdated for the head -> pending
+    let (pending, stats) = mempool_storage_cleaner.restore(head.as_ref())?;
+    info!(log, "Mempool - operations restored from storage"; "restored" => pending.len(), "stale" => stats.stale);
 
     // initialize internal mempool state (write lock)
     let mut state = current_mempool_state_storage.write()?;
@@ -430,7 +453,7 @@ fn hydrate_state(
     // reinit + add old unprocessed pendings
     let _ = state.reinit(prevalidator, head);
     for (oph, op) in pending {
-        let _ = state.add_to_pending(&oph, op.into());
+        let _ = state.add_to_pending(&oph, op);
     }
     // drop write lock
     drop(state);
@@ -7,9 +7,10 @@ use std::cmp;
 use std::collections::{HashMap, HashSet};
 use std::iter::FromIterator;
 use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, PoisonError, RwLock};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 use dns_lookup::LookupError;
 use failure::Fail;
@@ -22,14 +23,19 @@ use tokio::runtime::Handle;
 use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tokio::time::timeout;
 
+use crypto::hash::CryptoboxPublicKeyHash;
 use networking::p2p::peer::{bootstrap, Bootstrap, BootstrapOutput, Peer, PeerRef, SendMessage};
 use networking::p2p::{
+    capture::TrafficRecorderRef,
     network_channel::{
-        NetworkChannelMsg, NetworkChannelRef, NetworkChannelTopic, PeerBootstrapFailed,
+        NetworkChannelMsg, NetworkChannelRef, NetworkChannelTopic, PeerBootstrapFailed, PeerPenalty,
     },
     peer::PeerError,
+    rate_limit::{RateLimitConfig, RateLimiterRef},
 };
 use networking::{LocalPeerInfo, PeerId, ShellCompatibilityVersion};
+use storage::peer_storage::PointInfo;
+use storage::{PeerStorage, StorageError};
 use tezos_identity::Identity;
 use tezos_messages::p2p::encoding::limits::ADVERTISE_ID_LIST_MAX_LENGTH_FOR_SEND;
 use tezos_messages::p2p::encoding::prelude::*;
@@ -40,8 +46,12 @@ use crate::PeerConnectionThreshold;
 
 /// Timeout for outgoing connections
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(8);
-/// Whitelist all IP addresses after 30 minutes
-const WHITELIST_INTERVAL: Duration = Duration::from_secs(1_800);
+/// How long is IP address blacklisted
+const BAN_DURATION: Duration = Duration::from_secs(1_800);
+/// Backoff of reconnection after the first failure, doubled with every next failure
+const RECONNECT_BACKOFF_BASE: Duration = Duration::from_secs(15);
+/// Maximal backoff of reconnection
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(3_600);
 /// How often to do DNS peer discovery
 const DISCOVERY_INTERVAL: Duration = Duration::from_secs(60);
 /// Limit how often we allow to trigger check of a peer count
@@ -51,6 +61,14 @@ const CHECK_PEER_COUNT_LIMIT: Duration = Duration::from_secs(5);
 static ACTOR_ID_GENERATOR: AtomicU64 = AtomicU64::new(0);
 /// How often to print stats in logs
 const LOG_INTERVAL: Duration = Duration::from_secs(60);
+/// Minimal interval between two swaps of connections, also how long we wait for swap ack
+const SWAP_LINGER: Duration = Duration::from_secs(30);
+/// Maximal count of known points (memory and [PeerStorage]), the least valuable points are evicted over it
+const MAX_KNOWN_POINTS: usize = 4_096;
+/// Peer is blacklisted, when its misbehaviour score reaches this threshold
+const MISBEHAVIOUR_SCORE_THRESHOLD: f64 = 100.0;
+/// Misbehaviour score is decreased by this value every second
+const MISBEHAVIOUR_SCORE_DECAY_PER_SEC: f64 = 1.0;
 
 /// Message commands [`PeerManager`] to log its internal stats.
 #[derive(Clone, Debug)]
@@ -61,10 +79,25 @@ pub struct LogPeerStats;
 #[derive(Clone, Debug)]
 pub struct CheckPeerCount;
 
-/// Whitelist all IP address.
+/// Whitelist all IP address (regardless of ban expiration).
 #[derive(Clone, Debug)]
 pub struct WhitelistAllIpAddresses;
 
+/// Result of the outgoing connection to the remote peer node.
+#[derive(Clone, Debug)]
+pub struct OutgoingConnectionResult {
+    address: SocketAddr,
+    connected: bool,
+}
+
+/// Swap waiting for the result of the connection to the new point
+struct PendingSwap {
+    /// Peer replaced by the new connection
+    replaced_peer: PeerRef,
+    /// Swap ack for the replaced peer, if the swap was requested by it (sent only when connection succeeds)
+    ack: Option<Arc<PeerMessageResponse>>,
+}
+
 pub type IncomingConnectionPermit = Arc<OwnedSemaphorePermit>;
 
 /// Accept incoming peer connection.
@@ -100,6 +133,14 @@ pub struct P2p {
 
     /// Peers (IP:port) which we try to connect all the time
     pub bootstrap_peers: Vec<SocketAddr>,
+
+    /// Limits of the incoming traffic from peers
+    pub rate_limit: RateLimitConfig,
+
+    /// File, where decrypted p2p traffic of all peers is captured (for replay)
+    pub capture_file: Option<PathBuf>,
+    /// Size of the capture file in bytes, when it is rotated
+    pub capture_file_max_size: u64,
 }
 
 impl P2p {
@@ -111,6 +152,14 @@ impl P2p {
 pub enum PeerManagerError {
     #[fail(display = "Mutex/lock error, reason: {:?}", reason)]
     LockError { reason: String },
+    #[fail(display = "Storage error: {}", error)]
+    StorageError { error: StorageError },
+}
+
+impl From<StorageError> for PeerManagerError {
+    fn from(error: StorageError) -> Self {
+        PeerManagerError::StorageError { error }
+    }
 }
 
 impl<T> From<PoisonError<T>> for PeerManagerError {
@@ -124,13 +173,14 @@ impl<T> From<PoisonError<T>> for PeerManagerError {
 /// This actor is responsible for peer management.
 ///
 /// It monitors number of connected peers. If the number of connected peers is too low it tries to
-/// connect to more peers. If the number of connected peers is too high, then randomly selected peers
-/// are disconnected.
+/// connect to more peers (preferring the ones with higher score, see [P2pPoints]). If the number of
+/// connected peers is too high, then randomly selected peers are disconnected.
 #[actor(
     CheckPeerCount,
     WhitelistAllIpAddresses,
     AcceptPeer,
     ConnectToPeer,
+    OutgoingConnectionResult,
     LogPeerStats,
     NetworkChannelMsg,
     ShellChannelMsg,
@@ -144,12 +194,16 @@ pub struct PeerManager {
     shell_channel: ShellChannelRef,
     /// Tokio runtime
     tokio_executor: Handle,
+    /// Limits of the incoming traffic shared by all peers
+    rate_limiter: RateLimiterRef,
+    /// Optional capture of the decrypted traffic of all peers
+    traffic_recorder: Option<TrafficRecorderRef>,
 
     /// Peer count threshold
     threshold: Arc<PeerConnectionThreshold>,
 
-    // PeerManager's state of peers (potential and connected)
-    peers: Arc<P2pPeers>,
+    // PeerManager's state of peers (potential and connected) shared with RPC
+    peers: P2pPeersRef,
 
     /// Bootstrap peer, which we try to connect all the the, if no other peers presents
     bootstrap_addresses: HashSet<(String, u16)>,
@@ -159,6 +213,8 @@ pub struct PeerManager {
     /// Indicates that p2p is working in private mode
     private_node: bool,
 
+    /// Our node identity
+    identity_peer_id: CryptoboxPublicKeyHash,
     /// Local node info covers:
     /// - listener_port - we will listen for incoming connection at this port
     /// - identity
@@ -170,12 +226,18 @@ pub struct PeerManager {
     /// Message receiver boolean indicating whether
     /// more connections should be accepted from network
     rx_run: Arc<AtomicBool>,
-    /// set of blacklisted IP addresses
-    ip_blacklist: HashSet<IpAddr>,
     /// Last time we did DNS peer discovery
     discovery_last: Option<Instant>,
     /// Last time we checked peer count
     check_peer_count_last: Option<Instant>,
+    /// Last time we accepted swap (requested by remote peer or acknowledged by remote peer)
+    swap_last: Option<Instant>,
+    /// Peers we sent swap request to (swap ack is accepted just from them)
+    swap_requests: HashMap<ActorUri, Instant>,
+    /// Points we connect to because of swap with the peer, which is disconnected, when connection succeeds
+    pending_swaps: HashMap<SocketAddr, PendingSwap>,
+    /// Misbehaviour scores of the connected peers
+    misbehaviour: HashMap<ActorUri, MisbehaviourScore>,
     /// Indicates that system is shutting down
     shutting_down: bool,
 }
@@ -188,7 +250,10 @@ impl PeerManager {
         sys: &impl ActorRefFactory,
         network_channel: NetworkChannelRef,
         shell_channel: ShellChannelRef,
+        peers: P2pPeersRef,
         tokio_executor: Handle,
+        rate_limiter: RateLimiterRef,
+        traffic_recorder: Option<TrafficRecorderRef>,
         identity: Arc<Identity>,
         shell_compatibility_version: Arc<ShellCompatibilityVersion>,
         p2p_config: P2p,
@@ -198,7 +263,10 @@ impl PeerManager {
             Props::new_args((
                 network_channel,
                 shell_channel,
+                peers,
                 tokio_executor,
+                rate_limiter,
+                traffic_recorder,
                 identity,
                 shell_compatibility_version,
                 p2p_config,
@@ -249,13 +317,12 @@ impl PeerManager {
         // write lock for potential peers
         let mut potential_peers = self.peers.potential_peers.write()?;
 
-        // randomize potential peers as a security measurement
-        let mut addresses_to_connect = potential_peers.iter().cloned().collect::<Vec<SocketAddr>>();
-        addresses_to_connect.shuffle(&mut rand::thread_rng());
-
-        // drain required count
-        addresses_to_connect
-            .drain(0..cmp::min(num_of_required_peers, addresses_to_connect.len()))
+        // select required count (ordered by score, without backoff or ban)
+        self.peers
+            .points
+            .read()?
+            .select_points_to_connect(&potential_peers, num_of_required_peers, SystemTime::now())
+            .into_iter()
             .for_each(|address| {
                 potential_peers.remove(&address);
                 ctx.myself()
@@ -278,6 +345,7 @@ impl PeerManager {
         sys: &impl ActorRefFactory,
         network_channel: NetworkChannelRef,
         tokio_executor: Handle,
+        rate_limiter: RateLimiterRef,
         info: BootstrapOutput,
     ) -> Result<PeerRef, CreateError> {
         Peer::actor(
@@ -285,21 +353,25 @@ impl PeerManager {
             sys,
             network_channel,
             tokio_executor,
+            rate_limiter,
             info,
         )
     }
 
     /// Check if given ip address is blacklisted to connect to
-    fn is_blacklisted(&self, ip_address: &IpAddr) -> bool {
-        self.ip_blacklist.contains(ip_address)
+    fn is_blacklisted(&self, ip_address: &IpAddr) -> Result<bool, PeerManagerError> {
+        self.peers.is_blacklisted(ip_address)
     }
 
     fn blacklist_address(&mut self, address: SocketAddr, reason: String, log: &Logger) {
         info!(log, "Blacklisting IP";
                    "ip" => format!("{}", address.ip()),
-                   "reason" => reason,
+                   "reason" => reason.clone(),
+                   "ban_duration" => format!("{:?}", BAN_DURATION),
         );
-        self.ip_blacklist.insert(address.ip());
+        if let Err(e) = self.peers.ban_point(address, reason) {
+            warn!(log, "Failed to store ban of the point"; "point" => address, "reason" => format!("{:?}", e));
+        }
 
         // TODO: call firewall
     }
@@ -328,6 +400,45 @@ impl PeerManager {
         );
     }
 
+    /// Adds penalty to the peer's misbehaviour score, peer is blacklisted, when score reaches threshold
+    fn penalize_peer(
+        &mut self,
+        peer_id: Arc<PeerId>,
+        penalty: PeerPenalty,
+        actor_system: &ActorSystem,
+    ) -> Result<(), PeerManagerError> {
+        let peer_uri = peer_id.peer_ref.uri();
+        // ignore late penalties of already disconnected peers
+        if !self.peers.connected_peers.read()?.contains_key(peer_uri) {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let score = self
+            .misbehaviour
+            .entry(peer_uri.clone())
+            .or_insert_with(|| MisbehaviourScore::new(now))
+            .add(penalty.score(), now);
+        debug!(actor_system.log(), "Peer penalized";
+                   "peer_id" => peer_id.peer_id_marker.clone(),
+                   "penalty" => format!("{:?}", &penalty),
+                   "score" => score);
+
+        if score >= MISBEHAVIOUR_SCORE_THRESHOLD {
+            self.misbehaviour.remove(peer_uri);
+            self.blacklist_peer(
+                peer_id,
+                format!(
+                    "misbehaviour score {:.0} reached threshold, last penalty: {:?}",
+                    score, penalty
+                ),
+                actor_system,
+            );
+        }
+
+        Ok(())
+    }
+
     fn trigger_check_peer_count(&mut self, ctx: &Context<PeerManagerMsg>) {
         if self.shutting_down {
             return;
@@ -348,10 +459,14 @@ impl PeerManager {
         &mut self,
         new_potential_peers: I,
     ) -> Result<(), PeerManagerError> {
-        let sock_addresses = new_potential_peers
-            .into_iter()
-            .filter(|address: &SocketAddr| !self.is_blacklisted(&address.ip()))
-            .collect::<Vec<_>>();
+        let sock_addresses = {
+            let points = self.peers.points.read()?;
+            let now = SystemTime::now();
+            new_potential_peers
+                .into_iter()
+                .filter(|address: &SocketAddr| !points.is_blacklisted(&address.ip(), now))
+                .collect::<Vec<_>>()
+        };
 
         // we want to make sure, that we dont want to have unlimited potential peers (num_of_required_peers * 10)
         let num_of_max_potential_peers = self.calculate_count_of_required_peers()? * 10;
@@ -382,6 +497,12 @@ impl PeerManager {
     fn check_peer_count(&mut self, ctx: &Context<PeerManagerMsg>) -> Result<(), PeerManagerError> {
         let connected_peers_count = self.peers.connected_peers.read()?.len();
 
+        // whitelist IP addresses with expired ban
+        self.peers
+            .points
+            .write()?
+            .remove_expired_bans(SystemTime::now())?;
+
         if connected_peers_count < self.threshold.low {
             // peer count is too low, try to connect to more peers
             let log = ctx.system.log();
@@ -409,6 +530,9 @@ impl PeerManager {
                 .iter()
                 .take(connected_peers_count - self.threshold.high)
                 .for_each(|peer_state| ctx.system.stop(peer_state.peer_ref.clone()))
+        } else {
+            // peer count is ok, try to replace some connection
+            self.send_swap_request(&ctx.system.log())?;
         }
 
         self.check_peer_count_last = Some(Instant::now());
@@ -416,6 +540,157 @@ impl PeerManager {
         Ok(())
     }
 
+    /// Returns true, if we are allowed to do next swap (swap is disabled for private node)
+    fn is_swap_allowed(&self) -> bool {
+        !self.private_node
+            && self
+                .swap_last
+                .filter(|swap_last| swap_last.elapsed() <= SWAP_LINGER)
+                .is_none()
+    }
+
+    /// Proposes to a random peer to replace its connection to us with a connection to other peer,
+    /// which we are connected to
+    fn send_swap_request(&mut self, log: &Logger) -> Result<(), PeerManagerError> {
+        self.swap_requests
+            .retain(|_, requested_at| requested_at.elapsed() <= SWAP_LINGER);
+        if !self.is_swap_allowed() || !self.swap_requests.is_empty() {
+            return Ok(());
+        }
+
+        let recipient = self
+            .peers
+            .connected_peers
+            .read()?
+            .values()
+            .cloned()
+            .collect::<Vec<_>>()
+            .choose(&mut rand::thread_rng())
+            .cloned();
+        if let Some(recipient) = recipient {
+            if let Some(proposed) = self.peers.random_swap_peer(&recipient.peer_ref)? {
+                debug!(log, "Sending swap request";
+                            "peer_id" => recipient.connection.peer_id_marker.clone(),
+                            "proposed_point" => proposed.peer_address,
+                            "proposed_peer_id" => proposed.connection.peer_id_marker.clone());
+                let msg: Arc<PeerMessageResponse> = Arc::new(
+                    PeerMessage::SwapRequest(SwapMessage::new(
+                        proposed.peer_address.to_string(),
+                        proposed.connection.peer_public_key_hash.clone(),
+                    ))
+                    .into(),
+                );
+                recipient.peer_ref.tell(SendMessage::new(msg), None);
+                self.swap_requests
+                    .insert(recipient.peer_ref.uri().clone(), Instant::now());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the point advertised by swap message, if we can connect to it
+    fn swap_point(&self, message: &SwapMessage) -> Result<Option<SocketAddr>, PeerManagerError> {
+        let point = match message.point().parse::<SocketAddr>() {
+            Ok(point) => point,
+            Err(_) => return Ok(None),
+        };
+        if message.peer_id() == &self.identity_peer_id
+            || point == self.listener_address
+            || !self
+                .peers
+                .is_swap_point_acceptable(&point, message.peer_id())?
+        {
+            return Ok(None);
+        }
+        Ok(Some(point))
+    }
+
+    /// Connects to the point and disconnects the peer, when connection succeeds,
+    /// swap `ack` is sent to the peer just before the disconnection
+    fn swap(
+        &mut self,
+        ctx: &Context<PeerManagerMsg>,
+        peer: &PeerId,
+        point: SocketAddr,
+        ack: Option<Arc<PeerMessageResponse>>,
+    ) {
+        info!(ctx.system.log(), "Swapping peer connection";
+                                "peer_id" => peer.peer_id_marker.clone(),
+                                "point" => point);
+        self.swap_last = Some(Instant::now());
+        self.pending_swaps.insert(
+            point,
+            PendingSwap {
+                replaced_peer: peer.peer_ref.clone(),
+                ack,
+            },
+        );
+        ctx.myself()
+            .tell(ConnectToPeer { address: point }, ctx.myself().into());
+    }
+
+    /// Remote peer asks us to replace our connection to it with a connection to the advertised point,
+    /// in exchange we propose one of our peers to it
+    fn process_swap_request(
+        &mut self,
+        ctx: &Context<PeerManagerMsg>,
+        peer: Arc<PeerId>,
+        message: SwapMessage,
+    ) -> Result<(), PeerManagerError> {
+        let log = ctx.system.log();
+        if !self.is_swap_allowed() {
+            debug!(log, "Ignoring swap request"; "peer_id" => peer.peer_id_marker.clone(), "reason" => "swap linger");
+            return Ok(());
+        }
+        let point = match self.swap_point(&message)? {
+            Some(point) => point,
+            None => {
+                debug!(log, "Ignoring swap request"; "peer_id" => peer.peer_id_marker.clone(), "point" => message.point().clone(), "reason" => "point is not acceptable");
+                return Ok(());
+            }
+        };
+        let proposed = match self.peers.random_swap_peer(&peer.peer_ref)? {
+            Some(proposed) => proposed,
+            None => {
+                debug!(log, "Ignoring swap request"; "peer_id" => peer.peer_id_marker.clone(), "reason" => "no peer to propose");
+                return Ok(());
+            }
+        };
+
+        // ack is sent after we connect to the advertised point
+        let ack: Arc<PeerMessageResponse> = Arc::new(
+            PeerMessage::SwapAck(SwapMessage::new(
+                proposed.peer_address.to_string(),
+                proposed.connection.peer_public_key_hash.clone(),
+            ))
+            .into(),
+        );
+        self.swap(ctx, &peer, point, Some(ack));
+        Ok(())
+    }
+
+    /// Remote peer accepted our swap request and proposed its peer instead
+    fn process_swap_ack(
+        &mut self,
+        ctx: &Context<PeerManagerMsg>,
+        peer: Arc<PeerId>,
+        message: SwapMessage,
+    ) -> Result<(), PeerManagerError> {
+        let log = ctx.system.log();
+        if self.swap_requests.remove(peer.peer_ref.uri()).is_none() {
+            debug!(log, "Ignoring swap ack"; "peer_id" => peer.peer_id_marker.clone(), "reason" => "swap was not requested");
+            return Ok(());
+        }
+        match self.swap_point(&message)? {
+            Some(point) => self.swap(ctx, &peer, point, None),
+            None => {
+                debug!(log, "Ignoring swap ack"; "peer_id" => peer.peer_id_marker.clone(), "point" => message.point().clone(), "reason" => "point is not acceptable");
+            }
+        }
+        Ok(())
+    }
+
     fn process_network_channel_message(
         &mut self,
         ctx: &Context<PeerManagerMsg>,
@@ -476,6 +751,15 @@ impl PeerManager {
             NetworkChannelMsg::BlacklistPeer(peer_id, reason) => {
                 self.blacklist_peer(peer_id, reason, &ctx.system);
             }
+            NetworkChannelMsg::ProcessSwapRequest(peer, message) => {
+                self.process_swap_request(ctx, peer, message)?;
+            }
+            NetworkChannelMsg::ProcessSwapAck(peer, message) => {
+                self.process_swap_ack(ctx, peer, message)?;
+            }
+            NetworkChannelMsg::PenalizePeer(peer, penalty) => {
+                self.penalize_peer(peer, penalty, &ctx.system)?;
+            }
             _ => (),
         }
 
@@ -487,7 +771,10 @@ impl
     ActorFactoryArgs<(
         NetworkChannelRef,
         ShellChannelRef,
+        P2pPeersRef,
         Handle,
+        RateLimiterRef,
+        Option<TrafficRecorderRef>,
         Arc<Identity>,
         Arc<ShellCompatibilityVersion>,
         P2p,
@@ -497,14 +784,20 @@ impl
         (
             network_channel,
             shell_channel,
+            peers,
             tokio_executor,
+            rate_limiter,
+            traffic_recorder,
             identity,
             shell_compatibility_version,
             p2p_config,
         ): (
             NetworkChannelRef,
             ShellChannelRef,
+            P2pPeersRef,
             Handle,
+            RateLimiterRef,
+            Option<TrafficRecorderRef>,
             Arc<Identity>,
             Arc<ShellCompatibilityVersion>,
             P2p,
@@ -524,14 +817,15 @@ impl
             bootstrap_addresses.extend(p2p_config.bootstrap_lookup_addresses);
         };
 
-        let peers_threshold = Arc::new(p2p_config.peer_threshold);
-
         PeerManager {
             network_channel,
             shell_channel,
             tokio_executor,
+            rate_limiter,
+            traffic_recorder,
             bootstrap_addresses,
-            threshold: peers_threshold.clone(),
+            threshold: peers.peers_threshold.clone(),
+            identity_peer_id: identity.peer_id(),
             local_node_info: Arc::new(LocalPeerInfo::new(
                 p2p_config.listener_port,
                 identity,
@@ -541,10 +835,13 @@ impl
             disable_mempool: p2p_config.disable_mempool,
             private_node: p2p_config.private_node,
             rx_run: Arc::new(AtomicBool::new(true)),
-            peers: Arc::new(P2pPeers::new(peers_threshold)),
-            ip_blacklist: HashSet::new(),
+            peers,
             discovery_last: None,
             check_peer_count_last: None,
+            swap_last: None,
+            swap_requests: HashMap::new(),
+            pending_swaps: HashMap::new(),
+            misbehaviour: HashMap::new(),
             shutting_down: false,
         }
     }
@@ -566,13 +863,6 @@ impl Actor for PeerManager {
             None,
             CheckPeerCount.into(),
         );
-        ctx.schedule::<Self::Msg, _>(
-            WHITELIST_INTERVAL,
-            WHITELIST_INTERVAL,
-            ctx.myself(),
-            None,
-            WhitelistAllIpAddresses.into(),
-        );
         ctx.schedule::<Self::Msg, _>(
             LOG_INTERVAL / 2,
             LOG_INTERVAL,
@@ -594,6 +884,20 @@ impl Actor for PeerManager {
     }
 
     fn post_start(&mut self, ctx: &Context<Self::Msg>) {
+        // load known points from the previous runs
+        match self.peers.load_points(SystemTime::now()) {
+            Ok((known_points, blacklisted_ip_count)) => {
+                info!(ctx.system.log(), "Known points loaded";
+                                        "known_points" => known_points.len(),
+                                        "blacklisted_ip_count" => blacklisted_ip_count);
+                if let Err(e) = self.process_new_potential_peers(known_points) {
+                    warn!(ctx.system.log(), "Failed to add known points to potential peers"; "reason" => format!("{:?}", e));
+                }
+            }
+            Err(e) => {
+                warn!(ctx.system.log(), "Failed to load known points"; "reason" => format!("{:?}", e))
+            }
+        }
         if let Err(e) = self.discover_peers(&ctx.system.log()) {
             warn!(ctx.system.log(), "Failed to discovery peers on startup"; "reason" => format!("{:?}", e));
         }
@@ -676,11 +980,15 @@ impl Receive<LogPeerStats> for PeerManager {
             Ok(potential_peers) => potential_peers.len().to_string(),
             Err(_) => "-failed-to-collect-".to_string(),
         };
+        let blacklisted_ip_count = match self.peers.points.read() {
+            Ok(points) => points.blacklisted_ip_count().to_string(),
+            Err(_) => "-failed-to-collect-".to_string(),
+        };
         info!(ctx.system.log(), "Peer manager info";
             "connected_peers_count" => connected_peers_count,
             "potential_peers_count" => potential_peers_count,
             "incoming_connection_tickets_available" => self.peers.incoming_connection_tickets.available_permits(),
-            "blacklisted_ip_count" => self.ip_blacklist.len(),
+            "blacklisted_ip_count" => blacklisted_ip_count,
             "check_peer_count_last_elapsed" => match self.check_peer_count_last.as_ref() {
                 Some(time) => format!("{:?}", time.elapsed()),
                 None => "--none--".to_string()
@@ -718,6 +1026,7 @@ impl Receive<SystemEvent> for PeerManager {
 
             // try to remove peers actor
             let peer_actor_uri = evt.actor.uri();
+            self.misbehaviour.remove(peer_actor_uri);
             match self.peers.try_remove_peer_actor(peer_actor_uri) {
                 Ok(was_removed) => {
                     if was_removed {
@@ -767,7 +1076,48 @@ impl Receive<WhitelistAllIpAddresses> for PeerManager {
         _sender: Sender,
     ) {
         info!(ctx.system.log(), "Whitelisting all IP addresses");
-        self.ip_blacklist.clear();
+        if let Err(e) = self.peers.remove_all_bans() {
+            warn!(ctx.system.log(), "Failed to remove stored bans"; "reason" => format!("{:?}", e));
+        }
+    }
+}
+
+impl Receive<OutgoingConnectionResult> for PeerManager {
+    type Msg = PeerManagerMsg;
+
+    fn receive(
+        &mut self,
+        ctx: &Context<Self::Msg>,
+        msg: OutgoingConnectionResult,
+        _sender: Sender,
+    ) {
+        // finish swap - disconnect replaced peer
+        if let Some(PendingSwap { replaced_peer, ack }) = self.pending_swaps.remove(&msg.address) {
+            if msg.connected {
+                debug!(ctx.system.log(), "Swap finished - disconnecting replaced peer"; "point" => msg.address, "peer_uri" => replaced_peer.uri().to_string());
+                if let Some(ack) = ack {
+                    replaced_peer.tell(SendMessage::new(ack), None);
+                }
+                ctx.system.stop(replaced_peer);
+            } else if ack.is_some() {
+                debug!(ctx.system.log(), "Swap failed - swap ack is not sent"; "point" => msg.address, "peer_uri" => replaced_peer.uri().to_string());
+            }
+        }
+
+        let now = SystemTime::now();
+        let result = if msg.connected {
+            self.peers.point_connected(msg.address, now)
+        } else {
+            self.peers.point_failed(msg.address, now)
+        };
+        match result {
+            Ok(info) => {
+                trace!(ctx.system.log(), "Point updated"; "point" => msg.address, "connected" => msg.connected, "score" => info.score(), "failures" => info.failures())
+            }
+            Err(e) => {
+                warn!(ctx.system.log(), "Failed to store point info"; "point" => msg.address, "reason" => format!("{:?}", e))
+            }
+        }
     }
 }
 
@@ -777,9 +1127,18 @@ impl Receive<ConnectToPeer> for PeerManager {
     fn receive(&mut self, ctx: &Context<Self::Msg>, msg: ConnectToPeer, _sender: Sender) {
         // received message instructing this actor that it should open new p2p connection to the remote peer
 
-        if self.is_blacklisted(&msg.address.ip()) {
-            debug!(ctx.system.log(), "Peer is blacklisted - will not connect"; "ip" => format!("{}", msg.address.ip()));
-            return;
+        match self.is_blacklisted(&msg.address.ip()) {
+            Ok(false) => (),
+            Ok(true) => {
+                debug!(ctx.system.log(), "Peer is blacklisted - will not connect"; "ip" => format!("{}", msg.address.ip()));
+                self.pending_swaps.remove(&msg.address);
+                return;
+            }
+            Err(e) => {
+                warn!(ctx.system.log(), "Failed to check blacklisted peer - will not connect"; "ip" => format!("{}", msg.address.ip()), "reason" => format!("{:?}", e));
+                self.pending_swaps.remove(&msg.address);
+                return;
+            }
         }
 
         // spawn non-blocking tcp stream for outgoing connection
@@ -787,43 +1146,61 @@ impl Receive<ConnectToPeer> for PeerManager {
         let local_node_info = self.local_node_info.clone();
         let network_channel = self.network_channel.clone();
         let tokio_executor = self.tokio_executor.clone();
+        let rate_limiter = self.rate_limiter.clone();
+        let traffic_recorder = self.traffic_recorder.clone();
         let disable_mempool = self.disable_mempool;
         let private_node = self.private_node;
         let peers = self.peers.clone();
+        let myself = ctx.myself();
 
         self.tokio_executor.spawn(async move {
             let log = system.log();
             debug!(log, "(Outgoing) Connecting to IP"; "ip" => msg.address);
-            match timeout(CONNECT_TIMEOUT, TcpStream::connect(&msg.address)).await {
+            let connected = match timeout(CONNECT_TIMEOUT, TcpStream::connect(&msg.address)).await {
                 Ok(Ok(stream)) => {
                     debug!(log, "(Outgoing) Connection to peer successful, so start bootstrapping"; "incoming" => false, "ip" => msg.address);
-                    match bootstrap(Bootstrap::outgoing(stream, msg.address.clone(), disable_mempool, private_node), local_node_info, &log).await {
+                    match bootstrap(Bootstrap::outgoing(stream, msg.address.clone(), disable_mempool, private_node).with_traffic_recorder(traffic_recorder), local_node_info, &log).await {
                         Ok(bootstrap_output) => {
-                            match Self::create_peer(&system, network_channel.clone(), tokio_executor, bootstrap_output) {
+                            let connection = P2pConnectionInfo::new(&bootstrap_output, false, MetadataMessage::new(disable_mempool, private_node));
+                            match Self::create_peer(&system, network_channel.clone(), tokio_executor, rate_limiter, bootstrap_output) {
                                 Ok(peer) => {
-                                    if let Err(e) = peers.add_outgoing_peer(peer.clone(), msg.address) {
+                                    if let Err(e) = peers.add_outgoing_peer(peer.clone(), msg.address, connection) {
                                         warn!(log, "Failed to add outgoing peer to state - stopping peer actor"; "reason" => format!("{:?}", e));
                                         system.stop(peer);
                                     }
+                                    true
                                 }
                                 Err(e) => {
-                                    warn!(log, "(Outgoing) Connection failed to create peer actor"; "ip" => format!("{}", msg.address.ip()), "reason" => format!("{}", e))
+                                    warn!(log, "(Outgoing) Connection failed to create peer actor"; "ip" => format!("{}", msg.address.ip()), "reason" => format!("{}", e));
+                                    false
                                 }
                             }
                         }
                         Err(err) => {
                             warn!(log, "(Outgoing) Connection to peer failed"; "incoming" => false, "reason" => format!("{}", &err), "ip" => &msg.address);
                             failed_bootstrap_peer(err, msg.address, network_channel);
+                            false
                         }
                     }
                 }
                 Ok(Err(e)) => {
                     info!(log, "(Outgoing) Connection to peer failed"; "ip" => msg.address, "reason" => format!("{:?}", e));
+                    false
                 }
                 Err(_) => {
                     info!(log, "(Outgoing) Connection timed out"; "ip" => msg.address);
+                    false
                 }
-            }
+            };
+
+            // update score/backoff of the point
+            myself.tell(
+                OutgoingConnectionResult {
+                    address: msg.address,
+                    connected,
+                },
+                None,
+            );
         });
     }
 }
@@ -832,9 +1209,16 @@ impl Receive<AcceptPeer> for PeerManager {
     type Msg = PeerManagerMsg;
 
     fn receive(&mut self, ctx: &Context<Self::Msg>, msg: AcceptPeer, _sender: Sender) {
-        if self.is_blacklisted(&msg.address.ip()) {
-            warn!(ctx.system.log(), "Peer is blacklisted - will not accept connection"; "ip" => format!("{}", msg.address.ip()));
-            return;
+        match self.is_blacklisted(&msg.address.ip()) {
+            Ok(false) => (),
+            Ok(true) => {
+                warn!(ctx.system.log(), "Peer is blacklisted - will not accept connection"; "ip" => format!("{}", msg.address.ip()));
+                return;
+            }
+            Err(e) => {
+                warn!(ctx.system.log(), "Failed to check blacklisted peer - will not accept connection"; "ip" => format!("{}", msg.address.ip()), "reason" => format!("{:?}", e));
+                return;
+            }
         }
 
         // TODO: TE-490 - allow here accept randomly more connections
@@ -847,6 +1231,8 @@ impl Receive<AcceptPeer> for PeerManager {
                 let local_node_info = self.local_node_info.clone();
                 let network_channel = self.network_channel.clone();
                 let tokio_executor = self.tokio_executor.clone();
+                let rate_limiter = self.rate_limiter.clone();
+                let traffic_recorder = self.traffic_recorder.clone();
                 let disable_mempool = self.disable_mempool;
                 let private_node = self.private_node;
                 let peers = self.peers.clone();
@@ -854,11 +1240,12 @@ impl Receive<AcceptPeer> for PeerManager {
                 self.tokio_executor.spawn(async move {
                     let log = system.log();
                     debug!(log, "Bootstrapping"; "incoming" => true, "ip" => &msg.address);
-                    match bootstrap(Bootstrap::incoming(msg.stream, msg.address.clone(), disable_mempool, private_node), local_node_info, &log).await {
+                    match bootstrap(Bootstrap::incoming(msg.stream, msg.address.clone(), disable_mempool, private_node).with_traffic_recorder(traffic_recorder), local_node_info, &log).await {
                         Ok(bootstrap_output) => {
-                            match Self::create_peer(&system, network_channel.clone(), tokio_executor, bootstrap_output) {
+                            let connection = P2pConnectionInfo::new(&bootstrap_output, true, MetadataMessage::new(disable_mempool, private_node));
+                            match Self::create_peer(&system, network_channel.clone(), tokio_executor, rate_limiter, bootstrap_output) {
                                 Ok(peer) => {
-                                    if let Err(e) = peers.add_incoming_peer(peer.clone(), msg.address) {
+                                    if let Err(e) = peers.add_incoming_peer(peer.clone(), msg.address, connection) {
                                         warn!(log, "Failed to add incoming peer to state - stopping peer actor"; "reason" => format!("{:?}", e));
                                         system.stop(peer);
                                     }
@@ -1039,13 +1426,85 @@ fn resolve_dns_name_to_peer_address(
 
 /// Holds information about a specific peer.
 #[derive(Clone)]
-struct P2pPeerState {
-    peer_ref: PeerRef,
-    peer_address: SocketAddr,
+pub struct P2pPeerState {
+    pub peer_ref: PeerRef,
+    pub peer_address: SocketAddr,
+    pub connection: Arc<P2pConnectionInfo>,
 }
 
+/// Misbehaviour score of the peer, which decays over time
+#[derive(Clone, Debug)]
+struct MisbehaviourScore {
+    score: f64,
+    last_update: Instant,
+}
+
+impl MisbehaviourScore {
+    fn new(now: Instant) -> Self {
+        Self {
+            score: 0.0,
+            last_update: now,
+        }
+    }
+
+    /// Adds penalty to the decayed score, returns new score
+    fn add(&mut self, penalty: f64, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.last_update);
+        self.score = (self.score - elapsed.as_secs_f64() * MISBEHAVIOUR_SCORE_DECAY_PER_SEC)
+            .max(0.0)
+            + penalty;
+        self.last_update = now;
+        self.score
+    }
+}
+
+/// Information about the connection negotiated with the peer during the bootstrap.
+#[derive(Clone, Debug)]
+pub struct P2pConnectionInfo {
+    pub peer_public_key_hash: CryptoboxPublicKeyHash,
+    pub peer_id_marker: String,
+    /// Indicates that the connection was initiated by the remote peer
+    pub incoming: bool,
+    /// Metadata sent to the peer
+    pub local_metadata: MetadataMessage,
+    /// Metadata received from the peer
+    pub remote_metadata: MetadataMessage,
+    pub network_version: NetworkVersion,
+    pub established_at: SystemTime,
+}
+
+impl P2pConnectionInfo {
+    fn new(
+        bootstrap_output: &BootstrapOutput,
+        incoming: bool,
+        local_metadata: MetadataMessage,
+    ) -> Self {
+        let BootstrapOutput(
+            _,
+            _,
+            peer_public_key_hash,
+            peer_id_marker,
+            remote_metadata,
+            network_version,
+            _,
+        ) = bootstrap_output;
+        Self {
+            peer_public_key_hash: peer_public_key_hash.clone(),
+            peer_id_marker: peer_id_marker.clone(),
+            incoming,
+            local_metadata,
+            remote_metadata: remote_metadata.clone(),
+            network_version: network_version.clone(),
+            established_at: SystemTime::now(),
+        }
+    }
+}
+
+/// Reference to the state of p2p peers shared between [PeerManager] and RPC.
+pub type P2pPeersRef = Arc<P2pPeers>;
+
 /// Represents inner state of PeerManager about p2p peers sharable between threads
-pub(crate) struct P2pPeers {
+pub struct P2pPeers {
     /// Threshold configration for peers
     peers_threshold: Arc<PeerConnectionThreshold>,
 
@@ -1057,10 +1516,14 @@ pub(crate) struct P2pPeers {
 
     /// List of potential peers to connect to
     potential_peers: Arc<RwLock<HashSet<SocketAddr>>>,
+
+    /// Known points with blacklisted IP addresses (persisted),
+    /// if both are needed, lock `potential_peers` first
+    points: Arc<RwLock<P2pPoints>>,
 }
 
 impl P2pPeers {
-    fn new(peers_threshold: Arc<PeerConnectionThreshold>) -> Self {
+    pub fn new(peers_threshold: PeerConnectionThreshold, peer_storage: PeerStorage) -> Self {
         let max_incoming_connection_tickets = {
             if peers_threshold.high == 1 {
                 1
@@ -1075,40 +1538,175 @@ impl P2pPeers {
             potential_peers: Arc::new(RwLock::new(HashSet::new())),
             incoming_connection_tickets: Arc::new(Semaphore::new(max_incoming_connection_tickets)),
             connected_peers: Arc::new(RwLock::new(HashMap::new())),
-            peers_threshold,
+            peers_threshold: Arc::new(peers_threshold),
+            points: Arc::new(RwLock::new(P2pPoints::new(peer_storage))),
         }
     }
 
+    /// Returns all connected peers
+    pub fn connections(&self) -> Result<Vec<P2pPeerState>, PeerManagerError> {
+        Ok(self.connected_peers.read()?.values().cloned().collect())
+    }
+
+    /// Returns connected peer by its peer id (base58 encoded public key hash)
+    pub fn connection(&self, peer_id: &str) -> Result<Option<P2pPeerState>, PeerManagerError> {
+        Ok(self
+            .connected_peers
+            .read()?
+            .values()
+            .find(|peer_state| peer_state.connection.peer_id_marker == peer_id)
+            .cloned())
+    }
+
+    /// Returns addresses waiting for the connection
+    pub fn potential_peers(&self) -> Result<Vec<SocketAddr>, PeerManagerError> {
+        Ok(self.potential_peers.read()?.iter().cloned().collect())
+    }
+
+    /// Returns all known points
+    pub fn points(&self) -> Result<Vec<(SocketAddr, PointInfo)>, PeerManagerError> {
+        Ok(self
+            .points
+            .read()?
+            .points
+            .iter()
+            .map(|(point, info)| (*point, info.clone()))
+            .collect())
+    }
+
+    pub fn point(&self, address: &SocketAddr) -> Result<Option<PointInfo>, PeerManagerError> {
+        Ok(self.points.read()?.points.get(address).cloned())
+    }
+
+    /// Returns point of the peer (base58 encoded public key hash) - point of the connection, if it is connected,
+    /// otherwise the last known point of the peer, so disconnected (e.g. banned) peer can be found too
+    pub fn peer_point(&self, peer_id: &str) -> Result<Option<SocketAddr>, PeerManagerError> {
+        if let Some(peer_state) = self.connection(peer_id)? {
+            return Ok(Some(peer_state.peer_address));
+        }
+        Ok(self.points.read()?.peer_point(peer_id))
+    }
+
+    pub fn is_blacklisted(&self, ip_address: &IpAddr) -> Result<bool, PeerManagerError> {
+        Ok(self
+            .points
+            .read()?
+            .is_blacklisted(ip_address, SystemTime::now()))
+    }
+
+    /// Bans the point (and its IP address) for the ban duration, trusted point is not banned
+    pub fn ban_point(&self, address: SocketAddr, reason: String) -> Result<(), PeerManagerError> {
+        self.points
+            .write()?
+            .blacklist(address, reason, SystemTime::now() + BAN_DURATION)?;
+        if self.is_blacklisted(&address.ip())? {
+            let _ = self.potential_peers.write()?.remove(&address);
+        }
+        Ok(())
+    }
+
+    pub fn unban_point(&self, address: SocketAddr) -> Result<(), PeerManagerError> {
+        self.points
+            .write()?
+            .unban(address, SystemTime::now())
+            .map_err(PeerManagerError::from)
+    }
+
+    /// Marks point as trusted (removes its ban) and adds it to potential peers
+    pub fn trust_point(&self, address: SocketAddr) -> Result<(), PeerManagerError> {
+        let mut potential_peers = self.potential_peers.write()?;
+        self.points.write()?.trust(address, SystemTime::now())?;
+        let _ = potential_peers.insert(address);
+        Ok(())
+    }
+
+    pub fn untrust_point(&self, address: SocketAddr) -> Result<(), PeerManagerError> {
+        self.points
+            .write()?
+            .update(address, |info| info.untrust())
+            .map(|_| ())
+            .map_err(PeerManagerError::from)
+    }
+
+    /// Loads stored points.
+    /// Returns points, which should be connected and count of blacklisted IP addresses.
+    fn load_points(&self, now: SystemTime) -> Result<(Vec<SocketAddr>, usize), PeerManagerError> {
+        let mut points = self.points.write()?;
+        let known_points = points.load(now)?;
+        Ok((known_points, points.blacklisted_ip_count()))
+    }
+
+    fn remove_all_bans(&self) -> Result<(), PeerManagerError> {
+        self.points
+            .write()?
+            .remove_all_bans()
+            .map_err(PeerManagerError::from)
+    }
+
+    fn point_connected(
+        &self,
+        address: SocketAddr,
+        now: SystemTime,
+    ) -> Result<PointInfo, PeerManagerError> {
+        self.points
+            .write()?
+            .connected(address, now)
+            .map_err(PeerManagerError::from)
+    }
+
+    fn point_failed(
+        &self,
+        address: SocketAddr,
+        now: SystemTime,
+    ) -> Result<PointInfo, PeerManagerError> {
+        self.points
+            .write()?
+            .failed(address, now)
+            .map_err(PeerManagerError::from)
+    }
+
     fn add_outgoing_peer(
         &self,
         peer_ref: PeerRef,
         peer_address: SocketAddr,
+        connection: P2pConnectionInfo,
     ) -> Result<(), PeerManagerError> {
+        let peer_id = connection.peer_id_marker.clone();
         // TODO: TE-490 - handle AlreadyConnected
         let _ = self.connected_peers.write()?.insert(
             peer_ref.uri().clone(),
             P2pPeerState {
                 peer_ref,
                 peer_address,
+                connection: Arc::new(connection),
             },
         );
-        Ok(())
+        self.points
+            .write()?
+            .identified(peer_address, peer_id)
+            .map_err(PeerManagerError::from)
     }
 
     fn add_incoming_peer(
         &self,
         peer_ref: PeerRef,
         peer_address: SocketAddr,
+        connection: P2pConnectionInfo,
     ) -> Result<(), PeerManagerError> {
+        let peer_id = connection.peer_id_marker.clone();
         // TODO: TE-490 - handle AlreadyConnected
         let _ = self.connected_peers.write()?.insert(
             peer_ref.uri().clone(),
             P2pPeerState {
                 peer_ref,
                 peer_address,
+                connection: Arc::new(connection),
             },
         );
-        Ok(())
+        self.points
+            .write()?
+            .identified(peer_address, peer_id)
+            .map_err(PeerManagerError::from)
     }
 
     /// Tries to remove peer_actor_uri from state.
@@ -1146,6 +1744,39 @@ impl P2pPeers {
         }
     }
 
+    /// Returns true, if the point advertised by swap is neither connected (by address or identity), nor blacklisted
+    fn is_swap_point_acceptable(
+        &self,
+        point: &SocketAddr,
+        peer_public_key_hash: &CryptoboxPublicKeyHash,
+    ) -> Result<bool, PeerManagerError> {
+        if self.is_blacklisted(&point.ip())? {
+            return Ok(false);
+        }
+        Ok(!self.connected_peers.read()?.values().any(|peer_state| {
+            &peer_state.peer_address == point
+                || &peer_state.connection.peer_public_key_hash == peer_public_key_hash
+        }))
+    }
+
+    /// Returns random peer (other than `different_than`), which can be proposed for swap,
+    /// just outgoing connections are used, because their address is the listening point of the peer
+    fn random_swap_peer(
+        &self,
+        different_than: &PeerRef,
+    ) -> Result<Option<P2pPeerState>, PeerManagerError> {
+        let connected_peers = self.connected_peers.read()?;
+        let candidates = connected_peers
+            .values()
+            .filter(|peer_state| {
+                !peer_state.connection.incoming && &peer_state.peer_ref != different_than
+            })
+            .collect::<Vec<_>>();
+        Ok(candidates
+            .choose(&mut rand::thread_rng())
+            .map(|peer_state| (*peer_state).clone()))
+    }
+
     fn is_max_connections_exceeded(&self) -> Result<bool, PeerManagerError> {
         Ok(self.connected_peers.read()?.len() >= self.peers_threshold.high)
     }
@@ -1164,16 +1795,305 @@ impl P2pPeers {
     }
 }
 
+/// Known p2p points persisted in [PeerStorage] with index of blacklisted IP addresses.
+///
+/// Bans are stored per point, but checked per IP address (incoming connections use random ports),
+/// every IP address is whitelisted, when its own ban expires.
+///
+/// Count of points is limited by `max_points` (peers can advertise any points), over the limit
+/// the least valuable point is evicted (see [P2pPoints::evict]).
+struct P2pPoints {
+    storage: PeerStorage,
+    /// Cache of the stored points
+    points: HashMap<SocketAddr, PointInfo>,
+    /// Blacklisted IP addresses with expiration of the ban
+    ip_blacklist: HashMap<IpAddr, SystemTime>,
+    /// Index of the points by [PointInfo::peer_id]
+    peer_points: HashMap<String, SocketAddr>,
+    max_points: usize,
+}
+
+impl P2pPoints {
+    fn new(storage: PeerStorage) -> Self {
+        Self {
+            storage,
+            points: HashMap::new(),
+            ip_blacklist: HashMap::new(),
+            peer_points: HashMap::new(),
+            max_points: MAX_KNOWN_POINTS,
+        }
+    }
+
+    /// Loads stored points and bans, expired bans are removed from the storage.
+    /// Returns points, which are trusted or were successfully connected before and are not banned.
+    fn load(&mut self, now: SystemTime) -> Result<Vec<SocketAddr>, StorageError> {
+        self.points.clear();
+        self.ip_blacklist.clear();
+        self.peer_points.clear();
+
+        for (point, mut info) in self.storage.iter()? {
+            match info.ban().as_ref().map(|ban| ban.until) {
+                Some(until) if until > now => self.blacklist_ip(point.ip(), until),
+                Some(_) => {
+                    info.unbanned();
+                    self.storage.put(&point, &info)?;
+                }
+                None => (),
+            }
+            self.points.insert(point, info);
+        }
+        // storage could be filled before the limit, or the limit was lowered
+        self.evict(None, now)?;
+        for (point, info) in &self.points {
+            if let Some(peer_id) = info.peer_id() {
+                self.peer_points.insert(peer_id.clone(), *point);
+            }
+        }
+
+        Ok(self
+            .points
+            .iter()
+            .filter(|(_, info)| {
+                !info.is_banned(now) && (info.trusted() || info.last_seen().is_some())
+            })
+            .map(|(point, _)| *point)
+            .collect())
+    }
+
+    /// Removes the least valuable points (from memory and storage), while there are more than `max_points` points.
+    ///
+    /// Trusted and banned points (and `keep`) are never evicted. Points never seen are evicted first,
+    /// then points with lower score, then points with older last activity.
+    fn evict(&mut self, keep: Option<&SocketAddr>, now: SystemTime) -> Result<(), StorageError> {
+        while self.points.len() > self.max_points {
+            let evicted = self
+                .points
+                .iter()
+                .filter(|(point, info)| {
+                    Some(*point) != keep && !info.trusted() && !info.is_banned(now)
+                })
+                .min_by_key(|(_, info)| {
+                    (
+                        info.last_seen().is_some(),
+                        info.score(),
+                        info.last_seen().or_else(|| info.last_failed()),
+                    )
+                })
+                .map(|(point, _)| *point);
+            match evicted {
+                Some(point) => {
+                    if let Some(peer_id) = self
+                        .points
+                        .remove(&point)
+                        .and_then(|info| info.peer_id().clone())
+                    {
+                        self.remove_peer_point(&peer_id, &point);
+                    }
+                    self.storage.delete(&point)?;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn is_blacklisted(&self, ip_address: &IpAddr, now: SystemTime) -> bool {
+        self.ip_blacklist
+            .get(ip_address)
+            .map_or(false, |until| *until > now)
+    }
+
+    fn blacklisted_ip_count(&self) -> usize {
+        self.ip_blacklist.len()
+    }
+
+    fn blacklist(
+        &mut self,
+        address: SocketAddr,
+        reason: String,
+        until: SystemTime,
+    ) -> Result<(), StorageError> {
+        if self
+            .points
+            .get(&address)
+            .map_or(false, |info| info.trusted())
+        {
+            return Ok(());
+        }
+        self.blacklist_ip(address.ip(), until);
+        self.update(address, |info| info.banned(reason, until))
+            .map(|_| ())
+    }
+
+    fn blacklist_ip(&mut self, ip_address: IpAddr, until: SystemTime) {
+        let ban_until = self.ip_blacklist.entry(ip_address).or_insert(until);
+        *ban_until = cmp::max(*ban_until, until);
+    }
+
+    /// Removes ban of the point, IP address stays blacklisted, if it has other banned point
+    fn unban(&mut self, address: SocketAddr, now: SystemTime) -> Result<(), StorageError> {
+        self.update(address, |info| info.unbanned())?;
+        self.refresh_ip_ban(address.ip(), now);
+        Ok(())
+    }
+
+    fn trust(&mut self, address: SocketAddr, now: SystemTime) -> Result<(), StorageError> {
+        self.update(address, |info| info.trust())?;
+        self.refresh_ip_ban(address.ip(), now);
+        Ok(())
+    }
+
+    /// Recalculates ban of the IP address from bans of its points
+    fn refresh_ip_ban(&mut self, ip_address: IpAddr, now: SystemTime) {
+        let until = self
+            .points
+            .iter()
+            .filter(|(point, _)| point.ip() == ip_address)
+            .filter_map(|(_, info)| info.ban().as_ref())
+            .map(|ban| ban.until)
+            .filter(|until| *until > now)
+            .max();
+        match until {
+            Some(until) => {
+                self.ip_blacklist.insert(ip_address, until);
+            }
+            None => {
+                self.ip_blacklist.remove(&ip_address);
+            }
+        }
+    }
+
+    /// Whitelist IP addresses, whose ban expired, and removes expired bans of the points (also from the storage)
+    fn remove_expired_bans(&mut self, now: SystemTime) -> Result<(), StorageError> {
+        self.ip_blacklist.retain(|_, until| *until > now);
+        for (point, info) in self.points.iter_mut() {
+            if info.ban().is_some() && !info.is_banned(now) {
+                info.unbanned();
+                self.storage.put(point, info)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whitelist all IP addresses and removes all stored bans
+    fn remove_all_bans(&mut self) -> Result<(), StorageError> {
+        self.ip_blacklist.clear();
+        for (point, info) in self.points.iter_mut() {
+            if info.ban().is_some() {
+                info.unbanned();
+                self.storage.put(point, info)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn peer_point(&self, peer_id: &str) -> Option<SocketAddr> {
+        self.peer_points.get(peer_id).cloned()
+    }
+
+    /// Peer with `peer_id` was connected from/to the point, the peer is forgotten by its previous point
+    fn identified(&mut self, address: SocketAddr, peer_id: String) -> Result<(), StorageError> {
+        if let Some(previous) = self.peer_point(&peer_id).filter(|point| *point != address) {
+            self.update(previous, |info| info.forget_peer_id())?;
+        }
+        self.update(address, |info| info.identified(peer_id))
+            .map(|_| ())
+    }
+
+    fn remove_peer_point(&mut self, peer_id: &str, address: &SocketAddr) {
+        if self.peer_points.get(peer_id) == Some(address) {
+            self.peer_points.remove(peer_id);
+        }
+    }
+
+    fn connected(
+        &mut self,
+        address: SocketAddr,
+        now: SystemTime,
+    ) -> Result<PointInfo, StorageError> {
+        self.update(address, |info| info.connected(now))
+    }
+
+    fn failed(&mut self, address: SocketAddr, now: SystemTime) -> Result<PointInfo, StorageError> {
+        self.update(address, |info| info.failed(now))
+    }
+
+    fn update<F: FnOnce(&mut PointInfo)>(
+        &mut self,
+        address: SocketAddr,
+        update: F,
+    ) -> Result<PointInfo, StorageError> {
+        let is_new = !self.points.contains_key(&address);
+        let info = self.points.entry(address).or_default();
+        let previous_peer_id = info.peer_id().clone();
+        update(info);
+        self.storage.put(&address, info)?;
+        let info = info.clone();
+        if &previous_peer_id != info.peer_id() {
+            if let Some(previous_peer_id) = previous_peer_id {
+                self.remove_peer_point(&previous_peer_id, &address);
+            }
+            if let Some(peer_id) = info.peer_id() {
+                self.peer_points.insert(peer_id.clone(), address);
+            }
+        }
+        if is_new {
+            self.evict(Some(&address), SystemTime::now())?;
+        }
+        Ok(info)
+    }
+
+    /// Selects at most `count` points to connect to, blacklisted points and points with not elapsed reconnection backoff are skipped.
+    /// Trusted points go first, then points are ordered by score (points with the same score are randomized as a security measurement).
+    fn select_points_to_connect(
+        &self,
+        potential_peers: &HashSet<SocketAddr>,
+        count: usize,
+        now: SystemTime,
+    ) -> Vec<SocketAddr> {
+        let mut points = potential_peers
+            .iter()
+            .filter(|address| !self.is_blacklisted(&address.ip(), now))
+            .filter(|address| {
+                self.points
+                    .get(address)
+                    .and_then(|info| {
+                        info.next_connection_attempt(RECONNECT_BACKOFF_BASE, RECONNECT_BACKOFF_MAX)
+                    })
+                    .map_or(true, |next_connection_attempt| {
+                        next_connection_attempt <= now
+                    })
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        points.shuffle(&mut rand::thread_rng());
+        points.sort_by_key(|address| {
+            self.points
+                .get(address)
+                .map_or((true, cmp::Reverse(0)), |info| {
+                    (!info.trusted(), cmp::Reverse(info.score()))
+                })
+        });
+        points.truncate(count);
+        points
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
+    use std::convert::TryFrom;
+
     use super::*;
 
+    use crypto::hash::HashType;
+
     use crate::state::peer_state::PeerState;
     use crate::state::tests::prerequisites::{
         create_logger, create_test_actor_system, create_test_tokio_runtime, test_peer,
     };
     use networking::p2p::network_channel::NetworkChannel;
     use slog::Level;
+    use storage::tests_common::TmpStorage;
 
     #[test]
     fn test_peer_actor_name() {
@@ -1191,6 +2111,37 @@ pub mod tests {
         ));
     }
 
+    #[test]
+    fn test_misbehaviour_score() {
+        let now = Instant::now();
+        let mut score = MisbehaviourScore::new(now);
+        let unsolicited = PeerPenalty::Unsolicited {
+            message_type: "block_header",
+        };
+        let flooding = PeerPenalty::Flooding {
+            message_type: "operation",
+            dropped: 10,
+        };
+
+        assert_eq!(10.0, score.add(unsolicited.score(), now));
+        assert_eq!(60.0, score.add(flooding.score(), now));
+
+        // score decays over time, but never below zero
+        assert_eq!(
+            40.0,
+            score.add(unsolicited.score(), now + Duration::from_secs(30))
+        );
+        assert_eq!(
+            10.0,
+            score.add(unsolicited.score(), now + Duration::from_secs(1000))
+        );
+
+        // flooding peer reaches the threshold
+        let later = now + Duration::from_secs(1000);
+        assert!(score.add(flooding.score(), later) < MISBEHAVIOUR_SCORE_THRESHOLD);
+        assert!(score.add(flooding.score(), later) >= MISBEHAVIOUR_SCORE_THRESHOLD);
+    }
+
     #[test]
     fn test_p2p_peers_max_connection_management() {
         // prerequisities
@@ -1204,6 +2155,8 @@ pub mod tests {
         let threshold_high = 3;
         let incoming_threshold_high = 2;
 
+        let tmp_storage = TmpStorage::create_to_out_dir("__peer_manager_max_connections")
+            .expect("Failed to create storage");
         let p2p_peers = P2pPeers {
             potential_peers: Arc::new(RwLock::new(HashSet::new())),
             incoming_connection_tickets: Arc::new(Semaphore::new(incoming_threshold_high)),
@@ -1211,6 +2164,9 @@ pub mod tests {
             peers_threshold: Arc::new(
                 PeerConnectionThreshold::try_new(0, threshold_high, None).expect("Incorrect range"),
             ),
+            points: Arc::new(RwLock::new(P2pPoints::new(PeerStorage::new(
+                tmp_storage.storage(),
+            )))),
         };
 
         // test
@@ -1240,7 +2196,11 @@ pub mod tests {
             let PeerState { peer_id, .. } =
                 test_peer(&actor_system, network_channel.clone(), &tokio_runtime, 7777);
             p2p_peers
-                .add_incoming_peer(peer_id.peer_ref.clone(), peer_id.peer_address)
+                .add_incoming_peer(
+                    peer_id.peer_ref.clone(),
+                    peer_id.peer_address,
+                    test_connection(&peer_id, true),
+                )
                 .unwrap();
 
             // we have more left
@@ -1260,7 +2220,11 @@ pub mod tests {
             let PeerState { peer_id, .. } =
                 test_peer(&actor_system, network_channel.clone(), &tokio_runtime, 7778);
             p2p_peers
-                .add_incoming_peer(peer_id.peer_ref.clone(), peer_id.peer_address)
+                .add_incoming_peer(
+                    peer_id.peer_ref.clone(),
+                    peer_id.peer_address,
+                    test_connection(&peer_id, true),
+                )
                 .unwrap();
 
             // we have more left
@@ -1275,12 +2239,61 @@ pub mod tests {
         let PeerState { peer_id, .. } =
             test_peer(&actor_system, network_channel.clone(), &tokio_runtime, 7779);
         p2p_peers
-            .add_outgoing_peer(peer_id.peer_ref.clone(), peer_id.peer_address)
+            .add_outgoing_peer(
+                peer_id.peer_ref.clone(),
+                peer_id.peer_address,
+                test_connection(&peer_id, false),
+            )
             .unwrap();
+        assert_eq!(
+            peer_id.peer_address,
+            p2p_peers
+                .connection(&peer_id.peer_id_marker)
+                .unwrap()
+                .expect("Connected peer not found")
+                .peer_address
+        );
 
         // exceeded yet
         assert!(p2p_peers.is_max_connections_exceeded().unwrap());
 
+        // just outgoing peer can be proposed for swap
+        let incoming_peer_ref = p2p_peers
+            .connections()
+            .unwrap()
+            .into_iter()
+            .find(|peer_state| peer_state.connection.incoming)
+            .expect("Incoming peer not found")
+            .peer_ref;
+        assert_eq!(
+            Some(peer_id.peer_ref.clone()),
+            p2p_peers
+                .random_swap_peer(&incoming_peer_ref)
+                .unwrap()
+                .map(|peer_state| peer_state.peer_ref)
+        );
+        assert!(p2p_peers
+            .random_swap_peer(&peer_id.peer_ref)
+            .unwrap()
+            .is_none());
+
+        // connected point cannot be swapped
+        let other_public_key_hash =
+            CryptoboxPublicKeyHash::try_from(vec![1; HashType::CryptoboxPublicKeyHash.size()])
+                .unwrap();
+        assert!(!p2p_peers
+            .is_swap_point_acceptable(&peer_id.peer_address, &other_public_key_hash)
+            .unwrap());
+        assert!(!p2p_peers
+            .is_swap_point_acceptable(
+                &"127.0.0.1:1111".parse().unwrap(),
+                &peer_id.peer_public_key_hash
+            )
+            .unwrap());
+        assert!(p2p_peers
+            .is_swap_point_acceptable(&"127.0.0.1:1111".parse().unwrap(), &other_public_key_hash)
+            .unwrap());
+
         // check permits for incoming - all available
         assert_eq!(2, p2p_peers.incoming_connection_tickets.available_permits());
         // but aquire failed - because total exceeded
@@ -1303,4 +2316,249 @@ pub mod tests {
             .unwrap()
             .is_some());
     }
+
+    #[test]
+    fn test_p2p_points_selection_and_bans() -> Result<(), failure::Error> {
+        let tmp_storage = TmpStorage::create_to_out_dir("__peer_manager_p2p_points")?;
+        let now = SystemTime::now();
+        let good: SocketAddr = "127.0.0.1:9732".parse()?;
+        let bad: SocketAddr = "127.0.0.2:9732".parse()?;
+        let unknown: SocketAddr = "127.0.0.3:9732".parse()?;
+        let banned: SocketAddr = "127.0.0.4:9732".parse()?;
+        let banned_other_port: SocketAddr = "127.0.0.4:9733".parse()?;
+        let potential_peers: HashSet<SocketAddr> =
+            HashSet::from_iter(vec![good, bad, unknown, banned, banned_other_port]);
+
+        let mut points = P2pPoints::new(PeerStorage::new(tmp_storage.storage()));
+        points.connected(good, now)?;
+        points.failed(bad, now)?;
+        points.blacklist(banned, "test".to_string(), now + BAN_DURATION)?;
+
+        // banned ip and point with backoff are skipped
+        assert!(points.is_blacklisted(&banned_other_port.ip(), now));
+        assert_eq!(
+            vec![good, unknown],
+            points.select_points_to_connect(&potential_peers, 10, now)
+        );
+        assert_eq!(
+            vec![good],
+            points.select_points_to_connect(&potential_peers, 1, now)
+        );
+        // after backoff, bad point is the last one (lowest score)
+        assert_eq!(
+            vec![good, unknown, bad],
+            points.select_points_to_connect(&potential_peers, 10, now + RECONNECT_BACKOFF_BASE)
+        );
+
+        // restart - points and bans are loaded from storage
+        let mut points = P2pPoints::new(PeerStorage::new(tmp_storage.storage()));
+        assert_eq!(vec![good], points.load(now)?);
+        assert!(points.is_blacklisted(&banned.ip(), now));
+        assert!(!points.is_blacklisted(&bad.ip(), now));
+
+        // bans expire per ip address
+        points.blacklist(bad, "test".to_string(), now + Duration::from_secs(1))?;
+        assert_eq!(2, points.blacklisted_ip_count());
+        points.remove_expired_bans(now + Duration::from_secs(2))?;
+        assert!(!points.is_blacklisted(&bad.ip(), now + Duration::from_secs(2)));
+        assert!(points.is_blacklisted(&banned.ip(), now + Duration::from_secs(2)));
+        assert_eq!(1, points.blacklisted_ip_count());
+        // expired ban is removed also from the storage
+        assert!(PeerStorage::new(tmp_storage.storage())
+            .get(&bad)?
+            .unwrap()
+            .ban()
+            .is_none());
+        assert!(PeerStorage::new(tmp_storage.storage())
+            .get(&banned)?
+            .unwrap()
+            .ban()
+            .is_some());
+
+        // whitelist all removes also stored bans
+        points.remove_all_bans()?;
+        let mut points = P2pPoints::new(PeerStorage::new(tmp_storage.storage()));
+        points.load(now)?;
+        assert_eq!(0, points.blacklisted_ip_count());
+
+        // ip address stays blacklisted, until all its points are unbanned
+        points.blacklist(banned, "test".to_string(), now + BAN_DURATION)?;
+        points.blacklist(banned_other_port, "test".to_string(), now + BAN_DURATION)?;
+        points.unban(banned, now)?;
+        assert!(points.is_blacklisted(&banned.ip(), now));
+        points.unban(banned_other_port, now)?;
+        assert!(!points.is_blacklisted(&banned.ip(), now));
+
+        // trusted point cannot be banned and goes first
+        points.trust(bad, now)?;
+        points.blacklist(bad, "test".to_string(), now + BAN_DURATION)?;
+        assert!(!points.is_blacklisted(&bad.ip(), now));
+        assert_eq!(
+            vec![bad, good],
+            points.select_points_to_connect(&potential_peers, 2, now)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_p2p_points_eviction() -> Result<(), failure::Error> {
+        let tmp_storage = TmpStorage::create_to_out_dir("__peer_manager_p2p_points_eviction")?;
+        let peer_storage = PeerStorage::new(tmp_storage.storage());
+        let now = SystemTime::now();
+        let seen: SocketAddr = "127.0.0.1:9732".parse()?;
+        let trusted: SocketAddr = "127.0.0.2:9732".parse()?;
+        let banned: SocketAddr = "127.0.0.3:9732".parse()?;
+        let failed: SocketAddr = "127.0.0.4:9732".parse()?;
+
+        let mut points = P2pPoints::new(peer_storage.clone());
+        points.max_points = 5;
+        points.connected(seen, now)?;
+        points.trust(trusted, now)?;
+        points.blacklist(banned, "test".to_string(), now + BAN_DURATION)?;
+        points.failed(failed, now)?;
+
+        // advertised junk points never seen are evicted, with the lowest score first
+        for port in 0..100 {
+            points.update(SocketAddr::new("10.0.0.1".parse()?, port), |_| ())?;
+        }
+        assert_eq!(5, points.points.len());
+        assert_eq!(5, peer_storage.iter()?.len());
+        assert!(!points.points.contains_key(&failed));
+        for point in &[seen, trusted, banned] {
+            assert!(points.points.contains_key(point));
+            assert!(peer_storage.get(point)?.is_some());
+        }
+        // the newest point is kept
+        assert!(points
+            .points
+            .contains_key(&SocketAddr::new("10.0.0.1".parse()?, 99)));
+
+        // lowered limit is applied on load, seen point is evicted just without never seen points
+        let mut points = P2pPoints::new(peer_storage.clone());
+        points.max_points = 3;
+        assert_eq!(
+            HashSet::<SocketAddr>::from_iter(vec![seen, trusted]),
+            points.load(now)?.into_iter().collect()
+        );
+        assert_eq!(3, points.points.len());
+        assert_eq!(3, peer_storage.iter()?.len());
+
+        let mut points = P2pPoints::new(peer_storage.clone());
+        points.max_points = 2;
+        points.load(now)?;
+        assert_eq!(
+            HashSet::<SocketAddr>::from_iter(vec![trusted, banned]),
+            points.points.keys().cloned().collect()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_p2p_peers_ban_unban_by_peer_id() -> Result<(), failure::Error> {
+        let log = create_logger(Level::Debug);
+        let tokio_runtime = create_test_tokio_runtime();
+        let actor_system = create_test_actor_system(log.clone());
+        let network_channel =
+            NetworkChannel::actor(&actor_system).expect("Failed to create network channel");
+        let tmp_storage = TmpStorage::create_to_out_dir("__peer_manager_ban_by_peer_id")?;
+        let threshold = || PeerConnectionThreshold::try_new(0, 10, None).expect("Incorrect range");
+        let p2p_peers = P2pPeers::new(threshold(), PeerStorage::new(tmp_storage.storage()));
+
+        let PeerState {
+            peer_id: outgoing, ..
+        } = test_peer(&actor_system, network_channel.clone(), &tokio_runtime, 7781);
+        let PeerState {
+            peer_id: incoming, ..
+        } = test_peer(&actor_system, network_channel, &tokio_runtime, 7782);
+        p2p_peers.add_outgoing_peer(
+            outgoing.peer_ref.clone(),
+            outgoing.peer_address,
+            test_connection(&outgoing, false),
+        )?;
+        p2p_peers.add_incoming_peer(
+            incoming.peer_ref.clone(),
+            incoming.peer_address,
+            test_connection(&incoming, true),
+        )?;
+
+        // connected peers are listed with their points
+        assert_eq!(
+            HashSet::<String>::from_iter(vec![
+                outgoing.peer_id_marker.clone(),
+                incoming.peer_id_marker.clone()
+            ]),
+            p2p_peers
+                .connections()?
+                .into_iter()
+                .map(|peer_state| peer_state.connection.peer_id_marker.clone())
+                .collect()
+        );
+        let points = p2p_peers.points()?.into_iter().collect::<HashMap<_, _>>();
+        assert_eq!(2, points.len());
+        assert_eq!(
+            &Some(outgoing.peer_id_marker.clone()),
+            points[&outgoing.peer_address].peer_id()
+        );
+        assert_eq!(
+            &Some(incoming.peer_id_marker.clone()),
+            points[&incoming.peer_address].peer_id()
+        );
+
+        // ban by peer id disconnects the peer
+        let point = p2p_peers
+            .peer_point(&outgoing.peer_id_marker)?
+            .expect("Connected peer not resolved");
+        assert_eq!(outgoing.peer_address, point);
+        p2p_peers.ban_point(point, "test".to_string())?;
+        assert!(p2p_peers.try_remove_peer_actor(outgoing.peer_ref.uri())?);
+        assert!(p2p_peers.connection(&outgoing.peer_id_marker)?.is_none());
+
+        // disconnected peer is still resolved, so it can be unbanned by its peer id
+        let point = p2p_peers
+            .peer_point(&outgoing.peer_id_marker)?
+            .expect("Disconnected peer not resolved");
+        assert_eq!(outgoing.peer_address, point);
+        assert!(p2p_peers.is_blacklisted(&point.ip())?);
+        p2p_peers.unban_point(point)?;
+        assert!(!p2p_peers.is_blacklisted(&point.ip())?);
+        assert!(p2p_peers.point(&point)?.unwrap().ban().is_none());
+        assert!(p2p_peers.peer_point("unknown")?.is_none());
+
+        // peer ids are loaded after restart
+        let restarted = P2pPeers::new(threshold(), PeerStorage::new(tmp_storage.storage()));
+        restarted.load_points(SystemTime::now())?;
+        assert_eq!(
+            Some(incoming.peer_address),
+            restarted.peer_point(&incoming.peer_id_marker)?
+        );
+
+        // peer identified on other point is resolved to the new one
+        let other: SocketAddr = "127.0.0.2:9732".parse()?;
+        restarted
+            .points
+            .write()
+            .unwrap()
+            .identified(other, incoming.peer_id_marker.clone())?;
+        assert_eq!(Some(other), restarted.peer_point(&incoming.peer_id_marker)?);
+        assert!(restarted
+            .point(&incoming.peer_address)?
+            .unwrap()
+            .peer_id()
+            .is_none());
+
+        Ok(())
+    }
+
+    fn test_connection(peer_id: &PeerId, incoming: bool) -> P2pConnectionInfo {
+        P2pConnectionInfo {
+            peer_public_key_hash: peer_id.peer_public_key_hash.clone(),
+            peer_id_marker: peer_id.peer_id_marker.clone(),
+            incoming,
+            local_metadata: MetadataMessage::new(false, false),
+            remote_metadata: MetadataMessage::new(false, false),
+            network_version: NetworkVersion::new("".to_owned(), 0, 0),
+            established_at: SystemTime::now(),
+        }
+    }
 }
@@ -14,8 +14,9 @@ use storage::block_meta_storage::Meta;
 use storage::chain_meta_storage::ChainMetaStorageReader;
 use storage::PersistentStorage;
 use storage::{
-    BlockHeaderWithHash, BlockMetaStorage, BlockMetaStorageReader, BlockStorage,
-    BlockStorageReader, ChainMetaStorage, OperationsMetaStorage, OperationsStorage, StorageError,
+    store_block_header, BlockHeaderWithHash, BlockMetaStorage, BlockMetaStorageReader,
+    BlockStorage, BlockStorageReader, ChainMetaStorage, OperationsMetaStorage, OperationsStorage,
+    StorageError,
 };
 use tezos_messages::p2p::encoding::current_branch::CurrentBranchMessage;
 use tezos_messages::p2p::encoding::prelude::{CurrentHeadMessage, OperationsForBlocksMessage};
@@ -62,6 +63,8 @@ pub enum BlockAcceptanceResult {
     AcceptBlock,
     IgnoreBlock,
     UnknownBranch,
+    /// Block could not be validated, because protocol is not known (sources can be downloaded from peers)
+    UnknownProtocol(ProtocolHash),
     MutlipassValidationError(ProtocolServiceError),
 }
 
@@ -198,11 +201,15 @@ impl BlockchainState {
                 // lets check strict multipass validation
                 match validation::check_multipass_validation(
                     head.chain_id(),
-                    protocol_hash,
+                    protocol_hash.clone(),
                     &validated_header,
                     predecessor_header,
                     api,
                 ) {
+                    Some(error) if error.is_unavailable_protocol() => {
+                        // protocol runner does not know the protocol, so it is not peer's fault
+                        Ok(BlockAcceptanceResult::UnknownProtocol(protocol_hash))
+                    }
                     Some(error) => Ok(BlockAcceptanceResult::MutlipassValidationError(error)),
                     None => Ok(BlockAcceptanceResult::AcceptBlock),
                 }
@@ -487,16 +494,15 @@ impl BlockchainState {
         received_block: &BlockHeaderWithHash,
         log: &Logger,
     ) -> Result<bool, StorageError> {
-        // store block
-        let is_new_block = self.block_storage.put_block_header(received_block)?;
-
-        // update block metadata
-        let block_metadata =
-            self.block_meta_storage
-                .put_block_header(received_block, &self.chain_id, &log)?;
-
-        // update operations metadata for block
-        let (are_operations_complete, _) = self.process_block_header_operations(received_block)?;
+        // store block with block and operations metadata at once
+        let (is_new_block, block_metadata, (are_operations_complete, _)) = store_block_header(
+            &self.block_storage,
+            &self.block_meta_storage,
+            &self.operations_meta_storage,
+            received_block,
+            &self.chain_id,
+            &log,
+        )?;
 
         // ping branch bootstrapper with received block and actual state
         if let Some(peer_branch_bootstrapper) = &peer.peer_branch_bootstrapper {
@@ -530,61 +536,19 @@ impl BlockchainState {
         block_header: &BlockHeaderWithHash,
         log: &Logger,
     ) -> Result<(Meta, bool, bool), StorageError> {
-        // store block
-        let is_new_block = self.block_storage.put_block_header(block_header)?;
-
-        // update block metadata
-        let metadata = self
-            .block_meta_storage
-            .put_block_header(block_header, chain_id, &log)?;
-
-        // update operations metadata
-        let are_operations_complete =
-            self.process_injected_block_header_operations(block_header)?;
+        // store block with block and operations metadata at once
+        let (is_new_block, metadata, (are_operations_complete, _)) = store_block_header(
+            &self.block_storage,
+            &self.block_meta_storage,
+            &self.operations_meta_storage,
+            block_header,
+            chain_id,
+            &log,
+        )?;
 
         Ok((metadata, is_new_block, are_operations_complete))
     }
 
-    /// Process block header. This will create record in meta storage with
-    /// unseen operations for the block header.
-    ///
-    /// If block header is not already present in storage, return `true`.
-    ///
-    /// Returns tuple:
-    ///     (
-    ///         are_operations_complete,
-    ///         missing_validation_passes
-    ///     )
-    fn process_block_header_operations(
-        &mut self,
-        block_header: &BlockHeaderWithHash,
-    ) -> Result<(bool, Option<HashSet<u8>>), StorageError> {
-        match self.operations_meta_storage.get(&block_header.hash)? {
-            Some(meta) => Ok((meta.is_complete(), meta.get_missing_validation_passes())),
-            None => self
-                .operations_meta_storage
-                .put_block_header(block_header, self.chain_id.as_ref().clone()),
-        }
-    }
-
-    /// Process injected block header. This will create record in meta storage.
-    /// As the the header is injected via RPC, the operations are as well, so we
-    /// won't mark its operations as missing
-    ///
-    /// Returns true, if validation_passes are completed (can happen, when validation_pass = 0)
-    fn process_injected_block_header_operations(
-        &mut self,
-        block_header: &BlockHeaderWithHash,
-    ) -> Result<bool, StorageError> {
-        match self.operations_meta_storage.get(&block_header.hash)? {
-            Some(meta) => Ok(meta.is_complete()),
-            None => self
-                .operations_meta_storage
-                .put_block_header(block_header, self.chain_id.as_ref().clone())
-                .map(|(is_complete, _)| is_complete),
-        }
-    }
-
     /// Processes comming operations from peers
     ///
     /// Returns true, if new block is successfully downloaded by this call
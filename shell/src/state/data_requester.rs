@@ -25,7 +25,7 @@ use tezos_messages::p2p::encoding::prelude::{
 
 use crate::chain_feeder::{ApplyBlock, ChainFeederRef};
 use crate::peer_branch_bootstrapper::PeerBranchBootstrapperRef;
-use crate::state::peer_state::{DataQueues, MissingOperations, PeerState};
+use crate::state::peer_state::{DataQueues, MissingOperations, PeerState, RequestedData};
 use crate::state::{BlockApplyBatch, StateError};
 use crate::utils::{AtomicTryLock, AtomicTryLockGuard, CondvarResult};
 use crate::validation;
@@ -112,6 +112,11 @@ impl DataRequester {
         let _ = peer_queued_block_headers.extend(blocks_to_download.clone());
         // release lock
         drop(peer_queued_block_headers);
+        peer_queues.requested(
+            blocks_to_download
+                .iter()
+                .map(|b| RequestedData::BlockHeader(b.as_ref().clone())),
+        );
 
         // send p2p msg - now we can fire msg to peer
         if limits::GET_BLOCK_HEADERS_MAX_LENGTH > 0 {
@@ -225,6 +230,11 @@ impl DataRequester {
         let _ = peer_queued_block_headers.extend(blocks_to_download.clone());
         // release lock
         drop(peer_queued_block_headers);
+        peer_queues.requested(blocks_to_download.iter().flat_map(|(block, missing_ops)| {
+            missing_ops
+                .iter()
+                .map(move |vp| RequestedData::BlockOperations(block.as_ref().clone(), *vp))
+        }));
 
         // send p2p msg - now we can fire msg to peer
         if limits::GET_OPERATIONS_FOR_BLOCKS_MAX_LENGTH > 0 {
@@ -402,6 +402,7 @@ pub mod tests {
         use crypto::hash::CryptoboxPublicKeyHash;
         use networking::p2p::network_channel::NetworkChannelRef;
         use networking::p2p::peer::{BootstrapOutput, Peer};
+        use networking::p2p::rate_limit::RateLimiter;
         use networking::PeerId;
         use tezos_identity::Identity;
         use tezos_messages::p2p::encoding::prelude::{MetadataMessage, NetworkVersion};
@@ -430,6 +431,7 @@ pub mod tests {
                 sys,
                 network_channel,
                 tokio_runtime.handle().clone(),
+                Arc::new(RateLimiter::unlimited()),
                 BootstrapOutput(
                     Arc::new(TokioMutex::new(None)),
                     Arc::new(TokioMutex::new(None)),
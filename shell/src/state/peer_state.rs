@@ -2,13 +2,13 @@
 // SPDX-License-Identifier: MIT
 
 use std::cmp;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, Instant, SystemTime};
 
 use riker::actors::*;
 
-use crypto::hash::{BlockHash, OperationHash};
+use crypto::hash::{BlockHash, OperationHash, ProtocolHash};
 use networking::p2p::peer::SendMessage;
 use networking::PeerId;
 use storage::mempool_storage::MempoolOperationType;
@@ -27,6 +27,10 @@ use crate::state::StateError;
 const MEMPOOL_OPERATIONS_BATCH_SIZE: usize = 20;
 /// Mempool operation time to live
 const MEMPOOL_OPERATION_TTL: Duration = Duration::from_secs(60);
+/// How long is the response to our request considered just late (not unsolicited)
+const LATE_RESPONSE_GRACE_PERIOD: Duration = Duration::from_secs(180);
+/// Limit of remembered recent requests per peer
+const RECENT_REQUESTS_MAX_COUNT: usize = 4_096;
 
 /// Holds information about a specific peer.
 pub struct PeerState {
@@ -282,6 +286,8 @@ impl PeerState {
                     .into_iter()
                     .map(|(op_hash, _)| op_hash)
                     .collect();
+                peer.queues
+                    .requested(ops_to_get.iter().cloned().map(RequestedData::Operation));
 
                 peer.mempool_operations_request_last = Instant::now();
 
@@ -366,6 +372,9 @@ pub struct DataQueues {
     pub(crate) block_operations_request_last: Arc<RwLock<Instant>>,
     /// Last time we received block operations from the peer
     pub(crate) block_operations_response_last: Arc<RwLock<Instant>>,
+
+    /// Data requested from the peer recently (for all kinds of requests)
+    recent_requests: Mutex<RecentRequests>,
 }
 
 impl DataQueues {
@@ -378,9 +387,26 @@ impl DataQueues {
             queued_block_operations: Arc::new(Mutex::new(HashMap::default())),
             block_operations_request_last: Arc::new(RwLock::new(Instant::now())),
             block_operations_response_last: Arc::new(RwLock::new(Instant::now())),
+            recent_requests: Mutex::new(RecentRequests::default()),
+        }
+    }
+
+    /// Remembers data requested from the peer
+    pub fn requested<I: IntoIterator<Item = RequestedData>>(&self, requested: I) {
+        if let Ok(mut recent_requests) = self.recent_requests.lock() {
+            let now = Instant::now();
+            requested
+                .into_iter()
+                .for_each(|data| recent_requests.insert(data, now));
         }
     }
 
+    /// Returns true, if the data was requested from the peer recently,
+    /// so response with the data, which is not queued anymore, is just late
+    pub fn was_requested(&self, data: &RequestedData) -> Result<bool, StateError> {
+        Ok(self.recent_requests.lock()?.contains(data, Instant::now()))
+    }
+
     pub fn get_already_queued_block_headers_and_max_capacity(
         &self,
     ) -> Result<(HashSet<Arc<BlockHash>>, usize), StateError> {
@@ -420,6 +446,59 @@ impl DataQueues {
     }
 }
 
+/// Data requested from the peer
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RequestedData {
+    BlockHeader(BlockHash),
+    BlockOperations(BlockHash, i8),
+    Operation(OperationHash),
+    Protocol(ProtocolHash),
+}
+
+/// Requests sent to the peer during the last [LATE_RESPONSE_GRACE_PERIOD] (at most [RECENT_REQUESTS_MAX_COUNT]).
+///
+/// Requested data are removed from the queues, when received, when requested again from other peer
+/// or when the peer is too slow, so the response can arrive after that and it is not unsolicited.
+#[derive(Default)]
+struct RecentRequests {
+    /// Count of requests of the data in `order`
+    requests: HashMap<RequestedData, usize>,
+    /// Requests ordered by request time, the same data can be here more times
+    order: VecDeque<(Instant, RequestedData)>,
+}
+
+impl RecentRequests {
+    fn insert(&mut self, data: RequestedData, now: Instant) {
+        *self.requests.entry(data.clone()).or_insert(0) += 1;
+        self.order.push_back((now, data));
+        self.expire(now);
+    }
+
+    fn contains(&mut self, data: &RequestedData, now: Instant) -> bool {
+        self.expire(now);
+        self.requests.contains_key(data)
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((requested, _)) = self.order.front() {
+            if self.order.len() <= RECENT_REQUESTS_MAX_COUNT
+                && now.saturating_duration_since(*requested) <= LATE_RESPONSE_GRACE_PERIOD
+            {
+                break;
+            }
+            if let Some((_, data)) = self.order.pop_front() {
+                // data requested again later stays
+                if let Some(count) = self.requests.get_mut(&data) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.requests.remove(&data);
+                    }
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DataQueuesLimits {
     /// Limit to how many blocks to request from peer
@@ -442,3 +521,50 @@ impl UpdateIsBootstrapped for PeerState {
 pub fn tell_peer(msg: Arc<PeerMessageResponse>, peer: &PeerState) {
     peer.peer_id.peer_ref.tell(SendMessage::new(msg), None);
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    #[test]
+    fn test_recent_requests_expire() -> Result<(), failure::Error> {
+        let block = RequestedData::BlockHeader(
+            "BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?,
+        );
+        let operation = RequestedData::Operation(
+            "onvN8U6QJ6DGJKVYkHXYRtFm3tgBJScj9P5bbPjSZUuFaGzwFuJ".try_into()?,
+        );
+        let now = Instant::now();
+        let mut recent_requests = RecentRequests::default();
+
+        recent_requests.insert(block.clone(), now);
+        recent_requests.insert(operation.clone(), now + Duration::from_secs(60));
+        assert!(recent_requests.contains(&block, now + LATE_RESPONSE_GRACE_PERIOD));
+        assert!(!recent_requests.contains(
+            &block,
+            now + LATE_RESPONSE_GRACE_PERIOD + Duration::from_secs(1)
+        ));
+        assert!(recent_requests.contains(
+            &operation,
+            now + LATE_RESPONSE_GRACE_PERIOD + Duration::from_secs(1)
+        ));
+
+        // the same data requested again is kept until its last request expires
+        recent_requests.insert(operation.clone(), now + LATE_RESPONSE_GRACE_PERIOD);
+        assert!(recent_requests.contains(
+            &operation,
+            now + LATE_RESPONSE_GRACE_PERIOD + Duration::from_secs(61)
+        ));
+
+        // count of requests is limited
+        for _ in 0..RECENT_REQUESTS_MAX_COUNT {
+            recent_requests.insert(block.clone(), now + LATE_RESPONSE_GRACE_PERIOD);
+        }
+        assert!(!recent_requests.contains(&operation, now + LATE_RESPONSE_GRACE_PERIOD));
+        assert!(recent_requests.contains(&block, now + LATE_RESPONSE_GRACE_PERIOD));
+
+        Ok(())
+    }
+}
@@ -5,3 +5,4 @@
 
 pub mod apply_block_stats;
 pub mod memory;
+pub mod network_stats;
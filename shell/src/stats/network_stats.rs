@@ -0,0 +1,81 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use getset::CopyGetters;
+use riker::actors::ActorUri;
+
+/// Shareable type for network stats
+pub type NetworkStatsRef = Arc<RwLock<NetworkStats>>;
+
+/// Inits empty network stats
+pub fn init_empty_network_stats() -> NetworkStatsRef {
+    Arc::new(RwLock::new(NetworkStats::default()))
+}
+
+/// Transfer statistics of the connected peer (only incoming bytes are counted for now)
+#[derive(CopyGetters, Clone, Debug, Default)]
+pub struct PeerTransferStats {
+    /// Total bytes received from the peer
+    #[get_copy = "pub"]
+    total_recv: u64,
+    /// Average inflow since the peer was connected (bytes/s)
+    #[get_copy = "pub"]
+    avg_inflow: f32,
+    /// Inflow since the last update (bytes/s)
+    #[get_copy = "pub"]
+    current_inflow: f32,
+}
+
+impl PeerTransferStats {
+    pub fn new(total_recv: u64, avg_inflow: f32, current_inflow: f32) -> Self {
+        Self {
+            total_recv,
+            avg_inflow,
+            current_inflow,
+        }
+    }
+}
+
+/// Transfer statistics of all peers, periodically updated by the monitoring
+#[derive(Default)]
+pub struct NetworkStats {
+    /// Stats of connected peers by peer actor
+    peers: HashMap<ActorUri, PeerTransferStats>,
+    /// Bytes received from already disconnected peers
+    disconnected_total_recv: u64,
+}
+
+impl NetworkStats {
+    pub fn update_peer(&mut self, peer: ActorUri, stats: PeerTransferStats) {
+        self.peers.insert(peer, stats);
+    }
+
+    /// Removes disconnected peer, its received bytes are kept in the total
+    pub fn remove_peer(&mut self, peer: &ActorUri) {
+        if let Some(stats) = self.peers.remove(peer) {
+            self.disconnected_total_recv += stats.total_recv;
+        }
+    }
+
+    pub fn peer(&self, peer: &ActorUri) -> Option<&PeerTransferStats> {
+        self.peers.get(peer)
+    }
+
+    /// Total bytes received since the node started
+    pub fn total_recv(&self) -> u64 {
+        self.disconnected_total_recv
+            + self
+                .peers
+                .values()
+                .map(|stats| stats.total_recv)
+                .sum::<u64>()
+    }
+
+    /// Current inflow of all connected peers (bytes/s)
+    pub fn current_inflow(&self) -> f32 {
+        self.peers.values().map(|stats| stats.current_inflow).sum()
+    }
+}
@@ -19,6 +19,11 @@ use lazy_static::lazy_static;
 use serial_test::serial;
 
 use crypto::hash::OperationHash;
+use networking::p2p::capture::{
+    CaptureEvent, CaptureReader, CaptureRecord, Direction, PeerTrafficRecorder, TrafficRecorder,
+    DEFAULT_CAPTURE_FILE_MAX_SIZE,
+};
+use networking::p2p::replay::{ReplayDriver, ReplayOptions};
 use networking::ShellCompatibilityVersion;
 use shell::peer_manager::P2p;
 use shell::PeerConnectionThreshold;
@@ -29,9 +34,11 @@ use storage::tests_common::TmpStorage;
 use storage::{BlockMetaStorage, BlockMetaStorageReader};
 use tezos_api::environment::{TezosEnvironmentConfiguration, TEZOS_ENV};
 use tezos_identity::Identity;
-use tezos_messages::p2p::binary_message::MessageHash;
+use tezos_messages::p2p::binary_message::{BinaryMessage, MessageHash};
 use tezos_messages::p2p::encoding::current_head::CurrentHeadMessage;
-use tezos_messages::p2p::encoding::prelude::Mempool;
+use tezos_messages::p2p::encoding::prelude::{
+    GetCurrentHeadMessage, Mempool, MetadataMessage, PeerMessage, PeerMessageResponse,
+};
 
 pub mod common;
 
@@ -48,6 +55,9 @@ lazy_static! {
             private_node: false,
             bootstrap_peers: vec![],
             peer_threshold: PeerConnectionThreshold::try_new(0, 10, Some(0)).expect("Invalid range"),
+            rate_limit: Default::default(),
+            capture_file: None,
+            capture_file_max_size: DEFAULT_CAPTURE_FILE_MAX_SIZE,
         },
         SHELL_COMPATIBILITY_VERSION.clone(),
     );
@@ -814,8 +824,10 @@ fn test_process_bootstrap_level1324_and_generate_action_file() -> Result<(), fai
     // context action recorders
     let context_action_recorders = vec![
         // action file recorder
-        Box::new(ActionFileStorage::new(target_action_file.clone()))
-            as Box<dyn ActionRecorder + Send>,
+        Box::new(
+            ActionFileStorage::new(target_action_file.clone())
+                .with_block_levels(BlockMetaStorage::new(storage.storage())),
+        ) as Box<dyn ActionRecorder + Send>,
     ];
 
     // start node
@@ -882,6 +894,107 @@ fn test_process_bootstrap_level1324_and_generate_action_file() -> Result<(), fai
     Ok(())
 }
 
+#[ignore]
+#[test]
+#[serial]
+fn test_replay_captured_traffic_to_chain_manager() -> Result<(), failure::Error> {
+    // logger
+    let log_level = common::log_level();
+    let log = common::create_logger(log_level);
+
+    let db = common::test_cases_data::current_branch_on_level_3::init_data(&log);
+    let tezos_env: &TezosEnvironmentConfiguration = TEZOS_ENV
+        .get(&db.tezos_env)
+        .expect("no environment configuration");
+
+    // start node
+    let node = crate::common::infra::NodeInfrastructure::start(
+        TmpStorage::create(common::prepare_empty_dir("__test_24"))?,
+        &common::prepare_empty_dir("__test_24_context"),
+        "test_replay_captured_traffic_to_chain_manager",
+        &tezos_env,
+        None,
+        Some(NODE_P2P_CFG.clone()),
+        NODE_IDENTITY.clone(),
+        (log, log_level),
+        vec![],
+        (false, false),
+    )?;
+
+    // wait for storage initialization to genesis
+    node.wait_for_new_current_head(
+        "genesis",
+        node.tezos_env.genesis_header_hash()?,
+        (Duration::from_secs(5), Duration::from_millis(250)),
+    )?;
+
+    // capture connection of the peer, which asks for the current head
+    let capture_file =
+        PathBuf::from(common::prepare_empty_dir("__test_24_capture")).join("p2p.capture");
+    let peer_id_marker = tezos_identity::Identity::generate(0f64)?
+        .peer_id()
+        .to_base58_check();
+    {
+        let recorder = Arc::new(TrafficRecorder::create(
+            &capture_file,
+            DEFAULT_CAPTURE_FILE_MAX_SIZE,
+            node.log.clone(),
+        )?);
+        let incoming = PeerTrafficRecorder::new(
+            recorder,
+            peer_id_marker.clone(),
+            "127.0.0.1:9732".parse()?,
+            Direction::Incoming,
+        );
+        incoming.connected(
+            MetadataMessage::new(false, false),
+            NODE_P2P_CFG.1.to_network_version(),
+        )?;
+        let message: PeerMessageResponse =
+            GetCurrentHeadMessage::new(node.tezos_env.main_chain_id()?).into();
+        incoming.chunk(&message.as_bytes()?)?;
+        // dropping recorders writes the whole capture
+    }
+
+    // replay capture as a fake peer
+    let mut driver = ReplayDriver::new(
+        node.network_channel.clone(),
+        ReplayOptions::default(),
+        node.log.clone(),
+    );
+    // captured disconnection (dropped recorder) is not replayed, so the fake peer waits for the answers
+    let records = CaptureReader::open(&capture_file)?.filter(|record| {
+        !matches!(
+            record,
+            Ok(CaptureRecord {
+                event: CaptureEvent::Disconnected,
+                ..
+            })
+        )
+    });
+    let summary = driver.replay(&node.actor_system, records)?;
+    assert_eq!(1, summary.connections);
+    assert_eq!(1, summary.incoming_messages);
+    assert_eq!(0, summary.invalid_messages);
+
+    // chain manager asks the bootstrapped peer for its branch and answers with the genesis head
+    let sent_messages = driver.wait_for_sent_messages(&peer_id_marker, 2, Duration::from_secs(10));
+    assert!(sent_messages
+        .iter()
+        .any(|message| matches!(message.message(), PeerMessage::GetCurrentBranch(_))));
+    let genesis_hash = node.tezos_env.genesis_header_hash()?;
+    assert!(sent_messages.iter().any(|message| match message.message() {
+        PeerMessage::CurrentHead(current_head) => {
+            current_head.current_block_header().message_hash().ok()
+                == Some(genesis_hash.as_ref().clone())
+        }
+        _ => false,
+    }));
+
+    driver.disconnect_all(&node.actor_system);
+    Ok(())
+}
+
 fn ensure_target_action_file() -> Result<PathBuf, failure::Error> {
     let action_file_path = env::var("TARGET_ACTION_FILE")
         .unwrap_or_else(|_| panic!("This test requires environment parameter: 'TARGET_ACTION_FILE' to point to the file, where to store recorded context action"));
@@ -17,6 +17,7 @@ use tokio::runtime::Runtime;
 
 use crypto::hash::{BlockHash, ContextHash, OperationHash};
 use networking::p2p::network_channel::{NetworkChannel, NetworkChannelRef};
+use networking::p2p::rate_limit::RateLimiter;
 use networking::ShellCompatibilityVersion;
 use shell::chain_current_head_manager::ChainCurrentHeadManager;
 use shell::chain_feeder::{ChainFeeder, ChainFeederRef};
@@ -24,7 +25,7 @@ use shell::chain_manager::{ChainManager, ChainManagerRef};
 use shell::context_listener::ContextListener;
 use shell::mempool::mempool_prevalidator::MempoolPrevalidator;
 use shell::mempool::{init_mempool_state_storage, CurrentMempoolStateStorageRef};
-use shell::peer_manager::{P2p, PeerManager, PeerManagerRef, WhitelistAllIpAddresses};
+use shell::peer_manager::{P2p, P2pPeers, PeerManager, PeerManagerRef, WhitelistAllIpAddresses};
 use shell::shell_channel::{ShellChannel, ShellChannelRef, ShellChannelTopic, ShuttingDown};
 use shell::state::head_state::init_current_head_state;
 use shell::state::synchronization_state::init_synchronization_bootstrap_state_storage;
@@ -32,8 +33,9 @@ use shell::stats::apply_block_stats::init_empty_apply_block_stats;
 use shell::PeerConnectionThreshold;
 use storage::chain_meta_storage::ChainMetaStorageReader;
 use storage::context::{ActionRecorder, ContextApi, TezedgeContext};
+use storage::history_mode::HistoryMode;
 use storage::tests_common::TmpStorage;
-use storage::{resolve_storage_init_chain_data, BlockStorage, ChainMetaStorage};
+use storage::{resolve_storage_init_chain_data, BlockStorage, ChainMetaStorage, PeerStorage};
 use tezos_api::environment::TezosEnvironmentConfiguration;
 use tezos_api::ffi::{PatchContext, TezosRuntimeConfiguration};
 use tezos_identity::Identity;
@@ -191,6 +193,7 @@ impl NodeInfrastructure {
             current_mempool_state_storage.clone(),
             bootstrap_state.clone(),
             apply_block_stats.clone(),
+            HistoryMode::Archive,
         )
         .expect("Failed to create chain current head manager");
         let block_applier = ChainFeeder::actor(
@@ -239,7 +242,13 @@ impl NodeInfrastructure {
                 &actor_system,
                 network_channel.clone(),
                 shell_channel.clone(),
+                Arc::new(P2pPeers::new(
+                    p2p_config.peer_threshold,
+                    PeerStorage::new(&persistent_storage),
+                )),
                 tokio_runtime.handle().clone(),
+                Arc::new(RateLimiter::new(p2p_config.rate_limit.clone())),
+                None,
                 identity,
                 Arc::new(shell_compatibility_version),
                 p2p_config,
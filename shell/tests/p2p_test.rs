@@ -15,12 +15,14 @@ use std::time::Duration;
 use lazy_static::lazy_static;
 use serial_test::serial;
 
+use networking::p2p::rate_limit::Rate;
 use networking::ShellCompatibilityVersion;
 use shell::peer_manager::P2p;
 use shell::PeerConnectionThreshold;
 use storage::tests_common::TmpStorage;
 use tezos_api::environment::{TezosEnvironmentConfiguration, TEZOS_ENV};
 use tezos_identity::Identity;
+use tezos_messages::p2p::encoding::prelude::PeerMessage;
 
 pub mod common;
 
@@ -37,6 +39,9 @@ lazy_static! {
             private_node: false,
             bootstrap_peers: vec![],
             peer_threshold: PeerConnectionThreshold::try_new(0, 2, Some(0)).expect("Invalid range"),
+            rate_limit: Default::default(),
+            capture_file: None,
+            capture_file_max_size: networking::p2p::capture::DEFAULT_CAPTURE_FILE_MAX_SIZE,
         },
         SHELL_COMPATIBILITY_VERSION.clone(),
     );
@@ -146,3 +151,98 @@ fn test_peer_threshold() -> Result<(), failure::Error> {
 
     Ok(())
 }
+
+#[ignore]
+#[test]
+#[serial]
+fn test_flooding_peer_is_blacklisted() -> Result<(), failure::Error> {
+    // logger
+    let log_level = common::log_level();
+    let log = common::create_logger(log_level);
+
+    // prepare env data
+    let (tezos_env, patch_context) = {
+        let (db, patch_context) = common::test_cases_data::sandbox_branch_1_level3::init_data(&log);
+        (db.tezos_env, patch_context)
+    };
+    let tezos_env: &TezosEnvironmentConfiguration = TEZOS_ENV
+        .get(&tezos_env)
+        .expect("no environment configuration");
+
+    // peer can send just one bootstrap message per second
+    let mut p2p_cfg = NODE_P2P_CFG.clone();
+    p2p_cfg.0.rate_limit.peer_messages.insert(
+        "bootstrap",
+        Rate {
+            per_sec: 1,
+            burst: 1,
+        },
+    );
+
+    // start node
+    let node = common::infra::NodeInfrastructure::start(
+        TmpStorage::create(common::prepare_empty_dir("__test_23"))?,
+        &common::prepare_empty_dir("__test_23_context"),
+        "test_flooding_peer_is_blacklisted",
+        &tezos_env,
+        patch_context,
+        Some(p2p_cfg),
+        NODE_IDENTITY.clone(),
+        (log, log_level),
+        vec![],
+        (false, false),
+    )?;
+
+    // register network channel listener
+    let peers_mirror = Arc::new(RwLock::new(HashMap::new()));
+    let _ = common::infra::test_actor::NetworkChannelListener::actor(
+        &node.actor_system,
+        node.network_channel.clone(),
+        peers_mirror.clone(),
+    );
+
+    // wait for storage initialization to genesis
+    node.wait_for_new_current_head(
+        "genesis",
+        node.tezos_env.genesis_header_hash()?,
+        (Duration::from_secs(5), Duration::from_millis(250)),
+    )?;
+
+    // connect mocked node peer
+    let mut mocked_peer_node = common::test_node_peer::TestNodePeer::connect(
+        "TEST_PEER_NODE_FLOODING".to_string(),
+        NODE_P2P_CFG.0.listener_port,
+        NODE_P2P_CFG.1.clone(),
+        tezos_identity::Identity::generate(0f64)?,
+        node.log.clone(),
+        &node.tokio_runtime,
+        common::test_cases_data::sandbox_branch_1_level3::serve_data,
+    );
+    assert!(mocked_peer_node
+        .wait_for_connection((Duration::from_secs(5), Duration::from_millis(100)))
+        .is_ok());
+    common::infra::test_actor::NetworkChannelListener::verify_connected(
+        &mocked_peer_node,
+        peers_mirror.clone(),
+    )?;
+
+    // flood the node, dropped messages are reported at most once per second
+    // (every dropped message adds 5 to the misbehaviour score, peer is blacklisted at 100)
+    for _ in 0..3 {
+        for _ in 0..30 {
+            mocked_peer_node.send_msg(PeerMessage::Bootstrap)?;
+        }
+        std::thread::sleep(Duration::from_millis(1100));
+    }
+
+    // peer should be now blacklisted
+    common::infra::test_actor::NetworkChannelListener::verify_blacklisted(
+        &mocked_peer_node,
+        peers_mirror,
+    )?;
+
+    drop(mocked_peer_node);
+    drop(node);
+
+    Ok(())
+}
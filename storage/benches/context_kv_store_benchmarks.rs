@@ -0,0 +1,154 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use slog::{warn, Drain, Level, Logger};
+
+use crypto::hash::ChainId;
+use storage::context::actions::action_file::ActionsFileReader;
+use storage::context::gc::SupportedContextGarbageCollector;
+use storage::context::merkle::entry_cache::DEFAULT_ENTRY_CACHE_CAPACITY;
+use storage::context::merkle::merkle_storage::MerkleStorage;
+use storage::context::{ContextApi, TezedgeContext};
+use storage::initializer::{
+    initialize_merkle, ContextKvStoreConfiguration, ContextRocksDbTableInitializer,
+    GlobalRocksDbCacheHolder, MainChain, RocksDbConfig,
+};
+use storage::persistent::database::ColumnFamiliesTuning;
+use tezos_context::channel::ContextAction;
+
+const LRU_CACHE_SIZE_64MB: usize = 64 * 1024 * 1024;
+const BLOCKS_PER_CYCLE: usize = 2048;
+
+fn create_logger() -> Logger {
+    let drain = slog_async::Async::new(
+        slog_term::FullFormat::new(slog_term::TermDecorator::new().build())
+            .build()
+            .fuse(),
+    )
+    .build()
+    .filter_level(Level::Info)
+    .fuse();
+
+    Logger::root(drain, slog::o!())
+}
+
+/// Loads all actions (grouped by block) from action file `TARGET_ACTION_FILE`, see storage/docs/action_replaying.md
+fn load_actions(log: &Logger) -> Option<Vec<Vec<ContextAction>>> {
+    let action_file_path = match env::var("TARGET_ACTION_FILE") {
+        Ok(action_file_path) => action_file_path,
+        Err(_) => {
+            warn!(log, "Skipping context kv store benchmarks, please set environment parameter: 'TARGET_ACTION_FILE' to point to the recorded action file");
+            return None;
+        }
+    };
+    let actions_reader =
+        ActionsFileReader::new(&action_file_path).expect("Failed to open action file");
+    Some(
+        actions_reader
+            .collect::<Result<_, _>>()
+            .expect("Failed to read action file"),
+    )
+}
+
+/// Creates merkle storage in clean directory
+fn create_merkle(
+    context_kv_store: &ContextKvStoreConfiguration,
+    path: &Path,
+    caches: &mut GlobalRocksDbCacheHolder,
+) -> Arc<RwLock<MerkleStorage>> {
+    if path.exists() {
+        fs::remove_dir_all(path).expect("Failed to clean context kv store directory");
+    }
+    fs::create_dir_all(path).expect("Failed to create context kv store directory");
+
+    let main_chain = MainChain::new(
+        ChainId::from_base58_check("NetXgtSLGNJvNye").expect("Failed to create chainId"),
+        "TEST_CHAIN_FOR_CONTEXT_KV_STORE_BENCHMARKS".to_string(),
+    );
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    Arc::new(RwLock::new(
+        initialize_merkle(
+            context_kv_store,
+            &SupportedContextGarbageCollector::None,
+            DEFAULT_ENTRY_CACHE_CAPACITY,
+            &main_chain,
+            &log,
+            caches,
+        )
+        .expect("Failed to initialize merkle storage"),
+    ))
+}
+
+/// Replays all actions the same way as `context-actions-replayer` (including notifications for garbage collection)
+fn replay(merkle: Arc<RwLock<MerkleStorage>>, blocks: &[Vec<ContextAction>]) {
+    let mut context = TezedgeContext::new(None, merkle);
+    for (block_index, actions) in blocks.iter().enumerate() {
+        for action in actions {
+            context
+                .perform_context_action(action.clone())
+                .expect("Failed to replay action");
+            if let ContextAction::Commit { .. } = action {
+                context.block_applied().expect("Failed to apply block");
+                if (block_index + 1) % BLOCKS_PER_CYCLE == 0 {
+                    context.cycle_started().expect("Failed to start cycle");
+                }
+            }
+        }
+    }
+}
+
+fn context_kv_store_replay_benchmark(c: &mut Criterion) {
+    let log = create_logger();
+    let blocks = match load_actions(&log) {
+        Some(blocks) => blocks,
+        None => return,
+    };
+    let base_dir: PathBuf = env::temp_dir().join("context_kv_store_benchmarks");
+
+    let rocksdb_path = base_dir.join("rocksdb");
+    let rocksdb = ContextKvStoreConfiguration::RocksDb(RocksDbConfig {
+        cache_size: LRU_CACHE_SIZE_64MB,
+        expected_db_version: 0,
+        db_path: rocksdb_path.clone(),
+        columns: ContextRocksDbTableInitializer,
+        threads: None,
+        column_families_tuning: ColumnFamiliesTuning::default(),
+    });
+    let pack_path = base_dir.join("pack");
+    let pack = ContextKvStoreConfiguration::PackFile {
+        path: pack_path.clone(),
+    };
+
+    let mut group = c.benchmark_group("replay_action_file");
+    group.sample_size(10);
+    for (name, context_kv_store, path) in &[
+        ("rocksdb", rocksdb, rocksdb_path),
+        ("pack", pack, pack_path),
+    ] {
+        group.bench_function(*name, |b| {
+            let mut caches = GlobalRocksDbCacheHolder::with_capacity(1);
+            b.iter_batched(
+                || create_merkle(context_kv_store, path, &mut caches),
+                |merkle| replay(merkle, &blocks),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+
+    let _ = fs::remove_dir_all(base_dir);
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default();
+    targets = context_kv_store_replay_benchmark
+}
+
+criterion_main!(benches);
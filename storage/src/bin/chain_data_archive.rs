@@ -0,0 +1,178 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Export of chain data (headers, operations, block metadata) to archive files and import back,
+//! see [storage::chain_archive].
+//!
+//! Merge operators (block/operations metadata) cannot be configured for read-only RocksDB instances,
+//! so the database is opened as usual - the node has to be stopped.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use failure::Error;
+use rocksdb::Cache;
+use slog::{info, Drain, Level, Logger};
+
+use storage::chain_archive::{
+    archive_files, export_chain_data, import_chain_data, ArchiveFormat, ExportConfig,
+};
+use storage::context::kv_store::in_memory_backend::InMemoryBackend;
+use storage::context::merkle::merkle_storage::MerkleStorage;
+use storage::initializer::{DbsRocksDbTableInitializer, RocksDbColumnFactory};
+use storage::persistent::database::{open_kv, ColumnFamiliesTuning};
+use storage::persistent::sequence::Sequences;
+use storage::persistent::{open_cl, CommitLogSchema, DbConfiguration};
+use storage::{BlockStorage, PersistentStorage};
+
+const LRU_CACHE_SIZE_64MB: usize = 64 * 1024 * 1024;
+
+fn create_app() -> App<'static, 'static> {
+    let db_path = Arg::with_name("db-path")
+        .long("db-path")
+        .takes_value(true)
+        .required(true)
+        .help("Path to the node storage (bootstrap db path)");
+    let archive_dir = Arg::with_name("archive-dir")
+        .long("archive-dir")
+        .takes_value(true)
+        .required(true)
+        .help("Directory with archive files");
+
+    App::new("chain-data-archive")
+        .about("Export chain data to JSON-lines/columnar archive files and import them back")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Export blocks of level range, completed parts are skipped and partial parts are resumed (with the same range, format and parallelism only)")
+                .arg(db_path.clone())
+                .arg(archive_dir.clone())
+                .arg(Arg::with_name("from-level")
+                    .long("from-level")
+                    .takes_value(true)
+                    .default_value("0")
+                    .help("First exported level"))
+                .arg(Arg::with_name("to-level")
+                    .long("to-level")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Last exported level (inclusive)"))
+                .arg(Arg::with_name("format")
+                    .long("format")
+                    .takes_value(true)
+                    .default_value("jsonl")
+                    .possible_values(&["jsonl", "columnar"])
+                    .help("Format of the archive files"))
+                .arg(Arg::with_name("parallelism")
+                    .long("parallelism")
+                    .takes_value(true)
+                    .default_value("1")
+                    .help("Level range is split to this count of parts exported in parallel"))
+                .arg(Arg::with_name("segment-size")
+                    .long("segment-size")
+                    .takes_value(true)
+                    .help("Count of blocks in one segment of the columnar file")),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Import all archive files of the directory into the storage")
+                .arg(db_path)
+                .arg(archive_dir),
+        )
+}
+
+fn parse_arg<T: std::str::FromStr>(matches: &ArgMatches, name: &str) -> T {
+    matches
+        .value_of(name)
+        .unwrap()
+        .parse::<T>()
+        .unwrap_or_else(|_| panic!("Provided value of '{}' is not valid", name))
+}
+
+fn create_logger() -> Logger {
+    let drain = slog_async::Async::new(
+        slog_term::FullFormat::new(slog_term::TermDecorator::new().build())
+            .build()
+            .fuse(),
+    )
+    .chan_size(32768)
+    .overflow_strategy(slog_async::OverflowStrategy::Block)
+    .build()
+    .filter_level(Level::Info)
+    .fuse();
+
+    Logger::root(drain, slog::o!())
+}
+
+/// Opens block storage part of the node storage (layout is the same as in light_node configuration),
+/// context is not needed, so it is kept just in memory
+fn open_storage(db_path: &Path, kv_cache: &Cache) -> Result<PersistentStorage, Error> {
+    let kv = Arc::new(open_kv(
+        db_path.join("db"),
+        DbsRocksDbTableInitializer.create(kv_cache, &ColumnFamiliesTuning::default()),
+        &DbConfiguration::default(),
+    )?);
+    let clog = open_cl(db_path, vec![BlockStorage::descriptor()])?;
+    Ok(PersistentStorage::new(
+        kv.clone(),
+        Arc::new(clog),
+        Arc::new(Sequences::new(kv, 1000)),
+        Arc::new(RwLock::new(MerkleStorage::new(Box::new(
+            InMemoryBackend::new(),
+        )))),
+        None,
+    ))
+}
+
+fn main() -> Result<(), Error> {
+    let matches = create_app().get_matches();
+    let log = create_logger();
+
+    // IMPORTANT: cache must live at least as long as database
+    let kv_cache = Cache::new_lru_cache(LRU_CACHE_SIZE_64MB)?;
+
+    match matches.subcommand() {
+        ("export", Some(matches)) => {
+            let db_path: PathBuf = parse_arg(matches, "db-path");
+            if !db_path.join("db").exists() {
+                return Err(failure::format_err!(
+                    "Storage directory does not exists: {:?}",
+                    db_path
+                ));
+            }
+            let config = ExportConfig {
+                format: parse_arg::<ArchiveFormat>(matches, "format"),
+                output_dir: parse_arg(matches, "archive-dir"),
+                from_level: parse_arg(matches, "from-level"),
+                to_level: parse_arg(matches, "to-level"),
+                parallelism: parse_arg(matches, "parallelism"),
+                segment_size: matches
+                    .value_of("segment-size")
+                    .map(|_| parse_arg(matches, "segment-size"))
+                    .unwrap_or(ExportConfig::DEFAULT_SEGMENT_SIZE),
+            };
+
+            let persistent_storage = open_storage(&db_path, &kv_cache)?;
+            let stats = export_chain_data(&persistent_storage, &config, &log)?;
+            info!(log, "Export finished";
+                       "files" => stats.files.len(),
+                       "exported_blocks" => stats.exported_blocks);
+        }
+        ("import", Some(matches)) => {
+            let db_path: PathBuf = parse_arg(matches, "db-path");
+            let archive_dir: PathBuf = parse_arg(matches, "archive-dir");
+            let files = archive_files(&archive_dir)?;
+
+            let persistent_storage = open_storage(&db_path, &kv_cache)?;
+            let stats = import_chain_data(&persistent_storage, &files, &log)?;
+            info!(log, "Import finished";
+                       "files" => files.len(),
+                       "imported_blocks" => stats.imported_blocks,
+                       "applied_blocks" => stats.applied_blocks);
+        }
+        _ => unreachable!("subcommand is required"),
+    }
+
+    Ok(())
+}
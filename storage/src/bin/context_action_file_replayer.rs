@@ -1,29 +1,40 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::convert::TryFrom;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::io::prelude::*;
 use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::mpsc::{sync_channel, Receiver};
 use std::sync::RwLock;
+use std::thread::{self, JoinHandle};
 use std::{fs, path::PathBuf, sync::Arc};
 
 use clap::{App, Arg};
 use failure::Error;
+use serde::{Deserialize, Serialize};
 use slog::{debug, info, warn, Drain, Level, Logger};
 
-use crypto::hash::ChainId;
-use storage::context::actions::action_file::ActionsFileReader;
-use storage::context::actions::get_new_tree_hash;
+use crypto::hash::{BlockHash, ChainId, ContextHash};
+use storage::context::actions::action_file::{
+    ActionFileError, ActionsFileBlock, ActionsFileReader,
+};
+use storage::context::actions::{get_new_tree_hash, get_new_tree_id, get_tree_id};
+use storage::context::gc::SupportedContextGarbageCollector;
 use storage::context::kv_store::SupportedContextKeyValueStore;
+use storage::context::merkle::entry_cache::DEFAULT_ENTRY_CACHE_CAPACITY;
 use storage::context::merkle::merkle_storage::MerkleStorage;
 use storage::context::merkle::merkle_storage_stats::MerkleStorageAction;
 use storage::context::merkle::merkle_storage_stats::OperationLatencyStats;
-use storage::context::{ContextApi, TezedgeContext};
+use storage::context::{ContextApi, ContextDiff, TezedgeContext};
 use storage::initializer::{
     initialize_merkle, ContextKvStoreConfiguration, ContextRocksDbTableInitializer,
     GlobalRocksDbCacheHolder, MainChain, RocksDbConfig,
 };
+use storage::persistent::database::ColumnFamiliesTuning;
+use storage::persistent::Flushable;
 use tezos_context::channel::ContextAction;
 
 struct Args {
@@ -32,10 +43,17 @@ struct Args {
     input: PathBuf,
     output: PathBuf,
     context_kv_store: ContextKvStoreConfiguration,
+    context_gc: SupportedContextGarbageCollector,
+    verify: bool,
+    resume: bool,
+    checkpoint_interval: Option<usize>,
 }
 
 const LRU_CACHE_SIZE_64MB: usize = 64 * 1024 * 1024;
 
+/// Count of blocks decoded ahead of the replayed one
+const READ_AHEAD_BLOCKS: usize = 16;
+
 impl Args {
     pub fn read_args() -> Self {
         let app = App::new("storage-stats")
@@ -67,7 +85,31 @@ impl Args {
                 .required(true)
                 .default_value("rocksdb")
                 .possible_values(&SupportedContextKeyValueStore::possible_values())
-                .help("Choose the merkle storege backend - supported backends: 'rocksdb', 'sled', 'inmem', 'btree'"));
+                .help("Choose the merkle storege backend - supported backends: 'rocksdb', 'sled', 'inmem', 'btree', 'pack'"))
+            .arg(Arg::with_name("context-gc")
+                .long("context-gc")
+                .takes_value(true)
+                .value_name("STRING")
+                .default_value("none")
+                .possible_values(&SupportedContextGarbageCollector::possible_values())
+                .help("Choose the garbage collector of the merkle storage - supported collectors: 'none', 'incremental', 'refcount'"))
+            .arg(Arg::with_name("context-gc-retained-cycles")
+                .long("context-gc-retained-cycles")
+                .takes_value(true)
+                .value_name("NUM")
+                .default_value("5")
+                .help("Number of cycles, which are not garbage collected"))
+            .arg(Arg::with_name("verify")
+                .long("verify")
+                .help("Verification mode - on the first commit, which differs from the recorded one, writes divergence report (staged trees diff and actions of the block) to the output dir and stops"))
+            .arg(Arg::with_name("checkpoint-interval")
+                .long("checkpoint-interval")
+                .takes_value(true)
+                .value_name("NUM")
+                .help("Stores checkpoint of the last verified block to the output dir every NUM blocks (needs persistent context kv store)"))
+            .arg(Arg::with_name("resume")
+                .long("resume")
+                .help("Resumes replaying from the checkpoint in the output dir, replayed context kv store is reused"));
 
         let matches = app.get_matches();
 
@@ -94,6 +136,7 @@ impl Args {
                             db_path: out_dir.join("replayed_context_rocksdb"),
                             columns: ContextRocksDbTableInitializer,
                             threads: None,
+                            column_families_tuning: ColumnFamiliesTuning::default(),
                         })
                     }
                     SupportedContextKeyValueStore::Sled { .. } => {
@@ -105,6 +148,11 @@ impl Args {
                     SupportedContextKeyValueStore::BTreeMap => {
                         ContextKvStoreConfiguration::BTreeMap
                     }
+                    SupportedContextKeyValueStore::PackFile { .. } => {
+                        ContextKvStoreConfiguration::PackFile {
+                            path: out_dir.join("replayed_context_pack"),
+                        }
+                    }
                 })
                 .unwrap_or_else(|e| {
                     panic!(
@@ -113,9 +161,34 @@ impl Args {
                         e
                     )
                 }),
+            context_gc: matches
+                .value_of("context-gc")
+                .unwrap()
+                .parse::<SupportedContextGarbageCollector>()
+                .map(|v| {
+                    v.with_retained_cycles(
+                        matches
+                            .value_of("context-gc-retained-cycles")
+                            .map(|s| s.parse::<usize>().unwrap())
+                            .unwrap(),
+                    )
+                })
+                .unwrap_or_else(|e| {
+                    panic!(
+                        "Expecting one value from {:?}, error: {:?}",
+                        SupportedContextGarbageCollector::possible_values(),
+                        e
+                    )
+                }),
             blocks_limit: matches
                 .value_of("blocks_limit")
                 .map(|s| s.parse::<usize>().unwrap()),
+            verify: matches.is_present("verify"),
+            resume: matches.is_present("resume"),
+            checkpoint_interval: matches
+                .value_of("checkpoint-interval")
+                .map(|s| s.parse::<usize>().unwrap())
+                .filter(|interval| *interval > 0),
             output: out_dir,
             input: matches
                 .value_of("input")
@@ -159,6 +232,7 @@ fn create_logger() -> Logger {
 
 // process actionfile without deselializing blocks
 // in order to get count of blocks
+/// Counts blocks of version 1 action file
 fn get_blocks_count(log: &Logger, path: PathBuf) -> Result<u32, Error> {
     let file = OpenOptions::new()
         .write(false)
@@ -195,7 +269,7 @@ struct StatsWriter {
 }
 
 impl StatsWriter {
-    fn new(output: File) -> Self {
+    fn new(output: File, write_header: bool) -> Self {
         let mut rv = Self {
             output,
             block_latencies_total: 0,
@@ -216,7 +290,9 @@ impl StatsWriter {
                 MerkleStorageAction::BlockApplied,
             ],
         };
-        rv.write_header();
+        if write_header {
+            rv.write_header();
+        }
         rv
     }
 
@@ -257,6 +333,8 @@ impl StatsWriter {
                 MerkleStorageAction::Commit => "Commit",
                 MerkleStorageAction::Checkout => "Checkout",
                 MerkleStorageAction::BlockApplied => "BlockApplied",
+                MerkleStorageAction::Diff => "Diff",
+                MerkleStorageAction::GetProof => "GetProof",
             })
             .map(|action| {
                 format!(
@@ -346,6 +424,213 @@ impl StatsWriter {
     }
 }
 
+const CHECKPOINT_FILE_NAME: &str = "replay_checkpoint.json";
+const DIVERGENCE_REPORT_FILE_NAME: &str = "divergence_report.json";
+
+/// Last verified block, replaying can be resumed after it
+#[derive(Serialize, Deserialize)]
+struct ReplayCheckpoint {
+    /// level of the block (chain level, or position in the file, if the recorder did not know it)
+    level: u32,
+    /// count of replayed blocks, missing in older checkpoints, where it was equal to level
+    #[serde(default)]
+    blocks_replayed: Option<usize>,
+    block_hash: String,
+    context_hash: String,
+    cycle_counter: usize,
+    /// garbage collector, whose state was stored with the replayed context
+    #[serde(default)]
+    context_gc: Option<String>,
+}
+
+impl ReplayCheckpoint {
+    fn load(output: &Path) -> Result<Self, Error> {
+        let file = File::open(output.join(CHECKPOINT_FILE_NAME))?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    /// Checkpoint is written and synced to the temp file first, so the previous one survives a crash
+    fn store(&self, output: &Path) -> Result<(), Error> {
+        let temp_path = output.join(format!("{}.tmp", CHECKPOINT_FILE_NAME));
+        let mut temp_file = File::create(&temp_path)?;
+        serde_json::to_writer_pretty(&mut temp_file, self)?;
+        temp_file.sync_all()?;
+        fs::rename(temp_path, output.join(CHECKPOINT_FILE_NAME))?;
+        // persist the rename
+        File::open(output)?.sync_all()?;
+        Ok(())
+    }
+}
+
+/// The first action of the block, after which the replayed context differs from the recorded one
+struct Divergence {
+    action_index: usize,
+    reason: String,
+    staged_diff: Option<ContextDiff>,
+}
+
+/// Describes the first block, whose replayed commit hash differs from the recorded one
+#[derive(Serialize)]
+struct DivergenceReport {
+    level: u32,
+    block_hash: String,
+    /// last commit, which was replayed correctly
+    parent_context_hash: Option<String>,
+    expected_context_hash: String,
+    replayed_context_hash: Option<String>,
+    expected_tree_hash: Option<String>,
+    replayed_tree_hash: String,
+    /// index (in the block) of the first action, after which the context differs
+    divergent_action_index: usize,
+    reason: String,
+    /// difference of staged trees before and after the divergent action,
+    /// or difference between parent commit and replayed commit
+    diff: Option<ContextDiff>,
+    /// actions of the block up to the divergent action (including)
+    actions: Vec<ContextAction>,
+}
+
+/// Compares state of the context after the action has been applied with the recorded one,
+/// returns description of the difference
+fn check_action(
+    context: &mut dyn ContextApi,
+    action: &ContextAction,
+) -> Result<Option<String>, Error> {
+    let mismatch = match action {
+        ContextAction::Checkout { context_hash, .. } => {
+            let replayed = context.get_last_commit_hash()?;
+            if replayed.as_ref() != Some(context_hash) {
+                Some(format!(
+                    "checkout - expected context hash: {}, replayed: {:?}",
+                    hex::encode(context_hash),
+                    replayed.map(hex::encode)
+                ))
+            } else {
+                None
+            }
+        }
+        ContextAction::Get { key, value, .. } => match context.get_key(key) {
+            Ok(replayed) if replayed == *value => None,
+            replayed => Some(format!(
+                "get {:?} - expected: {}, replayed: {:?}",
+                key,
+                hex::encode(value),
+                replayed.map(hex::encode)
+            )),
+        },
+        ContextAction::Mem { key, value, .. } => match context.mem(key) {
+            Ok(replayed) if replayed == *value => None,
+            replayed => Some(format!(
+                "mem {:?} - expected: {}, replayed: {:?}",
+                key, value, replayed
+            )),
+        },
+        ContextAction::DirMem { key, value, .. } => match context.dirmem(key) {
+            Ok(replayed) if replayed == *value => None,
+            replayed => Some(format!(
+                "dirmem {:?} - expected: {}, replayed: {:?}",
+                key, value, replayed
+            )),
+        },
+        _ => None,
+    };
+    if mismatch.is_some() {
+        return Ok(mismatch);
+    }
+
+    match get_new_tree_hash(action)? {
+        Some(expected_hash) => {
+            let replayed_hash = context.get_merkle_root()?;
+            if replayed_hash != expected_hash {
+                Ok(Some(format!(
+                    "{} - expected tree hash: {}, replayed: {}",
+                    get_tree_action(action),
+                    hex::encode(expected_hash),
+                    hex::encode(replayed_hash)
+                )))
+            } else {
+                Ok(None)
+            }
+        }
+        None => Ok(None),
+    }
+}
+
+/// Applies the action to the context, recorded tree and context hashes are not asserted
+/// (unlike [ContextApi::perform_context_action]), they are compared by [check_action]
+fn apply_action(context: &mut dyn ContextApi, action: &ContextAction) -> Result<(), Error> {
+    match action {
+        ContextAction::Commit {
+            parent_context_hash,
+            block_hash: Some(block_hash),
+            tree_id,
+            author,
+            message,
+            date,
+            ..
+        } => {
+            context.set_merkle_root(*tree_id)?;
+            let parent_context_hash = match parent_context_hash {
+                Some(parent_context_hash) => {
+                    Some(ContextHash::try_from(parent_context_hash.clone())?)
+                }
+                None => None,
+            };
+            context.commit(
+                &BlockHash::try_from(block_hash.clone())?,
+                &parent_context_hash,
+                author.clone(),
+                message.clone(),
+                *date,
+            )?;
+        }
+        _ => {
+            let mut action = action.clone();
+            match &mut action {
+                ContextAction::Set { new_tree_hash, .. }
+                | ContextAction::Copy { new_tree_hash, .. }
+                | ContextAction::Delete { new_tree_hash, .. }
+                | ContextAction::RemoveRecursively { new_tree_hash, .. } => *new_tree_hash = None,
+                _ => (),
+            }
+            context.perform_context_action(action)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads and decodes at most `blocks_limit` blocks on a separate thread,
+/// so decoding of the following blocks runs in parallel with replaying and verification of the current one
+fn spawn_blocks_reader(
+    mut actions_reader: ActionsFileReader,
+    blocks_limit: usize,
+) -> Result<
+    (
+        Receiver<Result<ActionsFileBlock, ActionFileError>>,
+        JoinHandle<()>,
+    ),
+    Error,
+> {
+    let (block_tx, block_rx) = sync_channel(READ_AHEAD_BLOCKS);
+    let handle = thread::Builder::new()
+        .name("actions-file-reader".to_string())
+        .spawn(move || {
+            for _ in 0..blocks_limit {
+                let block = match actions_reader.next_block() {
+                    Ok(Some(block)) => Ok(block),
+                    Ok(None) => break,
+                    Err(e) => Err(e),
+                };
+                let failed = block.is_err();
+                // receiver is dropped, when replaying stops
+                if block_tx.send(block).is_err() || failed {
+                    break;
+                }
+            }
+        })?;
+    Ok((block_rx, handle))
+}
+
 /// Resolve name and store path (if supports)
 fn resolve_context_kv_store(
     context_kv_store_configuration: &ContextKvStoreConfiguration,
@@ -357,13 +642,18 @@ fn resolve_context_kv_store(
         ContextKvStoreConfiguration::Sled { path } => ("sled".to_string(), Some(path.clone())),
         ContextKvStoreConfiguration::InMem => ("inmem".to_string(), None),
         ContextKvStoreConfiguration::BTreeMap => ("btree".to_string(), None),
+        ContextKvStoreConfiguration::PackFile { path } => ("pack".to_string(), Some(path.clone())),
     }
 }
 
 fn main() -> Result<(), Error> {
     let params = Args::read_args();
     let log = create_logger();
+    replay(params, &log).map(|_| ())
+}
 
+/// Replays the action file according to `params`, returns the replayed merkle storage
+fn replay(params: Args, log: &Logger) -> Result<Arc<RwLock<MerkleStorage>>, Error> {
     // prepare files
     let (context_kv_storage_name, context_kv_storage_path) =
         resolve_context_kv_store(&params.context_kv_store);
@@ -377,12 +667,47 @@ fn main() -> Result<(), Error> {
         ));
     }
 
-    // prepare storage path (if needed)
-    if let Some(context_kv_storage_path) = context_kv_storage_path {
-        if context_kv_storage_path.exists() {
-            let _ = fs::remove_dir_all(&context_kv_storage_path)?;
+    // prepare storage path (if needed), resumed replaying continues with the replayed context
+    let checkpoint = if params.resume {
+        if context_kv_storage_path.is_none() {
+            return Err(failure::format_err!(
+                "Replaying cannot be resumed with not persistent context kv store: {}",
+                context_kv_storage_name
+            ));
         }
-        let _ = fs::create_dir_all(&context_kv_storage_path)?;
+        let checkpoint = ReplayCheckpoint::load(&params.output)?;
+        // state of the collector is kept with the replayed context, it has to be resumed by the same collector
+        let context_gc = format!("{:?}", params.context_gc);
+        if let Some(checkpoint_context_gc) = &checkpoint.context_gc {
+            if checkpoint_context_gc != &context_gc {
+                return Err(failure::format_err!(
+                    "Replaying was checkpointed with context gc: {}, it cannot be resumed with: {}",
+                    checkpoint_context_gc,
+                    context_gc
+                ));
+            }
+        }
+        Some(checkpoint)
+    } else {
+        if let Some(context_kv_storage_path) = &context_kv_storage_path {
+            if context_kv_storage_path.exists() {
+                let _ = fs::remove_dir_all(context_kv_storage_path)?;
+            }
+            let _ = fs::create_dir_all(context_kv_storage_path)?;
+        }
+        // fresh replaying starts also with a fresh state of the collector
+        if let Some(gc_path) = params.context_kv_store.gc_path(&params.context_gc) {
+            if gc_path.exists() {
+                let _ = fs::remove_dir_all(gc_path)?;
+            }
+        }
+        None
+    };
+    if params.checkpoint_interval.is_some() && context_kv_storage_path.is_none() {
+        return Err(failure::format_err!(
+            "Checkpoints cannot be stored with not persistent context kv store: {}",
+            context_kv_storage_name
+        ));
     }
 
     // prepare stats output file
@@ -393,7 +718,7 @@ fn main() -> Result<(), Error> {
         let stats_output_file = params
             .output
             .join(&format!("{}.stats.txt", context_kv_storage_name));
-        if stats_output_file.exists() {
+        if stats_output_file.exists() && checkpoint.is_none() {
             let _ = fs::remove_file(&stats_output_file)?;
         }
         stats_output_file
@@ -403,7 +728,10 @@ fn main() -> Result<(), Error> {
                "input_file" => actions_file_path.to_str().unwrap(),
                "output_stats_file" => stats_output_file.to_str().unwrap(),
                "target_context_kv_store_path" => params.output.to_str().unwrap(),
-               "target_context_kv_store" => context_kv_storage_name);
+               "target_context_kv_store" => context_kv_storage_name,
+               "context_gc" => format!("{:?}", params.context_gc),
+               "verify" => params.verify,
+               "checkpoint_interval" => format!("{:?}", params.checkpoint_interval));
 
     let mocked_test_main_chain = MainChain::new(
         ChainId::from_base58_check("NetXgtSLGNJvNye").expect("Failed to create chainId"),
@@ -414,72 +742,156 @@ fn main() -> Result<(), Error> {
     // create merkle storage
     let merkle = Arc::new(RwLock::new(initialize_merkle(
         &params.context_kv_store,
+        &params.context_gc,
+        DEFAULT_ENTRY_CACHE_CAPACITY,
         &mocked_test_main_chain,
-        &log,
+        log,
         &mut global_cache_holder,
     )?));
     let mut context: Box<dyn ContextApi> = Box::new(TezedgeContext::new(None, merkle.clone()));
-    let mut stat_writer = StatsWriter::new(File::create(stats_output_file)?);
+    let mut stat_writer = StatsWriter::new(
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(stats_output_file)?,
+        checkpoint.is_none(),
+    );
 
     let mut counter = 0;
     let mut cycle_counter = 0;
-    let blocks_count = get_blocks_count(&log, actions_file_path.clone())?;
+    let mut actions_reader = ActionsFileReader::new(&actions_file_path)?;
+    // version 2 file knows count of blocks, version 1 file has to be scanned
+    let blocks_count = match actions_reader.block_count() {
+        Some(blocks_count) => blocks_count,
+        None => get_blocks_count(log, actions_file_path.clone())?,
+    };
 
-    info!(log, "{} blocks found", blocks_count);
+    info!(log, "{} blocks found", blocks_count; "action_file_version" => actions_reader.version());
 
-    let actions_reader = ActionsFileReader::new(&actions_file_path)?;
+    if let Some(checkpoint) = &checkpoint {
+        info!(log, "Resuming replaying from checkpoint";
+                   "level" => checkpoint.level,
+                   "block_hash" => &checkpoint.block_hash,
+                   "context_hash" => &checkpoint.context_hash);
+        context.checkout(&ContextHash::from_base58_check(&checkpoint.context_hash)?)?;
+        actions_reader.seek_to_levels(checkpoint.level + 1..)?;
+        counter = checkpoint
+            .blocks_replayed
+            .unwrap_or(checkpoint.level as usize);
+        cycle_counter = checkpoint.cycle_counter;
+    }
 
-    for messages in actions_reader.take(params.blocks_limit.unwrap_or(blocks_count as usize)) {
+    let blocks_limit = params.blocks_limit.unwrap_or(blocks_count as usize);
+    let (blocks, blocks_reader) = spawn_blocks_reader(actions_reader, blocks_limit)?;
+    for block in blocks.iter() {
+        let block = block?;
         counter += 1;
         let progress = counter as f64 / blocks_count as f64 * 100.0;
+        let parent_context_hash = context.get_last_commit_hash()?;
+        let mut divergence: Option<Divergence> = None;
 
-        for action in messages.iter() {
+        for (action_index, action) in block.actions.iter().enumerate() {
             // evaluate context action to context
-            context.perform_context_action(action.clone())?;
+            apply_action(context.as_mut(), action)?;
 
-            // verify state of the storage after action has been applied
-            match action {
-                ContextAction::Commit {
-                    new_context_hash, ..
-                } => {
-                    assert_eq!(
-                        new_context_hash.clone(),
-                        context.get_last_commit_hash()?.unwrap()
-                    );
-                }
-                ContextAction::Checkout { context_hash, .. } => {
-                    assert!(!context_hash.is_empty());
-                    assert_eq!(
-                        context_hash.clone(),
-                        context.get_last_commit_hash()?.unwrap()
-                    );
-                }
-                ContextAction::Get { key, value, .. } => {
-                    assert_eq!(value.clone(), context.get_key(key).unwrap());
+            // verify state of the storage after action has been applied,
+            // verification mode remembers the first difference and reports it with the commit
+            if divergence.is_none() {
+                if let Some(reason) = check_action(context.as_mut(), action)? {
+                    if !params.verify {
+                        return Err(failure::format_err!(
+                            "Replayed context differs at level {} (action {}): {}",
+                            block.level,
+                            action_index,
+                            reason
+                        ));
+                    }
+                    let staged_diff = match (get_tree_id(action), get_new_tree_id(action)) {
+                        (Some(tree_id), Some(new_tree_id)) => {
+                            Some(context.get_staged_diff(tree_id, new_tree_id)?)
+                        }
+                        _ => None,
+                    };
+                    divergence = Some(Divergence {
+                        action_index,
+                        reason,
+                        staged_diff,
+                    });
                 }
-                ContextAction::Mem { key, value, .. } => {
-                    assert_eq!(*value, context.mem(key).unwrap());
+            }
+
+            if let ContextAction::Commit {
+                new_context_hash,
+                tree_hash,
+                ..
+            } = &action
+            {
+                // verify resulting hash of the commit
+                let replayed_context_hash = context.get_last_commit_hash()?;
+                if replayed_context_hash.as_ref() != Some(new_context_hash) {
+                    if !params.verify {
+                        return Err(failure::format_err!(
+                            "Replayed commit at level {} differs - expected context hash: {}, replayed: {:?}",
+                            block.level,
+                            hex::encode(new_context_hash),
+                            replayed_context_hash.map(hex::encode)
+                        ));
+                    }
+                    let divergence = match divergence {
+                        Some(divergence) => divergence,
+                        None => Divergence {
+                            action_index,
+                            reason: "commit - all actions match, but context hash differs"
+                                .to_string(),
+                            staged_diff: match (&parent_context_hash, &replayed_context_hash) {
+                                (Some(parent), Some(replayed)) => Some(context.get_context_diff(
+                                    &ContextHash::try_from(parent.clone())?,
+                                    &ContextHash::try_from(replayed.clone())?,
+                                    &vec![],
+                                    None,
+                                    None,
+                                )?),
+                                _ => None,
+                            },
+                        },
+                    };
+                    let report = DivergenceReport {
+                        level: block.level,
+                        block_hash: block.block_hash.to_base58_check(),
+                        parent_context_hash: parent_context_hash.map(hex::encode),
+                        expected_context_hash: hex::encode(new_context_hash),
+                        replayed_context_hash: replayed_context_hash.map(hex::encode),
+                        expected_tree_hash: tree_hash.as_ref().map(hex::encode),
+                        replayed_tree_hash: hex::encode(context.get_merkle_root()?),
+                        divergent_action_index: divergence.action_index,
+                        reason: divergence.reason,
+                        diff: divergence.staged_diff,
+                        actions: block.actions[..=divergence.action_index].to_vec(),
+                    };
+                    let report_file = params.output.join(DIVERGENCE_REPORT_FILE_NAME);
+                    serde_json::to_writer_pretty(File::create(&report_file)?, &report)?;
+
+                    return Err(failure::format_err!(
+                        "Replayed commit at level {} differs, divergence report: {}",
+                        block.level,
+                        report_file.to_str().unwrap()
+                    ));
                 }
-                ContextAction::DirMem { key, value, .. } => {
-                    assert_eq!(*value, context.dirmem(key).unwrap());
+                if let Some(divergence) = divergence.take() {
+                    warn!(log, "Replayed commit matches, but block actions differ";
+                               "level" => block.level,
+                               "action_index" => divergence.action_index,
+                               "reason" => divergence.reason);
                 }
-                _ => {}
-            };
-
-            // verify context hashes after each block
-            if let Some(expected_hash) = get_new_tree_hash(&action)? {
-                assert_eq!(context.get_merkle_root()?, expected_hash);
-            }
 
-            if let ContextAction::Commit { block_hash, .. } = &action {
                 debug!(
                     log,
                     "progress {:.7}% - cycle nr: {} block nr {} [{}] with {} messages processed - {} mb",
                     progress,
                     cycle_counter,
                     counter,
-                    hex::encode(&block_hash.clone().unwrap().clone()),
-                    messages.len(),
+                    block.block_hash.to_base58_check(),
+                    block.actions.len(),
                     merkle.clone()
                         .read()
                         .unwrap()
@@ -494,6 +906,22 @@ fn main() -> Result<(), Error> {
                     context.cycle_started().unwrap();
                     cycle_counter += 1;
                 }
+
+                if let Some(checkpoint_interval) = params.checkpoint_interval {
+                    if counter % checkpoint_interval == 0 {
+                        merkle.read().unwrap().flush()?;
+                        ReplayCheckpoint {
+                            level: block.level,
+                            blocks_replayed: Some(counter),
+                            block_hash: block.block_hash.to_base58_check(),
+                            context_hash: ContextHash::try_from(new_context_hash.clone())?
+                                .to_base58_check(),
+                            cycle_counter,
+                            context_gc: Some(format!("{:?}", params.context_gc)),
+                        }
+                        .store(&params.output)?;
+                    }
+                }
             }
         }
         stat_writer.update(counter, merkle.clone());
@@ -501,5 +929,266 @@ fn main() -> Result<(), Error> {
 
     info!(log, "Context was successfully evaluated");
 
-    Ok(())
+    blocks_reader
+        .join()
+        .map_err(|_| failure::format_err!("Actions file reader panicked"))?;
+    Ok(merkle)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use storage::context::actions::action_file::ActionsFileWriter;
+    use storage::context::kv_store::in_memory_backend::InMemoryBackend;
+
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let out_dir = env::var("OUT_DIR").expect(
+            "OUT_DIR is not defined - please add build.rs to root or set env variable OUT_DIR",
+        );
+        let dir = Path::new(&out_dir).join(format!("replayer_{}", name));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn set_action(level: u8, value: u8) -> ContextAction {
+        ContextAction::Set {
+            context_hash: None,
+            block_hash: None,
+            operation_hash: None,
+            tree_hash: None,
+            new_tree_hash: None,
+            tree_id: 0,
+            new_tree_id: 0,
+            start_time: 0.0,
+            end_time: 0.0,
+            key: vec!["data".to_string(), format!("{}", level)],
+            value: vec![value],
+            value_as_json: None,
+        }
+    }
+
+    fn commit_action(level: u8, new_context_hash: Vec<u8>) -> ContextAction {
+        ContextAction::Commit {
+            parent_context_hash: None,
+            block_hash: Some(vec![level; 32]),
+            new_context_hash,
+            tree_hash: None,
+            tree_id: 0,
+            start_time: 0.0,
+            end_time: 0.0,
+            author: "Tezedge".to_string(),
+            message: "".to_string(),
+            date: 0,
+            parents: vec![],
+        }
+    }
+
+    /// Records blocks with resulting tree and context hashes computed by in-memory context
+    fn record_blocks(count: u8) -> Vec<Vec<ContextAction>> {
+        let merkle = Arc::new(RwLock::new(MerkleStorage::new(Box::new(
+            InMemoryBackend::new(),
+        ))));
+        let mut context = TezedgeContext::new(None, merkle);
+        (1..=count)
+            .map(|level| {
+                let mut set = set_action(level, level);
+                apply_action(&mut context, &set).unwrap();
+                if let ContextAction::Set { new_tree_hash, .. } = &mut set {
+                    *new_tree_hash = Some(context.get_merkle_root().unwrap().to_vec());
+                }
+                apply_action(&mut context, &commit_action(level, vec![0; 32])).unwrap();
+                let new_context_hash = context.get_last_commit_hash().unwrap().unwrap();
+                vec![set, commit_action(level, new_context_hash)]
+            })
+            .collect()
+    }
+
+    fn write_action_file(path: &Path, blocks: &[Vec<ContextAction>]) {
+        let mut writer = ActionsFileWriter::new(path).unwrap();
+        for actions in blocks {
+            writer.update(actions.clone()).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn args(input: &Path, output: &Path) -> Args {
+        Args {
+            blocks_per_cycle: 2,
+            blocks_limit: None,
+            input: input.to_path_buf(),
+            output: output.to_path_buf(),
+            context_kv_store: ContextKvStoreConfiguration::Sled {
+                path: output.join("replayed_context_sled"),
+            },
+            context_gc: SupportedContextGarbageCollector::None,
+            verify: false,
+            resume: false,
+            checkpoint_interval: None,
+        }
+    }
+
+    fn log() -> Logger {
+        Logger::root(slog::Discard, slog::o!())
+    }
+
+    fn last_commit_hash(merkle: &Arc<RwLock<MerkleStorage>>) -> Option<Vec<u8>> {
+        TezedgeContext::new(None, merkle.clone())
+            .get_last_commit_hash()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_verify_writes_divergence_report() {
+        let dir = test_dir("verify");
+        let input = dir.join("actions.bin");
+        let output = dir.join("output");
+        let mut blocks = record_blocks(3);
+        write_action_file(&input, &blocks);
+
+        // matching file is replayed completely
+        let merkle = replay(
+            Args {
+                verify: true,
+                ..args(&input, &output)
+            },
+            &log(),
+        )
+        .unwrap();
+        assert_eq!(
+            last_commit_hash(&merkle),
+            match &blocks[2][1] {
+                ContextAction::Commit {
+                    new_context_hash, ..
+                } => Some(new_context_hash.clone()),
+                _ => None,
+            }
+        );
+        drop(merkle);
+
+        // the second block sets different value, than the recorded tree hash was computed for
+        blocks[1][0] = match &blocks[1][0] {
+            ContextAction::Set { new_tree_hash, .. } => {
+                let mut set = set_action(2, 42);
+                if let ContextAction::Set {
+                    new_tree_hash: changed,
+                    ..
+                } = &mut set
+                {
+                    *changed = new_tree_hash.clone();
+                }
+                set
+            }
+            _ => unreachable!(),
+        };
+        write_action_file(&input, &blocks);
+        let report_file = output.join(DIVERGENCE_REPORT_FILE_NAME);
+
+        // without verification replaying just fails
+        assert!(replay(args(&input, &output), &log()).is_err());
+        assert!(!report_file.exists());
+
+        assert!(replay(
+            Args {
+                verify: true,
+                ..args(&input, &output)
+            },
+            &log()
+        )
+        .is_err());
+        let report: serde_json::Value =
+            serde_json::from_reader(File::open(&report_file).unwrap()).unwrap();
+        assert_eq!(report["level"], 2);
+        assert_eq!(
+            report["block_hash"],
+            BlockHash(vec![2; 32]).to_base58_check()
+        );
+        assert_eq!(report["divergent_action_index"], 0);
+        assert_eq!(report["actions"].as_array().unwrap().len(), 1);
+        assert!(report["diff"].is_object());
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let dir = test_dir("resume");
+        let input = dir.join("actions.bin");
+        let output = dir.join("output");
+        let blocks = record_blocks(6);
+        write_action_file(&input, &blocks);
+        let context_gc = SupportedContextGarbageCollector::Incremental { retained_cycles: 5 };
+
+        // replaying stops after the third block, the checkpoint is stored after the second one
+        let merkle = replay(
+            Args {
+                blocks_limit: Some(3),
+                checkpoint_interval: Some(2),
+                context_gc,
+                ..args(&input, &output)
+            },
+            &log(),
+        )
+        .unwrap();
+        drop(merkle);
+        let checkpoint = ReplayCheckpoint::load(&output).unwrap();
+        assert_eq!(checkpoint.level, 2);
+        assert_eq!(checkpoint.blocks_replayed, Some(2));
+        assert!(!output
+            .join(format!("{}.tmp", CHECKPOINT_FILE_NAME))
+            .exists());
+
+        // state of the collector has to be resumed by the same collector
+        assert!(replay(
+            Args {
+                resume: true,
+                context_gc: SupportedContextGarbageCollector::None,
+                ..args(&input, &output)
+            },
+            &log()
+        )
+        .is_err());
+
+        let merkle = replay(
+            Args {
+                resume: true,
+                checkpoint_interval: Some(2),
+                context_gc,
+                ..args(&input, &output)
+            },
+            &log(),
+        )
+        .unwrap();
+        let last_commit = match &blocks[5][1] {
+            ContextAction::Commit {
+                new_context_hash, ..
+            } => new_context_hash.clone(),
+            _ => unreachable!(),
+        };
+        assert_eq!(last_commit_hash(&merkle), Some(last_commit));
+        drop(merkle);
+        let checkpoint = ReplayCheckpoint::load(&output).unwrap();
+        assert_eq!(checkpoint.level, 6);
+        assert_eq!(checkpoint.blocks_replayed, Some(6));
+
+        // collector still knows commits replayed before the checkpoint
+        let first_commit = match &blocks[0][1] {
+            ContextAction::Commit {
+                new_context_hash, ..
+            } => new_context_hash.clone(),
+            _ => unreachable!(),
+        };
+        let gc_path = args(&input, &output)
+            .context_kv_store
+            .gc_path(&context_gc)
+            .unwrap();
+        let cycles = sled::open(gc_path).unwrap().open_tree("cycles").unwrap();
+        assert!(cycles
+            .iter()
+            .keys()
+            .any(|key| key.unwrap().ends_with(&first_commit)));
+    }
 }
@@ -0,0 +1,224 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Export of context snapshot of applied block and import of tezedge or Octez snapshots,
+//! see [storage::snapshot].
+//!
+//! The node has to be stopped, storage is opened as usual (layout is the same as in light_node configuration).
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use failure::Error;
+use rocksdb::Cache;
+use slog::{info, Drain, Level, Logger};
+
+use crypto::hash::BlockHash;
+use storage::context::kv_store::pack_file_backend::PackFileBackend;
+use storage::context::kv_store::rocksdb_backend::RocksDBBackend;
+use storage::context::kv_store::sled_backend::SledBackend;
+use storage::context::merkle::merkle_storage::MerkleStorage;
+use storage::initializer::{
+    ContextRocksDbTableInitializer, DbsRocksDbTableInitializer, RocksDbColumnFactory,
+};
+use storage::persistent::database::{open_kv, ColumnFamiliesTuning};
+use storage::persistent::sequence::Sequences;
+use storage::persistent::{open_cl, CommitLogSchema, DbConfiguration};
+use storage::snapshot::octez::import_octez_snapshot;
+use storage::snapshot::{export_snapshot, import_snapshot};
+use storage::{BlockStorage, PersistentStorage};
+use tezos_api::environment::{TezosEnvironment, TEZOS_ENV};
+
+const LRU_CACHE_SIZE_64MB: usize = 64 * 1024 * 1024;
+
+fn create_app() -> App<'static, 'static> {
+    let db_path = Arg::with_name("db-path")
+        .long("db-path")
+        .takes_value(true)
+        .required(true)
+        .help("Path to the node storage (bootstrap db path)");
+    let context_kv_store = Arg::with_name("context-kv-store")
+        .long("context-kv-store")
+        .takes_value(true)
+        .value_name("STRING")
+        .default_value("rocksdb")
+        .possible_values(&["rocksdb", "sled", "pack"])
+        .help("Merkle storage backend used by the node - supported persistent backends: 'rocksdb', 'sled', 'pack'");
+    let snapshot_file = Arg::with_name("snapshot-file")
+        .long("snapshot-file")
+        .takes_value(true)
+        .required(true)
+        .help("Path to the snapshot file");
+
+    App::new("context-snapshot")
+        .about("Export context snapshot of applied block and import tezedge or Octez snapshots")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Export context, header and metadata of applied block")
+                .arg(db_path.clone())
+                .arg(context_kv_store.clone())
+                .arg(snapshot_file.clone())
+                .arg(
+                    Arg::with_name("block-hash")
+                        .long("block-hash")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Hash of the exported block"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Import tezedge snapshot, the node then starts from the snapshot block")
+                .arg(db_path.clone())
+                .arg(context_kv_store.clone())
+                .arg(snapshot_file.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("import-octez")
+                .about("Import snapshot exported by the OCaml node into empty storage")
+                .arg(db_path)
+                .arg(context_kv_store)
+                .arg(snapshot_file)
+                .arg(
+                    Arg::with_name("network")
+                        .long("network")
+                        .takes_value(true)
+                        .required(true)
+                        .possible_values(&TezosEnvironment::possible_values())
+                        .help("Network of the snapshot, its chain id and genesis are used"),
+                ),
+        )
+}
+
+fn parse_arg<T: std::str::FromStr>(matches: &ArgMatches, name: &str) -> T {
+    matches
+        .value_of(name)
+        .unwrap()
+        .parse::<T>()
+        .unwrap_or_else(|_| panic!("Provided value of '{}' is not valid", name))
+}
+
+fn create_logger() -> Logger {
+    let drain = slog_async::Async::new(
+        slog_term::FullFormat::new(slog_term::TermDecorator::new().build())
+            .build()
+            .fuse(),
+    )
+    .chan_size(32768)
+    .overflow_strategy(slog_async::OverflowStrategy::Block)
+    .build()
+    .filter_level(Level::Info)
+    .fuse();
+
+    Logger::root(drain, slog::o!())
+}
+
+/// Opens the node storage together with its context
+fn open_storage(
+    db_path: &Path,
+    context_kv_store: &str,
+    kv_cache: &Cache,
+    kv_context_cache: &Cache,
+) -> Result<PersistentStorage, Error> {
+    let cfg = DbConfiguration::default();
+    let kv = Arc::new(open_kv(
+        db_path.join("db"),
+        DbsRocksDbTableInitializer.create(kv_cache, &ColumnFamiliesTuning::default()),
+        &cfg,
+    )?);
+    let clog = open_cl(db_path, vec![BlockStorage::descriptor()])?;
+    let merkle = MerkleStorage::new(match context_kv_store {
+        "sled" => Box::new(SledBackend::new(
+            sled::Config::new()
+                .path(db_path.join("context_sled"))
+                .open()?,
+        )),
+        "pack" => Box::new(PackFileBackend::new(db_path.join("context_pack"), None)?),
+        _ => Box::new(RocksDBBackend::new(Arc::new(open_kv(
+            db_path.join("context"),
+            ContextRocksDbTableInitializer
+                .create(kv_context_cache, &ColumnFamiliesTuning::default()),
+            &cfg,
+        )?))),
+    });
+    Ok(PersistentStorage::new(
+        kv.clone(),
+        Arc::new(clog),
+        Arc::new(Sequences::new(kv, 1000)),
+        Arc::new(RwLock::new(merkle)),
+        None,
+    ))
+}
+
+fn main() -> Result<(), Error> {
+    let matches = create_app().get_matches();
+    let log = create_logger();
+
+    // IMPORTANT: caches must live at least as long as databases
+    let kv_cache = Cache::new_lru_cache(LRU_CACHE_SIZE_64MB)?;
+    let kv_context_cache = Cache::new_lru_cache(LRU_CACHE_SIZE_64MB)?;
+
+    match matches.subcommand() {
+        ("export", Some(matches)) => {
+            let db_path: PathBuf = parse_arg(matches, "db-path");
+            if !db_path.join("db").exists() {
+                return Err(failure::format_err!(
+                    "Storage directory does not exists: {:?}",
+                    db_path
+                ));
+            }
+            let block_hash = BlockHash::from_base58_check(matches.value_of("block-hash").unwrap())?;
+            let snapshot_file: PathBuf = parse_arg(matches, "snapshot-file");
+
+            let persistent_storage = open_storage(
+                &db_path,
+                matches.value_of("context-kv-store").unwrap(),
+                &kv_cache,
+                &kv_context_cache,
+            )?;
+            let writer = BufWriter::new(File::create(&snapshot_file)?);
+            let info = export_snapshot(writer, &block_hash, &persistent_storage)?;
+            info!(log, "Snapshot exported";
+                       "block" => info.block_hash.to_base58_check(),
+                       "level" => info.level,
+                       "entries_count" => info.entries_count);
+        }
+        ("import", Some(matches)) => {
+            let db_path: PathBuf = parse_arg(matches, "db-path");
+            let snapshot_file: PathBuf = parse_arg(matches, "snapshot-file");
+
+            let persistent_storage = open_storage(
+                &db_path,
+                matches.value_of("context-kv-store").unwrap(),
+                &kv_cache,
+                &kv_context_cache,
+            )?;
+            let reader = BufReader::new(File::open(&snapshot_file)?);
+            import_snapshot(reader, &persistent_storage, &log)?;
+        }
+        ("import-octez", Some(matches)) => {
+            let db_path: PathBuf = parse_arg(matches, "db-path");
+            let snapshot_file: PathBuf = parse_arg(matches, "snapshot-file");
+            let network: TezosEnvironment = parse_arg(matches, "network");
+            let tezos_env = TEZOS_ENV.get(&network).ok_or_else(|| {
+                failure::format_err!("No tezos environment configured for: {:?}", network)
+            })?;
+
+            let persistent_storage = open_storage(
+                &db_path,
+                matches.value_of("context-kv-store").unwrap(),
+                &kv_cache,
+                &kv_context_cache,
+            )?;
+            let reader = BufReader::new(File::open(&snapshot_file)?);
+            import_octez_snapshot(reader, tezos_env, &persistent_storage, &log)?;
+        }
+        _ => unreachable!("subcommand is required"),
+    }
+
+    Ok(())
+}
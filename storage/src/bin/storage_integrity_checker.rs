@@ -0,0 +1,163 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Offline check of the node storage, see [storage::integrity_check].
+//!
+//! The node has to be stopped. The storage is opened read-only - RocksDB databases as read-only instances,
+//! commit log and pack files without any writes. Read-only RocksDB instance cannot merge block/operations
+//! metadata, which were not compacted yet, such records are counted in the report as unmerged.
+//! Sled cannot be opened read-only (it recovers and rewrites the database on open), so contexts are not checked
+//! for sled backend.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use clap::{App, Arg};
+use failure::Error;
+use slog::{info, Drain, Level, Logger};
+
+use storage::context::kv_store::in_memory_backend::InMemoryBackend;
+use storage::context::kv_store::pack_file_backend::PackFileBackend;
+use storage::context::kv_store::rocksdb_backend::RocksDBBackend;
+use storage::context::merkle::merkle_storage::MerkleStorage;
+use storage::initializer::{
+    ContextRocksDbTableInitializer, DbsRocksDbTableInitializer, RocksDbColumnFactory,
+};
+use storage::integrity_check::check_storage;
+use storage::persistent::database::open_kv_read_only;
+use storage::persistent::sequence::Sequences;
+use storage::persistent::{open_cl_read_only, CommitLogSchema, DbConfiguration};
+use storage::{BlockStorage, PersistentStorage};
+
+struct Args {
+    db_path: PathBuf,
+    context_kv_store: String,
+    output: Option<PathBuf>,
+}
+
+impl Args {
+    pub fn read_args() -> Self {
+        let app = App::new("storage-integrity-checker")
+            .about("Verify invariants of the storage and print report in json format")
+            .arg(Arg::with_name("db-path")
+                .long("db-path")
+                .takes_value(true)
+                .required(true)
+                .help("Path to the node storage (bootstrap db path)"))
+            .arg(Arg::with_name("context-kv-store")
+                .long("context-kv-store")
+                .takes_value(true)
+                .value_name("STRING")
+                .required(true)
+                .default_value("rocksdb")
+                .possible_values(&["rocksdb", "sled", "pack"])
+                .help("Merkle storage backend used by the node - supported persistent backends: 'rocksdb', 'sled', 'pack'"))
+            .arg(Arg::with_name("output")
+                .long("output")
+                .takes_value(true)
+                .help("Output file for the report, if not set, report is printed to stdout"));
+
+        let matches = app.get_matches();
+
+        Self {
+            db_path: matches
+                .value_of("db-path")
+                .unwrap()
+                .parse::<PathBuf>()
+                .expect("Provided value cannot be converted to path"),
+            context_kv_store: matches.value_of("context-kv-store").unwrap().to_string(),
+            output: matches.value_of("output").map(|output| {
+                output
+                    .parse::<PathBuf>()
+                    .expect("Provided value cannot be converted to path")
+            }),
+        }
+    }
+}
+
+fn create_logger() -> Logger {
+    let drain = slog_async::Async::new(
+        slog_term::FullFormat::new(slog_term::TermDecorator::new().build())
+            .build()
+            .fuse(),
+    )
+    .chan_size(32768)
+    .overflow_strategy(slog_async::OverflowStrategy::Block)
+    .build()
+    .filter_level(Level::Info)
+    .fuse();
+
+    Logger::root(drain, slog::o!())
+}
+
+fn main() -> Result<(), Error> {
+    let params = Args::read_args();
+    let log = create_logger();
+
+    // storage layout is the same as in light_node configuration
+    let db_path = params.db_path.join("db");
+    let context_path = match params.context_kv_store.as_str() {
+        "sled" => params.db_path.join("context_sled"),
+        "pack" => params.db_path.join("context_pack"),
+        _ => params.db_path.join("context"),
+    };
+    for path in &[&db_path, &context_path] {
+        if !path.exists() {
+            return Err(failure::format_err!(
+                "Storage directory does not exists: {:?}",
+                path.to_str().unwrap(),
+            ));
+        }
+    }
+
+    info!(log, "Storage integrity checker starts...";
+               "db_path" => params.db_path.to_str().unwrap(),
+               "context_kv_store" => &params.context_kv_store);
+
+    let cfg = DbConfiguration::default();
+
+    let kv = Arc::new(open_kv_read_only(
+        &db_path,
+        DbsRocksDbTableInitializer.column_families(),
+        &cfg,
+    )?);
+    let clog = open_cl_read_only(&params.db_path, vec![BlockStorage::descriptor()])?;
+    let check_contexts = params.context_kv_store != "sled";
+    let merkle = MerkleStorage::new(match params.context_kv_store.as_str() {
+        "sled" => {
+            info!(
+                log,
+                "Sled cannot be opened read-only, contexts are not checked"
+            );
+            Box::new(InMemoryBackend::new())
+        }
+        "pack" => Box::new(PackFileBackend::open_read_only(&context_path)?),
+        _ => Box::new(RocksDBBackend::new(Arc::new(open_kv_read_only(
+            &context_path,
+            ContextRocksDbTableInitializer.column_families(),
+            &cfg,
+        )?))),
+    });
+    let persistent_storage = PersistentStorage::new(
+        kv.clone(),
+        Arc::new(clog),
+        Arc::new(Sequences::new(kv, 1000)),
+        Arc::new(RwLock::new(merkle)),
+        None,
+    );
+
+    let report = check_storage(&persistent_storage, check_contexts, &log)?;
+    let report_json = serde_json::to_string_pretty(&report)?;
+    match &params.output {
+        Some(output) => fs::write(output, report_json)?,
+        None => println!("{}", report_json),
+    }
+
+    if report.is_ok() {
+        info!(log, "Storage is consistent");
+        Ok(())
+    } else {
+        Err(failure::format_err!("Storage integrity check found issues"))
+    }
+}
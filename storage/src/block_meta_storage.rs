@@ -13,7 +13,7 @@ use crypto::hash::{BlockHash, ChainId, HashType};
 use tezos_messages::p2p::encoding::block_header::Level;
 
 use crate::persistent::database::{
-    default_table_options, IteratorMode, IteratorWithSchema, RocksDbKeyValueSchema,
+    ColumnFamilyTuning, IteratorMode, IteratorWithSchema, RocksDbKeyValueSchema, SchemaWriteBatch,
 };
 use crate::persistent::{Decoder, Encoder, KeyValueSchema, KeyValueStoreWithSchema, SchemaError};
 use crate::predecessor_storage::{PredecessorKey, PredecessorStorage};
@@ -68,6 +68,36 @@ impl BlockMetaStorage {
         chain_id: &ChainId,
         log: &Logger,
     ) -> Result<Meta, StorageError> {
+        self.put_block_header_with(block_header, chain_id, log, |block_hash, meta| {
+            self.put(block_hash, meta)
+        })
+    }
+
+    /// The same as [put_block_header], but metadata are just added to the `batch`
+    pub(crate) fn put_block_header_to_batch(
+        &self,
+        block_header: &BlockHeaderWithHash,
+        chain_id: &ChainId,
+        log: &Logger,
+        batch: &mut SchemaWriteBatch,
+    ) -> Result<Meta, StorageError> {
+        self.put_block_header_with(block_header, chain_id, log, |block_hash, meta| {
+            batch
+                .merge::<Self>(block_hash, meta)
+                .map_err(StorageError::from)
+        })
+    }
+
+    fn put_block_header_with<F>(
+        &self,
+        block_header: &BlockHeaderWithHash,
+        chain_id: &ChainId,
+        log: &Logger,
+        mut put: F,
+    ) -> Result<Meta, StorageError>
+    where
+        F: FnMut(&BlockHash, &Meta) -> Result<(), StorageError>,
+    {
         // create/update record for block
         let block_metadata = match self.get(&block_header.hash)? {
             Some(mut meta) => {
@@ -91,7 +121,7 @@ impl BlockMetaStorage {
                 // predecessor cannot be rewriten (just from None to Some) - see see merge_meta_value
                 if meta.predecessor.is_none() {
                     meta.predecessor = Some(block_predecessor);
-                    self.put(&block_header.hash, &meta)?;
+                    put(&block_header.hash, &meta)?;
                 }
 
                 meta
@@ -104,7 +134,7 @@ impl BlockMetaStorage {
                     level: block_header.header.level(),
                     chain_id: chain_id.clone(),
                 };
-                self.put(&block_header.hash, &meta)?;
+                put(&block_header.hash, &meta)?;
                 meta
             }
         };
@@ -142,7 +172,7 @@ impl BlockMetaStorage {
 
                 if need_change {
                     meta.successors.push(block_hash.clone());
-                    self.put(block_header.header.predecessor(), &meta)?;
+                    put(block_header.header.predecessor(), &meta)?;
                 }
             }
             None => {
@@ -153,13 +183,52 @@ impl BlockMetaStorage {
                     level: block_header.header.level() - 1,
                     chain_id: chain_id.clone(),
                 };
-                self.put(block_header.header.predecessor(), &meta)?;
+                put(block_header.header.predecessor(), &meta)?;
             }
         }
 
         Ok(block_metadata)
     }
 
+    /// Resets predecessor of not applied block, so the block is considered as not downloaded (see [Meta::is_downloaded]).
+    /// Returns false, if block is unknown or already applied.
+    pub fn reset_downloaded(&self, block_hash: &BlockHash) -> Result<bool, StorageError> {
+        match self.get(block_hash)? {
+            Some(mut meta) if !meta.is_applied => {
+                meta.predecessor = None;
+                // merge cannot remove predecessor, so the whole record is rewritten
+                self.kv.put(block_hash, &meta)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Replaces successors of the block, e.g. when forks are pruned.
+    /// Returns false, if block is unknown.
+    pub fn set_successors(
+        &self,
+        block_hash: &BlockHash,
+        successors: Vec<BlockHash>,
+    ) -> Result<bool, StorageError> {
+        match self.get(block_hash)? {
+            Some(mut meta) => {
+                meta.successors = successors;
+                // merge cannot remove successors, so the whole record is rewritten
+                self.kv.put(block_hash, &meta)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Removes metadata of the block together with its predecessors index
+    pub fn delete(&self, block_hash: &BlockHash) -> Result<(), StorageError> {
+        self.predecessors_index
+            .delete_predecessors(block_hash, Self::STORED_PREDECESSORS_SIZE)?;
+        self.kv.delete(block_hash).map_err(StorageError::from)
+    }
+
     pub fn store_predecessors(
         &self,
         block_hash: &BlockHash,
@@ -173,6 +242,34 @@ impl BlockMetaStorage {
         Ok(())
     }
 
+    /// The same as [store_predecessors], but predecessors are just added to the `batch`
+    pub(crate) fn store_predecessors_to_batch(
+        &self,
+        block_hash: &BlockHash,
+        block_meta: &Meta,
+        batch: &mut SchemaWriteBatch,
+    ) -> Result<(), StorageError> {
+        self.predecessors_index.store_predecessors_to_batch(
+            block_hash,
+            block_meta,
+            Self::STORED_PREDECESSORS_SIZE,
+            batch,
+        )
+    }
+
+    /// The same as [put], but metadata are just added to the `batch`
+    #[inline]
+    pub(crate) fn put_to_batch(
+        &self,
+        block_hash: &BlockHash,
+        meta: &Meta,
+        batch: &mut SchemaWriteBatch,
+    ) -> Result<(), StorageError> {
+        batch
+            .merge::<Self>(block_hash, meta)
+            .map_err(StorageError::from)
+    }
+
     #[inline]
     pub fn put(&self, block_hash: &BlockHash, meta: &Meta) -> Result<(), StorageError> {
         self.kv.merge(block_hash, meta).map_err(StorageError::from)
@@ -513,12 +610,23 @@ impl KeyValueSchema for BlockMetaStorage {
 }
 
 impl RocksDbKeyValueSchema for BlockMetaStorage {
-    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        let mut cf_opts = default_table_options(cache);
+    fn descriptor_with_tuning(
+        cache: &Cache,
+        tuning: &ColumnFamilyTuning,
+    ) -> ColumnFamilyDescriptor {
+        let mut cf_opts = tuning.table_options(cache);
         cf_opts.set_merge_operator("block_meta_storage_merge_operator", merge_meta_value, None);
         ColumnFamilyDescriptor::new(Self::name(), cf_opts)
     }
 
+    /// Block metadata are read by random block hashes, so bloom filter saves most of the disk reads
+    fn tuning() -> ColumnFamilyTuning {
+        ColumnFamilyTuning {
+            bloom_filter_bits: 10,
+            ..ColumnFamilyTuning::default()
+        }
+    }
+
     #[inline]
     fn name() -> &'static str {
         "block_meta_storage"
@@ -1,10 +1,13 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
 
+use commitlog::{Offset, ReadError};
 use derive_builder::Builder;
 use getset::{CopyGetters, Getters};
+use rocksdb::DB;
 use serde::{Deserialize, Serialize};
 
 use crypto::hash::{
@@ -12,28 +15,45 @@ use crypto::hash::{
 };
 
 use crate::persistent::database::IteratorWithSchema;
-use crate::persistent::database::RocksDbKeyValueSchema;
+use crate::persistent::database::{RocksDbKeyValueSchema, SchemaWriteBatch};
 use crate::persistent::{
-    BincodeEncoded, CommitLogSchema, CommitLogWithSchema, KeyValueSchema, KeyValueStoreWithSchema,
-    Location,
+    BincodeEncoded, CommitLogError, CommitLogSchema, CommitLogWithSchema, DBError, KeyValueSchema,
+    KeyValueStoreWithSchema, Location,
+};
+use crate::system_storage::SystemValue;
+use crate::{
+    BlockHeaderWithHash, Direction, IteratorMode, PersistentStorage, StorageError, SystemStorage,
 };
-use crate::{BlockHeaderWithHash, Direction, IteratorMode, PersistentStorage, StorageError};
 
 /// Store block header data in a key-value store and into commit log.
 /// The value is first inserted into commit log, which returns a location of the newly inserted value.
 /// That location is then stored as a value in the key-value store.
 ///
 /// The assumption is that, if primary_index contains block_hash, then also commit_log contains header data
+///
+/// Locations are changed by commit log compaction, so every operation working with locations holds `locations_lock`.
 #[derive(Clone)]
 pub struct BlockStorage {
     primary_index: BlockPrimaryIndex,
     by_level_index: BlockByLevelIndex,
     by_context_hash_index: BlockByContextHashIndex,
     clog: Arc<BlockStorageCommitLog>,
+    locations_lock: Arc<RwLock<()>>,
+    compaction_lock: Arc<Mutex<()>>,
+    db: Arc<DB>,
 }
 
 pub type BlockStorageCommitLog = dyn CommitLogWithSchema<BlockStorage> + Sync + Send;
 
+/// Statistics of one commit log compaction
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CommitLogCompactionStats {
+    /// Count of records copied to the compacted commit log
+    pub live_records: usize,
+    /// Size of removed records
+    pub reclaimed_bytes: u64,
+}
+
 #[derive(Clone, Builder, Getters, Serialize, Deserialize, Debug)]
 pub struct BlockJsonData {
     #[get = "pub"]
@@ -147,12 +167,18 @@ pub trait BlockStorageReader: Sync + Send {
 }
 
 impl BlockStorage {
+    /// Count of records copied at once by [compact_commit_log]
+    const COMPACTION_SEGMENT_SIZE: usize = 10_000;
+
     pub fn new(persistent_storage: &PersistentStorage) -> Self {
         Self {
             primary_index: BlockPrimaryIndex::new(persistent_storage.db()),
             by_level_index: BlockByLevelIndex::new(persistent_storage.db()),
             by_context_hash_index: BlockByContextHashIndex::new(persistent_storage.db()),
             clog: persistent_storage.clog(),
+            locations_lock: persistent_storage.clog().locations_lock(),
+            compaction_lock: persistent_storage.clog().compaction_lock(),
+            db: persistent_storage.db(),
         }
     }
 
@@ -162,29 +188,78 @@ impl BlockStorage {
     pub fn put_block_header(
         &self,
         block_header: &BlockHeaderWithHash,
+    ) -> Result<bool, StorageError> {
+        let _locations_guard = self.locations_guard()?;
+        let mut batch = self.write_batch();
+        let is_new_block = self.put_block_header_to_batch(block_header, &mut batch)?;
+        batch.write(false)?;
+        Ok(is_new_block)
+    }
+
+    /// Appends header to commit log and flushes it, index entries are just added to the `batch`,
+    /// so they can be written atomically with other storages (see [crate::store_block_header]).
+    ///
+    /// Caller has to hold [locations_guard] until the batch is written.
+    pub(crate) fn put_block_header_to_batch(
+        &self,
+        block_header: &BlockHeaderWithHash,
+        batch: &mut SchemaWriteBatch,
     ) -> Result<bool, StorageError> {
         if self.primary_index.contains(&block_header.hash)? {
             // we assume that, if primary_index contains hash, then also commit_log contains header data, header data cannot be change, so there is nothing to do
             return Ok(false);
         }
 
-        self.clog
-            .append(&BlockStorageColumn::BlockHeader(block_header.clone()))
-            .map_err(StorageError::from)
-            .and_then(|block_header_location| {
-                let location = BlockStorageColumnsLocation {
-                    block_header: block_header_location,
-                    block_json_data: None,
-                    block_additional_data: None,
-                };
-                self.primary_index
-                    .put(&block_header.hash, &location)
-                    .and(
-                        self.by_level_index
-                            .put(block_header.header.level(), &location),
-                    )
-                    .and(Ok(true))
-            })
+        let block_header_location = self
+            .clog
+            .append(&BlockStorageColumn::BlockHeader(block_header.clone()))?;
+        // header has to be on disk before it is referenced from any index
+        self.clog.sync()?;
+
+        let location = BlockStorageColumnsLocation {
+            block_header: block_header_location,
+            block_json_data: None,
+            block_additional_data: None,
+        };
+        batch.put::<BlockPrimaryIndex>(&block_header.hash, &location)?;
+        batch.put::<BlockByLevelIndex>(&block_header.header.level(), &location)?;
+        Ok(true)
+    }
+
+    /// Stores json and additional data of applied block, indexes are updated in the `batch`.
+    /// Caller has to hold `locations_guard` until the batch is written.
+    pub(crate) fn put_block_result_to_batch(
+        &self,
+        block_hash: &BlockHash,
+        json_data: BlockJsonData,
+        additional_data: BlockAdditionalData,
+        batch: &mut SchemaWriteBatch,
+    ) -> Result<(), StorageError> {
+        let mut location = self
+            .primary_index
+            .get(block_hash)?
+            .ok_or(StorageError::MissingKey)?;
+        location.block_json_data = Some(
+            self.clog
+                .append(&BlockStorageColumn::BlockJsonData(json_data))?,
+        );
+        location.block_additional_data = Some(
+            self.clog
+                .append(&BlockStorageColumn::BlockAdditionalData(additional_data))?,
+        );
+        // both records have to be on disk before they are referenced from any index
+        self.clog.sync()?;
+
+        let block_header = self.get_block_header_by_location(&location)?;
+        batch.put::<BlockPrimaryIndex>(block_hash, &location)?;
+        batch.put::<BlockByLevelIndex>(&block_header.header.level(), &location)?;
+        Ok(())
+    }
+
+    /// Creates batch for atomic write to the same database as block storage indexes
+    #[inline]
+    pub(crate) fn write_batch(&self) -> SchemaWriteBatch {
+        SchemaWriteBatch::new(&self.db)
     }
 
     pub fn put_block_json_data(
@@ -192,10 +267,12 @@ impl BlockStorage {
         block_hash: &BlockHash,
         json_data: BlockJsonData,
     ) -> Result<(), StorageError> {
+        let _locations_guard = self.locations_guard()?;
         let updated_column_location = {
             let block_json_data_location = self
                 .clog
                 .append(&BlockStorageColumn::BlockJsonData(json_data))?;
+            self.clog.sync()?;
             let mut column_location = self
                 .primary_index
                 .get(block_hash)?
@@ -218,10 +295,12 @@ impl BlockStorage {
         block_hash: &BlockHash,
         additional_data: BlockAdditionalData,
     ) -> Result<(), StorageError> {
+        let _locations_guard = self.locations_guard()?;
         let updated_column_location = {
             let block_additional_data_location = self
                 .clog
                 .append(&BlockStorageColumn::BlockAdditionalData(additional_data))?;
+            self.clog.sync()?;
             let mut column_location = self
                 .primary_index
                 .get(block_hash)?
@@ -244,12 +323,380 @@ impl BlockStorage {
         block_hash: &BlockHash,
         context_hash: &ContextHash,
     ) -> Result<(), StorageError> {
+        let _locations_guard = self.locations_guard()?;
         match self.primary_index.get(block_hash)? {
             Some(location) => self.by_context_hash_index.put(context_hash, &location),
             None => Err(StorageError::MissingKey),
         }
     }
 
+    /// Returns block stored in level index for `level`
+    pub fn get_by_level(
+        &self,
+        level: BlockLevel,
+    ) -> Result<Option<BlockHeaderWithHash>, StorageError> {
+        let _locations_guard = self.locations_guard()?;
+        self.by_level_index
+            .get(level)?
+            .map(|location| self.get_block_header_by_location(&location))
+            .transpose()
+    }
+
+    /// Returns at most `limit` blocks stored in level index for levels `from_level..=to_level` (ascending)
+    /// together with json data and additional data, if stored
+    pub fn get_by_level_range(
+        &self,
+        from_level: BlockLevel,
+        to_level: BlockLevel,
+        limit: usize,
+    ) -> Result<
+        Vec<(
+            BlockHeaderWithHash,
+            Option<BlockJsonData>,
+            Option<BlockAdditionalData>,
+        )>,
+        StorageError,
+    > {
+        let _locations_guard = self.locations_guard()?;
+        self.by_level_index
+            .get_blocks_in_range(from_level, to_level, limit)?
+            .into_iter()
+            .map(|location| {
+                Ok((
+                    self.get_block_header_by_location(&location)?,
+                    self.get_block_json_data_by_location(&location)?,
+                    self.get_block_additional_data_by_location(&location)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Removes json data (operations/block metadata) of the block, header and additional data are kept.
+    /// All indexes pointing to the block are updated.
+    /// Returns true, if block had json data
+    pub fn remove_block_json_data(&self, block_hash: &BlockHash) -> Result<bool, StorageError> {
+        let _locations_guard = self.locations_guard()?;
+        let mut location = match self.primary_index.get(block_hash)? {
+            Some(location) => location,
+            None => return Ok(false),
+        };
+        if location.block_json_data.take().is_none() {
+            return Ok(false);
+        }
+        let block_header = self.get_block_header_by_location(&location)?;
+
+        self.primary_index.put(block_hash, &location)?;
+        if self.is_indexed_by_level(&block_header, &location)? {
+            self.by_level_index
+                .put(block_header.header.level(), &location)?;
+        }
+        if self.is_indexed_by_context_hash(&block_header, &location)? {
+            self.by_context_hash_index
+                .put(block_header.header.context(), &location)?;
+        }
+        Ok(true)
+    }
+
+    /// Removes block from context hash index, block is still readable by hash and level.
+    /// Returns true, if block was indexed by its context hash
+    pub fn unassign_context(&self, block_hash: &BlockHash) -> Result<bool, StorageError> {
+        let _locations_guard = self.locations_guard()?;
+        let location = match self.primary_index.get(block_hash)? {
+            Some(location) => location,
+            None => return Ok(false),
+        };
+        let block_header = self.get_block_header_by_location(&location)?;
+
+        if self.is_indexed_by_context_hash(&block_header, &location)? {
+            self.by_context_hash_index
+                .delete(block_header.header.context())?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Removes block from all indexes, so it cannot be read anymore.
+    /// Data in commit log are not touched.
+    /// Returns true, if block was stored
+    pub fn remove_block(&self, block_hash: &BlockHash) -> Result<bool, StorageError> {
+        let _locations_guard = self.locations_guard()?;
+        let location = match self.primary_index.get(block_hash)? {
+            Some(location) => location,
+            None => return Ok(false),
+        };
+        let block_header = self.get_block_header_by_location(&location)?;
+
+        // secondary indexes first, so primary index never misses block referenced from them
+        if self.is_indexed_by_level(&block_header, &location)? {
+            self.by_level_index.delete(block_header.header.level())?;
+        }
+        if self.is_indexed_by_context_hash(&block_header, &location)? {
+            self.by_context_hash_index
+                .delete(block_header.header.context())?;
+        }
+        self.primary_index.delete(block_hash)?;
+        Ok(true)
+    }
+
+    /// Removes blocks, which header cannot be read from commit log (e.g. commit log was not flushed before crash), from all indexes.
+    /// Just blocks stored from `from_offset` of the commit log are checked (see [crate::initializer::recover_storage]).
+    /// Block is removed only if its record is missing or corrupted, other errors (e.g. I/O) are returned.
+    /// Returns hashes of removed blocks.
+    pub fn remove_unreadable_blocks(
+        &self,
+        from_offset: Offset,
+    ) -> Result<Vec<BlockHash>, StorageError> {
+        let _locations_guard = self.locations_guard()?;
+
+        let mut unreadable_blocks = Vec::new();
+        let mut unreadable_offsets = HashSet::new();
+        for (block_hash, location) in self.primary_index.kv.iterator(IteratorMode::Start)? {
+            let (block_hash, location) = (block_hash?, location?);
+            if location.block_header.0 < from_offset {
+                continue;
+            }
+            let is_readable = match self.get_block_header_by_location(&location) {
+                Ok(block_header) => block_header.hash == block_hash,
+                Err(e) if is_record_lost(&e) => false,
+                Err(e) => return Err(e),
+            };
+            if !is_readable {
+                unreadable_offsets.insert(location.block_header.0);
+                unreadable_blocks.push(block_hash);
+            }
+        }
+        if unreadable_blocks.is_empty() {
+            return Ok(unreadable_blocks);
+        }
+
+        // header is unreadable, so secondary indexes are resolved just by location
+        let mut batch = self.write_batch();
+        for block_hash in &unreadable_blocks {
+            batch.delete::<BlockPrimaryIndex>(block_hash)?;
+        }
+        for (level, location) in collect_index(&*self.by_level_index.kv)? {
+            if unreadable_offsets.contains(&location.block_header.0) {
+                batch.delete::<BlockByLevelIndex>(&level)?;
+            }
+        }
+        for (context_hash, location) in collect_index(&*self.by_context_hash_index.kv)? {
+            if unreadable_offsets.contains(&location.block_header.0) {
+                batch.delete::<BlockByContextHashIndex>(&context_hash)?;
+            }
+        }
+        batch.write(true)?;
+
+        Ok(unreadable_blocks)
+    }
+
+    /// Returns hashes of blocks, which header was stored from `from_offset` of the commit log,
+    /// just the primary index is read
+    pub fn block_hashes_stored_from(
+        &self,
+        from_offset: Offset,
+    ) -> Result<Vec<BlockHash>, StorageError> {
+        let _locations_guard = self.locations_guard()?;
+        let mut block_hashes = Vec::new();
+        for (block_hash, location) in self.primary_index.kv.iterator(IteratorMode::Start)? {
+            if location?.block_header.0 >= from_offset {
+                block_hashes.push(block_hash?);
+            }
+        }
+        Ok(block_hashes)
+    }
+
+    /// Offset of the next record appended to the commit log
+    pub fn commit_log_next_offset(&self) -> Result<Offset, StorageError> {
+        let _locations_guard = self.locations_guard()?;
+        self.clog.next_offset().map_err(StorageError::from)
+    }
+
+    /// Rewrites all records referenced from indexes to a new commit log, records not referenced anymore
+    /// (pruned blocks, overwritten json/additional data) are dropped.
+    ///
+    /// Live records are copied in segments without blocking other block storage operations,
+    /// `locations_lock` is held just while indexes are switched to the new locations
+    /// (records appended meanwhile are copied at that time).
+    /// Indexes are switched in one atomic batch together with the pending compaction flag,
+    /// so interrupted compaction can be finished by [recover_commit_log_compaction] on next start.
+    pub fn compact_commit_log(&self) -> Result<CommitLogCompactionStats, StorageError> {
+        let _compaction_guard = self.compaction_lock.lock().map_err(DBError::from)?;
+
+        // records bellow this offset are copied without lock, indexes referencing newer records are switched later
+        let copy_until = self.clog.next_offset()?;
+
+        // the same location can be referenced from all indexes, so copy it just once and keep commit log order
+        let mut live_locations = BTreeMap::new();
+        collect_live_locations(&*self.primary_index.kv, copy_until, &mut live_locations)?;
+        collect_live_locations(&*self.by_level_index.kv, copy_until, &mut live_locations)?;
+        collect_live_locations(
+            &*self.by_context_hash_index.kv,
+            copy_until,
+            &mut live_locations,
+        )?;
+        let live_locations: Vec<Location> = live_locations.values().copied().collect();
+
+        self.clog.start_compaction()?;
+        let mut relocations: HashMap<Offset, Location> =
+            HashMap::with_capacity(live_locations.len());
+        for segment in live_locations.chunks(Self::COMPACTION_SEGMENT_SIZE) {
+            match self.clog.prepare_compaction(segment) {
+                Ok(compacted_locations) => relocations.extend(
+                    segment
+                        .iter()
+                        .map(|location| location.0)
+                        .zip(compacted_locations),
+                ),
+                Err(e) => {
+                    self.clog.abort_compaction()?;
+                    return Err(e.into());
+                }
+            }
+        }
+        drop(live_locations);
+
+        let _locations_guard = self.locations_lock.write().map_err(DBError::from)?;
+        if let Err(e) = self.write_relocated_indexes(&mut relocations) {
+            // indexes still point to the original commit log
+            self.clog.abort_compaction()?;
+            return Err(e);
+        }
+
+        let reclaimed_bytes = self.clog.finish_compaction()?;
+        SystemStorage::new(self.db.clone()).clear_block_storage_compaction()?;
+
+        Ok(CommitLogCompactionStats {
+            live_records: relocations.len(),
+            reclaimed_bytes,
+        })
+    }
+
+    /// Switches all indexes to compacted locations at once and marks compaction as pending,
+    /// has to be called with `locations_lock` held for writing
+    fn write_relocated_indexes(
+        &self,
+        relocations: &mut HashMap<Offset, Location>,
+    ) -> Result<(), StorageError> {
+        let mut batch = SchemaWriteBatch::new(&self.db);
+        self.relocate_index(&*self.primary_index.kv, &mut batch, relocations)?;
+        self.relocate_index(&*self.by_level_index.kv, &mut batch, relocations)?;
+        self.relocate_index(&*self.by_context_hash_index.kv, &mut batch, relocations)?;
+        batch.put::<SystemStorage>(
+            &SystemStorage::BLOCK_STORAGE_COMPACTION.to_string(),
+            &SystemValue::Integer(relocations.len() as i64),
+        )?;
+        // records checked by the last storage recovery are moved to the beginning of compacted commit log
+        if let Some(checkpoint) =
+            SystemStorage::new(self.db.clone()).get_storage_recovery_checkpoint()?
+        {
+            let relocated_checkpoint = relocations
+                .iter()
+                .filter(|(offset, _)| **offset >= checkpoint)
+                .map(|(_, relocated)| relocated.0)
+                .min()
+                .unwrap_or(relocations.len() as Offset);
+            batch.put::<SystemStorage>(
+                &SystemStorage::STORAGE_RECOVERY_CHECKPOINT.to_string(),
+                &SystemValue::Integer(relocated_checkpoint as i64),
+            )?;
+        }
+        // compacted records must be on disk before indexes point to them
+        self.clog.sync_compaction()?;
+        // must be on disk before original commit log is removed
+        batch.write(true)?;
+        Ok(())
+    }
+
+    /// Adds index entries with compacted locations to the batch,
+    /// records not copied yet (appended during compaction) are copied now
+    fn relocate_index<S>(
+        &self,
+        kv: &(dyn KeyValueStoreWithSchema<S> + Sync + Send),
+        batch: &mut SchemaWriteBatch,
+        relocations: &mut HashMap<Offset, Location>,
+    ) -> Result<(), StorageError>
+    where
+        S: RocksDbKeyValueSchema<Value = BlockStorageColumnsLocation>,
+    {
+        for (key, location) in kv.iterator(IteratorMode::Start)? {
+            let relocated = location?.relocate(|location| {
+                if let Some(relocated) = relocations.get(&location.0) {
+                    return Ok(*relocated);
+                }
+                let relocated = self
+                    .clog
+                    .prepare_compaction(&[*location])?
+                    .pop()
+                    .ok_or(StorageError::MissingKey)?;
+                relocations.insert(location.0, relocated);
+                Ok(relocated)
+            })?;
+            batch.put::<S>(&key?, &relocated)?;
+        }
+        Ok(())
+    }
+
+    /// Finishes or rolls back commit log compaction interrupted by restart.
+    /// Should be called on startup before block storage is used.
+    pub fn recover_commit_log_compaction(&self) -> Result<(), StorageError> {
+        let _locations_guard = self.locations_lock.write().map_err(DBError::from)?;
+
+        let mut system_storage = SystemStorage::new(self.db.clone());
+        if system_storage.is_block_storage_compaction_pending()? {
+            // indexes were already switched, so compacted commit log has to be used
+            self.clog.finish_compaction()?;
+            system_storage.clear_block_storage_compaction()?;
+        } else {
+            self.clog.abort_compaction()?;
+        }
+        Ok(())
+    }
+
+    /// Guards validity of locations against compaction
+    #[inline]
+    pub(crate) fn locations_guard(&self) -> Result<RwLockReadGuard<()>, StorageError> {
+        Ok(self.locations_lock.read().map_err(DBError::from)?)
+    }
+
+    /// Level index holds just one block per level, so it can point to another block (fork)
+    #[inline]
+    fn is_indexed_by_level(
+        &self,
+        block_header: &BlockHeaderWithHash,
+        location: &BlockStorageColumnsLocation,
+    ) -> Result<bool, StorageError> {
+        Ok(self
+            .by_level_index
+            .get(block_header.header.level())?
+            .map(|indexed| indexed.block_header.0 == location.block_header.0)
+            .unwrap_or(false))
+    }
+
+    #[inline]
+    fn is_indexed_by_context_hash(
+        &self,
+        block_header: &BlockHeaderWithHash,
+        location: &BlockStorageColumnsLocation,
+    ) -> Result<bool, StorageError> {
+        Ok(self
+            .by_context_hash_index
+            .get(block_header.header.context())?
+            .map(|indexed| indexed.block_header.0 == location.block_header.0)
+            .unwrap_or(false))
+    }
+
+    #[inline]
+    fn get_block_header(
+        &self,
+        block_hash: &BlockHash,
+    ) -> Result<Option<BlockHeaderWithHash>, StorageError> {
+        self.primary_index
+            .get(block_hash)?
+            .map(|location| self.get_block_header_by_location(&location))
+            .transpose()
+    }
+
     #[inline]
     fn get_block_header_by_location(
         &self,
@@ -330,10 +777,8 @@ impl BlockStorage {
 impl BlockStorageReader for BlockStorage {
     #[inline]
     fn get(&self, block_hash: &BlockHash) -> Result<Option<BlockHeaderWithHash>, StorageError> {
-        self.primary_index
-            .get(block_hash)?
-            .map(|location| self.get_block_header_by_location(&location))
-            .transpose()
+        let _locations_guard = self.locations_guard()?;
+        self.get_block_header(block_hash)
     }
 
     #[inline]
@@ -349,6 +794,7 @@ impl BlockStorageReader for BlockStorage {
         &self,
         block_hash: &BlockHash,
     ) -> Result<Option<(BlockHeaderWithHash, BlockJsonData)>, StorageError> {
+        let _locations_guard = self.locations_guard()?;
         match self.primary_index.get(block_hash)? {
             Some(location) => self
                 .get_block_json_data_by_location(&location)?
@@ -366,6 +812,7 @@ impl BlockStorageReader for BlockStorage {
         &self,
         block_hash: &BlockHash,
     ) -> Result<Option<(BlockHeaderWithHash, BlockAdditionalData)>, StorageError> {
+        let _locations_guard = self.locations_guard()?;
         match self.primary_index.get(block_hash)? {
             Some(location) => self
                 .get_block_additional_data_by_location(&location)?
@@ -384,7 +831,8 @@ impl BlockStorageReader for BlockStorage {
         block_hash: &BlockHash,
         limit: usize,
     ) -> Result<Vec<(BlockHeaderWithHash, BlockJsonData)>, StorageError> {
-        let locations = self.get(block_hash)?.map_or_else(
+        let _locations_guard = self.locations_guard()?;
+        let locations = self.get_block_header(block_hash)?.map_or_else(
             || Ok(Vec::new()),
             |block| self.by_level_index.get_blocks(block.header.level(), limit),
         )?;
@@ -398,7 +846,8 @@ impl BlockStorageReader for BlockStorage {
         from_block_hash: &BlockHash,
         limit: usize,
     ) -> Result<Vec<(BlockHeaderWithHash, BlockJsonData)>, StorageError> {
-        let locations = self.get(from_block_hash)?.map_or_else(
+        let _locations_guard = self.locations_guard()?;
+        let locations = self.get_block_header(from_block_hash)?.map_or_else(
             || Ok(Vec::new()),
             |block| {
                 self.by_level_index
@@ -414,7 +863,8 @@ impl BlockStorageReader for BlockStorage {
         block_hash: &BlockHash,
         limit: usize,
     ) -> Result<Vec<BlockHeaderWithHash>, StorageError> {
-        self.get(block_hash)?
+        let _locations_guard = self.locations_guard()?;
+        self.get_block_header(block_hash)?
             .map_or_else(
                 || Ok(Vec::new()),
                 |block| {
@@ -435,6 +885,7 @@ impl BlockStorageReader for BlockStorage {
         &self,
         context_hash: &ContextHash,
     ) -> Result<Option<BlockHeaderWithHash>, StorageError> {
+        let _locations_guard = self.locations_guard()?;
         self.by_context_hash_index
             .get(context_hash)?
             .map(|location| self.get_block_header_by_location(&location))
@@ -481,6 +932,86 @@ pub struct BlockStorageColumnsLocation {
 
 impl BincodeEncoded for BlockStorageColumnsLocation {}
 
+impl BlockStorageColumnsLocation {
+    /// Returns all stored column locations
+    fn locations(&self) -> impl Iterator<Item = Location> {
+        std::iter::once(self.block_header)
+            .chain(self.block_json_data)
+            .chain(self.block_additional_data)
+    }
+
+    /// Returns locations moved by commit log compaction
+    fn relocate<F>(&self, mut relocated: F) -> Result<Self, StorageError>
+    where
+        F: FnMut(&Location) -> Result<Location, StorageError>,
+    {
+        Ok(Self {
+            block_header: relocated(&self.block_header)?,
+            block_json_data: self
+                .block_json_data
+                .as_ref()
+                .map(&mut relocated)
+                .transpose()?,
+            block_additional_data: self
+                .block_additional_data
+                .as_ref()
+                .map(&mut relocated)
+                .transpose()?,
+        })
+    }
+}
+
+/// Returns true, if the record is missing in the commit log or cannot be decoded,
+/// other errors (e.g. I/O) do not prove, that the record was lost
+fn is_record_lost(error: &StorageError) -> bool {
+    match error {
+        StorageError::InvalidColumn => true,
+        StorageError::CommitLogError { error } => matches!(
+            error,
+            CommitLogError::ReadError {
+                error: ReadError::CorruptLog,
+                ..
+            } | CommitLogError::ReadError {
+                error: ReadError::NoSuchSegment,
+                ..
+            } | CommitLogError::SchemaError { .. }
+        ),
+        _ => false,
+    }
+}
+
+/// Reads whole index, which stores [BlockStorageColumnsLocation] as values
+fn collect_index<S>(
+    kv: &(dyn KeyValueStoreWithSchema<S> + Sync + Send),
+) -> Result<Vec<(S::Key, BlockStorageColumnsLocation)>, StorageError>
+where
+    S: RocksDbKeyValueSchema<Value = BlockStorageColumnsLocation>,
+{
+    kv.iterator(IteratorMode::Start)?
+        .map(|(key, location)| key.and_then(|key| location.map(|location| (key, location))))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(StorageError::from)
+}
+
+/// Adds locations of records bellow `until` offset referenced from the index, index is read sequentially
+fn collect_live_locations<S>(
+    kv: &(dyn KeyValueStoreWithSchema<S> + Sync + Send),
+    until: Offset,
+    live_locations: &mut BTreeMap<Offset, Location>,
+) -> Result<(), StorageError>
+where
+    S: RocksDbKeyValueSchema<Value = BlockStorageColumnsLocation>,
+{
+    for (_, location) in kv.iterator(IteratorMode::Start)? {
+        for location in location?.locations() {
+            if location.0 < until {
+                live_locations.insert(location.0, location);
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Index block data as `block_header_hash -> location`.
 #[derive(Clone)]
 pub struct BlockPrimaryIndex {
@@ -518,6 +1049,11 @@ impl BlockPrimaryIndex {
         self.kv.contains(block_hash).map_err(StorageError::from)
     }
 
+    #[inline]
+    fn delete(&self, block_hash: &BlockHash) -> Result<(), StorageError> {
+        self.kv.delete(block_hash).map_err(StorageError::from)
+    }
+
     #[inline]
     fn iterator(&self) -> Result<IteratorWithSchema<Self>, StorageError> {
         self.kv
@@ -560,6 +1096,14 @@ impl BlockByLevelIndex {
         self.kv.put(&level, location).map_err(StorageError::from)
     }
 
+    fn get(&self, level: BlockLevel) -> Result<Option<BlockStorageColumnsLocation>, StorageError> {
+        self.kv.get(&level).map_err(StorageError::from)
+    }
+
+    fn delete(&self, level: BlockLevel) -> Result<(), StorageError> {
+        self.kv.delete(&level).map_err(StorageError::from)
+    }
+
     fn get_blocks(
         &self,
         from_level: BlockLevel,
@@ -585,6 +1129,25 @@ impl BlockByLevelIndex {
             .collect()
     }
 
+    fn get_blocks_in_range(
+        &self,
+        from_level: BlockLevel,
+        to_level: BlockLevel,
+        limit: usize,
+    ) -> Result<Vec<BlockStorageColumnsLocation>, StorageError> {
+        let mut locations = Vec::new();
+        for (level, location) in self
+            .kv
+            .iterator(IteratorMode::From(&from_level, Direction::Forward))?
+        {
+            if level? > to_level || locations.len() >= limit {
+                break;
+            }
+            locations.push(location?);
+        }
+        Ok(locations)
+    }
+
     fn get_blocks_by_nth_level(
         &self,
         every_nth: BlockLevel,
@@ -646,6 +1209,10 @@ impl BlockByContextHashIndex {
     fn contains(&self, context_hash: &ContextHash) -> Result<bool, StorageError> {
         self.kv.contains(context_hash).map_err(StorageError::from)
     }
+
+    fn delete(&self, context_hash: &ContextHash) -> Result<(), StorageError> {
+        self.kv.delete(context_hash).map_err(StorageError::from)
+    }
 }
 
 impl KeyValueSchema for BlockByContextHashIndex {
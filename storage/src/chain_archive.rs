@@ -0,0 +1,1196 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! # Chain data archive
+//!
+//! Bulk export of block headers, operations and block metadata for offline analytics.
+//! Blocks are taken from [BlockByLevelIndex] for a level range, the range can be split
+//! into parts exported in parallel, every part is written to its own file `chain_<from>_<to>.<ext>`.
+//!
+//! Two formats are supported:
+//!
+//! * JSON-lines (`jsonl`) - one [JsonLineBlock] per line, binary data (header, operations) are hex encoded
+//! * columnar (`tzcol`) - compact binary file, blocks are grouped to segments and every column
+//!   of the segment is zstd compressed separately, so readers can decode just the columns they need:
+//! ```no_compile
+//! [magic(8)][version(4)]
+//! [SEGMENT_TAG(1)][rows(4)][columns_count(1)]([column_len(4)][column(column_len)])*   - repeated
+//! [END_TAG(1)][rows_count(8)]
+//! ```
+//! Columns are bincode encoded vectors: chain ids, levels, headers ([BlockHeaderWithHash] encoding),
+//! operations ([OperationsForBlocksMessage] encoding per validation pass), json data and additional data.
+//!
+//! Part is written to `<file>.partial` and renamed once complete. The export plan (format, range and its parts)
+//! is stored to [MANIFEST_FILE] of the output directory, export started again with the same plan skips completed
+//! parts and resumes partial ones after the last complete record. Export with a different plan, or to a directory
+//! with archive files of other parts, is refused, so the directory never contains overlapping parts.
+//!
+//! The archive can be imported back with [import_chain_data], which rebuilds block storage,
+//! block/operations metadata, current head, caboose and genesis of the chain.
+//!
+//! [BlockByLevelIndex]: crate::block_storage::BlockByLevelIndex
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+use slog::{info, Logger};
+
+use crypto::base58::FromBase58CheckError;
+use crypto::hash::ChainId;
+use tezos_messages::p2p::binary_message::BinaryMessage;
+use tezos_messages::p2p::encoding::prelude::{BlockHeader, OperationsForBlocksMessage};
+use tezos_messages::Head;
+
+use crate::block_meta_storage::Meta;
+use crate::block_storage::BlockLevel;
+use crate::chain_meta_storage::ChainMetaStorageReader;
+use crate::persistent::{Decoder, Encoder, SchemaError};
+use crate::{
+    operations_meta_storage, store_block_header, BlockAdditionalData, BlockHeaderWithHash,
+    BlockJsonData, BlockMetaStorage, BlockStorage, ChainMetaStorage, OperationsMetaStorage,
+    OperationsStorage, OperationsStorageReader, PersistentStorage, StorageError,
+};
+
+pub const COLUMNAR_MAGIC: [u8; 8] = *b"TZDGCHAR";
+pub const COLUMNAR_VERSION: u32 = 1;
+
+const COLUMNAR_HEADER_LEN: u64 = 12;
+const SEGMENT_TAG: u8 = 1;
+const END_TAG: u8 = 0;
+const COLUMNS_COUNT: u8 = 6;
+const ZSTD_COMPRESSION_LEVEL: i32 = 3;
+
+const PARTIAL_SUFFIX: &str = "partial";
+
+/// File of the output directory with the [ExportPlan] of the archive
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// How many blocks are read from the storage at once
+const EXPORT_BATCH_SIZE: usize = 256;
+
+#[derive(Debug, Fail)]
+pub enum ChainArchiveError {
+    #[fail(display = "Archive I/O error: {}", error)]
+    IOError { error: io::Error },
+    #[fail(display = "Storage error: {}", error)]
+    StorageError { error: StorageError },
+    #[fail(display = "Schema error: {}", error)]
+    SchemaError { error: SchemaError },
+    #[fail(display = "Serialization error: {}", error)]
+    SerializationError { error: bincode::Error },
+    #[fail(display = "Json serialization error: {}", error)]
+    JsonError { error: serde_json::Error },
+    #[fail(display = "Invalid archive {:?}, reason: {}", path, reason)]
+    InvalidArchive { path: PathBuf, reason: String },
+    #[fail(display = "Unsupported archive version: {}", version)]
+    UnsupportedVersion { version: u32 },
+    #[fail(
+        display = "Block {} cannot be exported, reason: {}",
+        block_hash, reason
+    )]
+    BlockNotExportable { block_hash: String, reason: String },
+    #[fail(display = "Export thread failed, reason: {}", reason)]
+    ThreadError { reason: String },
+    #[fail(
+        display = "Export to {:?} does not match the archive in the directory, reason: {}",
+        output_dir, reason
+    )]
+    ExportPlanMismatch { output_dir: PathBuf, reason: String },
+}
+
+impl From<io::Error> for ChainArchiveError {
+    fn from(error: io::Error) -> Self {
+        ChainArchiveError::IOError { error }
+    }
+}
+
+impl From<StorageError> for ChainArchiveError {
+    fn from(error: StorageError) -> Self {
+        ChainArchiveError::StorageError { error }
+    }
+}
+
+impl From<SchemaError> for ChainArchiveError {
+    fn from(error: SchemaError) -> Self {
+        ChainArchiveError::SchemaError { error }
+    }
+}
+
+impl From<bincode::Error> for ChainArchiveError {
+    fn from(error: bincode::Error) -> Self {
+        ChainArchiveError::SerializationError { error }
+    }
+}
+
+impl From<serde_json::Error> for ChainArchiveError {
+    fn from(error: serde_json::Error) -> Self {
+        ChainArchiveError::JsonError { error }
+    }
+}
+
+impl From<FromBase58CheckError> for ChainArchiveError {
+    fn from(error: FromBase58CheckError) -> Self {
+        ChainArchiveError::StorageError {
+            error: error.into(),
+        }
+    }
+}
+
+/// Format of the archive files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    JsonLines,
+    Columnar,
+}
+
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::JsonLines => "jsonl",
+            ArchiveFormat::Columnar => "tzcol",
+        }
+    }
+
+    fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "jsonl" => Some(ArchiveFormat::JsonLines),
+            "tzcol" => Some(ArchiveFormat::Columnar),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "jsonl" | "json-lines" => Ok(ArchiveFormat::JsonLines),
+            "columnar" | "tzcol" => Ok(ArchiveFormat::Columnar),
+            _ => Err(format!("Invalid archive format: {}", s)),
+        }
+    }
+}
+
+/// One exported block
+#[derive(Debug, Clone)]
+pub struct ArchivedBlock {
+    pub chain_id: ChainId,
+    pub header: BlockHeaderWithHash,
+    /// Operations of the block, one message per stored validation pass
+    pub operations: Vec<OperationsForBlocksMessage>,
+    pub json_data: Option<BlockJsonData>,
+    /// Stored just for applied blocks
+    pub additional_data: Option<BlockAdditionalData>,
+}
+
+impl ArchivedBlock {
+    #[inline]
+    pub fn level(&self) -> BlockLevel {
+        self.header.header.level()
+    }
+}
+
+/// Line of the JSON-lines archive
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JsonLineBlock {
+    pub chain_id: String,
+    pub level: BlockLevel,
+    pub hash: String,
+    pub predecessor: String,
+    pub timestamp: i64,
+    /// Hex encoded binary block header
+    pub header: String,
+    /// Hex encoded binary operations, one item per validation pass
+    pub operations: Vec<String>,
+    pub json_data: Option<BlockJsonData>,
+    pub additional_data: Option<BlockAdditionalData>,
+}
+
+impl JsonLineBlock {
+    fn new(block: &ArchivedBlock) -> Result<Self, ChainArchiveError> {
+        Ok(Self {
+            chain_id: block.chain_id.to_base58_check(),
+            level: block.level(),
+            hash: block.header.hash.to_base58_check(),
+            predecessor: block.header.header.predecessor().to_base58_check(),
+            timestamp: block.header.header.timestamp(),
+            header: hex::encode(
+                block
+                    .header
+                    .header
+                    .as_bytes()
+                    .map_err(|_| SchemaError::EncodeError)?,
+            ),
+            operations: block
+                .operations
+                .iter()
+                .map(|operations| operations.encode().map(hex::encode))
+                .collect::<Result<_, _>>()?,
+            json_data: block.json_data.clone(),
+            additional_data: block.additional_data.clone(),
+        })
+    }
+
+    fn into_block(self) -> Result<ArchivedBlock, ChainArchiveError> {
+        let header_bytes = hex::decode(&self.header).map_err(|_| SchemaError::DecodeError)?;
+        let header = BlockHeader::from_bytes(header_bytes).map_err(|_| SchemaError::DecodeError)?;
+        let header = BlockHeaderWithHash::new(header).map_err(StorageError::from)?;
+        if header.hash.to_base58_check() != self.hash || header.header.level() != self.level {
+            return Err(SchemaError::DecodeError.into());
+        }
+
+        let operations = self
+            .operations
+            .iter()
+            .map(|operations| {
+                hex::decode(operations)
+                    .map_err(|_| SchemaError::DecodeError)
+                    .and_then(|bytes| OperationsForBlocksMessage::decode(&bytes))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(ArchivedBlock {
+            chain_id: ChainId::from_base58_check(&self.chain_id)?,
+            header,
+            operations,
+            json_data: self.json_data,
+            additional_data: self.additional_data,
+        })
+    }
+}
+
+/// Configuration of the chain data export
+#[derive(Debug, Clone)]
+pub struct ExportConfig {
+    pub format: ArchiveFormat,
+    pub output_dir: PathBuf,
+    pub from_level: BlockLevel,
+    pub to_level: BlockLevel,
+    /// Level range is split to this count of parts exported in parallel
+    pub parallelism: usize,
+    /// Count of blocks in one segment of the columnar file
+    pub segment_size: usize,
+}
+
+impl ExportConfig {
+    pub const DEFAULT_SEGMENT_SIZE: usize = 1024;
+
+    /// Splits level range to (at most) `parallelism` continuous parts
+    pub fn parts(&self) -> Vec<(BlockLevel, BlockLevel)> {
+        if self.from_level > self.to_level {
+            return vec![];
+        }
+        let levels = (self.to_level - self.from_level) as i64 + 1;
+        let parts = self.parallelism.max(1) as i64;
+        let part_size = (levels + parts - 1) / parts;
+
+        let mut result = vec![];
+        let mut from = self.from_level as i64;
+        while from <= self.to_level as i64 {
+            let to = (from + part_size - 1).min(self.to_level as i64);
+            result.push((from as BlockLevel, to as BlockLevel));
+            from = to + 1;
+        }
+        result
+    }
+
+    /// Path of the complete file of the part
+    pub fn part_path(&self, from_level: BlockLevel, to_level: BlockLevel) -> PathBuf {
+        self.output_dir.join(format!(
+            "chain_{:010}_{:010}.{}",
+            from_level,
+            to_level,
+            self.format.extension()
+        ))
+    }
+}
+
+/// Plan of the export stored in the [MANIFEST_FILE], resumed export has to have the same plan
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExportPlan {
+    /// Extension of the archive files
+    pub format: String,
+    pub from_level: BlockLevel,
+    pub to_level: BlockLevel,
+    pub parallelism: usize,
+    pub parts: Vec<(BlockLevel, BlockLevel)>,
+}
+
+impl ExportPlan {
+    fn new(config: &ExportConfig) -> Self {
+        Self {
+            format: config.format.extension().to_string(),
+            from_level: config.from_level,
+            to_level: config.to_level,
+            parallelism: config.parallelism,
+            parts: config.parts(),
+        }
+    }
+
+    /// Stores the plan to the [MANIFEST_FILE], or checks, that the stored one is the same.
+    ///
+    /// Any archive file of the directory (complete or partial), which is not a part of the plan, is refused too,
+    /// because it would overlap with parts of the plan.
+    fn store_or_validate(&self, config: &ExportConfig) -> Result<(), ChainArchiveError> {
+        let mismatch = |reason: String| ChainArchiveError::ExportPlanMismatch {
+            output_dir: config.output_dir.clone(),
+            reason,
+        };
+
+        let manifest_path = config.output_dir.join(MANIFEST_FILE);
+        if manifest_path.exists() {
+            let stored: ExportPlan = serde_json::from_slice(&fs::read(&manifest_path)?)?;
+            if &stored != self {
+                return Err(mismatch(format!(
+                    "archive was exported with plan {:?}, use empty output directory for a different plan",
+                    stored
+                )));
+            }
+        }
+
+        let part_files = self
+            .parts
+            .iter()
+            .map(|(from_level, to_level)| config.part_path(*from_level, *to_level))
+            .collect::<Vec<_>>();
+        for entry in fs::read_dir(&config.output_dir)? {
+            let path = entry?.path();
+            let complete_path = if is_partial(&path) {
+                path.with_extension("")
+            } else {
+                path.clone()
+            };
+            let is_archive = complete_path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .and_then(ArchiveFormat::from_extension)
+                .is_some();
+            if is_archive && !part_files.contains(&complete_path) {
+                return Err(mismatch(format!(
+                    "file {:?} is not a part of the export",
+                    path
+                )));
+            }
+        }
+
+        if !manifest_path.exists() {
+            let tmp_path = partial_path(&manifest_path);
+            fs::write(&tmp_path, serde_json::to_vec_pretty(self)?)?;
+            fs::rename(&tmp_path, &manifest_path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Summary of the chain data export
+#[derive(Debug, Clone, Default)]
+pub struct ExportStats {
+    /// Count of blocks written by this run
+    pub exported_blocks: usize,
+    /// Count of blocks found in partial files from previous run
+    pub resumed_blocks: usize,
+    /// Count of parts completed by previous run
+    pub skipped_parts: usize,
+    pub files: Vec<PathBuf>,
+}
+
+/// Summary of the chain data import
+#[derive(Debug, Clone, Default)]
+pub struct ImportStats {
+    pub imported_blocks: usize,
+    pub applied_blocks: usize,
+    /// Current head of the chain after import, if changed
+    pub head: Option<Head>,
+    /// Caboose (the lowest stored block) of the chain after import, if changed
+    pub caboose: Option<Head>,
+    /// Genesis of the chain, if it was imported and not yet known
+    pub genesis: Option<Head>,
+}
+
+/// Exports blocks of level range to archive files, parts of the range are exported in parallel
+pub fn export_chain_data(
+    persistent_storage: &PersistentStorage,
+    config: &ExportConfig,
+    log: &Logger,
+) -> Result<ExportStats, ChainArchiveError> {
+    fs::create_dir_all(&config.output_dir)?;
+
+    info!(log, "Exporting chain data";
+               "from_level" => config.from_level,
+               "to_level" => config.to_level,
+               "format" => config.format.extension(),
+               "output_dir" => format!("{:?}", config.output_dir));
+
+    ExportPlan::new(config).store_or_validate(config)?;
+
+    let mut stats = ExportStats::default();
+    let mut handles = vec![];
+    for (from_level, to_level) in config.parts() {
+        let path = config.part_path(from_level, to_level);
+        stats.files.push(path.clone());
+        if path.exists() {
+            stats.skipped_parts += 1;
+            continue;
+        }
+
+        let persistent_storage = persistent_storage.clone();
+        let config = config.clone();
+        let log = log.clone();
+        let handle = thread::Builder::new()
+            .name(format!("chain-export-{}", from_level))
+            .spawn(move || {
+                export_part(
+                    &persistent_storage,
+                    &config,
+                    from_level,
+                    to_level,
+                    &path,
+                    &log,
+                )
+            })?;
+        handles.push(handle);
+    }
+
+    for handle in handles {
+        let (exported_blocks, resumed_blocks) =
+            handle.join().map_err(|e| ChainArchiveError::ThreadError {
+                reason: format!("{:?}", e),
+            })??;
+        stats.exported_blocks += exported_blocks;
+        stats.resumed_blocks += resumed_blocks;
+    }
+
+    info!(log, "Chain data exported";
+               "exported_blocks" => stats.exported_blocks,
+               "resumed_blocks" => stats.resumed_blocks,
+               "skipped_parts" => stats.skipped_parts);
+    Ok(stats)
+}
+
+/// Exports one part of the level range, returns count of (exported, resumed) blocks
+fn export_part(
+    persistent_storage: &PersistentStorage,
+    config: &ExportConfig,
+    from_level: BlockLevel,
+    to_level: BlockLevel,
+    path: &Path,
+    log: &Logger,
+) -> Result<(usize, usize), ChainArchiveError> {
+    let block_storage = BlockStorage::new(persistent_storage);
+    let block_meta_storage = BlockMetaStorage::new(persistent_storage);
+    let operations_storage = OperationsStorage::new(persistent_storage);
+
+    let partial_path = partial_path(path);
+    let mut writer = ArchiveWriter::open(config.format, &partial_path, config.segment_size)?;
+    let resumed_blocks = writer.resumed_blocks();
+    let mut next_level = match writer.last_level() {
+        Some(last_level) => {
+            info!(log, "Resuming chain data export";
+                       "file" => format!("{:?}", partial_path),
+                       "last_level" => last_level,
+                       "resumed_blocks" => resumed_blocks);
+            last_level + 1
+        }
+        None => from_level,
+    };
+
+    let mut exported_blocks = 0;
+    while next_level <= to_level {
+        let blocks = block_storage.get_by_level_range(next_level, to_level, EXPORT_BATCH_SIZE)?;
+        let last_level = match blocks.last() {
+            Some((header, _, _)) => header.header.level(),
+            None => break,
+        };
+
+        for (header, json_data, additional_data) in blocks {
+            let chain_id = match block_meta_storage.get(&header.hash)? {
+                Some(meta) => meta.chain_id().clone(),
+                None => {
+                    return Err(ChainArchiveError::BlockNotExportable {
+                        block_hash: header.hash.to_base58_check(),
+                        reason: "missing block metadata".to_string(),
+                    })
+                }
+            };
+            let operations = operations_storage.get_operations(&header.hash)?;
+            writer.write(ArchivedBlock {
+                chain_id,
+                header,
+                operations,
+                json_data,
+                additional_data,
+            })?;
+            exported_blocks += 1;
+        }
+        next_level = last_level + 1;
+    }
+
+    writer.finish()?;
+    fs::rename(&partial_path, path)?;
+    Ok((exported_blocks, resumed_blocks))
+}
+
+/// Returns complete archive files of the directory ordered by level range
+pub fn archive_files<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>, ChainArchiveError> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_archive = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(ArchiveFormat::from_extension)
+            .is_some();
+        if path.is_file() && is_archive {
+            files.push(path);
+        }
+    }
+    // levels in the file names are zero padded
+    files.sort();
+    Ok(files)
+}
+
+/// Imports blocks from archive files into the storage, files should be ordered by level range,
+/// so predecessors are imported before their successors.
+///
+/// Import is idempotent, already stored blocks are just updated.
+pub fn import_chain_data(
+    persistent_storage: &PersistentStorage,
+    files: &[PathBuf],
+    log: &Logger,
+) -> Result<ImportStats, ChainArchiveError> {
+    let block_storage = BlockStorage::new(persistent_storage);
+    let block_meta_storage = BlockMetaStorage::new(persistent_storage);
+    let operations_storage = OperationsStorage::new(persistent_storage);
+    let operations_meta_storage = OperationsMetaStorage::new(persistent_storage);
+    let chain_meta_storage = ChainMetaStorage::new(persistent_storage);
+
+    let mut stats = ImportStats::default();
+    let mut head: Option<(ChainId, Head)> = None;
+    let mut lowest: Option<(ChainId, Head)> = None;
+    let mut genesis: Option<(ChainId, Head)> = None;
+    for file in files {
+        let format = ArchiveFormat::from_extension(
+            file.extension()
+                .and_then(|extension| extension.to_str())
+                .unwrap_or(""),
+        )
+        .ok_or_else(|| ChainArchiveError::InvalidArchive {
+            path: file.clone(),
+            reason: "unknown file extension".to_string(),
+        })?;
+
+        info!(log, "Importing chain data"; "file" => format!("{:?}", file));
+        read_archive(format, file, false, |block| {
+            let is_applied = import_block(
+                &block,
+                &block_storage,
+                &block_meta_storage,
+                &operations_storage,
+                &operations_meta_storage,
+                log,
+            )?;
+            stats.imported_blocks += 1;
+            let is_lower = lowest
+                .as_ref()
+                .map_or(true, |(_, lowest)| block.level() < *lowest.level());
+            if is_lower {
+                lowest = Some((block.chain_id.clone(), to_head(&block)));
+            }
+            if block.level() == Meta::GENESIS_LEVEL {
+                genesis = Some((block.chain_id.clone(), to_head(&block)));
+            }
+            if is_applied {
+                stats.applied_blocks += 1;
+                let is_higher = head
+                    .as_ref()
+                    .map_or(true, |(_, head)| block.level() > *head.level());
+                if is_higher {
+                    head = Some((block.chain_id.clone(), to_head(&block)));
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    if let Some((chain_id, head)) = head {
+        let current_head = chain_meta_storage.get_current_head(&chain_id)?;
+        if current_head.map_or(true, |current_head| head.level() > current_head.level()) {
+            chain_meta_storage.set_current_head(&chain_id, head.clone())?;
+            stats.head = Some(head);
+        }
+    }
+    if let Some((chain_id, genesis)) = genesis {
+        if chain_meta_storage.get_genesis(&chain_id)?.is_none() {
+            chain_meta_storage.set_genesis(&chain_id, genesis.clone())?;
+            stats.genesis = Some(genesis);
+        }
+    }
+    // caboose is the lowest block with stored data, so it is lowered only
+    if let Some((chain_id, lowest)) = lowest {
+        let caboose = chain_meta_storage.get_caboose(&chain_id)?;
+        if caboose.map_or(true, |caboose| lowest.level() < caboose.level()) {
+            chain_meta_storage.set_caboose(&chain_id, lowest.clone())?;
+            stats.caboose = Some(lowest);
+        }
+    }
+
+    info!(log, "Chain data imported";
+               "imported_blocks" => stats.imported_blocks,
+               "applied_blocks" => stats.applied_blocks);
+    Ok(stats)
+}
+
+fn to_head(block: &ArchivedBlock) -> Head {
+    Head::new(
+        block.header.hash.clone(),
+        block.level(),
+        block.header.header.fitness().clone(),
+    )
+}
+
+/// Stores block with its operations and metadata, returns true, if the block was applied
+fn import_block(
+    block: &ArchivedBlock,
+    block_storage: &BlockStorage,
+    block_meta_storage: &BlockMetaStorage,
+    operations_storage: &OperationsStorage,
+    operations_meta_storage: &OperationsMetaStorage,
+    log: &Logger,
+) -> Result<bool, ChainArchiveError> {
+    let block_hash = &block.header.hash;
+
+    if block.level() == Meta::GENESIS_LEVEL {
+        // predecessor of the genesis is genesis itself, so it cannot be linked as usual (see initialize_storage_with_genesis_block)
+        let is_applied = block.json_data.is_some();
+        block_storage.put_block_header(&block.header)?;
+        block_meta_storage.put(
+            block_hash,
+            &Meta::genesis_meta(block_hash, &block.chain_id, is_applied),
+        )?;
+        operations_meta_storage.put(
+            block_hash,
+            &operations_meta_storage::Meta::genesis_meta(&block.chain_id),
+        )?;
+        if let Some(json_data) = &block.json_data {
+            block_storage.put_block_json_data(block_hash, json_data.clone())?;
+            block_storage.assign_to_context(block_hash, block.header.header.context())?;
+        }
+        return Ok(is_applied);
+    }
+
+    store_block_header(
+        block_storage,
+        block_meta_storage,
+        operations_meta_storage,
+        &block.header,
+        &block.chain_id,
+        log,
+    )?;
+    for operations in &block.operations {
+        operations_storage.put_operations(operations)?;
+        operations_meta_storage.put_operations(operations)?;
+    }
+    if let Some(json_data) = &block.json_data {
+        block_storage.put_block_json_data(block_hash, json_data.clone())?;
+    }
+
+    // additional data are stored only for applied blocks
+    match &block.additional_data {
+        Some(additional_data) => {
+            block_storage.put_block_additional_data(block_hash, additional_data.clone())?;
+            block_storage.assign_to_context(block_hash, block.header.header.context())?;
+
+            let mut meta = block_meta_storage
+                .get(block_hash)?
+                .ok_or(StorageError::MissingKey)?;
+            meta.set_is_applied(true);
+            block_meta_storage.put(block_hash, &meta)?;
+            block_meta_storage.store_predecessors(block_hash, &meta)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Summary of the read archive file
+#[derive(Debug, Default)]
+struct ReadSummary {
+    /// Position after the last complete record
+    valid_len: u64,
+    blocks: usize,
+    last_level: Option<BlockLevel>,
+}
+
+/// Reads all blocks of the archive file, `on_block` is called for every block in the file order.
+///
+/// If `allow_truncated` is true, reading stops at the first incomplete or invalid record
+/// (partial file of the interrupted export), otherwise it is an error.
+fn read_archive<F>(
+    format: ArchiveFormat,
+    path: &Path,
+    allow_truncated: bool,
+    mut on_block: F,
+) -> Result<ReadSummary, ChainArchiveError>
+where
+    F: FnMut(ArchivedBlock) -> Result<(), ChainArchiveError>,
+{
+    let invalid = |reason: String| ChainArchiveError::InvalidArchive {
+        path: path.to_path_buf(),
+        reason,
+    };
+
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut summary = ReadSummary::default();
+    let mut on_block = |block: ArchivedBlock, summary: &mut ReadSummary, valid_len: u64| {
+        summary.valid_len = valid_len;
+        summary.blocks += 1;
+        summary.last_level = Some(block.level());
+        on_block(block)
+    };
+
+    match format {
+        ArchiveFormat::JsonLines => {
+            let mut line = vec![];
+            loop {
+                line.clear();
+                let read = reader.read_until(b'\n', &mut line)?;
+                if read == 0 {
+                    break;
+                }
+                let block = match line.last() {
+                    Some(b'\n') => serde_json::from_slice::<JsonLineBlock>(&line)
+                        .map_err(ChainArchiveError::from)
+                        .and_then(JsonLineBlock::into_block),
+                    _ => Err(invalid("incomplete last line".to_string())),
+                };
+                match block {
+                    Ok(block) => {
+                        let valid_len = summary.valid_len + read as u64;
+                        on_block(block, &mut summary, valid_len)?
+                    }
+                    Err(_) if allow_truncated => break,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        ArchiveFormat::Columnar => {
+            match read_columnar_header(&mut reader) {
+                Ok(()) => summary.valid_len = COLUMNAR_HEADER_LEN,
+                Err(_) if allow_truncated => return Ok(summary),
+                Err(e) => return Err(e),
+            }
+            loop {
+                let mut tag = [0u8; 1];
+                match reader.read_exact(&mut tag) {
+                    Ok(()) => (),
+                    Err(_) if allow_truncated => break,
+                    Err(_) => return Err(invalid("missing end of the archive".to_string())),
+                }
+                match tag[0] {
+                    SEGMENT_TAG => {
+                        let segment = match read_segment(&mut reader) {
+                            Ok(segment) => segment,
+                            Err(_) if allow_truncated => break,
+                            Err(e) => return Err(e),
+                        };
+                        let valid_len = reader.seek(SeekFrom::Current(0))?;
+                        for block in segment {
+                            on_block(block, &mut summary, valid_len)?;
+                        }
+                    }
+                    END_TAG => {
+                        let rows_count = read_u64(&mut reader)?;
+                        if rows_count != summary.blocks as u64 {
+                            return Err(invalid(format!(
+                                "expected {} blocks, but found {}",
+                                rows_count, summary.blocks
+                            )));
+                        }
+                        break;
+                    }
+                    _ if allow_truncated => break,
+                    tag => return Err(invalid(format!("unexpected tag: {}", tag))),
+                }
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Reads all blocks of the complete archive file
+pub fn read_archive_file<P: AsRef<Path>>(path: P) -> Result<Vec<ArchivedBlock>, ChainArchiveError> {
+    let path = path.as_ref();
+    let format = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(ArchiveFormat::from_extension)
+        .ok_or_else(|| ChainArchiveError::InvalidArchive {
+            path: path.to_path_buf(),
+            reason: "unknown file extension".to_string(),
+        })?;
+
+    let mut blocks = vec![];
+    read_archive(format, path, false, |block| {
+        blocks.push(block);
+        Ok(())
+    })?;
+    Ok(blocks)
+}
+
+fn read_columnar_header<R: Read>(reader: &mut R) -> Result<(), ChainArchiveError> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if magic != COLUMNAR_MAGIC {
+        return Err(SchemaError::DecodeError.into());
+    }
+    let version = read_u32(reader)?;
+    if version != COLUMNAR_VERSION {
+        return Err(ChainArchiveError::UnsupportedVersion { version });
+    }
+    Ok(())
+}
+
+fn read_segment<R: Read>(reader: &mut R) -> Result<Vec<ArchivedBlock>, ChainArchiveError> {
+    let rows = read_u32(reader)? as usize;
+    let mut columns_count = [0u8; 1];
+    reader.read_exact(&mut columns_count)?;
+    if columns_count[0] != COLUMNS_COUNT {
+        return Err(SchemaError::DecodeError.into());
+    }
+
+    let mut columns = Vec::with_capacity(COLUMNS_COUNT as usize);
+    for _ in 0..COLUMNS_COUNT {
+        let column_len = read_u32(reader)? as usize;
+        let mut compressed = vec![0u8; column_len];
+        reader.read_exact(&mut compressed)?;
+        columns.push(zstd::decode_all(compressed.as_slice())?);
+    }
+
+    let chain_ids: Vec<ChainId> = bincode::deserialize(&columns[0])?;
+    let levels: Vec<BlockLevel> = bincode::deserialize(&columns[1])?;
+    let headers: Vec<Vec<u8>> = bincode::deserialize(&columns[2])?;
+    let operations: Vec<Vec<Vec<u8>>> = bincode::deserialize(&columns[3])?;
+    let json_data: Vec<Option<BlockJsonData>> = bincode::deserialize(&columns[4])?;
+    let additional_data: Vec<Option<BlockAdditionalData>> = bincode::deserialize(&columns[5])?;
+    if [
+        chain_ids.len(),
+        levels.len(),
+        headers.len(),
+        operations.len(),
+        json_data.len(),
+        additional_data.len(),
+    ]
+    .iter()
+    .any(|len| *len != rows)
+    {
+        return Err(SchemaError::DecodeError.into());
+    }
+
+    chain_ids
+        .into_iter()
+        .zip(levels)
+        .zip(headers)
+        .zip(operations)
+        .zip(json_data)
+        .zip(additional_data)
+        .map(
+            |(((((chain_id, level), header), operations), json_data), additional_data)| {
+                let header = BlockHeaderWithHash::decode(&header)?;
+                // levels column is redundant, readers of just this column have to get the same levels
+                if header.header.level() != level {
+                    return Err(SchemaError::DecodeError.into());
+                }
+                Ok(ArchivedBlock {
+                    chain_id,
+                    header,
+                    operations: operations
+                        .iter()
+                        .map(|operations| OperationsForBlocksMessage::decode(operations))
+                        .collect::<Result<_, _>>()?,
+                    json_data,
+                    additional_data,
+                })
+            },
+        )
+        .collect()
+}
+
+/// Writer of one archive file, which can continue with partial file of the interrupted export
+struct ArchiveWriter {
+    format: ArchiveFormat,
+    writer: BufWriter<File>,
+    /// Blocks of not yet written segment (columnar format)
+    segment: Vec<ArchivedBlock>,
+    segment_size: usize,
+    rows_count: u64,
+    resumed_blocks: usize,
+    last_level: Option<BlockLevel>,
+}
+
+impl ArchiveWriter {
+    fn open(
+        format: ArchiveFormat,
+        path: &Path,
+        segment_size: usize,
+    ) -> Result<Self, ChainArchiveError> {
+        let summary = if path.exists() {
+            read_archive(format, path, true, |_| Ok(()))?
+        } else {
+            ReadSummary::default()
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .open(path)?;
+        // drop incomplete record of the interrupted export
+        file.set_len(summary.valid_len)?;
+        file.seek(SeekFrom::End(0))?;
+
+        let mut writer = Self {
+            format,
+            writer: BufWriter::new(file),
+            segment: Vec::with_capacity(segment_size),
+            segment_size: segment_size.max(1),
+            rows_count: summary.blocks as u64,
+            resumed_blocks: summary.blocks,
+            last_level: summary.last_level,
+        };
+        if format == ArchiveFormat::Columnar && summary.valid_len == 0 {
+            writer.writer.write_all(&COLUMNAR_MAGIC)?;
+            writer.writer.write_all(&COLUMNAR_VERSION.to_be_bytes())?;
+        }
+        Ok(writer)
+    }
+
+    fn resumed_blocks(&self) -> usize {
+        self.resumed_blocks
+    }
+
+    fn last_level(&self) -> Option<BlockLevel> {
+        self.last_level
+    }
+
+    fn write(&mut self, block: ArchivedBlock) -> Result<(), ChainArchiveError> {
+        match self.format {
+            ArchiveFormat::JsonLines => {
+                serde_json::to_writer(&mut self.writer, &JsonLineBlock::new(&block)?)?;
+                self.writer.write_all(b"\n")?;
+                self.rows_count += 1;
+            }
+            ArchiveFormat::Columnar => {
+                self.segment.push(block);
+                if self.segment.len() >= self.segment_size {
+                    self.write_segment()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_segment(&mut self) -> Result<(), ChainArchiveError> {
+        let segment = std::mem::take(&mut self.segment);
+        let columns = [
+            bincode::serialize(&segment.iter().map(|b| &b.chain_id).collect::<Vec<_>>())?,
+            bincode::serialize(&segment.iter().map(|b| b.level()).collect::<Vec<_>>())?,
+            bincode::serialize(
+                &segment
+                    .iter()
+                    .map(|b| b.header.encode())
+                    .collect::<Result<Vec<_>, _>>()?,
+            )?,
+            bincode::serialize(
+                &segment
+                    .iter()
+                    .map(|b| {
+                        b.operations
+                            .iter()
+                            .map(|o| o.encode())
+                            .collect::<Result<Vec<_>, _>>()
+                    })
+                    .collect::<Result<Vec<Vec<_>>, _>>()?,
+            )?,
+            bincode::serialize(&segment.iter().map(|b| &b.json_data).collect::<Vec<_>>())?,
+            bincode::serialize(
+                &segment
+                    .iter()
+                    .map(|b| &b.additional_data)
+                    .collect::<Vec<_>>(),
+            )?,
+        ];
+
+        self.writer.write_all(&[SEGMENT_TAG])?;
+        self.writer
+            .write_all(&(segment.len() as u32).to_be_bytes())?;
+        self.writer.write_all(&[COLUMNS_COUNT])?;
+        for column in columns.iter() {
+            let compressed = zstd::encode_all(column.as_slice(), ZSTD_COMPRESSION_LEVEL)?;
+            self.writer
+                .write_all(&(compressed.len() as u32).to_be_bytes())?;
+            self.writer.write_all(&compressed)?;
+        }
+        // segment has to be on disk, so the export can be resumed after it
+        self.writer.flush()?;
+        self.rows_count += segment.len() as u64;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), ChainArchiveError> {
+        if self.format == ArchiveFormat::Columnar {
+            if !self.segment.is_empty() {
+                self.write_segment()?;
+            }
+            self.writer.write_all(&[END_TAG])?;
+            self.writer.write_all(&self.rows_count.to_be_bytes())?;
+        }
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+        Ok(())
+    }
+}
+
+fn partial_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(PARTIAL_SUFFIX);
+    path.with_file_name(file_name)
+}
+
+fn is_partial(path: &Path) -> bool {
+    path.extension()
+        .map_or(false, |extension| extension == PARTIAL_SUFFIX)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, ChainArchiveError> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64, ChainArchiveError> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+    use std::env;
+
+    use failure::Error;
+
+    use tezos_messages::p2p::encoding::prelude::BlockHeaderBuilder;
+
+    use super::*;
+
+    fn config(from_level: BlockLevel, to_level: BlockLevel, parallelism: usize) -> ExportConfig {
+        ExportConfig {
+            format: ArchiveFormat::JsonLines,
+            output_dir: PathBuf::from("/tmp"),
+            from_level,
+            to_level,
+            parallelism,
+            segment_size: ExportConfig::DEFAULT_SEGMENT_SIZE,
+        }
+    }
+
+    #[test]
+    fn test_export_parts() {
+        assert_eq!(vec![(0, 9)], config(0, 9, 1).parts());
+        assert_eq!(vec![(0, 3), (4, 7), (8, 9)], config(0, 9, 3).parts());
+        assert_eq!(vec![(5, 5)], config(5, 5, 4).parts());
+        assert_eq!(vec![(1, 1), (2, 2), (3, 3)], config(1, 3, 10).parts());
+        assert!(config(5, 4, 2).parts().is_empty());
+    }
+
+    #[test]
+    fn test_part_path() {
+        let config = config(0, 9, 1);
+        assert_eq!(
+            PathBuf::from("/tmp/chain_0000000000_0000000009.jsonl"),
+            config.part_path(0, 9)
+        );
+        assert_eq!(
+            PathBuf::from("/tmp/chain_0000000000_0000000009.jsonl.partial"),
+            partial_path(&config.part_path(0, 9))
+        );
+    }
+
+    #[test]
+    fn test_archive_format_from_str() {
+        assert_eq!(
+            Ok(ArchiveFormat::JsonLines),
+            "jsonl".parse::<ArchiveFormat>()
+        );
+        assert_eq!(
+            Ok(ArchiveFormat::Columnar),
+            "columnar".parse::<ArchiveFormat>()
+        );
+        assert!("csv".parse::<ArchiveFormat>().is_err());
+    }
+
+    #[test]
+    fn test_columnar_levels_are_checked() -> Result<(), Error> {
+        let out_dir = env::var("OUT_DIR").expect("OUT_DIR is not defined");
+        let path = Path::new(&out_dir).join("__chain_archive_levels.tzcol");
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        let header = BlockHeaderBuilder::default()
+            .level(7)
+            .proto(0)
+            .predecessor("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?)
+            .timestamp(5_635_634)
+            .validation_pass(0)
+            .operations_hash("LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc".try_into()?)
+            .fitness(vec![])
+            .context("CoV16kW8WgL51SpcftQKdeqc94D6ekghMgPMmEn7TSZzFA697PeE".try_into()?)
+            .protocol_data(vec![])
+            .build()
+            .unwrap();
+        let mut writer = ArchiveWriter::open(ArchiveFormat::Columnar, &path, 1)?;
+        writer.write(ArchivedBlock {
+            chain_id: ChainId::from_base58_check("NetXgtSLGNJvNye")?,
+            header: BlockHeaderWithHash::new(header)?,
+            operations: vec![],
+            json_data: None,
+            additional_data: None,
+        })?;
+        writer.finish()?;
+        assert_eq!(
+            vec![7],
+            read_archive_file(&path)?
+                .iter()
+                .map(ArchivedBlock::level)
+                .collect::<Vec<_>>()
+        );
+
+        // replace levels column (the second one of the only segment) with a wrong level
+        let bytes = fs::read(&path)?;
+        let mut reader = &bytes[COLUMNAR_HEADER_LEN as usize + 1 + 4 + 1..];
+        let chain_ids_len = read_u32(&mut reader)? as usize;
+        let levels_start = bytes.len() - reader.len() + chain_ids_len;
+        let levels_len = read_u32(&mut &bytes[levels_start..])? as usize;
+        let levels = zstd::encode_all(
+            bincode::serialize(&vec![8 as BlockLevel])?.as_slice(),
+            ZSTD_COMPRESSION_LEVEL,
+        )?;
+        let mut tampered = bytes[..levels_start].to_vec();
+        tampered.extend_from_slice(&(levels.len() as u32).to_be_bytes());
+        tampered.extend_from_slice(&levels);
+        tampered.extend_from_slice(&bytes[levels_start + 4 + levels_len..]);
+        fs::write(&path, tampered)?;
+
+        assert!(read_archive_file(&path).is_err());
+        Ok(())
+    }
+}
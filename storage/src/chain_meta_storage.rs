@@ -11,7 +11,7 @@ use serde::{Deserialize, Serialize};
 use crypto::hash::{ChainId, HashType};
 use tezos_messages::Head;
 
-use crate::persistent::database::{default_table_options, RocksDbKeyValueSchema};
+use crate::persistent::database::{ColumnFamilyTuning, RocksDbKeyValueSchema};
 use crate::persistent::{
     BincodeEncoded, Decoder, Encoder, KeyValueSchema, KeyValueStoreWithSchema, SchemaError,
 };
@@ -30,6 +30,9 @@ pub trait ChainMetaStorageReader: Sync + Send {
     /// - caboose - so in particular it is the lowest block for which we have stored the context
     fn get_caboose(&self, chain_id: &ChainId) -> Result<Option<Head>, StorageError>;
 
+    /// Load save_point for chain_id from dedicated storage (see [get_caboose])
+    fn get_save_point(&self, chain_id: &ChainId) -> Result<Option<Head>, StorageError>;
+
     /// Load genesis for chain_id from dedicated storage
     fn get_genesis(&self, chain_id: &ChainId) -> Result<Option<Head>, StorageError>;
 }
@@ -83,6 +86,16 @@ impl ChainMetaStorage {
             .map_err(StorageError::from)
     }
 
+    #[inline]
+    pub fn set_save_point(&self, chain_id: &ChainId, head: Head) -> Result<(), StorageError> {
+        self.kv
+            .put(
+                &MetaKey::key_save_point(chain_id.clone()),
+                &MetadataValue::Head(head),
+            )
+            .map_err(StorageError::from)
+    }
+
     #[inline]
     pub fn set_genesis(&self, chain_id: &ChainId, head: Head) -> Result<(), StorageError> {
         self.kv
@@ -149,6 +162,17 @@ impl ChainMetaStorageReader for ChainMetaStorage {
             .map_err(StorageError::from)
     }
 
+    #[inline]
+    fn get_save_point(&self, chain_id: &ChainId) -> Result<Option<Head>, StorageError> {
+        self.kv
+            .get(&MetaKey::key_save_point(chain_id.clone()))
+            .map(|result| match result {
+                Some(MetadataValue::Head(value)) => Some(value),
+                _ => None,
+            })
+            .map_err(StorageError::from)
+    }
+
     #[inline]
     fn get_genesis(&self, chain_id: &ChainId) -> Result<Option<Head>, StorageError> {
         self.kv
@@ -167,8 +191,11 @@ impl KeyValueSchema for ChainMetaStorage {
 }
 
 impl RocksDbKeyValueSchema for ChainMetaStorage {
-    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        let cf_opts = default_table_options(cache);
+    fn descriptor_with_tuning(
+        cache: &Cache,
+        tuning: &ColumnFamilyTuning,
+    ) -> ColumnFamilyDescriptor {
+        let cf_opts = tuning.table_options(cache);
         ColumnFamilyDescriptor::new(Self::name(), cf_opts)
     }
 
@@ -191,6 +218,7 @@ impl MetaKey {
 
     const KEY_CURRENT_HEAD: &'static str = "ch";
     const KEY_CABOOSE: &'static str = "cbs";
+    const KEY_SAVE_POINT: &'static str = "svp";
     const KEY_GENESIS: &'static str = "gns";
     const KEY_TEST_CHAIN_ID: &'static str = "tcid";
 
@@ -208,6 +236,13 @@ impl MetaKey {
         }
     }
 
+    fn key_save_point(chain_id: ChainId) -> MetaKey {
+        MetaKey {
+            chain_id,
+            key: Self::KEY_SAVE_POINT.to_string(),
+        }
+    }
+
     fn key_genesis(chain_id: ChainId) -> MetaKey {
         MetaKey {
             chain_id,
@@ -390,6 +425,45 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_save_point() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create_to_out_dir("__test_save_point")?;
+        let index = ChainMetaStorage::new(tmp_storage.storage());
+
+        let chain_id = "NetXgtSLGNJvNye".try_into()?;
+        let block_1 = Head::new(
+            "BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?,
+            1,
+            vec![],
+        );
+        let block_2 = Head::new(
+            "BLockGenesisGenesisGenesisGenesisGenesisd6f5afWyME7".try_into()?,
+            2,
+            vec![],
+        );
+
+        // no save_point
+        assert!(index.get_save_point(&chain_id)?.is_none());
+
+        // set
+        index.set_save_point(&chain_id, block_1.clone())?;
+        assert_eq!(
+            index.get_save_point(&chain_id)?.unwrap().block_hash(),
+            block_1.block_hash()
+        );
+        // save_point does not touch caboose
+        assert!(index.get_caboose(&chain_id)?.is_none());
+
+        // update
+        index.set_save_point(&chain_id, block_2.clone())?;
+        assert_eq!(
+            index.get_save_point(&chain_id)?.unwrap().block_hash(),
+            block_2.block_hash()
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_genesis() -> Result<(), Error> {
         let tmp_storage = TmpStorage::create_to_out_dir("__test_genesis")?;
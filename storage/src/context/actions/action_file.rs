@@ -1,14 +1,46 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use bytes::Buf;
-use crypto::hash::BlockHash;
-use failure::Fail;
+//! Context action file.
+//!
+//! Version 1 is a sequence of blocks `[len: u32][snappy compressed bincode actions]`, which can be read only sequentially.
+//!
+//! Version 2 starts with [ActionsFileHeader] followed by blocks
+//! `[len: u32][level: u32][block_hash: 32B][actions_count: u32][crc32: u32][zstd compressed bincode actions]`
+//! and by a trailing index of all blocks, which is written when the writer is finished.
+//! The header points to the index and holds checksum of the header and the index.
+//! If the index is missing (the writer was not finished) or it is corrupted, reader rebuilds it by scanning the blocks.
+//!
+//! Levels of version 2 blocks are chain levels of the recorded blocks, if the recorder knows them,
+//! otherwise positions of the blocks in the file starting from 1 (always for version 1).
+//! Existing version 1 file is migrated to version 2 by the writer (blocks keep their positions as levels).
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fs::{File, OpenOptions};
 use std::io::{BufReader, Read, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+
+use bytes::{Buf, BufMut};
+use failure::Fail;
+
+use crypto::hash::{BlockHash, FromBytesError, HashType};
 use tezos_context::channel::ContextAction;
 
+pub const ACTIONS_FILE_VERSION_1: u32 = 1;
+pub const ACTIONS_FILE_VERSION_2: u32 = 2;
+
+/// Version 2 file starts with magic, version 1 file starts with length of the first block
+const MAGIC: [u8; 4] = *b"TZAF";
+/// magic + version + block_hash + block_height + actions_count + block_count + index_offset + checksum
+const HEADER_LEN: u64 = 4 + 4 + 32 + 4 + 4 + 4 + 8 + 4;
+/// len + level + block_hash + actions_count + crc32
+const BLOCK_HEADER_LEN: u64 = 4 + 4 + 32 + 4 + 4;
+/// block_hash + level + offset + actions_count
+const INDEX_ENTRY_LEN: u64 = 32 + 4 + 8 + 4;
+const ZSTD_COMPRESSION_LEVEL: i32 = 3;
+
 /// Possible errors for storage
 #[derive(Debug, Fail)]
 pub enum ActionFileError {
@@ -18,6 +50,19 @@ pub enum ActionFileError {
     IOError { error: std::io::Error },
     #[fail(display = "Serialization error, reason: {}", error)]
     SerializeError { error: bincode::Error },
+    #[fail(display = "Unsupported action file version: {}", version)]
+    UnsupportedVersion { version: u32 },
+    #[fail(display = "Invalid action file header, reason: {}", reason)]
+    InvalidHeader { reason: String },
+    #[fail(display = "Checksum mismatch of block at offset: {}", offset)]
+    ChecksumMismatch { offset: u64 },
+    #[fail(display = "Invalid block hash, reason: {}", error)]
+    InvalidBlockHash { error: FromBytesError },
+    #[fail(
+        display = "Block at offset: {} exceeds end of blocks: {}",
+        offset, blocks_end
+    )]
+    TruncatedBlock { offset: u64, blocks_end: u64 },
 }
 
 impl From<std::io::Error> for ActionFileError {
@@ -32,104 +77,933 @@ impl From<bincode::Error> for ActionFileError {
     }
 }
 
-#[derive(Clone, Debug)]
+impl From<FromBytesError> for ActionFileError {
+    fn from(error: FromBytesError) -> Self {
+        ActionFileError::InvalidBlockHash { error }
+    }
+}
+
+/// Header of the version 2 file
+#[derive(Clone, Debug, PartialEq)]
 pub struct ActionsFileHeader {
+    pub version: u32,
+    /// Hash of the last block
     pub current_block_hash: BlockHash,
+    /// Level of the last block
     pub block_height: u32,
     pub actions_count: u32,
     pub block_count: u32,
+    /// Offset of the trailing index, zero if the index is not written
+    pub index_offset: u64,
+    /// crc32 of the header (without checksum) and the index
+    pub checksum: u32,
+}
+
+impl ActionsFileHeader {
+    fn new() -> Self {
+        Self {
+            version: ACTIONS_FILE_VERSION_2,
+            current_block_hash: empty_block_hash(),
+            block_height: 0,
+            actions_count: 0,
+            block_count: 0,
+            index_offset: 0,
+            checksum: 0,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HEADER_LEN as usize);
+        buf.put_slice(&MAGIC);
+        buf.put_u32(self.version);
+        buf.put_slice(&self.current_block_hash.0);
+        buf.put_u32(self.block_height);
+        buf.put_u32(self.actions_count);
+        buf.put_u32(self.block_count);
+        buf.put_u64(self.index_offset);
+        buf.put_u32(self.checksum);
+        buf
+    }
+
+    fn decode(mut buf: &[u8]) -> Result<Self, ActionFileError> {
+        if (buf.len() as u64) < HEADER_LEN || buf[..MAGIC.len()] != MAGIC {
+            return Err(ActionFileError::InvalidHeader {
+                reason: "missing magic".to_string(),
+            });
+        }
+        buf.advance(MAGIC.len());
+        let version = buf.get_u32();
+        if version != ACTIONS_FILE_VERSION_2 {
+            return Err(ActionFileError::UnsupportedVersion { version });
+        }
+        let current_block_hash = BlockHash::try_from(&buf[..HashType::BlockHash.size()])?;
+        buf.advance(HashType::BlockHash.size());
+        Ok(Self {
+            version,
+            current_block_hash,
+            block_height: buf.get_u32(),
+            actions_count: buf.get_u32(),
+            block_count: buf.get_u32(),
+            index_offset: buf.get_u64(),
+            checksum: buf.get_u32(),
+        })
+    }
+
+    fn compute_checksum(&self, index: &[u8]) -> u32 {
+        let header = self.encode();
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&header[..header.len() - 4]);
+        hasher.update(index);
+        hasher.finalize()
+    }
+}
+
+/// Position of the block in the version 2 file
+#[derive(Clone, Debug, PartialEq)]
+pub struct ActionsFileIndexEntry {
+    pub block_hash: BlockHash,
+    pub level: u32,
+    /// Offset of the block header
+    pub offset: u64,
+    pub actions_count: u32,
+}
+
+impl ActionsFileIndexEntry {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_slice(&self.block_hash.0);
+        buf.put_u32(self.level);
+        buf.put_u64(self.offset);
+        buf.put_u32(self.actions_count);
+    }
+
+    fn decode(buf: &mut &[u8]) -> Result<Self, ActionFileError> {
+        let block_hash = BlockHash::try_from(&buf[..HashType::BlockHash.size()])?;
+        buf.advance(HashType::BlockHash.size());
+        Ok(Self {
+            block_hash,
+            level: buf.get_u32(),
+            offset: buf.get_u64(),
+            actions_count: buf.get_u32(),
+        })
+    }
+}
+
+/// Actions of the single block
+#[derive(Clone, Debug)]
+pub struct ActionsFileBlock {
+    pub block_hash: BlockHash,
+    pub level: u32,
+    pub actions: Vec<ContextAction>,
+}
+
+/// Header of the block in version 2 file
+struct BlockHeader {
+    len: u32,
+    level: u32,
+    block_hash: BlockHash,
+    actions_count: u32,
+    crc: u32,
+}
+
+impl BlockHeader {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(BLOCK_HEADER_LEN as usize);
+        buf.put_u32(self.len);
+        buf.put_u32(self.level);
+        buf.put_slice(&self.block_hash.0);
+        buf.put_u32(self.actions_count);
+        buf.put_u32(self.crc);
+        buf
+    }
+
+    fn read<R: Read>(reader: &mut R) -> Result<Self, ActionFileError> {
+        let mut bytes = [0_u8; BLOCK_HEADER_LEN as usize];
+        reader.read_exact(&mut bytes)?;
+        let mut buf = &bytes[..];
+        let len = buf.get_u32();
+        let level = buf.get_u32();
+        let block_hash = BlockHash::try_from(&buf[..HashType::BlockHash.size()])?;
+        buf.advance(HashType::BlockHash.size());
+        Ok(Self {
+            len,
+            level,
+            block_hash,
+            actions_count: buf.get_u32(),
+            crc: buf.get_u32(),
+        })
+    }
+}
+
+fn empty_block_hash() -> BlockHash {
+    BlockHash(vec![0; HashType::BlockHash.size()])
+}
+
+/// Block hash of the commit action in the block (the last action)
+fn commit_block_hash(actions: &[ContextAction]) -> Result<BlockHash, ActionFileError> {
+    match actions.iter().rev().find_map(|action| match action {
+        ContextAction::Commit {
+            block_hash: Some(block_hash),
+            ..
+        } => Some(block_hash),
+        _ => None,
+    }) {
+        Some(block_hash) => Ok(BlockHash::try_from(block_hash.as_slice())?),
+        None => Ok(empty_block_hash()),
+    }
+}
+
+/// Reads length of the version 1 block at `offset`, `None` at the end of blocks
+fn read_block_len_v1<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    file_len: u64,
+) -> Result<Option<u32>, ActionFileError> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut h = [0_u8; 4];
+    if reader.read_exact(&mut h).is_err() {
+        return Ok(None);
+    }
+    let content_len = u32::from_be_bytes(h);
+    if content_len == 0 {
+        return Ok(None);
+    }
+    if offset + h.len() as u64 + content_len as u64 > file_len {
+        return Err(ActionFileError::TruncatedBlock {
+            offset,
+            blocks_end: file_len,
+        });
+    }
+    Ok(Some(content_len))
+}
+
+/// Reads header and index of version 2 file, returns also end of the last block.
+/// Index is rebuilt, if it is missing or invalid.
+fn read_header_and_index(
+    file: &mut File,
+) -> Result<(ActionsFileHeader, Vec<ActionsFileIndexEntry>, u64), ActionFileError> {
+    let file_len = file.metadata()?.len();
+    let mut bytes = [0_u8; HEADER_LEN as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut bytes)?;
+    let mut header = ActionsFileHeader::decode(&bytes)?;
+
+    if header.index_offset >= HEADER_LEN && header.index_offset <= file_len {
+        let mut index_bytes = vec![0_u8; (file_len - header.index_offset) as usize];
+        file.seek(SeekFrom::Start(header.index_offset))?;
+        file.read_exact(&mut index_bytes)?;
+
+        if index_bytes.len() as u64 == header.block_count as u64 * INDEX_ENTRY_LEN
+            && header.compute_checksum(&index_bytes) == header.checksum
+        {
+            let mut buf = &index_bytes[..];
+            let mut index = Vec::with_capacity(header.block_count as usize);
+            while buf.has_remaining() {
+                index.push(ActionsFileIndexEntry::decode(&mut buf)?);
+            }
+            let blocks_end = header.index_offset;
+            return Ok((header, index, blocks_end));
+        }
+    }
+
+    // index is missing or corrupted, scan blocks,
+    // the first incomplete block or block with invalid checksum (e.g. start of stale index) ends the blocks
+    let mut reader = BufReader::new(&mut *file);
+    let mut offset = HEADER_LEN;
+    let mut index = Vec::new();
+    header.actions_count = 0;
+    header.block_height = 0;
+    header.current_block_hash = empty_block_hash();
+    loop {
+        if offset + BLOCK_HEADER_LEN > file_len {
+            break;
+        }
+        reader.seek(SeekFrom::Start(offset))?;
+        let block_header = match BlockHeader::read(&mut reader) {
+            Ok(block_header) => block_header,
+            Err(_) => break,
+        };
+        let block_end = offset + BLOCK_HEADER_LEN + block_header.len as u64;
+        if block_end > file_len {
+            break;
+        }
+        let mut compressed = vec![0; block_header.len as usize];
+        reader.read_exact(&mut compressed)?;
+        if crc32fast::hash(&compressed) != block_header.crc {
+            break;
+        }
+        header.actions_count += block_header.actions_count;
+        header.block_height = block_header.level;
+        header.current_block_hash = block_header.block_hash.clone();
+        index.push(ActionsFileIndexEntry {
+            block_hash: block_header.block_hash,
+            level: block_header.level,
+            offset,
+            actions_count: block_header.actions_count,
+        });
+        offset = block_end;
+    }
+    header.block_count = index.len() as u32;
+    header.index_offset = 0;
+    Ok((header, index, offset))
 }
 
 pub struct ActionsFileReader {
     cursor: u64,
     reader: BufReader<File>,
+    /// `None` for version 1 file
+    header: Option<ActionsFileHeader>,
+    index: Vec<ActionsFileIndexEntry>,
+    /// position in index by block hash
+    levels_by_hash: HashMap<BlockHash, usize>,
+    /// end of the last block (version 2) or length of the file (version 1)
+    blocks_end: u64,
+    /// level of the next block (used for version 1)
+    next_level: u32,
+    /// reading stops before the first block with higher level
+    last_level: Option<u32>,
+    /// iteration stops after the first error
+    failed: bool,
 }
 
 impl ActionsFileReader {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, ActionFileError> {
-        let file = OpenOptions::new()
+        let mut file = OpenOptions::new()
             .write(false)
             .create(false)
             .read(true)
             .open(path)?;
+
+        let mut magic = [0_u8; 4];
+        let is_version_2 = file.read_exact(&mut magic).is_ok() && magic == MAGIC;
+        let (header, index, blocks_end, cursor) = if is_version_2 {
+            let (header, index, blocks_end) = read_header_and_index(&mut file)?;
+            (Some(header), index, blocks_end, HEADER_LEN)
+        } else {
+            (None, Vec::new(), file.metadata()?.len(), 0)
+        };
+        let levels_by_hash = index
+            .iter()
+            .enumerate()
+            .map(|(position, entry)| (entry.block_hash.clone(), position))
+            .collect();
+
         let mut reader = BufReader::new(file);
-        reader.seek(SeekFrom::Start(0))?;
-        Ok(ActionsFileReader { reader, cursor: 0 })
+        reader.seek(SeekFrom::Start(cursor))?;
+        Ok(ActionsFileReader {
+            reader,
+            cursor,
+            header,
+            index,
+            levels_by_hash,
+            blocks_end,
+            next_level: 1,
+            last_level: None,
+            failed: false,
+        })
     }
-}
 
-impl Iterator for ActionsFileReader {
-    type Item = Vec<ContextAction>;
+    pub fn version(&self) -> u32 {
+        match &self.header {
+            Some(header) => header.version,
+            None => ACTIONS_FILE_VERSION_1,
+        }
+    }
 
-    /// Return a tuple of a block and list action in the block
-    fn next(&mut self) -> Option<Self::Item> {
-        self.cursor = match self.reader.seek(SeekFrom::Start(self.cursor)) {
-            Ok(c) => c,
-            Err(_) => {
-                return None;
-            }
+    /// Header of version 2 file
+    pub fn header(&self) -> Option<&ActionsFileHeader> {
+        self.header.as_ref()
+    }
+
+    /// Index of version 2 file (empty for version 1)
+    pub fn index(&self) -> &[ActionsFileIndexEntry] {
+        &self.index
+    }
+
+    /// Count of blocks, if it is known without reading the whole file (version 2)
+    pub fn block_count(&self) -> Option<u32> {
+        self.header.as_ref().map(|header| header.block_count)
+    }
+
+    /// Moves reader to the first block of `levels`, reading stops before the first block with higher level.
+    /// Version 2 file seeks directly to the first block with at least the start level (levels of recorded blocks
+    /// can decrease after reorg), version 1 file skips blocks without decoding them.
+    pub fn seek_to_levels<R: RangeBounds<u32>>(
+        &mut self,
+        levels: R,
+    ) -> Result<(), ActionFileError> {
+        let first_level = match levels.start_bound() {
+            Bound::Included(level) => *level,
+            Bound::Excluded(level) => level.saturating_add(1),
+            Bound::Unbounded => 1,
         };
-        let mut h = [0_u8; 4];
-        match self.reader.read_exact(&mut h) {
-            Ok(_) => {}
-            Err(_) => return None,
+        self.last_level = match levels.end_bound() {
+            Bound::Included(level) => Some(*level),
+            Bound::Excluded(level) => Some(level.saturating_sub(1)),
+            Bound::Unbounded => None,
+        };
+
+        self.failed = false;
+        if self.header.is_some() {
+            self.cursor = self
+                .index
+                .iter()
+                .find(|entry| entry.level >= first_level)
+                .map_or(self.blocks_end, |entry| entry.offset);
+            return Ok(());
         }
-        let content_len = u32::from_be_bytes(h);
-        if content_len == 0 {
-            return None;
+
+        self.cursor = 0;
+        self.next_level = 1;
+        while self.next_level < first_level {
+            match read_block_len_v1(&mut self.reader, self.cursor, self.blocks_end)? {
+                Some(content_len) => self.cursor += 4 + content_len as u64,
+                None => break,
+            }
+            self.next_level += 1;
         }
+        Ok(())
+    }
+
+    /// Reads actions of the block with `block_hash` (only version 2), does not move the reader
+    pub fn read_block(
+        &mut self,
+        block_hash: &BlockHash,
+    ) -> Result<Option<ActionsFileBlock>, ActionFileError> {
+        let offset = match self.levels_by_hash.get(block_hash) {
+            Some(position) => self.index[*position].offset,
+            None => return Ok(None),
+        };
+        self.read_block_v2(offset)
+            .map(|block| block.map(|(block, _)| block))
+    }
+
+    /// Reads the next block, returns `None` at the end of file or at the end of the level range
+    pub fn next_block(&mut self) -> Result<Option<ActionsFileBlock>, ActionFileError> {
+        let (block, block_len) = if self.header.is_some() {
+            match self.read_block_v2(self.cursor)? {
+                Some(block) => block,
+                None => return Ok(None),
+            }
+        } else {
+            match self.read_block_v1()? {
+                Some(block) => block,
+                None => return Ok(None),
+            }
+        };
+        if let Some(last_level) = self.last_level {
+            if block.level > last_level {
+                return Ok(None);
+            }
+        }
+        self.cursor += block_len;
+        self.next_level = block.level + 1;
+        Ok(Some(block))
+    }
+
+    /// Returns block and its length (including length prefix)
+    fn read_block_v1(&mut self) -> Result<Option<(ActionsFileBlock, u64)>, ActionFileError> {
+        let content_len = match read_block_len_v1(&mut self.reader, self.cursor, self.blocks_end)? {
+            Some(content_len) => content_len,
+            None => return Ok(None),
+        };
         let mut b = vec![0; content_len as usize];
-        let _ = self.reader.read_exact(&mut b);
+        self.reader.read_exact(&mut b)?;
 
         let reader = snap::read::FrameDecoder::new(b.reader());
+        let actions = bincode::deserialize_from::<_, Vec<ContextAction>>(reader)?;
+
+        Ok(Some((
+            ActionsFileBlock {
+                block_hash: commit_block_hash(&actions)?,
+                level: self.next_level,
+                actions,
+            },
+            4 + content_len as u64,
+        )))
+    }
 
-        let item = match bincode::deserialize_from::<_, Self::Item>(reader) {
-            Ok(item) => item,
-            Err(_) => {
-                return None;
+    /// Returns block and its length (including block header)
+    fn read_block_v2(
+        &mut self,
+        offset: u64,
+    ) -> Result<Option<(ActionsFileBlock, u64)>, ActionFileError> {
+        if offset + BLOCK_HEADER_LEN > self.blocks_end {
+            return Ok(None);
+        }
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let block_header = BlockHeader::read(&mut self.reader)?;
+        if offset + BLOCK_HEADER_LEN + block_header.len as u64 > self.blocks_end {
+            return Err(ActionFileError::TruncatedBlock {
+                offset,
+                blocks_end: self.blocks_end,
+            });
+        }
+
+        let mut compressed = vec![0; block_header.len as usize];
+        self.reader.read_exact(&mut compressed)?;
+        if crc32fast::hash(&compressed) != block_header.crc {
+            return Err(ActionFileError::ChecksumMismatch { offset });
+        }
+        let data = zstd::decode_all(compressed.as_slice())?;
+        let actions = bincode::deserialize::<Vec<ContextAction>>(&data)?;
+
+        Ok(Some((
+            ActionsFileBlock {
+                block_hash: block_header.block_hash,
+                level: block_header.level,
+                actions,
+            },
+            BLOCK_HEADER_LEN + block_header.len as u64,
+        )))
+    }
+}
+
+impl Iterator for ActionsFileReader {
+    type Item = Result<Vec<ContextAction>, ActionFileError>;
+
+    /// Returns actions of the next block, iteration ends after the first error
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.next_block() {
+            Ok(Some(block)) => Some(Ok(block.actions)),
+            Ok(None) => None,
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
             }
-        };
-        self.cursor += h.len() as u64 + content_len as u64;
-        Some(item)
+        }
     }
 }
 
 /// # ActionFileWriter
 ///
-/// writes block and list actions to file in `path`
+/// writes block and list actions to file in `path` (version 2), existing version 2 file is appended,
+/// existing version 1 file is migrated to version 2 first
 pub struct ActionsFileWriter {
     file: File,
+    header: ActionsFileHeader,
+    index: Vec<ActionsFileIndexEntry>,
+    /// end of the last block, next block (or index) is written here
+    blocks_end: u64,
 }
 
 impl ActionsFileWriter {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, ActionFileError> {
-        let file = OpenOptions::new()
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
             .write(true)
             .create(true)
             .read(true)
             .open(path)?;
 
-        Ok(ActionsFileWriter { file })
+        let (header, index, blocks_end) = if file.metadata()?.len() == 0 {
+            (ActionsFileHeader::new(), Vec::new(), HEADER_LEN)
+        } else {
+            let mut magic = [0_u8; 4];
+            file.read_exact(&mut magic)?;
+            if magic != MAGIC {
+                drop(file);
+                migrate_v1(path)?;
+                return Self::new(path);
+            }
+            read_header_and_index(&mut file)?
+        };
+
+        let mut writer = ActionsFileWriter {
+            file,
+            header,
+            index,
+            blocks_end,
+        };
+        // index is overwritten by next block
+        writer.header.index_offset = 0;
+        writer.write_header()?;
+        Ok(writer)
+    }
+}
+
+/// Rewrites version 1 file in `path` to version 2, blocks keep their positions as levels.
+/// New file is written next to the old one and replaces it when it is complete,
+/// so the old file survives an interrupted migration.
+fn migrate_v1(path: &Path) -> Result<(), ActionFileError> {
+    let mut migrated_path = PathBuf::from(path).into_os_string();
+    migrated_path.push(".v2.tmp");
+    let migrated_path = PathBuf::from(migrated_path);
+    if migrated_path.exists() {
+        std::fs::remove_file(&migrated_path)?;
+    }
+
+    let mut reader = ActionsFileReader::new(path)?;
+    if reader.version() != ACTIONS_FILE_VERSION_1 {
+        return Err(ActionFileError::UnsupportedVersion {
+            version: reader.version(),
+        });
+    }
+    {
+        let mut writer = ActionsFileWriter::new(&migrated_path)?;
+        while let Some(block) = reader.next_block()? {
+            writer.update_at_level(block.actions, block.level)?;
+        }
+        writer.finish()?;
     }
+    std::fs::rename(&migrated_path, path)?;
+    Ok(())
 }
 
 impl ActionsFileWriter {
+    /// Appends actions of the next block with unknown chain level (level following the last block is used),
+    /// block hash is taken from the commit action
     pub fn update(&mut self, actions: Vec<ContextAction>) -> Result<(), ActionFileError> {
-        let mut out = Vec::new();
-        let writer = snap::write::FrameEncoder::new(&mut out);
-        bincode::serialize_into(writer, &actions)?;
+        let level = self.header.block_height + 1;
+        self.update_at_level(actions, level)
+    }
 
-        self._update(&out)?;
-        Ok(())
+    /// Appends actions of the next block with chain `level`, block hash is taken from the commit action
+    pub fn update_at_level(
+        &mut self,
+        actions: Vec<ContextAction>,
+        level: u32,
+    ) -> Result<(), ActionFileError> {
+        let block_hash = commit_block_hash(&actions)?;
+        let actions_count = actions.len() as u32;
+
+        let data = bincode::serialize(&actions)?;
+        let compressed = zstd::encode_all(data.as_slice(), ZSTD_COMPRESSION_LEVEL)?;
+        let block_header = BlockHeader {
+            len: compressed.len() as u32,
+            level,
+            block_hash: block_hash.clone(),
+            actions_count,
+            crc: crc32fast::hash(&compressed),
+        };
+
+        let mut dt = block_header.encode();
+        dt.extend_from_slice(&compressed);
+        self._update(&dt)?;
+
+        self.index.push(ActionsFileIndexEntry {
+            block_hash: block_hash.clone(),
+            level,
+            offset: self.blocks_end,
+            actions_count,
+        });
+        self.blocks_end += dt.len() as u64;
+        self.header.current_block_hash = block_hash;
+        self.header.block_height = level;
+        self.header.block_count += 1;
+        self.header.actions_count += actions_count;
+        self.header.index_offset = 0;
+        self.write_header()
     }
 
     pub fn _update(&mut self, data: &[u8]) -> Result<(), ActionFileError> {
-        self.file.seek(SeekFrom::End(0))?;
-        let chunk_size = (data.len() as u32).to_be_bytes();
-        let mut dt = vec![];
-        dt.extend_from_slice(&chunk_size);
-        dt.extend_from_slice(data);
-        self.file.write_all(dt.as_slice())?;
+        self.file.seek(SeekFrom::Start(self.blocks_end))?;
+        self.file.write_all(data)?;
         Ok(())
     }
+
+    /// Writes trailing index, the writer can still be used for appending
+    pub fn finish(&mut self) -> Result<(), ActionFileError> {
+        let mut index = Vec::with_capacity(self.index.len() * INDEX_ENTRY_LEN as usize);
+        for entry in &self.index {
+            entry.encode(&mut index);
+        }
+        self.file.seek(SeekFrom::Start(self.blocks_end))?;
+        self.file.write_all(&index)?;
+        self.file.set_len(self.blocks_end + index.len() as u64)?;
+
+        self.header.index_offset = self.blocks_end;
+        self.header.checksum = self.header.compute_checksum(&index);
+        self.write_header()?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> Result<(), ActionFileError> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&self.header.encode())?;
+        Ok(())
+    }
+}
+
+impl Drop for ActionsFileWriter {
+    fn drop(&mut self) {
+        if self.header.index_offset == 0 {
+            let _ = self.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn test_file(name: &str) -> PathBuf {
+        let out_dir = env::var("OUT_DIR").expect(
+            "OUT_DIR is not defined - please add build.rs to root or set env variable OUT_DIR",
+        );
+        let path = Path::new(&out_dir).join(format!("action_file_{}.bin", name));
+        if path.exists() {
+            fs::remove_file(&path).unwrap();
+        }
+        path
+    }
+
+    fn block(level: u8) -> Vec<ContextAction> {
+        vec![
+            ContextAction::Set {
+                context_hash: None,
+                block_hash: None,
+                operation_hash: None,
+                tree_hash: None,
+                new_tree_hash: None,
+                tree_id: level as i32,
+                new_tree_id: level as i32,
+                start_time: 0.0,
+                end_time: 0.0,
+                key: vec!["data".to_string(), format!("{}", level)],
+                value: vec![level],
+                value_as_json: None,
+            },
+            ContextAction::Commit {
+                parent_context_hash: None,
+                block_hash: Some(vec![level; HashType::BlockHash.size()]),
+                new_context_hash: vec![level; 32],
+                tree_hash: None,
+                tree_id: level as i32,
+                start_time: 0.0,
+                end_time: 0.0,
+                author: "Tezedge".to_string(),
+                message: "".to_string(),
+                date: 0,
+                parents: vec![],
+            },
+        ]
+    }
+
+    /// actions does not implement PartialEq, so they are compared in serialized form
+    fn encoded(actions: &[ContextAction]) -> Vec<u8> {
+        bincode::serialize(actions).unwrap()
+    }
+
+    fn write_v1(path: &Path, blocks: &[Vec<ContextAction>]) {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(path)
+            .unwrap();
+        for actions in blocks {
+            let mut out = Vec::new();
+            bincode::serialize_into(snap::write::FrameEncoder::new(&mut out), actions).unwrap();
+            file.write_all(&(out.len() as u32).to_be_bytes()).unwrap();
+            file.write_all(&out).unwrap();
+        }
+    }
+
+    fn levels(reader: &mut ActionsFileReader) -> Vec<u32> {
+        let mut levels = Vec::new();
+        while let Some(block) = reader.next_block().unwrap() {
+            levels.push(block.level);
+        }
+        levels
+    }
+
+    #[test]
+    fn test_write_and_read_v2() {
+        let path = test_file("test_write_and_read_v2");
+        {
+            let mut writer = ActionsFileWriter::new(&path).unwrap();
+            for level in 1..=5 {
+                writer.update(block(level)).unwrap();
+            }
+        }
+        // append to finished file
+        {
+            let mut writer = ActionsFileWriter::new(&path).unwrap();
+            writer.update(block(6)).unwrap();
+            writer.finish().unwrap();
+        }
+
+        let mut reader = ActionsFileReader::new(&path).unwrap();
+        assert_eq!(reader.version(), ACTIONS_FILE_VERSION_2);
+        let header = reader.header().unwrap().clone();
+        assert_eq!(header.block_count, 6);
+        assert_eq!(header.block_height, 6);
+        assert_eq!(header.actions_count, 12);
+        assert_eq!(header.current_block_hash, BlockHash(vec![6; 32]));
+        assert_eq!(reader.index().len(), 6);
+
+        let blocks = ActionsFileReader::new(&path)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(blocks.len(), 6);
+        assert_eq!(encoded(&blocks[2]), encoded(&block(3)));
+
+        reader.seek_to_levels(3..5).unwrap();
+        assert_eq!(levels(&mut reader), vec![3, 4]);
+        reader.seek_to_levels(5..).unwrap();
+        assert_eq!(levels(&mut reader), vec![5, 6]);
+
+        let found = reader.read_block(&BlockHash(vec![2; 32])).unwrap().unwrap();
+        assert_eq!(found.level, 2);
+        assert_eq!(encoded(&found.actions), encoded(&block(2)));
+        assert!(reader
+            .read_block(&BlockHash(vec![9; 32]))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_rebuild_missing_index() {
+        let path = test_file("test_rebuild_missing_index");
+        {
+            let mut writer = ActionsFileWriter::new(&path).unwrap();
+            for level in 1..=3 {
+                writer.update(block(level)).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        // corrupt the index
+        let len = fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(len - 1)).unwrap();
+        file.write_all(&[0xff]).unwrap();
+
+        let mut reader = ActionsFileReader::new(&path).unwrap();
+        assert_eq!(reader.block_count(), Some(3));
+        reader.seek_to_levels(2..=2).unwrap();
+        assert_eq!(levels(&mut reader), vec![2]);
+        assert_eq!(
+            ActionsFileReader::new(&path)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_read_v1() {
+        let path = test_file("test_read_v1");
+        let blocks: Vec<_> = (1..=4).map(block).collect();
+        write_v1(&path, &blocks);
+
+        let mut reader = ActionsFileReader::new(&path).unwrap();
+        assert_eq!(reader.version(), ACTIONS_FILE_VERSION_1);
+        assert_eq!(reader.block_count(), None);
+        reader.seek_to_levels(2..=3).unwrap();
+        let block = reader.next_block().unwrap().unwrap();
+        assert_eq!(block.level, 2);
+        assert_eq!(block.block_hash, BlockHash(vec![2; 32]));
+        assert_eq!(levels(&mut reader), vec![3]);
+
+        let read = ActionsFileReader::new(&path)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(read.len(), blocks.len());
+        for (read, written) in read.iter().zip(blocks.iter()) {
+            assert_eq!(encoded(read), encoded(written));
+        }
+    }
+
+    #[test]
+    fn test_migrate_v1() {
+        let path = test_file("test_migrate_v1");
+        let blocks: Vec<_> = (1..=3).map(block).collect();
+        write_v1(&path, &blocks);
+
+        // writer migrates the file and appends to it
+        {
+            let mut writer = ActionsFileWriter::new(&path).unwrap();
+            writer.update_at_level(block(4), 4).unwrap();
+        }
+
+        let mut reader = ActionsFileReader::new(&path).unwrap();
+        assert_eq!(reader.version(), ACTIONS_FILE_VERSION_2);
+        assert_eq!(reader.block_count(), Some(4));
+        assert_eq!(reader.header().unwrap().actions_count, 8);
+        let read = ActionsFileReader::new(&path)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        for (read, written) in read.iter().zip(blocks.iter()) {
+            assert_eq!(encoded(read), encoded(written));
+        }
+        assert_eq!(levels(&mut reader), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_seek_to_chain_levels() {
+        let path = test_file("test_seek_to_chain_levels");
+        {
+            let mut writer = ActionsFileWriter::new(&path).unwrap();
+            for level in &[100, 101, 102, 101, 102, 103] {
+                writer
+                    .update_at_level(block(*level), *level as u32)
+                    .unwrap();
+            }
+            // unknown level follows the last one
+            writer.update(block(104)).unwrap();
+        }
+
+        let mut reader = ActionsFileReader::new(&path).unwrap();
+        assert_eq!(reader.header().unwrap().block_height, 104);
+        // blocks reapplied after reorg are part of the range
+        reader.seek_to_levels(101..=102).unwrap();
+        assert_eq!(levels(&mut reader), vec![101, 102, 101, 102]);
+        reader.seek_to_levels(102..).unwrap();
+        assert_eq!(levels(&mut reader), vec![102, 101, 102, 103, 104]);
+        reader.seek_to_levels(105..).unwrap();
+        assert_eq!(levels(&mut reader), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_truncated_block() {
+        let path = test_file("test_truncated_block");
+        {
+            let mut writer = ActionsFileWriter::new(&path).unwrap();
+            for level in 1..=2 {
+                writer.update(block(level)).unwrap();
+            }
+        }
+        let len = fs::metadata(&path).unwrap().len();
+        let mut reader = ActionsFileReader::new(&path).unwrap();
+        let second_block = reader.index()[1].offset;
+
+        // length of the second block points behind the end of the file
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(second_block)).unwrap();
+        file.write_all(&u32::MAX.to_be_bytes()).unwrap();
+        assert!(matches!(
+            reader.nth(1),
+            Some(Err(ActionFileError::TruncatedBlock { .. }))
+        ));
+        assert!(reader.next().is_none());
+
+        // scan of the file without valid index stops before the damaged block
+        file.set_len(len - 1).unwrap();
+        assert_eq!(
+            ActionsFileReader::new(&path).unwrap().block_count(),
+            Some(1)
+        );
+
+        // the same for version 1 file
+        let path = test_file("test_truncated_block_v1");
+        write_v1(&path, &[block(1)]);
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.write_all(&u32::MAX.to_be_bytes()).unwrap();
+        assert!(matches!(
+            ActionsFileReader::new(&path).unwrap().next(),
+            Some(Err(ActionFileError::TruncatedBlock { .. }))
+        ));
+    }
 }
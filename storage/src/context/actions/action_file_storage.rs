@@ -1,44 +1,101 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::convert::TryFrom;
 use std::path::PathBuf;
 
+use crypto::hash::BlockHash;
 use tezos_context::channel::ContextAction;
 
+use crate::block_meta_storage::BlockMetaStorage;
 use crate::context::actions::action_file::{ActionFileError, ActionsFileWriter};
+use crate::context::actions::context_action_storage::resolve_block_level;
 use crate::context::actions::{ActionRecorder, ActionRecorderError};
 
 pub struct ActionFileStorage {
     file: PathBuf,
+    /// opened with the first block and kept open, so the index is written just once
+    writer: Option<ActionsFileWriter>,
     staging: Vec<ContextAction>,
-    level: u32,
+    // Used to resolve chain levels of the blocks (without it, levels are positions of the blocks in the file)
+    block_meta_storage: Option<BlockMetaStorage>,
 }
 
 impl ActionFileStorage {
     pub fn new(path: PathBuf) -> Self {
         ActionFileStorage {
             file: path,
+            writer: None,
             staging: Vec::new(),
-            level: 0,
+            block_meta_storage: None,
         }
     }
 
+    /// Blocks are stored with their chain levels resolved from `block_meta_storage`
+    pub fn with_block_levels(mut self, block_meta_storage: BlockMetaStorage) -> Self {
+        self.block_meta_storage = Some(block_meta_storage);
+        self
+    }
+
     fn store_single_action(&mut self, action: &ContextAction) {
         self.staging.push(action.clone());
     }
 
-    fn store_commit_action(&mut self, action: &ContextAction) -> Result<(), ActionFileError> {
-        self.level += 1;
+    fn store_commit_action(&mut self, action: &ContextAction) -> Result<(), ActionRecorderError> {
+        let level = match action {
+            ContextAction::Commit {
+                block_hash: Some(block_hash),
+                ..
+            } => self.resolve_block_level(block_hash)?,
+            _ => None,
+        };
         self.store_single_action(action);
-        self.flush_entries_to_file()
+        self.flush_entries_to_file(level)
+            .map_err(|e| ActionRecorderError::StoreError {
+                reason: format!("Failed to store action to action file, reason: {:?}", e),
+            })
+    }
+
+    /// Chain level of the block, `None` if it is not known
+    fn resolve_block_level(&self, block_hash: &[u8]) -> Result<Option<u32>, ActionRecorderError> {
+        let block_meta_storage = match &self.block_meta_storage {
+            Some(block_meta_storage) => block_meta_storage,
+            None => return Ok(None),
+        };
+        let block_hash = match BlockHash::try_from(block_hash) {
+            Ok(block_hash) => block_hash,
+            Err(_) => return Ok(None),
+        };
+        let level = resolve_block_level(block_meta_storage, &block_hash).map_err(|e| {
+            ActionRecorderError::StoreError {
+                reason: format!("Failed to resolve level of the block, reason: {:?}", e),
+            }
+        })?;
+        Ok(level.and_then(|level| u32::try_from(level).ok()))
     }
 
-    fn flush_entries_to_file(&mut self) -> Result<(), ActionFileError> {
-        let mut action_file_writer = ActionsFileWriter::new(&self.file)?;
-        action_file_writer.update(self.staging.clone())?;
+    fn flush_entries_to_file(&mut self, level: Option<u32>) -> Result<(), ActionFileError> {
+        if self.writer.is_none() {
+            self.writer = Some(ActionsFileWriter::new(&self.file)?);
+        }
+        if let Some(action_file_writer) = self.writer.as_mut() {
+            let actions = self.staging.clone();
+            match level {
+                Some(level) => action_file_writer.update_at_level(actions, level)?,
+                None => action_file_writer.update(actions)?,
+            }
+        }
         self.staging.clear();
         Ok(())
     }
+
+    /// Writes index of the action file
+    fn finish(&mut self) -> Result<(), ActionFileError> {
+        match self.writer.as_mut() {
+            Some(action_file_writer) => action_file_writer.finish(),
+            None => Ok(()),
+        }
+    }
 }
 
 pub fn get_tree_action(action: &ContextAction) -> String {
@@ -75,12 +132,10 @@ impl ActionRecorder for ActionFileStorage {
                 self.store_single_action(context_action);
                 Ok(())
             }
-            ContextAction::Commit { .. } => self.store_commit_action(context_action).map_err(|e| {
-                ActionRecorderError::StoreError {
-                    reason: format!("Failed to store action to action file, reason: {:?}", e),
-                }
+            ContextAction::Commit { .. } => self.store_commit_action(context_action),
+            ContextAction::Shutdown => self.finish().map_err(|e| ActionRecorderError::StoreError {
+                reason: format!("Failed to finish action file, reason: {:?}", e),
             }),
-            ContextAction::Shutdown => Ok(()),
         }
     }
 }
@@ -0,0 +1,775 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Streaming of context actions to an external consumer.
+//!
+//! Every action is sent as a frame `[len: u32][sequence: u64][bincode action]` to a unix socket, a tcp socket or
+//! to a directory of segment files `actions-<seq>.seg`. Segment is rotated, when it reaches its size limit,
+//! and only the newest segments are kept in the directory.
+//!
+//! Recording does not wait for the sink, frames are passed to the writer thread through a bounded buffer.
+//! When the buffer is full, recording blocks (or drops the frame, see [ActionStreamOverflow]).
+//! Frames are dropped also while the sink is not connected, writer thread tries to reconnect periodically.
+//! Sequence number is increased by one for every recorded action (starting from 0 after the node start),
+//! so the consumer detects dropped frames as a gap in the sequence.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use failure::Fail;
+use serde::Serialize;
+use slog::{info, warn, Logger};
+
+use tezos_context::channel::ContextAction;
+
+use crate::context::actions::{ActionRecorder, ActionRecorderError};
+
+pub const DEFAULT_ACTIONS_STREAM_BUFFER_SIZE: usize = 65536;
+pub const DEFAULT_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+pub const DEFAULT_MAX_SEGMENTS: usize = 16;
+
+const SEGMENT_FILE_PREFIX: &str = "actions-";
+const SEGMENT_FILE_SUFFIX: &str = ".seg";
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+/// Statistics are logged after every STATS_LOG_INTERVAL recorded blocks
+const STATS_LOG_INTERVAL: u64 = 1000;
+
+#[derive(Debug, Fail)]
+pub enum ActionStreamError {
+    #[fail(display = "IOError detected, reason: {}", error)]
+    IOError { error: std::io::Error },
+    #[fail(display = "Serialization error, reason: {}", error)]
+    SerializeError { error: bincode::Error },
+    #[fail(display = "Invalid actions stream target: {}", target)]
+    InvalidTarget { target: String },
+    #[fail(display = "Invalid actions stream overflow policy: {}", value)]
+    InvalidOverflowPolicy { value: String },
+}
+
+impl From<std::io::Error> for ActionStreamError {
+    fn from(error: std::io::Error) -> Self {
+        ActionStreamError::IOError { error }
+    }
+}
+
+impl From<bincode::Error> for ActionStreamError {
+    fn from(error: bincode::Error) -> Self {
+        ActionStreamError::SerializeError { error }
+    }
+}
+
+/// Where are the actions streamed to, parsed from `unix:<path>`, `tcp:<host:port>` or `dir:<path>`
+#[derive(PartialEq, Debug, Clone)]
+pub enum ActionStreamTarget {
+    Unix {
+        path: PathBuf,
+    },
+    Tcp {
+        address: String,
+    },
+    SegmentDir {
+        path: PathBuf,
+        segment_size: u64,
+        max_segments: usize,
+    },
+}
+
+impl FromStr for ActionStreamTarget {
+    type Err = ActionStreamError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid_target = || ActionStreamError::InvalidTarget {
+            target: s.to_string(),
+        };
+        let (kind, value) = match s.find(':') {
+            Some(position) => (&s[..position], &s[position + 1..]),
+            None => return Err(invalid_target()),
+        };
+        if value.is_empty() {
+            return Err(invalid_target());
+        }
+
+        match kind.to_ascii_lowercase().as_str() {
+            "unix" => Ok(ActionStreamTarget::Unix {
+                path: PathBuf::from(value),
+            }),
+            "tcp" => Ok(ActionStreamTarget::Tcp {
+                address: value.to_string(),
+            }),
+            "dir" => Ok(ActionStreamTarget::SegmentDir {
+                path: PathBuf::from(value),
+                segment_size: DEFAULT_SEGMENT_SIZE,
+                max_segments: DEFAULT_MAX_SEGMENTS,
+            }),
+            _ => Err(invalid_target()),
+        }
+    }
+}
+
+impl ActionStreamTarget {
+    fn open(&self) -> Result<Box<dyn FrameSink>, ActionStreamError> {
+        match self {
+            ActionStreamTarget::Unix { path } => {
+                Ok(Box::new(BufWriter::new(UnixStream::connect(path)?)))
+            }
+            ActionStreamTarget::Tcp { address } => {
+                Ok(Box::new(BufWriter::new(TcpStream::connect(address)?)))
+            }
+            ActionStreamTarget::SegmentDir {
+                path,
+                segment_size,
+                max_segments,
+            } => Ok(Box::new(SegmentDirSink::open(
+                path,
+                *segment_size,
+                *max_segments,
+            )?)),
+        }
+    }
+}
+
+/// What to do with the action, when the buffer is full
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ActionStreamOverflow {
+    /// recording waits for the writer thread (slows down the node)
+    Block,
+    /// action is not streamed
+    Drop,
+}
+
+impl FromStr for ActionStreamOverflow {
+    type Err = ActionStreamError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "block" => Ok(ActionStreamOverflow::Block),
+            "drop" => Ok(ActionStreamOverflow::Drop),
+            _ => Err(ActionStreamError::InvalidOverflowPolicy {
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct ActionStreamConfiguration {
+    pub target: ActionStreamTarget,
+    /// Max count of frames waiting for the writer thread
+    pub buffer_size: usize,
+    pub overflow: ActionStreamOverflow,
+}
+
+impl Default for ActionStreamConfiguration {
+    fn default() -> Self {
+        ActionStreamConfiguration {
+            target: ActionStreamTarget::Tcp {
+                address: "127.0.0.1:7733".to_string(),
+            },
+            buffer_size: DEFAULT_ACTIONS_STREAM_BUFFER_SIZE,
+            overflow: ActionStreamOverflow::Block,
+        }
+    }
+}
+
+/// Snapshot of the streaming statistics
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ActionStreamStats {
+    /// frames waiting in the buffer
+    pub queued_frames: usize,
+    /// max count of frames, which were waiting in the buffer at once
+    pub max_queued_frames: usize,
+    pub sent_frames: u64,
+    pub sent_bytes: u64,
+    /// frames dropped because of full buffer or disconnected sink
+    pub dropped_frames: u64,
+    /// count of recordings, which had to wait for the free space in the buffer
+    pub blocked_records: u64,
+    pub blocked_time: Duration,
+    pub connects: u64,
+}
+
+#[derive(Default)]
+struct ActionStreamCounters {
+    queued_frames: AtomicUsize,
+    max_queued_frames: AtomicUsize,
+    sent_frames: AtomicU64,
+    sent_bytes: AtomicU64,
+    dropped_frames: AtomicU64,
+    blocked_records: AtomicU64,
+    blocked_time_nanos: AtomicU64,
+    connects: AtomicU64,
+}
+
+impl ActionStreamCounters {
+    fn snapshot(&self) -> ActionStreamStats {
+        ActionStreamStats {
+            queued_frames: self.queued_frames.load(Ordering::Relaxed),
+            max_queued_frames: self.max_queued_frames.load(Ordering::Relaxed),
+            sent_frames: self.sent_frames.load(Ordering::Relaxed),
+            sent_bytes: self.sent_bytes.load(Ordering::Relaxed),
+            dropped_frames: self.dropped_frames.load(Ordering::Relaxed),
+            blocked_records: self.blocked_records.load(Ordering::Relaxed),
+            blocked_time: Duration::from_nanos(self.blocked_time_nanos.load(Ordering::Relaxed)),
+            connects: self.connects.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Shared handle to the streaming statistics, which are read by the RPC
+#[derive(Clone, Default)]
+pub struct ActionStreamStatsRef(Arc<ActionStreamCounters>);
+
+impl ActionStreamStatsRef {
+    pub fn stats(&self) -> ActionStreamStats {
+        self.0.snapshot()
+    }
+}
+
+/// Recorder, which streams actions to [ActionStreamTarget]
+pub struct ActionStreamRecorder {
+    sender: Option<SyncSender<Vec<u8>>>,
+    overflow: ActionStreamOverflow,
+    counters: Arc<ActionStreamCounters>,
+    next_sequence: u64,
+    recorded_blocks: u64,
+    thread: Option<thread::JoinHandle<()>>,
+    log: Logger,
+}
+
+impl ActionStreamRecorder {
+    pub fn new(
+        configuration: ActionStreamConfiguration,
+        stats: ActionStreamStatsRef,
+        log: Logger,
+    ) -> Self {
+        let (sender, receiver) = sync_channel(configuration.buffer_size);
+        let counters = stats.0;
+
+        let writer = FrameWriter {
+            target: configuration.target,
+            receiver,
+            counters: counters.clone(),
+            sink: None,
+            next_connect: Instant::now(),
+            log: log.clone(),
+        };
+
+        ActionStreamRecorder {
+            sender: Some(sender),
+            overflow: configuration.overflow,
+            counters,
+            next_sequence: 0,
+            recorded_blocks: 0,
+            thread: Some(thread::spawn(move || writer.run())),
+            log,
+        }
+    }
+
+    pub fn stats(&self) -> ActionStreamStats {
+        self.counters.snapshot()
+    }
+
+    fn send(&self, frame: Vec<u8>) -> Result<(), ActionRecorderError> {
+        let sender = match self.sender.as_ref() {
+            Some(sender) => sender,
+            None => return Ok(()),
+        };
+        // counted before sending, so the writer thread never decrements below zero
+        let queued = self.counters.queued_frames.fetch_add(1, Ordering::Relaxed) + 1;
+        self.counters
+            .max_queued_frames
+            .fetch_max(queued, Ordering::Relaxed);
+
+        let result = match sender.try_send(frame) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(frame)) => match self.overflow {
+                ActionStreamOverflow::Block => {
+                    let blocked_since = Instant::now();
+                    let result = sender.send(frame).map_err(|_| ());
+                    self.counters
+                        .blocked_records
+                        .fetch_add(1, Ordering::Relaxed);
+                    self.counters
+                        .blocked_time_nanos
+                        .fetch_add(blocked_since.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                    result
+                }
+                ActionStreamOverflow::Drop => {
+                    self.counters.queued_frames.fetch_sub(1, Ordering::Relaxed);
+                    self.counters.dropped_frames.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+            },
+            Err(TrySendError::Disconnected(_)) => Err(()),
+        };
+
+        result.map_err(|_| {
+            self.counters.queued_frames.fetch_sub(1, Ordering::Relaxed);
+            ActionRecorderError::StoreError {
+                reason: "Actions stream writer thread is not running".to_string(),
+            }
+        })
+    }
+}
+
+impl ActionRecorder for ActionStreamRecorder {
+    fn record(&mut self, action: &ContextAction) -> Result<(), ActionRecorderError> {
+        let frame = encode_frame(self.next_sequence, action).map_err(|e| {
+            ActionRecorderError::StoreError {
+                reason: format!("Failed to encode action frame, reason: {}", e),
+            }
+        })?;
+        // increased also for the dropped frames, so the consumer sees the gap
+        self.next_sequence += 1;
+        self.send(frame)?;
+
+        if let ContextAction::Commit { .. } = action {
+            self.recorded_blocks += 1;
+            if self.recorded_blocks % STATS_LOG_INTERVAL == 0 {
+                let stats = self.stats();
+                info!(self.log, "Context actions stream statistics";
+                                "queued_frames" => stats.queued_frames,
+                                "max_queued_frames" => stats.max_queued_frames,
+                                "sent_frames" => stats.sent_frames,
+                                "sent_bytes" => stats.sent_bytes,
+                                "dropped_frames" => stats.dropped_frames,
+                                "blocked_records" => stats.blocked_records,
+                                "blocked_time" => format!("{:?}", stats.blocked_time),
+                                "connects" => stats.connects);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ActionStreamRecorder {
+    fn drop(&mut self) {
+        // writer thread writes rest of the buffer and finishes, when the channel is closed
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Encodes action as `[len: u32][sequence: u64][bincode action]`, `len` is the length of the bincode action
+pub fn encode_frame(sequence: u64, action: &ContextAction) -> Result<Vec<u8>, ActionStreamError> {
+    let data = bincode::serialize(action)?;
+    let mut frame = Vec::with_capacity(12 + data.len());
+    frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&sequence.to_be_bytes());
+    frame.extend_from_slice(&data);
+    Ok(frame)
+}
+
+/// Reads the next action frame with its sequence number, returns `None` at the end of the stream
+pub fn read_frame<R: Read>(
+    reader: &mut R,
+) -> Result<Option<(u64, ContextAction)>, ActionStreamError> {
+    let mut len = [0_u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => (),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let mut sequence = [0_u8; 8];
+    reader.read_exact(&mut sequence)?;
+    let mut data = vec![0_u8; u32::from_be_bytes(len) as usize];
+    reader.read_exact(&mut data)?;
+    Ok(Some((
+        u64::from_be_bytes(sequence),
+        bincode::deserialize(&data)?,
+    )))
+}
+
+trait FrameSink: Send {
+    fn write_frame(&mut self, frame: &[u8]) -> std::io::Result<()>;
+
+    fn flush(&mut self) -> std::io::Result<()>;
+}
+
+impl<W: Write + Send> FrameSink for BufWriter<W> {
+    fn write_frame(&mut self, frame: &[u8]) -> std::io::Result<()> {
+        self.write_all(frame)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Write::flush(self)
+    }
+}
+
+/// Writes frames to the segment files, frame is never split between two segments
+struct SegmentDirSink {
+    dir: PathBuf,
+    segment_size: u64,
+    max_segments: usize,
+    segment: BufWriter<File>,
+    segment_len: u64,
+    segment_seq: u64,
+}
+
+impl SegmentDirSink {
+    fn open(dir: &Path, segment_size: u64, max_segments: usize) -> Result<Self, ActionStreamError> {
+        fs::create_dir_all(dir)?;
+        // continue with a new segment after the existing ones
+        let segment_seq = list_segments(dir)?.last().map_or(0, |(seq, _)| seq + 1);
+
+        let mut sink = SegmentDirSink {
+            dir: dir.to_path_buf(),
+            segment_size,
+            max_segments: max_segments.max(1),
+            segment: BufWriter::new(create_segment(dir, segment_seq)?),
+            segment_len: 0,
+            segment_seq,
+        };
+        sink.remove_old_segments()?;
+        Ok(sink)
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        Write::flush(&mut self.segment)?;
+        self.segment_seq += 1;
+        self.segment = BufWriter::new(create_segment(&self.dir, self.segment_seq)?);
+        self.segment_len = 0;
+        self.remove_old_segments()
+    }
+
+    fn remove_old_segments(&self) -> std::io::Result<()> {
+        let segments = list_segments(&self.dir)?;
+        if segments.len() > self.max_segments {
+            for (_, path) in &segments[..segments.len() - self.max_segments] {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FrameSink for SegmentDirSink {
+    fn write_frame(&mut self, frame: &[u8]) -> std::io::Result<()> {
+        if self.segment_len > 0 && self.segment_len + frame.len() as u64 > self.segment_size {
+            self.rotate()?;
+        }
+        self.segment.write_all(frame)?;
+        self.segment_len += frame.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Write::flush(&mut self.segment)
+    }
+}
+
+fn segment_path(dir: &Path, seq: u64) -> PathBuf {
+    dir.join(format!(
+        "{}{:010}{}",
+        SEGMENT_FILE_PREFIX, seq, SEGMENT_FILE_SUFFIX
+    ))
+}
+
+fn create_segment(dir: &Path, seq: u64) -> std::io::Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(segment_path(dir, seq))
+}
+
+/// Returns segments of the directory sorted by their sequence number
+pub fn list_segments(dir: &Path) -> std::io::Result<Vec<(u64, PathBuf)>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let seq = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(SEGMENT_FILE_PREFIX))
+            .and_then(|name| name.strip_suffix(SEGMENT_FILE_SUFFIX))
+            .and_then(|seq| seq.parse::<u64>().ok());
+        if let Some(seq) = seq {
+            segments.push((seq, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+/// Runs in the writer thread
+struct FrameWriter {
+    target: ActionStreamTarget,
+    receiver: Receiver<Vec<u8>>,
+    counters: Arc<ActionStreamCounters>,
+    sink: Option<Box<dyn FrameSink>>,
+    next_connect: Instant,
+    log: Logger,
+}
+
+impl FrameWriter {
+    fn run(mut self) {
+        while let Ok(frame) = self.receiver.recv() {
+            self.write(frame);
+            // write all waiting frames and flush them at once
+            while let Ok(frame) = self.receiver.try_recv() {
+                self.write(frame);
+            }
+            if let Some(sink) = self.sink.as_mut() {
+                if let Err(e) = sink.flush() {
+                    self.disconnect(e.into());
+                }
+            }
+        }
+        if let Some(mut sink) = self.sink.take() {
+            let _ = sink.flush();
+        }
+    }
+
+    fn write(&mut self, frame: Vec<u8>) {
+        self.counters.queued_frames.fetch_sub(1, Ordering::Relaxed);
+
+        if self.sink.is_none() && Instant::now() >= self.next_connect {
+            match self.target.open() {
+                Ok(sink) => {
+                    self.counters.connects.fetch_add(1, Ordering::Relaxed);
+                    self.sink = Some(sink);
+                }
+                Err(e) => self.disconnect(e),
+            }
+        }
+
+        let result = match self.sink.as_mut() {
+            Some(sink) => sink.write_frame(&frame),
+            None => {
+                self.counters.dropped_frames.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        };
+        match result {
+            Ok(()) => {
+                self.counters.sent_frames.fetch_add(1, Ordering::Relaxed);
+                self.counters
+                    .sent_bytes
+                    .fetch_add(frame.len() as u64, Ordering::Relaxed);
+            }
+            Err(e) => {
+                self.counters.dropped_frames.fetch_add(1, Ordering::Relaxed);
+                self.disconnect(e.into());
+            }
+        }
+    }
+
+    fn disconnect(&mut self, error: ActionStreamError) {
+        warn!(self.log, "Context actions stream is not connected, actions are dropped";
+                        "target" => format!("{:?}", self.target),
+                        "retry_in" => format!("{:?}", RECONNECT_INTERVAL),
+                        "reason" => format!("{}", error));
+        self.sink = None;
+        self.next_connect = Instant::now() + RECONNECT_INTERVAL;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::io::BufReader;
+    use std::net::TcpListener;
+
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let out_dir = env::var("OUT_DIR").expect(
+            "OUT_DIR is not defined - please add build.rs to root or set env variable OUT_DIR",
+        );
+        let path = Path::new(&out_dir).join(format!("action_stream_{}", name));
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+        path
+    }
+
+    fn set_action(tree_id: i32, value: Vec<u8>) -> ContextAction {
+        ContextAction::Set {
+            context_hash: None,
+            block_hash: None,
+            operation_hash: None,
+            tree_hash: None,
+            new_tree_hash: None,
+            tree_id,
+            new_tree_id: tree_id + 1,
+            start_time: 0.0,
+            end_time: 0.0,
+            key: vec!["data".to_string()],
+            value,
+            value_as_json: None,
+        }
+    }
+
+    fn read_all_frames<R: Read>(mut reader: R) -> Vec<(u64, Vec<u8>)> {
+        let mut actions = Vec::new();
+        while let Some((sequence, action)) = read_frame(&mut reader).unwrap() {
+            actions.push((sequence, bincode::serialize(&action).unwrap()));
+        }
+        actions
+    }
+
+    fn expected_frames(actions: &[ContextAction], first_sequence: u64) -> Vec<(u64, Vec<u8>)> {
+        actions
+            .iter()
+            .enumerate()
+            .map(|(i, action)| {
+                (
+                    first_sequence + i as u64,
+                    bincode::serialize(action).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn discard_logger() -> Logger {
+        Logger::root(slog::Discard, slog::o!())
+    }
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(
+            "unix:/tmp/actions.sock"
+                .parse::<ActionStreamTarget>()
+                .unwrap(),
+            ActionStreamTarget::Unix {
+                path: PathBuf::from("/tmp/actions.sock")
+            }
+        );
+        assert_eq!(
+            "tcp:127.0.0.1:7733".parse::<ActionStreamTarget>().unwrap(),
+            ActionStreamTarget::Tcp {
+                address: "127.0.0.1:7733".to_string()
+            }
+        );
+        assert_eq!(
+            "dir:/tmp/actions".parse::<ActionStreamTarget>().unwrap(),
+            ActionStreamTarget::SegmentDir {
+                path: PathBuf::from("/tmp/actions"),
+                segment_size: DEFAULT_SEGMENT_SIZE,
+                max_segments: DEFAULT_MAX_SEGMENTS,
+            }
+        );
+        assert!("file:/tmp/actions".parse::<ActionStreamTarget>().is_err());
+        assert!("tcp:".parse::<ActionStreamTarget>().is_err());
+    }
+
+    #[test]
+    fn test_stream_to_rotated_segments() {
+        let dir = test_dir("segments");
+        let actions: Vec<ContextAction> =
+            (0..100).map(|i| set_action(i, vec![i as u8; 64])).collect();
+        let frame_len = encode_frame(0, &actions[0]).unwrap().len() as u64;
+
+        let mut recorder = ActionStreamRecorder::new(
+            ActionStreamConfiguration {
+                target: ActionStreamTarget::SegmentDir {
+                    path: dir.clone(),
+                    segment_size: frame_len * 10,
+                    max_segments: 3,
+                },
+                buffer_size: 8,
+                overflow: ActionStreamOverflow::Block,
+            },
+            ActionStreamStatsRef::default(),
+            discard_logger(),
+        );
+        for action in &actions {
+            recorder.record(action).unwrap();
+        }
+        let counters = recorder.counters.clone();
+        drop(recorder);
+
+        let stats = counters.snapshot();
+        assert_eq!(stats.sent_frames, 100);
+        assert_eq!(stats.sent_bytes, 100 * frame_len);
+        assert_eq!(stats.dropped_frames, 0);
+        assert_eq!(stats.queued_frames, 0);
+
+        // only the last 3 segments (30 actions) are kept
+        let segments = list_segments(&dir).unwrap();
+        assert_eq!(
+            segments.iter().map(|(seq, _)| *seq).collect::<Vec<_>>(),
+            vec![7, 8, 9]
+        );
+        let mut streamed = Vec::new();
+        for (_, path) in segments {
+            streamed.extend(read_all_frames(BufReader::new(File::open(path).unwrap())));
+        }
+        assert_eq!(streamed, expected_frames(&actions[70..], 70));
+    }
+
+    #[test]
+    fn test_stream_to_tcp_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let consumer = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            read_all_frames(BufReader::new(stream))
+        });
+
+        let actions: Vec<ContextAction> = (0..10).map(|i| set_action(i, vec![i as u8])).collect();
+        let mut recorder = ActionStreamRecorder::new(
+            ActionStreamConfiguration {
+                target: ActionStreamTarget::Tcp { address },
+                buffer_size: 4,
+                overflow: ActionStreamOverflow::Block,
+            },
+            ActionStreamStatsRef::default(),
+            discard_logger(),
+        );
+        for action in &actions {
+            recorder.record(action).unwrap();
+        }
+        let counters = recorder.counters.clone();
+        drop(recorder);
+
+        assert_eq!(consumer.join().unwrap(), expected_frames(&actions, 0));
+        let stats = counters.snapshot();
+        assert_eq!(stats.sent_frames, 10);
+        assert_eq!(stats.connects, 1);
+        assert_eq!(stats.dropped_frames, 0);
+    }
+
+    #[test]
+    fn test_dropped_frames_leave_sequence_gap() {
+        // recorder without the writer thread, so the buffer is drained only by the test
+        let (sender, receiver) = sync_channel(2);
+        let stats = ActionStreamStatsRef::default();
+        let mut recorder = ActionStreamRecorder {
+            sender: Some(sender),
+            overflow: ActionStreamOverflow::Drop,
+            counters: stats.0.clone(),
+            next_sequence: 0,
+            recorded_blocks: 0,
+            thread: None,
+            log: discard_logger(),
+        };
+
+        let actions: Vec<ContextAction> = (0..7).map(|i| set_action(i, vec![i as u8])).collect();
+        // buffer is full after the first two actions, the next three are dropped
+        for action in &actions[..5] {
+            recorder.record(action).unwrap();
+        }
+        let mut received: Vec<u8> = receiver.try_iter().flatten().collect();
+        for action in &actions[5..] {
+            recorder.record(action).unwrap();
+        }
+        received.extend(receiver.try_iter().flatten());
+
+        let mut expected = expected_frames(&actions[..2], 0);
+        expected.extend(expected_frames(&actions[5..], 5));
+        assert_eq!(read_all_frames(received.as_slice()), expected);
+        assert_eq!(stats.stats().dropped_frames, 3);
+    }
+}
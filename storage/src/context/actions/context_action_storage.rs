@@ -2,13 +2,15 @@
 // SPDX-License-Identifier: MIT
 
 use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::mem;
-use std::ops::Range;
+use std::ops::{Bound, Range, RangeBounds};
 use std::str::FromStr;
 use std::sync::atomic::{AtomicU64, Ordering::SeqCst};
 use std::sync::Arc;
 
+use blake2::{Blake2b, Digest};
 use failure::Fail;
 use rocksdb::{Cache, ColumnFamilyDescriptor, SliceTransform, DB};
 use serde::{Deserialize, Serialize};
@@ -18,16 +20,20 @@ use crypto::hash::{
 };
 pub use tezos_context::channel::{get_end_time, get_start_time, get_time, ContextAction};
 use tezos_messages::base::signature_public_key_hash::{ConversionError, SignaturePublicKeyHash};
+use tezos_messages::p2p::encoding::block_header::Level;
 
+use crate::block_meta_storage::BlockMetaStorage;
 use crate::context::actions::{ActionRecorder, ActionRecorderError};
 use crate::num_from_slice;
 use crate::persistent::codec::range_from_idx_len;
-use crate::persistent::database::{default_table_options, RocksDbKeyValueSchema};
+use crate::persistent::database::{
+    ColumnFamilyTuning, Direction, IteratorMode, RocksDbKeyValueSchema,
+};
 use crate::persistent::sequence::{SequenceGenerator, SequenceNumber, Sequences};
 use crate::persistent::{
     BincodeEncoded, Decoder, Encoder, KeyValueSchema, KeyValueStoreWithSchema, SchemaError,
 };
-use crate::StorageError;
+use crate::{StorageError, SystemStorage};
 
 pub enum ContextHashType {
     Block,
@@ -73,6 +79,9 @@ pub struct ContextActionStorage {
     context_by_block_index: ContextActionByBlockHashIndex,
     context_by_contract_index: ContextActionByContractIndex,
     context_by_type_index: ContextActionByTypeIndex,
+    context_by_key_prefix_index: ContextActionByKeyPrefixIndex,
+    // Used to resolve levels of the blocks for the key prefix index (index is not populated without it)
+    block_meta_storage: Option<BlockMetaStorage>,
 
     generator: Arc<SequenceGenerator>,
     // Used to detect block change. Actions flow by one in order so when the last block changes
@@ -80,6 +89,14 @@ pub struct ContextActionStorage {
     last_block_hash: Arc<Option<BlockHash>>,
     // A basic counter for the block_action_ids. Reset to 0 for a differnt block (Not persisted)
     next_block_action_id: Arc<AtomicU64>,
+    // Level of the last block, if it is known (Not persisted)
+    last_block_level: Option<Level>,
+    // Ids of the actions of the last block by hashes of their key prefixes, they are written to the key prefix index
+    // with the commit of the block, so there is a single index entry per key prefix and block (Not persisted)
+    key_prefix_batch: HashMap<KeyPrefixHash, Vec<SequenceNumber>>,
+    // Stores the first action indexed by key prefixes, older actions are indexed by [backfill_key_prefix_index]
+    system_storage: SystemStorage,
+    key_prefix_index_start_stored: bool,
 }
 
 impl ContextActionStorage {
@@ -89,12 +106,25 @@ impl ContextActionStorage {
             generator: sequences.generator(Self::name()),
             context_by_block_index: ContextActionByBlockHashIndex::new(storage.clone()),
             context_by_contract_index: ContextActionByContractIndex::new(storage.clone()),
-            context_by_type_index: ContextActionByTypeIndex::new(storage),
+            context_by_type_index: ContextActionByTypeIndex::new(storage.clone()),
+            context_by_key_prefix_index: ContextActionByKeyPrefixIndex::new(storage.clone()),
+            block_meta_storage: None,
             last_block_hash: Arc::new(None),
             next_block_action_id: Arc::new(AtomicU64::new(0)),
+            last_block_level: None,
+            key_prefix_batch: HashMap::new(),
+            system_storage: SystemStorage::new(storage),
+            key_prefix_index_start_stored: false,
         }
     }
 
+    /// Levels of the blocks are resolved from `block_meta_storage`,
+    /// actions of the blocks with known level are indexed also by key prefixes
+    pub fn with_block_levels(mut self, block_meta_storage: BlockMetaStorage) -> Self {
+        self.block_meta_storage = Some(block_meta_storage);
+        self
+    }
+
     #[inline]
     pub fn put_action(
         &mut self,
@@ -104,12 +134,15 @@ impl ContextActionStorage {
         // logic for the block_action_id
         if let Some(last_block_hash) = &*self.last_block_hash {
             if block_hash != last_block_hash {
+                self.flush_key_prefix_batch()?;
                 self.next_block_action_id = Arc::new(AtomicU64::new(0));
                 self.last_block_hash = Arc::new(Some(block_hash.clone()));
+                self.last_block_level = self.resolve_block_level(block_hash)?;
             }
         } else {
             // first block hash
             self.last_block_hash = Arc::new(Some(block_hash.clone()));
+            self.last_block_level = self.resolve_block_level(block_hash)?;
         }
 
         // generate ID
@@ -131,6 +164,14 @@ impl ContextActionStorage {
             self.context_by_type_index
                 .put(&ContextActionByTypeIndexKey::new(action_type, id))?;
         }
+        if self.last_block_level.is_some() {
+            for prefix_hash in extract_key_prefix_hashes(action.action()) {
+                self.key_prefix_batch
+                    .entry(prefix_hash)
+                    .or_insert_with(Vec::new)
+                    .push(id);
+            }
+        }
         self.next_block_action_id
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
 
@@ -139,7 +180,51 @@ impl ContextActionStorage {
             .try_for_each(|contract_address| {
                 self.context_by_contract_index
                     .put(&ContextActionByContractIndexKey::new(contract_address, id))
-            })
+            })?;
+
+        if let ContextAction::Commit { .. } = action.action() {
+            self.flush_key_prefix_batch()?;
+        }
+        Ok(())
+    }
+
+    /// Writes ids of the actions of the last block to the key prefix index
+    fn flush_key_prefix_batch(&mut self) -> Result<(), StorageError> {
+        let level = match self.last_block_level {
+            Some(level) if !self.key_prefix_batch.is_empty() => level,
+            _ => return Ok(()),
+        };
+        if !self.key_prefix_index_start_stored {
+            let first_id = self
+                .key_prefix_batch
+                .values()
+                .map(|ids| ids[0])
+                .min()
+                .unwrap_or_default();
+            if self
+                .system_storage
+                .get_context_actions_key_prefix_indexed_from()?
+                .is_none()
+            {
+                self.system_storage
+                    .set_context_actions_key_prefix_indexed_from(first_id)?;
+            }
+            self.key_prefix_index_start_stored = true;
+        }
+        for (prefix_hash, ids) in self.key_prefix_batch.drain() {
+            self.context_by_key_prefix_index.put(
+                &ContextActionByKeyPrefixIndexKey::new(&prefix_hash, level, ids[0]),
+                &KeyPrefixIndexIds(ids),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn resolve_block_level(&self, block_hash: &BlockHash) -> Result<Option<Level>, StorageError> {
+        match &self.block_meta_storage {
+            Some(block_meta_storage) => resolve_block_level(block_meta_storage, block_hash),
+            None => Ok(None),
+        }
     }
 }
 
@@ -149,6 +234,7 @@ pub struct ContextActionStorageReader {
     context_by_block_index: ContextActionByBlockHashIndex,
     context_by_contract_index: ContextActionByContractIndex,
     context_by_type_index: ContextActionByTypeIndex,
+    context_by_key_prefix_index: ContextActionByKeyPrefixIndex,
 }
 
 impl ContextActionStorageReader {
@@ -157,8 +243,74 @@ impl ContextActionStorageReader {
             kv: storage.clone(),
             context_by_block_index: ContextActionByBlockHashIndex::new(storage.clone()),
             context_by_contract_index: ContextActionByContractIndex::new(storage.clone()),
-            context_by_type_index: ContextActionByTypeIndex::new(storage),
+            context_by_type_index: ContextActionByTypeIndex::new(storage.clone()),
+            context_by_key_prefix_index: ContextActionByKeyPrefixIndex::new(storage),
+        }
+    }
+
+    #[inline]
+    pub fn get_by_id(
+        &self,
+        id: SequenceNumber,
+    ) -> Result<Option<ContextActionRecordValue>, StorageError> {
+        self.kv.get(&id).map_err(StorageError::from)
+    }
+
+    /// Returns at most `limit` actions with a key under `prefix` from blocks in `levels` ordered by level (and id).
+    /// Paging continues from (including) the action `cursor` = (level of its block, id).
+    ///
+    /// Only prefixes up to [MAX_INDEXED_KEY_DEPTH] parts are indexed, longer prefixes are filtered while loading.
+    pub fn get_by_key_prefix<R: RangeBounds<Level>>(
+        &self,
+        prefix: &[String],
+        levels: R,
+        cursor: Option<(Level, SequenceNumber)>,
+        limit: usize,
+    ) -> Result<Vec<ContextActionRecordValue>, StorageError> {
+        let from_level = match levels.start_bound() {
+            Bound::Included(level) => *level,
+            Bound::Excluded(level) => level.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let to_level = match levels.end_bound() {
+            Bound::Included(level) => Some(*level),
+            Bound::Excluded(level) => Some(level.saturating_sub(1)),
+            Bound::Unbounded => None,
+        };
+        let (from_level, from_id) = match cursor {
+            Some((cursor_level, cursor_id)) if cursor_level >= from_level => {
+                (cursor_level, cursor_id)
+            }
+            _ => (from_level, std::u64::MIN),
+        };
+
+        let mut result = Vec::new();
+        if limit == 0 {
+            return Ok(result);
         }
+        // entries of the cursor level are read from the start, ids of the blocks before the cursor are skipped
+        for entry in self
+            .context_by_key_prefix_index
+            .get_by_key_prefix_iterator(prefix, from_level, to_level)?
+        {
+            let (level, ids) = entry?;
+            for id in ids {
+                if level == from_level && id < from_id {
+                    continue;
+                }
+                let value = self.kv.get(&id)?.ok_or(StorageError::MissingKey)?;
+                if extract_keys(&value.action)
+                    .iter()
+                    .any(|key| key.starts_with(prefix))
+                {
+                    result.push(value);
+                    if result.len() >= limit {
+                        return Ok(result);
+                    }
+                }
+            }
+        }
+        Ok(result)
     }
 
     #[inline]
@@ -396,6 +548,152 @@ fn extract_contract_addresses(value: &ContextActionRecordValue) -> Vec<ContractA
         .collect()
 }
 
+/// Keys touched by the action
+fn extract_keys(action: &ContextAction) -> Vec<&Vec<String>> {
+    match action {
+        ContextAction::Set { key, .. }
+        | ContextAction::Delete { key, .. }
+        | ContextAction::RemoveRecursively { key, .. }
+        | ContextAction::Mem { key, .. }
+        | ContextAction::DirMem { key, .. }
+        | ContextAction::Get { key, .. }
+        | ContextAction::Fold { key, .. } => vec![key],
+        ContextAction::Copy {
+            from_key, to_key, ..
+        } => vec![from_key, to_key],
+        _ => vec![],
+    }
+}
+
+/// Hashes of all prefixes (up to [MAX_INDEXED_KEY_DEPTH] parts) of all keys of the action
+fn extract_key_prefix_hashes(action: &ContextAction) -> HashSet<KeyPrefixHash> {
+    let mut prefix_hashes = HashSet::new();
+    for key in extract_keys(action) {
+        for depth in 1..=std::cmp::min(key.len(), MAX_INDEXED_KEY_DEPTH) {
+            prefix_hashes.insert(hash_key_prefix(&key[..depth]));
+        }
+    }
+    prefix_hashes
+}
+
+/// Parts are length prefixed, so `["ab", "c"]` and `["a", "bc"]` has different hashes
+fn hash_key_prefix(prefix: &[String]) -> KeyPrefixHash {
+    let mut hasher = Blake2b::new();
+    for part in prefix {
+        hasher.update(&(part.len() as u32).to_be_bytes());
+        hasher.update(part.as_bytes());
+    }
+    let mut prefix_hash = [0_u8; ContextActionByKeyPrefixIndexKey::LEN_PREFIX_HASH];
+    prefix_hash
+        .copy_from_slice(&hasher.finalize()[..ContextActionByKeyPrefixIndexKey::LEN_PREFIX_HASH]);
+    prefix_hash
+}
+
+/// Returns block hash of the action, if the action belongs to a block
+pub fn extract_block_hash(action: &ContextAction) -> Option<&Vec<u8>> {
+    match action {
+        ContextAction::Set { block_hash, .. }
+        | ContextAction::Copy { block_hash, .. }
+        | ContextAction::Delete { block_hash, .. }
+        | ContextAction::RemoveRecursively { block_hash, .. }
+        | ContextAction::Mem { block_hash, .. }
+        | ContextAction::DirMem { block_hash, .. }
+        | ContextAction::Get { block_hash, .. }
+        | ContextAction::Fold { block_hash, .. }
+        | ContextAction::Commit { block_hash, .. } => block_hash.as_ref(),
+        ContextAction::Checkout { .. } | ContextAction::Shutdown => None,
+    }
+}
+
+/// Level of the block from block meta storage, `None` if the block is not known
+pub fn resolve_block_level(
+    block_meta_storage: &BlockMetaStorage,
+    block_hash: &BlockHash,
+) -> Result<Option<Level>, StorageError> {
+    Ok(block_meta_storage.get(block_hash)?.map(|meta| meta.level()))
+}
+
+/// Indexes by key prefixes the actions, which were recorded before the first action indexed while recording
+/// (recorded before the index was introduced or without known levels of blocks).
+/// Progress is stored after every block, so backfill continues after restart. Returns count of indexed blocks.
+pub fn backfill_key_prefix_index(
+    storage: Arc<DB>,
+    block_meta_storage: &BlockMetaStorage,
+) -> Result<usize, StorageError> {
+    let kv: Arc<ContextActionStorageKV> = storage.clone();
+    let context_by_key_prefix_index = ContextActionByKeyPrefixIndex::new(storage.clone());
+    let mut system_storage = SystemStorage::new(storage);
+
+    let from_id = system_storage
+        .get_context_actions_key_prefix_backfill()?
+        .unwrap_or(std::u64::MIN);
+    // without actions indexed while recording, all stored actions are indexed
+    let to_id = match system_storage.get_context_actions_key_prefix_indexed_from()? {
+        Some(to_id) => to_id,
+        None => match kv.iterator(IteratorMode::End)?.next() {
+            Some((last_id, _)) => last_id? + 1,
+            None => return Ok(0),
+        },
+    };
+    if from_id >= to_id {
+        return Ok(0);
+    }
+
+    let flush =
+        |level: Option<Level>, batch: &mut HashMap<KeyPrefixHash, Vec<SequenceNumber>>| match level
+        {
+            Some(level) => batch.drain().try_for_each(|(prefix_hash, ids)| {
+                context_by_key_prefix_index.put(
+                    &ContextActionByKeyPrefixIndexKey::new(&prefix_hash, level, ids[0]),
+                    &KeyPrefixIndexIds(ids),
+                )
+            }),
+            None => {
+                batch.clear();
+                Ok(())
+            }
+        };
+
+    let mut indexed_blocks = 0;
+    let mut block: Option<(Vec<u8>, Option<Level>)> = None;
+    let mut batch = HashMap::new();
+    for (id, value) in kv.iterator(IteratorMode::From(&from_id, Direction::Forward))? {
+        let (id, value) = (id?, value?);
+        if id >= to_id {
+            break;
+        }
+        let block_hash = match extract_block_hash(&value.action) {
+            Some(block_hash) => block_hash,
+            None => continue,
+        };
+        let block_changed = match &block {
+            Some((last_block_hash, _)) => last_block_hash != block_hash,
+            None => true,
+        };
+        if block_changed {
+            if let Some((_, level)) = block.take() {
+                flush(level, &mut batch)?;
+                indexed_blocks += 1;
+                system_storage.set_context_actions_key_prefix_backfill(id)?;
+            }
+            let level = match BlockHash::try_from(&block_hash[..]) {
+                Ok(hash) => resolve_block_level(block_meta_storage, &hash)?,
+                Err(_) => None,
+            };
+            block = Some((block_hash.clone(), level));
+        }
+        for prefix_hash in extract_key_prefix_hashes(&value.action) {
+            batch.entry(prefix_hash).or_insert_with(Vec::new).push(id);
+        }
+    }
+    if let Some((_, level)) = block {
+        flush(level, &mut batch)?;
+        indexed_blocks += 1;
+    }
+    system_storage.set_context_actions_key_prefix_backfill(to_id)?;
+    Ok(indexed_blocks)
+}
+
 /// Extracts contract id for index from contracts keys - see [contract_id_to_contract_address]
 ///
 /// Relevant keys for contract index should looks like:
@@ -487,8 +785,11 @@ impl KeyValueSchema for ContextActionByBlockHashIndex {
 }
 
 impl RocksDbKeyValueSchema for ContextActionByBlockHashIndex {
-    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        let mut cf_opts = default_table_options(cache);
+    fn descriptor_with_tuning(
+        cache: &Cache,
+        tuning: &ColumnFamilyTuning,
+    ) -> ColumnFamilyDescriptor {
+        let mut cf_opts = tuning.table_options(cache);
         cf_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(
             ContextActionByBlockHashKey::LEN_BLOCK_HASH,
         ));
@@ -641,8 +942,11 @@ impl KeyValueSchema for ContextActionByContractIndex {
 }
 
 impl RocksDbKeyValueSchema for ContextActionByContractIndex {
-    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        let mut cf_opts = default_table_options(cache);
+    fn descriptor_with_tuning(
+        cache: &Cache,
+        tuning: &ColumnFamilyTuning,
+    ) -> ColumnFamilyDescriptor {
+        let mut cf_opts = tuning.table_options(cache);
         cf_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(
             ContextActionByContractIndexKey::LEN_CONTRACT_ADDRESS,
         ));
@@ -865,8 +1169,11 @@ impl KeyValueSchema for ContextActionByTypeIndex {
 }
 
 impl RocksDbKeyValueSchema for ContextActionByTypeIndex {
-    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        let mut cf_opts = default_table_options(cache);
+    fn descriptor_with_tuning(
+        cache: &Cache,
+        tuning: &ColumnFamilyTuning,
+    ) -> ColumnFamilyDescriptor {
+        let mut cf_opts = tuning.table_options(cache);
         cf_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(mem::size_of::<
             ContextActionType,
         >()));
@@ -965,6 +1272,161 @@ impl Encoder for ContextActionByTypeIndexKey {
     }
 }
 
+/// Max count of key parts indexed by [ContextActionByKeyPrefixIndex]
+pub const MAX_INDEXED_KEY_DEPTH: usize = 10;
+
+pub type KeyPrefixHash = [u8; ContextActionByKeyPrefixIndexKey::LEN_PREFIX_HASH];
+
+/// Index data as `key prefix + block level + first ID -> all IDs of the block`.
+///
+/// Index is composed from:
+/// * hash of the key prefix (every prefix of the action keys up to [MAX_INDEXED_KEY_DEPTH] parts)
+/// * block level
+/// * ID of the first action of the block with the key prefix
+///
+/// Value holds IDs of all actions of the block with the key prefix, so there is a single entry per key prefix and block.
+/// This allows for fast search of context actions with keys under a prefix in a range of levels.
+pub struct ContextActionByKeyPrefixIndex {
+    kv: Arc<ContextActionByKeyPrefixIndexKV>,
+}
+
+pub type ContextActionByKeyPrefixIndexKV =
+    dyn KeyValueStoreWithSchema<ContextActionByKeyPrefixIndex> + Sync + Send;
+
+impl ContextActionByKeyPrefixIndex {
+    fn new(kv: Arc<ContextActionByKeyPrefixIndexKV>) -> Self {
+        Self { kv }
+    }
+
+    #[inline]
+    fn put(
+        &self,
+        key: &ContextActionByKeyPrefixIndexKey,
+        ids: &KeyPrefixIndexIds,
+    ) -> Result<(), StorageError> {
+        self.kv.put(key, ids).map_err(StorageError::from)
+    }
+
+    /// Iterates (level, ids of the block) ordered by level from `from_level` up to `to_level` (including)
+    #[inline]
+    fn get_by_key_prefix_iterator<'a>(
+        &'a self,
+        prefix: &[String],
+        from_level: Level,
+        to_level: Option<Level>,
+    ) -> Result<
+        impl Iterator<Item = Result<(Level, Vec<SequenceNumber>), StorageError>> + 'a,
+        StorageError,
+    > {
+        let indexed_prefix = &prefix[..std::cmp::min(prefix.len(), MAX_INDEXED_KEY_DEPTH)];
+        let iterate_from_key = ContextActionByKeyPrefixIndexKey::new(
+            &hash_key_prefix(indexed_prefix),
+            from_level.max(0),
+            std::u64::MIN,
+        );
+
+        Ok(self
+            .kv
+            .prefix_iterator(&iterate_from_key)?
+            .map(|(key, ids)| Ok((key?, ids?)))
+            .take_while(move |entry| match (entry, to_level) {
+                (Ok((key, _)), Some(to_level)) => key.level <= to_level,
+                _ => true,
+            })
+            .map(|entry| entry.map(|(key, ids)| (key.level, ids.0))))
+    }
+}
+
+/// IDs of the actions of one block with the same key prefix
+#[derive(Serialize, Deserialize)]
+pub struct KeyPrefixIndexIds(pub Vec<SequenceNumber>);
+
+impl BincodeEncoded for KeyPrefixIndexIds {}
+
+impl KeyValueSchema for ContextActionByKeyPrefixIndex {
+    type Key = ContextActionByKeyPrefixIndexKey;
+    type Value = KeyPrefixIndexIds;
+}
+
+impl RocksDbKeyValueSchema for ContextActionByKeyPrefixIndex {
+    fn descriptor_with_tuning(
+        cache: &Cache,
+        tuning: &ColumnFamilyTuning,
+    ) -> ColumnFamilyDescriptor {
+        let mut cf_opts = tuning.table_options(cache);
+        cf_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(
+            ContextActionByKeyPrefixIndexKey::LEN_PREFIX_HASH,
+        ));
+        cf_opts.set_memtable_prefix_bloom_ratio(0.2);
+        ColumnFamilyDescriptor::new(Self::name(), cf_opts)
+    }
+
+    fn name() -> &'static str {
+        "context_by_key_prefix_storage"
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub struct ContextActionByKeyPrefixIndexKey {
+    pub prefix_hash: KeyPrefixHash,
+    pub level: Level,
+    pub id: SequenceNumber,
+}
+
+impl ContextActionByKeyPrefixIndexKey {
+    const LEN_PREFIX_HASH: usize = 16;
+    const LEN_LEVEL: usize = mem::size_of::<u32>();
+    const LEN_ID: usize = mem::size_of::<SequenceNumber>();
+    const LEN_TOTAL: usize = Self::LEN_PREFIX_HASH + Self::LEN_LEVEL + Self::LEN_ID;
+
+    const IDX_LEVEL: usize = Self::LEN_PREFIX_HASH;
+    const IDX_ID: usize = Self::IDX_LEVEL + Self::LEN_LEVEL;
+
+    pub fn new(prefix_hash: &KeyPrefixHash, level: Level, id: SequenceNumber) -> Self {
+        Self {
+            prefix_hash: *prefix_hash,
+            level,
+            id,
+        }
+    }
+}
+
+/// Decoder for `ContextActionByKeyPrefixIndexKey`
+///
+/// * bytes layout `[prefix_hash(16)][level(4)][id(8)]`
+impl Decoder for ContextActionByKeyPrefixIndexKey {
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        if Self::LEN_TOTAL == bytes.len() {
+            let mut prefix_hash = [0_u8; Self::LEN_PREFIX_HASH];
+            prefix_hash.copy_from_slice(&bytes[..Self::LEN_PREFIX_HASH]);
+            let level = num_from_slice!(bytes, Self::IDX_LEVEL, u32);
+            let id = num_from_slice!(bytes, Self::IDX_ID, SequenceNumber);
+            Ok(Self {
+                prefix_hash,
+                level: Level::try_from(level).map_err(|_| SchemaError::DecodeError)?,
+                id,
+            })
+        } else {
+            Err(SchemaError::DecodeError)
+        }
+    }
+}
+
+/// Encoder for `ContextActionByKeyPrefixIndexKey`
+///
+/// * bytes layout `[prefix_hash(16)][level(4)][id(8)]`, level is unsigned, so keys are ordered by level
+impl Encoder for ContextActionByKeyPrefixIndexKey {
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        let level = u32::try_from(self.level).map_err(|_| SchemaError::EncodeError)?;
+        let mut result = Vec::with_capacity(Self::LEN_TOTAL);
+        result.extend_from_slice(&self.prefix_hash);
+        result.extend_from_slice(&level.to_be_bytes());
+        result.extend_from_slice(&self.id.to_be_bytes());
+        assert_eq!(result.len(), Self::LEN_TOTAL, "Result length mismatch");
+        Ok(result)
+    }
+}
+
 #[repr(u16)]
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub enum ContextActionType {
@@ -1196,6 +1658,10 @@ mod tests {
 
     use crypto::hash::HashType;
 
+    use crate::block_meta_storage::Meta;
+    use crate::tests_common::TmpStorage;
+    use crate::PersistentStorage;
+
     use super::*;
 
     #[test]
@@ -1392,6 +1858,136 @@ mod tests {
         assert_eq!(0, contract_address.len());
     }
 
+    #[test]
+    fn context_record_key_prefix_key_encoded_equals_decoded() -> Result<(), Error> {
+        let expected = ContextActionByKeyPrefixIndexKey::new(
+            &hash_key_prefix(&to_key(vec!["data", "contracts"])),
+            1_234_567,
+            6548654,
+        );
+        let encoded_bytes = expected.encode()?;
+        let decoded = ContextActionByKeyPrefixIndexKey::decode(&encoded_bytes)?;
+        assert_eq!(expected, decoded);
+
+        // keys are ordered by level
+        let lower =
+            ContextActionByKeyPrefixIndexKey::new(&expected.prefix_hash, 255, 7).encode()?;
+        let higher =
+            ContextActionByKeyPrefixIndexKey::new(&expected.prefix_hash, 256, 1).encode()?;
+        assert!(lower < higher);
+        Ok(())
+    }
+
+    #[test]
+    fn extract_key_prefix_hashes_all_prefixes() {
+        let hashes = extract_key_prefix_hashes(action(vec!["data", "contracts", "index"]).action());
+        assert_eq!(3, hashes.len());
+        assert!(hashes.contains(&hash_key_prefix(&to_key(vec!["data"]))));
+        assert!(hashes.contains(&hash_key_prefix(&to_key(vec!["data", "contracts"]))));
+        assert!(!hashes.contains(&hash_key_prefix(&to_key(vec!["contracts"]))));
+
+        // parts are not just concatenated
+        assert_ne!(
+            hash_key_prefix(&to_key(vec!["ab", "c"])),
+            hash_key_prefix(&to_key(vec!["a", "bc"]))
+        );
+
+        // deep keys are indexed just up to max depth
+        let deep_key: Vec<&str> = vec!["a"; MAX_INDEXED_KEY_DEPTH + 5];
+        assert_eq!(
+            MAX_INDEXED_KEY_DEPTH,
+            extract_key_prefix_hashes(action(deep_key).action()).len()
+        );
+    }
+
+    #[test]
+    fn test_key_prefix_index_entry_per_block() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create_to_out_dir("__ctx_actions_key_prefix_per_block")?;
+        let block_1 = store_block_level(tmp_storage.storage(), BLOCK_HASH_1, 5)?;
+        let block_2 = store_block_level(tmp_storage.storage(), BLOCK_HASH_2, 6)?;
+        let db = tmp_storage.storage().merkle_context_actions().unwrap();
+        let mut storage = ContextActionStorage::new(db.clone(), tmp_storage.storage().seq())
+            .with_block_levels(BlockMetaStorage::new(tmp_storage.storage()));
+
+        // ids 0..=3 for the first block, 4..=6 for the second one
+        for key in &[vec!["data", "a"], vec!["data", "b"], vec!["data", "a"]] {
+            storage.put_action(&block_1, set_action(&block_1, key.clone()))?;
+        }
+        storage.put_action(&block_1, commit_action(&block_1))?;
+        for key in &[vec!["data", "a"], vec!["other"]] {
+            storage.put_action(&block_2, set_action(&block_2, key.clone()))?;
+        }
+        storage.put_action(&block_2, commit_action(&block_2))?;
+
+        // single entry per key prefix and block
+        let index = ContextActionByKeyPrefixIndex::new(db.clone());
+        let entries = index
+            .get_by_key_prefix_iterator(&to_key(vec!["data"]), 0, None)?
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(entries, vec![(5, vec![0, 1, 2]), (6, vec![4])]);
+        let entries = index
+            .get_by_key_prefix_iterator(&to_key(vec!["data", "a"]), 0, Some(5))?
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(entries, vec![(5, vec![0, 2])]);
+        assert_eq!(
+            SystemStorage::new(db.clone()).get_context_actions_key_prefix_indexed_from()?,
+            Some(0)
+        );
+
+        // paging continues from the cursor
+        let reader = ContextActionStorageReader::new(db);
+        let prefix = to_key(vec!["data"]);
+        let page = reader.get_by_key_prefix(&prefix, .., None, 2)?;
+        assert_eq!(page.iter().map(|a| a.id()).collect::<Vec<_>>(), vec![0, 1]);
+        let page = reader.get_by_key_prefix(&prefix, .., Some((5, 2)), 2)?;
+        assert_eq!(page.iter().map(|a| a.id()).collect::<Vec<_>>(), vec![2, 4]);
+        let page = reader.get_by_key_prefix(&prefix, 6.., None, 10)?;
+        assert_eq!(page.iter().map(|a| a.id()).collect::<Vec<_>>(), vec![4]);
+        assert!(reader.get_by_key_prefix(&prefix, .., None, 0)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_backfill_key_prefix_index() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create_to_out_dir("__ctx_actions_key_prefix_backfill")?;
+        let block_1 = store_block_level(tmp_storage.storage(), BLOCK_HASH_1, 5)?;
+        let block_2 = store_block_level(tmp_storage.storage(), BLOCK_HASH_2, 6)?;
+        let db = tmp_storage.storage().merkle_context_actions().unwrap();
+        let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+
+        // the first block is recorded without levels, so it is not indexed while recording
+        let mut storage = ContextActionStorage::new(db.clone(), tmp_storage.storage().seq());
+        storage.put_action(&block_1, set_action(&block_1, vec!["data", "a"]))?;
+        storage.put_action(&block_1, commit_action(&block_1))?;
+        let mut storage = ContextActionStorage::new(db.clone(), tmp_storage.storage().seq())
+            .with_block_levels(block_meta_storage.clone());
+        storage.put_action(&block_2, set_action(&block_2, vec!["data", "a"]))?;
+        storage.put_action(&block_2, commit_action(&block_2))?;
+
+        let reader = ContextActionStorageReader::new(db.clone());
+        let prefix = to_key(vec!["data", "a"]);
+        let ids = |reader: &ContextActionStorageReader| -> Result<Vec<SequenceNumber>, Error> {
+            Ok(reader
+                .get_by_key_prefix(&prefix, .., None, 10)?
+                .iter()
+                .map(|a| a.id())
+                .collect())
+        };
+        assert_eq!(ids(&reader)?, vec![2]);
+
+        // only the actions recorded before the first indexed action are backfilled, just once
+        assert_eq!(
+            backfill_key_prefix_index(db.clone(), &block_meta_storage)?,
+            1
+        );
+        assert_eq!(ids(&reader)?, vec![0, 2]);
+        assert_eq!(backfill_key_prefix_index(db, &block_meta_storage)?, 0);
+        assert_eq!(ids(&reader)?, vec![0, 2]);
+
+        Ok(())
+    }
+
     #[test]
     fn test_calculate_block_action_details() {
         let action_1 = action_get_with_time(1.0, 2.0);
@@ -1406,6 +2002,55 @@ mod tests {
         assert_eq!(expected, block_details);
     }
 
+    const BLOCK_HASH_1: &str = "BKyQ9EofHrgaZKENioHyP4FZNsTmiSEcVmcghgzCC9cGhE7oCET";
+    const BLOCK_HASH_2: &str = "BLaf78njreWdt2WigJjM9e3ecEdVKm5ehahUfYBKvcWvZ8vfTcJ";
+
+    fn store_block_level(
+        persistent_storage: &PersistentStorage,
+        block_hash: &str,
+        level: Level,
+    ) -> Result<BlockHash, Error> {
+        let block_hash: BlockHash = block_hash.try_into()?;
+        BlockMetaStorage::new(persistent_storage).put(
+            &block_hash,
+            &Meta::new(true, None, level, "NetXgtSLGNJvNye".try_into()?),
+        )?;
+        Ok(block_hash)
+    }
+
+    fn set_action(block_hash: &BlockHash, key: Vec<&str>) -> ContextAction {
+        ContextAction::Set {
+            context_hash: None,
+            block_hash: Some(block_hash.as_ref().clone()),
+            operation_hash: None,
+            tree_hash: None,
+            new_tree_hash: None,
+            tree_id: 0,
+            new_tree_id: 0,
+            key: to_key(key),
+            value: vec![1],
+            value_as_json: None,
+            start_time: 0 as f64,
+            end_time: 0 as f64,
+        }
+    }
+
+    fn commit_action(block_hash: &BlockHash) -> ContextAction {
+        ContextAction::Commit {
+            parent_context_hash: None,
+            block_hash: Some(block_hash.as_ref().clone()),
+            new_context_hash: vec![0; 32],
+            tree_hash: None,
+            tree_id: 0,
+            start_time: 0 as f64,
+            end_time: 0 as f64,
+            author: "Tezos".to_string(),
+            message: "Block".to_string(),
+            date: 0,
+            parents: Vec::new(),
+        }
+    }
+
     fn to_key(key: Vec<&str>) -> Vec<String> {
         key.into_iter().map(|k| k.to_string()).collect()
     }
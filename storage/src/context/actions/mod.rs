@@ -12,11 +12,13 @@ use strum_macros::EnumIter;
 
 use tezos_context::channel::ContextAction;
 
+use crate::context::actions::action_stream::ActionStreamConfiguration;
 use crate::context::merkle::hash::HashingError;
 use crate::context::{EntryHash, TreeId};
 
 pub mod action_file;
 pub mod action_file_storage;
+pub mod action_stream;
 pub mod context_action_storage;
 
 pub const ROCKSDB: &str = "rocksdb";
@@ -25,7 +27,12 @@ pub const ROCKSDB: &str = "rocksdb";
 pub enum ContextActionStoreBackend {
     NoneBackend,
     RocksDB,
-    FileStorage { path: PathBuf },
+    FileStorage {
+        path: PathBuf,
+    },
+    StreamStorage {
+        configuration: ActionStreamConfiguration,
+    },
 }
 
 impl ContextActionStoreBackend {
@@ -41,6 +48,7 @@ impl ContextActionStoreBackend {
         match self {
             ContextActionStoreBackend::RocksDB => vec![ROCKSDB],
             ContextActionStoreBackend::FileStorage { .. } => vec!["file"],
+            ContextActionStoreBackend::StreamStorage { .. } => vec!["stream"],
             ContextActionStoreBackend::NoneBackend => vec!["none"],
         }
     }
@@ -118,3 +126,19 @@ pub fn get_tree_id(action: &ContextAction) -> Option<TreeId> {
         ContextAction::Checkout { .. } | ContextAction::Shutdown => None,
     }
 }
+
+pub fn get_new_tree_id(action: &ContextAction) -> Option<TreeId> {
+    match &action {
+        ContextAction::Set { new_tree_id, .. }
+        | ContextAction::Copy { new_tree_id, .. }
+        | ContextAction::Delete { new_tree_id, .. }
+        | ContextAction::RemoveRecursively { new_tree_id, .. } => Some(*new_tree_id),
+        ContextAction::Get { .. }
+        | ContextAction::Mem { .. }
+        | ContextAction::DirMem { .. }
+        | ContextAction::Commit { .. }
+        | ContextAction::Fold { .. }
+        | ContextAction::Checkout { .. }
+        | ContextAction::Shutdown => None,
+    }
+}
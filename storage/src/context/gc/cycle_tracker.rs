@@ -0,0 +1,217 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Announces applied blocks and starts of cycles to the context garbage collector.
+//!
+//! Commit of the block is announced by [ContextApi::block_applied] right after it was stored.
+//! Cycle of the block is computed from its level and `blocks_per_cycle` protocol constant (read from the just
+//! committed context), a new cycle is announced by [ContextApi::cycle_started] by the first block of a higher cycle.
+//! After restart, the first announced block starts a new cycle only if it is the first block of its cycle,
+//! reapplied blocks of a lower cycle (e.g. after reorg) do not start any cycle.
+
+use std::collections::HashMap;
+
+use crypto::hash::BlockHash;
+
+use crate::block_storage::BlockLevel;
+use crate::context::ContextApi;
+use crate::history_mode::{read_blocks_per_cycle, BlockPrunerError};
+use crate::{BlockStorage, BlockStorageReader, PersistentStorage};
+
+pub struct CycleTracker {
+    block_storage: BlockStorage,
+    /// Cache of `blocks_per_cycle` constant per protocol (`proto` of block header)
+    blocks_per_cycle: HashMap<u8, BlockLevel>,
+    /// Cycle of the last announced block
+    last_cycle: Option<BlockLevel>,
+}
+
+impl CycleTracker {
+    pub fn new(persistent_storage: &PersistentStorage) -> Self {
+        Self {
+            block_storage: BlockStorage::new(persistent_storage),
+            blocks_per_cycle: HashMap::new(),
+            last_cycle: None,
+        }
+    }
+
+    /// Announces commit of the `block_hash` stored in the `context`, new cycle is announced before the commit,
+    /// so the commit belongs to the cycle of its block.
+    /// Returns true, if the block started a new cycle.
+    pub fn block_committed(
+        &mut self,
+        context: &dyn ContextApi,
+        block_hash: &BlockHash,
+    ) -> Result<bool, BlockPrunerError> {
+        let cycle_started = self.starts_cycle(context, block_hash)?;
+        if cycle_started {
+            context.cycle_started()?;
+        }
+        context.block_applied()?;
+        Ok(cycle_started)
+    }
+
+    fn starts_cycle(
+        &mut self,
+        context: &dyn ContextApi,
+        block_hash: &BlockHash,
+    ) -> Result<bool, BlockPrunerError> {
+        let block = match self.block_storage.get(block_hash)? {
+            Some(block) => block,
+            None => return Ok(false),
+        };
+        let level = block.header.level();
+        if level < 1 {
+            return Ok(false);
+        }
+        let proto = block.header.proto();
+        let blocks_per_cycle = match self.blocks_per_cycle.get(&proto) {
+            Some(blocks_per_cycle) => *blocks_per_cycle,
+            None => match read_blocks_per_cycle(context, &block)? {
+                Some(blocks_per_cycle) => {
+                    self.blocks_per_cycle.insert(proto, blocks_per_cycle);
+                    blocks_per_cycle
+                }
+                // e.g. genesis protocol has no cycles
+                None => return Ok(false),
+            },
+        };
+
+        let cycle = (level - 1) / blocks_per_cycle;
+        let cycle_started = match self.last_cycle {
+            Some(last_cycle) => cycle > last_cycle,
+            None => (level - 1) % blocks_per_cycle == 0,
+        };
+        if cycle_started || self.last_cycle.is_none() {
+            self.last_cycle = Some(cycle);
+        }
+        Ok(cycle_started)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+    use std::sync::{Arc, RwLock};
+
+    use failure::Error;
+
+    use crypto::hash::ContextHash;
+    use tezos_messages::p2p::encoding::block_header::BlockHeaderBuilder;
+
+    use crate::context::gc::refcount_gced::RefCountGCed;
+    use crate::context::kv_store::in_memory_backend::InMemoryBackend;
+    use crate::context::merkle::merkle_storage::MerkleStorage;
+    use crate::context::TezedgeContext;
+    use crate::tests_common::TmpStorage;
+    use crate::{context_key, BlockHeaderWithHash};
+
+    use super::*;
+
+    const PROTO: u8 = 1;
+
+    fn store_block(
+        block_storage: &BlockStorage,
+        level: BlockLevel,
+        context_hash: ContextHash,
+    ) -> Result<BlockHash, Error> {
+        let header = BlockHeaderBuilder::default()
+            .level(level)
+            .proto(PROTO)
+            .predecessor("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?)
+            .timestamp(5_635_634)
+            .validation_pass(0)
+            .operations_hash("LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc".try_into()?)
+            .fitness(vec![])
+            .context(context_hash)
+            .protocol_data(vec![])
+            .build()
+            .unwrap();
+        let block = BlockHeaderWithHash::new(header)?;
+        block_storage.put_block_header(&block)?;
+        Ok(block.hash)
+    }
+
+    #[test]
+    fn test_announces_blocks_and_cycles() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create_to_out_dir("__cycle_tracker_announces")?;
+        let block_storage = BlockStorage::new(tmp_storage.storage());
+        let gc = RefCountGCed::new(
+            Box::new(InMemoryBackend::new()),
+            sled::Config::new().temporary(true).open()?,
+            0,
+        )?;
+        let merkle = Arc::new(RwLock::new(MerkleStorage::new(Box::new(gc))));
+        let mut context = TezedgeContext::new(None, merkle.clone());
+        let mut tracker = CycleTracker::new(tmp_storage.storage());
+        tracker.blocks_per_cycle.insert(PROTO, 4);
+
+        let mut cycles_started = Vec::new();
+        let mut last_context_hash = None;
+        for level in 3..=10 {
+            context.set(
+                &None,
+                level,
+                &context_key!("data/level"),
+                level.to_be_bytes().to_vec(),
+            )?;
+            let context_hash = context.commit(
+                &"BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?,
+                &None,
+                "Tezos".to_string(),
+                "Block".to_string(),
+                0,
+            )?;
+            let block_hash = store_block(&block_storage, level, context_hash.clone())?;
+            last_context_hash = Some(context_hash);
+            if tracker.block_committed(&context, &block_hash)? {
+                cycles_started.push(level);
+            }
+        }
+
+        // cycles start with levels 5 and 9, the first block (level 3) is not the first one of its cycle
+        assert_eq!(cycles_started, vec![5, 9]);
+        // with no retained cycle, only commits of the current cycle are kept
+        let stats = merkle.read().unwrap().get_merkle_stats()?.gc_stats.unwrap();
+        assert_eq!(stats.collected_cycles, 2);
+        // the first commit of a cycle belongs to the new cycle, so the head context is kept
+        assert!(context
+            .get_key_from_history(&last_context_hash.unwrap(), &context_key!("data/level"))?
+            .is_some());
+
+        // reapplied block of older cycle does not start a new cycle
+        let context_hash = context.commit(
+            &"BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?,
+            &None,
+            "Tezos".to_string(),
+            "Block".to_string(),
+            0,
+        )?;
+        let block_hash = store_block(&block_storage, 5, context_hash)?;
+        assert!(!tracker.block_committed(&context, &block_hash)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_first_block_of_cycle_after_restart() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create_to_out_dir("__cycle_tracker_restart")?;
+        let block_storage = BlockStorage::new(tmp_storage.storage());
+        let mut context = TezedgeContext::new(None, tmp_storage.storage().merkle());
+        let mut tracker = CycleTracker::new(tmp_storage.storage());
+        tracker.blocks_per_cycle.insert(PROTO, 4);
+
+        context.set(&None, 0, &context_key!("data/a"), vec![1])?;
+        let context_hash = context.commit(
+            &"BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?,
+            &None,
+            "Tezos".to_string(),
+            "Block".to_string(),
+            0,
+        )?;
+        let block_hash = store_block(&block_storage, 9, context_hash)?;
+        assert!(tracker.block_committed(&context, &block_hash)?);
+
+        Ok(())
+    }
+}
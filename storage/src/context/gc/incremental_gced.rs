@@ -0,0 +1,857 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Incremental garbage collector, which marks and sweeps on a dedicated thread.
+//!
+//! Commits of applied blocks are the roots, commits of the last `retained_cycles` cycles are kept.
+//! When a cycle falls out of the retention window, the collector:
+//! 1. marks every entry reachable from the retained commits (including commits applied during the collection),
+//!    subtrees already marked are not visited again,
+//! 2. sweeps every entry reachable from the expired commits, which was not marked - marked subtrees are skipped.
+//!
+//! Both phases run in bounded steps, messages from the writer are processed between the steps.
+//!
+//! Commits of the retention window and marks are kept in sled trees, so the memory usage does not grow
+//! with the live set and the window survives restarts. Collection interrupted by a restart is started again
+//! (marks are cleared), entries of partially swept subtrees can only leak.
+//!
+//! Writer coordination: [GarbageCollector::commit_started] means that a new commit (with possibly unknown roots)
+//! is being stored, so sweeping is paused until the commit is announced by [GarbageCollector::block_applied] and marked.
+//! Other writes (e.g. entries imported from a snapshot) do not pause sweeping.
+//! The writer is blocked only while a single sweep step is running, which is reported as pause time.
+
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use failure::Error;
+
+use crypto::hash::HashType;
+
+use crate::context::gc::{
+    GarbageCollectionError, GarbageCollectionPhase, GarbageCollectionStats, GarbageCollector,
+};
+use crate::context::merkle::entry_cache::EntryCache;
+use crate::context::merkle::hash::EntryHash;
+use crate::context::merkle::{Entry, NodeKind};
+use crate::context::{ContextKeyValueStore, ContextKeyValueStoreSchema, ContextValue};
+use crate::persistent::database::DBError;
+use crate::persistent::{
+    Flushable, KeyValueStoreBackend, MultiInstanceable, MultiInstanceableSyncError, Persistable,
+};
+
+/// Count of entries marked or swept in a single step
+const COUNT_OF_ENTRIES_IN_SINGLE_GC_STEP: usize = 2048;
+
+/// How long the GC thread waits for the writer to announce a stored commit
+const WAIT_FOR_BLOCK_APPLIED_TIMEOUT: Duration = Duration::from_millis(20);
+
+const CYCLES_TREE: &str = "cycles";
+const MARKED_TREE: &str = "marked";
+const META_TREE: &str = "meta";
+const CURRENT_CYCLE_KEY: &str = "current_cycle";
+/// First cycle, which was not collected yet
+const FIRST_UNCOLLECTED_CYCLE_KEY: &str = "first_uncollected_cycle";
+
+/// Commands used by IncrementalGCed to interact with GC thread.
+enum CmdMsg {
+    BlockApplied(EntryHash),
+    StartNewCycle,
+    SetEntryCache(Arc<EntryCache>),
+    Exit,
+}
+
+/// Garbage Collected Key Value Store
+pub struct IncrementalGCed {
+    store: Arc<ContextKeyValueStore>,
+    /// state of GC thread (retention window and marks)
+    db: sled::Db,
+    /// Set by commit_started, cleared by block_applied - sweeping is paused while set
+    commit_in_progress: Arc<Mutex<bool>>,
+    stats: Arc<Mutex<GarbageCollectionStats>>,
+    /// Count of messages not yet processed by GC thread
+    msg_cnt: Arc<AtomicUsize>,
+    /// Channel to communicate with GC thread from main thread
+    msg: Mutex<mpsc::Sender<CmdMsg>>,
+    thread: Option<thread::JoinHandle<()>>,
+    /// cache of decoded entries shared with readers, removed entries are invalidated
+    entry_cache: Option<Arc<EntryCache>>,
+}
+
+impl IncrementalGCed {
+    /// Wraps `store`, retention window and marks are stored in `db` (which must not be shared with other collector)
+    pub fn new(
+        store: Box<ContextKeyValueStore>,
+        db: sled::Db,
+        retained_cycles: usize,
+    ) -> Result<Self, GarbageCollectionError> {
+        let (mut gced, collector) = Self::with_collector(store, db, retained_cycles)?;
+        gced.thread = Some(thread::spawn(move || collector.run()));
+        Ok(gced)
+    }
+
+    /// Creates the store and the state of GC thread, which is not started
+    fn with_collector(
+        store: Box<ContextKeyValueStore>,
+        db: sled::Db,
+        retained_cycles: usize,
+    ) -> Result<(Self, Collector), GarbageCollectionError> {
+        let store: Arc<ContextKeyValueStore> = Arc::from(store);
+        let commit_in_progress = Arc::new(Mutex::new(false));
+        let stats = Arc::new(Mutex::new(GarbageCollectionStats::default()));
+        let msg_cnt = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = mpsc::channel();
+
+        let meta = db.open_tree(META_TREE)?;
+        let collector = Collector {
+            store: store.clone(),
+            commit_in_progress: commit_in_progress.clone(),
+            stats: stats.clone(),
+            msg_cnt: msg_cnt.clone(),
+            rx,
+            retained_cycles: retained_cycles as u64,
+            current_cycle: read_u64(&meta, CURRENT_CYCLE_KEY)?,
+            first_uncollected_cycle: read_u64(&meta, FIRST_UNCOLLECTED_CYCLE_KEY)?,
+            cycles: db.open_tree(CYCLES_TREE)?,
+            marked: db.open_tree(MARKED_TREE)?,
+            meta,
+            db: db.clone(),
+            collection: None,
+            entry_cache: None,
+            step_size: COUNT_OF_ENTRIES_IN_SINGLE_GC_STEP,
+            exit: false,
+        };
+        collector.update_stats(|stats| stats.pending_cycles = collector.expired_cycles());
+
+        let gced = Self {
+            store,
+            db,
+            commit_in_progress,
+            stats,
+            msg_cnt,
+            msg: Mutex::new(tx),
+            thread: None,
+            entry_cache: None,
+        };
+        Ok((gced, collector))
+    }
+
+    fn send(&self, msg: CmdMsg) -> Result<(), GarbageCollectionError> {
+        self.msg_cnt.fetch_add(1, Ordering::AcqRel);
+        self.msg.lock()?.send(msg).map_err(|_| {
+            self.msg_cnt.fetch_sub(1, Ordering::AcqRel);
+            GarbageCollectionError::GarbageCollectorError {
+                error: "cannot send message to GC thread".to_string(),
+            }
+        })
+    }
+
+    /// Sets the flag, which pauses sweeping, the wait for a running sweep step is reported as pause
+    fn set_commit_in_progress(&self, value: bool) -> Result<(), DBError> {
+        let started = Instant::now();
+        let mut commit_in_progress = self.commit_in_progress.lock()?;
+        let pause = started.elapsed().as_nanos() as u64;
+        *commit_in_progress = value;
+        drop(commit_in_progress);
+
+        let mut stats = self.stats.lock()?;
+        stats.pause_time_total += pause;
+        stats.pause_time_max = stats.pause_time_max.max(pause);
+        Ok(())
+    }
+
+    /// Waits for garbage collector to process all messages and finish all collections.
+    #[cfg(test)]
+    fn wait_for_gc_finish(&self) {
+        loop {
+            let idle = self.msg_cnt.load(Ordering::Acquire) == 0
+                && self
+                    .stats
+                    .lock()
+                    .map(|stats| {
+                        stats.phase == GarbageCollectionPhase::Idle && stats.pending_cycles == 0
+                    })
+                    .unwrap_or(true);
+            if idle {
+                return;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+impl Drop for IncrementalGCed {
+    fn drop(&mut self) {
+        let _ = self.send(CmdMsg::Exit);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl GarbageCollector for IncrementalGCed {
+    fn new_cycle_started(&mut self) -> Result<(), GarbageCollectionError> {
+        self.send(CmdMsg::StartNewCycle)
+    }
+
+    fn commit_started(&mut self) -> Result<(), GarbageCollectionError> {
+        Ok(self.set_commit_in_progress(true)?)
+    }
+
+    fn block_applied(&mut self, commit: EntryHash) -> Result<(), GarbageCollectionError> {
+        // message has to be sent before the flag is cleared, so sweeping never runs with unknown roots
+        self.send(CmdMsg::BlockApplied(commit))?;
+        Ok(self.set_commit_in_progress(false)?)
+    }
+
+    fn set_entry_cache(&mut self, entry_cache: Arc<EntryCache>) {
+        self.entry_cache = Some(entry_cache.clone());
+        // entries are removed by GC thread, message is processed before any collection starts
+        if let Err(err) = self.send(CmdMsg::SetEntryCache(entry_cache)) {
+            eprintln!("MerkleStorage GC: failed to share entry cache: {:?}", err);
+        }
+    }
+
+    fn gc_stats(&self) -> Option<GarbageCollectionStats> {
+        self.stats.lock().ok().map(|stats| stats.clone())
+    }
+}
+
+impl KeyValueStoreBackend<ContextKeyValueStoreSchema> for IncrementalGCed {
+    fn put(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
+        self.store.put(key, value)
+    }
+
+    fn delete(&self, key: &EntryHash) -> Result<(), DBError> {
+        self.store.delete(key)?;
+        if let Some(entry_cache) = self.entry_cache.as_ref() {
+            entry_cache.invalidate(key);
+        }
+        Ok(())
+    }
+
+    fn merge(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
+        self.store.merge(key, value)
+    }
+
+    fn get(&self, key: &EntryHash) -> Result<Option<ContextValue>, DBError> {
+        self.store.get(key)
+    }
+
+    fn contains(&self, key: &EntryHash) -> Result<bool, DBError> {
+        self.store.contains(key)
+    }
+
+    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<(), DBError> {
+        self.store.retain(predicate)?;
+        // removed entries are not known
+        if let Some(entry_cache) = self.entry_cache.as_ref() {
+            entry_cache.clear();
+        }
+        Ok(())
+    }
+
+    fn write_batch(&self, batch: Vec<(EntryHash, ContextValue)>) -> Result<(), DBError> {
+        self.store.write_batch(batch)
+    }
+
+    fn total_get_mem_usage(&self) -> Result<usize, DBError> {
+        self.store.total_get_mem_usage()
+    }
+}
+
+impl Flushable for IncrementalGCed {
+    fn flush(&self) -> Result<(), Error> {
+        self.store.flush()?;
+        match self.db.flush() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(failure::format_err!(
+                "Failed to flush sled db of incremental garbage collector, reason: {:?}",
+                e
+            )),
+        }
+    }
+}
+
+impl Persistable for IncrementalGCed {
+    fn is_persistent(&self) -> bool {
+        self.store.is_persistent()
+    }
+}
+
+impl MultiInstanceable for IncrementalGCed {
+    fn supports_multiple_opened_instances(&self) -> bool {
+        self.store.supports_multiple_opened_instances()
+    }
+
+    fn sync_with_primary(&self) -> Result<(), MultiInstanceableSyncError> {
+        self.store.sync_with_primary()
+    }
+}
+
+/// State of one collection, entries reachable from retained commits are marked in [Collector::marked]
+struct Collection {
+    /// entries waiting for marking
+    mark_todo: Vec<EntryHash>,
+    /// entries reachable from expired commits waiting for sweeping
+    sweep_todo: Vec<EntryHash>,
+    /// cycles older than this one are collected by this collection
+    first_retained_cycle: u64,
+}
+
+/// GC thread state
+struct Collector {
+    store: Arc<ContextKeyValueStore>,
+    commit_in_progress: Arc<Mutex<bool>>,
+    stats: Arc<Mutex<GarbageCollectionStats>>,
+    msg_cnt: Arc<AtomicUsize>,
+    rx: mpsc::Receiver<CmdMsg>,
+    retained_cycles: u64,
+    current_cycle: u64,
+    first_uncollected_cycle: u64,
+    db: sled::Db,
+    /// cycle (u64 BE) + commit hash -> () for commits of applied blocks, which are not collected yet
+    cycles: sled::Tree,
+    /// entry hash -> () for entries marked by the running collection
+    marked: sled::Tree,
+    meta: sled::Tree,
+    collection: Option<Collection>,
+    /// cache of decoded entries shared with readers, removed entries are invalidated
+    entry_cache: Option<Arc<EntryCache>>,
+    /// count of entries marked or swept in a single step
+    step_size: usize,
+    exit: bool,
+}
+
+impl Collector {
+    /// Garbage collector main function
+    fn run(mut self) {
+        loop {
+            // wait (block) for main thread events if there is nothing to collect
+            let msg = if self.collection.is_none() && self.expired_cycles() == 0 {
+                match self.rx.recv() {
+                    Ok(msg) => Some(msg),
+                    Err(_) => return,
+                }
+            } else {
+                match self.rx.try_recv() {
+                    Ok(msg) => Some(msg),
+                    Err(mpsc::TryRecvError::Empty) => None,
+                    Err(mpsc::TryRecvError::Disconnected) => return,
+                }
+            };
+            if let Some(msg) = msg {
+                self.process_msg(msg);
+            }
+            if self.exit {
+                return;
+            }
+
+            if let Err(err) = self.step() {
+                eprintln!("MerkleStorage GC: collection step failed: {:?}", err);
+            }
+        }
+    }
+
+    fn process_msg(&mut self, msg: CmdMsg) {
+        if let Err(err) = self.handle_msg(msg) {
+            eprintln!("MerkleStorage GC: failed to process message: {:?}", err);
+        }
+        self.msg_cnt.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    fn handle_msg(&mut self, msg: CmdMsg) -> Result<(), GarbageCollectionError> {
+        match msg {
+            CmdMsg::BlockApplied(commit) => {
+                let key = [&self.current_cycle.to_be_bytes()[..], &commit[..]].concat();
+                self.cycles.insert(key, Vec::new())?;
+                // commits applied during collection are roots too
+                if let Some(collection) = self.collection.as_mut() {
+                    collection.mark_todo.push(commit);
+                }
+            }
+            CmdMsg::StartNewCycle => {
+                self.current_cycle += 1;
+                self.meta
+                    .insert(CURRENT_CYCLE_KEY, &self.current_cycle.to_be_bytes()[..])?;
+                let pending_cycles = self.expired_cycles();
+                self.update_stats(|stats| stats.pending_cycles = pending_cycles);
+            }
+            CmdMsg::SetEntryCache(entry_cache) => self.entry_cache = Some(entry_cache),
+            CmdMsg::Exit => self.exit = true,
+        }
+        Ok(())
+    }
+
+    /// Cycles older than this one are out of the retention window
+    fn first_retained_cycle(&self) -> u64 {
+        self.current_cycle.saturating_sub(self.retained_cycles)
+    }
+
+    /// Count of expired cycles, which are not collected yet
+    fn expired_cycles(&self) -> usize {
+        self.first_retained_cycle()
+            .saturating_sub(self.first_uncollected_cycle) as usize
+    }
+
+    /// Returns commits of cycles in `range`
+    fn commits<R: std::ops::RangeBounds<[u8; 8]>>(
+        &self,
+        range: R,
+    ) -> Result<Vec<EntryHash>, GarbageCollectionError> {
+        let mut commits = Vec::new();
+        for item in self.cycles.range(range) {
+            let (key, _) = item?;
+            commits.push(key[8..].try_into()?);
+        }
+        Ok(commits)
+    }
+
+    fn update_stats<F: FnOnce(&mut GarbageCollectionStats)>(&self, update: F) {
+        if let Ok(mut stats) = self.stats.lock() {
+            update(&mut stats);
+        }
+    }
+
+    /// Runs single bounded step of the collection (starts a new one, if needed)
+    fn step(&mut self) -> Result<(), GarbageCollectionError> {
+        if self.collection.is_none() {
+            if self.expired_cycles() == 0 {
+                return Ok(());
+            }
+            // expired commits are collected with the roots known right now, commits expired
+            // during this collection are marked as reachable and collected by the next one
+            let first_retained_cycle = self.first_retained_cycle();
+            self.marked.clear()?;
+            self.collection = Some(Collection {
+                mark_todo: self.commits(first_retained_cycle.to_be_bytes()..)?,
+                sweep_todo: self.commits(..first_retained_cycle.to_be_bytes())?,
+                first_retained_cycle,
+            });
+            self.update_stats(|stats| {
+                stats.phase = GarbageCollectionPhase::Marking;
+                stats.marked_entries = 0;
+                stats.swept_entries = 0;
+            });
+        }
+
+        let mark_finished = match self.collection.as_ref() {
+            Some(collection) => collection.mark_todo.is_empty(),
+            None => return Ok(()),
+        };
+        if !mark_finished {
+            return self.mark_step();
+        }
+        self.sweep_step()
+    }
+
+    fn mark_step(&mut self) -> Result<(), GarbageCollectionError> {
+        let collection = match self.collection.as_mut() {
+            Some(collection) => collection,
+            None => return Ok(()),
+        };
+        let mut marked_count = 0;
+
+        for _ in 0..self.step_size {
+            let hash = match collection.mark_todo.pop() {
+                Some(hash) => hash,
+                None => break,
+            };
+            if self.marked.insert(&hash, Vec::new())?.is_some() {
+                continue;
+            }
+            marked_count += 1;
+
+            match fetch_entry(&*self.store, hash) {
+                Ok(Entry::Commit(commit)) => collection.mark_todo.push(commit.root_hash),
+                Ok(Entry::Tree(tree)) => {
+                    for (_, node) in tree.iter() {
+                        match node.node_kind {
+                            // blobs does not have to be read
+                            NodeKind::Leaf => {
+                                if self
+                                    .marked
+                                    .insert(&node.entry_hash[..], Vec::new())?
+                                    .is_none()
+                                {
+                                    marked_count += 1;
+                                }
+                            }
+                            NodeKind::NonLeaf => collection.mark_todo.push(*node.entry_hash),
+                        }
+                    }
+                }
+                Ok(Entry::Blob(_)) => (),
+                // entry could be already collected (e.g. commit, which was not stored)
+                Err(GarbageCollectionError::EntryNotFound { .. }) => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        self.update_stats(|stats| stats.marked_entries += marked_count);
+        Ok(())
+    }
+
+    fn sweep_step(&mut self) -> Result<(), GarbageCollectionError> {
+        let commit_in_progress = self.commit_in_progress.clone();
+        let commit_in_progress = commit_in_progress.lock()?;
+        if *commit_in_progress {
+            // roots of the stored commit are not known yet, wait for block_applied
+            drop(commit_in_progress);
+            return self.wait_for_msg();
+        }
+
+        // every stored commit was already announced, so all its roots are in the channel
+        while let Ok(msg) = self.rx.try_recv() {
+            self.process_msg(msg);
+        }
+        if self.exit {
+            return Ok(());
+        }
+        let mark_finished = self
+            .collection
+            .as_ref()
+            .map_or(true, |collection| collection.mark_todo.is_empty());
+        if !mark_finished {
+            self.update_stats(|stats| stats.phase = GarbageCollectionPhase::Marking);
+            return Ok(());
+        }
+
+        self.update_stats(|stats| stats.phase = GarbageCollectionPhase::Sweeping);
+        self.sweep_entries(commit_in_progress)
+    }
+
+    /// Deletes unmarked entries, writer is blocked until the guard is released
+    fn sweep_entries(
+        &mut self,
+        _commit_in_progress: MutexGuard<bool>,
+    ) -> Result<(), GarbageCollectionError> {
+        let collection = match self.collection.as_mut() {
+            Some(collection) => collection,
+            None => return Ok(()),
+        };
+        let mut swept_count = 0;
+        let mut reclaimed_bytes = 0;
+
+        for _ in 0..self.step_size {
+            let hash = match collection.sweep_todo.pop() {
+                Some(hash) => hash,
+                None => break,
+            };
+            // whole subtree is reachable
+            if self.marked.contains_key(&hash)? {
+                continue;
+            }
+            let entry_bytes = match self.store.get(&hash)? {
+                Some(entry_bytes) => entry_bytes,
+                // already removed through another expired commit
+                None => continue,
+            };
+            match bincode::deserialize::<Entry>(&entry_bytes) {
+                Ok(Entry::Commit(commit)) => collection.sweep_todo.push(commit.root_hash),
+                Ok(Entry::Tree(tree)) => collection
+                    .sweep_todo
+                    .extend(tree.iter().map(|(_, node)| *node.entry_hash)),
+                Ok(Entry::Blob(_)) => (),
+                Err(err) => {
+                    eprintln!(
+                        "MerkleStorage GC: error while deserializing entry: {:?}",
+                        err
+                    );
+                }
+            }
+            self.store.delete(&hash)?;
+            if let Some(entry_cache) = self.entry_cache.as_ref() {
+                entry_cache.invalidate(&hash);
+            }
+            swept_count += 1;
+            reclaimed_bytes += (hash.len() + entry_bytes.len()) as u64;
+        }
+
+        let collected_cycles = if collection.sweep_todo.is_empty() {
+            let first_retained_cycle = collection.first_retained_cycle;
+            self.collection = None;
+            Some(self.finish_collection(first_retained_cycle)?)
+        } else {
+            None
+        };
+        let pending_cycles = self.expired_cycles();
+        self.update_stats(|stats| {
+            stats.swept_entries += swept_count;
+            stats.reclaimed_bytes += reclaimed_bytes;
+            if let Some(collected_cycles) = collected_cycles {
+                stats.phase = GarbageCollectionPhase::Idle;
+                stats.collected_cycles += collected_cycles;
+                stats.pending_cycles = pending_cycles;
+            }
+        });
+        Ok(())
+    }
+
+    /// Removes collected cycles from the retention window, returns count of collected cycles
+    fn finish_collection(
+        &mut self,
+        first_retained_cycle: u64,
+    ) -> Result<usize, GarbageCollectionError> {
+        for item in self.cycles.range(..first_retained_cycle.to_be_bytes()) {
+            let (key, _) = item?;
+            self.cycles.remove(key)?;
+        }
+        self.marked.clear()?;
+        let collected_cycles = first_retained_cycle.saturating_sub(self.first_uncollected_cycle);
+        self.first_uncollected_cycle = first_retained_cycle;
+        self.meta.insert(
+            FIRST_UNCOLLECTED_CYCLE_KEY,
+            &first_retained_cycle.to_be_bytes()[..],
+        )?;
+        self.db.flush()?;
+        Ok(collected_cycles as usize)
+    }
+
+    fn wait_for_msg(&mut self) -> Result<(), GarbageCollectionError> {
+        if let Ok(msg) = self.rx.recv_timeout(WAIT_FOR_BLOCK_APPLIED_TIMEOUT) {
+            self.process_msg(msg);
+        }
+        Ok(())
+    }
+}
+
+fn read_u64(tree: &sled::Tree, key: &str) -> Result<u64, GarbageCollectionError> {
+    match tree.get(key)? {
+        Some(value) => Ok(u64::from_be_bytes(value[..].try_into()?)),
+        None => Ok(0),
+    }
+}
+
+/// Fetches and deserializes entry from the wrapped store
+fn fetch_entry(
+    store: &ContextKeyValueStore,
+    hash: EntryHash,
+) -> Result<Entry, GarbageCollectionError> {
+    match store.get(&hash)? {
+        None => Err(GarbageCollectionError::EntryNotFound {
+            hash: HashType::ContextHash.hash_to_b58check(&hash)?,
+        }),
+        Some(entry_bytes) => Ok(bincode::deserialize(&entry_bytes)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::context::gc::test_support::{commit, get, put, temporary_db, test_dir, tree};
+    use crate::context::kv_store::in_memory_backend::InMemoryBackend;
+    use crate::context::kv_store::sled_backend::SledBackend;
+    use crate::context::kv_store::test_support::{blob, entry_hash};
+
+    use super::*;
+
+    #[test]
+    fn test_collects_expired_cycle() {
+        let mut store =
+            IncrementalGCed::new(Box::new(InMemoryBackend::new()), temporary_db(), 1).unwrap();
+        let entry_cache = Arc::new(EntryCache::new(1024 * 1024));
+        store.set_entry_cache(entry_cache.clone());
+
+        // cycle 0: commit [3] -> tree [2] -> blob [1]
+        put(&store, &[1], blob(vec![1]));
+        put(&store, &[2], tree(&[("a", NodeKind::Leaf, &[1])]));
+        put(&store, &[3], commit(&[2]));
+        store.block_applied(entry_hash(&[3])).unwrap();
+        store.new_cycle_started().unwrap();
+
+        // cycle 1: commit [6] -> tree [5] -> blobs [1] (reused), [4]
+        put(&store, &[4], blob(vec![4]));
+        put(
+            &store,
+            &[5],
+            tree(&[("a", NodeKind::Leaf, &[1]), ("b", NodeKind::Leaf, &[4])]),
+        );
+        put(&store, &[6], commit(&[5]));
+        store.block_applied(entry_hash(&[6])).unwrap();
+        for key in &[[1u8], [2], [3]] {
+            entry_cache.insert(entry_hash(key), Arc::new(get(&store, key).unwrap()));
+        }
+        store.new_cycle_started().unwrap();
+        store.wait_for_gc_finish();
+
+        // swept entries are invalidated in the shared cache
+        assert_eq!(entry_cache.get(&entry_hash(&[3])), None);
+        assert_eq!(entry_cache.get(&entry_hash(&[2])), None);
+        assert!(entry_cache.get(&entry_hash(&[1])).is_some());
+
+        assert_eq!(get(&store, &[3]), None);
+        assert_eq!(get(&store, &[2]), None);
+        assert_eq!(get(&store, &[1]), Some(blob(vec![1])));
+        assert_eq!(get(&store, &[4]), Some(blob(vec![4])));
+        assert!(get(&store, &[5]).is_some());
+        assert!(get(&store, &[6]).is_some());
+
+        let stats = store.gc_stats().unwrap();
+        assert_eq!(stats.phase, GarbageCollectionPhase::Idle);
+        assert_eq!(stats.collected_cycles, 1);
+        assert_eq!(stats.pending_cycles, 0);
+        assert_eq!(stats.swept_entries, 2);
+        assert!(stats.reclaimed_bytes > 0);
+    }
+
+    #[test]
+    fn test_retains_cycles() {
+        let mut store =
+            IncrementalGCed::new(Box::new(InMemoryBackend::new()), temporary_db(), 2).unwrap();
+
+        put(&store, &[1], blob(vec![1]));
+        put(&store, &[2], tree(&[("a", NodeKind::Leaf, &[1])]));
+        put(&store, &[3], commit(&[2]));
+        store.block_applied(entry_hash(&[3])).unwrap();
+        store.new_cycle_started().unwrap();
+        store.new_cycle_started().unwrap();
+        store.wait_for_gc_finish();
+
+        assert_eq!(get(&store, &[1]), Some(blob(vec![1])));
+        assert!(get(&store, &[3]).is_some());
+        assert_eq!(store.gc_stats().unwrap().collected_cycles, 0);
+
+        store.new_cycle_started().unwrap();
+        store.wait_for_gc_finish();
+
+        assert_eq!(get(&store, &[1]), None);
+        assert_eq!(get(&store, &[2]), None);
+        assert_eq!(get(&store, &[3]), None);
+        assert_eq!(store.gc_stats().unwrap().collected_cycles, 1);
+        assert_eq!(store.total_get_mem_usage().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_writes_interleaving_with_sweep() {
+        // collector is driven step by step by the test
+        let (mut store, mut collector) =
+            IncrementalGCed::with_collector(Box::new(InMemoryBackend::new()), temporary_db(), 0)
+                .unwrap();
+        collector.step_size = 1;
+        let process_messages = |collector: &mut Collector| {
+            while let Ok(msg) = collector.rx.try_recv() {
+                collector.process_msg(msg);
+            }
+        };
+
+        // cycle 0: commit [4] -> tree [3] -> blobs [1], [2]
+        put(&store, &[1], blob(vec![1]));
+        put(&store, &[2], blob(vec![2]));
+        put(
+            &store,
+            &[3],
+            tree(&[("a", NodeKind::Leaf, &[1]), ("b", NodeKind::Leaf, &[2])]),
+        );
+        put(&store, &[4], commit(&[3]));
+        store.block_applied(entry_hash(&[4])).unwrap();
+        store.new_cycle_started().unwrap();
+        process_messages(&mut collector);
+
+        // nothing is retained, so the sweep starts right away and removes the commit
+        collector.step().unwrap();
+        assert!(collector.collection.is_some());
+        assert_eq!(get(&store, &[4]), None);
+        assert!(get(&store, &[3]).is_some());
+
+        // new block reuses blob [1] of the swept tree
+        store.commit_started().unwrap();
+        put(&store, &[5], tree(&[("a", NodeKind::Leaf, &[1])]));
+        put(&store, &[6], commit(&[5]));
+
+        // sweeping is paused until the commit is announced
+        for _ in 0..3 {
+            collector.step().unwrap();
+        }
+        assert!(get(&store, &[3]).is_some());
+        assert_eq!(get(&store, &[2]), Some(blob(vec![2])));
+
+        store.block_applied(entry_hash(&[6])).unwrap();
+        while collector.collection.is_some() {
+            collector.step().unwrap();
+        }
+        process_messages(&mut collector);
+
+        assert_eq!(get(&store, &[3]), None);
+        assert_eq!(get(&store, &[2]), None);
+        assert_eq!(get(&store, &[1]), Some(blob(vec![1])));
+        assert!(get(&store, &[5]).is_some());
+        assert!(get(&store, &[6]).is_some());
+        assert_eq!(collector.expired_cycles(), 0);
+        assert_eq!(store.msg_cnt.load(Ordering::Acquire), 0);
+        assert_eq!(store.gc_stats().unwrap().collected_cycles, 1);
+    }
+
+    #[test]
+    fn test_sweep_resumes_after_non_commit_write() {
+        // collector is driven step by step by the test
+        let (mut store, mut collector) =
+            IncrementalGCed::with_collector(Box::new(InMemoryBackend::new()), temporary_db(), 0)
+                .unwrap();
+        collector.step_size = 1;
+
+        // cycle 0: commit [3] -> tree [2] -> blob [1]
+        store.commit_started().unwrap();
+        put(&store, &[1], blob(vec![1]));
+        put(&store, &[2], tree(&[("a", NodeKind::Leaf, &[1])]));
+        put(&store, &[3], commit(&[2]));
+        store.block_applied(entry_hash(&[3])).unwrap();
+        store.new_cycle_started().unwrap();
+
+        // entries imported without any commit (e.g. from snapshot) are not followed by block_applied
+        store
+            .write_batch(vec![(
+                entry_hash(&[4]),
+                bincode::serialize(&blob(vec![4])).unwrap(),
+            )])
+            .unwrap();
+        while let Ok(msg) = collector.rx.try_recv() {
+            collector.process_msg(msg);
+        }
+
+        for _ in 0..10 {
+            collector.step().unwrap();
+        }
+        assert!(collector.collection.is_none());
+        assert_eq!(get(&store, &[3]), None);
+        assert_eq!(get(&store, &[2]), None);
+        assert_eq!(get(&store, &[1]), None);
+        assert_eq!(get(&store, &[4]), Some(blob(vec![4])));
+        assert_eq!(store.gc_stats().unwrap().collected_cycles, 1);
+    }
+
+    #[test]
+    fn test_cycles_survive_restart() {
+        let dir = test_dir("incremental_gc", "test_cycles_survive_restart");
+        let open = || {
+            let db = sled::Config::new().path(&dir).open().unwrap();
+            IncrementalGCed::new(Box::new(SledBackend::new(db.clone())), db, 1).unwrap()
+        };
+
+        {
+            let mut store = open();
+            put(&store, &[1], blob(vec![1]));
+            put(&store, &[2], tree(&[("a", NodeKind::Leaf, &[1])]));
+            put(&store, &[3], commit(&[2]));
+            store.block_applied(entry_hash(&[3])).unwrap();
+            store.new_cycle_started().unwrap();
+            store.wait_for_gc_finish();
+            store.flush().unwrap();
+        }
+
+        let mut store = open();
+        put(&store, &[4], tree(&[("a", NodeKind::Leaf, &[1])]));
+        put(&store, &[5], commit(&[4]));
+        store.block_applied(entry_hash(&[5])).unwrap();
+        store.new_cycle_started().unwrap();
+        store.wait_for_gc_finish();
+
+        // cycle 0 applied before restart was collected, blob is still reachable from cycle 1
+        assert_eq!(get(&store, &[3]), None);
+        assert_eq!(get(&store, &[2]), None);
+        assert_eq!(get(&store, &[1]), Some(blob(vec![1])));
+        assert!(get(&store, &[5]).is_some());
+        assert_eq!(store.gc_stats().unwrap().collected_cycles, 1);
+    }
+}
@@ -15,6 +15,7 @@ use crypto::hash::HashType;
 use crate::context::gc::{
     collect_hashes_recursively, fetch_entry_from_store, GarbageCollectionError, GarbageCollector,
 };
+use crate::context::merkle::entry_cache::EntryCache;
 use crate::context::merkle::hash::EntryHash;
 use crate::context::merkle::Entry;
 use crate::context::{ContextKeyValueStoreSchema, ContextValue};
@@ -85,6 +86,7 @@ pub enum CmdMsg {
     StartNewCycle,
     Exit,
     MarkReused(EntryHash),
+    SetEntryCache(Arc<EntryCache>),
 }
 
 /// Garbage Collected Key Value Store
@@ -100,6 +102,8 @@ pub struct MarkMoveGCed<T: KeyValueStoreBackend<ContextKeyValueStoreSchema>> {
     /// Channel to communicate with GC thread from main thread
     msg: Mutex<mpsc::Sender<CmdMsg>>,
     cache: HashMap<EntryHash, HashSet<EntryHash>>,
+    /// cache of decoded entries shared with readers, removed entries are invalidated
+    entry_cache: Option<Arc<EntryCache>>,
 }
 
 impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Send + Sync + Default>
@@ -130,6 +134,7 @@ impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Send + Sync
             msg_cnt: msg_cnt_ref,
             current: Default::default(),
             cache: HashMap::new(),
+            entry_cache: None,
         }
     }
 
@@ -198,7 +203,11 @@ impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Send + Sync
     }
 
     fn delete(&self, key: &EntryHash) -> Result<(), DBError> {
-        self.current.delete(key)
+        self.current.delete(key)?;
+        if let Some(entry_cache) = self.entry_cache.as_ref() {
+            entry_cache.invalidate(key);
+        }
+        Ok(())
     }
 
     fn merge(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
@@ -232,7 +241,12 @@ impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Send + Sync
     }
 
     fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<(), DBError> {
-        self.current.retain(predicate)
+        self.current.retain(predicate)?;
+        // removed entries are not known
+        if let Some(entry_cache) = self.entry_cache.as_ref() {
+            entry_cache.clear();
+        }
+        Ok(())
     }
 }
 
@@ -252,6 +266,7 @@ fn kvstore_gc_thread_fn<T: KeyValueStoreBackend<ContextKeyValueStoreSchema>>(
     // stores so that after destroying oldest store they are preserved.
     let mut todo_keys: Vec<EntryHash> = vec![];
     let mut received_exit_msg = false;
+    let mut entry_cache: Option<Arc<EntryCache>> = None;
 
     loop {
         // wait (block) for main thread events if there are no items to garbage collect
@@ -287,6 +302,9 @@ fn kvstore_gc_thread_fn<T: KeyValueStoreBackend<ContextKeyValueStoreSchema>>(
             Some(CmdMsg::Exit) => {
                 received_exit_msg = true;
             }
+            Some(CmdMsg::SetEntryCache(cache)) => {
+                entry_cache = Some(cache);
+            }
             Some(CmdMsg::MarkReused(key)) => {
                 if let Some(index) = stores_containing(&stores.read()?, &key) {
                     // only way index can be greater than reused_keys.len() is if GC thread
@@ -387,6 +405,10 @@ fn kvstore_gc_thread_fn<T: KeyValueStoreBackend<ContextKeyValueStoreSchema>>(
         if reused_keys.len() > len && reused_keys[0].is_empty() && todo_keys.is_empty() {
             drop(reused_keys.drain(..1));
             drop(stores.write()?.drain(..1));
+            // entries left in the dropped store are not known
+            if let Some(entry_cache) = entry_cache.as_ref() {
+                entry_cache.clear();
+            }
         }
     }
 }
@@ -398,6 +420,14 @@ impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Send + Sync
         self.new_cycle_started()
     }
 
+    fn set_entry_cache(&mut self, entry_cache: Arc<EntryCache>) {
+        self.entry_cache = Some(entry_cache.clone());
+        // old stores are dropped by GC thread
+        if let Ok(msg) = self.msg.lock() {
+            let _ = msg.send(CmdMsg::SetEntryCache(entry_cache));
+        }
+    }
+
     fn block_applied(&mut self, commit: EntryHash) -> Result<(), GarbageCollectionError> {
         let commit_entry = fetch_entry_from_store(
             self.deref() as &dyn KeyValueStoreBackend<ContextKeyValueStoreSchema>,
@@ -3,12 +3,14 @@
 
 use std::collections::HashMap;
 use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
 
 use crypto::hash::HashType;
 
 use crate::context::gc::{
     collect_hashes, fetch_entry_from_store, GarbageCollectionError, GarbageCollector,
 };
+use crate::context::merkle::entry_cache::EntryCache;
 use crate::context::merkle::hash::EntryHash;
 use crate::context::merkle::Entry;
 use crate::context::{ContextKeyValueStoreSchema, ContextValue};
@@ -22,6 +24,8 @@ pub struct MarkSweepGCed<T: KeyValueStoreBackend<ContextKeyValueStoreSchema>> {
     cycles_limit: usize,
     cycles: VecDeque<HashSet<EntryHash>>,
     cache: HashMap<EntryHash, HashSet<EntryHash>>,
+    /// cache of decoded entries shared with readers, removed entries are invalidated
+    entry_cache: Option<Arc<EntryCache>>,
 }
 
 impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Default> MarkSweepGCed<T> {
@@ -39,6 +43,7 @@ impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Default> Ma
             cycles_limit: cycle_count + 1,
             cycles,
             cache: HashMap::new(),
+            entry_cache: None,
         }
     }
 
@@ -106,6 +111,10 @@ impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Default> Ga
     fn block_applied(&mut self, commit: EntryHash) -> Result<(), GarbageCollectionError> {
         self.store_entries_referenced_by_commit(commit)
     }
+
+    fn set_entry_cache(&mut self, entry_cache: Arc<EntryCache>) {
+        self.entry_cache = Some(entry_cache);
+    }
 }
 
 impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Default>
@@ -116,7 +125,11 @@ impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Default>
     }
 
     fn delete(&self, key: &EntryHash) -> Result<(), DBError> {
-        self.store.delete(key)
+        self.store.delete(key)?;
+        if let Some(entry_cache) = self.entry_cache.as_ref() {
+            entry_cache.invalidate(key);
+        }
+        Ok(())
     }
 
     fn merge(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
@@ -153,7 +166,12 @@ impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Default>
     }
 
     fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<(), DBError> {
-        self.store.retain(predicate)
+        self.store.retain(predicate)?;
+        // removed entries are not known
+        if let Some(entry_cache) = self.entry_cache.as_ref() {
+            entry_cache.clear();
+        }
+        Ok(())
     }
 }
 
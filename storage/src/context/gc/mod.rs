@@ -5,25 +5,157 @@
 
 use std::array::TryFromSliceError;
 use std::collections::{HashMap, HashSet};
-use std::sync::PoisonError;
+use std::str::FromStr;
+use std::sync::{Arc, PoisonError};
 
 use blake2::digest::InvalidOutputSize;
 use failure::Fail;
+use serde::Serialize;
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
 
 use crypto::hash::{FromBytesError, HashType};
 
+use crate::context::merkle::entry_cache::EntryCache;
 use crate::context::merkle::hash::{hash_entry, HashingError};
 use crate::context::merkle::Entry;
 use crate::context::{ContextKeyValueStoreSchema, EntryHash};
 use crate::persistent::{DBError, KeyValueStoreBackend};
 
+pub mod cycle_tracker;
+pub mod incremental_gced;
 pub mod mark_move_gced;
 pub mod mark_sweep_gced;
+pub mod refcount_gced;
+#[cfg(test)]
+mod test_support;
+
+pub const NO_GC: &str = "none";
+
+/// Default number of cycles, which are not garbage collected
+pub const DEFAULT_RETAINED_CYCLES: usize = 5;
+
+/// Garbage collection strategies, which can wrap any context kv store
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, EnumIter)]
+pub enum SupportedContextGarbageCollector {
+    None,
+    Incremental { retained_cycles: usize },
+    RefCount { retained_cycles: usize },
+}
+
+impl SupportedContextGarbageCollector {
+    pub fn possible_values() -> Vec<&'static str> {
+        let mut possible_values = Vec::new();
+        for sp in SupportedContextGarbageCollector::iter() {
+            possible_values.extend(sp.supported_values());
+        }
+        possible_values
+    }
+
+    fn supported_values(&self) -> Vec<&'static str> {
+        match self {
+            SupportedContextGarbageCollector::None => vec![NO_GC],
+            SupportedContextGarbageCollector::Incremental { .. } => vec!["incremental"],
+            SupportedContextGarbageCollector::RefCount { .. } => vec!["refcount"],
+        }
+    }
+
+    /// Count of retained cycles, `None` if garbage collection is disabled
+    pub fn retained_cycles(&self) -> Option<usize> {
+        match self {
+            SupportedContextGarbageCollector::None => None,
+            SupportedContextGarbageCollector::Incremental { retained_cycles }
+            | SupportedContextGarbageCollector::RefCount { retained_cycles } => {
+                Some(*retained_cycles)
+            }
+        }
+    }
+
+    /// Returns the same strategy with `retained_cycles` (ignored for none)
+    pub fn with_retained_cycles(self, retained_cycles: usize) -> Self {
+        match self {
+            SupportedContextGarbageCollector::None => SupportedContextGarbageCollector::None,
+            SupportedContextGarbageCollector::Incremental { .. } => {
+                SupportedContextGarbageCollector::Incremental { retained_cycles }
+            }
+            SupportedContextGarbageCollector::RefCount { .. } => {
+                SupportedContextGarbageCollector::RefCount { retained_cycles }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseGarbageCollectorError(String);
+
+impl FromStr for SupportedContextGarbageCollector {
+    type Err = ParseGarbageCollectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        for sp in SupportedContextGarbageCollector::iter() {
+            if sp.supported_values().contains(&s.as_str()) {
+                return Ok(sp.with_retained_cycles(DEFAULT_RETAINED_CYCLES));
+            }
+        }
+
+        Err(ParseGarbageCollectorError(format!(
+            "Invalid variant name: {}",
+            s
+        )))
+    }
+}
 
 pub trait GarbageCollector {
     fn new_cycle_started(&mut self) -> Result<(), GarbageCollectionError>;
 
+    /// Announces, that entries of a new commit are going to be written, the commit is announced by `block_applied`
+    fn commit_started(&mut self) -> Result<(), GarbageCollectionError> {
+        Ok(())
+    }
+
     fn block_applied(&mut self, commit: EntryHash) -> Result<(), GarbageCollectionError>;
+
+    /// Shares cache of decoded entries with the collector, which has to invalidate every entry removed from the store
+    fn set_entry_cache(&mut self, entry_cache: Arc<EntryCache>);
+
+    /// Progress of the garbage collection, if reported by the collector
+    fn gc_stats(&self) -> Option<GarbageCollectionStats> {
+        None
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GarbageCollectionPhase {
+    Idle,
+    Marking,
+    Sweeping,
+}
+
+impl Default for GarbageCollectionPhase {
+    fn default() -> Self {
+        GarbageCollectionPhase::Idle
+    }
+}
+
+/// Progress and cost of the garbage collection reported by `/stats/context`
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct GarbageCollectionStats {
+    pub phase: GarbageCollectionPhase,
+    /// Count of cycles removed from the store
+    pub collected_cycles: usize,
+    /// Count of expired cycles waiting for collection
+    pub pending_cycles: usize,
+    /// Count of entries marked as reachable by the current (or the last) collection
+    pub marked_entries: usize,
+    /// Count of entries removed by the current (or the last) collection
+    pub swept_entries: usize,
+    /// Total time (in nanoseconds) the writer was blocked by the garbage collector
+    pub pause_time_total: u64,
+    /// The longest time (in nanoseconds) the writer was blocked by the garbage collector
+    pub pause_time_max: u64,
+    /// Total size of keys and values removed from the store
+    pub reclaimed_bytes: u64,
 }
 
 pub trait NotGarbageCollected {}
@@ -36,6 +168,10 @@ impl<T: NotGarbageCollected> GarbageCollector for T {
     fn block_applied(&mut self, _commit: EntryHash) -> Result<(), GarbageCollectionError> {
         Ok(())
     }
+
+    fn set_entry_cache(&mut self, _entry_cache: Arc<EntryCache>) {
+        // nothing is removed from the store
+    }
 }
 
 /// helper function for fetching and deserializing entry from the store
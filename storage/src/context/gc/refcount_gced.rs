@@ -0,0 +1,690 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Reference counting garbage collector.
+//!
+//! Every entry has a persistent count of references:
+//! - trees and commits stored for the first time add a reference to each of their children
+//!   (parent commits are not referenced, so the history can be collected),
+//! - commits of applied blocks are referenced by the retention window, until their cycle expires.
+//!
+//! When a cycle falls out of the window, references of its commits are released on a dedicated thread,
+//! every entry, whose count drops to zero, is removed and releases its children. So collecting a cycle touches
+//! only the garbage, the live set is never walked. Entries are released in bounded steps, the writer is blocked
+//! only while a single step is running, which is reported as pause time.
+//!
+//! Counts and the window are kept in sled trees, so they survive restarts. The references added by an entry
+//! are recorded together with a marker of the entry in a single sled transaction (and released together
+//! with the marker), so the counts are consistent with the markers, whatever is flushed first:
+//! - entry stored without its (lost) marker adds its references again, when it is stored next time
+//!   and it does not release any reference, when it is removed,
+//! - marker stored without its (lost) entry keeps the references of the entry.
+//!
+//! So interrupted write or collection can only leak entries, never remove a live one.
+
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Instant;
+
+use failure::Error;
+use sled::transaction::{ConflictableTransactionResult, TransactionError, TransactionalTree};
+use sled::{IVec, Transactional};
+
+use crate::context::gc::{
+    GarbageCollectionError, GarbageCollectionPhase, GarbageCollectionStats, GarbageCollector,
+};
+use crate::context::merkle::entry_cache::EntryCache;
+use crate::context::merkle::hash::EntryHash;
+use crate::context::merkle::Entry;
+use crate::context::{ContextKeyValueStore, ContextKeyValueStoreSchema, ContextValue};
+use crate::persistent::codec::SchemaError;
+use crate::persistent::database::DBError;
+use crate::persistent::{Flushable, KeyValueStoreBackend, MultiInstanceable, Persistable};
+
+const REFCOUNTS_TREE: &str = "refcounts";
+/// entry hash -> () for stored entries, whose references are counted
+const COUNTED_TREE: &str = "counted";
+const CYCLES_TREE: &str = "cycles";
+const META_TREE: &str = "meta";
+const CURRENT_CYCLE_KEY: &str = "current_cycle";
+
+/// Count of entries released in a single step
+const COUNT_OF_ENTRIES_IN_SINGLE_GC_STEP: usize = 2048;
+
+/// Commands used by RefCountGCed to interact with GC thread.
+enum CmdMsg {
+    /// Release commits of all cycles older than the given one
+    CollectCycles(u64),
+    SetEntryCache(Arc<EntryCache>),
+    Exit,
+}
+
+/// Store and counts shared by the writer and GC thread
+struct RefCounts {
+    store: Box<ContextKeyValueStore>,
+    db: sled::Db,
+    /// entry hash -> count of references (u64 BE)
+    refcounts: sled::Tree,
+    counted: sled::Tree,
+    /// cycle (u64 BE) + commit hash -> () for commits of applied blocks in retention window
+    cycles: sled::Tree,
+    /// Held by the writer while storing an entry and by GC thread while running a step
+    lock: Mutex<()>,
+}
+
+/// Garbage Collected Key Value Store
+pub struct RefCountGCed {
+    inner: Arc<RefCounts>,
+    meta: sled::Tree,
+    current_cycle: u64,
+    retained_cycles: usize,
+    stats: Arc<Mutex<GarbageCollectionStats>>,
+    /// Count of messages not yet processed by GC thread
+    msg_cnt: Arc<AtomicUsize>,
+    /// Channel to communicate with GC thread from main thread
+    msg: Mutex<mpsc::Sender<CmdMsg>>,
+    thread: Option<thread::JoinHandle<()>>,
+    /// cache of decoded entries shared with readers, removed entries are invalidated
+    entry_cache: Option<Arc<EntryCache>>,
+}
+
+impl RefCountGCed {
+    /// Wraps `store`, reference counts are stored in `db` (which must not be shared with other collector)
+    pub fn new(
+        store: Box<ContextKeyValueStore>,
+        db: sled::Db,
+        retained_cycles: usize,
+    ) -> Result<Self, GarbageCollectionError> {
+        let meta = db.open_tree(META_TREE)?;
+        let current_cycle = match meta.get(CURRENT_CYCLE_KEY)? {
+            Some(value) => decode_u64(&value)?,
+            None => 0,
+        };
+        let inner = Arc::new(RefCounts {
+            refcounts: db.open_tree(REFCOUNTS_TREE)?,
+            counted: db.open_tree(COUNTED_TREE)?,
+            cycles: db.open_tree(CYCLES_TREE)?,
+            db,
+            store,
+            lock: Mutex::new(()),
+        });
+        let stats = Arc::new(Mutex::new(GarbageCollectionStats::default()));
+        let msg_cnt = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = mpsc::channel();
+
+        let collector = Collector {
+            inner: inner.clone(),
+            stats: stats.clone(),
+            msg_cnt: msg_cnt.clone(),
+            rx,
+            entry_cache: None,
+            exit: false,
+        };
+
+        let gced = Self {
+            inner,
+            meta,
+            current_cycle,
+            retained_cycles,
+            stats,
+            msg_cnt,
+            msg: Mutex::new(tx),
+            thread: Some(thread::spawn(move || collector.run())),
+            entry_cache: None,
+        };
+        // cycles expired before restart, which were not collected yet
+        gced.collect_expired_cycles()?;
+        Ok(gced)
+    }
+
+    fn send(&self, msg: CmdMsg) -> Result<(), GarbageCollectionError> {
+        self.msg_cnt.fetch_add(1, Ordering::AcqRel);
+        self.msg.lock()?.send(msg).map_err(|_| {
+            self.msg_cnt.fetch_sub(1, Ordering::AcqRel);
+            GarbageCollectionError::GarbageCollectorError {
+                error: "cannot send message to GC thread".to_string(),
+            }
+        })
+    }
+
+    /// Asks GC thread to release commits of all cycles older than retention window
+    fn collect_expired_cycles(&self) -> Result<(), GarbageCollectionError> {
+        match self.current_cycle.checked_sub(self.retained_cycles as u64) {
+            Some(first_retained) if first_retained > 0 => {
+                self.send(CmdMsg::CollectCycles(first_retained))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Locks the store for the writer, the wait for a running step is reported as pause
+    fn lock_for_write(&self) -> Result<MutexGuard<()>, DBError> {
+        let started = Instant::now();
+        let guard = self.inner.lock.lock()?;
+        let pause = started.elapsed().as_nanos() as u64;
+
+        let mut stats = self.stats.lock()?;
+        stats.pause_time_total += pause;
+        stats.pause_time_max = stats.pause_time_max.max(pause);
+        Ok(guard)
+    }
+
+    /// Waits for garbage collector to process all messages.
+    #[cfg(test)]
+    fn wait_for_gc_finish(&self) {
+        while self.msg_cnt.load(Ordering::Acquire) > 0 {
+            thread::sleep(std::time::Duration::from_millis(20));
+        }
+    }
+}
+
+impl Drop for RefCountGCed {
+    fn drop(&mut self) {
+        let _ = self.send(CmdMsg::Exit);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl RefCounts {
+    /// Adds reference to every child of the entry, if references of the entry are not counted yet
+    fn reference_children(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
+        let children = children(value)?;
+        (&self.refcounts, &self.counted)
+            .transaction(|(refcounts, counted)| {
+                if counted.insert(&key[..], Vec::new())?.is_some() {
+                    return Ok(());
+                }
+                for child in &children {
+                    add_reference(refcounts, &child[..])?;
+                }
+                Ok(())
+            })
+            .map_err(transaction_error)
+    }
+
+    /// Adds reference of the retention window to the commit, once per cycle
+    fn reference_commit(&self, cycle: u64, commit: &EntryHash) -> Result<(), DBError> {
+        let key = [&cycle.to_be_bytes()[..], &commit[..]].concat();
+        (&self.cycles, &self.refcounts)
+            .transaction(|(cycles, refcounts)| {
+                // the same commit can be applied more times, but it is referenced only once per cycle
+                if cycles.insert(&key[..], Vec::new())?.is_none() {
+                    add_reference(refcounts, &commit[..])?;
+                }
+                Ok(())
+            })
+            .map_err(transaction_error)
+    }
+
+    /// Removes reference to the `hash` (reference of the retention window, if `cycle_key` is set).
+    /// Returns Some(counted), if the entry is no longer referenced, `counted` is true, if references
+    /// of the entry were counted (and have to be released).
+    fn release_reference(
+        &self,
+        hash: &EntryHash,
+        cycle_key: Option<&[u8]>,
+    ) -> Result<Option<bool>, DBError> {
+        (&self.cycles, &self.refcounts, &self.counted)
+            .transaction(|(cycles, refcounts, counted)| {
+                if let Some(cycle_key) = cycle_key {
+                    // already released
+                    if cycles.remove(cycle_key)?.is_none() {
+                        return Ok(None);
+                    }
+                }
+                if remove_reference(refcounts, &hash[..])? > 0 {
+                    return Ok(None);
+                }
+                Ok(Some(counted.remove(&hash[..])?.is_some()))
+            })
+            .map_err(transaction_error)
+    }
+}
+
+impl GarbageCollector for RefCountGCed {
+    fn new_cycle_started(&mut self) -> Result<(), GarbageCollectionError> {
+        self.current_cycle += 1;
+        self.meta
+            .insert(CURRENT_CYCLE_KEY, &self.current_cycle.to_be_bytes()[..])?;
+        self.collect_expired_cycles()
+    }
+
+    fn set_entry_cache(&mut self, entry_cache: Arc<EntryCache>) {
+        self.entry_cache = Some(entry_cache.clone());
+        if let Err(err) = self.send(CmdMsg::SetEntryCache(entry_cache)) {
+            eprintln!("MerkleStorage GC: failed to share entry cache: {:?}", err);
+        }
+    }
+
+    fn block_applied(&mut self, commit: EntryHash) -> Result<(), GarbageCollectionError> {
+        let _guard = self.lock_for_write()?;
+        Ok(self.inner.reference_commit(self.current_cycle, &commit)?)
+    }
+
+    fn gc_stats(&self) -> Option<GarbageCollectionStats> {
+        self.stats.lock().ok().map(|stats| stats.clone())
+    }
+}
+
+impl KeyValueStoreBackend<ContextKeyValueStoreSchema> for RefCountGCed {
+    fn put(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
+        let _guard = self.lock_for_write()?;
+        self.inner.reference_children(key, value)?;
+        self.inner.store.put(key, value)
+    }
+
+    fn delete(&self, key: &EntryHash) -> Result<(), DBError> {
+        self.inner.store.delete(key)?;
+        if let Some(entry_cache) = self.entry_cache.as_ref() {
+            entry_cache.invalidate(key);
+        }
+        Ok(())
+    }
+
+    fn merge(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
+        let _guard = self.lock_for_write()?;
+        self.inner.reference_children(key, value)?;
+        self.inner.store.merge(key, value)
+    }
+
+    fn get(&self, key: &EntryHash) -> Result<Option<ContextValue>, DBError> {
+        self.inner.store.get(key)
+    }
+
+    fn contains(&self, key: &EntryHash) -> Result<bool, DBError> {
+        self.inner.store.contains(key)
+    }
+
+    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<(), DBError> {
+        self.inner.store.retain(predicate)?;
+        // removed entries are not known
+        if let Some(entry_cache) = self.entry_cache.as_ref() {
+            entry_cache.clear();
+        }
+        Ok(())
+    }
+
+    fn write_batch(&self, batch: Vec<(EntryHash, ContextValue)>) -> Result<(), DBError> {
+        let _guard = self.lock_for_write()?;
+        // batch can contain the same entry more times
+        let mut stored = HashSet::with_capacity(batch.len());
+        for (key, value) in &batch {
+            if stored.insert(*key) {
+                self.inner.reference_children(key, value)?;
+            }
+        }
+        self.inner.store.write_batch(batch)
+    }
+
+    fn total_get_mem_usage(&self) -> Result<usize, DBError> {
+        self.inner.store.total_get_mem_usage()
+    }
+}
+
+impl Flushable for RefCountGCed {
+    fn flush(&self) -> Result<(), Error> {
+        self.inner.store.flush()?;
+        match self.inner.db.flush() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(failure::format_err!(
+                "Failed to flush sled db for reference counts, reason: {:?}",
+                e
+            )),
+        }
+    }
+}
+
+impl Persistable for RefCountGCed {
+    fn is_persistent(&self) -> bool {
+        self.inner.store.is_persistent()
+    }
+}
+
+impl MultiInstanceable for RefCountGCed {
+    fn supports_multiple_opened_instances(&self) -> bool {
+        false
+    }
+}
+
+/// GC thread state
+struct Collector {
+    inner: Arc<RefCounts>,
+    stats: Arc<Mutex<GarbageCollectionStats>>,
+    msg_cnt: Arc<AtomicUsize>,
+    rx: mpsc::Receiver<CmdMsg>,
+    /// cache of decoded entries shared with readers, removed entries are invalidated
+    entry_cache: Option<Arc<EntryCache>>,
+    exit: bool,
+}
+
+impl Collector {
+    /// Garbage collector main function
+    fn run(mut self) {
+        while let Ok(msg) = self.rx.recv() {
+            match msg {
+                CmdMsg::CollectCycles(first_retained) => {
+                    if let Err(err) = self.collect(first_retained) {
+                        eprintln!("MerkleStorage GC: collection failed: {:?}", err);
+                    }
+                }
+                CmdMsg::SetEntryCache(entry_cache) => self.entry_cache = Some(entry_cache),
+                CmdMsg::Exit => self.exit = true,
+            }
+            self.msg_cnt.fetch_sub(1, Ordering::AcqRel);
+            if self.exit {
+                return;
+            }
+        }
+    }
+
+    fn update_stats<F: FnOnce(&mut GarbageCollectionStats)>(&self, update: F) {
+        if let Ok(mut stats) = self.stats.lock() {
+            update(&mut stats);
+        }
+    }
+
+    /// Releases commits of all cycles older than `first_retained`
+    fn collect(&self, first_retained: u64) -> Result<(), GarbageCollectionError> {
+        let mut expired = Vec::new();
+        for item in self.inner.cycles.range(..first_retained.to_be_bytes()) {
+            let (key, _) = item?;
+            expired.push(key);
+        }
+        let expired_cycles = expired
+            .iter()
+            .map(|key| key[..8].to_vec())
+            .collect::<HashSet<_>>()
+            .len();
+        self.update_stats(|stats| {
+            stats.phase = GarbageCollectionPhase::Sweeping;
+            stats.pending_cycles = expired_cycles;
+            stats.swept_entries = 0;
+        });
+
+        for key in expired {
+            let commit: EntryHash = key[8..].try_into()?;
+            let mut todo = vec![(commit, Some(key))];
+            while !todo.is_empty() {
+                self.release_step(&mut todo)?;
+            }
+        }
+
+        self.update_stats(|stats| {
+            stats.phase = GarbageCollectionPhase::Idle;
+            stats.pending_cycles = 0;
+            stats.collected_cycles += expired_cycles;
+        });
+        Ok(())
+    }
+
+    /// Releases bounded count of references from `todo`, removes all entries, which are no longer referenced
+    fn release_step(
+        &self,
+        todo: &mut Vec<(EntryHash, Option<IVec>)>,
+    ) -> Result<(), GarbageCollectionError> {
+        let _guard = self.inner.lock.lock()?;
+        let mut swept_count = 0;
+        let mut reclaimed_bytes = 0;
+
+        for _ in 0..COUNT_OF_ENTRIES_IN_SINGLE_GC_STEP {
+            let (hash, cycle_key) = match todo.pop() {
+                Some(item) => item,
+                None => break,
+            };
+            let counted = match self.inner.release_reference(&hash, cycle_key.as_deref())? {
+                Some(counted) => counted,
+                None => continue,
+            };
+            let entry_bytes = match self.inner.store.get(&hash)? {
+                Some(entry_bytes) => entry_bytes,
+                None => continue,
+            };
+            // references of entry without marker were lost (or were never added), so they are leaked
+            if counted {
+                todo.extend(
+                    children(&entry_bytes)?
+                        .into_iter()
+                        .map(|child| (child, None)),
+                );
+            }
+            self.inner.store.delete(&hash)?;
+            if let Some(entry_cache) = self.entry_cache.as_ref() {
+                entry_cache.invalidate(&hash);
+            }
+            swept_count += 1;
+            reclaimed_bytes += (hash.len() + entry_bytes.len()) as u64;
+        }
+
+        self.update_stats(|stats| {
+            stats.swept_entries += swept_count;
+            stats.reclaimed_bytes += reclaimed_bytes;
+        });
+        Ok(())
+    }
+}
+
+/// Adds reference to the `hash`, returns the new count
+fn add_reference(refcounts: &TransactionalTree, hash: &[u8]) -> ConflictableTransactionResult<u64> {
+    let count = read_count(refcounts.get(hash)?) + 1;
+    refcounts.insert(hash, &count.to_be_bytes()[..])?;
+    Ok(count)
+}
+
+/// Removes reference to the `hash`, returns the count of remaining references,
+/// entries without any recorded reference are considered unreferenced
+fn remove_reference(
+    refcounts: &TransactionalTree,
+    hash: &[u8],
+) -> ConflictableTransactionResult<u64> {
+    let count = read_count(refcounts.get(hash)?).saturating_sub(1);
+    if count == 0 {
+        refcounts.remove(hash)?;
+    } else {
+        refcounts.insert(hash, &count.to_be_bytes()[..])?;
+    }
+    Ok(count)
+}
+
+fn read_count(count: Option<IVec>) -> u64 {
+    count.map_or(0, |count| decode_u64(&count).unwrap_or(0))
+}
+
+fn transaction_error(error: TransactionError) -> DBError {
+    match error {
+        TransactionError::Storage(error) => error.into(),
+        // transactions are never aborted
+        TransactionError::Abort(()) => SchemaError::DecodeError.into(),
+    }
+}
+
+/// Returns hashes of entries referenced by the serialized entry
+fn children(entry_bytes: &[u8]) -> Result<Vec<EntryHash>, DBError> {
+    match bincode::deserialize::<Entry>(entry_bytes) {
+        Ok(Entry::Blob(_)) => Ok(Vec::new()),
+        Ok(Entry::Tree(tree)) => Ok(tree.iter().map(|(_, node)| *node.entry_hash).collect()),
+        Ok(Entry::Commit(commit)) => Ok(vec![commit.root_hash]),
+        Err(_) => Err(SchemaError::DecodeError.into()),
+    }
+}
+
+fn decode_u64(bytes: &[u8]) -> Result<u64, DBError> {
+    Ok(u64::from_be_bytes(
+        bytes.try_into().map_err(|_| SchemaError::DecodeError)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::context::gc::test_support::{commit, get, put, temporary_db, test_dir, tree};
+    use crate::context::kv_store::in_memory_backend::InMemoryBackend;
+    use crate::context::kv_store::sled_backend::SledBackend;
+    use crate::context::kv_store::test_support::{blob, entry_hash};
+    use crate::context::merkle::NodeKind;
+
+    use super::*;
+
+    #[test]
+    fn test_collects_expired_cycle() {
+        let mut store =
+            RefCountGCed::new(Box::new(InMemoryBackend::new()), temporary_db(), 1).unwrap();
+
+        // cycle 0: commit [3] -> tree [2] -> blob [1]
+        put(&store, &[1], blob(vec![1]));
+        put(&store, &[2], tree(&[("a", NodeKind::Leaf, &[1])]));
+        put(&store, &[3], commit(&[2]));
+        store.block_applied(entry_hash(&[3])).unwrap();
+        store.new_cycle_started().unwrap();
+
+        // cycle 1: commit [6] -> tree [5] -> blobs [1] (reused), [4]
+        put(&store, &[4], blob(vec![4]));
+        put(
+            &store,
+            &[5],
+            tree(&[("a", NodeKind::Leaf, &[1]), ("b", NodeKind::Leaf, &[4])]),
+        );
+        put(&store, &[6], commit(&[5]));
+        store.block_applied(entry_hash(&[6])).unwrap();
+        store.new_cycle_started().unwrap();
+        store.wait_for_gc_finish();
+
+        assert_eq!(get(&store, &[3]), None);
+        assert_eq!(get(&store, &[2]), None);
+        assert_eq!(get(&store, &[1]), Some(blob(vec![1])));
+        assert_eq!(get(&store, &[4]), Some(blob(vec![4])));
+        assert!(get(&store, &[5]).is_some());
+        assert!(get(&store, &[6]).is_some());
+
+        let stats = store.gc_stats().unwrap();
+        assert_eq!(stats.collected_cycles, 1);
+        assert_eq!(stats.swept_entries, 2);
+        assert!(stats.reclaimed_bytes > 0);
+
+        store.new_cycle_started().unwrap();
+        store.wait_for_gc_finish();
+        assert_eq!(store.total_get_mem_usage().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_stored_entry_is_counted_once() {
+        let mut store =
+            RefCountGCed::new(Box::new(InMemoryBackend::new()), temporary_db(), 0).unwrap();
+
+        let blob_entry = (
+            entry_hash(&[1]),
+            bincode::serialize(&blob(vec![1])).unwrap(),
+        );
+        let tree_entry = (
+            entry_hash(&[2]),
+            bincode::serialize(&tree(&[("a", NodeKind::Leaf, &[1])])).unwrap(),
+        );
+        store
+            .write_batch(vec![
+                tree_entry.clone(),
+                tree_entry.clone(),
+                blob_entry.clone(),
+            ])
+            .unwrap();
+        store.write_batch(vec![tree_entry, blob_entry]).unwrap();
+        put(&store, &[3], commit(&[2]));
+        store.block_applied(entry_hash(&[3])).unwrap();
+        store.block_applied(entry_hash(&[3])).unwrap();
+
+        store.new_cycle_started().unwrap();
+        store.wait_for_gc_finish();
+        assert_eq!(store.total_get_mem_usage().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_counts_survive_restart() {
+        let dir = test_dir("refcount_gc", "test_counts_survive_restart");
+        let open = || {
+            let db = sled::Config::new().path(&dir).open().unwrap();
+            RefCountGCed::new(Box::new(SledBackend::new(db.clone())), db, 1).unwrap()
+        };
+
+        {
+            let mut store = open();
+            put(&store, &[1], blob(vec![1]));
+            put(&store, &[2], tree(&[("a", NodeKind::Leaf, &[1])]));
+            put(&store, &[3], commit(&[2]));
+            store.block_applied(entry_hash(&[3])).unwrap();
+            store.new_cycle_started().unwrap();
+            store.flush().unwrap();
+        }
+
+        let mut store = open();
+        put(&store, &[4], tree(&[("a", NodeKind::Leaf, &[1])]));
+        put(&store, &[5], commit(&[4]));
+        store.block_applied(entry_hash(&[5])).unwrap();
+        store.new_cycle_started().unwrap();
+        store.wait_for_gc_finish();
+
+        // cycle 0 was collected after restart, blob is still referenced from cycle 1
+        assert_eq!(get(&store, &[3]), None);
+        assert_eq!(get(&store, &[2]), None);
+        assert_eq!(get(&store, &[1]), Some(blob(vec![1])));
+        assert!(get(&store, &[5]).is_some());
+
+        store.new_cycle_started().unwrap();
+        store.wait_for_gc_finish();
+        assert_eq!(get(&store, &[1]), None);
+        assert_eq!(get(&store, &[5]), None);
+    }
+
+    #[test]
+    fn test_lost_references_only_leak() {
+        let mut store =
+            RefCountGCed::new(Box::new(InMemoryBackend::new()), temporary_db(), 0).unwrap();
+
+        // cycle 0: commit [3] -> tree [2] -> blob [1]
+        put(&store, &[1], blob(vec![1]));
+        put(&store, &[2], tree(&[("a", NodeKind::Leaf, &[1])]));
+        put(&store, &[3], commit(&[2]));
+        store.block_applied(entry_hash(&[3])).unwrap();
+        // references added by the tree were not flushed before crash
+        store.inner.counted.remove(&entry_hash(&[2])[..]).unwrap();
+        store.inner.refcounts.remove(&entry_hash(&[1])[..]).unwrap();
+
+        // cycle 1: commit [5] -> tree [4] -> blob [1] (reused)
+        store.new_cycle_started().unwrap();
+        put(&store, &[4], tree(&[("a", NodeKind::Leaf, &[1])]));
+        put(&store, &[5], commit(&[4]));
+        store.block_applied(entry_hash(&[5])).unwrap();
+        store.wait_for_gc_finish();
+
+        // tree without recorded references does not release its blob
+        assert_eq!(get(&store, &[3]), None);
+        assert_eq!(get(&store, &[2]), None);
+        assert_eq!(get(&store, &[1]), Some(blob(vec![1])));
+        assert!(get(&store, &[4]).is_some());
+    }
+
+    #[test]
+    fn test_invalidates_entry_cache() {
+        let mut store =
+            RefCountGCed::new(Box::new(InMemoryBackend::new()), temporary_db(), 0).unwrap();
+        let entry_cache = Arc::new(EntryCache::new(1024 * 1024));
+        store.set_entry_cache(entry_cache.clone());
+
+        put(&store, &[1], blob(vec![1]));
+        put(&store, &[2], tree(&[("a", NodeKind::Leaf, &[1])]));
+        put(&store, &[3], commit(&[2]));
+        store.block_applied(entry_hash(&[3])).unwrap();
+        for key in &[[1u8], [2], [3]] {
+            entry_cache.insert(entry_hash(key), Arc::new(get(&store, key).unwrap()));
+        }
+        store.new_cycle_started().unwrap();
+        store.wait_for_gc_finish();
+
+        for key in &[[1u8], [2], [3]] {
+            assert_eq!(get(&store, key), None);
+            assert_eq!(entry_cache.get(&entry_hash(key)), None);
+        }
+        assert_eq!(entry_cache.stats().entries, 0);
+    }
+}
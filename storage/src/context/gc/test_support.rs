@@ -0,0 +1,69 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Helpers shared by tests of garbage collected stores.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::context::kv_store::test_support::entry_hash;
+use crate::context::merkle::{Commit, Entry, Node, NodeKind, Tree};
+use crate::context::ContextKeyValueStoreSchema;
+use crate::persistent::KeyValueStoreBackend;
+
+/// Empty directory `<gc>_<name>` in OUT_DIR
+pub fn test_dir(gc: &str, name: &str) -> PathBuf {
+    let out_dir = env::var("OUT_DIR")
+        .expect("OUT_DIR is not defined - please add build.rs to root or set env variable OUT_DIR");
+    let dir = Path::new(&out_dir).join(format!("{}_{}", gc, name));
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    dir
+}
+
+pub fn temporary_db() -> sled::Db {
+    sled::Config::new().temporary(true).open().unwrap()
+}
+
+pub fn put(store: &dyn KeyValueStoreBackend<ContextKeyValueStoreSchema>, key: &[u8], value: Entry) {
+    store
+        .put(&entry_hash(key), &bincode::serialize(&value).unwrap())
+        .unwrap();
+}
+
+pub fn get(
+    store: &dyn KeyValueStoreBackend<ContextKeyValueStoreSchema>,
+    key: &[u8],
+) -> Option<Entry> {
+    store
+        .get(&entry_hash(key))
+        .unwrap()
+        .map(|x| bincode::deserialize(&x[..]).unwrap())
+}
+
+pub fn tree(children: &[(&str, NodeKind, &[u8])]) -> Entry {
+    let mut tree = Tree::new();
+    for (name, node_kind, key) in children {
+        tree.insert(
+            name.to_string(),
+            Arc::new(Node {
+                node_kind: node_kind.clone(),
+                entry_hash: Arc::new(entry_hash(key)),
+            }),
+        );
+    }
+    Entry::Tree(tree)
+}
+
+pub fn commit(root: &[u8]) -> Entry {
+    Entry::Commit(Commit {
+        parent_commit_hash: None,
+        root_hash: entry_hash(root),
+        time: 0,
+        author: "Tezos".to_string(),
+        message: "Genesis".to_string(),
+    })
+}
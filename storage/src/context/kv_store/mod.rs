@@ -11,6 +11,7 @@ use strum_macros::EnumIter;
 
 pub mod btree_map;
 pub mod in_memory_backend;
+pub mod pack_file_backend;
 pub mod rocksdb_backend;
 pub mod sled_backend;
 pub mod stats;
@@ -23,6 +24,7 @@ pub enum SupportedContextKeyValueStore {
     InMem,
     Sled { path: PathBuf },
     BTreeMap,
+    PackFile { path: PathBuf },
 }
 
 impl SupportedContextKeyValueStore {
@@ -40,6 +42,7 @@ impl SupportedContextKeyValueStore {
             SupportedContextKeyValueStore::InMem => vec!["inmem"],
             SupportedContextKeyValueStore::Sled { .. } => vec!["sled"],
             SupportedContextKeyValueStore::BTreeMap => vec!["btree"],
+            SupportedContextKeyValueStore::PackFile { .. } => vec!["pack"],
         }
     }
 }
@@ -139,6 +142,14 @@ pub mod test_support {
                     SupportedContextKeyValueStore::BTreeMap,
                     Box::new(BTreeMapBackendTestContextKvStoreFactory),
                 ),
+                SupportedContextKeyValueStore::PackFile { .. } => store_factories.insert(
+                    SupportedContextKeyValueStore::PackFile {
+                        path: base_dir.clone(),
+                    },
+                    Box::new(PackFileBackendTestContextKvStoreFactory {
+                        base_path: base_dir.clone(),
+                    }),
+                ),
             };
         }
 
@@ -265,6 +276,43 @@ pub mod test_support {
         }
     }
 
+    /// Pack file kv-store
+    pub struct PackFileBackendTestContextKvStoreFactory {
+        base_path: PathBuf,
+    }
+
+    impl PackFileBackendTestContextKvStoreFactory {
+        fn db_path(&self, name: &str) -> PathBuf {
+            self.base_path.join(format!("pack_{}", name))
+        }
+    }
+
+    impl TestContextKvStoreFactory for PackFileBackendTestContextKvStoreFactory {
+        fn create(&self, name: &str) -> Result<Box<ContextKeyValueStore>, TestKeyValueStoreError> {
+            use crate::context::kv_store::pack_file_backend::PackFileBackend;
+
+            // clear files
+            let db_path = self.db_path(name);
+            if Path::new(&db_path).exists() {
+                let _ = fs::remove_dir_all(&db_path)?;
+            }
+
+            Ok(Box::new(PackFileBackend::new(db_path, None)?))
+        }
+    }
+
+    impl MultiInstanceable for PackFileBackendTestContextKvStoreFactory {
+        fn supports_multiple_opened_instances(&self) -> bool {
+            false
+        }
+    }
+
+    impl Persistable for PackFileBackendTestContextKvStoreFactory {
+        fn is_persistent(&self) -> bool {
+            true
+        }
+    }
+
     /// Rocksdb map kv-store
     pub struct RocksDbBackendTestContextKvStoreFactory {
         base_path: PathBuf,
@@ -308,7 +356,6 @@ pub mod test_support {
             let db_path = self.db_path(db_name);
             let db_path_secondary_log = db_path.join("secondary");
 
-            // TODO: TE-150 - real support mutliprocess
             Ok(DB::open_cf_as_secondary(
                 &db_opts,
                 db_path,
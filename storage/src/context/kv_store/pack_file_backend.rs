@@ -0,0 +1,972 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Context store with entries appended to pack files
+//!
+//! Entries are never rewritten in place, every put appends a record `[hash][value length][value]`
+//! to the newest pack file (`<pack_id>.pack`). Location of every entry is kept in memory
+//! and persisted to the append-only `index` file with fixed size records `[hash][pack_id][offset][value length]`,
+//! so opening the store does not read the packs. Only records of the newest pack written after the last
+//! indexed record (e.g. crash between pack and index write) are scanned and indexed again on open.
+//!
+//! Entries are content addressed, so entry already stored in any pack is not appended again.
+//!
+//! Garbage collection works with whole packs - every cycle is written to a new pack and commits
+//! of applied blocks are recorded to `<pack_id>.commits`. Hashes of entries from older packs referenced
+//! by entries of the pack are recorded to `<pack_id>.refs`, when the pack is sealed (references of the newest
+//! pack are kept in memory and collected again by scan of the newest pack on open).
+//! When there are more packs than retained cycles, entries of the oldest pack referenced from newer packs
+//! (and entries of the oldest pack reachable from them) are appended to the newest pack and the oldest pack
+//! is removed, so collection reads just the oldest pack, not the whole live set.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::mem;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use failure::Error;
+
+use crate::context::gc::{GarbageCollectionError, GarbageCollector};
+use crate::context::merkle::entry_cache::EntryCache;
+use crate::context::merkle::hash::{EntryHash, ENTRY_HASH_LEN};
+use crate::context::merkle::Entry;
+use crate::context::{ContextKeyValueStoreSchema, ContextValue};
+use crate::persistent::database::DBError;
+use crate::persistent::SchemaError;
+use crate::persistent::{Flushable, KeyValueStoreBackend, MultiInstanceable, Persistable};
+
+const PACK_FILE_EXTENSION: &str = "pack";
+const COMMITS_FILE_EXTENSION: &str = "commits";
+const REFS_FILE_EXTENSION: &str = "refs";
+const INDEX_FILE_NAME: &str = "index";
+const INDEX_TMP_FILE_NAME: &str = "index.tmp";
+
+/// `[hash][value length]`
+const RECORD_HEADER_LEN: u64 = (ENTRY_HASH_LEN + 4) as u64;
+/// `[hash][pack_id][offset][value length]`
+const INDEX_RECORD_LEN: usize = ENTRY_HASH_LEN + 4 + 8 + 4;
+/// Value length of index record, which removes the entry
+const REMOVED_VALUE_LEN: u32 = u32::MAX;
+/// Entries moved by garbage collection are appended in batches of this size
+const MOVE_BATCH_SIZE: usize = 4 * 1024 * 1024;
+
+/// Position of the entry record in the pack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PackLocation {
+    pack_id: u32,
+    /// Offset of the record (not value) in the pack
+    offset: u64,
+    value_len: u32,
+}
+
+impl PackLocation {
+    fn value_offset(&self) -> u64 {
+        self.offset + RECORD_HEADER_LEN
+    }
+
+    fn record_end(&self) -> u64 {
+        self.value_offset() + self.value_len as u64
+    }
+}
+
+struct Pack {
+    file: File,
+    size: u64,
+}
+
+/// Pack files with index, all data of [PackFileBackend] are guarded by one lock
+struct PackFiles {
+    dir: PathBuf,
+    packs: BTreeMap<u32, Pack>,
+    index: HashMap<EntryHash, PackLocation>,
+    index_file: File,
+    /// Read-only pack files are never written, records not indexed because of crash are indexed just in memory
+    read_only: bool,
+    /// References from the newest pack to older packs are tracked only for garbage collection
+    track_refs: bool,
+    /// Entries of older packs referenced by entries of the newest pack
+    newest_refs: HashSet<EntryHash>,
+}
+
+impl PackFiles {
+    fn open(dir: &Path, read_only: bool, track_refs: bool) -> Result<Self, DBError> {
+        if !read_only {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut packs = BTreeMap::new();
+        for dir_entry in fs::read_dir(dir)? {
+            let path = dir_entry?.path();
+            if let Some(pack_id) = parse_pack_id(&path) {
+                packs.insert(pack_id, open_pack(&path, read_only)?);
+            }
+        }
+        if packs.is_empty() {
+            packs.insert(0, open_pack(&pack_path(dir, 0), read_only)?);
+        }
+
+        let index_path = dir.join(INDEX_FILE_NAME);
+        let mut pack_files = Self {
+            dir: dir.to_path_buf(),
+            packs,
+            index: HashMap::new(),
+            index_file: if read_only {
+                File::open(&index_path)?
+            } else {
+                open_append(&index_path)?
+            },
+            read_only,
+            track_refs,
+            newest_refs: HashSet::new(),
+        };
+        let last_indexed_offset = pack_files.load_index()?;
+        pack_files.recover_newest_pack(last_indexed_offset)?;
+        if track_refs {
+            pack_files.scan_newest_refs()?;
+        }
+        if !read_only {
+            // pack and index could be just created
+            sync_dir(dir)?;
+        }
+        Ok(pack_files)
+    }
+
+    fn newest_pack_id(&self) -> u32 {
+        // there is always at least one pack
+        *self.packs.keys().next_back().unwrap()
+    }
+
+    fn oldest_pack_id(&self) -> u32 {
+        *self.packs.keys().next().unwrap()
+    }
+
+    /// Loads all records from the index file and returns offset of the last indexed record of the newest pack
+    fn load_index(&mut self) -> Result<Option<u64>, DBError> {
+        let index_path = self.dir.join(INDEX_FILE_NAME);
+        let data = fs::read(&index_path)?;
+        let newest_pack_id = self.newest_pack_id();
+        let mut last_indexed_offset = None;
+
+        for record in data.chunks_exact(INDEX_RECORD_LEN) {
+            let (hash, location) = decode_index_record(record);
+            if location.pack_id == newest_pack_id {
+                last_indexed_offset = last_indexed_offset.max(Some(location.offset));
+            }
+            if location.value_len == REMOVED_VALUE_LEN {
+                self.index.remove(&hash);
+            } else {
+                self.index.insert(hash, location);
+            }
+        }
+
+        // partially written record
+        let valid_len = data.len() - data.len() % INDEX_RECORD_LEN;
+        if valid_len < data.len() && !self.read_only {
+            self.index_file.set_len(valid_len as u64)?;
+        }
+
+        // removed packs or pack records lost by crash
+        let packs = &self.packs;
+        self.index
+            .retain(|_, location| match packs.get(&location.pack_id) {
+                Some(pack) => location.record_end() <= pack.size,
+                None => false,
+            });
+
+        Ok(last_indexed_offset)
+    }
+
+    /// Indexes complete records of the newest pack written after the last indexed record
+    /// and truncates partially written record
+    fn recover_newest_pack(&mut self, last_indexed_offset: Option<u64>) -> Result<(), DBError> {
+        let pack_id = self.newest_pack_id();
+        let pack = &self.packs[&pack_id];
+        let pack_size = pack.size;
+
+        let mut offset = match last_indexed_offset {
+            Some(offset) if offset + RECORD_HEADER_LEN <= pack_size => {
+                let (_, value_len) = read_record_header(&pack.file, offset)?;
+                offset + RECORD_HEADER_LEN + value_len as u64
+            }
+            Some(_) => pack_size,
+            None => 0,
+        };
+
+        let mut recovered = Vec::new();
+        while offset + RECORD_HEADER_LEN <= pack_size {
+            let (hash, value_len) = read_record_header(&pack.file, offset)?;
+            let location = PackLocation {
+                pack_id,
+                offset,
+                value_len,
+            };
+            if location.record_end() > pack_size {
+                break;
+            }
+            recovered.push((hash, location));
+            offset = location.record_end();
+        }
+
+        if self.read_only {
+            self.index.extend(recovered);
+            return Ok(());
+        }
+
+        if offset < pack_size {
+            let pack = self.packs.get_mut(&pack_id).unwrap();
+            pack.file.set_len(offset)?;
+            pack.size = offset;
+        }
+        if !recovered.is_empty() {
+            let mut index_buffer = Vec::with_capacity(recovered.len() * INDEX_RECORD_LEN);
+            for (hash, location) in recovered {
+                encode_index_record(&mut index_buffer, &hash, &location);
+                self.index.insert(hash, location);
+            }
+            self.index_file.write_all(&index_buffer)?;
+        }
+        Ok(())
+    }
+
+    /// Collects references to older packs from all records of the newest pack
+    fn scan_newest_refs(&mut self) -> Result<(), DBError> {
+        let pack_id = self.newest_pack_id();
+        let pack = &self.packs[&pack_id];
+        let mut offset = 0;
+        while offset + RECORD_HEADER_LEN <= pack.size {
+            let (_, value_len) = read_record_header(&pack.file, offset)?;
+            let location = PackLocation {
+                pack_id,
+                offset,
+                value_len,
+            };
+            let value = self.read_value(&location)?;
+            for reference in entry_references(&value)? {
+                if let Some(reference_location) = self.index.get(&reference) {
+                    if reference_location.pack_id != pack_id {
+                        self.newest_refs.insert(reference);
+                    }
+                }
+            }
+            offset = location.record_end();
+        }
+        Ok(())
+    }
+
+    fn check_writable(&self) -> Result<(), DBError> {
+        if self.read_only {
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("Pack files are opened read-only: {:?}", self.dir),
+            )
+            .into())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read_value(&self, location: &PackLocation) -> Result<ContextValue, DBError> {
+        let pack = self.packs.get(&location.pack_id).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Pack {} is missing", location.pack_id),
+            )
+        })?;
+        let mut value = vec![0; location.value_len as usize];
+        pack.file
+            .read_exact_at(&mut value, location.value_offset())?;
+        Ok(value)
+    }
+
+    fn get(&self, key: &EntryHash) -> Result<Option<ContextValue>, DBError> {
+        match self.index.get(key) {
+            Some(location) => self.read_value(location).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Appends entries to the newest pack, pack is written before index,
+    /// so every indexed entry is readable after crash
+    fn append<'a, I>(&mut self, entries: I) -> Result<(), DBError>
+    where
+        I: IntoIterator<Item = (&'a EntryHash, &'a [u8])>,
+    {
+        self.check_writable()?;
+        let pack_id = self.newest_pack_id();
+        let pack = self.packs.get_mut(&pack_id).unwrap();
+
+        let mut pack_buffer = Vec::new();
+        let mut index_buffer = Vec::new();
+        let mut locations = Vec::new();
+        for (hash, value) in entries {
+            let location = PackLocation {
+                pack_id,
+                offset: pack.size + pack_buffer.len() as u64,
+                value_len: value.len().try_into().map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Value is too large for pack file: {} bytes", value.len()),
+                    )
+                })?,
+            };
+            if self.track_refs {
+                for reference in entry_references(value)? {
+                    if let Some(reference_location) = self.index.get(&reference) {
+                        if reference_location.pack_id != pack_id {
+                            self.newest_refs.insert(reference);
+                        }
+                    }
+                }
+            }
+            pack_buffer.extend_from_slice(hash);
+            pack_buffer.extend_from_slice(&location.value_len.to_be_bytes());
+            pack_buffer.extend_from_slice(value);
+            encode_index_record(&mut index_buffer, hash, &location);
+            locations.push((*hash, location));
+        }
+        if locations.is_empty() {
+            return Ok(());
+        }
+
+        pack.file.write_all(&pack_buffer)?;
+        pack.size += pack_buffer.len() as u64;
+        self.index_file.write_all(&index_buffer)?;
+        self.index.extend(locations);
+        Ok(())
+    }
+
+    /// Appends just entries, which are not stored yet
+    fn append_new<'a, I>(&mut self, entries: I) -> Result<(), DBError>
+    where
+        I: IntoIterator<Item = (&'a EntryHash, &'a [u8])>,
+    {
+        let mut appended = HashSet::new();
+        let new_entries: Vec<_> = entries
+            .into_iter()
+            .filter(|(hash, _)| !self.index.contains_key(*hash) && appended.insert(**hash))
+            .collect();
+        self.append(new_entries)
+    }
+
+    fn remove<'a, I>(&mut self, keys: I) -> Result<(), DBError>
+    where
+        I: IntoIterator<Item = &'a EntryHash>,
+    {
+        self.check_writable()?;
+        let mut index_buffer = Vec::new();
+        for key in keys {
+            if let Some(mut location) = self.index.remove(key) {
+                location.value_len = REMOVED_VALUE_LEN;
+                encode_index_record(&mut index_buffer, key, &location);
+            }
+        }
+        if !index_buffer.is_empty() {
+            self.index_file.write_all(&index_buffer)?;
+        }
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<(), DBError> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.packs[&self.newest_pack_id()].file.sync_data()?;
+        self.index_file.sync_data()?;
+        Ok(())
+    }
+
+    /// Seals the newest pack (with its references) and starts a new one
+    fn roll(&mut self) -> Result<(), DBError> {
+        self.check_writable()?;
+        self.sync()?;
+        if self.track_refs {
+            let mut refs_buffer = Vec::with_capacity(self.newest_refs.len() * ENTRY_HASH_LEN);
+            for reference in self.newest_refs.drain() {
+                refs_buffer.extend_from_slice(&reference);
+            }
+            let mut refs_file = File::create(refs_path(&self.dir, self.newest_pack_id()))?;
+            refs_file.write_all(&refs_buffer)?;
+            refs_file.sync_all()?;
+        }
+        let pack_id = self.newest_pack_id() + 1;
+        self.packs
+            .insert(pack_id, open_pack(&pack_path(&self.dir, pack_id), false)?);
+        sync_dir(&self.dir)?;
+        Ok(())
+    }
+
+    fn record_commit(&self, commit: &EntryHash) -> Result<(), DBError> {
+        self.check_writable()?;
+        let path = commits_path(&self.dir, self.newest_pack_id());
+        let created = !path.exists();
+        let mut commits_file = open_append(&path)?;
+        commits_file.write_all(commit)?;
+        if created {
+            sync_dir(&self.dir)?;
+        }
+        Ok(())
+    }
+
+    fn read_commits(&self, pack_id: u32) -> Result<Vec<EntryHash>, DBError> {
+        read_hashes(&commits_path(&self.dir, pack_id))
+    }
+
+    fn read_refs(&self, pack_id: u32) -> Result<Vec<EntryHash>, DBError> {
+        if pack_id == self.newest_pack_id() {
+            Ok(self.newest_refs.iter().cloned().collect())
+        } else {
+            read_hashes(&refs_path(&self.dir, pack_id))
+        }
+    }
+
+    /// Moves entries referenced from newer packs (and entries reachable from them) out of the oldest pack
+    /// and removes it. Returns false (and keeps the pack), if no block was applied since the oldest pack.
+    fn collect_oldest_pack(&mut self) -> Result<Option<Vec<EntryHash>>, GarbageCollectionError> {
+        let collected_pack_id = self.oldest_pack_id();
+        let newer_pack_ids: Vec<u32> = self.packs.keys().skip(1).cloned().collect();
+        let mut pending = Vec::new();
+        let mut has_commits = false;
+        for pack_id in newer_pack_ids {
+            let commits = self.read_commits(pack_id)?;
+            has_commits |= !commits.is_empty();
+            pending.extend(commits);
+            pending.extend(self.read_refs(pack_id)?);
+        }
+        if !has_commits {
+            return Ok(None);
+        }
+
+        // mark - just entries of the collected pack are walked, all references from newer packs are recorded,
+        // so entries, which are not referenced, are not reachable from retained commits
+        let mut visited = HashSet::new();
+        let mut moved = Vec::new();
+        let mut moved_size = 0;
+        while let Some(hash) = pending.pop() {
+            let location = match self.index.get(&hash) {
+                Some(location) if location.pack_id == collected_pack_id => *location,
+                _ => continue,
+            };
+            if !visited.insert(hash) {
+                continue;
+            }
+            let value = self.read_value(&location)?;
+            pending.extend(entry_references(&value)?);
+
+            moved_size += value.len();
+            moved.push((hash, value));
+            if moved_size >= MOVE_BATCH_SIZE {
+                self.append(moved.iter().map(|(hash, value)| (hash, value.as_slice())))?;
+                moved.clear();
+                moved_size = 0;
+            }
+        }
+        self.append(moved.iter().map(|(hash, value)| (hash, value.as_slice())))?;
+        self.sync()?;
+
+        // sweep - index without collected pack is stored before the pack is removed
+        let mut removed = Vec::new();
+        self.index.retain(|hash, location| {
+            if location.pack_id == collected_pack_id {
+                removed.push(*hash);
+                false
+            } else {
+                true
+            }
+        });
+        self.rewrite_index()?;
+        self.packs.remove(&collected_pack_id);
+        fs::remove_file(pack_path(&self.dir, collected_pack_id)).map_err(DBError::from)?;
+        for path in &[
+            commits_path(&self.dir, collected_pack_id),
+            refs_path(&self.dir, collected_pack_id),
+        ] {
+            if path.exists() {
+                fs::remove_file(path).map_err(DBError::from)?;
+            }
+        }
+        sync_dir(&self.dir)?;
+        Ok(Some(removed))
+    }
+
+    /// Replaces the index file with current locations only
+    fn rewrite_index(&mut self) -> Result<(), DBError> {
+        let tmp_path = self.dir.join(INDEX_TMP_FILE_NAME);
+        let mut index_buffer = Vec::with_capacity(self.index.len() * INDEX_RECORD_LEN);
+        for (hash, location) in &self.index {
+            encode_index_record(&mut index_buffer, hash, location);
+        }
+        let mut tmp_file = File::create(&tmp_path)?;
+        tmp_file.write_all(&index_buffer)?;
+        tmp_file.sync_all()?;
+
+        let index_path = self.dir.join(INDEX_FILE_NAME);
+        fs::rename(&tmp_path, &index_path)?;
+        // rename is durable just after its directory is synced
+        sync_dir(&self.dir)?;
+        self.index_file = open_append(&index_path)?;
+        Ok(())
+    }
+}
+
+fn read_record_header(file: &File, offset: u64) -> Result<(EntryHash, u32), DBError> {
+    let mut header = [0; RECORD_HEADER_LEN as usize];
+    file.read_exact_at(&mut header, offset)?;
+    let hash = header[..ENTRY_HASH_LEN].try_into().unwrap();
+    let value_len = u32::from_be_bytes(header[ENTRY_HASH_LEN..].try_into().unwrap());
+    Ok((hash, value_len))
+}
+
+fn encode_index_record(buffer: &mut Vec<u8>, hash: &EntryHash, location: &PackLocation) {
+    buffer.extend_from_slice(hash);
+    buffer.extend_from_slice(&location.pack_id.to_be_bytes());
+    buffer.extend_from_slice(&location.offset.to_be_bytes());
+    buffer.extend_from_slice(&location.value_len.to_be_bytes());
+}
+
+fn decode_index_record(record: &[u8]) -> (EntryHash, PackLocation) {
+    let (hash, record) = record.split_at(ENTRY_HASH_LEN);
+    let (pack_id, record) = record.split_at(4);
+    let (offset, value_len) = record.split_at(8);
+    (
+        hash.try_into().unwrap(),
+        PackLocation {
+            pack_id: u32::from_be_bytes(pack_id.try_into().unwrap()),
+            offset: u64::from_be_bytes(offset.try_into().unwrap()),
+            value_len: u32::from_be_bytes(value_len.try_into().unwrap()),
+        },
+    )
+}
+
+fn pack_path(dir: &Path, pack_id: u32) -> PathBuf {
+    dir.join(format!("{:010}.{}", pack_id, PACK_FILE_EXTENSION))
+}
+
+fn commits_path(dir: &Path, pack_id: u32) -> PathBuf {
+    dir.join(format!("{:010}.{}", pack_id, COMMITS_FILE_EXTENSION))
+}
+
+fn refs_path(dir: &Path, pack_id: u32) -> PathBuf {
+    dir.join(format!("{:010}.{}", pack_id, REFS_FILE_EXTENSION))
+}
+
+fn read_hashes(path: &Path) -> Result<Vec<EntryHash>, DBError> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(fs::read(path)?
+        .chunks_exact(ENTRY_HASH_LEN)
+        .map(|hash| hash.try_into().unwrap())
+        .collect())
+}
+
+/// Hashes of entries directly referenced by the serialized entry
+fn entry_references(value: &[u8]) -> Result<Vec<EntryHash>, DBError> {
+    let entry: Entry = bincode::deserialize(value).map_err(|_| SchemaError::DecodeError)?;
+    Ok(match entry {
+        Entry::Blob(_) => Vec::new(),
+        Entry::Tree(tree) => tree.values().map(|node| *node.entry_hash).collect(),
+        Entry::Commit(commit) => vec![commit.root_hash],
+    })
+}
+
+/// Makes changes of directory entries (create/rename/remove) durable
+fn sync_dir(dir: &Path) -> Result<(), DBError> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+fn parse_pack_id(path: &Path) -> Option<u32> {
+    if path.extension()? != PACK_FILE_EXTENSION {
+        return None;
+    }
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+fn open_append(path: &Path) -> Result<File, DBError> {
+    Ok(OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)?)
+}
+
+fn open_pack(path: &Path, read_only: bool) -> Result<Pack, DBError> {
+    let file = if read_only {
+        File::open(path)?
+    } else {
+        open_append(path)?
+    };
+    let size = file.metadata()?.len();
+    Ok(Pack { file, size })
+}
+
+/// Persistent context store with append-only pack files, see [module documentation](self)
+pub struct PackFileBackend {
+    inner: RwLock<PackFiles>,
+    /// Count of finished cycles, which commits are kept by garbage collection, `None` keeps everything
+    retained_cycles: Option<usize>,
+    /// cache of decoded entries shared with readers, entries of collected packs are invalidated
+    entry_cache: Option<Arc<EntryCache>>,
+}
+
+impl PackFileBackend {
+    /// Opens (or creates) store in the directory `dir`, garbage collection is enabled by `retained_cycles`
+    pub fn new<P: AsRef<Path>>(dir: P, retained_cycles: Option<usize>) -> Result<Self, DBError> {
+        Ok(PackFileBackend {
+            inner: RwLock::new(PackFiles::open(
+                dir.as_ref(),
+                false,
+                retained_cycles.is_some(),
+            )?),
+            retained_cycles,
+            entry_cache: None,
+        })
+    }
+
+    /// Opens existing store in the directory `dir` just for reading, every write fails
+    pub fn open_read_only<P: AsRef<Path>>(dir: P) -> Result<Self, DBError> {
+        Ok(PackFileBackend {
+            inner: RwLock::new(PackFiles::open(dir.as_ref(), true, false)?),
+            retained_cycles: None,
+            entry_cache: None,
+        })
+    }
+}
+
+impl GarbageCollector for PackFileBackend {
+    fn new_cycle_started(&mut self) -> Result<(), GarbageCollectionError> {
+        let retained_cycles = self.retained_cycles;
+        let pack_files = self.inner.get_mut()?;
+        pack_files.roll()?;
+
+        if let Some(retained_cycles) = retained_cycles {
+            // retained cycles + current cycle
+            while pack_files.packs.len() > retained_cycles + 1 {
+                match pack_files.collect_oldest_pack()? {
+                    Some(removed) => {
+                        if let Some(entry_cache) = self.entry_cache.as_ref() {
+                            removed.iter().for_each(|hash| entry_cache.invalidate(hash));
+                        }
+                    }
+                    None => break,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn block_applied(&mut self, commit: EntryHash) -> Result<(), GarbageCollectionError> {
+        if self.retained_cycles.is_some() {
+            self.inner.get_mut()?.record_commit(&commit)?;
+        }
+        Ok(())
+    }
+
+    fn set_entry_cache(&mut self, entry_cache: Arc<EntryCache>) {
+        self.entry_cache = Some(entry_cache);
+    }
+}
+
+impl KeyValueStoreBackend<ContextKeyValueStoreSchema> for PackFileBackend {
+    fn put(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
+        let mut w = self.inner.write()?;
+        w.append_new(vec![(key, value.as_slice())])
+    }
+
+    fn delete(&self, key: &EntryHash) -> Result<(), DBError> {
+        let mut w = self.inner.write()?;
+        w.remove(vec![key])?;
+        if let Some(entry_cache) = self.entry_cache.as_ref() {
+            entry_cache.invalidate(key);
+        }
+        Ok(())
+    }
+
+    fn merge(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
+        self.put(key, value)
+    }
+
+    fn get(&self, key: &EntryHash) -> Result<Option<ContextValue>, DBError> {
+        let r = self.inner.read()?;
+        r.get(key)
+    }
+
+    fn contains(&self, key: &EntryHash) -> Result<bool, DBError> {
+        let r = self.inner.read()?;
+        Ok(r.index.contains_key(key))
+    }
+
+    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<(), DBError> {
+        let mut w = self.inner.write()?;
+        let garbage_keys: Vec<_> = w.index.keys().filter(|k| !predicate(k)).cloned().collect();
+        w.remove(garbage_keys.iter())?;
+        if let Some(entry_cache) = self.entry_cache.as_ref() {
+            garbage_keys
+                .iter()
+                .for_each(|hash| entry_cache.invalidate(hash));
+        }
+        Ok(())
+    }
+
+    fn write_batch(&self, batch: Vec<(EntryHash, ContextValue)>) -> Result<(), DBError> {
+        let mut w = self.inner.write()?;
+        w.append_new(batch.iter().map(|(k, v)| (k, v.as_slice())))
+    }
+
+    fn total_get_mem_usage(&self) -> Result<usize, DBError> {
+        let r = self.inner.read()?;
+        Ok(
+            r.index.capacity() * (mem::size_of::<EntryHash>() + mem::size_of::<PackLocation>())
+                + r.newest_refs.capacity() * mem::size_of::<EntryHash>(),
+        )
+    }
+}
+
+impl Flushable for PackFileBackend {
+    fn flush(&self) -> Result<(), Error> {
+        Ok(self.inner.read().map_err(DBError::from)?.sync()?)
+    }
+}
+
+impl MultiInstanceable for PackFileBackend {
+    fn supports_multiple_opened_instances(&self) -> bool {
+        false
+    }
+}
+
+impl Persistable for PackFileBackend {
+    fn is_persistent(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use crate::context::kv_store::test_support::{blob_serialized, entry_hash};
+    use crate::context::merkle::merkle_storage::MerkleStorage;
+
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let out_dir = env::var("OUT_DIR").expect(
+            "OUT_DIR is not defined - please add build.rs to root or set env variable OUT_DIR",
+        );
+        let dir = Path::new(&out_dir).join(format!("pack_file_{}", name));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_reopen() {
+        let dir = test_dir("reopen");
+        {
+            let storage = PackFileBackend::new(&dir, None).unwrap();
+            storage
+                .put(&entry_hash(&[1]), &blob_serialized(vec![1]))
+                .unwrap();
+            storage
+                .write_batch(vec![
+                    (entry_hash(&[2]), blob_serialized(vec![2])),
+                    (entry_hash(&[3]), blob_serialized(vec![3])),
+                ])
+                .unwrap();
+            storage.delete(&entry_hash(&[2])).unwrap();
+            storage.flush().unwrap();
+        }
+
+        let storage = PackFileBackend::new(&dir, None).unwrap();
+        assert_eq!(
+            storage.get(&entry_hash(&[1])).unwrap(),
+            Some(blob_serialized(vec![1]))
+        );
+        assert!(storage.get(&entry_hash(&[2])).unwrap().is_none());
+        assert_eq!(
+            storage.get(&entry_hash(&[3])).unwrap(),
+            Some(blob_serialized(vec![3]))
+        );
+    }
+
+    #[test]
+    fn test_put_stored_entry_is_not_appended() {
+        let dir = test_dir("put_stored_entry_is_not_appended");
+        let storage = PackFileBackend::new(&dir, None).unwrap();
+        storage
+            .put(&entry_hash(&[1]), &blob_serialized(vec![1]))
+            .unwrap();
+        storage.flush().unwrap();
+        let pack_size = fs::metadata(pack_path(&dir, 0)).unwrap().len();
+
+        storage
+            .put(&entry_hash(&[1]), &blob_serialized(vec![1]))
+            .unwrap();
+        storage
+            .write_batch(vec![
+                (entry_hash(&[1]), blob_serialized(vec![1])),
+                (entry_hash(&[2]), blob_serialized(vec![2])),
+                (entry_hash(&[2]), blob_serialized(vec![2])),
+            ])
+            .unwrap();
+        storage.flush().unwrap();
+
+        // just the second entry is appended, once
+        assert_eq!(
+            fs::metadata(pack_path(&dir, 0)).unwrap().len(),
+            pack_size * 2
+        );
+    }
+
+    #[test]
+    fn test_recover_unindexed_records() {
+        let dir = test_dir("recover_unindexed_records");
+        {
+            let storage = PackFileBackend::new(&dir, None).unwrap();
+            storage
+                .write_batch(vec![
+                    (entry_hash(&[1]), blob_serialized(vec![1])),
+                    (entry_hash(&[2]), blob_serialized(vec![2])),
+                ])
+                .unwrap();
+            storage.flush().unwrap();
+        }
+
+        // crash after records were written to the pack, but only partially to the index
+        let index_file = OpenOptions::new()
+            .write(true)
+            .open(dir.join(INDEX_FILE_NAME))
+            .unwrap();
+        index_file.set_len(INDEX_RECORD_LEN as u64 + 10).unwrap();
+        // and partially written record at the end of the pack
+        let pack_path = pack_path(&dir, 0);
+        let mut pack_file = open_append(&pack_path).unwrap();
+        let pack_size = pack_file.metadata().unwrap().len();
+        pack_file.write_all(&entry_hash(&[3])).unwrap();
+
+        // read-only store recovers just in memory and does not touch the files
+        {
+            let index_size = fs::metadata(dir.join(INDEX_FILE_NAME)).unwrap().len();
+            let storage = PackFileBackend::open_read_only(&dir).unwrap();
+            assert_eq!(
+                storage.get(&entry_hash(&[2])).unwrap(),
+                Some(blob_serialized(vec![2]))
+            );
+            assert!(storage
+                .put(&entry_hash(&[4]), &blob_serialized(vec![4]))
+                .is_err());
+            storage.flush().unwrap();
+            drop(storage);
+            assert_eq!(
+                fs::metadata(dir.join(INDEX_FILE_NAME)).unwrap().len(),
+                index_size
+            );
+            assert_eq!(
+                fs::metadata(&pack_path).unwrap().len(),
+                pack_size + ENTRY_HASH_LEN as u64
+            );
+        }
+
+        let storage = PackFileBackend::new(&dir, None).unwrap();
+        assert_eq!(
+            storage.get(&entry_hash(&[1])).unwrap(),
+            Some(blob_serialized(vec![1]))
+        );
+        assert_eq!(
+            storage.get(&entry_hash(&[2])).unwrap(),
+            Some(blob_serialized(vec![2]))
+        );
+        assert!(!storage.contains(&entry_hash(&[3])).unwrap());
+        assert_eq!(fs::metadata(&pack_path).unwrap().len(), pack_size);
+
+        // new records are appended after the recovered ones
+        storage
+            .put(&entry_hash(&[4]), &blob_serialized(vec![4]))
+            .unwrap();
+        drop(storage);
+        let storage = PackFileBackend::new(&dir, None).unwrap();
+        assert_eq!(
+            storage.get(&entry_hash(&[4])).unwrap(),
+            Some(blob_serialized(vec![4]))
+        );
+        assert!(storage.contains(&entry_hash(&[2])).unwrap());
+    }
+
+    #[test]
+    fn test_gc_removes_oldest_pack() {
+        let dir = test_dir("gc_removes_oldest_pack");
+        let mut merkle = MerkleStorage::new(Box::new(PackFileBackend::new(&dir, Some(1)).unwrap()));
+        let kept_key = vec!["data".to_string(), "kept".to_string()];
+        let changed_key = vec!["data".to_string(), "changed".to_string()];
+
+        // cycle 0
+        merkle.set(1, &kept_key, vec![1]).unwrap();
+        merkle.set(1, &changed_key, vec![1]).unwrap();
+        let commit_0 = merkle
+            .commit(0, "Tezos".to_string(), "0".to_string())
+            .unwrap();
+        merkle.block_applied().unwrap();
+        merkle.start_new_cycle().unwrap();
+
+        // cycle 1
+        merkle.set(2, &changed_key, vec![2]).unwrap();
+        let commit_1 = merkle
+            .commit(1, "Tezos".to_string(), "1".to_string())
+            .unwrap();
+        merkle.block_applied().unwrap();
+        assert!(pack_path(&dir, 0).exists());
+        drop(merkle);
+
+        // references of the newest pack to the older one are found by scan after reopen
+        let mut merkle = MerkleStorage::new(Box::new(PackFileBackend::new(&dir, Some(1)).unwrap()));
+
+        // entries of cycle 0 are cached
+        assert_eq!(
+            merkle.get_history(&commit_0, &changed_key).unwrap(),
+            vec![1]
+        );
+
+        // only cycle 1 is retained, pack of cycle 0 is collected
+        merkle.start_new_cycle().unwrap();
+        assert!(!pack_path(&dir, 0).exists());
+        assert!(!commits_path(&dir, 0).exists());
+        assert!(!refs_path(&dir, 0).exists());
+        assert!(refs_path(&dir, 1).exists());
+
+        assert!(!merkle.contains_commit(&commit_0).unwrap());
+        assert!(merkle.contains_commit(&commit_1).unwrap());
+        // collected entries are not served from cache
+        assert!(merkle.get_history(&commit_0, &changed_key).is_err());
+        // unchanged value from cycle 0 is moved to the newer pack
+        assert_eq!(merkle.get_history(&commit_1, &kept_key).unwrap(), vec![1]);
+        assert_eq!(
+            merkle.get_history(&commit_1, &changed_key).unwrap(),
+            vec![2]
+        );
+        drop(merkle);
+
+        // moved entries are found after reopen
+        let mut merkle = MerkleStorage::new(Box::new(PackFileBackend::new(&dir, Some(1)).unwrap()));
+        assert_eq!(merkle.get_history(&commit_1, &kept_key).unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn test_gc_keeps_packs_without_commits() {
+        let dir = test_dir("gc_keeps_packs_without_commits");
+        let mut storage = PackFileBackend::new(&dir, Some(1)).unwrap();
+        storage
+            .put(&entry_hash(&[1]), &blob_serialized(vec![1]))
+            .unwrap();
+        for _ in 0..3 {
+            storage.new_cycle_started().unwrap();
+        }
+
+        assert!(pack_path(&dir, 0).exists());
+        assert!(storage.contains(&entry_hash(&[1])).unwrap());
+    }
+}
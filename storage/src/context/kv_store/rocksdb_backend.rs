@@ -13,7 +13,7 @@ use crate::context::{
     ContextKeyValueStoreSchema, ContextKeyValueStoreSchemaKeyType, ContextValue,
     MerkleKeyValueStoreSchemaValueType,
 };
-use crate::persistent::database::{default_table_options, DBError, RocksDbKeyValueSchema};
+use crate::persistent::database::{ColumnFamilyTuning, DBError, RocksDbKeyValueSchema};
 use crate::persistent::{
     BincodeEncoded, Flushable, KeyValueSchema, KeyValueStoreBackend, MultiInstanceable,
     MultiInstanceableSyncError, Persistable,
@@ -27,11 +27,22 @@ impl KeyValueSchema for RocksDBBackend {
 }
 
 impl RocksDbKeyValueSchema for RocksDBBackend {
-    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        let cf_opts = default_table_options(cache);
+    fn descriptor_with_tuning(
+        cache: &Cache,
+        tuning: &ColumnFamilyTuning,
+    ) -> ColumnFamilyDescriptor {
+        let cf_opts = tuning.table_options(cache);
         ColumnFamilyDescriptor::new(Self::name(), cf_opts)
     }
 
+    /// Context is read by random hashes, so bloom filter saves most of the disk reads
+    fn tuning() -> ColumnFamilyTuning {
+        ColumnFamilyTuning {
+            bloom_filter_bits: 10,
+            ..ColumnFamilyTuning::default()
+        }
+    }
+
     #[inline]
     fn name() -> &'static str {
         "merkle_storage"
@@ -115,7 +126,6 @@ impl MultiInstanceable for RocksDBBackend {
     }
 
     fn sync_with_primary(&self) -> Result<(), MultiInstanceableSyncError> {
-        // TODO: TE-150 - real support mutliprocess
         self.inner
             .try_catch_up_with_primary()
             .map_err(|e| MultiInstanceableSyncError::new(format!("{:?}", e)))
@@ -0,0 +1,348 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Bounded LRU cache of decoded merkle entries.
+//!
+//! Entries are content-addressed (keyed by [EntryHash]), so a cached entry never becomes outdated,
+//! it can only be removed from the kv store by garbage collection - the cache is shared with the collector
+//! (see [GarbageCollector::set_entry_cache]), which invalidates every entry it removes.
+//!
+//! The cache is shared by all readers (`&self` access), it is split into shards with their own lock and LRU,
+//! so concurrent readers do not contend on a single lock.
+//!
+//! Memory usage of an entry is estimated from its decoded (in-memory) form plus a fixed overhead
+//! of the cache record, every shard evicts the least recently used entries to keep its total below
+//! its part of the capacity.
+//!
+//! [GarbageCollector::set_entry_cache]: crate::context::gc::GarbageCollector::set_entry_cache
+
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde::Serialize;
+
+use crate::context::merkle::hash::EntryHash;
+use crate::context::merkle::{Entry, Node};
+
+/// Default capacity of the cache in bytes
+pub const DEFAULT_ENTRY_CACHE_CAPACITY: usize = 128 * 1024 * 1024;
+
+/// Count of independently locked parts of the cache
+const SHARDS_COUNT: usize = 16;
+
+/// Estimated overhead of one cached entry (record, hash in map and in lru index, allocation of `Arc<Entry>`)
+const ENTRY_OVERHEAD: usize = mem::size_of::<CachedEntry>()
+    + 2 * mem::size_of::<EntryHash>()
+    + mem::size_of::<u64>()
+    + ARC_OVERHEAD
+    + mem::size_of::<Entry>();
+
+/// Reference counters of `Arc` allocation
+const ARC_OVERHEAD: usize = 2 * mem::size_of::<usize>();
+
+/// Estimated memory of one tree item (key and value in the map, `Arc<Node>` allocation and its `Arc<EntryHash>`)
+const TREE_ITEM_OVERHEAD: usize = mem::size_of::<String>()
+    + mem::size_of::<Arc<Node>>()
+    + ARC_OVERHEAD
+    + mem::size_of::<Node>()
+    + ARC_OVERHEAD
+    + mem::size_of::<EntryHash>();
+
+/// Cache statistics reported with [MerkleStoragePerfReport](crate::context::merkle::merkle_storage_stats::MerkleStoragePerfReport)
+#[derive(Serialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Count of cached entries
+    pub entries: usize,
+    /// Estimated memory usage in bytes
+    pub memory_usage: usize,
+    /// Capacity in bytes
+    pub capacity: usize,
+}
+
+struct CachedEntry {
+    entry: Arc<Entry>,
+    size: usize,
+    last_used: u64,
+}
+
+struct EntryCacheShard {
+    capacity: usize,
+    memory_usage: usize,
+    entries: HashMap<EntryHash, CachedEntry>,
+    /// entry hashes ordered by last usage, the first one is evicted first
+    lru: BTreeMap<u64, EntryHash>,
+    clock: u64,
+}
+
+impl EntryCacheShard {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            memory_usage: 0,
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    fn get(&mut self, hash: &EntryHash) -> Option<Arc<Entry>> {
+        self.clock += 1;
+        let clock = self.clock;
+        let cached = self.entries.get_mut(hash)?;
+        self.lru.remove(&cached.last_used);
+        self.lru.insert(clock, *hash);
+        cached.last_used = clock;
+        Some(cached.entry.clone())
+    }
+
+    fn insert(&mut self, hash: EntryHash, entry: Arc<Entry>, size: usize) {
+        self.remove(&hash);
+        if size > self.capacity {
+            return;
+        }
+        while self.memory_usage + size > self.capacity {
+            match self.lru.keys().next().copied() {
+                Some(oldest) => {
+                    if let Some(evicted) = self.lru.remove(&oldest) {
+                        self.remove(&evicted);
+                    }
+                }
+                None => break,
+            }
+        }
+
+        self.clock += 1;
+        self.lru.insert(self.clock, hash);
+        self.entries.insert(
+            hash,
+            CachedEntry {
+                entry,
+                size,
+                last_used: self.clock,
+            },
+        );
+        self.memory_usage += size;
+    }
+
+    fn remove(&mut self, hash: &EntryHash) {
+        if let Some(cached) = self.entries.remove(hash) {
+            self.lru.remove(&cached.last_used);
+            self.memory_usage -= cached.size;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.lru.clear();
+        self.memory_usage = 0;
+    }
+}
+
+pub struct EntryCache {
+    capacity: usize,
+    shards: Vec<Mutex<EntryCacheShard>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl EntryCache {
+    /// Creates cache, which holds at most `capacity` bytes (estimated), zero capacity disables the cache
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            shards: (0..SHARDS_COUNT)
+                .map(|_| Mutex::new(EntryCacheShard::new(capacity / SHARDS_COUNT)))
+                .collect(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Poisoned shard is just bypassed, the cache is not needed for correct reads
+    fn shard(&self, hash: &EntryHash) -> Option<MutexGuard<EntryCacheShard>> {
+        self.shards[hash[0] as usize % SHARDS_COUNT].lock().ok()
+    }
+
+    /// Returns cached entry and marks it as recently used
+    pub fn get(&self, hash: &EntryHash) -> Option<Arc<Entry>> {
+        let cached = self.shard(hash).and_then(|mut shard| shard.get(hash));
+        match cached {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        cached
+    }
+
+    /// Caches decoded entry
+    pub fn insert(&self, hash: EntryHash, entry: Arc<Entry>) {
+        let size = entry_memory_size(&entry) + ENTRY_OVERHEAD;
+        if let Some(mut shard) = self.shard(&hash) {
+            shard.insert(hash, entry, size);
+        }
+    }
+
+    /// Removes entry, which was removed from the store
+    pub fn invalidate(&self, hash: &EntryHash) {
+        if let Some(mut shard) = self.shard(hash) {
+            shard.remove(hash);
+        }
+    }
+
+    /// Removes all cached entries, hit/miss counters are kept
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            if let Ok(mut shard) = shard.lock() {
+                shard.clear();
+            }
+        }
+    }
+
+    pub fn stats(&self) -> EntryCacheStats {
+        let mut stats = EntryCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            capacity: self.capacity,
+            ..EntryCacheStats::default()
+        };
+        for shard in &self.shards {
+            if let Ok(shard) = shard.lock() {
+                stats.entries += shard.entries.len();
+                stats.memory_usage += shard.memory_usage;
+            }
+        }
+        stats
+    }
+}
+
+/// Estimated heap memory owned by the decoded entry, nodes shared with other trees are counted for every tree
+fn entry_memory_size(entry: &Entry) -> usize {
+    match entry {
+        Entry::Blob(blob) => blob.capacity(),
+        Entry::Tree(tree) => tree
+            .iter()
+            .map(|(name, _)| name.capacity() + TREE_ITEM_OVERHEAD)
+            .sum(),
+        Entry::Commit(commit) => commit.author.capacity() + commit.message.capacity(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::context::merkle::{NodeKind, Tree};
+
+    use super::*;
+
+    /// Entries with hashes starting with the same byte modulo shards count share the shard
+    fn blob(value: u8) -> (EntryHash, Arc<Entry>) {
+        ([value; 32], Arc::new(Entry::Blob(vec![value])))
+    }
+
+    fn blob_size() -> usize {
+        entry_memory_size(&Entry::Blob(vec![0])) + ENTRY_OVERHEAD
+    }
+
+    #[test]
+    fn test_hits_and_misses() {
+        let cache = EntryCache::new(1024 * 1024);
+        let (hash, entry) = blob(1);
+
+        assert_eq!(cache.get(&hash), None);
+        cache.insert(hash, entry.clone());
+        assert_eq!(cache.get(&hash), Some(entry));
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.entries, 1);
+        assert_eq!(stats.memory_usage, blob_size());
+
+        cache.clear();
+        assert_eq!(cache.get(&hash), None);
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.entries, 0);
+        assert_eq!(stats.memory_usage, 0);
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        // room for exactly two entries in every shard
+        let cache = EntryCache::new(SHARDS_COUNT * 2 * blob_size());
+        let (hash1, entry1) = blob(1);
+        let (hash2, entry2) = blob(1 + SHARDS_COUNT as u8);
+        let (hash3, entry3) = blob(1 + 2 * SHARDS_COUNT as u8);
+        let (hash4, entry4) = blob(2);
+
+        cache.insert(hash1, entry1.clone());
+        cache.insert(hash2, entry2);
+        cache.insert(hash4, entry4.clone());
+        // touch first entry, so the second one is the least recently used
+        assert!(cache.get(&hash1).is_some());
+        cache.insert(hash3, entry3);
+
+        assert_eq!(cache.get(&hash1), Some(entry1));
+        assert_eq!(cache.get(&hash2), None);
+        assert!(cache.get(&hash3).is_some());
+        // other shard is not affected
+        assert_eq!(cache.get(&hash4), Some(entry4));
+        assert_eq!(cache.stats().entries, 3);
+        assert!(cache.stats().memory_usage <= cache.stats().capacity);
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let cache = EntryCache::new(1024 * 1024);
+        let (hash1, entry1) = blob(1);
+        let (hash2, entry2) = blob(2);
+
+        cache.insert(hash1, entry1);
+        cache.insert(hash2, entry2.clone());
+        cache.invalidate(&hash1);
+
+        assert_eq!(cache.get(&hash1), None);
+        assert_eq!(cache.get(&hash2), Some(entry2));
+        assert_eq!(cache.stats().entries, 1);
+        assert_eq!(cache.stats().memory_usage, blob_size());
+    }
+
+    #[test]
+    fn test_accounts_decoded_size() {
+        let cache = EntryCache::new(1024 * 1024);
+
+        cache.insert([1; 32], Arc::new(Entry::Blob(vec![0; 1000])));
+        assert_eq!(cache.stats().memory_usage, 1000 + ENTRY_OVERHEAD);
+        cache.clear();
+
+        // every tree item owns its name, node and hash, which are not part of the serialized size only
+        let mut tree = Tree::new();
+        for name in &["a", "b"] {
+            tree.insert(
+                name.to_string(),
+                Arc::new(Node {
+                    node_kind: NodeKind::Leaf,
+                    entry_hash: Arc::new([0; 32]),
+                }),
+            );
+        }
+        cache.insert([2; 32], Arc::new(Entry::Tree(tree)));
+        assert_eq!(
+            cache.stats().memory_usage,
+            2 * (1 + TREE_ITEM_OVERHEAD) + ENTRY_OVERHEAD
+        );
+    }
+
+    #[test]
+    fn test_zero_capacity_disables_cache() {
+        let cache = EntryCache::new(0);
+        let (hash, entry) = blob(1);
+
+        cache.insert(hash, entry);
+        assert_eq!(cache.get(&hash), None);
+        assert_eq!(cache.stats().entries, 0);
+    }
+}
@@ -5,7 +5,7 @@
 //!
 //! A document describing the algorithm can be found [here](https://github.com/tarides/tezos-context-hash).
 
-use std::{array::TryFromSliceError, convert::TryInto, io};
+use std::{array::TryFromSliceError, convert::TryInto, io, sync::Arc};
 
 use blake2::digest::{InvalidOutputSize, Update, VariableOutput};
 use blake2::VarBlake2b;
@@ -13,7 +13,9 @@ use failure::Fail;
 
 use ocaml::ocaml_hash_string;
 
-use crate::context::merkle::{Commit, Entry, Node, NodeKind, Tree};
+use crate::context::merkle::{
+    Commit, ContextProof, Entry, InodeProof, Node, NodeKind, Tree, TreeProof,
+};
 use crate::context::ContextValue;
 
 mod ocaml;
@@ -78,6 +80,18 @@ fn index(depth: u32, name: &str) -> u32 {
     ocaml_hash_string(depth, name.as_bytes()) % 32
 }
 
+fn entries_at_index<'a>(
+    depth: u32,
+    i: u32,
+    entries: &[(&'a String, &'a Node)],
+) -> Vec<(&'a String, &'a Node)> {
+    entries
+        .iter()
+        .filter(|(name, _)| index(depth, name) == i)
+        .cloned()
+        .collect()
+}
+
 // IMPORTANT: entries must be sorted in lexicographic order of the name
 // Because we use `OrdMap`, this holds true when we iterate the items, but this is
 // something to keep in mind if the representation of `Tree` changes.
@@ -98,12 +112,7 @@ fn partition_entries(depth: u32, entries: &[(&String, &Node)]) -> Result<Inode,
 
         // pointers = {p(i) | i <- [0..31], t(i) != Empty}
         for i in 0..=31 {
-            let entries_at_depth_and_index_i: Vec<(&String, &Node)> = entries
-                .iter()
-                .filter(|(name, _)| index(depth, name) == i)
-                .cloned()
-                .collect();
-            let ti = partition_entries(depth + 1, &entries_at_depth_and_index_i)?;
+            let ti = partition_entries(depth + 1, &entries_at_index(depth, i, entries))?;
 
             match ti {
                 Inode::Empty => (),
@@ -232,6 +241,110 @@ pub(crate) fn hash_tree(tree: &Tree) -> Result<EntryHash, HashingError> {
     }
 }
 
+/// Builds proof of the tree for node under `name`, see [TreeProof]
+pub(crate) fn tree_proof(tree: &Tree, name: &str) -> Result<TreeProof, HashingError> {
+    let siblings = |entries: Vec<(&String, &Node)>| -> Vec<(String, Node)> {
+        entries
+            .into_iter()
+            .filter(|(sibling_name, _)| sibling_name.as_str() != name)
+            .map(|(sibling_name, node)| (sibling_name.clone(), node.clone()))
+            .collect()
+    };
+    let entries: Vec<(&String, &Node)> = tree.iter().map(|(s, n)| (s, n.as_ref())).collect();
+    if tree.len() <= 256 {
+        return Ok(TreeProof::Short {
+            siblings: siblings(entries),
+        });
+    }
+
+    // follow the inodes containing `name` down to the inode value
+    let mut inodes = Vec::new();
+    let mut entries = entries;
+    let mut depth = 0;
+    while entries.len() > 32 {
+        let path_index = index(depth, name);
+        let mut pointers = Vec::with_capacity(31);
+        for i in (0..=31).filter(|i| *i != path_index) {
+            match partition_entries(depth + 1, &entries_at_index(depth, i, &entries))? {
+                Inode::Empty => (),
+                non_empty => pointers.push((i as u8, hash_long_inode(&non_empty)?)),
+            }
+        }
+        inodes.push(InodeProof {
+            children: entries.len(),
+            pointers,
+        });
+        entries = entries_at_index(depth, path_index, &entries);
+        depth += 1;
+    }
+
+    Ok(TreeProof::Long {
+        inodes,
+        siblings: siblings(entries),
+    })
+}
+
+/// Recomputes hash of the tree from its proof and the `node` under `name`,
+/// returns `None` if the proof has not a shape of any tree containing `name`.
+fn hash_tree_proof(
+    proof: &TreeProof,
+    name: &str,
+    node: Node,
+) -> Result<Option<EntryHash>, HashingError> {
+    match proof {
+        TreeProof::Short { siblings } => {
+            if siblings.len() >= 256 {
+                return Ok(None);
+            }
+            let mut tree = Tree::new();
+            for (sibling_name, sibling) in siblings {
+                if sibling_name == name {
+                    return Ok(None);
+                }
+                tree.insert(sibling_name.clone(), Arc::new(sibling.clone()));
+            }
+            tree.insert(name.to_string(), Arc::new(node));
+            Ok(Some(hash_short_inode(&tree)?))
+        }
+        TreeProof::Long { inodes, siblings } => {
+            let root_children = inodes.first().map(|inode| inode.children).unwrap_or(0);
+            if root_children <= 256 || siblings.len() >= 32 {
+                return Ok(None);
+            }
+            let mut entries = siblings.clone();
+            match entries.binary_search_by(|(sibling_name, _)| sibling_name.as_str().cmp(name)) {
+                Ok(_) => return Ok(None),
+                Err(position) => entries.insert(position, (name.to_string(), node)),
+            }
+            if entries.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+                return Ok(None);
+            }
+            let mut hash = hash_long_inode(&Inode::Value(entries))?;
+
+            for (depth, inode) in inodes.iter().enumerate().rev() {
+                let depth = depth as u32;
+                let path_index = index(depth, name) as u8;
+                let mut pointers = inode.pointers.clone();
+                match pointers.binary_search_by_key(&path_index, |(i, _)| *i) {
+                    Ok(_) => return Ok(None),
+                    Err(position) => pointers.insert(position, (path_index, hash)),
+                }
+                if pointers.windows(2).any(|pair| pair[0].0 >= pair[1].0)
+                    || pointers.iter().any(|(i, _)| *i > 31)
+                {
+                    return Ok(None);
+                }
+                hash = hash_long_inode(&Inode::Tree {
+                    depth,
+                    children: inode.children,
+                    pointers,
+                })?;
+            }
+            Ok(Some(hash))
+        }
+    }
+}
+
 // Calculates hash of BLOB
 // uses BLAKE2 binary 256 length hash function
 // hash is calculated as <length of data (8 bytes)><data>
@@ -283,6 +396,43 @@ pub(crate) fn hash_entry(entry: &Entry) -> Result<EntryHash, HashingError> {
     }
 }
 
+/// Verifies, that `proof` proves its value under `key` in the context of commit `commit_hash`.
+///
+/// Hashes are recomputed from the value up through all trees on the key path to the commit,
+/// so the proof is valid only if the resulting commit hash equals `commit_hash`.
+pub fn verify_proof(
+    proof: &ContextProof,
+    key: &[String],
+    commit_hash: &EntryHash,
+) -> Result<bool, HashingError> {
+    if key.is_empty() || key.len() != proof.trees.len() {
+        return Ok(false);
+    }
+
+    let mut node = Node {
+        node_kind: NodeKind::Leaf,
+        entry_hash: Arc::new(hash_entry(&Entry::Blob(proof.value.clone()))?),
+    };
+    for (name, tree_proof) in key.iter().zip(proof.trees.iter()).rev() {
+        node = match hash_tree_proof(tree_proof, name, node)? {
+            Some(tree_hash) => Node {
+                node_kind: NodeKind::NonLeaf,
+                entry_hash: Arc::new(tree_hash),
+            },
+            None => return Ok(false),
+        };
+    }
+
+    let commit = Commit {
+        parent_commit_hash: proof.parent_commit_hash,
+        root_hash: *node.entry_hash,
+        time: proof.time,
+        author: proof.author.clone(),
+        message: proof.message.clone(),
+    };
+    Ok(hash_entry(&Entry::Commit(commit))? == *commit_hash)
+}
+
 #[cfg(test)]
 #[allow(unused_must_use)]
 mod tests {
@@ -45,7 +45,7 @@
 //! ``
 //!
 //! Reference: https://git-scm.com/book/en/v2/Git-Internals-Git-Objects
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::{array::TryFromSliceError, sync::Arc};
 
 use failure::{Error, Fail};
@@ -55,17 +55,21 @@ use serde::Serialize;
 use crypto::hash::{FromBytesError, HashType};
 
 use crate::context::gc::GarbageCollectionError;
+use crate::context::merkle::entry_cache::{EntryCache, DEFAULT_ENTRY_CACHE_CAPACITY};
 use crate::context::merkle::hash::EntryHash;
-use crate::context::merkle::hash::{hash_blob, hash_commit, hash_entry, hash_tree, HashingError};
+use crate::context::merkle::hash::{
+    hash_blob, hash_commit, hash_entry, hash_tree, tree_proof, HashingError,
+};
 use crate::context::merkle::merkle_storage_stats::{
     MerkleStorageAction, MerkleStoragePerfReport, MerkleStorageStatistics, StatUpdater,
 };
-use crate::context::merkle::{Commit, Entry, Node, NodeKind, Tree};
+use crate::context::merkle::{Commit, ContextProof, Entry, Node, NodeKind, Tree};
 use crate::context::{
-    ContextKey, ContextKeyValueStore, ContextValue, StringTreeEntry, StringTreeMap, TreeId,
+    ContextDiff, ContextKey, ContextKeyValueStore, ContextValue, StringTreeEntry, StringTreeMap,
+    TreeId,
 };
 use crate::persistent;
-use crate::persistent::Flushable;
+use crate::persistent::{Flushable, MultiInstanceable, MultiInstanceableSyncError};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct SetAction {
@@ -104,6 +108,8 @@ pub struct MerkleStorage {
     last_commit_hash: Option<EntryHash>,
     /// storage latency statistics
     stats: MerkleStorageStatistics,
+    /// decoded entries read from db, shared by all readers and with the garbage collector
+    entry_cache: Arc<EntryCache>,
 }
 
 #[derive(Debug, Fail)]
@@ -215,8 +221,77 @@ pub enum CheckEntryHashError {
     },
 }
 
+/// Collects changes of the context diff (in key order) after the cursor, until the limit is reached
+struct DiffCollector<'a> {
+    cursor: Option<&'a ContextKey>,
+    limit: Option<usize>,
+    count: usize,
+    last_key: Option<ContextKey>,
+    diff: ContextDiff,
+}
+
+impl<'a> DiffCollector<'a> {
+    fn new(cursor: Option<&'a ContextKey>, limit: Option<usize>) -> Self {
+        Self {
+            cursor,
+            limit,
+            count: 0,
+            last_key: None,
+            diff: ContextDiff::default(),
+        }
+    }
+
+    /// Returns true, when the limit was reached and there is at least one more change
+    fn is_full(&self) -> bool {
+        self.diff.next_cursor.is_some()
+    }
+
+    /// Returns true, if there can be a key after the cursor under the `path`
+    fn has_keys_after_cursor(&self, path: &[String]) -> bool {
+        match self.cursor {
+            Some(cursor) => path > cursor.as_slice() || cursor.starts_with(path),
+            None => true,
+        }
+    }
+
+    /// Returns true, if change of the `key` should be added to the diff
+    fn accept(&mut self, key: &[String]) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        if let Some(cursor) = self.cursor {
+            if key <= cursor.as_slice() {
+                return false;
+            }
+        }
+        if self.limit.map_or(false, |limit| self.count >= limit) {
+            // next page starts after the last returned key
+            let cursor = self.cursor;
+            self.diff.next_cursor = Some(
+                self.last_key
+                    .take()
+                    .unwrap_or_else(|| cursor.cloned().unwrap_or_default()),
+            );
+            return false;
+        }
+        self.count += 1;
+        self.last_key = Some(key.to_vec());
+        true
+    }
+}
+
 impl MerkleStorage {
     pub fn new(db: Box<ContextKeyValueStore>) -> Self {
+        Self::with_entry_cache(db, Arc::new(EntryCache::new(DEFAULT_ENTRY_CACHE_CAPACITY)))
+    }
+
+    /// Creates storage, which reads entries through `entry_cache`,
+    /// the cache is shared with the garbage collector of `db`, which invalidates removed entries
+    pub fn with_entry_cache(
+        mut db: Box<ContextKeyValueStore>,
+        entry_cache: Arc<EntryCache>,
+    ) -> Self {
+        db.set_entry_cache(entry_cache.clone());
         let tree = Tree::new();
         let tree_hash = hash_tree(&tree).unwrap();
         let tree_id = 0;
@@ -233,6 +308,17 @@ impl MerkleStorage {
             current_stage_tree: (tree, tree_id),
             last_commit_hash: None,
             stats: MerkleStorageStatistics::default(),
+            entry_cache,
+        }
+    }
+
+    /// Catches up secondary instance of the kv store with the primary one (see [MultiInstanceable]),
+    /// does nothing for kv stores, which cannot be shared (e.g. in-memory)
+    pub fn sync_with_primary(&self) -> Result<(), MultiInstanceableSyncError> {
+        if self.db.supports_multiple_opened_instances() {
+            self.db.sync_with_primary()
+        } else {
+            Ok(())
         }
     }
 
@@ -514,6 +600,232 @@ impl MerkleStorage {
         }
     }
 
+    /// Compares contexts of two commits, only keys under `prefix` are compared (empty prefix compares everything).
+    /// Subtrees with the same hash in both contexts are skipped without reading.
+    /// Changes are collected in key order, just keys after `cursor` are compared and at most `limit` changes
+    /// are returned, [ContextDiff::next_cursor] is set, if there are more changes.
+    pub fn diff(
+        &self,
+        from_context_hash: &EntryHash,
+        to_context_hash: &EntryHash,
+        prefix: &ContextKey,
+        cursor: Option<&ContextKey>,
+        limit: Option<usize>,
+    ) -> Result<ContextDiff, MerkleError> {
+        let from_tree = self.get_tree(&self.get_commit(from_context_hash)?.root_hash)?;
+        let to_tree = self.get_tree(&self.get_commit(to_context_hash)?.root_hash)?;
+
+        let mut collector = DiffCollector::new(cursor, limit);
+        self.diff_trees(
+            &mut Vec::new(),
+            &from_tree,
+            &to_tree,
+            prefix,
+            &mut collector,
+        )?;
+        Ok(collector.diff)
+    }
+
+    /// Compares two trees from the staging area (identified by tree ids of context actions),
+    /// used to find out which action caused the context to diverge.
+    pub fn diff_staged_trees(
+        &mut self,
+        from_tree_id: TreeId,
+        to_tree_id: TreeId,
+    ) -> Result<ContextDiff, MerkleError> {
+        let stat_updater = StatUpdater::new(MerkleStorageAction::Diff, None);
+        let from_tree = self.get_staged_tree(from_tree_id)?;
+        let to_tree = self.get_staged_tree(to_tree_id)?;
+
+        let mut collector = DiffCollector::new(None, None);
+        self.diff_trees(&mut Vec::new(), &from_tree, &to_tree, &[], &mut collector)?;
+        stat_updater.update_execution_stats(&mut self.stats);
+        Ok(collector.diff)
+    }
+
+    fn get_staged_tree(&self, tree_id: TreeId) -> Result<Tree, MerkleError> {
+        self.trees
+            .get(&tree_id)
+            .cloned()
+            .ok_or(MerkleError::TreeNotFoundInStaging { tree_id })
+    }
+
+    /// Compares children of two trees under `path`, `prefix` is the rest of the requested prefix (relative to `path`)
+    fn diff_trees(
+        &self,
+        path: &mut ContextKey,
+        from_tree: &Tree,
+        to_tree: &Tree,
+        prefix: &[String],
+        collector: &mut DiffCollector,
+    ) -> Result<(), MerkleError> {
+        let names: BTreeSet<&String> = from_tree
+            .keys()
+            .chain(to_tree.keys())
+            .filter(|name| prefix.first().map_or(true, |first| first == *name))
+            .collect();
+        let prefix = prefix.get(1..).unwrap_or(&[]);
+
+        for name in names {
+            if collector.is_full() {
+                break;
+            }
+            let from_node = from_tree.get(name);
+            let to_node = to_tree.get(name);
+            if let (Some(from_node), Some(to_node)) = (from_node, to_node) {
+                if from_node.entry_hash == to_node.entry_hash {
+                    continue;
+                }
+            }
+
+            path.push(name.clone());
+            if !collector.has_keys_after_cursor(path) {
+                path.pop();
+                continue;
+            }
+            let from_entry = from_node
+                .map(|node| self.get_entry(&node.entry_hash))
+                .transpose()?;
+            let to_entry = to_node
+                .map(|node| self.get_entry(&node.entry_hash))
+                .transpose()?;
+
+            match (from_entry, to_entry) {
+                (Some(Entry::Tree(from_tree)), Some(Entry::Tree(to_tree))) => {
+                    self.diff_trees(path, &from_tree, &to_tree, prefix, collector)?
+                }
+                (Some(Entry::Blob(from_value)), Some(Entry::Blob(to_value))) => {
+                    if prefix.is_empty() && collector.accept(path) {
+                        collector
+                            .diff
+                            .modified
+                            .push((path.clone(), from_value, to_value));
+                    }
+                }
+                (Some(from_entry @ Entry::Tree(_)), Some(to_entry)) => {
+                    // changed from tree to blob, blob key is before keys of the tree
+                    self.collect_diff_values(path, &to_entry, prefix, true, collector)?;
+                    self.collect_diff_values(path, &from_entry, prefix, false, collector)?;
+                }
+                (from_entry, to_entry) => {
+                    // key was added/removed or changed from blob to tree
+                    if let Some(from_entry) = from_entry {
+                        self.collect_diff_values(path, &from_entry, prefix, false, collector)?;
+                    }
+                    if let Some(to_entry) = to_entry {
+                        self.collect_diff_values(path, &to_entry, prefix, true, collector)?;
+                    }
+                }
+            }
+            path.pop();
+        }
+        Ok(())
+    }
+
+    /// Collects all key-values of the entry (under `path`) matching `prefix` as added or removed
+    fn collect_diff_values(
+        &self,
+        path: &mut ContextKey,
+        entry: &Entry,
+        prefix: &[String],
+        added: bool,
+        collector: &mut DiffCollector,
+    ) -> Result<(), MerkleError> {
+        match entry {
+            Entry::Blob(value) => {
+                if prefix.is_empty() && collector.accept(path) {
+                    let values = if added {
+                        &mut collector.diff.added
+                    } else {
+                        &mut collector.diff.removed
+                    };
+                    values.push((path.clone(), value.clone()));
+                }
+                Ok(())
+            }
+            Entry::Tree(tree) => {
+                for (name, child_node) in tree.iter() {
+                    if collector.is_full() {
+                        break;
+                    }
+                    if prefix.first().map_or(false, |first| first != name) {
+                        continue;
+                    }
+                    path.push(name.clone());
+                    if collector.has_keys_after_cursor(path) {
+                        let child_entry = self.get_entry(&child_node.entry_hash)?;
+                        self.collect_diff_values(
+                            path,
+                            &child_entry,
+                            prefix.get(1..).unwrap_or(&[]),
+                            added,
+                            collector,
+                        )?;
+                    }
+                    path.pop();
+                }
+                Ok(())
+            }
+            Entry::Commit(_) => Err(MerkleError::FoundUnexpectedStructure {
+                sought: "Tree/Blob".to_string(),
+                found: "commit".to_string(),
+            }),
+        }
+    }
+
+    /// Builds proof of the value under `key` in historical context identified by commit hash,
+    /// see [crate::context::merkle::hash::verify_proof].
+    pub fn get_proof(
+        &mut self,
+        commit_hash: &EntryHash,
+        key: &ContextKey,
+    ) -> Result<ContextProof, MerkleError> {
+        let stat_updater = StatUpdater::new(MerkleStorageAction::GetProof, Some(key));
+        let commit = self.get_commit(commit_hash)?;
+        let (file, path) = key.split_last().ok_or(MerkleError::KeyEmpty)?;
+
+        let mut trees = Vec::with_capacity(key.len());
+        let mut tree = self.get_tree(&commit.root_hash)?;
+        for name in path {
+            let node = tree.get(name).ok_or_else(|| MerkleError::ValueNotFound {
+                key: self.key_to_string(key),
+            })?;
+            let subtree = match self.get_entry(&node.entry_hash)? {
+                Entry::Tree(subtree) => subtree,
+                _ => {
+                    return Err(MerkleError::ValueNotFound {
+                        key: self.key_to_string(key),
+                    })
+                }
+            };
+            trees.push(tree_proof(&tree, name)?);
+            tree = subtree;
+        }
+
+        let node = tree.get(file).ok_or_else(|| MerkleError::ValueNotFound {
+            key: self.key_to_string(key),
+        })?;
+        let value = match self.get_entry(&node.entry_hash)? {
+            Entry::Blob(value) => value,
+            _ => {
+                return Err(MerkleError::ValueIsNotABlob {
+                    key: self.key_to_string(key),
+                })
+            }
+        };
+        trees.push(tree_proof(&tree, file)?);
+
+        stat_updater.update_execution_stats(&mut self.stats);
+        Ok(ContextProof {
+            value,
+            trees,
+            parent_commit_hash: commit.parent_commit_hash,
+            time: commit.time,
+            author: commit.author,
+            message: commit.message,
+        })
+    }
+
     /// Flush the staging area and and move to work on a certain commit from history.
     pub fn checkout(&mut self, context_hash: &EntryHash) -> Result<(), MerkleError> {
         let stat_updater = StatUpdater::new(MerkleStorageAction::Checkout, None);
@@ -555,6 +867,7 @@ impl MerkleStorage {
         let mut batch: Vec<(EntryHash, ContextValue)> = Vec::new();
         self.get_entries_recursively(&entry, &mut batch)?;
         // write all entries at once (depends on backend)
+        self.db.commit_started()?;
         self.db.write_batch(batch)?;
 
         self.last_commit_hash = Some(hash_commit(&new_commit)?);
@@ -847,16 +1160,23 @@ impl MerkleStorage {
         }
     }
 
-    /// Get entry from staging area or look up in DB if not found
+    /// Get entry from staging area or look up in cache and DB if not found
     fn get_entry(&self, hash: &EntryHash) -> Result<Entry, MerkleError> {
         match self.staged.get(hash) {
             None => {
+                if let Some(entry) = self.entry_cache.get(hash) {
+                    return Ok(Entry::clone(&entry));
+                }
                 let entry_bytes = self.db.get(hash)?;
                 match entry_bytes {
                     None => Err(MerkleError::EntryNotFound {
                         hash: HashType::ContextHash.hash_to_b58check(hash)?,
                     }),
-                    Some(entry_bytes) => Ok(bincode::deserialize(&entry_bytes)?),
+                    Some(entry_bytes) => {
+                        let entry: Arc<Entry> = Arc::new(bincode::deserialize(&entry_bytes)?);
+                        self.entry_cache.insert(*hash, entry.clone());
+                        Ok(Entry::clone(&entry))
+                    }
                 }
             }
             Some(entry) => Ok(entry.clone()),
@@ -885,6 +1205,12 @@ impl MerkleStorage {
         self.last_commit_hash
     }
 
+    /// Sets parent of the next commit without checking it out, used when the parent commit
+    /// is not available in the store (e.g. first commit imported from snapshot)
+    pub fn set_last_commit_hash(&mut self, last_commit_hash: Option<EntryHash>) {
+        self.last_commit_hash = last_commit_hash;
+    }
+
     pub fn get_staged_entries(&self) -> Result<std::string::String, MerkleError> {
         let mut result = String::new();
         for (hash, entry) in &self.staged {
@@ -931,6 +1257,8 @@ impl MerkleStorage {
         Ok(MerkleStoragePerfReport {
             perf_stats: self.stats.perf_stats.clone(),
             kv_store_stats: self.db.total_get_mem_usage()?,
+            entry_cache_stats: self.entry_cache.stats(),
+            gc_stats: self.db.gc_stats(),
         })
     }
 
@@ -942,6 +1270,64 @@ impl MerkleStorage {
         self.stats.block_latencies.get(offset_from_last_applied)
     }
 
+    /// Checks, if commit `context_hash` is stored
+    pub fn contains_commit(&self, context_hash: &EntryHash) -> Result<bool, MerkleError> {
+        match self.get_commit(context_hash) {
+            Ok(_) => Ok(true),
+            Err(MerkleError::EntryNotFound { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Checks, if entry `hash` is stored (staged entries are not checked)
+    pub fn contains_entry(&self, hash: &EntryHash) -> Result<bool, MerkleError> {
+        Ok(self.db.contains(hash)?)
+    }
+
+    /// Visits every entry reachable from commit `context_hash` - the commit itself, its root tree
+    /// and all subtrees and blobs. Every entry is visited exactly once, parent commits are not followed.
+    pub fn walk_commit_entries<F, E>(
+        &self,
+        context_hash: &EntryHash,
+        mut visitor: F,
+    ) -> Result<(), E>
+    where
+        F: FnMut(&EntryHash, &Entry) -> Result<(), E>,
+        E: From<MerkleError>,
+    {
+        let commit = self.get_commit(context_hash)?;
+        let root_hash = commit.root_hash;
+        visitor(context_hash, &Entry::Commit(commit))?;
+
+        let mut visited: HashSet<EntryHash> = HashSet::new();
+        let mut pending = vec![root_hash];
+        while let Some(hash) = pending.pop() {
+            if !visited.insert(hash) {
+                continue;
+            }
+            let entry = self.get_entry(&hash)?;
+            if let Entry::Tree(tree) = &entry {
+                pending.extend(
+                    tree.values()
+                        .map(|node| *node.entry_hash)
+                        .filter(|child_hash| !visited.contains(child_hash)),
+                );
+            }
+            visitor(&hash, &entry)?;
+        }
+        Ok(())
+    }
+
+    /// Writes already serialized entries (e.g. from snapshot) directly to the key-value store,
+    /// staging area is not touched.
+    pub fn import_entries(
+        &mut self,
+        batch: Vec<(EntryHash, ContextValue)>,
+    ) -> Result<(), MerkleError> {
+        self.db.write_batch(batch)?;
+        Ok(())
+    }
+
     fn flush_db(&self) -> Result<(), Error> {
         self.db.flush()
     }
@@ -963,6 +1349,8 @@ mod tests {
 
     use crate::context::kv_store::test_support::TestContextKvStoreFactoryInstance;
     use crate::context::kv_store::SupportedContextKeyValueStore;
+    use crate::context::merkle::hash::verify_proof;
+    use crate::context::merkle::TreeProof;
     use crate::context::ContextValue;
 
     use super::*;
@@ -1324,6 +1712,232 @@ mod tests {
         assert_eq!(storage.get(&key_abx).unwrap(), vec![4u8]);
     }
 
+    fn test_diff(kv_store_factory: &TestContextKvStoreFactoryInstance) {
+        let key_abc: &ContextKey = &vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let key_abx: &ContextKey = &vec!["a".to_string(), "b".to_string(), "x".to_string()];
+        let key_nm: &ContextKey = &vec!["n".to_string(), "m".to_string()];
+        let key_z: &ContextKey = &vec!["z".to_string()];
+
+        let mut storage = MerkleStorage::new(kv_store_factory.create("test_diff").unwrap());
+
+        storage.set(1, key_abc, vec![1u8]).unwrap();
+        storage.set(2, key_abx, vec![2u8]).unwrap();
+        storage.set(3, key_z, vec![3u8]).unwrap();
+        let commit1 = storage.commit(0, "".to_string(), "".to_string()).unwrap();
+
+        storage.set(4, key_abc, vec![4u8]).unwrap();
+        storage.delete(5, key_abx).unwrap();
+        storage.set(6, key_nm, vec![5u8]).unwrap();
+        let commit2 = storage.commit(0, "".to_string(), "".to_string()).unwrap();
+
+        let diff = storage
+            .diff(&commit1, &commit2, &vec![], None, None)
+            .unwrap();
+        assert_eq!(diff.added, vec![(key_nm.clone(), vec![5u8])]);
+        assert_eq!(diff.removed, vec![(key_abx.clone(), vec![2u8])]);
+        assert_eq!(diff.modified, vec![(key_abc.clone(), vec![1u8], vec![4u8])]);
+
+        // reversed diff
+        let diff = storage
+            .diff(&commit2, &commit1, &vec![], None, None)
+            .unwrap();
+        assert_eq!(diff.added, vec![(key_abx.clone(), vec![2u8])]);
+        assert_eq!(diff.removed, vec![(key_nm.clone(), vec![5u8])]);
+        assert_eq!(diff.modified, vec![(key_abc.clone(), vec![4u8], vec![1u8])]);
+
+        // filtered by prefix
+        let diff = storage
+            .diff(&commit1, &commit2, &vec!["a".to_string()], None, None)
+            .unwrap();
+        assert!(diff.added.is_empty());
+        assert_eq!(diff.removed, vec![(key_abx.clone(), vec![2u8])]);
+        assert_eq!(diff.modified.len(), 1);
+
+        let diff = storage
+            .diff(&commit1, &commit2, key_abc, None, None)
+            .unwrap();
+        assert!(diff.added.is_empty());
+        assert!(diff.removed.is_empty());
+        assert_eq!(diff.modified, vec![(key_abc.clone(), vec![1u8], vec![4u8])]);
+
+        // no difference
+        assert_eq!(
+            storage
+                .diff(&commit2, &commit2, &vec![], None, None)
+                .unwrap(),
+            ContextDiff::default()
+        );
+
+        // paged by cursor - changes in key order: a/b/c (modified), a/b/x (removed), n/m (added)
+        let diff = storage
+            .diff(&commit1, &commit2, &vec![], None, Some(2))
+            .unwrap();
+        assert_eq!(diff.modified.len(), 1);
+        assert_eq!(diff.removed, vec![(key_abx.clone(), vec![2u8])]);
+        assert!(diff.added.is_empty());
+        assert_eq!(diff.next_cursor, Some(key_abx.clone()));
+
+        let diff = storage
+            .diff(
+                &commit1,
+                &commit2,
+                &vec![],
+                diff.next_cursor.as_ref(),
+                Some(2),
+            )
+            .unwrap();
+        assert!(diff.modified.is_empty());
+        assert!(diff.removed.is_empty());
+        assert_eq!(diff.added, vec![(key_nm.clone(), vec![5u8])]);
+        assert_eq!(diff.next_cursor, None);
+
+        // exactly limit changes
+        let diff = storage
+            .diff(&commit1, &commit2, &vec![], Some(key_abc), Some(2))
+            .unwrap();
+        assert_eq!(diff.removed.len() + diff.added.len(), 2);
+        assert_eq!(diff.next_cursor, None);
+    }
+
+    fn test_diff_staged_trees(kv_store_factory: &TestContextKvStoreFactoryInstance) {
+        let key_abc: &ContextKey = &vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let key_abx: &ContextKey = &vec!["a".to_string(), "b".to_string(), "x".to_string()];
+
+        let mut storage =
+            MerkleStorage::new(kv_store_factory.create("test_diff_staged_trees").unwrap());
+
+        storage.set(1, key_abc, vec![1u8]).unwrap();
+        storage.set(2, key_abx, vec![2u8]).unwrap();
+        storage.set(3, key_abc, vec![3u8]).unwrap();
+
+        let diff = storage.diff_staged_trees(1, 2).unwrap();
+        assert_eq!(diff.added, vec![(key_abx.clone(), vec![2u8])]);
+        assert!(diff.removed.is_empty());
+        assert!(diff.modified.is_empty());
+
+        let diff = storage.diff_staged_trees(2, 3).unwrap();
+        assert!(diff.added.is_empty());
+        assert!(diff.removed.is_empty());
+        assert_eq!(diff.modified, vec![(key_abc.clone(), vec![1u8], vec![3u8])]);
+
+        assert!(matches!(
+            storage.diff_staged_trees(1, 4),
+            Err(MerkleError::TreeNotFoundInStaging { tree_id: 4 })
+        ));
+    }
+
+    fn test_proof(kv_store_factory: &TestContextKvStoreFactoryInstance) {
+        let key_abc: &ContextKey = &vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let key_abx: &ContextKey = &vec!["a".to_string(), "b".to_string(), "x".to_string()];
+        let key_ab: &ContextKey = &vec!["a".to_string(), "b".to_string()];
+        let key_z: &ContextKey = &vec!["z".to_string()];
+
+        let mut storage = MerkleStorage::new(kv_store_factory.create("test_proof").unwrap());
+
+        storage.set(1, key_abc, vec![1u8]).unwrap();
+        storage.set(2, key_abx, vec![2u8]).unwrap();
+        storage.set(3, key_z, vec![3u8]).unwrap();
+        let commit1 = storage
+            .commit(0, "Tezedge".to_string(), "first".to_string())
+            .unwrap();
+        storage.set(4, key_abc, vec![4u8]).unwrap();
+        let commit2 = storage
+            .commit(1, "Tezedge".to_string(), "second".to_string())
+            .unwrap();
+
+        let proof = storage.get_proof(&commit1, key_abc).unwrap();
+        assert_eq!(proof.value, vec![1u8]);
+        assert_eq!(proof.trees.len(), 3);
+        assert!(verify_proof(&proof, key_abc, &commit1).unwrap());
+        assert!(!verify_proof(&proof, key_abx, &commit1).unwrap());
+        assert!(!verify_proof(&proof, key_abc, &commit2).unwrap());
+
+        let proof = storage.get_proof(&commit2, key_abc).unwrap();
+        assert_eq!(proof.value, vec![4u8]);
+        assert_eq!(proof.parent_commit_hash, Some(commit1));
+        assert!(verify_proof(&proof, key_abc, &commit2).unwrap());
+
+        let proof = storage.get_proof(&commit2, key_z).unwrap();
+        assert!(verify_proof(&proof, key_z, &commit2).unwrap());
+
+        // tampered value
+        let mut tampered = proof.clone();
+        tampered.value = vec![5u8];
+        assert!(!verify_proof(&tampered, key_z, &commit2).unwrap());
+
+        // tampered commit
+        let mut tampered = proof;
+        tampered.message = "third".to_string();
+        assert!(!verify_proof(&tampered, key_z, &commit2).unwrap());
+
+        // large tree is proven by hashes of sibling inodes only
+        for i in 0..300 {
+            storage
+                .set(5, &vec!["big".to_string(), i.to_string()], vec![i as u8])
+                .unwrap();
+        }
+        let commit3 = storage
+            .commit(2, "Tezedge".to_string(), "third".to_string())
+            .unwrap();
+        let key_big: &ContextKey = &vec!["big".to_string(), "123".to_string()];
+        let proof = storage.get_proof(&commit3, key_big).unwrap();
+        assert_eq!(proof.value, vec![123u8]);
+        match &proof.trees[1] {
+            TreeProof::Long { inodes, siblings } => {
+                assert_eq!(inodes[0].children, 300);
+                assert!(inodes.iter().all(|inode| inode.pointers.len() < 32));
+                assert!(siblings.len() < 32);
+            }
+            _ => panic!("expected proof of large tree"),
+        }
+        assert!(verify_proof(&proof, key_big, &commit3).unwrap());
+        assert!(!verify_proof(
+            &proof,
+            &vec!["big".to_string(), "124".to_string()],
+            &commit3
+        )
+        .unwrap());
+
+        // tampered inode pointer
+        let mut tampered = proof;
+        if let TreeProof::Long { inodes, .. } = &mut tampered.trees[1] {
+            inodes[0].pointers[0].1[0] ^= 1;
+        }
+        assert!(!verify_proof(&tampered, key_big, &commit3).unwrap());
+
+        // only values can be proven
+        assert!(matches!(
+            storage.get_proof(&commit2, key_ab),
+            Err(MerkleError::ValueIsNotABlob { .. })
+        ));
+        assert!(matches!(
+            storage.get_proof(&commit2, &vec!["y".to_string()]),
+            Err(MerkleError::ValueNotFound { .. })
+        ));
+    }
+
+    fn test_entry_cache(kv_store_factory: &TestContextKvStoreFactoryInstance) {
+        let key_abc: &ContextKey = &vec!["a".to_string(), "b".to_string(), "c".to_string()];
+
+        let mut storage = MerkleStorage::new(kv_store_factory.create("test_entry_cache").unwrap());
+
+        storage.set(1, key_abc, vec![1u8]).unwrap();
+        let commit = storage.commit(0, "".to_string(), "".to_string()).unwrap();
+        // clears staging area, so entries are read from db
+        storage.checkout(&commit).unwrap();
+
+        assert_eq!(storage.get_history(&commit, key_abc).unwrap(), vec![1u8]);
+        let stats = storage.get_merkle_stats().unwrap().entry_cache_stats;
+        assert!(stats.entries > 0);
+        assert!(stats.memory_usage > 0);
+
+        // commit, root tree, trees "a" and "b" and blob are cached
+        assert_eq!(storage.get_history(&commit, key_abc).unwrap(), vec![1u8]);
+        let cached_stats = storage.get_merkle_stats().unwrap().entry_cache_stats;
+        assert_eq!(cached_stats.hits, stats.hits + 5);
+        assert_eq!(cached_stats.misses, stats.misses);
+    }
+
     /// Test getting entire tree in string format for JSON RPC
     fn test_get_context_tree_by_prefix(kv_store_factory: &TestContextKvStoreFactoryInstance) {
         let mut storage = MerkleStorage::new(
@@ -1616,6 +2230,22 @@ mod tests {
                     super::test_checkout($kv_store_factory)
                 }
                 #[test]
+                fn test_diff() {
+                    super::test_diff($kv_store_factory)
+                }
+                #[test]
+                fn test_diff_staged_trees() {
+                    super::test_diff_staged_trees($kv_store_factory)
+                }
+                #[test]
+                fn test_proof() {
+                    super::test_proof($kv_store_factory)
+                }
+                #[test]
+                fn test_entry_cache() {
+                    super::test_entry_cache($kv_store_factory)
+                }
+                #[test]
                 fn test_get_context_tree_by_prefix() {
                     super::test_get_context_tree_by_prefix($kv_store_factory)
                 }
@@ -1680,6 +2310,16 @@ mod tests {
                     )
                     .unwrap()
             );
+            tests_with_storage!(
+                kv_store_pack_file_tests,
+                super::SUPPORTED_KV_STORES
+                    .get(
+                        &crate::context::kv_store::SupportedContextKeyValueStore::PackFile {
+                            path: super::out_dir_path()
+                        }
+                    )
+                    .unwrap()
+            );
         };
     }
 
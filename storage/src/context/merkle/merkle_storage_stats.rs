@@ -4,6 +4,9 @@ use std::collections::HashMap;
 use std::fmt;
 use std::time::Instant;
 
+use crate::context::gc::GarbageCollectionStats;
+use crate::context::merkle::entry_cache::EntryCacheStats;
+
 /// Latency statistics for each action (in nanoseconds)
 #[derive(Serialize, Debug, Clone, Copy)]
 pub struct OperationLatencies {
@@ -46,6 +49,9 @@ pub type PerPathOperationStats = HashMap<String, OperationLatencyStats>;
 pub struct MerkleStoragePerfReport {
     pub perf_stats: MerklePerfStats,
     pub kv_store_stats: usize,
+    pub entry_cache_stats: EntryCacheStats,
+    /// Present only if the kv store is garbage collected in the background
+    pub gc_stats: Option<GarbageCollectionStats>,
 }
 
 #[derive(Serialize, Default, Debug, Clone)]
@@ -95,6 +101,8 @@ pub enum MerkleStorageAction {
     Commit,
     Checkout,
     BlockApplied,
+    Diff,
+    GetProof,
 }
 
 impl fmt::Display for MerkleStorageAction {
@@ -142,6 +150,12 @@ impl fmt::Display for MerkleStorageAction {
             MerkleStorageAction::BlockApplied => {
                 write!(f, "GC")
             }
+            MerkleStorageAction::Diff => {
+                write!(f, "Diff")
+            }
+            MerkleStorageAction::GetProof => {
+                write!(f, "GetProof")
+            }
         }
     }
 }
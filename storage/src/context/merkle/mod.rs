@@ -8,6 +8,7 @@ use serde::{Deserialize, Serialize};
 use crate::context::merkle::hash::EntryHash;
 use crate::context::ContextValue;
 
+pub mod entry_cache;
 pub mod hash;
 pub mod merkle_storage;
 pub mod merkle_storage_stats;
@@ -44,3 +45,42 @@ pub enum Entry {
     Blob(ContextValue),
     Commit(Commit),
 }
+
+/// Proof, that value is stored under the key in the context of a commit, can be verified with [hash::verify_proof]
+/// without access to the storage.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ContextProof {
+    /// Proven value
+    pub value: ContextValue,
+    /// Proofs of every tree on the key path, from the root tree to the tree containing the value.
+    pub trees: Vec<TreeProof>,
+    /// Commit fields, root hash is computed by verifier
+    pub parent_commit_hash: Option<EntryHash>,
+    pub time: u64,
+    pub author: String,
+    pub message: String,
+}
+
+/// Part of the tree needed to recompute its hash, node on the key path itself is not included,
+/// verifier computes its hash.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub enum TreeProof {
+    /// Tree with at most 256 entries is hashed from names, kinds and hashes of all its entries,
+    /// so all sibling nodes are included.
+    Short { siblings: Vec<(String, Node)> },
+    /// Larger tree is hashed as inodes partitioned by name, only hashes of sibling inodes on the path
+    /// from the root inode (`inodes[0]`) and sibling nodes of the last inode value are included.
+    Long {
+        inodes: Vec<InodeProof>,
+        siblings: Vec<(String, Node)>,
+    },
+}
+
+/// Inode tree on the key path of a large tree
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct InodeProof {
+    /// Count of all entries under the inode
+    pub children: usize,
+    /// Indexes and hashes of other non-empty child inodes
+    pub pointers: Vec<(u8, EntryHash)>,
+}
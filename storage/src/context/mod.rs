@@ -19,6 +19,7 @@ use tezos_context::channel::ContextAction;
 use crate::context::gc::GarbageCollector;
 use crate::context::merkle::merkle_storage::MerkleError;
 use crate::context::merkle::merkle_storage_stats::MerkleStoragePerfReport;
+use crate::context::merkle::ContextProof;
 use crate::persistent::{
     Flushable, KeyValueSchema, KeyValueStoreBackend, MultiInstanceable, Persistable,
 };
@@ -48,6 +49,19 @@ pub enum StringTreeEntry {
     Null,
 }
 
+/// Difference of context between two commits
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ContextDiff {
+    /// Key-values, which are only in the newer context
+    pub added: Vec<(ContextKey, ContextValue)>,
+    /// Key-values, which are only in the older context
+    pub removed: Vec<(ContextKey, ContextValue)>,
+    /// Keys with changed value - (key, old value, new value)
+    pub modified: Vec<(ContextKey, ContextValue, ContextValue)>,
+    /// Last returned key, if the diff was limited and there are more changes after it
+    pub next_cursor: Option<ContextKey>,
+}
+
 /// Abstraction on context manipulation
 pub trait ContextApi {
     // set key-value
@@ -115,6 +129,21 @@ pub trait ContextApi {
         prefix: &ContextKey,
         depth: Option<usize>,
     ) -> Result<StringTreeEntry, ContextError>;
+    // get difference between two contexts (only keys under prefix), at most limit changes after cursor
+    fn get_context_diff(
+        &self,
+        from_context_hash: &ContextHash,
+        to_context_hash: &ContextHash,
+        prefix: &ContextKey,
+        cursor: Option<&ContextKey>,
+        limit: Option<usize>,
+    ) -> Result<ContextDiff, ContextError>;
+    // get proof of value under key in context, see merkle::hash::verify_proof
+    fn get_context_proof(
+        &self,
+        context_hash: &ContextHash,
+        key: &ContextKey,
+    ) -> Result<ContextProof, ContextError>;
 
     // get currently checked out hash
     fn get_last_commit_hash(&self) -> Result<Option<Vec<u8>>, ContextError>;
@@ -129,6 +158,13 @@ pub trait ContextApi {
 
     fn get_merkle_root(&mut self) -> Result<EntryHash, ContextError>;
 
+    // get difference between two staged (not yet committed) trees of the current block
+    fn get_staged_diff(
+        &self,
+        from_tree_id: TreeId,
+        to_tree_id: TreeId,
+    ) -> Result<ContextDiff, ContextError>;
+
     fn block_applied(&self) -> Result<(), ContextError>;
 
     fn cycle_started(&self) -> Result<(), ContextError>;
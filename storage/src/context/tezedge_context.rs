@@ -14,7 +14,10 @@ use crate::context::actions::{get_new_tree_hash, get_tree_id};
 use crate::context::merkle::hash::EntryHash;
 use crate::context::merkle::merkle_storage::{MerkleError, MerkleStorage};
 use crate::context::merkle::merkle_storage_stats::MerkleStoragePerfReport;
-use crate::context::{ContextApi, ContextError, ContextKey, ContextValue, StringTreeEntry, TreeId};
+use crate::context::merkle::ContextProof;
+use crate::context::{
+    ContextApi, ContextDiff, ContextError, ContextKey, ContextValue, StringTreeEntry, TreeId,
+};
 use crate::{BlockStorage, BlockStorageReader, StorageError};
 
 impl ContextApi for TezedgeContext {
@@ -154,6 +157,40 @@ impl ContextApi for TezedgeContext {
             .map_err(ContextError::from)
     }
 
+    fn get_context_diff(
+        &self,
+        from_context_hash: &ContextHash,
+        to_context_hash: &ContextHash,
+        prefix: &ContextKey,
+        cursor: Option<&ContextKey>,
+        limit: Option<usize>,
+    ) -> Result<ContextDiff, ContextError> {
+        let from_context_hash_arr: EntryHash = from_context_hash.as_ref().as_slice().try_into()?;
+        let to_context_hash_arr: EntryHash = to_context_hash.as_ref().as_slice().try_into()?;
+        let merkle = self.merkle.read()?;
+        merkle
+            .diff(
+                &from_context_hash_arr,
+                &to_context_hash_arr,
+                prefix,
+                cursor,
+                limit,
+            )
+            .map_err(ContextError::from)
+    }
+
+    fn get_context_proof(
+        &self,
+        context_hash: &ContextHash,
+        key: &ContextKey,
+    ) -> Result<ContextProof, ContextError> {
+        let context_hash_arr: EntryHash = context_hash.as_ref().as_slice().try_into()?;
+        let mut merkle = self.merkle.write()?;
+        merkle
+            .get_proof(&context_hash_arr, key)
+            .map_err(ContextError::from)
+    }
+
     fn get_last_commit_hash(&self) -> Result<Option<Vec<u8>>, ContextError> {
         let merkle = self.merkle.read()?;
         Ok(merkle.get_last_commit_hash().map(|x| x.to_vec()))
@@ -183,6 +220,17 @@ impl ContextApi for TezedgeContext {
         merkle.get_staged_root_hash().map_err(ContextError::from)
     }
 
+    fn get_staged_diff(
+        &self,
+        from_tree_id: TreeId,
+        to_tree_id: TreeId,
+    ) -> Result<ContextDiff, ContextError> {
+        let mut merkle = self.merkle.write()?;
+        merkle
+            .diff_staged_trees(from_tree_id, to_tree_id)
+            .map_err(ContextError::from)
+    }
+
     fn block_applied(&self) -> Result<(), ContextError> {
         let mut merkle = self.merkle.write()?;
         Ok(merkle.block_applied()?)
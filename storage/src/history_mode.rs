@@ -0,0 +1,522 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! # History modes
+//!
+//! Defines, how much of the chain history is kept in the storage:
+//! - `archive` - everything is kept forever
+//! - `full` - blocks and operations are kept, but json data (block/operations metadata) older than N cycles are dropped,
+//!            `save_point` is moved to the lowest block with metadata
+//! - `rolling` - like full, but also block headers and operations older than N cycles are dropped,
+//!               `caboose` (and `save_point`) is moved to the lowest stored block
+//!
+//! Forks branching bellow the kept history are removed completely in both `full` and `rolling` mode.
+//!
+//! Pruning removes data from the indexes, space in block storage commit log is reclaimed afterwards by compaction.
+//! Genesis block is never pruned. Cycle length is read from the protocol constants of the current head.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+use failure::Fail;
+use slog::{info, warn, Logger};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+use crypto::hash::{BlockHash, ChainId, FromBytesError, ProtocolHash};
+use tezos_messages::base::rpc_support::UniversalValue;
+use tezos_messages::protocol::{
+    get_constants_for_rpc, ContextConstantsDecodeError, SupportedProtocol,
+};
+use tezos_messages::Head;
+
+use crate::block_meta_storage::BlockMetaStorageReader;
+use crate::block_storage::BlockLevel;
+use crate::chain_meta_storage::ChainMetaStorageReader;
+use crate::context::merkle::merkle_storage::MerkleStorage;
+use crate::context::{ContextApi, ContextError, TezedgeContext};
+use crate::{
+    context_key, BlockHeaderWithHash, BlockMetaStorage, BlockStorage, BlockStorageReader,
+    ChainMetaStorage, OperationsMetaStorage, OperationsStorage, PersistentStorage, StorageError,
+};
+
+pub const ARCHIVE: &str = "archive";
+
+/// Default number of cycles kept with all data in `full` and `rolling` mode
+pub const DEFAULT_ADDITIONAL_CYCLES: u32 = 5;
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, EnumIter)]
+pub enum HistoryMode {
+    Archive,
+    Full { additional_cycles: u32 },
+    Rolling { additional_cycles: u32 },
+}
+
+impl HistoryMode {
+    pub fn possible_values() -> Vec<&'static str> {
+        let mut possible_values = Vec::new();
+        for sp in HistoryMode::iter() {
+            possible_values.extend(sp.supported_values());
+        }
+        possible_values
+    }
+
+    fn supported_values(&self) -> Vec<&'static str> {
+        match self {
+            HistoryMode::Archive => vec![ARCHIVE],
+            HistoryMode::Full { .. } => vec!["full"],
+            HistoryMode::Rolling { .. } => vec!["rolling"],
+        }
+    }
+
+    /// Returns the same mode with `additional_cycles` (ignored for archive)
+    pub fn with_additional_cycles(self, additional_cycles: u32) -> Self {
+        match self {
+            HistoryMode::Archive => HistoryMode::Archive,
+            HistoryMode::Full { .. } => HistoryMode::Full { additional_cycles },
+            HistoryMode::Rolling { .. } => HistoryMode::Rolling { additional_cycles },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseHistoryModeError(String);
+
+impl FromStr for HistoryMode {
+    type Err = ParseHistoryModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        for sp in HistoryMode::iter() {
+            if sp.supported_values().contains(&s.as_str()) {
+                return Ok(sp.with_additional_cycles(DEFAULT_ADDITIONAL_CYCLES));
+            }
+        }
+
+        Err(ParseHistoryModeError(format!(
+            "Invalid variant name: {}",
+            s
+        )))
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum BlockPrunerError {
+    #[fail(display = "Storage error: {}", error)]
+    StorageError { error: StorageError },
+    #[fail(display = "Context error: {}", error)]
+    ContextError { error: ContextError },
+    #[fail(display = "Invalid protocol constants, reason: {}", reason)]
+    InvalidConstants { reason: String },
+}
+
+impl From<StorageError> for BlockPrunerError {
+    fn from(error: StorageError) -> Self {
+        BlockPrunerError::StorageError { error }
+    }
+}
+
+impl From<ContextError> for BlockPrunerError {
+    fn from(error: ContextError) -> Self {
+        BlockPrunerError::ContextError { error }
+    }
+}
+
+impl From<FromBytesError> for BlockPrunerError {
+    fn from(error: FromBytesError) -> Self {
+        BlockPrunerError::InvalidConstants {
+            reason: format!("{}", error),
+        }
+    }
+}
+
+impl From<ContextConstantsDecodeError> for BlockPrunerError {
+    fn from(error: ContextConstantsDecodeError) -> Self {
+        BlockPrunerError::InvalidConstants {
+            reason: format!("{}", error),
+        }
+    }
+}
+
+/// Reads `blocks_per_cycle` from protocol constants stored in the context of the `block`.
+/// Returns None, if protocol does not define constants (e.g. genesis protocol) or is not supported.
+pub fn read_blocks_per_cycle(
+    context: &dyn ContextApi,
+    block: &BlockHeaderWithHash,
+) -> Result<Option<BlockLevel>, BlockPrunerError> {
+    let context_hash = block.header.context();
+    let protocol_hash =
+        match context.get_key_from_history(context_hash, &context_key!("protocol"))? {
+            Some(data) => ProtocolHash::try_from(data)?,
+            None => return Ok(None),
+        };
+    let protocol = match SupportedProtocol::try_from(protocol_hash) {
+        Ok(protocol) => protocol,
+        Err(_) => return Ok(None),
+    };
+    let constants =
+        match context.get_key_from_history(context_hash, &context_key!("data/v1/constants"))? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+
+    match get_constants_for_rpc(&constants, &protocol)?
+        .as_ref()
+        .and_then(|constants| constants.get("blocks_per_cycle"))
+    {
+        Some(UniversalValue::Number(blocks_per_cycle)) if *blocks_per_cycle > 0 => {
+            Ok(Some(*blocks_per_cycle))
+        }
+        Some(value) => Err(BlockPrunerError::InvalidConstants {
+            reason: format!("Invalid blocks_per_cycle: {:?}", value),
+        }),
+        None => Ok(None),
+    }
+}
+
+/// Statistics of one pruning run
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PruningStats {
+    /// Count of blocks, which json data were removed
+    pub pruned_json_data_count: usize,
+    /// Count of blocks removed completely (header and operations)
+    pub pruned_blocks_count: usize,
+    /// Count of removed blocks of forks, which branched bellow the new lowest level
+    pub pruned_fork_blocks_count: usize,
+    /// New lowest level of kept data
+    pub pruned_to_level: Option<BlockLevel>,
+    /// Bytes reclaimed by block storage commit log compaction
+    pub reclaimed_bytes: u64,
+}
+
+/// Removes old data according to [HistoryMode]
+pub struct BlockPruner {
+    history_mode: HistoryMode,
+    /// Lowest kept level after the last pruning, loaded from save_point/caboose by the first pruning
+    last_pruned_level: Option<BlockLevel>,
+    /// Cache of `blocks_per_cycle` constant per protocol (`proto` of block header)
+    blocks_per_cycle: HashMap<u8, BlockLevel>,
+    block_storage: BlockStorage,
+    block_meta_storage: BlockMetaStorage,
+    operations_storage: OperationsStorage,
+    operations_meta_storage: OperationsMetaStorage,
+    chain_meta_storage: ChainMetaStorage,
+    merkle: Arc<RwLock<MerkleStorage>>,
+}
+
+impl BlockPruner {
+    pub fn new(history_mode: HistoryMode, persistent_storage: &PersistentStorage) -> Self {
+        Self {
+            history_mode,
+            last_pruned_level: None,
+            blocks_per_cycle: HashMap::new(),
+            block_storage: BlockStorage::new(persistent_storage),
+            block_meta_storage: BlockMetaStorage::new(persistent_storage),
+            operations_storage: OperationsStorage::new(persistent_storage),
+            operations_meta_storage: OperationsMetaStorage::new(persistent_storage),
+            chain_meta_storage: ChainMetaStorage::new(persistent_storage),
+            merkle: persistent_storage.merkle(),
+        }
+    }
+
+    pub fn history_mode(&self) -> &HistoryMode {
+        &self.history_mode
+    }
+
+    /// Pruning is triggered, when the new lowest kept level moved at least by one cycle from the last pruning
+    /// (or nothing was pruned yet since start)
+    pub fn should_prune(&self, head_level: BlockLevel, blocks_per_cycle: BlockLevel) -> bool {
+        let keep_from_level = match self.keep_from_level(head_level, blocks_per_cycle) {
+            Some(keep_from_level) => keep_from_level,
+            None => return false,
+        };
+        match self.last_pruned_level {
+            Some(last_pruned_level) => {
+                keep_from_level >= last_pruned_level.saturating_add(blocks_per_cycle)
+            }
+            None => keep_from_level > 0,
+        }
+    }
+
+    /// Reads `blocks_per_cycle` of the `block` protocol, see [read_blocks_per_cycle].
+    pub fn blocks_per_cycle(
+        &mut self,
+        block: &BlockHeaderWithHash,
+    ) -> Result<Option<BlockLevel>, BlockPrunerError> {
+        let proto = block.header.proto();
+        if let Some(blocks_per_cycle) = self.blocks_per_cycle.get(&proto) {
+            return Ok(Some(*blocks_per_cycle));
+        }
+
+        let context = TezedgeContext::new(None, self.merkle.clone());
+        let blocks_per_cycle = match read_blocks_per_cycle(&context, block)? {
+            Some(blocks_per_cycle) => blocks_per_cycle,
+            None => return Ok(None),
+        };
+        self.blocks_per_cycle.insert(proto, blocks_per_cycle);
+        Ok(Some(blocks_per_cycle))
+    }
+
+    /// Prunes data for new `head`, if needed, `blocks_per_cycle` is read from the protocol constants of the head.
+    /// Returns None, if nothing was pruned.
+    pub fn prune_for_head(
+        &mut self,
+        chain_id: &ChainId,
+        head: &Head,
+    ) -> Result<Option<PruningStats>, BlockPrunerError> {
+        if let HistoryMode::Archive = self.history_mode {
+            return Ok(None);
+        }
+        let block = match self.block_storage.get(head.block_hash())? {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        let blocks_per_cycle = match self.blocks_per_cycle(&block)? {
+            Some(blocks_per_cycle) => blocks_per_cycle,
+            None => return Ok(None),
+        };
+        if !self.should_prune(*head.level(), blocks_per_cycle) {
+            return Ok(None);
+        }
+        self.prune(chain_id, head, blocks_per_cycle)
+            .map(Some)
+            .map_err(BlockPrunerError::from)
+    }
+
+    /// Prunes all data older than configured count of cycles bellow the `head`.
+    ///
+    /// Main chain is resolved from the `head` through the predecessors, every fork branching from the pruned blocks
+    /// is removed completely (forks cannot be applied so deep bellow the head).
+    /// Blocks are pruned from the lowest level, so interrupted pruning is just finished by the next run,
+    /// already pruned levels (bellow save_point/caboose) are skipped.
+    pub fn prune(
+        &mut self,
+        chain_id: &ChainId,
+        head: &Head,
+        blocks_per_cycle: BlockLevel,
+    ) -> Result<PruningStats, StorageError> {
+        let mut stats = PruningStats::default();
+
+        let remove_blocks = match self.history_mode {
+            HistoryMode::Archive => return Ok(stats),
+            HistoryMode::Full { .. } => false,
+            HistoryMode::Rolling { .. } => true,
+        };
+        let keep_from_level = match self.keep_from_level(*head.level(), blocks_per_cycle) {
+            Some(keep_from_level) => keep_from_level,
+            None => return Ok(stats),
+        };
+
+        // genesis (level 0) is never pruned
+        let already_pruned_level = if remove_blocks {
+            self.chain_meta_storage.get_caboose(chain_id)?
+        } else {
+            self.chain_meta_storage.get_save_point(chain_id)?
+        }
+        .map(|head| *head.level())
+        .unwrap_or(0);
+        self.last_pruned_level = Some(already_pruned_level);
+        let from_level = std::cmp::max(1, already_pruned_level);
+        if from_level >= keep_from_level {
+            return Ok(stats);
+        }
+
+        // new lower bound has to be stored block, otherwise we would move save_point/caboose to nowhere
+        let new_lowest_block = match self
+            .block_meta_storage
+            .find_block_at_distance(head.block_hash().clone(), head.level() - keep_from_level)?
+        {
+            Some(block_hash) => match self.block_storage.get(&block_hash)? {
+                Some(block) => block,
+                None => return Ok(stats),
+            },
+            None => return Ok(stats),
+        };
+
+        // (block, its successor on the main chain) from the lowest level
+        let main_chain = self.collect_main_chain(&new_lowest_block.hash, from_level)?;
+        for (block_hash, main_successor) in main_chain {
+            stats.pruned_fork_blocks_count += self.prune_forks(&block_hash, &main_successor)?;
+
+            if self.block_storage.remove_block_json_data(&block_hash)? {
+                stats.pruned_json_data_count += 1;
+            }
+            if remove_blocks {
+                if self.remove_block(&block_hash)? {
+                    stats.pruned_blocks_count += 1;
+                }
+            } else {
+                // just removes the block from the context hash index (block stays readable by hash and level),
+                // context itself is not touched here, it is removed independently by the context garbage collector
+                self.block_storage.unassign_context(&block_hash)?;
+            }
+        }
+
+        let new_lowest_head = to_head(&new_lowest_block);
+        self.chain_meta_storage
+            .set_save_point(chain_id, new_lowest_head.clone())?;
+        if remove_blocks {
+            self.chain_meta_storage
+                .set_caboose(chain_id, new_lowest_head)?;
+        }
+        self.last_pruned_level = Some(keep_from_level);
+        stats.pruned_to_level = Some(keep_from_level);
+
+        if stats.pruned_json_data_count > 0
+            || stats.pruned_blocks_count > 0
+            || stats.pruned_fork_blocks_count > 0
+        {
+            stats.reclaimed_bytes = self.block_storage.compact_commit_log()?.reclaimed_bytes;
+        }
+
+        Ok(stats)
+    }
+
+    fn keep_from_level(
+        &self,
+        head_level: BlockLevel,
+        blocks_per_cycle: BlockLevel,
+    ) -> Option<BlockLevel> {
+        match self.history_mode {
+            HistoryMode::Archive => None,
+            HistoryMode::Full { additional_cycles }
+            | HistoryMode::Rolling { additional_cycles } => Some(std::cmp::max(
+                0,
+                head_level.saturating_sub(
+                    (additional_cycles as BlockLevel).saturating_mul(blocks_per_cycle),
+                ),
+            )),
+        }
+    }
+
+    /// Walks predecessors of the `lowest_kept_block` down to the `from_level`,
+    /// returns pairs (block, successor on the main chain) ordered from the lowest level.
+    fn collect_main_chain(
+        &self,
+        lowest_kept_block: &BlockHash,
+        from_level: BlockLevel,
+    ) -> Result<Vec<(BlockHash, BlockHash)>, StorageError> {
+        let mut main_chain = Vec::new();
+        let mut successor = lowest_kept_block.clone();
+        while let Some(meta) = self.block_meta_storage.get(&successor)? {
+            let predecessor = match meta.predecessor() {
+                Some(predecessor) if meta.level() > from_level && *predecessor != successor => {
+                    predecessor.clone()
+                }
+                _ => break,
+            };
+            main_chain.push((predecessor.clone(), successor));
+            successor = predecessor;
+        }
+        main_chain.reverse();
+        Ok(main_chain)
+    }
+
+    /// Removes all blocks of forks branching from the `block_hash`, just the `main_successor` is kept.
+    /// Returns count of removed blocks.
+    fn prune_forks(
+        &self,
+        block_hash: &BlockHash,
+        main_successor: &BlockHash,
+    ) -> Result<usize, StorageError> {
+        let mut forks = match self.block_meta_storage.get(block_hash)? {
+            Some(meta) if meta.successors().len() > 1 => meta
+                .successors()
+                .iter()
+                .filter(|successor| *successor != main_successor)
+                .cloned()
+                .collect::<Vec<_>>(),
+            _ => return Ok(0),
+        };
+
+        let mut pruned_blocks_count = 0;
+        while let Some(fork_block) = forks.pop() {
+            if let Some(meta) = self.block_meta_storage.get(&fork_block)? {
+                forks.extend(meta.successors().iter().cloned());
+            }
+            self.block_storage.remove_block_json_data(&fork_block)?;
+            if self.remove_block(&fork_block)? {
+                pruned_blocks_count += 1;
+            }
+        }
+        self.block_meta_storage
+            .set_successors(block_hash, vec![main_successor.clone()])?;
+
+        Ok(pruned_blocks_count)
+    }
+
+    /// Removes block header, operations and all its metadata
+    fn remove_block(&self, block_hash: &BlockHash) -> Result<bool, StorageError> {
+        self.operations_storage.delete_operations(block_hash)?;
+        self.operations_meta_storage.delete(block_hash)?;
+        self.block_meta_storage.delete(block_hash)?;
+        self.block_storage.remove_block(block_hash)
+    }
+}
+
+/// Background thread running [BlockPruner], so pruning does not block processing of new heads
+pub struct BlockPrunerWorker {
+    /// Dropped to stop the thread
+    new_heads: Option<SyncSender<(ChainId, Head)>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl BlockPrunerWorker {
+    pub fn start(mut block_pruner: BlockPruner, log: Logger) -> Result<Self, std::io::Error> {
+        // heads received while pruning are dropped, the latest one triggers the next pruning anyway
+        let (new_heads, new_heads_rx) = sync_channel::<(ChainId, Head)>(1);
+        let thread = thread::Builder::new()
+            .name("block-pruner".to_string())
+            .spawn(move || {
+                while let Ok((chain_id, head)) = new_heads_rx.recv() {
+                    match block_pruner.prune_for_head(&chain_id, &head) {
+                        Ok(Some(stats)) => info!(log, "Old blocks pruned";
+                                                 "history_mode" => format!("{:?}", block_pruner.history_mode()),
+                                                 "pruned_to_level" => format!("{:?}", stats.pruned_to_level),
+                                                 "pruned_json_data_count" => stats.pruned_json_data_count,
+                                                 "pruned_blocks_count" => stats.pruned_blocks_count,
+                                                 "pruned_fork_blocks_count" => stats.pruned_fork_blocks_count,
+                                                 "reclaimed_bytes" => stats.reclaimed_bytes),
+                        Ok(None) => (),
+                        Err(e) => warn!(log, "Failed to prune old blocks";
+                                        "level" => head.level(),
+                                        "reason" => format!("{}", e)),
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            new_heads: Some(new_heads),
+            thread: Some(thread),
+        })
+    }
+
+    /// Notifies pruner about new current head, does not wait for pruning
+    pub fn head_applied(&self, chain_id: ChainId, head: Head) {
+        if let Some(new_heads) = self.new_heads.as_ref() {
+            // full channel means pruning is running, disconnected means thread has finished
+            let _ = new_heads.try_send((chain_id, head));
+        }
+    }
+}
+
+impl Drop for BlockPrunerWorker {
+    fn drop(&mut self) {
+        drop(self.new_heads.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn to_head(block: &BlockHeaderWithHash) -> Head {
+    Head::new(
+        block.hash.clone(),
+        block.header.level(),
+        block.header.fitness().clone(),
+    )
+}
@@ -0,0 +1,399 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! # Storage integrity check
+//!
+//! Verifies invariants, which should hold for every consistent storage:
+//! - every [BlockPrimaryIndex] entry resolves to readable records in block storage commit log
+//! - predecessor/successor links in [Meta] are symmetric
+//! - [PredecessorStorage] exponent slots match walking the chain
+//! - context hash of every applied block exists in the merkle storage
+//!
+//! Checks only read the storage, all inconsistencies are collected to the [IntegrityReport].
+//! Storage can be opened read-only, where block/operations metadata with merge operands not yet
+//! compacted cannot be read (see [open_kv_read_only]), such records are counted as unmerged, not as issues.
+//! Metadata are looked up by block hashes from [BlockPrimaryIndex], because iteration over merged values
+//! stops without any error on the first unmerged value.
+//!
+//! [BlockPrimaryIndex]: crate::block_storage::BlockPrimaryIndex
+//! [Meta]: crate::block_meta_storage::Meta
+//! [open_kv_read_only]: crate::persistent::database::open_kv_read_only
+
+use std::convert::TryInto;
+use std::time::Instant;
+
+use serde::Serialize;
+use slog::{info, Logger};
+
+use crypto::hash::BlockHash;
+
+use crate::context::merkle::hash::EntryHash;
+use crate::persistent::DBError;
+use crate::predecessor_storage::PredecessorKey;
+use crate::{
+    BlockMetaStorage, BlockMetaStorageReader, BlockStorage, BlockStorageReader, IteratorMode,
+    PersistentStorage, PredecessorStorage, StorageError,
+};
+
+/// Result of all integrity checks
+#[derive(Serialize, Debug, Default)]
+pub struct IntegrityReport {
+    pub block_storage: CheckReport,
+    pub block_meta_links: CheckReport,
+    pub predecessor_index: CheckReport,
+    /// None, if contexts were not checked
+    pub applied_block_contexts: Option<CheckReport>,
+}
+
+impl IntegrityReport {
+    /// Returns true, if no inconsistency was found
+    pub fn is_ok(&self) -> bool {
+        self.block_storage.issues.is_empty()
+            && self.block_meta_links.issues.is_empty()
+            && self.predecessor_index.issues.is_empty()
+            && self
+                .applied_block_contexts
+                .as_ref()
+                .map_or(true, |report| report.issues.is_empty())
+    }
+}
+
+/// Result of one integrity check
+#[derive(Serialize, Debug, Default)]
+pub struct CheckReport {
+    /// Count of checked records
+    pub checked: usize,
+    /// Count of records, which could not be checked, because their merge operands are not compacted
+    pub unmerged: usize,
+    pub issues: Vec<IntegrityIssue>,
+}
+
+impl CheckReport {
+    fn add_issue(&mut self, block_hash: &BlockHash, reason: String) {
+        self.issues.push(IntegrityIssue {
+            block_hash: block_hash.to_base58_check(),
+            reason,
+        });
+    }
+}
+
+/// Inconsistency found for the block
+#[derive(Serialize, Debug)]
+pub struct IntegrityIssue {
+    pub block_hash: String,
+    pub reason: String,
+}
+
+/// Runs all integrity checks, contexts are checked only if `check_contexts` is set
+pub fn check_storage(
+    persistent_storage: &PersistentStorage,
+    check_contexts: bool,
+    log: &Logger,
+) -> Result<IntegrityReport, StorageError> {
+    let block_storage = BlockStorage::new(persistent_storage);
+    let block_meta_storage = BlockMetaStorage::new(persistent_storage);
+    let predecessor_storage = PredecessorStorage::new(persistent_storage);
+
+    let started = Instant::now();
+    let report = IntegrityReport {
+        block_storage: check_block_storage(&block_storage)?,
+        block_meta_links: check_block_meta_links(&block_storage, &block_meta_storage)?,
+        predecessor_index: check_predecessor_index(&predecessor_storage, &block_meta_storage)?,
+        applied_block_contexts: if check_contexts {
+            Some(check_applied_block_contexts(
+                persistent_storage,
+                &block_storage,
+                &block_meta_storage,
+            )?)
+        } else {
+            None
+        },
+    };
+    let (checked_applied_blocks, unmerged_applied_blocks) = report
+        .applied_block_contexts
+        .as_ref()
+        .map_or((0, 0), |report| (report.checked, report.unmerged));
+
+    info!(log, "Storage integrity check finished";
+               "is_ok" => report.is_ok(),
+               "checked_blocks" => report.block_storage.checked,
+               "checked_block_metas" => report.block_meta_links.checked,
+               "checked_predecessor_slots" => report.predecessor_index.checked,
+               "checked_applied_blocks" => checked_applied_blocks,
+               "unmerged_records" => report.block_meta_links.unmerged
+                    + report.predecessor_index.unmerged
+                    + unmerged_applied_blocks,
+               "elapsed" => format!("{:?}", started.elapsed()));
+    Ok(report)
+}
+
+fn stored_block_hashes(block_storage: &BlockStorage) -> Result<Vec<BlockHash>, StorageError> {
+    Ok(block_storage
+        .iterator()?
+        .map(|(block_hash, _)| block_hash)
+        .collect::<Result<Vec<BlockHash>, _>>()?)
+}
+
+/// Every block from primary index has readable header (with the same hash), json data and additional data
+fn check_block_storage(block_storage: &BlockStorage) -> Result<CheckReport, StorageError> {
+    let mut report = CheckReport::default();
+
+    for block_hash in stored_block_hashes(block_storage)? {
+        report.checked += 1;
+        match block_storage.get(&block_hash) {
+            Ok(Some(block_header)) => {
+                if block_header.hash != block_hash {
+                    report.add_issue(
+                        &block_hash,
+                        format!(
+                            "Commit log record belongs to another block: {}",
+                            block_header.hash.to_base58_check()
+                        ),
+                    );
+                    continue;
+                }
+            }
+            Ok(None) => continue,
+            Err(e) => {
+                report.add_issue(&block_hash, format!("Block header is not readable: {}", e));
+                continue;
+            }
+        }
+        if let Err(e) = block_storage.get_with_json_data(&block_hash) {
+            report.add_issue(
+                &block_hash,
+                format!("Block json data are not readable: {}", e),
+            );
+        }
+        if let Err(e) = block_storage.get_with_additional_data(&block_hash) {
+            report.add_issue(
+                &block_hash,
+                format!("Block additional data are not readable: {}", e),
+            );
+        }
+    }
+
+    Ok(report)
+}
+
+/// Stored block is in successors of its predecessor and every successor points back to the block
+fn check_block_meta_links(
+    block_storage: &BlockStorage,
+    block_meta_storage: &BlockMetaStorage,
+) -> Result<CheckReport, StorageError> {
+    let mut report = CheckReport::default();
+
+    for block_hash in stored_block_hashes(block_storage)? {
+        let meta = match block_meta_storage.get(&block_hash) {
+            Ok(Some(meta)) => meta,
+            Ok(None) => continue,
+            Err(StorageError::DBError { error }) if error.is_merge_operator_missing() => {
+                report.unmerged += 1;
+                continue;
+            }
+            Err(e) => {
+                report.add_issue(&block_hash, format!("Metadata are not readable: {}", e));
+                continue;
+            }
+        };
+        report.checked += 1;
+
+        // genesis is its own predecessor
+        let predecessor = meta
+            .predecessor()
+            .as_ref()
+            .filter(|predecessor| *predecessor != &block_hash);
+        if let Some(predecessor) = predecessor {
+            match block_meta_storage.get(predecessor) {
+                Ok(Some(predecessor_meta)) => {
+                    if !predecessor_meta.successors().contains(&block_hash) {
+                        report.add_issue(
+                            &block_hash,
+                            format!(
+                                "Block is missing in successors of its predecessor: {}",
+                                predecessor.to_base58_check()
+                            ),
+                        );
+                    }
+                }
+                Ok(None) => report.add_issue(
+                    &block_hash,
+                    format!(
+                        "Predecessor metadata are missing: {}",
+                        predecessor.to_base58_check()
+                    ),
+                ),
+                Err(StorageError::DBError { error }) if error.is_merge_operator_missing() => {
+                    report.unmerged += 1
+                }
+                Err(e) => report.add_issue(
+                    &block_hash,
+                    format!(
+                        "Predecessor metadata are not readable: {}, reason: {}",
+                        predecessor.to_base58_check(),
+                        e
+                    ),
+                ),
+            }
+        }
+
+        for successor in meta.successors() {
+            match block_meta_storage.get(successor) {
+                Ok(Some(successor_meta)) => match successor_meta.predecessor() {
+                    // successor is not downloaded (yet or again after recovery)
+                    None => (),
+                    Some(predecessor) if predecessor == &block_hash => (),
+                    Some(predecessor) => report.add_issue(
+                        &block_hash,
+                        format!(
+                            "Successor {} points to another predecessor: {}",
+                            successor.to_base58_check(),
+                            predecessor.to_base58_check()
+                        ),
+                    ),
+                },
+                Ok(None) => report.add_issue(
+                    &block_hash,
+                    format!(
+                        "Successor metadata are missing: {}",
+                        successor.to_base58_check()
+                    ),
+                ),
+                Err(StorageError::DBError { error }) if error.is_merge_operator_missing() => {
+                    report.unmerged += 1
+                }
+                Err(e) => report.add_issue(
+                    &block_hash,
+                    format!(
+                        "Successor metadata are not readable: {}, reason: {}",
+                        successor.to_base58_check(),
+                        e
+                    ),
+                ),
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Slot 0 has to be the direct predecessor from [Meta] and slot `n` has to be predecessor at distance `2^(n-1)`
+/// of the predecessor at distance `2^(n-1)`. As every slot is checked, this is the same as walking the chain
+/// `2^n` blocks back, but without reading the whole chain for every slot.
+fn check_predecessor_index(
+    predecessor_storage: &PredecessorStorage,
+    block_meta_storage: &BlockMetaStorage,
+) -> Result<CheckReport, StorageError> {
+    let mut report = CheckReport::default();
+
+    for (key, stored_predecessor) in predecessor_storage.iter(IteratorMode::Start)? {
+        let (key, stored_predecessor) = (key?, stored_predecessor?);
+        report.checked += 1;
+
+        let block_hash = key.block_hash();
+        let exponent_slot = key.exponent_slot();
+        let expected_predecessor = if exponent_slot == 0 {
+            match block_meta_storage.get(block_hash) {
+                Ok(meta) => meta.and_then(|meta| meta.predecessor().clone()),
+                Err(StorageError::DBError { error }) if error.is_merge_operator_missing() => {
+                    report.unmerged += 1;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+        } else {
+            predecessor_storage
+                .get(&PredecessorKey::new(block_hash.clone(), exponent_slot - 1))?
+                .map(|half_way| {
+                    predecessor_storage.get(&PredecessorKey::new(half_way, exponent_slot - 1))
+                })
+                .transpose()?
+                .flatten()
+        };
+
+        match expected_predecessor {
+            Some(expected_predecessor) if expected_predecessor == stored_predecessor => (),
+            Some(expected_predecessor) => report.add_issue(
+                block_hash,
+                format!(
+                    "Exponent slot {} points to {}, but walking the chain leads to {}",
+                    exponent_slot,
+                    stored_predecessor.to_base58_check(),
+                    expected_predecessor.to_base58_check()
+                ),
+            ),
+            None => report.add_issue(
+                block_hash,
+                format!(
+                    "Exponent slot {} points to {}, but the chain cannot be walked that far",
+                    exponent_slot,
+                    stored_predecessor.to_base58_check()
+                ),
+            ),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Commit of every applied block is stored in merkle storage.
+/// Applied blocks removed from block storage (pruned) are skipped, unreadable blocks are reported by [check_block_storage].
+fn check_applied_block_contexts(
+    persistent_storage: &PersistentStorage,
+    block_storage: &BlockStorage,
+    block_meta_storage: &BlockMetaStorage,
+) -> Result<CheckReport, StorageError> {
+    let mut report = CheckReport::default();
+    let merkle = persistent_storage.merkle();
+    let merkle = merkle.read().map_err(DBError::from)?;
+
+    for block_hash in stored_block_hashes(block_storage)? {
+        match block_meta_storage.is_applied(&block_hash) {
+            Ok(true) => (),
+            Ok(false) => continue,
+            Err(StorageError::DBError { error }) if error.is_merge_operator_missing() => {
+                report.unmerged += 1;
+                continue;
+            }
+            // unreadable metadata are reported by [check_block_meta_links]
+            Err(_) => continue,
+        }
+        let block_header = match block_storage.get(&block_hash) {
+            Ok(Some(block_header)) => block_header,
+            Ok(None) | Err(_) => continue,
+        };
+        report.checked += 1;
+
+        let context_hash = block_header.header.context();
+        let commit_hash: EntryHash = match context_hash.as_ref().as_slice().try_into() {
+            Ok(commit_hash) => commit_hash,
+            Err(_) => {
+                report.add_issue(
+                    &block_hash,
+                    format!("Invalid context hash: {}", context_hash.to_base58_check()),
+                );
+                continue;
+            }
+        };
+        match merkle.contains_commit(&commit_hash) {
+            Ok(true) => (),
+            Ok(false) => report.add_issue(
+                &block_hash,
+                format!(
+                    "Context is missing in merkle storage: {}",
+                    context_hash.to_base58_check()
+                ),
+            ),
+            Err(e) => report.add_issue(
+                &block_hash,
+                format!(
+                    "Context is not readable from merkle storage: {}, reason: {}",
+                    context_hash.to_base58_check(),
+                    e
+                ),
+            ),
+        }
+    }
+
+    Ok(report)
+}
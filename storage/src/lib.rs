@@ -4,6 +4,7 @@
 #![feature(const_fn)]
 #![feature(allocator_api)]
 
+use std::collections::HashSet;
 use std::convert::{TryFrom, TryInto};
 use std::path::Path;
 use std::sync::{Arc, RwLock};
@@ -37,23 +38,32 @@ pub use crate::operations_meta_storage::{OperationsMetaStorage, OperationsMetaSt
 pub use crate::operations_storage::{
     OperationKey, OperationsStorage, OperationsStorageKV, OperationsStorageReader,
 };
+pub use crate::peer_storage::{PeerStorage, PeerStorageKV};
 pub use crate::persistent::database::{Direction, IteratorMode};
 use crate::persistent::sequence::{SequenceError, Sequences};
 use crate::persistent::{
-    CommitLogError, CommitLogs, DBError, Decoder, Encoder, Flushable, SchemaError,
+    CommitLogError, CommitLogs, DBError, Decoder, Encoder, Flushable, MultiInstanceableSyncError,
+    SchemaError,
 };
 pub use crate::predecessor_storage::PredecessorStorage;
+pub use crate::protocol_storage::{ProtocolStorage, ProtocolStorageKV};
 pub use crate::system_storage::SystemStorage;
 
 pub mod block_meta_storage;
 pub mod block_storage;
+pub mod chain_archive;
 pub mod chain_meta_storage;
 pub mod context;
+pub mod history_mode;
+pub mod integrity_check;
 pub mod mempool_storage;
 pub mod operations_meta_storage;
 pub mod operations_storage;
+pub mod peer_storage;
 pub mod persistent;
 pub mod predecessor_storage;
+pub mod protocol_storage;
+pub mod snapshot;
 pub mod system_storage;
 
 /// Extension of block header with block hash
@@ -134,6 +144,8 @@ pub enum StorageError {
     HashError { error: FromBytesError },
     #[fail(display = "Error decoding hash: {}", error)]
     HashDecodeError { error: FromBase58CheckError },
+    #[fail(display = "Failed to sync with primary instance: {}", reason)]
+    SyncWithPrimaryError { reason: String },
 }
 
 impl From<DBError> for StorageError {
@@ -186,6 +198,14 @@ impl From<FromBase58CheckError> for StorageError {
     }
 }
 
+impl From<MultiInstanceableSyncError> for StorageError {
+    fn from(error: MultiInstanceableSyncError) -> Self {
+        StorageError::SyncWithPrimaryError {
+            reason: error.to_string(),
+        }
+    }
+}
+
 impl slog::Value for StorageError {
     fn serialize(
         &self,
@@ -241,6 +261,45 @@ pub fn resolve_storage_init_chain_data(
     Ok(init_data)
 }
 
+/// Stores block header with block metadata and operations metadata (if not stored yet) in one atomic write.
+///
+/// Header is appended and flushed to commit log first and all indexes/metadata are written afterwards at once,
+/// so crash can leave just record in commit log, which is not referenced from anywhere.
+///
+/// Returns tuple:
+///     (
+///         is_new_block,
+///         block_metadata,
+///         (are_operations_complete, missing_validation_passes)
+///     )
+pub fn store_block_header(
+    block_storage: &BlockStorage,
+    block_meta_storage: &BlockMetaStorage,
+    operations_meta_storage: &OperationsMetaStorage,
+    block_header: &BlockHeaderWithHash,
+    chain_id: &ChainId,
+    log: &Logger,
+) -> Result<(bool, block_meta_storage::Meta, (bool, Option<HashSet<u8>>)), StorageError> {
+    // locations stored to the batch has to be valid until the batch is written
+    let _locations_guard = block_storage.locations_guard()?;
+    let mut batch = block_storage.write_batch();
+
+    let is_new_block = block_storage.put_block_header_to_batch(block_header, &mut batch)?;
+    let block_metadata =
+        block_meta_storage.put_block_header_to_batch(block_header, chain_id, log, &mut batch)?;
+    let operations_status = match operations_meta_storage.get(&block_header.hash)? {
+        Some(meta) => (meta.is_complete(), meta.get_missing_validation_passes()),
+        None => operations_meta_storage.put_block_header_to_batch(
+            block_header,
+            chain_id.clone(),
+            &mut batch,
+        )?,
+    };
+
+    batch.write(false)?;
+    Ok((is_new_block, block_metadata, operations_status))
+}
+
 /// Stores apply result to storage and mark block as applied, if everythnig is ok.
 pub fn store_applied_block_result(
     block_storage: &BlockStorage,
@@ -256,7 +315,6 @@ pub fn store_applied_block_result(
         .operations_proto_metadata_json(block_result.operations_proto_metadata_json)
         .build()
         .unwrap();
-    block_storage.put_block_json_data(&block_hash, block_json_data.clone())?;
 
     // store additional data
     let block_additional_data = BlockAdditionalDataBuilder::default()
@@ -281,17 +339,27 @@ pub fn store_applied_block_result(
         .ops_metadata_hashes(block_result.ops_metadata_hashes)
         .build()
         .unwrap();
-    block_storage.put_block_additional_data(&block_hash, block_additional_data.clone())?;
 
     // TODO: check context checksum or context_hash
 
+    // everything is written at once, so block is never marked as applied without its result
+    let _locations_guard = block_storage.locations_guard()?;
+    let mut batch = block_storage.write_batch();
+    block_storage.put_block_result_to_batch(
+        &block_hash,
+        block_json_data.clone(),
+        block_additional_data.clone(),
+        &mut batch,
+    )?;
+
     // if everything is stored and ok, we can considere this block as applied
     // mark current head as applied
     block_metadata.set_is_applied(true);
-    block_meta_storage.put(&block_hash, &block_metadata)?;
+    block_meta_storage.put_to_batch(&block_hash, &block_metadata, &mut batch)?;
     // populate predecessor storage
-    block_meta_storage.store_predecessors(&block_hash, &block_metadata)?;
+    block_meta_storage.store_predecessors_to_batch(&block_hash, &block_metadata, &mut batch)?;
 
+    batch.write(true)?;
     Ok((block_json_data, block_additional_data))
 }
 
@@ -342,6 +410,7 @@ pub fn store_commit_genesis_result(
             // init chain data
             chain_meta_storage.set_genesis(&chain_id, head.clone())?;
             chain_meta_storage.set_caboose(&chain_id, head.clone())?;
+            chain_meta_storage.set_save_point(&chain_id, head.clone())?;
             chain_meta_storage.set_current_head(&chain_id, head)?;
 
             Ok(block_json_data)
@@ -403,18 +472,32 @@ pub fn initialize_storage_with_genesis_block(
 
 /// Helper module to easily initialize databases
 pub mod initializer {
-    use std::path::PathBuf;
-    use std::sync::Arc;
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc::{self, RecvTimeoutError};
+    use std::sync::{Arc, RwLock};
+    use std::thread;
+    use std::time::{Duration, Instant};
 
     use rocksdb::{Cache, ColumnFamilyDescriptor, DB};
-    use slog::{error, Logger};
+    use slog::{error, info, warn, Logger};
 
     use crypto::hash::ChainId;
 
+    use crate::context::gc::incremental_gced::IncrementalGCed;
+    use crate::context::gc::refcount_gced::RefCountGCed;
+    use crate::context::gc::SupportedContextGarbageCollector;
+    use crate::context::merkle::entry_cache::EntryCache;
     use crate::context::merkle::merkle_storage::MerkleStorage;
-    use crate::persistent::database::{open_kv, RocksDbKeyValueSchema};
-    use crate::persistent::{DBError, DbConfiguration};
-    use crate::{StorageError, SystemStorage};
+    use crate::context::ContextKeyValueStore;
+    use crate::persistent::database::{
+        open_kv, open_kv_as_secondary, ColumnFamiliesTuning, RocksDbKeyValueSchema,
+    };
+    use crate::persistent::sequence::Sequences;
+    use crate::persistent::{open_cl_read_only, CommitLogSchema, DBError, DbConfiguration};
+    use crate::{
+        BlockMetaStorage, BlockStorage, BlockStorageReader, OperationsMetaStorage,
+        OperationsStorage, OperationsStorageReader, PersistentStorage, StorageError, SystemStorage,
+    };
 
     // IMPORTANT: Cache object must live at least as long as DB (returned by open_kv)
     pub type GlobalRocksDbCacheHolder = Vec<RocksDbCache>;
@@ -422,7 +505,14 @@ pub mod initializer {
 
     /// Factory for creation of grouped column family descriptors
     pub trait RocksDbColumnFactory {
-        fn create(&self, cache: &RocksDbCache) -> Vec<ColumnFamilyDescriptor>;
+        fn create(
+            &self,
+            cache: &RocksDbCache,
+            tuning: &ColumnFamiliesTuning,
+        ) -> Vec<ColumnFamilyDescriptor>;
+
+        /// Names of the created column families (used for secondary instance)
+        fn column_families(&self) -> Vec<&'static str>;
     }
 
     /// Tables initializer for all operational datbases
@@ -438,40 +528,92 @@ pub mod initializer {
     pub struct ContextActionsRocksDbTableInitializer;
 
     impl RocksDbColumnFactory for ContextRocksDbTableInitializer {
-        fn create(&self, cache: &RocksDbCache) -> Vec<ColumnFamilyDescriptor> {
+        fn create(
+            &self,
+            cache: &RocksDbCache,
+            tuning: &ColumnFamiliesTuning,
+        ) -> Vec<ColumnFamilyDescriptor> {
             vec![
-                crate::SystemStorage::descriptor(cache),
-                crate::context::kv_store::rocksdb_backend::RocksDBBackend::descriptor(cache),
+                tuning.descriptor::<crate::SystemStorage>(cache),
+                tuning
+                    .descriptor::<crate::context::kv_store::rocksdb_backend::RocksDBBackend>(cache),
+            ]
+        }
+
+        fn column_families(&self) -> Vec<&'static str> {
+            vec![
+                crate::SystemStorage::name(),
+                crate::context::kv_store::rocksdb_backend::RocksDBBackend::name(),
             ]
         }
     }
 
     impl RocksDbColumnFactory for DbsRocksDbTableInitializer {
-        fn create(&self, cache: &RocksDbCache) -> Vec<ColumnFamilyDescriptor> {
+        fn create(
+            &self,
+            cache: &RocksDbCache,
+            tuning: &ColumnFamiliesTuning,
+        ) -> Vec<ColumnFamilyDescriptor> {
             vec![
-                crate::block_storage::BlockPrimaryIndex::descriptor(cache),
-                crate::block_storage::BlockByLevelIndex::descriptor(cache),
-                crate::block_storage::BlockByContextHashIndex::descriptor(cache),
-                crate::BlockMetaStorage::descriptor(cache),
-                crate::OperationsStorage::descriptor(cache),
-                crate::OperationsMetaStorage::descriptor(cache),
-                crate::SystemStorage::descriptor(cache),
-                crate::persistent::sequence::Sequences::descriptor(cache),
-                crate::MempoolStorage::descriptor(cache),
-                crate::ChainMetaStorage::descriptor(cache),
-                crate::PredecessorStorage::descriptor(cache),
+                tuning.descriptor::<crate::block_storage::BlockPrimaryIndex>(cache),
+                tuning.descriptor::<crate::block_storage::BlockByLevelIndex>(cache),
+                tuning.descriptor::<crate::block_storage::BlockByContextHashIndex>(cache),
+                tuning.descriptor::<crate::BlockMetaStorage>(cache),
+                tuning.descriptor::<crate::OperationsStorage>(cache),
+                tuning.descriptor::<crate::OperationsMetaStorage>(cache),
+                tuning.descriptor::<crate::SystemStorage>(cache),
+                tuning.descriptor::<crate::persistent::sequence::Sequences>(cache),
+                tuning.descriptor::<crate::MempoolStorage>(cache),
+                tuning.descriptor::<crate::ChainMetaStorage>(cache),
+                tuning.descriptor::<crate::PredecessorStorage>(cache),
+                tuning.descriptor::<crate::PeerStorage>(cache),
+                tuning.descriptor::<crate::ProtocolStorage>(cache),
+            ]
+        }
+
+        fn column_families(&self) -> Vec<&'static str> {
+            vec![
+                crate::block_storage::BlockPrimaryIndex::name(),
+                crate::block_storage::BlockByLevelIndex::name(),
+                crate::block_storage::BlockByContextHashIndex::name(),
+                crate::BlockMetaStorage::name(),
+                crate::OperationsStorage::name(),
+                crate::OperationsMetaStorage::name(),
+                crate::SystemStorage::name(),
+                crate::persistent::sequence::Sequences::name(),
+                crate::MempoolStorage::name(),
+                crate::ChainMetaStorage::name(),
+                crate::PredecessorStorage::name(),
+                crate::PeerStorage::name(),
+                crate::ProtocolStorage::name(),
             ]
         }
     }
 
     impl RocksDbColumnFactory for ContextActionsRocksDbTableInitializer {
-        fn create(&self, cache: &RocksDbCache) -> Vec<ColumnFamilyDescriptor> {
+        fn create(
+            &self,
+            cache: &RocksDbCache,
+            tuning: &ColumnFamiliesTuning,
+        ) -> Vec<ColumnFamilyDescriptor> {
+            vec![
+                tuning.descriptor::<crate::SystemStorage>(cache),
+                tuning.descriptor::<crate::context::actions::context_action_storage::ContextActionByBlockHashIndex>(cache),
+                tuning.descriptor::<crate::context::actions::context_action_storage::ContextActionByContractIndex>(cache),
+                tuning.descriptor::<crate::context::actions::context_action_storage::ContextActionByTypeIndex>(cache),
+                tuning.descriptor::<crate::context::actions::context_action_storage::ContextActionByKeyPrefixIndex>(cache),
+                tuning.descriptor::<crate::context::actions::context_action_storage::ContextActionStorage>(cache),
+            ]
+        }
+
+        fn column_families(&self) -> Vec<&'static str> {
             vec![
-                crate::SystemStorage::descriptor(cache),
-                crate::context::actions::context_action_storage::ContextActionByBlockHashIndex::descriptor(cache),
-                crate::context::actions::context_action_storage::ContextActionByContractIndex::descriptor(cache),
-                crate::context::actions::context_action_storage::ContextActionByTypeIndex::descriptor(cache),
-                crate::context::actions::context_action_storage::ContextActionStorage::descriptor(cache),
+                crate::SystemStorage::name(),
+                crate::context::actions::context_action_storage::ContextActionByBlockHashIndex::name(),
+                crate::context::actions::context_action_storage::ContextActionByContractIndex::name(),
+                crate::context::actions::context_action_storage::ContextActionByTypeIndex::name(),
+                crate::context::actions::context_action_storage::ContextActionByKeyPrefixIndex::name(),
+                crate::context::actions::context_action_storage::ContextActionStorage::name(),
             ]
         }
     }
@@ -483,6 +625,7 @@ pub mod initializer {
         pub db_path: PathBuf,
         pub columns: C,
         pub threads: Option<usize>,
+        pub column_families_tuning: ColumnFamiliesTuning,
     }
 
     #[derive(Debug, Clone)]
@@ -491,6 +634,34 @@ pub mod initializer {
         Sled { path: PathBuf },
         InMem,
         BTreeMap,
+        PackFile { path: PathBuf },
+    }
+
+    impl ContextKvStoreConfiguration {
+        /// Directory of the persistent store, `None` for in-memory stores
+        pub fn path(&self) -> Option<&PathBuf> {
+            match self {
+                ContextKvStoreConfiguration::RocksDb(cfg) => Some(&cfg.db_path),
+                ContextKvStoreConfiguration::Sled { path } => Some(path),
+                ContextKvStoreConfiguration::PackFile { path } => Some(path),
+                ContextKvStoreConfiguration::InMem | ContextKvStoreConfiguration::BTreeMap => None,
+            }
+        }
+
+        /// Directory with state of the `context_gc` collector (next to the persistent store),
+        /// `None` for in-memory stores, pack files and no collector
+        pub fn gc_path(&self, context_gc: &SupportedContextGarbageCollector) -> Option<PathBuf> {
+            let suffix = match (self, context_gc) {
+                (ContextKvStoreConfiguration::PackFile { .. }, _)
+                | (_, SupportedContextGarbageCollector::None) => return None,
+                (_, SupportedContextGarbageCollector::Incremental { .. }) => "_incremental_gc",
+                (_, SupportedContextGarbageCollector::RefCount { .. }) => "_refcount_gc",
+            };
+            let path = self.path()?;
+            let mut dir_name = path.file_name().unwrap_or_default().to_os_string();
+            dir_name.push(suffix);
+            Some(path.with_file_name(dir_name))
+        }
     }
 
     pub fn initialize_rocksdb<Factory: RocksDbColumnFactory>(
@@ -501,13 +672,19 @@ pub mod initializer {
     ) -> Result<Arc<DB>, DBError> {
         let db = open_kv(
             &config.db_path,
-            config.columns.create(cache),
+            config.columns.create(cache, &config.column_families_tuning),
             &DbConfiguration {
                 max_threads: config.threads,
             },
         )
         .map(Arc::new)?;
 
+        for column_family in config.column_families_tuning.column_families() {
+            if db.cf_handle(column_family).is_none() {
+                warn!(log, "Tuning of unknown column family is ignored"; "column_family" => column_family, "db_path" => config.db_path.to_str());
+            }
+        }
+
         match check_database_compatibility(
             db.clone(),
             config.expected_db_version,
@@ -595,13 +772,253 @@ pub mod initializer {
         Ok(db_version_ok && chain_id_ok)
     }
 
+    /// Statistics of [recover_storage]
+    #[derive(Debug, Default, Clone, PartialEq)]
+    pub struct StorageRecoveryStats {
+        /// Count of blocks with missing metadata, which were created again
+        pub repaired_blocks: usize,
+        /// Count of blocks with unreadable header, which were removed and will be downloaded again
+        pub rolled_back_blocks: usize,
+    }
+
+    /// Detects blocks partially written before crash (see [crate::store_block_header]) and repairs or rolls them back:
+    /// - block header is not readable from commit log - block is removed from block storage and marked as not downloaded
+    /// - block metadata or operations metadata are missing - they are created again from the stored block header and operations
+    ///
+    /// Just blocks stored after the checkpoint of the previous recovery (commit log offset) are checked,
+    /// new checkpoint is stored when recovery finishes.
+    ///
+    /// Also finishes interrupted commit log compaction. Has to be called before shell is started.
+    pub fn recover_storage(
+        persistent_storage: &PersistentStorage,
+        chain_id: &ChainId,
+        log: &Logger,
+    ) -> Result<StorageRecoveryStats, StorageError> {
+        let started = Instant::now();
+        let block_storage = BlockStorage::new(persistent_storage);
+        let block_meta_storage = BlockMetaStorage::new(persistent_storage);
+        let operations_storage = OperationsStorage::new(persistent_storage);
+        let operations_meta_storage = OperationsMetaStorage::new(persistent_storage);
+        let mut stats = StorageRecoveryStats::default();
+
+        // compaction moves checkpoint too, so it is read after compaction is finished
+        block_storage.recover_commit_log_compaction()?;
+        let mut system_storage = SystemStorage::new(persistent_storage.db());
+        let checkpoint = system_storage
+            .get_storage_recovery_checkpoint()?
+            .unwrap_or(0);
+
+        // roll back blocks, which header was lost
+        for block_hash in block_storage.remove_unreadable_blocks(checkpoint)? {
+            if !block_meta_storage.reset_downloaded(&block_hash)? {
+                // applied block cannot be downloaded again, just report it
+                error!(log, "Header of applied block is missing in block storage"; "block_hash" => block_hash.to_base58_check());
+            }
+            stats.rolled_back_blocks += 1;
+        }
+
+        // repair metadata of stored blocks
+        for block_hash in block_storage.block_hashes_stored_from(checkpoint)? {
+            let is_block_meta_missing = match block_meta_storage.get(&block_hash)? {
+                Some(meta) => !meta.is_downloaded(),
+                None => true,
+            };
+            let is_operations_meta_missing = !operations_meta_storage.contains(&block_hash)?;
+            if !is_block_meta_missing && !is_operations_meta_missing {
+                continue;
+            }
+
+            let block_header = match block_storage.get(&block_hash)? {
+                Some(block_header) => block_header,
+                None => continue,
+            };
+            if block_header.header.predecessor() == &block_header.hash {
+                // genesis metadata are stored by commit_genesis
+                continue;
+            }
+
+            if is_block_meta_missing {
+                block_meta_storage.put_block_header(&block_header, chain_id, log)?;
+            }
+            if is_operations_meta_missing {
+                operations_meta_storage.put_block_header(&block_header, chain_id.clone())?;
+                for operations in operations_storage.get_operations(&block_hash)? {
+                    operations_meta_storage.put_operations(&operations)?;
+                }
+            }
+            warn!(log, "Repaired partially stored block"; "block_hash" => block_hash.to_base58_check());
+            stats.repaired_blocks += 1;
+        }
+
+        let new_checkpoint = block_storage.commit_log_next_offset()?;
+        system_storage.set_storage_recovery_checkpoint(new_checkpoint)?;
+
+        info!(log, "Storage recovery finished";
+                   "checkpoint" => checkpoint,
+                   "new_checkpoint" => new_checkpoint,
+                   "repaired_blocks" => stats.repaired_blocks,
+                   "rolled_back_blocks" => stats.rolled_back_blocks,
+                   "elapsed" => format!("{:?}", started.elapsed()));
+        Ok(stats)
+    }
+
+    /// Opens storage of the node (primary instance) in secondary (read-only) mode, so other processes
+    /// (rpc, indexers) can read it in parallel with the running node. Configurations are the same as the node uses.
+    ///
+    /// Changes written by the node become visible after [PersistentStorage::sync_with_primary],
+    /// which can be called periodically by [SecondaryStorageSync].
+    ///
+    /// The rocksdb crate opens column families of a secondary instance just by names (without merge operators),
+    /// so values of [BlockMetaStorage] and [OperationsMetaStorage] with merge operands not yet compacted
+    /// by the node cannot be read, reading them fails with error recognized by [DBError::is_merge_operator_missing].
+    /// Commit log is opened read-only, records appended by the node are loaded when they are requested.
+    /// Context is available only in persistent (RocksDB) store, in-memory stores of the node cannot be shared.
+    ///
+    /// * `commit_logs_path` - directory of the block header commit log (bootstrap db path of the node)
+    /// * `secondary_path` - own directory of the secondary instance, has to be unique for every secondary instance
+    pub fn initialize_secondary_storage(
+        db: &RocksDbConfig<DbsRocksDbTableInitializer>,
+        commit_logs_path: &Path,
+        context_kv_store: &ContextKvStoreConfiguration,
+        context_actions_store: Option<&RocksDbConfig<ContextActionsRocksDbTableInitializer>>,
+        secondary_path: &Path,
+    ) -> Result<PersistentStorage, failure::Error> {
+        let kv = open_secondary_rocksdb(db, &secondary_path.join("db"))?;
+        let commit_logs = Arc::new(open_cl_read_only(
+            commit_logs_path,
+            vec![BlockStorage::descriptor()],
+        )?);
+        let sequences = Arc::new(Sequences::new(kv.clone(), 1000));
+
+        let mut context_db = None;
+        let kv_store: Box<ContextKeyValueStore> = match context_kv_store {
+            ContextKvStoreConfiguration::RocksDb(cfg) => {
+                let kv_context = open_secondary_rocksdb(cfg, &secondary_path.join("context"))?;
+                context_db = Some(kv_context.clone());
+                Box::new(crate::context::kv_store::rocksdb_backend::RocksDBBackend::new(kv_context))
+            }
+            ContextKvStoreConfiguration::InMem
+            | ContextKvStoreConfiguration::BTreeMap
+            | ContextKvStoreConfiguration::Sled { .. }
+            | ContextKvStoreConfiguration::PackFile { .. } => {
+                return Err(failure::format_err!(
+                    "Context kv store {:?} cannot be opened as secondary instance",
+                    context_kv_store
+                ))
+            }
+        };
+
+        let merkle_context_actions = match context_actions_store {
+            Some(cfg) => Some(open_secondary_rocksdb(
+                cfg,
+                &secondary_path.join("context_actions"),
+            )?),
+            None => None,
+        };
+
+        Ok(PersistentStorage::new(
+            kv,
+            commit_logs,
+            sequences,
+            Arc::new(RwLock::new(MerkleStorage::new(kv_store))),
+            merkle_context_actions,
+        )
+        .with_context_db(context_db)
+        .into_secondary())
+    }
+
+    fn open_secondary_rocksdb<Factory: RocksDbColumnFactory>(
+        config: &RocksDbConfig<Factory>,
+        secondary_path: &Path,
+    ) -> Result<Arc<DB>, DBError> {
+        open_kv_as_secondary(
+            &config.db_path,
+            secondary_path,
+            config.columns.column_families(),
+            &DbConfiguration {
+                max_threads: config.threads,
+            },
+        )
+        .map(Arc::new)
+    }
+
+    /// Background thread, which periodically catches up secondary storage with the primary instance
+    pub struct SecondaryStorageSync {
+        /// Dropped to stop the thread
+        stop: Option<mpsc::Sender<()>>,
+        thread: Option<thread::JoinHandle<()>>,
+    }
+
+    impl SecondaryStorageSync {
+        pub fn start(
+            persistent_storage: PersistentStorage,
+            interval: Duration,
+            log: Logger,
+        ) -> Result<Self, std::io::Error> {
+            let (stop, stop_rx) = mpsc::channel();
+            let thread = thread::Builder::new()
+                .name("secondary-storage-sync".to_string())
+                .spawn(move || {
+                    while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(interval) {
+                        if let Err(e) = persistent_storage.sync_with_primary() {
+                            warn!(log, "Failed to sync secondary storage with primary"; "reason" => e);
+                        }
+                    }
+                })?;
+
+            Ok(Self {
+                stop: Some(stop),
+                thread: Some(thread),
+            })
+        }
+    }
+
+    impl Drop for SecondaryStorageSync {
+        fn drop(&mut self) {
+            drop(self.stop.take());
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+
     pub fn initialize_merkle(
         context_kv_store: &ContextKvStoreConfiguration,
+        context_gc: &SupportedContextGarbageCollector,
+        entry_cache_capacity: usize,
         expected_main_chain: &MainChain,
         log: &Logger,
         caches: &mut GlobalRocksDbCacheHolder,
     ) -> Result<MerkleStorage, failure::Error> {
-        Ok(MerkleStorage::new(match context_kv_store {
+        initialize_merkle_with_db(
+            context_kv_store,
+            context_gc,
+            entry_cache_capacity,
+            expected_main_chain,
+            log,
+            caches,
+        )
+        .map(|(merkle, _)| merkle)
+    }
+
+    /// Same as [initialize_merkle], returns also the context RocksDB (if configured), e.g. for statistics
+    pub fn initialize_merkle_with_db(
+        context_kv_store: &ContextKvStoreConfiguration,
+        context_gc: &SupportedContextGarbageCollector,
+        entry_cache_capacity: usize,
+        expected_main_chain: &MainChain,
+        log: &Logger,
+        caches: &mut GlobalRocksDbCacheHolder,
+    ) -> Result<(MerkleStorage, Option<Arc<DB>>), failure::Error> {
+        // decoded entries cache is shared by all readers and the garbage collector
+        let merkle = |kv_store: Box<ContextKeyValueStore>| {
+            MerkleStorage::with_entry_cache(
+                kv_store,
+                Arc::new(EntryCache::new(entry_cache_capacity)),
+            )
+        };
+        let mut context_db = None;
+        let kv_store: Box<ContextKeyValueStore> = match context_kv_store {
             ContextKvStoreConfiguration::RocksDb(cfg) => {
                 let kv_context_cache = Cache::new_lru_cache(cfg.cache_size)
                     .expect("Failed to initialize RocksDB cache (db_context)");
@@ -609,6 +1026,7 @@ pub mod initializer {
                     initialize_rocksdb(&log, &kv_context_cache, cfg, expected_main_chain)
                         .expect("Failed to create/initialize RocksDB database (db_context)");
                 caches.push(kv_context_cache);
+                context_db = Some(kv_context.clone());
                 Box::new(crate::context::kv_store::rocksdb_backend::RocksDBBackend::new(kv_context))
             }
             ContextKvStoreConfiguration::Sled { path } => {
@@ -626,7 +1044,56 @@ pub mod initializer {
             ContextKvStoreConfiguration::BTreeMap => {
                 Box::new(crate::context::kv_store::btree_map::BTreeMapBackend::new())
             }
-        }))
+            ContextKvStoreConfiguration::PackFile { path } => {
+                // pack files are collected by the store itself (whole packs), not by the configured collector
+                let retained_cycles = context_gc.retained_cycles();
+                if let Some(retained_cycles) = retained_cycles {
+                    info!(log, "Context garbage collection enabled";
+                               "gc" => "pack",
+                               "retained_cycles" => retained_cycles);
+                }
+                return Ok((
+                    merkle(Box::new(
+                        crate::context::kv_store::pack_file_backend::PackFileBackend::new(
+                            path,
+                            retained_cycles,
+                        )
+                        .expect("Failed to create/initialize pack file store (db_context)"),
+                    )),
+                    None,
+                ));
+            }
+        };
+
+        // state of the collector is kept next to the persistent store, in-memory store has temporary one
+        let gc_db = || {
+            match context_kv_store.gc_path(context_gc) {
+                Some(path) => sled::Config::new().path(path),
+                None => sled::Config::new().temporary(true),
+            }
+            .open()
+        };
+        let kv_store: Box<ContextKeyValueStore> = match context_gc {
+            SupportedContextGarbageCollector::None => kv_store,
+            SupportedContextGarbageCollector::Incremental { retained_cycles } => {
+                let db = gc_db().expect(
+                    "Failed to create/initialize Sled database (db_context_incremental_gc)",
+                );
+                info!(log, "Context garbage collection enabled";
+                           "gc" => "incremental",
+                           "retained_cycles" => retained_cycles);
+                Box::new(IncrementalGCed::new(kv_store, db, *retained_cycles)?)
+            }
+            SupportedContextGarbageCollector::RefCount { retained_cycles } => {
+                let refcounts = gc_db()
+                    .expect("Failed to create/initialize Sled database (db_context_refcount_gc)");
+                info!(log, "Context garbage collection enabled";
+                           "gc" => "refcount",
+                           "retained_cycles" => retained_cycles);
+                Box::new(RefCountGCed::new(kv_store, refcounts, *retained_cycles)?)
+            }
+        };
+        Ok((merkle(kv_store), context_db))
     }
 }
 
@@ -642,6 +1109,10 @@ pub struct PersistentStorage {
     merkle: Arc<RwLock<MerkleStorage>>,
     /// persistent context actions storage
     merkle_context_actions: Option<Arc<DB>>,
+    /// context RocksDB (if context is stored in RocksDB), accessed directly just for statistics
+    context_db: Option<Arc<DB>>,
+    /// opened read-only by [initializer::initialize_secondary_storage]
+    secondary: bool,
 }
 
 impl PersistentStorage {
@@ -658,7 +1129,50 @@ impl PersistentStorage {
             seq,
             merkle,
             merkle_context_actions,
+            context_db: None,
+            secondary: false,
+        }
+    }
+
+    pub fn with_context_db(mut self, context_db: Option<Arc<DB>>) -> Self {
+        self.context_db = context_db;
+        self
+    }
+
+    fn into_secondary(mut self) -> Self {
+        self.secondary = true;
+        self
+    }
+
+    #[inline]
+    pub fn is_secondary(&self) -> bool {
+        self.secondary
+    }
+
+    /// Catches up storage opened by [initializer::initialize_secondary_storage] with changes written by the node
+    pub fn sync_with_primary(&self) -> Result<(), StorageError> {
+        if !self.secondary {
+            return Err(StorageError::SyncWithPrimaryError {
+                reason: "storage is not opened as secondary instance".to_string(),
+            });
+        }
+
+        self.db.try_catch_up_with_primary().map_err(DBError::from)?;
+        if let Some(merkle_context_actions) = self.merkle_context_actions.as_ref() {
+            merkle_context_actions
+                .try_catch_up_with_primary()
+                .map_err(DBError::from)?;
         }
+        self.merkle
+            .read()
+            .map_err(|e| StorageError::SyncWithPrimaryError {
+                reason: format!("Failed to lock merkle storage, reason: {}", e),
+            })?
+            .sync_with_primary()?;
+        // commit log is refreshed as the last one, so it contains all records referenced by the db
+        // (appended records are loaded on demand, just commit logs replaced by compaction are opened again)
+        self.clog.refresh_compacted()?;
+        Ok(())
     }
 
     #[inline]
@@ -686,7 +1200,16 @@ impl PersistentStorage {
         self.merkle_context_actions.clone()
     }
 
+    #[inline]
+    pub fn context_db(&self) -> Option<Arc<DB>> {
+        self.context_db.clone()
+    }
+
     pub fn flush_dbs(&mut self) {
+        if self.secondary {
+            // secondary instance is read-only
+            return;
+        }
         let clog = self.clog.flush();
         let db = self.db.flush();
         let merkle = match self.merkle.write() {
@@ -783,6 +1306,8 @@ pub mod tests_common {
                     MempoolStorage::descriptor(&db_cache),
                     ChainMetaStorage::descriptor(&db_cache),
                     PredecessorStorage::descriptor(&db_cache),
+                    PeerStorage::descriptor(&db_cache),
+                    ProtocolStorage::descriptor(&db_cache),
                 ],
                 &cfg,
             )?);
@@ -791,7 +1316,10 @@ pub mod tests_common {
             let db_context_cache = Cache::new_lru_cache(64 * 1024 * 1024)?; // 64 MB
             let context_kv_store = RocksDBBackend::new(Arc::new(open_kv(
                 path.join("context"),
-                vec![RocksDBBackend::descriptor(&db_context_cache)],
+                vec![
+                    SystemStorage::descriptor(&db_context_cache),
+                    RocksDBBackend::descriptor(&db_context_cache),
+                ],
                 &cfg,
             )?));
             let merkle = MerkleStorage::new(Box::new(context_kv_store));
@@ -801,6 +1329,7 @@ pub mod tests_common {
             let kv_context_action = open_kv(
                 path.join("context_actions"),
                 vec![
+                    SystemStorage::descriptor(&db_context_actions_cache),
                     context_action_storage::ContextActionStorage::descriptor(
                         &db_context_actions_cache,
                     ),
@@ -813,6 +1342,9 @@ pub mod tests_common {
                     context_action_storage::ContextActionByTypeIndex::descriptor(
                         &db_context_actions_cache,
                     ),
+                    context_action_storage::ContextActionByKeyPrefixIndex::descriptor(
+                        &db_context_actions_cache,
+                    ),
                 ],
                 &cfg,
             )?;
@@ -170,6 +170,19 @@ impl MempoolStorage {
         }
         Ok(operations)
     }
+
+    /// Returns all stored operations together with their time to live
+    #[inline]
+    pub fn iter_with_ttl(
+        &self,
+    ) -> Result<Vec<(OperationHash, OperationMessage, SystemTime)>, StorageError> {
+        let mut operations = Vec::new();
+        for (key, value) in self.kv.iterator(IteratorMode::Start)? {
+            let (key, value) = (key?, value?);
+            operations.push((key.operation_hash, value.operation, value.time_to_live));
+        }
+        Ok(operations)
+    }
 }
 
 impl KeyValueSchema for MempoolStorage {
@@ -12,7 +12,7 @@ use tezos_messages::p2p::encoding::block_header::Level;
 use tezos_messages::p2p::encoding::prelude::*;
 
 use crate::persistent::database::{
-    default_table_options, IteratorMode, IteratorWithSchema, RocksDbKeyValueSchema,
+    ColumnFamilyTuning, IteratorMode, IteratorWithSchema, RocksDbKeyValueSchema, SchemaWriteBatch,
 };
 use crate::persistent::{Decoder, Encoder, KeyValueSchema, KeyValueStoreWithSchema, SchemaError};
 use crate::{num_from_slice, PersistentStorage};
@@ -56,6 +56,22 @@ impl OperationsMetaStorage {
             .and(Ok((meta.is_complete, meta.get_missing_validation_passes())))
     }
 
+    /// The same as [put_block_header], but metadata are just added to the `batch`
+    pub(crate) fn put_block_header_to_batch(
+        &self,
+        block_header: &BlockHeaderWithHash,
+        chain_id: ChainId,
+        batch: &mut SchemaWriteBatch,
+    ) -> Result<(bool, Option<HashSet<u8>>), StorageError> {
+        let meta = Meta::new(
+            block_header.header.validation_pass(),
+            block_header.header.level(),
+            chain_id,
+        );
+        batch.merge::<Self>(&block_header.hash, &meta)?;
+        Ok((meta.is_complete, meta.get_missing_validation_passes()))
+    }
+
     /// Stores operation validation_passes metadata and check if is_complete
     ///
     /// Returns tuple:
@@ -110,6 +126,11 @@ impl OperationsMetaStorage {
         self.kv.contains(block_hash).map_err(StorageError::from)
     }
 
+    #[inline]
+    pub fn delete(&self, block_hash: &BlockHash) -> Result<(), StorageError> {
+        self.kv.delete(block_hash).map_err(StorageError::from)
+    }
+
     #[inline]
     pub fn iter(&self, mode: IteratorMode<Self>) -> Result<IteratorWithSchema<Self>, StorageError> {
         self.kv.iterator(mode).map_err(StorageError::from)
@@ -122,8 +143,11 @@ impl KeyValueSchema for OperationsMetaStorage {
 }
 
 impl RocksDbKeyValueSchema for OperationsMetaStorage {
-    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        let mut cf_opts = default_table_options(cache);
+    fn descriptor_with_tuning(
+        cache: &Cache,
+        tuning: &ColumnFamilyTuning,
+    ) -> ColumnFamilyDescriptor {
+        let mut cf_opts = tuning.table_options(cache);
         cf_opts.set_merge_operator(
             "operations_meta_storage_merge_operator",
             merge_meta_value,
@@ -10,7 +10,7 @@ use crypto::hash::{BlockHash, HashType};
 use tezos_messages::p2p::binary_message::BinaryMessage;
 use tezos_messages::p2p::encoding::prelude::*;
 
-use crate::persistent::database::{default_table_options, RocksDbKeyValueSchema};
+use crate::persistent::database::{ColumnFamilyTuning, RocksDbKeyValueSchema};
 use crate::persistent::{Decoder, Encoder, KeyValueSchema, KeyValueStoreWithSchema, SchemaError};
 use crate::{PersistentStorage, StorageError};
 
@@ -54,6 +54,15 @@ impl OperationsStorage {
     ) -> Result<(), StorageError> {
         self.kv.put(key, value).map_err(StorageError::from)
     }
+
+    /// Removes operations of all validation passes for the block
+    pub fn delete_operations(&self, block_hash: &BlockHash) -> Result<(), StorageError> {
+        let key = OperationKey::new(block_hash, 0);
+        for (key, _) in self.kv.prefix_iterator(&key)? {
+            self.kv.delete(&key?).map_err(StorageError::from)?;
+        }
+        Ok(())
+    }
 }
 
 impl OperationsStorageReader for OperationsStorage {
@@ -89,8 +98,11 @@ impl KeyValueSchema for OperationsStorage {
 }
 
 impl RocksDbKeyValueSchema for OperationsStorage {
-    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        let mut cf_opts = default_table_options(cache);
+    fn descriptor_with_tuning(
+        cache: &Cache,
+        tuning: &ColumnFamilyTuning,
+    ) -> ColumnFamilyDescriptor {
+        let mut cf_opts = tuning.table_options(cache);
         cf_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(
             HashType::BlockHash.size(),
         ));
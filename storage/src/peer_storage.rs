@@ -0,0 +1,260 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Persistent information about p2p points (ip:port) - when the point was seen or failed last time,
+//! reputation score and ban, so the peer selection and bans survive restart of the node.
+//! Count of stored points is limited by the peer manager, which evicts the least valuable ones.
+
+use std::cmp;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Serialize};
+
+use crate::persistent::database::RocksDbKeyValueSchema;
+use crate::persistent::{BincodeEncoded, KeyValueSchema, KeyValueStoreWithSchema};
+use crate::{IteratorMode, PersistentStorage, StorageError};
+
+pub type PeerStorageKV = dyn KeyValueStoreWithSchema<PeerStorage> + Sync + Send;
+
+/// Storage of the known p2p points
+#[derive(Clone)]
+pub struct PeerStorage {
+    kv: Arc<PeerStorageKV>,
+}
+
+impl PeerStorage {
+    pub fn new(persistent_storage: &PersistentStorage) -> Self {
+        Self {
+            kv: persistent_storage.db(),
+        }
+    }
+
+    #[inline]
+    pub fn put(&self, point: &SocketAddr, info: &PointInfo) -> Result<(), StorageError> {
+        self.kv.put(point, info).map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn get(&self, point: &SocketAddr) -> Result<Option<PointInfo>, StorageError> {
+        self.kv.get(point).map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn delete(&self, point: &SocketAddr) -> Result<(), StorageError> {
+        self.kv.delete(point).map_err(StorageError::from)
+    }
+
+    /// Applies `update` to the stored info of the point (or to the new one, if point is not known yet)
+    /// and stores the result
+    pub fn update<F: FnOnce(&mut PointInfo)>(
+        &self,
+        point: &SocketAddr,
+        update: F,
+    ) -> Result<PointInfo, StorageError> {
+        let mut info = self.get(point)?.unwrap_or_default();
+        update(&mut info);
+        self.put(point, &info)?;
+        Ok(info)
+    }
+
+    /// Returns all known points
+    pub fn iter(&self) -> Result<Vec<(SocketAddr, PointInfo)>, StorageError> {
+        let mut points = Vec::new();
+        for (key, value) in self.kv.iterator(IteratorMode::Start)? {
+            points.push((key?, value?));
+        }
+        Ok(points)
+    }
+}
+
+impl KeyValueSchema for PeerStorage {
+    type Key = SocketAddr;
+    type Value = PointInfo;
+}
+
+impl RocksDbKeyValueSchema for PeerStorage {
+    #[inline]
+    fn name() -> &'static str {
+        "peer_storage"
+    }
+}
+
+impl BincodeEncoded for SocketAddr {}
+
+/// Ban of the point
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PointBan {
+    pub reason: String,
+    pub until: SystemTime,
+}
+
+/// Information about p2p point
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Getters, CopyGetters)]
+pub struct PointInfo {
+    /// Last time of successfully bootstrapped connection
+    #[get_copy = "pub"]
+    last_seen: Option<SystemTime>,
+    /// Last time of failed connection
+    #[get_copy = "pub"]
+    last_failed: Option<SystemTime>,
+    /// Count of failed connections since the last successful one
+    #[get_copy = "pub"]
+    failures: u32,
+    /// Reputation of the point, see [PointInfo::MIN_SCORE], [PointInfo::MAX_SCORE]
+    #[get_copy = "pub"]
+    score: i32,
+    #[get = "pub"]
+    ban: Option<PointBan>,
+    /// Trusted point is never banned and is always kept between potential peers
+    #[get_copy = "pub"]
+    trusted: bool,
+    /// Peer id (base58 encoded public key hash) of the last peer connected from/to the point,
+    /// so the peer can be found by its id also when it is disconnected
+    #[get = "pub"]
+    peer_id: Option<String>,
+}
+
+impl PointInfo {
+    pub const MIN_SCORE: i32 = -100;
+    pub const MAX_SCORE: i32 = 100;
+
+    const SCORE_CONNECTED: i32 = 5;
+    const SCORE_FAILED: i32 = -10;
+    const SCORE_BANNED: i32 = -50;
+
+    /// Point was successfully connected and bootstrapped
+    pub fn connected(&mut self, now: SystemTime) {
+        self.last_seen = Some(now);
+        self.failures = 0;
+        self.add_score(Self::SCORE_CONNECTED);
+    }
+
+    /// Connection to the point failed
+    pub fn failed(&mut self, now: SystemTime) {
+        self.last_failed = Some(now);
+        self.failures = self.failures.saturating_add(1);
+        self.add_score(Self::SCORE_FAILED);
+    }
+
+    /// Bans the point, trusted point cannot be banned
+    pub fn banned(&mut self, reason: String, until: SystemTime) {
+        if self.trusted {
+            return;
+        }
+        self.ban = Some(PointBan { reason, until });
+        self.add_score(Self::SCORE_BANNED);
+    }
+
+    pub fn unbanned(&mut self) {
+        self.ban = None;
+    }
+
+    /// Marks the point as trusted, removes its ban
+    pub fn trust(&mut self) {
+        self.trusted = true;
+        self.ban = None;
+    }
+
+    pub fn untrust(&mut self) {
+        self.trusted = false;
+    }
+
+    /// Peer with the `peer_id` was connected from/to the point
+    pub fn identified(&mut self, peer_id: String) {
+        self.peer_id = Some(peer_id);
+    }
+
+    /// Peer of the point was identified on other point
+    pub fn forget_peer_id(&mut self) {
+        self.peer_id = None;
+    }
+
+    /// Returns true, if point has ban, which is not expired at `now`
+    pub fn is_banned(&self, now: SystemTime) -> bool {
+        self.ban.as_ref().map_or(false, |ban| ban.until > now)
+    }
+
+    /// Returns time of the next connection attempt - exponential backoff `base * 2^(failures - 1)` (limited by `max`)
+    /// from the last failure, or `None` if there is no failure since the last successful connection (or point is trusted)
+    pub fn next_connection_attempt(&self, base: Duration, max: Duration) -> Option<SystemTime> {
+        if self.failures == 0 || self.trusted {
+            return None;
+        }
+        let last_failed = self.last_failed?;
+        let backoff = base
+            .checked_mul(1 << cmp::min(self.failures - 1, 16))
+            .map_or(max, |backoff| cmp::min(backoff, max));
+        Some(last_failed + backoff)
+    }
+
+    fn add_score(&mut self, delta: i32) {
+        self.score = cmp::max(
+            Self::MIN_SCORE,
+            cmp::min(Self::MAX_SCORE, self.score.saturating_add(delta)),
+        );
+    }
+}
+
+impl BincodeEncoded for PointInfo {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_connection_attempt_backoff() {
+        let now = SystemTime::now();
+        let base = Duration::from_secs(10);
+        let max = Duration::from_secs(60);
+
+        let mut info = PointInfo::default();
+        assert_eq!(None, info.next_connection_attempt(base, max));
+
+        info.failed(now);
+        assert_eq!(Some(now + base), info.next_connection_attempt(base, max));
+        info.failed(now);
+        assert_eq!(
+            Some(now + base * 2),
+            info.next_connection_attempt(base, max)
+        );
+        info.failed(now);
+        assert_eq!(
+            Some(now + base * 4),
+            info.next_connection_attempt(base, max)
+        );
+        for _ in 0..100 {
+            info.failed(now);
+        }
+        assert_eq!(Some(now + max), info.next_connection_attempt(base, max));
+        assert_eq!(PointInfo::MIN_SCORE, info.score());
+
+        info.connected(now);
+        assert_eq!(None, info.next_connection_attempt(base, max));
+        assert_eq!(0, info.failures());
+    }
+
+    #[test]
+    fn test_ban_expiration() {
+        let now = SystemTime::now();
+        let mut info = PointInfo::default();
+        assert!(!info.is_banned(now));
+
+        info.banned("test".to_string(), now + Duration::from_secs(10));
+        assert!(info.is_banned(now));
+        assert!(!info.is_banned(now + Duration::from_secs(10)));
+        assert_eq!(PointInfo::SCORE_BANNED, info.score());
+
+        // trusted point cannot be banned
+        info.trust();
+        assert!(!info.is_banned(now));
+        info.banned("test".to_string(), now + Duration::from_secs(10));
+        assert!(!info.is_banned(now));
+
+        info.untrust();
+        info.banned("test".to_string(), now + Duration::from_secs(10));
+        assert!(info.is_banned(now));
+    }
+}
@@ -2,8 +2,10 @@
 // SPDX-License-Identifier: MIT
 
 use std::collections::HashMap;
+use std::fs::File;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use std::{fmt, io};
 
 use commitlog::message::MessageSet;
@@ -33,6 +35,8 @@ pub enum CommitLogError {
     IOError { error: io::Error },
     #[fail(display = "Commit log {} is missing", name)]
     MissingCommitLog { name: &'static str },
+    #[fail(display = "Commit log {} is opened read-only", name)]
+    ReadOnly { name: &'static str },
 }
 
 impl From<SchemaError> for CommitLogError {
@@ -94,10 +98,41 @@ pub trait CommitLogWithSchema<S: CommitLogSchema> {
 
     /// Retrieve stored records stored in a single range.
     fn get_range(&self, range: &Range) -> Result<Vec<S::Value>, CommitLogError>;
+
+    /// Flush appended records to disk, so they can be safely referenced from other storages.
+    fn sync(&self) -> Result<(), CommitLogError>;
+
+    /// Offset of the next appended record, records bellow it are never changed (until compaction is finished).
+    fn next_offset(&self) -> Result<Offset, CommitLogError>;
+
+    /// Create a new empty compacted commit log, which is not used until [finish_compaction],
+    /// compacted commit log left by previous compaction is removed.
+    fn start_compaction(&self) -> Result<(), CommitLogError>;
+
+    /// Copy records at `locations` into the compacted commit log created by [start_compaction].
+    /// Returns new locations in the same order as `locations`.
+    ///
+    /// Can be called repeatedly with segments of records, commit log is locked just while the segment is read,
+    /// so records can be appended concurrently.
+    ///
+    /// Compacted commit log survives restart, so the owner of the locations decides,
+    /// whether to finish or abort interrupted compaction.
+    fn prepare_compaction(&self, locations: &[Location]) -> Result<Vec<Location>, CommitLogError>;
+
+    /// Flush compacted commit log to disk, has to be called before compacted locations are stored anywhere.
+    fn sync_compaction(&self) -> Result<(), CommitLogError>;
+
+    /// Replace commit log with the compacted one prepared by [prepare_compaction].
+    /// Returns count of reclaimed bytes, zero if there is no compacted commit log.
+    fn finish_compaction(&self) -> Result<u64, CommitLogError>;
+
+    /// Remove compacted commit log prepared by [prepare_compaction], original commit log stays untouched.
+    fn abort_compaction(&self) -> Result<(), CommitLogError>;
 }
 
 impl<S: CommitLogSchema> CommitLogWithSchema<S> for CommitLogs {
     fn append(&self, value: &S::Value) -> Result<Location, CommitLogError> {
+        self.check_writable(S::name())?;
         let cl = self
             .cl_handle(S::name())
             .ok_or(CommitLogError::MissingCommitLog { name: S::name() })?;
@@ -114,6 +149,7 @@ impl<S: CommitLogSchema> CommitLogWithSchema<S> for CommitLogs {
         let cl = self
             .cl_handle(S::name())
             .ok_or(CommitLogError::MissingCommitLog { name: S::name() })?;
+        self.refresh_if_behind(S::name(), &cl, location.0)?;
         let cl = cl.read().expect("Read lock failed");
         let msg_buf = cl
             .read(location.0, fit_read_limit(location.1))
@@ -134,6 +170,7 @@ impl<S: CommitLogSchema> CommitLogWithSchema<S> for CommitLogs {
         let cl = self
             .cl_handle(S::name())
             .ok_or(CommitLogError::MissingCommitLog { name: S::name() })?;
+        self.refresh_if_behind(S::name(), &cl, range.0)?;
         let cl = cl.read().expect("Read lock failed");
         let msg_buf = cl
             .read(range.0, fit_batch_read_limit(range.1, range.2))
@@ -152,6 +189,179 @@ impl<S: CommitLogSchema> CommitLogWithSchema<S> for CommitLogs {
             })
             .collect()
     }
+
+    fn sync(&self) -> Result<(), CommitLogError> {
+        self.check_writable(S::name())?;
+        let cl = self
+            .cl_handle(S::name())
+            .ok_or(CommitLogError::MissingCommitLog { name: S::name() })?;
+        let mut cl = cl.write().expect("Write lock failed");
+        cl.flush()?;
+        Ok(())
+    }
+
+    fn next_offset(&self) -> Result<Offset, CommitLogError> {
+        let cl = self
+            .cl_handle(S::name())
+            .ok_or(CommitLogError::MissingCommitLog { name: S::name() })?;
+        let cl = cl.read().expect("Read lock failed");
+        Ok(cl.next_offset())
+    }
+
+    fn start_compaction(&self) -> Result<(), CommitLogError> {
+        self.check_writable(S::name())?;
+        let mut compacted_logs = self.compacted_logs.lock().expect("Lock failed");
+        compacted_logs.remove(S::name());
+
+        let compacted_path = self.compacted_path(S::name());
+        if compacted_path.exists() {
+            std::fs::remove_dir_all(&compacted_path)?;
+        }
+        compacted_logs.insert(S::name().to_string(), open_log(&compacted_path, false)?);
+        Ok(())
+    }
+
+    fn prepare_compaction(&self, locations: &[Location]) -> Result<Vec<Location>, CommitLogError> {
+        let mut compacted_logs = self.compacted_logs.lock().expect("Lock failed");
+        let compacted = compacted_logs
+            .get_mut(S::name())
+            .ok_or(CommitLogError::MissingCommitLog { name: S::name() })?;
+
+        let cl = self
+            .cl_handle(S::name())
+            .ok_or(CommitLogError::MissingCommitLog { name: S::name() })?;
+        let cl = cl.read().expect("Read lock failed");
+
+        let mut new_locations = Vec::with_capacity(locations.len());
+        for location in locations {
+            let msg_buf = cl
+                .read(location.0, fit_read_limit(location.1))
+                .map_err(|error| CommitLogError::ReadError {
+                    error,
+                    location: *location,
+                })?;
+            let message = msg_buf.iter().next().ok_or(CommitLogError::ReadError {
+                error: ReadError::CorruptLog,
+                location: *location,
+            })?;
+            let offset = compacted
+                .append_msg(message.payload())
+                .map_err(|error| CommitLogError::AppendError { error })?;
+            new_locations.push(Location(offset, message.payload().len()));
+        }
+
+        Ok(new_locations)
+    }
+
+    fn sync_compaction(&self) -> Result<(), CommitLogError> {
+        let mut compacted_logs = self.compacted_logs.lock().expect("Lock failed");
+        let compacted = compacted_logs
+            .get_mut(S::name())
+            .ok_or(CommitLogError::MissingCommitLog { name: S::name() })?;
+        compacted.flush()?;
+
+        // commit log does not fsync, so whole compacted commit log is synced (it is written only once)
+        let compacted_path = self.compacted_path(S::name());
+        for entry in std::fs::read_dir(&compacted_path)? {
+            let entry = entry?;
+            if entry.metadata()?.is_file() {
+                File::open(entry.path())?.sync_all()?;
+            }
+        }
+        sync_dir(&compacted_path)?;
+        sync_dir(&self.base_path)?;
+        Ok(())
+    }
+
+    fn finish_compaction(&self) -> Result<u64, CommitLogError> {
+        self.check_writable(S::name())?;
+        // compacted commit log is opened again as the main one
+        self.compacted_logs
+            .lock()
+            .expect("Lock failed")
+            .remove(S::name());
+
+        let compacted_path = self.compacted_path(S::name());
+        if !compacted_path.exists() {
+            // nothing prepared or already finished
+            return Ok(0);
+        }
+
+        let cl = self
+            .cl_handle(S::name())
+            .ok_or(CommitLogError::MissingCommitLog { name: S::name() })?;
+        let mut cl = cl.write().expect("Write lock failed");
+        cl.flush()?;
+
+        let path = self.base_path.join(S::name());
+        let size_before = dir_size(&path)?;
+
+        std::fs::remove_dir_all(&path)?;
+        std::fs::rename(&compacted_path, &path)?;
+        // rename is durable just after its directory is synced
+        sync_dir(&self.base_path)?;
+        *cl = open_log(&path, false)?;
+
+        Ok(size_before.saturating_sub(dir_size(&path)?))
+    }
+
+    fn abort_compaction(&self) -> Result<(), CommitLogError> {
+        self.check_writable(S::name())?;
+        self.compacted_logs
+            .lock()
+            .expect("Lock failed")
+            .remove(S::name());
+
+        let compacted_path = self.compacted_path(S::name());
+        if compacted_path.exists() {
+            std::fs::remove_dir_all(&compacted_path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Suffix of the directory with commit log being compacted
+const COMPACTION_DIR_SUFFIX: &str = ".compacted";
+
+fn open_log(path: &Path, read_only: bool) -> Result<CommitLog, CommitLogError> {
+    if !path.exists() {
+        if read_only {
+            return Err(CommitLogError::IOError {
+                error: io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Commit log directory does not exist: {:?}", path),
+                ),
+            });
+        }
+        std::fs::create_dir_all(path)?;
+    }
+
+    let mut opts = LogOptions::new(path);
+    // TODO: TE-396 - rework
+    opts.message_max_bytes(15_000_000);
+    Ok(CommitLog::new(opts)?)
+}
+
+/// Identifies directory of the commit log, it is changed, when the commit log is replaced by compaction
+fn dir_id(path: &Path) -> Result<u64, CommitLogError> {
+    Ok(std::fs::metadata(path)?.ino())
+}
+
+/// Makes changes of directory entries (create/rename/remove) durable
+fn sync_dir(path: &Path) -> Result<(), CommitLogError> {
+    File::open(path)?.sync_all()?;
+    Ok(())
+}
+
+fn dir_size(path: &Path) -> Result<u64, CommitLogError> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let metadata = entry?.metadata()?;
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
 }
 
 #[inline]
@@ -192,10 +402,38 @@ pub fn fold_consecutive_locations(locations: &[Location]) -> Vec<Range> {
 pub struct CommitLogs {
     base_path: PathBuf,
     commit_log_map: RwLock<HashMap<String, CommitLogRef>>,
+    /// Locations are valid only until compaction, so everybody who reads/writes locations
+    /// holds read lock and compaction holds write lock while switching to the compacted commit log
+    locations_lock: Arc<RwLock<()>>,
+    /// Just one compaction can run at the same time
+    compaction_lock: Arc<Mutex<()>>,
+    /// Commit logs being compacted (see [CommitLogWithSchema::start_compaction])
+    compacted_logs: Mutex<HashMap<String, CommitLog>>,
+    /// Read-only commit logs are never appended, flushed or compacted
+    read_only: bool,
+    /// Directories of the read-only commit logs at the time they were opened (see [dir_id])
+    opened_dirs: Mutex<HashMap<String, u64>>,
 }
 
 impl CommitLogs {
     pub(crate) fn new<P, I>(path: P, cfs: I) -> Result<Self, CommitLogError>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item = CommitLogDescriptor>,
+    {
+        Self::open(path, cfs, false)
+    }
+
+    /// Opens existing commit logs just for reading, missing commit log is an error
+    pub(crate) fn new_read_only<P, I>(path: P, cfs: I) -> Result<Self, CommitLogError>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item = CommitLogDescriptor>,
+    {
+        Self::open(path, cfs, true)
+    }
+
+    fn open<P, I>(path: P, cfs: I, read_only: bool) -> Result<Self, CommitLogError>
     where
         P: AsRef<Path>,
         I: IntoIterator<Item = CommitLogDescriptor>,
@@ -203,6 +441,11 @@ impl CommitLogs {
         let myself = Self {
             base_path: path.as_ref().into(),
             commit_log_map: RwLock::new(HashMap::new()),
+            locations_lock: Arc::new(RwLock::new(())),
+            compaction_lock: Arc::new(Mutex::new(())),
+            compacted_logs: Mutex::new(HashMap::new()),
+            read_only,
+            opened_dirs: Mutex::new(HashMap::new()),
         };
 
         for descriptor in cfs.into_iter() {
@@ -215,15 +458,14 @@ impl CommitLogs {
     /// Register a new commit log.
     fn register(&self, name: &str) -> Result<(), CommitLogError> {
         let path = self.base_path.join(name);
-        if !Path::new(&path).exists() {
-            std::fs::create_dir_all(&path)?;
+        let log = open_log(&path, self.read_only)?;
+        if self.read_only {
+            self.opened_dirs
+                .lock()
+                .expect("Lock failed")
+                .insert(name.into(), dir_id(&path)?);
         }
 
-        let mut opts = LogOptions::new(&path);
-        // TODO: TE-396 - rework
-        opts.message_max_bytes(15_000_000);
-        let log = CommitLog::new(opts)?;
-
         let mut commit_log_map = self.commit_log_map.write().unwrap();
         commit_log_map.insert(name.into(), Arc::new(RwLock::new(log)));
 
@@ -237,8 +479,77 @@ impl CommitLogs {
         commit_log_map.get(name).cloned()
     }
 
-    /// Flush all registered commit logs.
+    /// Lock, which guards validity of all locations (see [CommitLogWithSchema::prepare_compaction]).
+    #[inline]
+    pub fn locations_lock(&self) -> Arc<RwLock<()>> {
+        self.locations_lock.clone()
+    }
+
+    /// Lock, which serializes compactions, taken for the whole compaction
+    #[inline]
+    pub fn compaction_lock(&self) -> Arc<Mutex<()>> {
+        self.compaction_lock.clone()
+    }
+
+    #[inline]
+    fn check_writable(&self, name: &'static str) -> Result<(), CommitLogError> {
+        if self.read_only {
+            Err(CommitLogError::ReadOnly { name })
+        } else {
+            Ok(())
+        }
+    }
+
+    #[inline]
+    fn compacted_path(&self, name: &str) -> PathBuf {
+        self.base_path
+            .join(format!("{}{}", name, COMPACTION_DIR_SUFFIX))
+    }
+
+    /// Read-only commit log does not see records appended by another process (primary instance of the storage)
+    /// after it was opened, so it is opened again just when a record after its end is requested.
+    fn refresh_if_behind(
+        &self,
+        name: &str,
+        cl: &CommitLogRef,
+        offset: Offset,
+    ) -> Result<(), CommitLogError> {
+        if !self.read_only || cl.read().expect("Read lock failed").next_offset() > offset {
+            return Ok(());
+        }
+        let mut cl = cl.write().expect("Write lock failed");
+        if cl.next_offset() <= offset {
+            *cl = open_log(&self.base_path.join(name), true)?;
+        }
+        Ok(())
+    }
+
+    /// Opens again the read-only commit logs, which were replaced by compaction of another process
+    /// (primary instance of the storage) - compaction changes locations of the records,
+    /// so the replaced commit log cannot be read with the new locations.
+    pub fn refresh_compacted(&self) -> Result<(), CommitLogError> {
+        if !self.read_only {
+            return Ok(());
+        }
+        let commit_log_map = self.commit_log_map.read().unwrap();
+        let mut opened_dirs = self.opened_dirs.lock().expect("Lock failed");
+        for (name, commit_log) in commit_log_map.iter() {
+            let path = self.base_path.join(name);
+            let dir_id = dir_id(&path)?;
+            if opened_dirs.get(name) != Some(&dir_id) {
+                *commit_log.write().unwrap() = open_log(&path, true)?;
+                opened_dirs.insert(name.clone(), dir_id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Flush all registered commit logs, read-only commit logs are not flushed.
     pub fn flush(&self) -> Result<(), CommitLogError> {
+        if self.read_only {
+            return Ok(());
+        }
         let commit_log_map = self.commit_log_map.read().unwrap();
         for commit_log in commit_log_map.values() {
             let mut commit_log = commit_log.write().unwrap();
@@ -308,4 +619,132 @@ mod tests {
             ranges
         );
     }
+
+    struct TestLog;
+
+    impl CommitLogSchema for TestLog {
+        type Value = String;
+
+        fn name() -> &'static str {
+            "test_log"
+        }
+    }
+
+    #[test]
+    fn test_compaction_in_segments() -> Result<(), failure::Error> {
+        let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is not defined - check build.rs");
+        let path = Path::new(&out_dir).join("__commit_log_compaction_in_segments");
+        if path.exists() {
+            std::fs::remove_dir_all(&path)?;
+        }
+        let logs = CommitLogs::new(&path, vec![TestLog::descriptor()])?;
+        let append = |value: &str| -> Result<Location, CommitLogError> {
+            CommitLogWithSchema::<TestLog>::append(&logs, &value.to_string())
+        };
+
+        let first = append("first")?;
+        let _garbage = append("garbage")?;
+        let second = append("second")?;
+
+        CommitLogWithSchema::<TestLog>::start_compaction(&logs)?;
+        let mut compacted = CommitLogWithSchema::<TestLog>::prepare_compaction(&logs, &[first])?;
+        // appended while compaction is running
+        let third = append("third")?;
+        compacted.extend(CommitLogWithSchema::<TestLog>::prepare_compaction(
+            &logs,
+            &[second, third],
+        )?);
+        CommitLogWithSchema::<TestLog>::sync_compaction(&logs)?;
+        assert!(CommitLogWithSchema::<TestLog>::finish_compaction(&logs)? > 0);
+
+        let values = compacted
+            .iter()
+            .map(|location| CommitLogWithSchema::<TestLog>::get(&logs, location))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(values, vec!["first", "second", "third"]);
+        assert!(!path
+            .join(format!("test_log{}", COMPACTION_DIR_SUFFIX))
+            .exists());
+
+        std::fs::remove_dir_all(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_only() -> Result<(), failure::Error> {
+        let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is not defined - check build.rs");
+        let path = Path::new(&out_dir).join("__commit_log_read_only");
+        if path.exists() {
+            std::fs::remove_dir_all(&path)?;
+        }
+        assert!(CommitLogs::new_read_only(&path, vec![TestLog::descriptor()]).is_err());
+
+        let location = {
+            let logs = CommitLogs::new(&path, vec![TestLog::descriptor()])?;
+            CommitLogWithSchema::<TestLog>::append(&logs, &"first".to_string())?
+        };
+
+        let logs = CommitLogs::new_read_only(&path, vec![TestLog::descriptor()])?;
+        assert_eq!(
+            CommitLogWithSchema::<TestLog>::get(&logs, &location)?,
+            "first"
+        );
+        assert!(matches!(
+            CommitLogWithSchema::<TestLog>::append(&logs, &"second".to_string()),
+            Err(CommitLogError::ReadOnly { .. })
+        ));
+        assert!(CommitLogWithSchema::<TestLog>::start_compaction(&logs).is_err());
+        drop(logs);
+
+        std::fs::remove_dir_all(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_only_sees_appended_and_compacted() -> Result<(), failure::Error> {
+        let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is not defined - check build.rs");
+        let path = Path::new(&out_dir).join("__commit_log_read_only_refresh");
+        if path.exists() {
+            std::fs::remove_dir_all(&path)?;
+        }
+        let primary = CommitLogs::new(&path, vec![TestLog::descriptor()])?;
+        let append = |value: &str| -> Result<Location, CommitLogError> {
+            let location = CommitLogWithSchema::<TestLog>::append(&primary, &value.to_string())?;
+            CommitLogWithSchema::<TestLog>::sync(&primary)?;
+            Ok(location)
+        };
+        let _garbage = append("garbage")?;
+        let first = append("first")?;
+
+        let reader = CommitLogs::new_read_only(&path, vec![TestLog::descriptor()])?;
+        assert_eq!(
+            CommitLogWithSchema::<TestLog>::get(&reader, &first)?,
+            "first"
+        );
+
+        // record appended after the reader was opened
+        let second = append("second")?;
+        assert_eq!(
+            CommitLogWithSchema::<TestLog>::get(&reader, &second)?,
+            "second"
+        );
+
+        // compaction moves records to lower offsets
+        CommitLogWithSchema::<TestLog>::start_compaction(&primary)?;
+        let compacted =
+            CommitLogWithSchema::<TestLog>::prepare_compaction(&primary, &[first, second])?;
+        CommitLogWithSchema::<TestLog>::sync_compaction(&primary)?;
+        CommitLogWithSchema::<TestLog>::finish_compaction(&primary)?;
+        reader.refresh_compacted()?;
+        let values = compacted
+            .iter()
+            .map(|location| CommitLogWithSchema::<TestLog>::get(&reader, location))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(values, vec!["first", "second"]);
+
+        drop(reader);
+        drop(primary);
+        std::fs::remove_dir_all(&path)?;
+        Ok(())
+    }
 }
@@ -4,15 +4,19 @@
 //! This module provides wrapper on RocksDB database.
 //! Everything related to RocksDB should be placed here.
 
+use std::collections::HashMap;
 use std::io;
+use std::iter::FromIterator;
 use std::marker::PhantomData;
+use std::os::raw::c_int;
 use std::path::Path;
+use std::str::FromStr;
 use std::sync::PoisonError;
 
 use failure::Fail;
 use rocksdb::{
-    BlockBasedOptions, Cache, ColumnFamilyDescriptor, DBIterator, Error, Options, WriteBatch,
-    WriteOptions, DB,
+    BlockBasedOptions, Cache, ColumnFamily, ColumnFamilyDescriptor, DBCompactionStyle,
+    DBCompressionType, DBIterator, Error, Options, WriteBatch, WriteOptions, DB,
 };
 use serde::Serialize;
 
@@ -34,6 +38,66 @@ where
     DB::open_cf_descriptors(&default_kv_options(cfg), path, cfs).map_err(DBError::from)
 }
 
+/// Open existing RocksDB database at given path as read-only instance, nothing is written to the database
+/// (but the database must not be opened by another process, which could write to it).
+///
+/// Column families are opened just by names with default options (rocksdb crate does not support
+/// descriptors for read-only instance), so merge operands not yet compacted cannot be read.
+///
+/// # Arguments
+/// * `path` - Path to open RocksDB
+/// * `cfs` - Iterator of Column Family names
+pub fn open_kv_read_only<P, I, N>(path: P, cfs: I, cfg: &DbConfiguration) -> Result<DB, DBError>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = N>,
+    N: AsRef<str>,
+{
+    let mut db_opts = default_kv_options(cfg);
+    db_opts.create_if_missing(false);
+    db_opts.create_missing_column_families(false);
+
+    DB::open_cf_for_read_only(&db_opts, path, cfs, false).map_err(DBError::from)
+}
+
+/// Open RocksDB database at `primary_path` as secondary instance, so another process can read
+/// the database opened by the primary instance. Secondary instance is read-only and sees changes
+/// of the primary instance only after [DB::try_catch_up_with_primary].
+///
+/// Column families are opened just by names with default options (rocksdb crate does not support
+/// descriptors for secondary instance), so merge operands not yet compacted by the primary instance cannot be read.
+///
+/// # Arguments
+/// * `primary_path` - Path of RocksDB opened by the primary instance
+/// * `secondary_path` - Path for info logs of the secondary instance, has to be unique for every secondary instance
+/// * `cfs` - Iterator of Column Family names
+pub fn open_kv_as_secondary<P, S, I, N>(
+    primary_path: P,
+    secondary_path: S,
+    cfs: I,
+    cfg: &DbConfiguration,
+) -> Result<DB, DBError>
+where
+    P: AsRef<Path>,
+    S: AsRef<Path>,
+    I: IntoIterator<Item = N>,
+    N: AsRef<str>,
+{
+    let mut db_opts = default_kv_options(cfg);
+    db_opts.create_if_missing(false);
+    db_opts.create_missing_column_families(false);
+    // secondary instance has to keep all files opened: https://github.com/facebook/rocksdb/wiki/Secondary-instance
+    db_opts.set_max_open_files(-1);
+
+    DB::open_cf_as_secondary(
+        &db_opts,
+        primary_path.as_ref(),
+        secondary_path.as_ref(),
+        cfs,
+    )
+    .map_err(DBError::from)
+}
+
 /// Create default database configuration options,
 /// based on recommended setting: https://github.com/facebook/rocksdb/wiki/Setup-Options-and-Basic-Tuning#other-general-options
 fn default_kv_options(cfg: &DbConfiguration) -> Options {
@@ -62,39 +126,390 @@ fn default_kv_options(cfg: &DbConfiguration) -> Options {
     db_opts
 }
 
-/// Create default database configuration options,
-/// based on recommended setting:
-///     https://github.com/facebook/rocksdb/wiki/Setup-Options-and-Basic-Tuning#other-general-options
-///     https://rocksdb.org/blog/2019/03/08/format-version-4.html
-pub fn default_table_options(cache: &Cache) -> Options {
-    // default db options
-    let mut db_opts = Options::default();
+/// Compaction style of the column family (see [rocksdb compaction](https://github.com/facebook/rocksdb/wiki/Compaction))
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompactionStyle {
+    Level,
+    Universal,
+}
 
-    // https://github.com/facebook/rocksdb/wiki/Setup-Options-and-Basic-Tuning#other-general-options
-    db_opts.set_level_compaction_dynamic_level_bytes(true);
+impl FromStr for CompactionStyle {
+    type Err = ColumnFamilyTuningError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "level" => Ok(CompactionStyle::Level),
+            "universal" => Ok(CompactionStyle::Universal),
+            _ => Err(ColumnFamilyTuningError::InvalidValue {
+                option: "compaction_style".to_string(),
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl From<CompactionStyle> for DBCompactionStyle {
+    fn from(style: CompactionStyle) -> Self {
+        match style {
+            CompactionStyle::Level => DBCompactionStyle::Level,
+            CompactionStyle::Universal => DBCompactionStyle::Universal,
+        }
+    }
+}
 
-    // block table options
-    let mut table_options = BlockBasedOptions::default();
-    table_options.set_block_cache(cache);
-    table_options.set_block_size(16 * 1024);
-    table_options.set_cache_index_and_filter_blocks(true);
-    table_options.set_pin_l0_filter_and_index_blocks_in_cache(true);
+/// Compression of the column family blocks, just compressions compiled in rocksdb are supported
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Snappy,
+    Lz4,
+    Zstd,
+    Zlib,
+}
 
-    // set format_version 4 https://rocksdb.org/blog/2019/03/08/format-version-4.html
-    table_options.set_format_version(4);
-    table_options.set_index_block_restart_interval(16);
+impl FromStr for Compression {
+    type Err = ColumnFamilyTuningError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "snappy" => Ok(Compression::Snappy),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Ok(Compression::Zstd),
+            "zlib" => Ok(Compression::Zlib),
+            _ => Err(ColumnFamilyTuningError::InvalidValue {
+                option: "compression".to_string(),
+                value: s.to_string(),
+            }),
+        }
+    }
+}
 
-    db_opts.set_block_based_table_factory(&table_options);
+impl From<Compression> for DBCompressionType {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::None => DBCompressionType::None,
+            Compression::Snappy => DBCompressionType::Snappy,
+            Compression::Lz4 => DBCompressionType::Lz4,
+            Compression::Zstd => DBCompressionType::Zstd,
+            Compression::Zlib => DBCompressionType::Zlib,
+        }
+    }
+}
 
-    db_opts
+/// Possible errors for column family tuning configuration
+#[derive(Debug, Fail)]
+pub enum ColumnFamilyTuningError {
+    #[fail(
+        display = "Invalid column family tuning: {}, expected format is <column_family>:<option>=<value>[,<option>=<value>]",
+        value
+    )]
+    InvalidFormat { value: String },
+    #[fail(
+        display = "Unknown column family option: {}, expected one of compaction_style, bloom_filter_bits, block_size, compression",
+        option
+    )]
+    UnknownOption { option: String },
+    #[fail(
+        display = "Invalid value of column family option {}: {}",
+        option, value
+    )]
+    InvalidValue { option: String, value: String },
+}
+
+/// RocksDB options, which can differ per column family.
+///
+/// Every [RocksDbKeyValueSchema] declares its own defaults by [RocksDbKeyValueSchema::tuning],
+/// these can be overridden by [ColumnFamiliesTuning] from configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnFamilyTuning {
+    pub compaction_style: CompactionStyle,
+    /// Bits per key of the bloom filter, 0 means no bloom filter
+    pub bloom_filter_bits: u32,
+    pub block_size: usize,
+    pub compression: Compression,
+}
+
+impl Default for ColumnFamilyTuning {
+    fn default() -> Self {
+        Self {
+            compaction_style: CompactionStyle::Level,
+            bloom_filter_bits: 0,
+            block_size: 16 * 1024,
+            compression: Compression::Snappy,
+        }
+    }
+}
+
+impl ColumnFamilyTuning {
+    /// Create column family options,
+    /// based on recommended setting:
+    ///     https://github.com/facebook/rocksdb/wiki/Setup-Options-and-Basic-Tuning#other-general-options
+    ///     https://rocksdb.org/blog/2019/03/08/format-version-4.html
+    pub fn table_options(&self, cache: &Cache) -> Options {
+        // default db options
+        let mut db_opts = Options::default();
+
+        db_opts.set_compaction_style(self.compaction_style.into());
+        db_opts.set_compression_type(self.compression.into());
+        if self.compaction_style == CompactionStyle::Level {
+            // https://github.com/facebook/rocksdb/wiki/Setup-Options-and-Basic-Tuning#other-general-options
+            db_opts.set_level_compaction_dynamic_level_bytes(true);
+        }
+
+        // block table options
+        let mut table_options = BlockBasedOptions::default();
+        table_options.set_block_cache(cache);
+        table_options.set_block_size(self.block_size);
+        table_options.set_cache_index_and_filter_blocks(true);
+        table_options.set_pin_l0_filter_and_index_blocks_in_cache(true);
+        if self.bloom_filter_bits > 0 {
+            table_options.set_bloom_filter(self.bloom_filter_bits as c_int, false);
+        }
+
+        // set format_version 4 https://rocksdb.org/blog/2019/03/08/format-version-4.html
+        table_options.set_format_version(4);
+        table_options.set_index_block_restart_interval(16);
+
+        db_opts.set_block_based_table_factory(&table_options);
+
+        db_opts
+    }
+}
+
+/// Overrides of the [ColumnFamilyTuning] options, parsed from `<option>=<value>[,<option>=<value>]`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ColumnFamilyTuningOverride {
+    pub compaction_style: Option<CompactionStyle>,
+    pub bloom_filter_bits: Option<u32>,
+    pub block_size: Option<usize>,
+    pub compression: Option<Compression>,
+}
+
+impl ColumnFamilyTuningOverride {
+    pub fn apply_to(&self, tuning: ColumnFamilyTuning) -> ColumnFamilyTuning {
+        ColumnFamilyTuning {
+            compaction_style: self.compaction_style.unwrap_or(tuning.compaction_style),
+            bloom_filter_bits: self.bloom_filter_bits.unwrap_or(tuning.bloom_filter_bits),
+            block_size: self.block_size.unwrap_or(tuning.block_size),
+            compression: self.compression.unwrap_or(tuning.compression),
+        }
+    }
+}
+
+impl FromStr for ColumnFamilyTuningOverride {
+    type Err = ColumnFamilyTuningError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut result = Self::default();
+        for option in s.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+            let (name, value) = match option.find('=') {
+                Some(idx) => (option[..idx].trim(), option[idx + 1..].trim()),
+                None => {
+                    return Err(ColumnFamilyTuningError::InvalidFormat {
+                        value: s.to_string(),
+                    })
+                }
+            };
+            let invalid_value = || ColumnFamilyTuningError::InvalidValue {
+                option: name.to_string(),
+                value: value.to_string(),
+            };
+            match name {
+                "compaction_style" => result.compaction_style = Some(value.parse()?),
+                "bloom_filter_bits" => {
+                    result.bloom_filter_bits = Some(value.parse().map_err(|_| invalid_value())?)
+                }
+                "block_size" => match value.parse::<usize>() {
+                    Ok(block_size) if block_size > 0 => result.block_size = Some(block_size),
+                    _ => return Err(invalid_value()),
+                },
+                "compression" => result.compression = Some(value.parse()?),
+                _ => {
+                    return Err(ColumnFamilyTuningError::UnknownOption {
+                        option: name.to_string(),
+                    })
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Overrides of column family options by column family name, configured e.g. by
+/// `block_meta_storage:bloom_filter_bits=10,compression=lz4`
+#[derive(Debug, Clone, Default)]
+pub struct ColumnFamiliesTuning {
+    overrides: HashMap<String, ColumnFamilyTuningOverride>,
+}
+
+impl ColumnFamiliesTuning {
+    pub fn with_override(
+        mut self,
+        column_family: &str,
+        tuning_override: ColumnFamilyTuningOverride,
+    ) -> Self {
+        self.overrides
+            .insert(column_family.to_string(), tuning_override);
+        self
+    }
+
+    /// Default tuning of the schema with applied overrides
+    pub fn tuning<S: RocksDbKeyValueSchema>(&self) -> ColumnFamilyTuning {
+        match self.overrides.get(S::name()) {
+            Some(tuning_override) => tuning_override.apply_to(S::tuning()),
+            None => S::tuning(),
+        }
+    }
+
+    pub fn descriptor<S: RocksDbKeyValueSchema>(&self, cache: &Cache) -> ColumnFamilyDescriptor {
+        S::descriptor_with_tuning(cache, &self.tuning::<S>())
+    }
+
+    /// Names of the overridden column families
+    pub fn column_families(&self) -> impl Iterator<Item = &String> {
+        self.overrides.keys()
+    }
+}
+
+impl FromStr for ColumnFamiliesTuning {
+    type Err = ColumnFamilyTuningError;
+
+    /// Parses single column family override `<column_family>:<option>=<value>[,<option>=<value>]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.find(':') {
+            Some(idx) if !s[..idx].trim().is_empty() => {
+                Ok(Self::default().with_override(s[..idx].trim(), s[idx + 1..].parse()?))
+            }
+            _ => Err(ColumnFamilyTuningError::InvalidFormat {
+                value: s.to_string(),
+            }),
+        }
+    }
 }
 
+impl FromIterator<ColumnFamiliesTuning> for ColumnFamiliesTuning {
+    fn from_iter<I: IntoIterator<Item = ColumnFamiliesTuning>>(iter: I) -> Self {
+        Self {
+            overrides: iter.into_iter().flat_map(|t| t.overrides).collect(),
+        }
+    }
+}
+
+/// [ColumnFamiliesTuning] by database (name of its directory, e.g. `db`, `context` or `context_actions`),
+/// configured e.g. by `db/block_meta_storage:bloom_filter_bits=10,compression=lz4`
+#[derive(Debug, Clone, Default)]
+pub struct DatabasesTuning {
+    databases: HashMap<String, ColumnFamiliesTuning>,
+}
+
+impl DatabasesTuning {
+    /// Overrides of column families of the `database`
+    pub fn database(&self, database: &str) -> ColumnFamiliesTuning {
+        self.databases.get(database).cloned().unwrap_or_default()
+    }
+
+    /// Names of the tuned databases
+    pub fn databases(&self) -> impl Iterator<Item = &String> {
+        self.databases.keys()
+    }
+}
+
+impl FromStr for DatabasesTuning {
+    type Err = ColumnFamilyTuningError;
+
+    /// Parses single column family override `<database>/<column_family>:<option>=<value>[,<option>=<value>]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.find('/') {
+            Some(idx) if !s[..idx].trim().is_empty() => {
+                let mut databases = HashMap::new();
+                databases.insert(s[..idx].trim().to_string(), s[idx + 1..].parse()?);
+                Ok(Self { databases })
+            }
+            _ => Err(ColumnFamilyTuningError::InvalidFormat {
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl FromIterator<DatabasesTuning> for DatabasesTuning {
+    fn from_iter<I: IntoIterator<Item = DatabasesTuning>>(iter: I) -> Self {
+        let mut databases: HashMap<String, ColumnFamiliesTuning> = HashMap::new();
+        for (database, tuning) in iter.into_iter().flat_map(|t| t.databases) {
+            databases
+                .entry(database)
+                .or_default()
+                .overrides
+                .extend(tuning.overrides);
+        }
+        Self { databases }
+    }
+}
+
+/// Memory usage of the whole database and statistics of every column family
 #[derive(Serialize, Debug, Clone)]
 pub struct RocksDBStats {
     pub mem_table_total: u64,
     pub mem_table_unflushed: u64,
     pub mem_table_readers_total: u64,
     pub cache_total: u64,
+    pub column_families: Vec<ColumnFamilyStats>,
+}
+
+/// Statistics of the column family (from rocksdb properties, so values are estimates)
+#[derive(Serialize, Debug, Clone)]
+pub struct ColumnFamilyStats {
+    pub name: String,
+    /// Total size of all SST files
+    pub size_on_disk: u64,
+    pub live_data_size: u64,
+    pub estimated_keys: u64,
+    pub mem_table_size: u64,
+    pub table_readers_mem: u64,
+    /// Block cache is shared by column families of the database
+    pub block_cache_usage: u64,
+    pub block_cache_pinned_usage: u64,
+}
+
+impl RocksDBStats {
+    /// Collects statistics of the database and all its column families
+    pub fn collect(db: &DB) -> Result<Self, DBError> {
+        let memory_usage_stats = rocksdb::perf::get_memory_usage_stats(Some(&[db]), None)?;
+        let column_families = DB::list_cf(&Options::default(), db.path())?
+            .into_iter()
+            .filter_map(|name| {
+                db.cf_handle(&name)
+                    .map(|cf| ColumnFamilyStats::collect(db, cf, name.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            mem_table_total: memory_usage_stats.mem_table_total,
+            mem_table_unflushed: memory_usage_stats.mem_table_unflushed,
+            mem_table_readers_total: memory_usage_stats.mem_table_readers_total,
+            cache_total: memory_usage_stats.cache_total,
+            column_families,
+        })
+    }
+}
+
+impl ColumnFamilyStats {
+    fn collect(db: &DB, cf: &ColumnFamily, name: String) -> Result<Self, DBError> {
+        let property = |property: &str| -> Result<u64, DBError> {
+            Ok(db.property_int_value_cf(cf, property)?.unwrap_or(0))
+        };
+        Ok(Self {
+            size_on_disk: property("rocksdb.total-sst-files-size")?,
+            live_data_size: property("rocksdb.estimate-live-data-size")?,
+            estimated_keys: property("rocksdb.estimate-num-keys")?,
+            mem_table_size: property("rocksdb.cur-size-all-mem-tables")?,
+            table_readers_mem: property("rocksdb.estimate-table-readers-mem")?,
+            block_cache_usage: property("rocksdb.block-cache-usage")?,
+            block_cache_pinned_usage: property("rocksdb.block-cache-pinned-usage")?,
+            name,
+        })
+    }
 }
 
 /// Possible errors for schema
@@ -124,6 +539,17 @@ pub enum DBError {
     MemoryStatisticsOverflow,
 }
 
+impl DBError {
+    /// Returns true, if value has merge operands, which cannot be merged, because merge operator
+    /// is not configured (e.g. read-only or secondary instance, see [open_kv_read_only])
+    pub fn is_merge_operator_missing(&self) -> bool {
+        match self {
+            DBError::RocksDBError { error } => error.to_string().contains("merge_operator"),
+            _ => false,
+        }
+    }
+}
+
 impl From<SchemaError> for DBError {
     fn from(error: SchemaError) -> Self {
         DBError::SchemaError { error }
@@ -174,8 +600,22 @@ impl From<io::Error> for DBError {
 }
 
 pub trait RocksDbKeyValueSchema: KeyValueSchema {
+    /// Column family descriptor with default tuning of the schema
     fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        ColumnFamilyDescriptor::new(Self::name(), default_table_options(cache))
+        Self::descriptor_with_tuning(cache, &Self::tuning())
+    }
+
+    /// Column family descriptor with the `tuning` (usually [ColumnFamiliesTuning::tuning])
+    fn descriptor_with_tuning(
+        cache: &Cache,
+        tuning: &ColumnFamilyTuning,
+    ) -> ColumnFamilyDescriptor {
+        ColumnFamilyDescriptor::new(Self::name(), tuning.table_options(cache))
+    }
+
+    /// Default options of the column family
+    fn tuning() -> ColumnFamilyTuning {
+        ColumnFamilyTuning::default()
     }
 
     fn name() -> &'static str;
@@ -347,6 +787,69 @@ impl<S: RocksDbKeyValueSchema> KeyValueStoreBackend<S> for DB {
     }
 }
 
+/// Batch of changes across multiple column families, which are written atomically
+pub struct SchemaWriteBatch<'a> {
+    db: &'a DB,
+    batch: WriteBatch,
+}
+
+impl<'a> SchemaWriteBatch<'a> {
+    pub fn new(db: &'a DB) -> Self {
+        Self {
+            db,
+            batch: WriteBatch::default(),
+        }
+    }
+
+    pub fn put<S: RocksDbKeyValueSchema>(
+        &mut self,
+        key: &S::Key,
+        value: &S::Value,
+    ) -> Result<(), DBError> {
+        let key = key.encode()?;
+        let value = value.encode()?;
+        let cf = self
+            .db
+            .cf_handle(S::name())
+            .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
+        self.batch.put_cf(cf, &key, &value);
+        Ok(())
+    }
+
+    pub fn merge<S: RocksDbKeyValueSchema>(
+        &mut self,
+        key: &S::Key,
+        value: &S::Value,
+    ) -> Result<(), DBError> {
+        let key = key.encode()?;
+        let value = value.encode()?;
+        let cf = self
+            .db
+            .cf_handle(S::name())
+            .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
+        self.batch.merge_cf(cf, &key, &value);
+        Ok(())
+    }
+
+    pub fn delete<S: RocksDbKeyValueSchema>(&mut self, key: &S::Key) -> Result<(), DBError> {
+        let key = key.encode()?;
+        let cf = self
+            .db
+            .cf_handle(S::name())
+            .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
+        self.batch.delete_cf(cf, &key);
+        Ok(())
+    }
+
+    /// Writes all changes at once, `sync` waits until data are persisted on disk
+    pub fn write(self, sync: bool) -> Result<(), DBError> {
+        let mut opts = default_write_options();
+        opts.set_sync(sync);
+        self.db.write_opt(self.batch, &opts)?;
+        Ok(())
+    }
+}
+
 fn default_write_options() -> WriteOptions {
     let mut opts = WriteOptions::default();
     opts.set_sync(false);
@@ -388,3 +891,112 @@ pub enum IteratorMode<'a, S: KeyValueSchema> {
     End,
     From(&'a S::Key, Direction),
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::block_meta_storage::BlockMetaStorage;
+    use crate::system_storage::SystemStorage;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_column_families_tuning() -> Result<(), failure::Error> {
+        let tuning: ColumnFamiliesTuning = vec![
+            "block_meta_storage:compaction_style=universal, block_size=4096".parse()?,
+            "system_storage:bloom_filter_bits=0,compression=ZSTD".parse()?,
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            ColumnFamilyTuning {
+                compaction_style: CompactionStyle::Universal,
+                bloom_filter_bits: 10,
+                block_size: 4096,
+                compression: Compression::Snappy,
+            },
+            tuning.tuning::<BlockMetaStorage>()
+        );
+        assert_eq!(
+            ColumnFamilyTuning {
+                compression: Compression::Zstd,
+                ..ColumnFamilyTuning::default()
+            },
+            tuning.tuning::<SystemStorage>()
+        );
+        assert_eq!(
+            BlockMetaStorage::tuning(),
+            ColumnFamiliesTuning::default().tuning::<BlockMetaStorage>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_databases_tuning() -> Result<(), failure::Error> {
+        let tuning: DatabasesTuning = vec![
+            "db/block_meta_storage:block_size=4096".parse()?,
+            "context_actions/system_storage:compression=zstd".parse()?,
+            "db/system_storage:bloom_filter_bits=10".parse()?,
+        ]
+        .into_iter()
+        .collect();
+
+        // overrides are applied just to the column families of the configured database
+        let db = tuning.database("db");
+        assert_eq!(4096, db.tuning::<BlockMetaStorage>().block_size);
+        assert_eq!(10, db.tuning::<SystemStorage>().bloom_filter_bits);
+        assert_eq!(
+            Compression::Snappy,
+            db.tuning::<SystemStorage>().compression
+        );
+        assert_eq!(
+            ColumnFamilyTuning {
+                compression: Compression::Zstd,
+                ..ColumnFamilyTuning::default()
+            },
+            tuning.database("context_actions").tuning::<SystemStorage>()
+        );
+        assert_eq!(
+            SystemStorage::tuning(),
+            tuning.database("context").tuning::<SystemStorage>()
+        );
+
+        let mut databases = tuning.databases().cloned().collect::<Vec<_>>();
+        databases.sort();
+        assert_eq!(vec!["context_actions", "db"], databases);
+
+        assert!(matches!(
+            "block_meta_storage:compression=lz4".parse::<DatabasesTuning>(),
+            Err(ColumnFamilyTuningError::InvalidFormat { .. })
+        ));
+        assert!(matches!(
+            "/block_meta_storage:compression=lz4".parse::<DatabasesTuning>(),
+            Err(ColumnFamilyTuningError::InvalidFormat { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid_column_families_tuning() {
+        assert!(matches!(
+            "compression=lz4".parse::<ColumnFamiliesTuning>(),
+            Err(ColumnFamilyTuningError::InvalidFormat { .. })
+        ));
+        assert!(matches!(
+            "system_storage:compression".parse::<ColumnFamiliesTuning>(),
+            Err(ColumnFamilyTuningError::InvalidFormat { .. })
+        ));
+        assert!(matches!(
+            "system_storage:ttl=10".parse::<ColumnFamiliesTuning>(),
+            Err(ColumnFamilyTuningError::UnknownOption { .. })
+        ));
+        assert!(matches!(
+            "system_storage:compaction_style=fifo".parse::<ColumnFamiliesTuning>(),
+            Err(ColumnFamilyTuningError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            "system_storage:block_size=0".parse::<ColumnFamiliesTuning>(),
+            Err(ColumnFamilyTuningError::InvalidValue { .. })
+        ));
+    }
+}
@@ -4,6 +4,7 @@
 use std::path::Path;
 
 use derive_builder::Builder;
+use failure::Fail;
 
 pub use codec::{BincodeEncoded, Codec, Decoder, Encoder, SchemaError};
 pub use commit_log::{CommitLogError, CommitLogRef, CommitLogWithSchema, CommitLogs, Location};
@@ -39,6 +40,15 @@ where
     CommitLogs::new(path, cfs)
 }
 
+/// Open existing commit log at a given path just for reading (nothing is appended, flushed or compacted).
+pub fn open_cl_read_only<P, I>(path: P, cfs: I) -> Result<CommitLogs, CommitLogError>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = CommitLogDescriptor>,
+{
+    CommitLogs::new_read_only(path, cfs)
+}
+
 /// This trait extends basic column family by introducing Codec types safety and enforcement
 pub trait KeyValueSchema {
     type Key: Codec;
@@ -57,21 +67,16 @@ pub trait Persistable {
 pub trait MultiInstanceable {
     fn supports_multiple_opened_instances(&self) -> bool;
 
-    // TODO: TE-150 - real support mutliprocess
+    /// Catches up secondary (read-only) instance with changes written by the primary instance
     fn sync_with_primary(&self) -> Result<(), MultiInstanceableSyncError> {
-        if self.supports_multiple_opened_instances() {
-            Err(MultiInstanceableSyncError::new(
-                "Not implemented yet, please implement correctly!".to_string(),
-            ))
-        } else {
-            Err(MultiInstanceableSyncError::new(
-                "Not supported - supports_multiple_opened_instances is false".to_string(),
-            ))
-        }
+        Err(MultiInstanceableSyncError::new(
+            "Not supported - supports_multiple_opened_instances is false".to_string(),
+        ))
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Fail)]
+#[fail(display = "{}", _0)]
 pub struct MultiInstanceableSyncError(String);
 
 impl MultiInstanceableSyncError {
@@ -3,6 +3,7 @@
 
 use std::sync::Arc;
 
+use getset::{CopyGetters, Getters};
 use rocksdb::{Cache, ColumnFamilyDescriptor};
 use serde::{Deserialize, Serialize};
 
@@ -10,16 +11,18 @@ use crypto::hash::BlockHash;
 
 use crate::block_meta_storage::Meta;
 use crate::persistent::database::{
-    default_table_options, IteratorMode, IteratorWithSchema, RocksDbKeyValueSchema,
+    ColumnFamilyTuning, IteratorMode, IteratorWithSchema, RocksDbKeyValueSchema, SchemaWriteBatch,
 };
 use crate::persistent::{BincodeEncoded, KeyValueSchema, KeyValueStoreWithSchema};
 use crate::{PersistentStorage, StorageError};
 
 pub type PredecessorsIndexStorageKV = dyn KeyValueStoreWithSchema<PredecessorStorage> + Sync + Send;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Getters, CopyGetters)]
 pub struct PredecessorKey {
+    #[get = "pub"]
     block_hash: BlockHash,
+    #[get_copy = "pub"]
     exponent_slot: u32,
 }
 
@@ -50,13 +53,52 @@ impl PredecessorStorage {
         block_meta: &Meta,
         stored_predecessors_size: u32,
     ) -> Result<(), StorageError> {
+        self.store_predecessors_with(
+            block_hash,
+            block_meta,
+            stored_predecessors_size,
+            |key, predecessor| self.put(key, predecessor),
+        )
+    }
+
+    /// The same as [store_predecessors], but predecessors are just added to the `batch`
+    pub(crate) fn store_predecessors_to_batch(
+        &self,
+        block_hash: &BlockHash,
+        block_meta: &Meta,
+        stored_predecessors_size: u32,
+        batch: &mut SchemaWriteBatch,
+    ) -> Result<(), StorageError> {
+        self.store_predecessors_with(
+            block_hash,
+            block_meta,
+            stored_predecessors_size,
+            |key, predecessor| {
+                batch
+                    .put::<Self>(key, predecessor)
+                    .map_err(StorageError::from)
+            },
+        )
+    }
+
+    /// Predecessors of the block are resolved from already stored predecessors of its direct predecessor
+    fn store_predecessors_with<F>(
+        &self,
+        block_hash: &BlockHash,
+        block_meta: &Meta,
+        stored_predecessors_size: u32,
+        mut put: F,
+    ) -> Result<(), StorageError>
+    where
+        F: FnMut(&PredecessorKey, &BlockHash) -> Result<(), StorageError>,
+    {
         if let Some(direct_predecessor) = block_meta.predecessor() {
             // genesis
             if direct_predecessor == block_hash {
                 return Ok(());
             } else {
                 // put the direct predecessor to slot 0
-                self.put(
+                put(
                     &PredecessorKey::new(block_hash.clone(), 0),
                     direct_predecessor,
                 )?;
@@ -69,7 +111,7 @@ impl PredecessorStorage {
                     if let Some(p) = self.get(&predecessor_key)? {
                         let key =
                             PredecessorKey::new(block_hash.clone(), predecessor_exponent_slot);
-                        self.put(&key, &p)?;
+                        put(&key, &p)?;
                         predecessor = p;
                     } else {
                         return Ok(());
@@ -81,6 +123,19 @@ impl PredecessorStorage {
         Ok(())
     }
 
+    /// Removes all stored predecessors of the block
+    pub fn delete_predecessors(
+        &self,
+        block_hash: &BlockHash,
+        stored_predecessors_size: u32,
+    ) -> Result<(), StorageError> {
+        for exponent_slot in 0..stored_predecessors_size {
+            self.kv
+                .delete(&PredecessorKey::new(block_hash.clone(), exponent_slot))?;
+        }
+        Ok(())
+    }
+
     #[inline]
     pub fn put(
         &self,
@@ -111,8 +166,11 @@ impl KeyValueSchema for PredecessorStorage {
 }
 
 impl RocksDbKeyValueSchema for PredecessorStorage {
-    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        let cf_opts = default_table_options(cache);
+    fn descriptor_with_tuning(
+        cache: &Cache,
+        tuning: &ColumnFamilyTuning,
+    ) -> ColumnFamilyDescriptor {
+        let cf_opts = tuning.table_options(cache);
         ColumnFamilyDescriptor::new(Self::name(), cf_opts)
     }
 
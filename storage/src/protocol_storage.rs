@@ -0,0 +1,80 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Sources of the protocols downloaded from peers (p2p `Protocol` message), stored by protocol hash.
+//!
+//! Sources are served to other peers (`GetProtocols`) and chain manager loads them to the protocol runner
+//! (compiled and registered), so blocks with the downloaded protocol can be validated and applied.
+
+use std::sync::Arc;
+
+use crypto::hash::ProtocolHash;
+use tezos_messages::p2p::binary_message::BinaryMessage;
+use tezos_messages::p2p::encoding::prelude::*;
+
+use crate::persistent::database::RocksDbKeyValueSchema;
+use crate::persistent::{Decoder, Encoder, KeyValueSchema, KeyValueStoreWithSchema, SchemaError};
+use crate::{PersistentStorage, StorageError};
+
+pub type ProtocolStorageKV = dyn KeyValueStoreWithSchema<ProtocolStorage> + Sync + Send;
+
+/// Storage of the protocol sources
+#[derive(Clone)]
+pub struct ProtocolStorage {
+    kv: Arc<ProtocolStorageKV>,
+}
+
+impl ProtocolStorage {
+    pub fn new(persistent_storage: &PersistentStorage) -> Self {
+        Self {
+            kv: persistent_storage.db(),
+        }
+    }
+
+    #[inline]
+    pub fn put(
+        &self,
+        protocol_hash: &ProtocolHash,
+        protocol: &Protocol,
+    ) -> Result<(), StorageError> {
+        self.kv
+            .put(protocol_hash, protocol)
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn get(&self, protocol_hash: &ProtocolHash) -> Result<Option<Protocol>, StorageError> {
+        self.kv.get(protocol_hash).map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn contains(&self, protocol_hash: &ProtocolHash) -> Result<bool, StorageError> {
+        self.kv.contains(protocol_hash).map_err(StorageError::from)
+    }
+}
+
+impl KeyValueSchema for ProtocolStorage {
+    type Key = ProtocolHash;
+    type Value = Protocol;
+}
+
+impl RocksDbKeyValueSchema for ProtocolStorage {
+    #[inline]
+    fn name() -> &'static str {
+        "protocol_storage"
+    }
+}
+
+impl Decoder for Protocol {
+    #[inline]
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        Protocol::from_bytes(bytes).map_err(|_| SchemaError::DecodeError)
+    }
+}
+
+impl Encoder for Protocol {
+    #[inline]
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        self.as_bytes().map_err(|_| SchemaError::EncodeError)
+    }
+}
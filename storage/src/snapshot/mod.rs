@@ -0,0 +1,586 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! # Context snapshots
+//!
+//! Snapshot contains the whole merkle context of one applied block together with the block data
+//! needed to start a node from that block (header, additional data, chain metadata),
+//! so a new node can be bootstrapped without replaying the whole chain.
+//!
+//! File layout (all numbers are big-endian):
+//! ```no_compile
+//! [magic(8)][version(4)][header_len(4)][header(header_len)]
+//! [ENTRY_TAG(1)][entry_hash(32)][entry_len(4)][entry(entry_len)]   - repeated for every entry
+//! [END_TAG(1)][entries_count(8)][checksum(32)]
+//! ```
+//!
+//! * `header` - bincode encoded [SnapshotHeader]
+//! * `entry` - bincode encoded [Entry] exactly as stored in merkle storage, first entry is always the commit
+//! * `checksum` - blake2b-256 of all preceding bytes
+//!
+//! Snapshots are exported and imported by the `context-snapshot` binary.
+
+use std::array::TryFromSliceError;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::PoisonError;
+
+use blake2::digest::{InvalidOutputSize, Update, VariableOutput};
+use blake2::VarBlake2b;
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+use slog::{info, Logger};
+
+use crypto::hash::{BlockHash, ChainId, ContextHash};
+use tezos_messages::Head;
+
+use crate::chain_meta_storage::ChainMetaStorageReader;
+use crate::context::merkle::hash::{hash_entry, EntryHash, HashingError, ENTRY_HASH_LEN};
+use crate::context::merkle::merkle_storage::MerkleError;
+use crate::context::merkle::Entry;
+use crate::context::ContextValue;
+use crate::{
+    BlockAdditionalData, BlockHeaderWithHash, BlockJsonData, BlockMetaStorage, BlockStorage,
+    BlockStorageReader, ChainMetaStorage, PersistentStorage, StorageError,
+};
+
+pub mod octez;
+
+pub const SNAPSHOT_MAGIC: [u8; 8] = *b"TZDGSNAP";
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+const ENTRY_TAG: u8 = 1;
+const END_TAG: u8 = 0;
+const CHECKSUM_LEN: usize = 32;
+
+/// How many context entries are written to the kv store at once during import
+const IMPORT_BATCH_SIZE: usize = 4096;
+
+/// Lengths are read from untrusted file, so they are bounded before allocation
+const MAX_HEADER_LEN: usize = 16 * 1024 * 1024;
+const MAX_ENTRY_LEN: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Fail)]
+pub enum SnapshotError {
+    #[fail(display = "Snapshot I/O error: {}", error)]
+    IOError { error: io::Error },
+    #[fail(display = "Storage error: {}", error)]
+    StorageError { error: StorageError },
+    #[fail(display = "Merkle storage error: {}", error)]
+    MerkleError { error: MerkleError },
+    #[fail(display = "Serialization error: {}", error)]
+    SerializationError { error: bincode::Error },
+    #[fail(display = "Hashing error: {}", error)]
+    HashingError { error: HashingError },
+    #[fail(display = "Failed to convert hash: {}", error)]
+    HashConversionError { error: TryFromSliceError },
+    #[fail(display = "Failed to lock merkle storage, reason: {}", reason)]
+    LockError { reason: String },
+    #[fail(display = "Invalid snapshot, reason: {}", reason)]
+    InvalidSnapshot { reason: String },
+    #[fail(display = "Unsupported snapshot version: {}", version)]
+    UnsupportedVersion { version: u32 },
+    #[fail(display = "Snapshot checksum does not match")]
+    ChecksumMismatch,
+    #[fail(
+        display = "Entry hash mismatch, expected: {}, calculated: {}",
+        expected, calculated
+    )]
+    EntryHashMismatch {
+        expected: String,
+        calculated: String,
+    },
+    #[fail(
+        display = "Block {} cannot be exported, reason: {}",
+        block_hash, reason
+    )]
+    BlockNotExportable { block_hash: String, reason: String },
+    #[fail(display = "Snapshot can be imported only into empty storage")]
+    StorageNotEmpty,
+    #[fail(display = "Invalid Octez snapshot command length: {}", length)]
+    InvalidCommandLength { length: u64 },
+    #[fail(display = "Unsupported Octez snapshot version: {}", version)]
+    UnsupportedOctezVersion { version: String },
+    #[fail(
+        display = "Context hash mismatch for block {}, expected: {}, calculated: {}",
+        block_hash, expected, calculated
+    )]
+    ContextHashMismatch {
+        block_hash: String,
+        expected: String,
+        calculated: String,
+    },
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        SnapshotError::IOError { error }
+    }
+}
+
+impl From<StorageError> for SnapshotError {
+    fn from(error: StorageError) -> Self {
+        SnapshotError::StorageError { error }
+    }
+}
+
+impl From<MerkleError> for SnapshotError {
+    fn from(error: MerkleError) -> Self {
+        SnapshotError::MerkleError { error }
+    }
+}
+
+impl From<bincode::Error> for SnapshotError {
+    fn from(error: bincode::Error) -> Self {
+        SnapshotError::SerializationError { error }
+    }
+}
+
+impl From<HashingError> for SnapshotError {
+    fn from(error: HashingError) -> Self {
+        SnapshotError::HashingError { error }
+    }
+}
+
+impl From<InvalidOutputSize> for SnapshotError {
+    fn from(error: InvalidOutputSize) -> Self {
+        SnapshotError::HashingError {
+            error: error.into(),
+        }
+    }
+}
+
+impl From<TryFromSliceError> for SnapshotError {
+    fn from(error: TryFromSliceError) -> Self {
+        SnapshotError::HashConversionError { error }
+    }
+}
+
+impl<T> From<PoisonError<T>> for SnapshotError {
+    fn from(pe: PoisonError<T>) -> Self {
+        SnapshotError::LockError {
+            reason: format!("{}", pe),
+        }
+    }
+}
+
+/// Block data stored in snapshot, context hash is taken from `block.header.context()`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotHeader {
+    pub chain_id: ChainId,
+    pub block: BlockHeaderWithHash,
+    pub block_json_data: Option<BlockJsonData>,
+    pub block_additional_data: BlockAdditionalData,
+    pub genesis: Option<Head>,
+}
+
+/// Summary of exported/imported snapshot
+#[derive(Debug, Clone)]
+pub struct SnapshotInfo {
+    pub chain_id: ChainId,
+    pub block_hash: BlockHash,
+    pub level: i32,
+    pub context_hash: ContextHash,
+    pub entries_count: u64,
+}
+
+impl SnapshotInfo {
+    fn new(header: &SnapshotHeader, entries_count: u64) -> Self {
+        Self {
+            chain_id: header.chain_id.clone(),
+            block_hash: header.block.hash.clone(),
+            level: header.block.header.level(),
+            context_hash: header.block.header.context().clone(),
+            entries_count,
+        }
+    }
+}
+
+/// Writes snapshot of applied block `block_hash` and its context.
+pub fn export_snapshot<W: Write>(
+    writer: W,
+    block_hash: &BlockHash,
+    persistent_storage: &PersistentStorage,
+) -> Result<SnapshotInfo, SnapshotError> {
+    let block_storage = BlockStorage::new(persistent_storage);
+    let block_meta_storage = BlockMetaStorage::new(persistent_storage);
+    let chain_meta_storage = ChainMetaStorage::new(persistent_storage);
+
+    let not_exportable = |reason: &str| SnapshotError::BlockNotExportable {
+        block_hash: block_hash.to_base58_check(),
+        reason: reason.to_string(),
+    };
+
+    let meta = block_meta_storage
+        .get(block_hash)?
+        .ok_or_else(|| not_exportable("missing block metadata"))?;
+    if !meta.is_applied() {
+        return Err(not_exportable("block is not applied"));
+    }
+    let (block, block_additional_data) = block_storage
+        .get_with_additional_data(block_hash)?
+        .ok_or_else(|| not_exportable("missing block header or additional data"))?;
+    let block_json_data = block_storage
+        .get_with_json_data(block_hash)?
+        .map(|(_, json_data)| json_data);
+
+    let header = SnapshotHeader {
+        chain_id: meta.chain_id().clone(),
+        genesis: chain_meta_storage.get_genesis(meta.chain_id())?,
+        block,
+        block_json_data,
+        block_additional_data,
+    };
+    let context_hash: EntryHash = header
+        .block
+        .header
+        .context()
+        .as_ref()
+        .as_slice()
+        .try_into()?;
+
+    let mut writer = ChecksumWriter::new(writer)?;
+    writer.write_all(&SNAPSHOT_MAGIC)?;
+    writer.write_all(&SNAPSHOT_VERSION.to_be_bytes())?;
+    let header_bytes = bincode::serialize(&header)?;
+    writer.write_all(&(header_bytes.len() as u32).to_be_bytes())?;
+    writer.write_all(&header_bytes)?;
+
+    let mut entries_count = 0_u64;
+    {
+        let merkle = persistent_storage.merkle();
+        let merkle = merkle.read()?;
+        merkle.walk_commit_entries(&context_hash, |hash, entry| -> Result<(), SnapshotError> {
+            let entry_bytes = bincode::serialize(entry)?;
+            writer.write_all(&[ENTRY_TAG])?;
+            writer.write_all(hash)?;
+            writer.write_all(&(entry_bytes.len() as u32).to_be_bytes())?;
+            writer.write_all(&entry_bytes)?;
+            entries_count += 1;
+            Ok(())
+        })?;
+    }
+
+    writer.write_all(&[END_TAG])?;
+    writer.write_all(&entries_count.to_be_bytes())?;
+    writer.finish()?;
+
+    Ok(SnapshotInfo::new(&header, entries_count))
+}
+
+/// Reads snapshot and stores its context and block into empty storage, so the node can start from this block.
+///
+/// Snapshot is read twice. The first pass verifies hashes of all entries, completeness of the
+/// context graph and the file checksum, nothing is written. Only the second pass stores entries,
+/// block data and chain metadata, so a corrupted or truncated snapshot leaves the storage untouched.
+pub fn import_snapshot<R: Read + Seek>(
+    mut reader: R,
+    persistent_storage: &PersistentStorage,
+    log: &Logger,
+) -> Result<SnapshotInfo, SnapshotError> {
+    let start = reader.seek(SeekFrom::Current(0))?;
+
+    // snapshot block would replace current head, caboose and save point of the existing chain
+    let header = read_snapshot_header(&mut reader)?;
+    if ChainMetaStorage::new(persistent_storage)
+        .get_current_head(&header.chain_id)?
+        .is_some()
+    {
+        return Err(SnapshotError::StorageNotEmpty);
+    }
+
+    // verification pass
+    reader.seek(SeekFrom::Start(start))?;
+    let (header, entries_count) = read_snapshot(&mut reader, |_, _| Ok(()))?;
+    info!(log, "Importing context snapshot";
+               "block" => header.block.hash.to_base58_check(),
+               "level" => header.block.header.level(),
+               "context_hash" => header.block.header.context().to_base58_check(),
+               "entries_count" => entries_count);
+
+    // import pass
+    reader.seek(SeekFrom::Start(start))?;
+    let context_hash: EntryHash = header
+        .block
+        .header
+        .context()
+        .as_ref()
+        .as_slice()
+        .try_into()?;
+    let merkle = persistent_storage.merkle();
+    let mut merkle = merkle.write()?;
+    let mut batch: Vec<(EntryHash, ContextValue)> = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    read_snapshot(&mut reader, |hash, entry_bytes| {
+        batch.push((hash, entry_bytes));
+        if batch.len() >= IMPORT_BATCH_SIZE {
+            merkle.import_entries(std::mem::take(&mut batch))?;
+        }
+        Ok(())
+    })?;
+    if !batch.is_empty() {
+        merkle.import_entries(batch)?;
+    }
+
+    // all entries are stored, so we can move context to the snapshot commit
+    merkle.checkout(&context_hash)?;
+    drop(merkle);
+
+    store_snapshot_block(&header, persistent_storage, log)?;
+
+    let info = SnapshotInfo::new(&header, entries_count);
+    info!(log, "Context snapshot imported";
+               "block" => info.block_hash.to_base58_check(),
+               "entries_count" => info.entries_count);
+    Ok(info)
+}
+
+/// Reads and verifies the whole snapshot, every verified entry is passed to `on_entry`.
+///
+/// Returns header and count of entries, fails if any entry does not match its hash,
+/// if any entry referenced by the commit or trees is missing or if the checksum does not match.
+fn read_snapshot<R, F>(reader: R, mut on_entry: F) -> Result<(SnapshotHeader, u64), SnapshotError>
+where
+    R: Read,
+    F: FnMut(EntryHash, ContextValue) -> Result<(), SnapshotError>,
+{
+    let mut reader = ChecksumReader::new(reader)?;
+    let header = read_snapshot_header(&mut reader)?;
+    let context_hash: EntryHash = header
+        .block
+        .header
+        .context()
+        .as_ref()
+        .as_slice()
+        .try_into()?;
+
+    // entries are written parents first, so every referenced hash has to show up later in the file
+    let mut seen: HashSet<EntryHash> = HashSet::new();
+    let mut missing: HashSet<EntryHash> = HashSet::new();
+    let mut entries_count = 0_u64;
+    loop {
+        let mut tag = [0u8; 1];
+        reader.read_exact(&mut tag)?;
+        match tag[0] {
+            ENTRY_TAG => {
+                let mut hash: EntryHash = [0u8; ENTRY_HASH_LEN];
+                reader.read_exact(&mut hash)?;
+                let entry_len = read_u32(&mut reader)? as usize;
+                if entry_len > MAX_ENTRY_LEN {
+                    return Err(SnapshotError::InvalidSnapshot {
+                        reason: format!("entry too big: {}", entry_len),
+                    });
+                }
+                let mut entry_bytes = vec![0u8; entry_len];
+                reader.read_exact(&mut entry_bytes)?;
+
+                let entry: Entry = bincode::deserialize(&entry_bytes)?;
+                if entries_count == 0
+                    && (hash != context_hash || !matches!(entry, Entry::Commit(_)))
+                {
+                    return Err(SnapshotError::InvalidSnapshot {
+                        reason: "first entry is not the block context commit".to_string(),
+                    });
+                }
+                let calculated = hash_entry(&entry)?;
+                if calculated != hash {
+                    return Err(SnapshotError::EntryHashMismatch {
+                        expected: hex::encode(&hash),
+                        calculated: hex::encode(&calculated),
+                    });
+                }
+
+                missing.remove(&hash);
+                seen.insert(hash);
+                match &entry {
+                    // parent commit is not part of the snapshot
+                    Entry::Commit(commit) => {
+                        if !seen.contains(&commit.root_hash) {
+                            missing.insert(commit.root_hash);
+                        }
+                    }
+                    Entry::Tree(tree) => {
+                        for node in tree.values() {
+                            if !seen.contains(node.entry_hash.as_ref()) {
+                                missing.insert(*node.entry_hash);
+                            }
+                        }
+                    }
+                    Entry::Blob(_) => (),
+                }
+
+                on_entry(hash, entry_bytes)?;
+                entries_count += 1;
+            }
+            END_TAG => break,
+            tag => {
+                return Err(SnapshotError::InvalidSnapshot {
+                    reason: format!("unexpected tag: {}", tag),
+                })
+            }
+        }
+    }
+
+    let expected_entries_count = read_u64(&mut reader)?;
+    if expected_entries_count != entries_count {
+        return Err(SnapshotError::InvalidSnapshot {
+            reason: format!(
+                "expected {} entries, but found {}",
+                expected_entries_count, entries_count
+            ),
+        });
+    }
+    if !missing.is_empty() {
+        return Err(SnapshotError::InvalidSnapshot {
+            reason: format!("{} referenced context entries are missing", missing.len()),
+        });
+    }
+    reader.verify_checksum()?;
+
+    Ok((header, entries_count))
+}
+
+/// Reads and validates snapshot magic, version and header
+fn read_snapshot_header<R: Read>(reader: &mut R) -> Result<SnapshotHeader, SnapshotError> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if magic != SNAPSHOT_MAGIC {
+        return Err(SnapshotError::InvalidSnapshot {
+            reason: "not a tezedge snapshot file".to_string(),
+        });
+    }
+    let version = read_u32(reader)?;
+    if version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion { version });
+    }
+    let header_len = read_u32(reader)? as usize;
+    if header_len > MAX_HEADER_LEN {
+        return Err(SnapshotError::InvalidSnapshot {
+            reason: format!("header too big: {}", header_len),
+        });
+    }
+    let mut header_bytes = vec![0u8; header_len];
+    reader.read_exact(&mut header_bytes)?;
+    Ok(bincode::deserialize(&header_bytes)?)
+}
+
+/// Stores block from snapshot header and sets it as caboose and current head of the chain
+fn store_snapshot_block(
+    header: &SnapshotHeader,
+    persistent_storage: &PersistentStorage,
+    log: &Logger,
+) -> Result<(), SnapshotError> {
+    let block_storage = BlockStorage::new(persistent_storage);
+    let block_meta_storage = BlockMetaStorage::new(persistent_storage);
+    let chain_meta_storage = ChainMetaStorage::new(persistent_storage);
+    let block = &header.block;
+
+    block_storage.put_block_header(block)?;
+    block_storage.put_block_additional_data(&block.hash, header.block_additional_data.clone())?;
+    if let Some(json_data) = &header.block_json_data {
+        block_storage.put_block_json_data(&block.hash, json_data.clone())?;
+    }
+    block_storage.assign_to_context(&block.hash, block.header.context())?;
+
+    // creates also metadata of the predecessor (with this block as successor)
+    let mut meta = block_meta_storage.put_block_header(block, &header.chain_id, log)?;
+    meta.set_is_applied(true);
+    block_meta_storage.put(&block.hash, &meta)?;
+    block_meta_storage.store_predecessors(&block.hash, &meta)?;
+
+    if let Some(genesis) = &header.genesis {
+        if chain_meta_storage.get_genesis(&header.chain_id)?.is_none() {
+            chain_meta_storage.set_genesis(&header.chain_id, genesis.clone())?;
+        }
+    }
+    let head = Head::new(
+        block.hash.clone(),
+        block.header.level(),
+        block.header.fitness().clone(),
+    );
+    chain_meta_storage.set_caboose(&header.chain_id, head.clone())?;
+    chain_meta_storage.set_save_point(&header.chain_id, head.clone())?;
+    chain_meta_storage.set_current_head(&header.chain_id, head)?;
+    Ok(())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, SnapshotError> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64, SnapshotError> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// Writer which calculates checksum of all written bytes
+struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: VarBlake2b,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn new(inner: W) -> Result<Self, SnapshotError> {
+        Ok(Self {
+            inner,
+            hasher: VarBlake2b::new(CHECKSUM_LEN)?,
+        })
+    }
+
+    /// Appends checksum (not included in itself) and flushes inner writer
+    fn finish(mut self) -> Result<(), SnapshotError> {
+        let checksum = self.hasher.finalize_boxed();
+        self.inner.write_all(&checksum)?;
+        self.inner.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reader which calculates checksum of all read bytes
+struct ChecksumReader<R: Read> {
+    inner: R,
+    hasher: VarBlake2b,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    fn new(inner: R) -> Result<Self, SnapshotError> {
+        Ok(Self {
+            inner,
+            hasher: VarBlake2b::new(CHECKSUM_LEN)?,
+        })
+    }
+
+    /// Reads trailing checksum and compares it with checksum of all read bytes
+    fn verify_checksum(mut self) -> Result<(), SnapshotError> {
+        let mut expected = [0u8; CHECKSUM_LEN];
+        self.inner.read_exact(&mut expected)?;
+        if self.hasher.finalize_boxed()[..] == expected[..] {
+            Ok(())
+        } else {
+            Err(SnapshotError::ChecksumMismatch)
+        }
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
@@ -0,0 +1,924 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! # Octez snapshots
+//!
+//! Reader for snapshots exported by the OCaml node (`tezos-node snapshot export`), so tezedge
+//! can be bootstrapped from the same `.full`/`.rolling` files as the OCaml node.
+//!
+//! Snapshot is a sequence of length-prefixed commands (all numbers are big-endian):
+//! ```no_compile
+//! [len(8)][version_len(4)][version][history_mode(1)]        - metadata, always first
+//! [len(8)][tag(1)][body(len - 1)]                           - repeated command
+//! ```
+//!
+//! * `'b'` - context blob `[len(4)][bytes]`
+//! * `'d'` - context node, list of children `[name_len(4)][name][kind(1)][hash(32)]`,
+//!   children are always sent before their parents and the root node is the last one before `'r'`
+//! * `'r'` - root: `[header_len(4)][header][author_len(4)][author][message_len(4)][message]`
+//!   `[timestamp(8)][parents_len(4)][parents(32*n)][block_data_len(4)][block_data]`, the rest is ignored
+//!   * `block_data` - `[header_len(4)][header][operations_len(4)][operations_per_pass]`
+//!   * `operations_per_pass` - `[pass_len(4)][operation_len(4)][operation]...` for every validation pass
+//! * `'p'` - pruned block (history below the root) `[header_len(4)][header][operations_len(4)][operations]`
+//!   * `operations` - `[pass(4)][pass_len(4)][operation_len(4)][operation]...`
+//! * `'l'` - protocol data (loot), ignored
+//! * `'e'` - end of snapshot
+//!
+//! Context entries are streamed to the merkle key-value store and the commit of the root node
+//! must match the `context` field of the root block header, otherwise no block is stored
+//! and nothing is set as current head.
+
+use std::collections::HashSet;
+use std::convert::{TryFrom, TryInto};
+use std::io::Read;
+use std::sync::Arc;
+
+use slog::{info, Logger};
+
+use crypto::hash::{BlockHash, ChainId, ContextHash, HashType};
+use tezos_api::environment::TezosEnvironmentConfiguration;
+use tezos_messages::p2p::binary_message::BinaryMessage;
+use tezos_messages::p2p::encoding::prelude::{
+    BlockHeader, Operation, OperationsForBlock, OperationsForBlocksMessage, Path,
+};
+use tezos_messages::Head;
+
+use crate::chain_meta_storage::ChainMetaStorageReader;
+use crate::context::merkle::hash::{hash_blob, hash_commit, hash_tree, EntryHash, ENTRY_HASH_LEN};
+use crate::context::merkle::merkle_storage::MerkleStorage;
+use crate::context::merkle::{Commit, Entry, Node, NodeKind, Tree};
+use crate::context::ContextValue;
+use crate::snapshot::{read_u32, read_u64, SnapshotError, IMPORT_BATCH_SIZE};
+use crate::{
+    block_meta_storage, operations_meta_storage, BlockAdditionalDataBuilder, BlockHeaderWithHash,
+    BlockMetaStorage, BlockStorage, ChainMetaStorage, OperationsMetaStorage, OperationsStorage,
+    PersistentStorage, StorageError,
+};
+
+/// Supported snapshot versions, see `Snapshot_version` in the OCaml node
+pub const SUPPORTED_OCTEZ_VERSIONS: [&str; 2] = ["tezos-snapshot-1.0.0", "tezos-snapshot-1.1.0"];
+
+const BLOB_TAG: u8 = b'b';
+const NODE_TAG: u8 = b'd';
+const ROOT_TAG: u8 = b'r';
+const PRUNED_BLOCK_TAG: u8 = b'p';
+const LOOT_TAG: u8 = b'l';
+const END_TAG: u8 = b'e';
+
+const NODE_KIND_NODE: u8 = 0;
+const NODE_KIND_BLOB: u8 = 1;
+
+/// Commands are length-prefixed by untrusted u64, so their size is bounded before allocation
+const MAX_COMMAND_LEN: usize = 512 * 1024 * 1024;
+
+/// Block metadata (with `max_operations_ttl`) are not part of the snapshot,
+/// all protocols since 003 use the same value
+const MAX_OPERATIONS_TTL: i32 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OctezHistoryMode {
+    Archive,
+    Full,
+    Rolling,
+}
+
+impl TryFrom<u8> for OctezHistoryMode {
+    type Error = SnapshotError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(OctezHistoryMode::Archive),
+            1 => Ok(OctezHistoryMode::Full),
+            2 => Ok(OctezHistoryMode::Rolling),
+            mode => Err(SnapshotError::InvalidSnapshot {
+                reason: format!("unknown history mode: {}", mode),
+            }),
+        }
+    }
+}
+
+/// Summary of imported Octez snapshot
+#[derive(Debug, Clone)]
+pub struct OctezSnapshotInfo {
+    pub version: String,
+    pub history_mode: OctezHistoryMode,
+    pub block_hash: BlockHash,
+    pub level: i32,
+    pub context_hash: ContextHash,
+    pub pruned_blocks_count: usize,
+}
+
+/// Root block of the snapshot with its context commit info
+struct SnapshotRoot {
+    block: BlockHeaderWithHash,
+    author: String,
+    message: String,
+    timestamp: i64,
+    parent: Option<ContextHash>,
+    operations: Vec<Vec<Operation>>,
+}
+
+/// Imports snapshot exported by the OCaml node into empty storage.
+///
+/// Context entries are streamed directly to the merkle key-value store in batches, the commit
+/// is created only when its hash matches the `context` of the root block header. Pruned blocks are stored
+/// to [BlockStorage], [OperationsStorage] and [BlockMetaStorage] as they are read (not applied), the root block
+/// is stored and applied and chain metadata (genesis, caboose, save point and current head) are set
+/// only after the end of the snapshot is reached, so a truncated snapshot never sets the current head.
+pub fn import_octez_snapshot<R: Read>(
+    mut reader: R,
+    tezos_env: &TezosEnvironmentConfiguration,
+    persistent_storage: &PersistentStorage,
+    log: &Logger,
+) -> Result<OctezSnapshotInfo, SnapshotError> {
+    let chain_id = tezos_env.main_chain_id().map_err(StorageError::from)?;
+    let chain_meta_storage = ChainMetaStorage::new(persistent_storage);
+    if chain_meta_storage.get_current_head(&chain_id)?.is_some() {
+        return Err(SnapshotError::StorageNotEmpty);
+    }
+
+    let (version, history_mode) = read_metadata(&mut reader)?;
+    info!(log, "Importing Octez snapshot"; "version" => &version, "history_mode" => format!("{:?}", history_mode));
+
+    let merkle = persistent_storage.merkle();
+    let mut merkle = merkle.write()?;
+    let mut context = ContextImporter::default();
+    let mut last_node: Option<EntryHash> = None;
+    let mut head: Option<(SnapshotRoot, ContextHash)> = None;
+    let mut caboose: Option<BlockHeaderWithHash> = None;
+    let mut pruned_blocks_count = 0;
+
+    loop {
+        let command_len = read_command_len(&mut reader)?;
+        let mut tag = [0u8; 1];
+        reader.read_exact(&mut tag)?;
+        let mut body = vec![0u8; command_len - 1];
+        reader.read_exact(&mut body)?;
+        let mut body = body.as_slice();
+
+        match tag[0] {
+            BLOB_TAG => {
+                let blob = read_bytes(&mut body)?;
+                context.put(&mut merkle, hash_blob(&blob)?, Entry::Blob(blob))?;
+            }
+            NODE_TAG => {
+                let tree = read_node(&mut body, |hash| context.contains(&merkle, hash))?;
+                let hash = hash_tree(&tree)?;
+                context.put(&mut merkle, hash, Entry::Tree(tree))?;
+                last_node = Some(hash);
+            }
+            ROOT_TAG => {
+                if head.is_some() {
+                    return Err(SnapshotError::InvalidSnapshot {
+                        reason: "multiple root blocks".to_string(),
+                    });
+                }
+                let root_hash = last_node
+                    .take()
+                    .ok_or_else(|| SnapshotError::InvalidSnapshot {
+                        reason: "root block without context".to_string(),
+                    })?;
+                let root = read_root(&mut body)?;
+
+                // nothing from the block is stored, until the context and the whole stream is verified
+                let context_hash = context.commit(&mut merkle, &root, root_hash)?;
+                head = Some((root, context_hash));
+            }
+            PRUNED_BLOCK_TAG => {
+                if head.is_none() {
+                    return Err(SnapshotError::InvalidSnapshot {
+                        reason: "pruned block before root block".to_string(),
+                    });
+                }
+                let (block, operations) = read_pruned_block(&mut body)?;
+                store_block(&block, &operations, &chain_id, persistent_storage, log)?;
+                pruned_blocks_count += 1;
+
+                let is_lower = caboose
+                    .as_ref()
+                    .map(|c| c.header.level() > block.header.level())
+                    .unwrap_or(true);
+                if is_lower {
+                    caboose = Some(block);
+                }
+            }
+            LOOT_TAG => (),
+            END_TAG => break,
+            tag => {
+                return Err(SnapshotError::InvalidSnapshot {
+                    reason: format!("unexpected command tag: {}", tag),
+                })
+            }
+        }
+    }
+    drop(merkle);
+
+    // end of the snapshot was reached, so the root block can be stored and applied
+    let (root, context_hash) = head.ok_or_else(|| SnapshotError::InvalidSnapshot {
+        reason: "missing root block".to_string(),
+    })?;
+    store_block(
+        &root.block,
+        &root.operations,
+        &chain_id,
+        persistent_storage,
+        log,
+    )?;
+    BlockStorage::new(persistent_storage).assign_to_context(&root.block.hash, &context_hash)?;
+    let block_meta_storage = BlockMetaStorage::new(persistent_storage);
+    let mut meta = block_meta_storage.get(&root.block.hash)?.ok_or_else(|| {
+        SnapshotError::InvalidSnapshot {
+            reason: "root block metadata was not stored".to_string(),
+        }
+    })?;
+    meta.set_is_applied(true);
+    block_meta_storage.put(&root.block.hash, &meta)?;
+    block_meta_storage.store_predecessors(&root.block.hash, &meta)?;
+
+    let block = root.block;
+    let caboose = caboose.unwrap_or_else(|| block.clone());
+
+    // block metadata are not part of the snapshot, so forks below the caboose cannot be handled anyway
+    BlockStorage::new(persistent_storage).put_block_additional_data(
+        &block.hash,
+        BlockAdditionalDataBuilder::default()
+            .max_operations_ttl(MAX_OPERATIONS_TTL.min(block.header.level()) as u16)
+            .last_allowed_fork_level(caboose.header.level())
+            .block_metadata_hash(None)
+            .ops_metadata_hash(None)
+            .ops_metadata_hashes(None)
+            .build()
+            .unwrap(),
+    )?;
+    store_genesis(tezos_env, &chain_id, persistent_storage)?;
+
+    chain_meta_storage.set_caboose(
+        &chain_id,
+        Head::new(
+            caboose.hash.clone(),
+            caboose.header.level(),
+            caboose.header.fitness().clone(),
+        ),
+    )?;
+    let head = Head::new(
+        block.hash.clone(),
+        block.header.level(),
+        block.header.fitness().clone(),
+    );
+    // pruned blocks do not contain metadata, so root block is the save_point
+    chain_meta_storage.set_save_point(&chain_id, head.clone())?;
+    chain_meta_storage.set_current_head(&chain_id, head)?;
+
+    let info = OctezSnapshotInfo {
+        version,
+        history_mode,
+        block_hash: block.hash,
+        level: block.header.level(),
+        context_hash,
+        pruned_blocks_count,
+    };
+    info!(log, "Octez snapshot imported";
+               "block" => info.block_hash.to_base58_check(),
+               "level" => info.level,
+               "pruned_blocks_count" => info.pruned_blocks_count);
+    Ok(info)
+}
+
+/// Writes context entries to the merkle key-value store in batches,
+/// only hashes of the not yet written batch are kept in memory
+#[derive(Default)]
+struct ContextImporter {
+    batch: Vec<(EntryHash, ContextValue)>,
+    batch_hashes: HashSet<EntryHash>,
+}
+
+impl ContextImporter {
+    fn contains(&self, merkle: &MerkleStorage, hash: &EntryHash) -> Result<bool, SnapshotError> {
+        Ok(self.batch_hashes.contains(hash) || merkle.contains_entry(hash)?)
+    }
+
+    fn put(
+        &mut self,
+        merkle: &mut MerkleStorage,
+        hash: EntryHash,
+        entry: Entry,
+    ) -> Result<(), SnapshotError> {
+        if self.batch_hashes.insert(hash) {
+            self.batch.push((hash, bincode::serialize(&entry)?));
+        }
+        if self.batch.len() >= IMPORT_BATCH_SIZE {
+            self.flush(merkle)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, merkle: &mut MerkleStorage) -> Result<(), SnapshotError> {
+        if !self.batch.is_empty() {
+            merkle.import_entries(std::mem::take(&mut self.batch))?;
+            self.batch_hashes.clear();
+        }
+        Ok(())
+    }
+
+    /// Creates commit of the root tree, which is stored only if its hash matches the block header,
+    /// so the context is never checked out for a block with different context
+    fn commit(
+        &mut self,
+        merkle: &mut MerkleStorage,
+        root: &SnapshotRoot,
+        root_hash: EntryHash,
+    ) -> Result<ContextHash, SnapshotError> {
+        self.flush(merkle)?;
+
+        let parent_commit_hash = match &root.parent {
+            Some(parent) => Some(parent.as_ref().as_slice().try_into()?),
+            None => None,
+        };
+        let commit = Commit {
+            parent_commit_hash,
+            root_hash,
+            time: root.timestamp as u64,
+            author: root.author.clone(),
+            message: root.message.clone(),
+        };
+        let commit_hash = hash_commit(&commit)?;
+        let calculated = ContextHash::try_from(&commit_hash[..]).map_err(|e| {
+            SnapshotError::InvalidSnapshot {
+                reason: format!("invalid commit hash: {}", e),
+            }
+        })?;
+        let expected = root.block.header.context();
+        if &calculated != expected {
+            return Err(SnapshotError::ContextHashMismatch {
+                block_hash: root.block.hash.to_base58_check(),
+                expected: expected.to_base58_check(),
+                calculated: calculated.to_base58_check(),
+            });
+        }
+
+        merkle.import_entries(vec![(
+            commit_hash,
+            bincode::serialize(&Entry::Commit(commit))?,
+        )])?;
+        merkle.checkout(&commit_hash)?;
+        Ok(calculated)
+    }
+}
+
+/// Genesis block is not part of the snapshot, but chain metadata has to point to it
+/// and it has to be marked as applied, otherwise the node would commit genesis again on startup
+fn store_genesis(
+    tezos_env: &TezosEnvironmentConfiguration,
+    chain_id: &ChainId,
+    persistent_storage: &PersistentStorage,
+) -> Result<(), SnapshotError> {
+    let genesis_hash = tezos_env
+        .genesis_header_hash()
+        .map_err(StorageError::from)?;
+
+    BlockMetaStorage::new(persistent_storage).put(
+        &genesis_hash,
+        &block_meta_storage::Meta::genesis_meta(&genesis_hash, chain_id, true),
+    )?;
+    OperationsMetaStorage::new(persistent_storage).put(
+        &genesis_hash,
+        &operations_meta_storage::Meta::genesis_meta(chain_id),
+    )?;
+
+    let chain_meta_storage = ChainMetaStorage::new(persistent_storage);
+    if chain_meta_storage.get_genesis(chain_id)?.is_none() {
+        chain_meta_storage.set_genesis(
+            chain_id,
+            Head::new(
+                genesis_hash,
+                block_meta_storage::Meta::GENESIS_LEVEL,
+                vec![],
+            ),
+        )?;
+    }
+    Ok(())
+}
+
+/// Stores block header with operations, block is not marked as applied
+fn store_block(
+    block: &BlockHeaderWithHash,
+    operations: &[Vec<Operation>],
+    chain_id: &ChainId,
+    persistent_storage: &PersistentStorage,
+    log: &Logger,
+) -> Result<(), SnapshotError> {
+    BlockStorage::new(persistent_storage).put_block_header(block)?;
+    BlockMetaStorage::new(persistent_storage).put_block_header(block, chain_id, log)?;
+
+    let operations_storage = OperationsStorage::new(persistent_storage);
+    let operations_meta_storage = OperationsMetaStorage::new(persistent_storage);
+    operations_meta_storage.put_block_header(block, chain_id.clone())?;
+    for (validation_pass, operations) in operations.iter().enumerate() {
+        let message = OperationsForBlocksMessage::new(
+            OperationsForBlock::new(block.hash.clone(), validation_pass as i8),
+            Path::op(),
+            operations.clone(),
+        );
+        operations_storage.put_operations(&message)?;
+        operations_meta_storage.put_operations(&message)?;
+    }
+    Ok(())
+}
+
+/// Reads length of the next command, which is bounded before its body is allocated
+fn read_command_len<R: Read>(reader: &mut R) -> Result<usize, SnapshotError> {
+    let length = read_u64(reader)?;
+    if length == 0 || length > MAX_COMMAND_LEN as u64 {
+        return Err(SnapshotError::InvalidCommandLength { length });
+    }
+    Ok(length as usize)
+}
+
+fn read_metadata<R: Read>(reader: &mut R) -> Result<(String, OctezHistoryMode), SnapshotError> {
+    let len = read_command_len(reader)?;
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body)?;
+    let mut body = body.as_slice();
+
+    let version = read_string(&mut body)?;
+    if !SUPPORTED_OCTEZ_VERSIONS.contains(&version.as_str()) {
+        return Err(SnapshotError::UnsupportedOctezVersion { version });
+    }
+    let mut mode = [0u8; 1];
+    body.read_exact(&mut mode)?;
+    Ok((version, mode[0].try_into()?))
+}
+
+/// Reads children of the context node, every child has to be already imported
+fn read_node<F>(body: &mut &[u8], mut contains: F) -> Result<Tree, SnapshotError>
+where
+    F: FnMut(&EntryHash) -> Result<bool, SnapshotError>,
+{
+    let mut tree = Tree::new();
+    while !body.is_empty() {
+        let name = read_string(body)?;
+        let mut kind = [0u8; 1];
+        body.read_exact(&mut kind)?;
+        let mut hash: EntryHash = [0u8; ENTRY_HASH_LEN];
+        body.read_exact(&mut hash)?;
+
+        // wrong kind changes the tree hash, so it is caught by the commit hash check
+        let node_kind = match kind[0] {
+            NODE_KIND_NODE => NodeKind::NonLeaf,
+            NODE_KIND_BLOB => NodeKind::Leaf,
+            kind => {
+                return Err(SnapshotError::InvalidSnapshot {
+                    reason: format!("invalid kind {} of child node: {}", kind, name),
+                })
+            }
+        };
+        if !contains(&hash)? {
+            return Err(SnapshotError::InvalidSnapshot {
+                reason: format!("missing child node: {}", name),
+            });
+        }
+        tree.insert(
+            name,
+            Arc::new(Node {
+                node_kind,
+                entry_hash: Arc::new(hash),
+            }),
+        );
+    }
+    Ok(tree)
+}
+
+fn read_root(body: &mut &[u8]) -> Result<SnapshotRoot, SnapshotError> {
+    let header = read_bytes(body)?;
+    let author = read_string(body)?;
+    let message = read_string(body)?;
+    let timestamp = read_u64(body)? as i64;
+
+    let parents = read_bytes(body)?;
+    if parents.len() % HashType::ContextHash.size() != 0 {
+        return Err(SnapshotError::InvalidSnapshot {
+            reason: "invalid context parents".to_string(),
+        });
+    }
+    // tezedge merkle supports only one parent
+    let parent = match parents.chunks(HashType::ContextHash.size()).next() {
+        Some(parent) => {
+            Some(
+                ContextHash::try_from(parent).map_err(|e| SnapshotError::InvalidSnapshot {
+                    reason: format!("invalid context parent: {}", e),
+                })?,
+            )
+        }
+        None => None,
+    };
+
+    let block_data = read_bytes(body)?;
+    let mut block_data = block_data.as_slice();
+    let block_header = read_bytes(&mut block_data)?;
+    if block_header != header {
+        return Err(SnapshotError::InvalidSnapshot {
+            reason: "root block data does not match root header".to_string(),
+        });
+    }
+    let operations_per_pass = read_bytes(&mut block_data)?;
+    let mut operations_per_pass = operations_per_pass.as_slice();
+    let mut operations = Vec::new();
+    while !operations_per_pass.is_empty() {
+        let pass = read_bytes(&mut operations_per_pass)?;
+        operations.push(read_operations(&mut pass.as_slice())?);
+    }
+
+    let block = decode_block_header(&header)?;
+    if operations.len() > block.header.validation_pass() as usize {
+        return Err(SnapshotError::InvalidSnapshot {
+            reason: format!("too many validation passes: {}", operations.len()),
+        });
+    }
+
+    Ok(SnapshotRoot {
+        block,
+        author,
+        message,
+        timestamp,
+        parent,
+        operations,
+    })
+}
+
+fn read_pruned_block(
+    body: &mut &[u8],
+) -> Result<(BlockHeaderWithHash, Vec<Vec<Operation>>), SnapshotError> {
+    let block = decode_block_header(&read_bytes(body)?)?;
+
+    let operations_bytes = read_bytes(body)?;
+    let mut operations_bytes = operations_bytes.as_slice();
+    let mut operations = vec![Vec::new(); block.header.validation_pass() as usize];
+    while !operations_bytes.is_empty() {
+        let pass = read_u32(&mut operations_bytes)? as i32;
+        let pass_bytes = read_bytes(&mut operations_bytes)?;
+        match operations.get_mut(pass as usize) {
+            Some(pass_operations) if pass >= 0 => {
+                *pass_operations = read_operations(&mut pass_bytes.as_slice())?
+            }
+            _ => {
+                return Err(SnapshotError::InvalidSnapshot {
+                    reason: format!("invalid validation pass: {}", pass),
+                })
+            }
+        }
+    }
+    Ok((block, operations))
+}
+
+fn read_operations(body: &mut &[u8]) -> Result<Vec<Operation>, SnapshotError> {
+    let mut operations = Vec::new();
+    while !body.is_empty() {
+        let operation = Operation::from_bytes(read_bytes(body)?).map_err(|e| {
+            SnapshotError::InvalidSnapshot {
+                reason: format!("invalid operation: {}", e),
+            }
+        })?;
+        operations.push(operation);
+    }
+    Ok(operations)
+}
+
+fn decode_block_header(bytes: &[u8]) -> Result<BlockHeaderWithHash, SnapshotError> {
+    let header = BlockHeader::from_bytes(bytes).map_err(|e| SnapshotError::InvalidSnapshot {
+        reason: format!("invalid block header: {}", e),
+    })?;
+    BlockHeaderWithHash::new(header).map_err(|e| SnapshotError::InvalidSnapshot {
+        reason: format!("invalid block header hash: {}", e),
+    })
+}
+
+fn read_bytes(body: &mut &[u8]) -> Result<Vec<u8>, SnapshotError> {
+    let len = read_u32(body)? as usize;
+    if len > body.len() {
+        return Err(SnapshotError::InvalidSnapshot {
+            reason: format!("length {} exceeds command size", len),
+        });
+    }
+    let (bytes, rest) = body.split_at(len);
+    *body = rest;
+    Ok(bytes.to_vec())
+}
+
+fn read_string(body: &mut &[u8]) -> Result<String, SnapshotError> {
+    String::from_utf8(read_bytes(body)?).map_err(|e| SnapshotError::InvalidSnapshot {
+        reason: format!("invalid string: {}", e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use failure::Error;
+
+    use tezos_api::environment::{TezosEnvironment, TEZOS_ENV};
+    use tezos_messages::p2p::encoding::prelude::BlockHeaderBuilder;
+
+    use crate::tests_common::TmpStorage;
+    use crate::{context_key, BlockMetaStorageReader, BlockStorageReader};
+
+    use super::*;
+
+    const AUTHOR: &str = "Tezos";
+    const MESSAGE: &str = "Genesis";
+    const TIMESTAMP: i64 = 5_635_634;
+
+    #[test]
+    fn test_import_octez_snapshot() -> Result<(), Error> {
+        let log = create_logger();
+        let tezos_env = tezos_env();
+        let chain_id = tezos_env.main_chain_id()?;
+        let (context, root_hash) = prepare_context()?;
+        let context_hash = commit_hash(&root_hash)?;
+
+        let (head, snapshot) = prepare_snapshot(&context, context_hash.clone())?;
+
+        let tmp_storage = TmpStorage::create_to_out_dir("__octez_snapshot_import")?;
+        let info =
+            import_octez_snapshot(snapshot.as_slice(), tezos_env, tmp_storage.storage(), &log)?;
+        assert_eq!(info.history_mode, OctezHistoryMode::Full);
+        assert_eq!(info.block_hash, head.hash);
+        assert_eq!(info.context_hash, context_hash);
+        assert_eq!(info.pruned_blocks_count, 0);
+
+        // context
+        {
+            let merkle = tmp_storage.storage().merkle();
+            let mut merkle = merkle.write().unwrap();
+            let commit: EntryHash = context_hash.as_ref().as_slice().try_into()?;
+            assert_eq!(
+                merkle.get_history(&commit, &context_key!("data/a"))?,
+                vec![1, 2]
+            );
+            assert_eq!(
+                merkle.get_history(&commit, &context_key!("data/b/c"))?,
+                vec![3]
+            );
+        }
+
+        // block and metadata
+        let block_storage = BlockStorage::new(tmp_storage.storage());
+        assert_eq!(block_storage.get(&head.hash)?, Some(head.clone()));
+        assert!(block_storage.contains_context_hash(&context_hash)?);
+        assert!(BlockMetaStorage::new(tmp_storage.storage()).is_applied(&head.hash)?);
+        assert!(OperationsMetaStorage::new(tmp_storage.storage()).is_complete(&head.hash)?);
+        let (_, additional_data) = block_storage.get_with_additional_data(&head.hash)?.unwrap();
+        assert_eq!(additional_data.max_operations_ttl(), 1);
+
+        // genesis is known and applied, so it is not committed again on startup
+        let genesis_hash = tezos_env.genesis_header_hash()?;
+        assert!(BlockMetaStorage::new(tmp_storage.storage()).is_applied(&genesis_hash)?);
+
+        let chain_meta_storage = ChainMetaStorage::new(tmp_storage.storage());
+        let current_head = chain_meta_storage.get_current_head(&chain_id)?.unwrap();
+        assert_eq!(current_head.block_hash(), &head.hash);
+        let genesis = chain_meta_storage.get_genesis(&chain_id)?.unwrap();
+        assert_eq!(genesis.block_hash(), &genesis_hash);
+
+        // second import is not allowed
+        let result =
+            import_octez_snapshot(snapshot.as_slice(), tezos_env, tmp_storage.storage(), &log);
+        assert!(matches!(result, Err(SnapshotError::StorageNotEmpty)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_octez_snapshot_context_hash_mismatch() -> Result<(), Error> {
+        let log = create_logger();
+        let tezos_env = tezos_env();
+        let chain_id = tezos_env.main_chain_id()?;
+        let (context, _) = prepare_context()?;
+
+        // header points to different context
+        let (head, snapshot) = prepare_snapshot(&context, ContextHash::try_from(vec![7; 32])?)?;
+
+        let tmp_storage = TmpStorage::create_to_out_dir("__octez_snapshot_mismatch")?;
+        let result =
+            import_octez_snapshot(snapshot.as_slice(), tezos_env, tmp_storage.storage(), &log);
+        assert!(matches!(
+            result,
+            Err(SnapshotError::ContextHashMismatch { .. })
+        ));
+        assert!(ChainMetaStorage::new(tmp_storage.storage())
+            .get_current_head(&chain_id)?
+            .is_none());
+        // block is not stored, when its context is not verified
+        assert!(BlockStorage::new(tmp_storage.storage())
+            .get(&head.hash)?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_octez_snapshot_missing_child() -> Result<(), Error> {
+        let log = create_logger();
+        let tezos_env = tezos_env();
+        let (mut context, root_hash) = prepare_context()?;
+        let context_hash = commit_hash(&root_hash)?;
+
+        // drop the first blob, which is referenced by the tree
+        context.remove(0);
+        let (head, snapshot) = prepare_snapshot(&context, context_hash)?;
+
+        let tmp_storage = TmpStorage::create_to_out_dir("__octez_snapshot_missing_child")?;
+        let result =
+            import_octez_snapshot(snapshot.as_slice(), tezos_env, tmp_storage.storage(), &log);
+        assert!(matches!(result, Err(SnapshotError::InvalidSnapshot { .. })));
+        assert!(BlockStorage::new(tmp_storage.storage())
+            .get(&head.hash)?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_octez_snapshot_truncated() -> Result<(), Error> {
+        let log = create_logger();
+        let tezos_env = tezos_env();
+        let chain_id = tezos_env.main_chain_id()?;
+        let (context, root_hash) = prepare_context()?;
+        let (head, snapshot) = prepare_snapshot(&context, commit_hash(&root_hash)?)?;
+
+        // end command (length and tag) is missing
+        let snapshot = &snapshot[..snapshot.len() - 9];
+
+        let tmp_storage = TmpStorage::create_to_out_dir("__octez_snapshot_truncated")?;
+        let result = import_octez_snapshot(snapshot, tezos_env, tmp_storage.storage(), &log);
+        assert!(matches!(result, Err(SnapshotError::IOError { .. })));
+        // verified root block is neither stored nor applied without the end of the snapshot
+        assert!(BlockStorage::new(tmp_storage.storage())
+            .get(&head.hash)?
+            .is_none());
+        assert!(!BlockMetaStorage::new(tmp_storage.storage()).is_applied(&head.hash)?);
+        assert!(ChainMetaStorage::new(tmp_storage.storage())
+            .get_current_head(&chain_id)?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_octez_snapshot_invalid_metadata_length() -> Result<(), Error> {
+        let log = create_logger();
+        let tezos_env = tezos_env();
+        let tmp_storage = TmpStorage::create_to_out_dir("__octez_snapshot_metadata_length")?;
+
+        for length in &[0, u64::MAX] {
+            let snapshot = length.to_be_bytes();
+            let result =
+                import_octez_snapshot(&snapshot[..], tezos_env, tmp_storage.storage(), &log);
+            assert!(matches!(
+                result,
+                Err(SnapshotError::InvalidCommandLength { length: l }) if l == *length
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn tezos_env() -> &'static TezosEnvironmentConfiguration {
+        TEZOS_ENV
+            .get(&TezosEnvironment::Sandbox)
+            .expect("no tezos environment configured")
+    }
+
+    /// Returns context commands (children first) and hash of the root node
+    fn prepare_context() -> Result<(Vec<Vec<u8>>, EntryHash), Error> {
+        let mut commands = Vec::new();
+
+        let a = vec![1, 2];
+        let c = vec![3];
+        commands.push(command(BLOB_TAG, &blob_body(&a)));
+        commands.push(command(BLOB_TAG, &blob_body(&c)));
+
+        let b_hash = hash_tree(&tree(&[("c", NodeKind::Leaf, hash_blob(&c)?)]))?;
+        commands.push(command(
+            NODE_TAG,
+            &node_body(&[("c", NODE_KIND_BLOB, hash_blob(&c)?)]),
+        ));
+
+        let data_hash = hash_tree(&tree(&[
+            ("a", NodeKind::Leaf, hash_blob(&a)?),
+            ("b", NodeKind::NonLeaf, b_hash),
+        ]))?;
+        commands.push(command(
+            NODE_TAG,
+            &node_body(&[
+                ("a", NODE_KIND_BLOB, hash_blob(&a)?),
+                ("b", NODE_KIND_NODE, b_hash),
+            ]),
+        ));
+
+        let root_hash = hash_tree(&tree(&[("data", NodeKind::NonLeaf, data_hash)]))?;
+        commands.push(command(
+            NODE_TAG,
+            &node_body(&[("data", NODE_KIND_NODE, data_hash)]),
+        ));
+
+        Ok((commands, root_hash))
+    }
+
+    /// Builds full snapshot with one root block pointing to `context_hash`
+    fn prepare_snapshot(
+        context: &[Vec<u8>],
+        context_hash: ContextHash,
+    ) -> Result<(BlockHeaderWithHash, Vec<u8>), Error> {
+        let header: BlockHeader = BlockHeaderBuilder::default()
+            .level(1)
+            .proto(0)
+            .predecessor("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?)
+            .timestamp(TIMESTAMP)
+            .validation_pass(0)
+            .operations_hash("LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc".try_into()?)
+            .fitness(vec![])
+            .context(context_hash)
+            .protocol_data(vec![])
+            .build()
+            .unwrap();
+        let header_bytes = header.as_bytes()?;
+        let head = BlockHeaderWithHash::new(header)?;
+
+        let mut snapshot = Vec::new();
+        let mut metadata = string_body(SUPPORTED_OCTEZ_VERSIONS[0]);
+        metadata.push(1);
+        snapshot.extend_from_slice(&(metadata.len() as u64).to_be_bytes());
+        snapshot.extend_from_slice(&metadata);
+
+        context.iter().for_each(|c| snapshot.extend_from_slice(c));
+
+        let mut block_data = blob_body(&header_bytes);
+        block_data.extend_from_slice(&blob_body(&[]));
+        let mut root = blob_body(&header_bytes);
+        root.extend_from_slice(&string_body(AUTHOR));
+        root.extend_from_slice(&string_body(MESSAGE));
+        root.extend_from_slice(&TIMESTAMP.to_be_bytes());
+        root.extend_from_slice(&blob_body(&[]));
+        root.extend_from_slice(&blob_body(&block_data));
+        snapshot.extend_from_slice(&command(ROOT_TAG, &root));
+        snapshot.extend_from_slice(&command(END_TAG, &[]));
+
+        Ok((head, snapshot))
+    }
+
+    fn commit_hash(root_hash: &EntryHash) -> Result<ContextHash, Error> {
+        let hash = hash_commit(&Commit {
+            parent_commit_hash: None,
+            root_hash: *root_hash,
+            time: TIMESTAMP as u64,
+            author: AUTHOR.to_string(),
+            message: MESSAGE.to_string(),
+        })?;
+        Ok(ContextHash::try_from(&hash[..])?)
+    }
+
+    fn tree(children: &[(&str, NodeKind, EntryHash)]) -> Tree {
+        children
+            .iter()
+            .map(|(name, node_kind, hash)| {
+                (
+                    name.to_string(),
+                    Arc::new(Node {
+                        node_kind: node_kind.clone(),
+                        entry_hash: Arc::new(*hash),
+                    }),
+                )
+            })
+            .collect()
+    }
+
+    fn command(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut command = ((body.len() + 1) as u64).to_be_bytes().to_vec();
+        command.push(tag);
+        command.extend_from_slice(body);
+        command
+    }
+
+    fn node_body(children: &[(&str, u8, EntryHash)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, kind, hash) in children {
+            body.extend_from_slice(&string_body(name));
+            body.push(*kind);
+            body.extend_from_slice(hash);
+        }
+        body
+    }
+
+    fn blob_body(bytes: &[u8]) -> Vec<u8> {
+        let mut body = (bytes.len() as u32).to_be_bytes().to_vec();
+        body.extend_from_slice(bytes);
+        body
+    }
+
+    fn string_body(value: &str) -> Vec<u8> {
+        blob_body(value.as_bytes())
+    }
+
+    fn create_logger() -> Logger {
+        Logger::root(slog::Discard, slog::o!())
+    }
+}
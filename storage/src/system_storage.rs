@@ -8,7 +8,7 @@ use serde::{Deserialize, Serialize};
 
 use crypto::hash::ChainId;
 
-use crate::persistent::database::{default_table_options, RocksDbKeyValueSchema};
+use crate::persistent::database::{ColumnFamilyTuning, RocksDbKeyValueSchema};
 use crate::persistent::{BincodeEncoded, KeyValueSchema, KeyValueStoreWithSchema};
 use crate::StorageError;
 
@@ -28,6 +28,15 @@ impl SystemStorage {
     const CHAIN_ID: &'static str = "chain_id";
     const DB_VERSION: &'static str = "db_version";
     const CHAIN_NAME: &'static str = "chain_name";
+    /// Set together with block storage indexes pointing to the compacted commit log, until the commit log is replaced
+    pub(crate) const BLOCK_STORAGE_COMPACTION: &'static str = "block_storage_compaction";
+    /// Block storage commit log offset, records bellow it were checked by the last storage recovery
+    pub(crate) const STORAGE_RECOVERY_CHECKPOINT: &'static str = "storage_recovery_checkpoint";
+    /// Id of the first context action indexed by key prefixes while recording
+    const CONTEXT_ACTIONS_KEY_PREFIX_INDEXED_FROM: &'static str =
+        "context_actions_key_prefix_indexed_from";
+    /// Id of the next context action to be backfilled to the key prefix index
+    const CONTEXT_ACTIONS_KEY_PREFIX_BACKFILL: &'static str = "context_actions_key_prefix_backfill";
 
     pub fn new(kv: Arc<SystemStorageKv>) -> Self {
         SystemStorage { kv }
@@ -105,6 +114,80 @@ impl SystemStorage {
             )
             .map_err(StorageError::from)
     }
+
+    #[inline]
+    pub fn is_block_storage_compaction_pending(&self) -> Result<bool, StorageError> {
+        self.kv
+            .contains(&Self::BLOCK_STORAGE_COMPACTION.to_string())
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn clear_block_storage_compaction(&mut self) -> Result<(), StorageError> {
+        self.kv
+            .delete(&Self::BLOCK_STORAGE_COMPACTION.to_string())
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn get_storage_recovery_checkpoint(&self) -> Result<Option<u64>, StorageError> {
+        self.kv
+            .get(&Self::STORAGE_RECOVERY_CHECKPOINT.to_string())
+            .map(|result| match result {
+                Some(SystemValue::Integer(value)) => Some(value as u64),
+                _ => None,
+            })
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn set_storage_recovery_checkpoint(&mut self, offset: u64) -> Result<(), StorageError> {
+        self.kv
+            .put(
+                &Self::STORAGE_RECOVERY_CHECKPOINT.to_string(),
+                &SystemValue::Integer(offset as i64),
+            )
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn get_context_actions_key_prefix_indexed_from(&self) -> Result<Option<u64>, StorageError> {
+        self.get_integer(Self::CONTEXT_ACTIONS_KEY_PREFIX_INDEXED_FROM)
+    }
+
+    #[inline]
+    pub fn set_context_actions_key_prefix_indexed_from(
+        &mut self,
+        id: u64,
+    ) -> Result<(), StorageError> {
+        self.set_integer(Self::CONTEXT_ACTIONS_KEY_PREFIX_INDEXED_FROM, id)
+    }
+
+    #[inline]
+    pub fn get_context_actions_key_prefix_backfill(&self) -> Result<Option<u64>, StorageError> {
+        self.get_integer(Self::CONTEXT_ACTIONS_KEY_PREFIX_BACKFILL)
+    }
+
+    #[inline]
+    pub fn set_context_actions_key_prefix_backfill(&mut self, id: u64) -> Result<(), StorageError> {
+        self.set_integer(Self::CONTEXT_ACTIONS_KEY_PREFIX_BACKFILL, id)
+    }
+
+    fn get_integer(&self, key: &str) -> Result<Option<u64>, StorageError> {
+        self.kv
+            .get(&key.to_string())
+            .map(|result| match result {
+                Some(SystemValue::Integer(value)) => Some(value as u64),
+                _ => None,
+            })
+            .map_err(StorageError::from)
+    }
+
+    fn set_integer(&mut self, key: &str, value: u64) -> Result<(), StorageError> {
+        self.kv
+            .put(&key.to_string(), &SystemValue::Integer(value as i64))
+            .map_err(StorageError::from)
+    }
 }
 
 impl KeyValueSchema for SystemStorage {
@@ -113,8 +196,11 @@ impl KeyValueSchema for SystemStorage {
 }
 
 impl RocksDbKeyValueSchema for SystemStorage {
-    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
-        let cf_opts = default_table_options(cache);
+    fn descriptor_with_tuning(
+        cache: &Cache,
+        tuning: &ColumnFamilyTuning,
+    ) -> ColumnFamilyDescriptor {
+        let cf_opts = tuning.table_options(cache);
         ColumnFamilyDescriptor::new(Self::name(), cf_opts)
     }
 
@@ -73,6 +73,58 @@ fn block_storage_assign_context() -> Result<(), Error> {
     Ok(())
 }
 
+#[test]
+fn block_storage_compact_commit_log() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__block_storage_compact_commit_log")?;
+    let storage = BlockStorage::new(tmp_storage.storage());
+
+    let block_header = make_test_block_header()?;
+    let context_hash = vec![1; HashType::ContextHash.size()].try_into()?;
+    storage.put_block_header(&block_header)?;
+    storage.assign_to_context(&block_header.hash, &context_hash)?;
+
+    // the first json data stays in commit log as garbage
+    storage.put_block_json_data(&block_header.hash, make_test_json_data("old"))?;
+    storage.put_block_json_data(&block_header.hash, make_test_json_data("new"))?;
+
+    let stats = storage.compact_commit_log()?;
+    assert_eq!(stats.live_records, 2);
+    assert!(stats.reclaimed_bytes > 0);
+
+    // all indexes point to compacted commit log
+    let (block_header_res, json_data) = storage.get_with_json_data(&block_header.hash)?.unwrap();
+    assert_eq!(block_header_res, block_header);
+    assert_eq!(json_data.block_header_proto_json(), "new");
+    assert_eq!(
+        storage.get_by_level(block_header.header.level())?.unwrap(),
+        block_header
+    );
+    assert_eq!(
+        storage.get_by_context_hash(&context_hash)?.unwrap(),
+        block_header
+    );
+
+    // compacted commit log is writable
+    storage.put_block_json_data(&block_header.hash, make_test_json_data("newest"))?;
+    let (_, json_data) = storage.get_with_json_data(&block_header.hash)?.unwrap();
+    assert_eq!(json_data.block_header_proto_json(), "newest");
+
+    // nothing to recover after finished compaction
+    storage.recover_commit_log_compaction()?;
+    assert_eq!(storage.get(&block_header.hash)?.unwrap(), block_header);
+
+    Ok(())
+}
+
+fn make_test_json_data(block_header_proto_json: &str) -> BlockJsonData {
+    BlockJsonDataBuilder::default()
+        .block_header_proto_json(block_header_proto_json.to_string())
+        .block_header_proto_metadata_json("{}".to_string())
+        .operations_proto_metadata_json("[]".to_string())
+        .build()
+        .unwrap()
+}
+
 fn make_test_block_header() -> Result<BlockHeaderWithHash, Error> {
     let message_bytes = hex::decode("00006d6e0102dd00defaf70c53e180ea148b349a6feb4795610b2abc7b07fe91ce50a90814000000005c1276780432bc1d3a28df9a67b363aa1638f807214bb8987e5f9c0abcbd69531facffd1c80000001100000001000000000800000000000c15ef15a6f54021cb353780e2847fb9c546f1d72c1dc17c3db510f45553ce501ce1de000000000003c762c7df00a856b8bfcaf0676f069f825ca75f37f2bee9fe55ba109cec3d1d041d8c03519626c0c0faa557e778cb09d2e0c729e8556ed6a7a518c84982d1f2682bc6aa753f")?;
     let block_header = BlockHeaderWithHash::new(BlockHeader::from_bytes(message_bytes)?)?;
@@ -0,0 +1,402 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::convert::{TryFrom, TryInto};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use failure::Error;
+use slog::{Drain, Level, Logger};
+
+use crypto::hash::{ChainId, ContextHash};
+use storage::chain_archive::{
+    archive_files, export_chain_data, import_chain_data, read_archive_file, ArchiveFormat,
+    ChainArchiveError, ExportConfig, MANIFEST_FILE,
+};
+use storage::chain_meta_storage::ChainMetaStorageReader;
+use storage::tests_common::TmpStorage;
+use storage::{
+    store_block_header, BlockAdditionalDataBuilder, BlockHeaderWithHash, BlockJsonDataBuilder,
+    BlockMetaStorage, BlockStorage, BlockStorageReader, ChainMetaStorage, OperationsMetaStorage,
+    OperationsStorage, OperationsStorageReader,
+};
+use tezos_messages::p2p::encoding::prelude::{
+    BlockHeaderBuilder, OperationsForBlock, OperationsForBlocksMessage, Path as OperationsPath,
+};
+
+const HEAD_LEVEL: i32 = 12;
+/// Blocks above this level are stored, but not applied
+const APPLIED_LEVEL: i32 = 10;
+
+#[test]
+fn test_export_import_json_lines() -> Result<(), Error> {
+    test_export_import(
+        "__chain_archive_json_lines",
+        ArchiveFormat::JsonLines,
+        3,
+        ExportConfig::DEFAULT_SEGMENT_SIZE,
+    )
+}
+
+#[test]
+fn test_export_import_columnar() -> Result<(), Error> {
+    test_export_import("__chain_archive_columnar", ArchiveFormat::Columnar, 2, 4)
+}
+
+fn test_export_import(
+    name: &str,
+    format: ArchiveFormat,
+    parallelism: usize,
+    segment_size: usize,
+) -> Result<(), Error> {
+    let log = create_logger();
+    let source = TmpStorage::create_to_out_dir(&format!("{}_source", name))?;
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+    let blocks = prepare_chain(&source, &chain_id, &log)?;
+
+    let config = ExportConfig {
+        format,
+        output_dir: clean_out_dir_path(&format!("{}_archive", name))?,
+        from_level: 0,
+        to_level: HEAD_LEVEL,
+        parallelism,
+        segment_size,
+    };
+    let stats = export_chain_data(source.storage(), &config, &log)?;
+    assert_eq!(blocks.len(), stats.exported_blocks);
+    assert_eq!(0, stats.resumed_blocks);
+    assert_eq!(parallelism, stats.files.len());
+
+    // files are complete and ordered by levels
+    let files = archive_files(&config.output_dir)?;
+    assert_eq!(stats.files, files);
+    let archived_levels = files
+        .iter()
+        .map(read_archive_file)
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .map(|block| block.level())
+        .collect::<Vec<_>>();
+    assert_eq!((0..=HEAD_LEVEL).collect::<Vec<_>>(), archived_levels);
+
+    // export again does nothing
+    let stats = export_chain_data(source.storage(), &config, &log)?;
+    assert_eq!(0, stats.exported_blocks);
+    assert_eq!(parallelism, stats.skipped_parts);
+
+    // import to empty storage
+    let target = TmpStorage::create_to_out_dir(&format!("{}_target", name))?;
+    let stats = import_chain_data(target.storage(), &files, &log)?;
+    assert_eq!(blocks.len(), stats.imported_blocks);
+    // genesis with json data is applied too
+    assert_eq!(APPLIED_LEVEL as usize + 1, stats.applied_blocks);
+
+    let block_storage = BlockStorage::new(target.storage());
+    let block_meta_storage = BlockMetaStorage::new(target.storage());
+    let operations_storage = OperationsStorage::new(target.storage());
+    let operations_meta_storage = OperationsMetaStorage::new(target.storage());
+    for block in &blocks {
+        let level = block.header.level();
+        assert_eq!(Some(block.clone()), block_storage.get_by_level(level)?);
+        assert!(block_storage.get_with_json_data(&block.hash)?.is_some());
+        assert_eq!(
+            level > 0 && level <= APPLIED_LEVEL,
+            block_storage
+                .get_with_additional_data(&block.hash)?
+                .is_some()
+        );
+        assert_eq!(
+            level <= APPLIED_LEVEL,
+            block_meta_storage.get(&block.hash)?.unwrap().is_applied()
+        );
+        assert_eq!(1, operations_storage.get_operations(&block.hash)?.len());
+        assert!(operations_meta_storage.is_complete(&block.hash)?);
+    }
+    // successors are linked
+    assert_eq!(
+        &vec![blocks[3].hash.clone()],
+        block_meta_storage
+            .get(&blocks[2].hash)?
+            .unwrap()
+            .successors()
+    );
+
+    let chain_meta_storage = ChainMetaStorage::new(target.storage());
+    let head = chain_meta_storage.get_current_head(&chain_id)?.unwrap();
+    assert_eq!(APPLIED_LEVEL, *head.level());
+    assert_eq!(&blocks[APPLIED_LEVEL as usize].hash, head.block_hash());
+    let caboose = chain_meta_storage.get_caboose(&chain_id)?.unwrap();
+    assert_eq!(&blocks[0].hash, caboose.block_hash());
+    let genesis = chain_meta_storage.get_genesis(&chain_id)?.unwrap();
+    assert_eq!(&blocks[0].hash, genesis.block_hash());
+    assert_eq!(
+        Some(genesis.block_hash()),
+        stats.genesis.as_ref().map(|genesis| genesis.block_hash())
+    );
+    Ok(())
+}
+
+#[test]
+fn test_import_keeps_lower_caboose() -> Result<(), Error> {
+    let log = create_logger();
+    let name = "__chain_archive_caboose";
+    let source = TmpStorage::create_to_out_dir(&format!("{}_source", name))?;
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+    let blocks = prepare_chain(&source, &chain_id, &log)?;
+
+    let config = ExportConfig {
+        format: ArchiveFormat::JsonLines,
+        output_dir: clean_out_dir_path(&format!("{}_archive", name))?,
+        from_level: 0,
+        to_level: HEAD_LEVEL,
+        parallelism: 2,
+        segment_size: ExportConfig::DEFAULT_SEGMENT_SIZE,
+    };
+    export_chain_data(source.storage(), &config, &log)?;
+    let files = archive_files(&config.output_dir)?;
+
+    // import of the higher part only, genesis is not known
+    let target = TmpStorage::create_to_out_dir(&format!("{}_target", name))?;
+    let chain_meta_storage = ChainMetaStorage::new(target.storage());
+    let stats = import_chain_data(target.storage(), &files[1..], &log)?;
+    let first_level = config.parts()[1].0;
+    assert_eq!(
+        &blocks[first_level as usize].hash,
+        stats.caboose.unwrap().block_hash()
+    );
+    assert!(stats.genesis.is_none());
+    assert!(chain_meta_storage.get_genesis(&chain_id)?.is_none());
+
+    // import of the lower part lowers caboose, import of the higher part again does not raise it
+    import_chain_data(target.storage(), &files[..1], &log)?;
+    let stats = import_chain_data(target.storage(), &files[1..], &log)?;
+    assert!(stats.caboose.is_none());
+    assert_eq!(
+        &blocks[0].hash,
+        chain_meta_storage
+            .get_caboose(&chain_id)?
+            .unwrap()
+            .block_hash()
+    );
+    assert!(chain_meta_storage.get_genesis(&chain_id)?.is_some());
+    Ok(())
+}
+
+#[test]
+fn test_export_refuses_different_plan() -> Result<(), Error> {
+    let log = create_logger();
+    let name = "__chain_archive_plan";
+    let source = TmpStorage::create_to_out_dir(&format!("{}_source", name))?;
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+    prepare_chain(&source, &chain_id, &log)?;
+
+    let config = ExportConfig {
+        format: ArchiveFormat::JsonLines,
+        output_dir: clean_out_dir_path(&format!("{}_archive", name))?,
+        from_level: 0,
+        to_level: HEAD_LEVEL,
+        parallelism: 2,
+        segment_size: ExportConfig::DEFAULT_SEGMENT_SIZE,
+    };
+    export_chain_data(source.storage(), &config, &log)?;
+    assert!(config.output_dir.join(MANIFEST_FILE).exists());
+
+    for different in &[
+        ExportConfig {
+            parallelism: 3,
+            ..config.clone()
+        },
+        ExportConfig {
+            to_level: HEAD_LEVEL - 1,
+            ..config.clone()
+        },
+    ] {
+        match export_chain_data(source.storage(), different, &log) {
+            Err(ChainArchiveError::ExportPlanMismatch { .. }) => (),
+            result => panic!("Expected plan mismatch, but got: {:?}", result),
+        }
+    }
+    // no overlapping parts were written
+    assert_eq!(
+        config
+            .parts()
+            .into_iter()
+            .map(|(from_level, to_level)| config.part_path(from_level, to_level))
+            .collect::<Vec<_>>(),
+        archive_files(&config.output_dir)?
+    );
+
+    // archive files of other parts are refused, even if the manifest is missing
+    fs::remove_file(config.output_dir.join(MANIFEST_FILE))?;
+    match export_chain_data(
+        source.storage(),
+        &ExportConfig {
+            parallelism: 3,
+            ..config.clone()
+        },
+        &log,
+    ) {
+        Err(ChainArchiveError::ExportPlanMismatch { .. }) => (),
+        result => panic!("Expected plan mismatch, but got: {:?}", result),
+    }
+
+    // the same plan is accepted
+    let stats = export_chain_data(source.storage(), &config, &log)?;
+    assert_eq!(2, stats.skipped_parts);
+    Ok(())
+}
+
+#[test]
+fn test_export_resume() -> Result<(), Error> {
+    for format in &[ArchiveFormat::JsonLines, ArchiveFormat::Columnar] {
+        let log = create_logger();
+        let name = format!("__chain_archive_resume_{}", format.extension());
+        let source = TmpStorage::create_to_out_dir(&format!("{}_source", name))?;
+        let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+        prepare_chain(&source, &chain_id, &log)?;
+
+        // export just first part of the levels to other directory
+        let config = ExportConfig {
+            format: *format,
+            output_dir: clean_out_dir_path(&format!("{}_archive", name))?,
+            from_level: 0,
+            to_level: HEAD_LEVEL,
+            parallelism: 1,
+            segment_size: 3,
+        };
+        let first_part_config = ExportConfig {
+            output_dir: clean_out_dir_path(&format!("{}_first_part", name))?,
+            to_level: 5,
+            ..config.clone()
+        };
+        let part_path = config.part_path(0, HEAD_LEVEL);
+        export_chain_data(source.storage(), &first_part_config, &log)?;
+
+        // simulate interrupted export - partial file with incomplete record at the end
+        fs::create_dir_all(&config.output_dir)?;
+        let partial_path = PathBuf::from(format!("{}.partial", part_path.to_str().unwrap()));
+        fs::copy(first_part_config.part_path(0, 5), &partial_path)?;
+        OpenOptions::new()
+            .append(true)
+            .open(&partial_path)?
+            .write_all(&[1, 0, 0, 0])?;
+
+        let stats = export_chain_data(source.storage(), &config, &log)?;
+        assert_eq!(6, stats.resumed_blocks);
+        assert_eq!(HEAD_LEVEL as usize + 1 - 6, stats.exported_blocks);
+        assert!(!partial_path.exists());
+
+        let levels = read_archive_file(&part_path)?
+            .into_iter()
+            .map(|block| block.level())
+            .collect::<Vec<_>>();
+        assert_eq!((0..=HEAD_LEVEL).collect::<Vec<_>>(), levels);
+    }
+    Ok(())
+}
+
+/// Stores chain of blocks with operations from genesis to `HEAD_LEVEL`, blocks up to `APPLIED_LEVEL` are applied
+fn prepare_chain(
+    tmp_storage: &TmpStorage,
+    chain_id: &ChainId,
+    log: &Logger,
+) -> Result<Vec<BlockHeaderWithHash>, Error> {
+    let block_storage = BlockStorage::new(tmp_storage.storage());
+    let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+    let operations_storage = OperationsStorage::new(tmp_storage.storage());
+    let operations_meta_storage = OperationsMetaStorage::new(tmp_storage.storage());
+
+    let mut blocks: Vec<BlockHeaderWithHash> = Vec::new();
+    for level in 0..=HEAD_LEVEL {
+        let predecessor = match blocks.last() {
+            Some(predecessor) => predecessor.hash.clone(),
+            None => "BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?,
+        };
+        let block = BlockHeaderWithHash::new(
+            BlockHeaderBuilder::default()
+                .level(level)
+                .proto(0)
+                .predecessor(predecessor)
+                .timestamp(5_635_634 + level as i64)
+                .validation_pass(1)
+                .operations_hash(
+                    "LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc".try_into()?,
+                )
+                .fitness(vec![vec![level as u8]])
+                .context(ContextHash::try_from(vec![level as u8; 32])?)
+                .protocol_data(vec![])
+                .build()
+                .unwrap(),
+        )?;
+
+        let (_, mut meta, _) = store_block_header(
+            &block_storage,
+            &block_meta_storage,
+            &operations_meta_storage,
+            &block,
+            chain_id,
+            log,
+        )?;
+        let operations = OperationsForBlocksMessage::new(
+            OperationsForBlock::new(block.hash.clone(), 0),
+            OperationsPath::op(),
+            vec![],
+        );
+        operations_storage.put_operations(&operations)?;
+        operations_meta_storage.put_operations(&operations)?;
+        block_storage.put_block_json_data(
+            &block.hash,
+            BlockJsonDataBuilder::default()
+                .block_header_proto_json("{}".to_string())
+                .block_header_proto_metadata_json("{}".to_string())
+                .operations_proto_metadata_json("[]".to_string())
+                .build()
+                .unwrap(),
+        )?;
+
+        if level > 0 && level <= APPLIED_LEVEL {
+            block_storage.put_block_additional_data(
+                &block.hash,
+                BlockAdditionalDataBuilder::default()
+                    .max_operations_ttl(60)
+                    .last_allowed_fork_level(0)
+                    .block_metadata_hash(None)
+                    .ops_metadata_hash(None)
+                    .ops_metadata_hashes(None)
+                    .build()
+                    .unwrap(),
+            )?;
+            meta.set_is_applied(true);
+            block_meta_storage.put(&block.hash, &meta)?;
+        }
+
+        blocks.push(block);
+    }
+
+    Ok(blocks)
+}
+
+fn create_logger() -> Logger {
+    let drain = slog_async::Async::new(
+        slog_term::FullFormat::new(slog_term::TermDecorator::new().build())
+            .build()
+            .fuse(),
+    )
+    .build()
+    .filter_level(Level::Info)
+    .fuse();
+
+    Logger::root(drain, slog::o!())
+}
+
+fn clean_out_dir_path(dir_name: &str) -> Result<PathBuf, Error> {
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR is not defined");
+    let path = Path::new(out_dir.as_str()).join(Path::new(dir_name));
+    if path.exists() {
+        fs::remove_dir_all(&path)?;
+    }
+    Ok(path)
+}
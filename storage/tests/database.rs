@@ -0,0 +1,113 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use failure::Error;
+use rocksdb::{Cache, Options, DB};
+
+use storage::persistent::database::{
+    open_kv, open_kv_as_secondary, ColumnFamiliesTuning, RocksDBStats, RocksDbKeyValueSchema,
+};
+use storage::persistent::DbConfiguration;
+use storage::SystemStorage;
+
+#[test]
+fn test_tuned_column_family_stats() -> Result<(), Error> {
+    let path = out_dir_path("__database_tuned_column_family_stats");
+    if path.exists() {
+        std::fs::remove_dir_all(&path)?;
+    }
+
+    {
+        let cache = Cache::new_lru_cache(32 * 1024 * 1024)?;
+        let tuning: ColumnFamiliesTuning =
+            "system_storage:compaction_style=universal,bloom_filter_bits=10,compression=lz4"
+                .parse()?;
+        let db = open_kv(
+            &path,
+            vec![tuning.descriptor::<SystemStorage>(&cache)],
+            &DbConfiguration::default(),
+        )?;
+        let mut system_storage = SystemStorage::new(Arc::new(db));
+        system_storage.set_db_version(5)?;
+        assert_eq!(Some(5), system_storage.get_db_version()?);
+    }
+
+    {
+        // reopen with default tuning, data are still readable
+        let cache = Cache::new_lru_cache(32 * 1024 * 1024)?;
+        let db = open_kv(
+            &path,
+            vec![ColumnFamiliesTuning::default().descriptor::<SystemStorage>(&cache)],
+            &DbConfiguration::default(),
+        )?;
+        let cf = db
+            .cf_handle("system_storage")
+            .expect("Missing system_storage");
+        db.flush_cf(cf)?;
+
+        let stats = RocksDBStats::collect(&db)?;
+        let system_storage_stats = stats
+            .column_families
+            .iter()
+            .find(|cf| cf.name == "system_storage")
+            .expect("Missing stats of system_storage");
+        assert!(system_storage_stats.size_on_disk > 0);
+        assert!(system_storage_stats.estimated_keys > 0);
+    }
+
+    assert!(DB::destroy(&Options::default(), &path).is_ok());
+    Ok(())
+}
+
+#[test]
+fn test_secondary_instance_catches_up_with_primary() -> Result<(), Error> {
+    let path = out_dir_path("__database_secondary_instance");
+    let secondary_path = out_dir_path("__database_secondary_instance_secondary");
+    for p in &[&path, &secondary_path] {
+        if p.exists() {
+            std::fs::remove_dir_all(p)?;
+        }
+    }
+
+    let cache = Cache::new_lru_cache(32 * 1024 * 1024)?;
+    let primary = Arc::new(open_kv(
+        &path,
+        vec![ColumnFamiliesTuning::default().descriptor::<SystemStorage>(&cache)],
+        &DbConfiguration::default(),
+    )?);
+    let secondary = Arc::new(open_kv_as_secondary(
+        &path,
+        &secondary_path,
+        vec![SystemStorage::name()],
+        &DbConfiguration::default(),
+    )?);
+
+    let mut primary_system_storage = SystemStorage::new(primary.clone());
+    let mut secondary_system_storage = SystemStorage::new(secondary.clone());
+    assert_eq!(None, secondary_system_storage.get_db_version()?);
+
+    primary_system_storage.set_db_version(7)?;
+    // secondary sees changes only after catch up
+    assert_eq!(None, secondary_system_storage.get_db_version()?);
+    secondary.try_catch_up_with_primary()?;
+    assert_eq!(Some(7), secondary_system_storage.get_db_version()?);
+
+    // secondary instance is read-only
+    assert!(secondary_system_storage.set_db_version(8).is_err());
+
+    drop(secondary_system_storage);
+    drop(secondary);
+    drop(primary_system_storage);
+    drop(primary);
+    assert!(DB::destroy(&Options::default(), &path).is_ok());
+    Ok(())
+}
+
+fn out_dir_path(dir_name: &str) -> PathBuf {
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR is not defined");
+    Path::new(out_dir.as_str()).join(Path::new(dir_name))
+}
@@ -0,0 +1,405 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::convert::{TryFrom, TryInto};
+
+use failure::Error;
+
+use crypto::hash::{BlockHash, ChainId, ContextHash, ProtocolHash};
+use storage::chain_meta_storage::ChainMetaStorageReader;
+use storage::history_mode::{BlockPruner, HistoryMode};
+use storage::tests_common::TmpStorage;
+use storage::{
+    BlockHeaderWithHash, BlockJsonDataBuilder, BlockMetaStorage, BlockMetaStorageReader,
+    BlockStorage, BlockStorageReader, ChainMetaStorage, OperationsMetaStorage, OperationsStorage,
+    OperationsStorageReader,
+};
+use tezos_messages::p2p::encoding::prelude::{
+    BlockHeaderBuilder, OperationsForBlock, OperationsForBlocksMessage, Path,
+};
+use tezos_messages::protocol::proto_006;
+use tezos_messages::Head;
+
+const BLOCKS_PER_CYCLE: i32 = 4;
+const HEAD_LEVEL: i32 = 12;
+// head_level - 1 cycle
+const KEEP_FROM_LEVEL: i32 = 8;
+
+#[test]
+fn test_prune_archive() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__history_mode_archive")?;
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+    let blocks = prepare_chain(&tmp_storage, &chain_id)?;
+    let head = to_head(&blocks[HEAD_LEVEL as usize]);
+
+    let mut pruner = BlockPruner::new(HistoryMode::Archive, tmp_storage.storage());
+    assert!(!pruner.should_prune(HEAD_LEVEL, BLOCKS_PER_CYCLE));
+    let stats = pruner.prune(&chain_id, &head, BLOCKS_PER_CYCLE)?;
+    assert_eq!(stats.pruned_to_level, None);
+
+    let block_storage = BlockStorage::new(tmp_storage.storage());
+    for block in &blocks {
+        assert!(block_storage.get_with_json_data(&block.hash)?.is_some());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_prune_full() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__history_mode_full")?;
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+    let blocks = prepare_chain(&tmp_storage, &chain_id)?;
+    let head = to_head(&blocks[HEAD_LEVEL as usize]);
+
+    let mut pruner = BlockPruner::new(
+        HistoryMode::Full {
+            additional_cycles: 1,
+        },
+        tmp_storage.storage(),
+    );
+    assert!(pruner.should_prune(HEAD_LEVEL, BLOCKS_PER_CYCLE));
+    let stats = pruner.prune(&chain_id, &head, BLOCKS_PER_CYCLE)?;
+    assert_eq!(stats.pruned_json_data_count, (KEEP_FROM_LEVEL - 1) as usize);
+    assert_eq!(stats.pruned_blocks_count, 0);
+    assert_eq!(stats.pruned_to_level, Some(KEEP_FROM_LEVEL));
+    // pruned json data are removed from commit log
+    assert!(stats.reclaimed_bytes > 0);
+
+    let block_storage = BlockStorage::new(tmp_storage.storage());
+    let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+    let operations_storage = OperationsStorage::new(tmp_storage.storage());
+    for block in &blocks {
+        let level = block.header.level();
+        let is_kept = level == 0 || level >= KEEP_FROM_LEVEL;
+        let has_json_data = block_storage.get_with_json_data(&block.hash)?.is_some();
+        assert_eq!(has_json_data, is_kept);
+
+        // headers, operations, metadata and indexes are kept
+        assert_eq!(block_storage.get(&block.hash)?.as_ref(), Some(block));
+        assert_eq!(block_storage.get_by_level(level)?.as_ref(), Some(block));
+        assert!(block_meta_storage.get(&block.hash)?.is_some());
+        assert_eq!(operations_storage.get_operations(&block.hash)?.len(), 1);
+        // context of pruned blocks is not assigned anymore
+        assert_eq!(
+            block_storage.contains_context_hash(block.header.context())?,
+            is_kept
+        );
+    }
+
+    let chain_meta_storage = ChainMetaStorage::new(tmp_storage.storage());
+    assert_eq!(
+        *chain_meta_storage
+            .get_save_point(&chain_id)?
+            .unwrap()
+            .level(),
+        KEEP_FROM_LEVEL
+    );
+    assert_eq!(
+        *chain_meta_storage.get_caboose(&chain_id)?.unwrap().level(),
+        0
+    );
+
+    // nothing more to prune for the same head
+    assert!(!pruner.should_prune(HEAD_LEVEL, BLOCKS_PER_CYCLE));
+    let stats = pruner.prune(&chain_id, &head, BLOCKS_PER_CYCLE)?;
+    assert_eq!(stats.pruned_json_data_count, 0);
+    Ok(())
+}
+
+#[test]
+fn test_prune_rolling() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__history_mode_rolling")?;
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+    let blocks = prepare_chain(&tmp_storage, &chain_id)?;
+    let head = to_head(&blocks[HEAD_LEVEL as usize]);
+
+    let mut pruner = BlockPruner::new(
+        HistoryMode::Rolling {
+            additional_cycles: 1,
+        },
+        tmp_storage.storage(),
+    );
+    let stats = pruner.prune(&chain_id, &head, BLOCKS_PER_CYCLE)?;
+    assert_eq!(stats.pruned_blocks_count, (KEEP_FROM_LEVEL - 1) as usize);
+    assert_eq!(stats.pruned_to_level, Some(KEEP_FROM_LEVEL));
+
+    let block_storage = BlockStorage::new(tmp_storage.storage());
+    let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+    let operations_storage = OperationsStorage::new(tmp_storage.storage());
+    let operations_meta_storage = OperationsMetaStorage::new(tmp_storage.storage());
+    for block in &blocks {
+        let level = block.header.level();
+        let is_kept = level == 0 || level >= KEEP_FROM_LEVEL;
+
+        assert_eq!(block_storage.get(&block.hash)?.is_some(), is_kept);
+        assert_eq!(block_storage.get_by_level(level)?.is_some(), is_kept);
+        assert_eq!(
+            block_storage.contains_context_hash(block.header.context())?,
+            is_kept
+        );
+        assert_eq!(
+            operations_storage.get_operations(&block.hash)?.is_empty(),
+            !is_kept
+        );
+        assert_eq!(operations_meta_storage.contains(&block.hash)?, is_kept);
+        assert_eq!(block_meta_storage.contains(&block.hash)?, is_kept);
+        assert_eq!(
+            block_meta_storage
+                .find_block_at_distance(block.hash.clone(), 1)?
+                .is_some(),
+            is_kept
+        );
+    }
+
+    let chain_meta_storage = ChainMetaStorage::new(tmp_storage.storage());
+    let caboose = chain_meta_storage.get_caboose(&chain_id)?.unwrap();
+    assert_eq!(*caboose.level(), KEEP_FROM_LEVEL);
+    assert_eq!(caboose.block_hash(), &blocks[KEEP_FROM_LEVEL as usize].hash);
+    Ok(())
+}
+
+#[test]
+fn test_prune_by_last_pruned_level() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__history_mode_last_pruned_level")?;
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+    let blocks = prepare_chain(&tmp_storage, &chain_id)?;
+
+    let mut pruner = BlockPruner::new(
+        HistoryMode::Full {
+            additional_cycles: 1,
+        },
+        tmp_storage.storage(),
+    );
+
+    // first head after start is always checked, even if it is not the first level of the cycle
+    assert!(pruner.should_prune(HEAD_LEVEL - 1, BLOCKS_PER_CYCLE));
+    let stats = pruner.prune(
+        &chain_id,
+        &to_head(&blocks[(HEAD_LEVEL - 1) as usize]),
+        BLOCKS_PER_CYCLE,
+    )?;
+    assert_eq!(stats.pruned_to_level, Some(KEEP_FROM_LEVEL - 1));
+
+    // new pruning is triggered after the next full cycle, regardless of the cycle boundaries
+    assert!(!pruner.should_prune(HEAD_LEVEL, BLOCKS_PER_CYCLE));
+    assert!(!pruner.should_prune(HEAD_LEVEL + BLOCKS_PER_CYCLE - 2, BLOCKS_PER_CYCLE));
+    assert!(pruner.should_prune(HEAD_LEVEL + BLOCKS_PER_CYCLE - 1, BLOCKS_PER_CYCLE));
+    // skipped heads (e.g. pruning was running) do not prevent next pruning
+    assert!(pruner.should_prune(HEAD_LEVEL + 3 * BLOCKS_PER_CYCLE, BLOCKS_PER_CYCLE));
+
+    // other cycle length (e.g. changed by protocol)
+    assert!(pruner.should_prune(HEAD_LEVEL + 1, 1));
+    Ok(())
+}
+
+#[test]
+fn test_prune_forks() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__history_mode_forks")?;
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+    let blocks = prepare_chain(&tmp_storage, &chain_id)?;
+    let head = to_head(&blocks[HEAD_LEVEL as usize]);
+
+    // fork branching bellow the new lowest level, which is longer than the kept part of the main chain
+    let mut old_fork = vec![];
+    let mut predecessor = blocks[2].hash.clone();
+    for level in 3..=HEAD_LEVEL {
+        let block = store_block(&tmp_storage, &chain_id, predecessor, level, 100)?;
+        predecessor = block.hash.clone();
+        old_fork.push(block);
+    }
+    // fork branching from the new lowest level is kept
+    let kept_fork = store_block(
+        &tmp_storage,
+        &chain_id,
+        blocks[KEEP_FROM_LEVEL as usize].hash.clone(),
+        KEEP_FROM_LEVEL + 1,
+        200,
+    )?;
+    // level index points to the last stored block (fork), so main chain has to be resolved from the head
+    let block_storage = BlockStorage::new(tmp_storage.storage());
+    assert_eq!(
+        block_storage.get_by_level(KEEP_FROM_LEVEL + 1)?.as_ref(),
+        Some(&kept_fork)
+    );
+
+    let mut pruner = BlockPruner::new(
+        HistoryMode::Full {
+            additional_cycles: 1,
+        },
+        tmp_storage.storage(),
+    );
+    let stats = pruner.prune(&chain_id, &head, BLOCKS_PER_CYCLE)?;
+    assert_eq!(stats.pruned_fork_blocks_count, old_fork.len());
+    assert_eq!(stats.pruned_to_level, Some(KEEP_FROM_LEVEL));
+
+    let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+    for block in &old_fork {
+        assert!(block_storage.get(&block.hash)?.is_none());
+        assert!(!block_meta_storage.contains(&block.hash)?);
+    }
+    assert!(block_storage.get(&kept_fork.hash)?.is_some());
+    assert!(block_meta_storage.contains(&kept_fork.hash)?);
+
+    // main chain is kept (full mode)
+    for block in &blocks {
+        assert!(block_storage.get(&block.hash)?.is_some());
+    }
+    // fork is not a successor anymore
+    assert_eq!(
+        block_meta_storage
+            .get(&blocks[2].hash)?
+            .unwrap()
+            .successors(),
+        &vec![blocks[3].hash.clone()]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_blocks_per_cycle_from_constants() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__history_mode_blocks_per_cycle")?;
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+    let blocks = prepare_chain(&tmp_storage, &chain_id)?;
+
+    // context with protocol 006 constants (blocks_per_cycle = 2048)
+    let context_hash = {
+        let constants = hex::decode("030000080000000020000001000000080000000010000000000000001e0000000000000028002080fa7e80c4f50900003fffffffffff80a0d9e61d03e8c8d007000001018088d54480d1ca0800000006d0a54cecb80b00000006d0a54cb5ee32e807a0a907000000000000a8c000000bb800001b58000001f400180000000000000002")?;
+        let protocol_hash = ProtocolHash::try_from(proto_006::PROTOCOL_HASH)?;
+        let merkle = tmp_storage.storage().merkle();
+        let mut merkle = merkle.write().unwrap();
+        merkle.set(1, &vec!["protocol".to_string()], protocol_hash.into())?;
+        merkle.set(
+            1,
+            &vec![
+                "data".to_string(),
+                "v1".to_string(),
+                "constants".to_string(),
+            ],
+            constants,
+        )?;
+        ContextHash::try_from(
+            merkle
+                .commit(0, "Tezos".to_string(), "Block 13".to_string())?
+                .to_vec(),
+        )?
+    };
+    let block = BlockHeaderWithHash::new(
+        BlockHeaderBuilder::default()
+            .level(HEAD_LEVEL + 1)
+            .proto(1)
+            .predecessor(blocks[HEAD_LEVEL as usize].hash.clone())
+            .timestamp(5_635_634 + HEAD_LEVEL as i64 + 1)
+            .validation_pass(1)
+            .operations_hash("LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc".try_into()?)
+            .fitness(vec![])
+            .context(context_hash)
+            .protocol_data(vec![])
+            .build()
+            .unwrap(),
+    )?;
+
+    let mut pruner = BlockPruner::new(
+        HistoryMode::Full {
+            additional_cycles: 1,
+        },
+        tmp_storage.storage(),
+    );
+    assert_eq!(pruner.blocks_per_cycle(&block)?, Some(2048));
+
+    // head 13 is not one cycle (2048) above the save point, so nothing is pruned
+    BlockStorage::new(tmp_storage.storage()).put_block_header(&block)?;
+    assert_eq!(pruner.prune_for_head(&chain_id, &to_head(&block))?, None);
+    assert!(BlockStorage::new(tmp_storage.storage())
+        .get_with_json_data(&blocks[1].hash)?
+        .is_some());
+    Ok(())
+}
+
+/// Stores chain of blocks with json data and operations from genesis to `HEAD_LEVEL`
+fn prepare_chain(
+    tmp_storage: &TmpStorage,
+    chain_id: &ChainId,
+) -> Result<Vec<BlockHeaderWithHash>, Error> {
+    let chain_meta_storage = ChainMetaStorage::new(tmp_storage.storage());
+
+    let mut blocks: Vec<BlockHeaderWithHash> = Vec::new();
+    for level in 0..=HEAD_LEVEL {
+        let predecessor = match blocks.last() {
+            Some(predecessor) => predecessor.hash.clone(),
+            None => "BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?,
+        };
+        blocks.push(store_block(tmp_storage, chain_id, predecessor, level, 0)?);
+    }
+
+    let genesis_head = to_head(&blocks[0]);
+    chain_meta_storage.set_caboose(chain_id, genesis_head.clone())?;
+    chain_meta_storage.set_save_point(chain_id, genesis_head)?;
+
+    Ok(blocks)
+}
+
+/// Stores applied block with json data, metadata and operations, `seed` distinguishes blocks of forks
+fn store_block(
+    tmp_storage: &TmpStorage,
+    chain_id: &ChainId,
+    predecessor: BlockHash,
+    level: i32,
+    seed: u8,
+) -> Result<BlockHeaderWithHash, Error> {
+    let block_storage = BlockStorage::new(tmp_storage.storage());
+    let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+    let operations_storage = OperationsStorage::new(tmp_storage.storage());
+    let operations_meta_storage = OperationsMetaStorage::new(tmp_storage.storage());
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+
+    let block = BlockHeaderWithHash::new(
+        BlockHeaderBuilder::default()
+            .level(level)
+            .proto(0)
+            .predecessor(predecessor)
+            .timestamp(5_635_634 + level as i64 + seed as i64)
+            .validation_pass(1)
+            .operations_hash("LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc".try_into()?)
+            .fitness(vec![])
+            .context(ContextHash::try_from(vec![level as u8 + seed; 32])?)
+            .protocol_data(vec![])
+            .build()
+            .unwrap(),
+    )?;
+
+    block_storage.put_block_header(&block)?;
+    block_storage.put_block_json_data(
+        &block.hash,
+        BlockJsonDataBuilder::default()
+            .block_header_proto_json("{}".to_string())
+            .block_header_proto_metadata_json("{}".to_string())
+            .operations_proto_metadata_json("[]".to_string())
+            .build()
+            .unwrap(),
+    )?;
+    block_storage.assign_to_context(&block.hash, block.header.context())?;
+
+    let mut meta = block_meta_storage.put_block_header(&block, chain_id, &log)?;
+    meta.set_is_applied(true);
+    block_meta_storage.put(&block.hash, &meta)?;
+    block_meta_storage.store_predecessors(&block.hash, &meta)?;
+
+    operations_meta_storage.put_block_header(&block, chain_id.clone())?;
+    let operations = OperationsForBlocksMessage::new(
+        OperationsForBlock::new(block.hash.clone(), 0),
+        Path::op(),
+        vec![],
+    );
+    operations_storage.put_operations(&operations)?;
+    operations_meta_storage.put_operations(&operations)?;
+
+    Ok(block)
+}
+
+fn to_head(block: &BlockHeaderWithHash) -> Head {
+    Head::new(
+        block.hash.clone(),
+        block.header.level(),
+        block.header.fitness().clone(),
+    )
+}
@@ -0,0 +1,180 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::convert::{TryFrom, TryInto};
+
+use failure::Error;
+
+use crypto::hash::{BlockHash, ChainId, ContextHash};
+use storage::block_meta_storage::Meta;
+use storage::block_storage::{BlockPrimaryIndex, BlockStorageColumnsLocation};
+use storage::integrity_check::check_storage;
+use storage::persistent::{KeyValueStoreBackend, Location};
+use storage::predecessor_storage::PredecessorKey;
+use storage::tests_common::TmpStorage;
+use storage::{
+    store_block_header, BlockHeaderWithHash, BlockMetaStorage, BlockStorage, OperationsMetaStorage,
+    PredecessorStorage,
+};
+use tezos_messages::p2p::encoding::prelude::BlockHeaderBuilder;
+
+#[test]
+fn test_check_consistent_storage() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__integrity_check_consistent")?;
+    let log = create_logger();
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+    let blocks = prepare_chain(&tmp_storage, &chain_id)?;
+
+    let report = check_storage(tmp_storage.storage(), true, &log)?;
+    assert!(report.is_ok(), "{:?}", report);
+    assert_eq!(report.block_storage.checked, blocks.len());
+    // metadata are checked for stored blocks (genesis header is not stored)
+    assert_eq!(report.block_meta_links.checked, blocks.len());
+    assert_eq!(report.block_meta_links.unmerged, 0);
+    // level 1: slot 0, level 2: slots 0-1, level 3: slots 0-1
+    assert_eq!(report.predecessor_index.checked, 5);
+    assert_eq!(report.applied_block_contexts.unwrap().checked, 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_check_corrupted_storage() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__integrity_check_corrupted")?;
+    let log = create_logger();
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+    let blocks = prepare_chain(&tmp_storage, &chain_id)?;
+    let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+    let predecessor_storage = PredecessorStorage::new(tmp_storage.storage());
+
+    // block, which header is not in commit log and which is not linked from its predecessor
+    let lost_block = make_test_block_header(4, blocks[2].hash.clone(), vec![4; 32])?;
+    KeyValueStoreBackend::<BlockPrimaryIndex>::put(
+        &*tmp_storage.storage().db(),
+        &lost_block.hash,
+        &BlockStorageColumnsLocation {
+            block_header: Location(1_000, 100),
+            block_json_data: None,
+            block_additional_data: None,
+        },
+    )?;
+    block_meta_storage.put(
+        &lost_block.hash,
+        &Meta::new(false, Some(blocks[2].hash.clone()), 4, chain_id.clone()),
+    )?;
+
+    // wrong predecessor at distance 2
+    predecessor_storage.put(
+        &PredecessorKey::new(blocks[2].hash.clone(), 1),
+        &blocks[2].hash,
+    )?;
+
+    // applied block without context
+    let mut meta = block_meta_storage.get(&blocks[1].hash)?.unwrap();
+    meta.set_is_applied(true);
+    block_meta_storage.put(&blocks[1].hash, &meta)?;
+
+    let report = check_storage(tmp_storage.storage(), true, &log)?;
+    assert!(!report.is_ok());
+
+    let lost_block_hash = lost_block.hash.to_base58_check();
+    assert_eq!(report.block_storage.issues.len(), 1);
+    assert_eq!(report.block_storage.issues[0].block_hash, lost_block_hash);
+
+    assert_eq!(report.block_meta_links.issues.len(), 1);
+    assert_eq!(
+        report.block_meta_links.issues[0].block_hash,
+        lost_block_hash
+    );
+
+    assert_eq!(report.predecessor_index.issues.len(), 1);
+    assert_eq!(
+        report.predecessor_index.issues[0].block_hash,
+        blocks[2].hash.to_base58_check()
+    );
+
+    let applied_block_contexts = report.applied_block_contexts.unwrap();
+    assert_eq!(applied_block_contexts.checked, 2);
+    assert_eq!(applied_block_contexts.issues.len(), 1);
+    assert_eq!(
+        applied_block_contexts.issues[0].block_hash,
+        blocks[1].hash.to_base58_check()
+    );
+
+    Ok(())
+}
+
+/// Stores blocks 1-3 with metadata and predecessors, the first block is applied with committed context
+fn prepare_chain(
+    tmp_storage: &TmpStorage,
+    chain_id: &ChainId,
+) -> Result<Vec<BlockHeaderWithHash>, Error> {
+    let log = create_logger();
+    let block_storage = BlockStorage::new(tmp_storage.storage());
+    let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+    let operations_meta_storage = OperationsMetaStorage::new(tmp_storage.storage());
+
+    let context_hash = {
+        let merkle = tmp_storage.storage().merkle();
+        let mut merkle = merkle.write().unwrap();
+        merkle.set(1, &vec!["data".to_string(), "a".to_string()], vec![1])?;
+        let commit_hash = merkle.commit(0, "Tezos".to_string(), "Block 1".to_string())?;
+        commit_hash.to_vec()
+    };
+
+    let mut blocks: Vec<BlockHeaderWithHash> = Vec::new();
+    for level in 1..=3 {
+        let predecessor = match blocks.last() {
+            Some(predecessor) => predecessor.hash.clone(),
+            None => "BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?,
+        };
+        let context = if level == 1 {
+            context_hash.clone()
+        } else {
+            vec![level as u8; 32]
+        };
+        let block = make_test_block_header(level, predecessor, context)?;
+
+        let (_, meta, _) = store_block_header(
+            &block_storage,
+            &block_meta_storage,
+            &operations_meta_storage,
+            &block,
+            chain_id,
+            &log,
+        )?;
+        block_meta_storage.store_predecessors(&block.hash, &meta)?;
+        blocks.push(block);
+    }
+
+    let mut meta = block_meta_storage.get(&blocks[0].hash)?.unwrap();
+    meta.set_is_applied(true);
+    block_meta_storage.put(&blocks[0].hash, &meta)?;
+
+    Ok(blocks)
+}
+
+fn make_test_block_header(
+    level: i32,
+    predecessor: BlockHash,
+    context: Vec<u8>,
+) -> Result<BlockHeaderWithHash, Error> {
+    Ok(BlockHeaderWithHash::new(
+        BlockHeaderBuilder::default()
+            .level(level)
+            .proto(0)
+            .predecessor(predecessor)
+            .timestamp(5_635_634 + level as i64)
+            .validation_pass(1)
+            .operations_hash("LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc".try_into()?)
+            .fitness(vec![])
+            .context(ContextHash::try_from(context)?)
+            .protocol_data(vec![])
+            .build()
+            .unwrap(),
+    )?)
+}
+
+fn create_logger() -> slog::Logger {
+    slog::Logger::root(slog::Discard, slog::o!())
+}
@@ -1,7 +1,7 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use crypto::hash::OperationHash;
 use failure::Error;
@@ -35,6 +35,25 @@ fn mempool_storage_read_write() -> Result<(), Error> {
     Ok(())
 }
 
+#[test]
+fn mempool_storage_iter_with_ttl() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__mempool_storage_iter_with_ttl")?;
+    let mut storage = MempoolStorage::new(tmp_storage.storage());
+
+    let operation = make_test_operation_message()?;
+    let operation_hash = operation.message_typed_hash::<OperationHash>()?;
+    let ttl = SystemTime::now() + Duration::from_secs(60);
+    storage.put_pending(operation.clone(), ttl)?;
+
+    let stored = storage.iter_with_ttl()?;
+    assert_eq!(1, stored.len());
+    assert_eq!(operation_hash, stored[0].0);
+    assert_eq!(operation, stored[0].1);
+    assert_eq!(ttl, stored[0].2);
+
+    Ok(())
+}
+
 fn make_test_operation_message() -> Result<OperationMessage, Error> {
     let message_bytes = hex::decode("10490b79070cf19175cd7e3b9c1ee66f6e85799980404b119132ea7e58a4a97e000008c387fa065a181d45d47a9b78ddc77e92a881779ff2cbabbf9646eade4bf1405a08e00b725ed849eea46953b10b5cdebc518e6fd47e69b82d2ca18c4cf6d2f312dd08")?;
     let operation = Operation::from_bytes(message_bytes)?;
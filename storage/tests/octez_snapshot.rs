@@ -0,0 +1,69 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Import of a snapshot exported by the OCaml node (`tezos-node snapshot export`).
+//!
+//! Snapshots have hundreds of megabytes, so the file is not part of the repository, run with:
+//! `OCTEZ_SNAPSHOT_FILE=<path> OCTEZ_SNAPSHOT_NETWORK=<network> cargo test --test octez_snapshot -- --ignored`
+
+use std::convert::TryInto;
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+
+use failure::Error;
+
+use storage::chain_meta_storage::ChainMetaStorageReader;
+use storage::context::EntryHash;
+use storage::snapshot::octez::import_octez_snapshot;
+use storage::tests_common::TmpStorage;
+use storage::{
+    BlockMetaStorage, BlockMetaStorageReader, BlockStorage, BlockStorageReader, ChainMetaStorage,
+};
+use tezos_api::environment::{TezosEnvironment, TEZOS_ENV};
+
+#[test]
+#[ignore]
+fn test_import_real_octez_snapshot() -> Result<(), Error> {
+    let snapshot_file = env::var("OCTEZ_SNAPSHOT_FILE").expect("OCTEZ_SNAPSHOT_FILE is not set");
+    let network: TezosEnvironment = env::var("OCTEZ_SNAPSHOT_NETWORK")
+        .expect("OCTEZ_SNAPSHOT_NETWORK is not set")
+        .parse()
+        .expect("OCTEZ_SNAPSHOT_NETWORK is not valid network");
+    let tezos_env = TEZOS_ENV
+        .get(&network)
+        .expect("no tezos environment configured");
+    let chain_id = tezos_env.main_chain_id()?;
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+
+    let tmp_storage = TmpStorage::create_to_out_dir("__octez_real_snapshot")?;
+    let reader = BufReader::new(File::open(&snapshot_file)?);
+    let info = import_octez_snapshot(reader, tezos_env, tmp_storage.storage(), &log)?;
+
+    // context hash of the real block was rebuilt from the snapshot
+    let block = BlockStorage::new(tmp_storage.storage())
+        .get(&info.block_hash)?
+        .expect("root block is not stored");
+    assert_eq!(block.header.context(), &info.context_hash);
+    let context_hash: EntryHash = info.context_hash.as_ref().as_slice().try_into()?;
+    assert!(tmp_storage
+        .storage()
+        .merkle()
+        .read()
+        .unwrap()
+        .contains_commit(&context_hash)?);
+    assert!(BlockMetaStorage::new(tmp_storage.storage()).is_applied(&info.block_hash)?);
+
+    let chain_meta_storage = ChainMetaStorage::new(tmp_storage.storage());
+    assert_eq!(
+        chain_meta_storage
+            .get_current_head(&chain_id)?
+            .unwrap()
+            .block_hash(),
+        &info.block_hash
+    );
+    assert!(chain_meta_storage.get_caboose(&chain_id)?.unwrap().level() <= &info.level);
+    assert!(chain_meta_storage.get_genesis(&chain_id)?.is_some());
+
+    Ok(())
+}
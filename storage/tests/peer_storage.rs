@@ -0,0 +1,51 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+
+use failure::Error;
+
+use storage::peer_storage::PointInfo;
+use storage::tests_common::TmpStorage;
+use storage::PeerStorage;
+
+#[test]
+fn peer_storage_read_write() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__peer_storage_read_write")?;
+    let storage = PeerStorage::new(tmp_storage.storage());
+    let now = SystemTime::now();
+
+    let point_v4: SocketAddr = "127.0.0.1:9732".parse()?;
+    let point_v6: SocketAddr = "[::1]:9732".parse()?;
+    assert!(storage.get(&point_v4)?.is_none());
+
+    // update creates new record
+    let info = storage.update(&point_v4, |info| info.connected(now))?;
+    assert_eq!(Some(now), info.last_seen());
+    assert_eq!(Some(info), storage.get(&point_v4)?);
+
+    let info = storage.update(&point_v6, |info| {
+        info.failed(now);
+        info.banned("test".to_string(), now + Duration::from_secs(60));
+    })?;
+    assert_eq!(1, info.failures());
+    assert!(info.is_banned(now));
+
+    let mut points = storage.iter()?;
+    points.sort_by_key(|(point, _)| *point);
+    assert_eq!(
+        vec![point_v4, point_v6],
+        points.iter().map(|(point, _)| *point).collect::<Vec<_>>()
+    );
+    assert_eq!(Some(&info), points.iter().map(|(_, info)| info).last());
+
+    storage.delete(&point_v6)?;
+    assert!(storage.get(&point_v6)?.is_none());
+    assert_eq!(1, storage.iter()?.len());
+
+    // default score of the unknown point
+    assert_eq!(0, PointInfo::default().score());
+
+    Ok(())
+}
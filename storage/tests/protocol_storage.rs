@@ -0,0 +1,49 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use failure::Error;
+
+use crypto::hash::ProtocolHash;
+use storage::tests_common::TmpStorage;
+use storage::ProtocolStorage;
+use tezos_messages::p2p::encoding::prelude::*;
+
+#[test]
+fn protocol_storage_read_write() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__protocol_storage_read_write")?;
+    let storage = ProtocolStorage::new(tmp_storage.storage());
+
+    let protocol_hash =
+        ProtocolHash::from_base58_check("PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb")?;
+    assert!(!storage.contains(&protocol_hash)?);
+    assert!(storage.get(&protocol_hash)?.is_none());
+
+    let protocol = Protocol::new(
+        0,
+        vec![
+            Component::new(
+                "Main".to_string(),
+                Some("module Main : sig end".to_string()),
+                "let x = 1".to_string(),
+            ),
+            Component::new("Apply".to_string(), None, "let y = 2".to_string()),
+        ],
+    );
+    storage.put(&protocol_hash, &protocol)?;
+    assert!(storage.contains(&protocol_hash)?);
+
+    let stored = storage
+        .get(&protocol_hash)?
+        .expect("protocol should be stored");
+    assert_eq!(0, stored.expected_env_version());
+    assert_eq!(2, stored.components().len());
+    assert_eq!("Main", stored.components()[0].name());
+    assert_eq!(
+        &Some("module Main : sig end".to_string()),
+        stored.components()[0].interface()
+    );
+    assert_eq!(&None, stored.components()[1].interface());
+    assert_eq!("let y = 2", stored.components()[1].implementation());
+
+    Ok(())
+}
@@ -0,0 +1,94 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::convert::TryInto;
+
+use failure::Error;
+
+use storage::initializer::{
+    initialize_secondary_storage, ContextKvStoreConfiguration, ContextRocksDbTableInitializer,
+    DbsRocksDbTableInitializer, RocksDbConfig,
+};
+use storage::persistent::database::ColumnFamiliesTuning;
+use storage::tests_common::TmpStorage;
+use storage::{BlockHeaderWithHash, BlockStorage, BlockStorageReader};
+use tezos_messages::p2p::encoding::prelude::BlockHeaderBuilder;
+
+#[test]
+fn test_secondary_storage_reads_blocks_stored_later() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__secondary_storage_primary")?;
+    let secondary_path = tmp_storage.path().join("secondary");
+    let block_storage = BlockStorage::new(tmp_storage.storage());
+    let first = block(1)?;
+    block_storage.put_block_header(&first)?;
+
+    let db = rocksdb_config(tmp_storage.path().join("db"), DbsRocksDbTableInitializer);
+    let context = ContextKvStoreConfiguration::RocksDb(rocksdb_config(
+        tmp_storage.path().join("context"),
+        ContextRocksDbTableInitializer,
+    ));
+
+    // in-memory context of the node cannot be shared
+    assert!(initialize_secondary_storage(
+        &db,
+        tmp_storage.path(),
+        &ContextKvStoreConfiguration::InMem,
+        None,
+        &secondary_path,
+    )
+    .is_err());
+
+    let secondary =
+        initialize_secondary_storage(&db, tmp_storage.path(), &context, None, &secondary_path)?;
+    let secondary_block_storage = BlockStorage::new(&secondary);
+    assert_eq!(
+        Some(first.clone()),
+        secondary_block_storage.get(&first.hash)?
+    );
+
+    // header appended to the commit log after the secondary was opened is loaded on demand
+    let second = block(2)?;
+    block_storage.put_block_header(&second)?;
+    assert_eq!(None, secondary_block_storage.get(&second.hash)?);
+    secondary.sync_with_primary()?;
+    assert_eq!(
+        Some(second.clone()),
+        secondary_block_storage.get(&second.hash)?
+    );
+    assert_eq!(
+        Some(first.clone()),
+        secondary_block_storage.get(&first.hash)?
+    );
+
+    Ok(())
+}
+
+fn rocksdb_config<C: storage::initializer::RocksDbColumnFactory>(
+    db_path: std::path::PathBuf,
+    columns: C,
+) -> RocksDbConfig<C> {
+    RocksDbConfig {
+        cache_size: 16 * 1024 * 1024,
+        expected_db_version: 0,
+        db_path,
+        columns,
+        threads: None,
+        column_families_tuning: ColumnFamiliesTuning::default(),
+    }
+}
+
+fn block(level: i32) -> Result<BlockHeaderWithHash, Error> {
+    let header = BlockHeaderBuilder::default()
+        .level(level)
+        .proto(1)
+        .predecessor("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?)
+        .timestamp(5_635_634)
+        .validation_pass(0)
+        .operations_hash("LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc".try_into()?)
+        .fitness(vec![])
+        .context("CoVmAcMV64uAQo8XvfLr9VDuz7HVZLT4cgK1w1qYmTjQNbGwQwDd".try_into()?)
+        .protocol_data(vec![])
+        .build()
+        .unwrap();
+    Ok(BlockHeaderWithHash::new(header)?)
+}
@@ -0,0 +1,287 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::convert::{TryFrom, TryInto};
+use std::io::Cursor;
+use std::sync::Arc;
+
+use blake2::digest::{Update, VariableOutput};
+use blake2::VarBlake2b;
+
+use failure::Error;
+
+use crypto::hash::{ChainId, ContextHash};
+use storage::block_meta_storage::Meta;
+use storage::chain_meta_storage::ChainMetaStorageReader;
+use storage::context::EntryHash;
+use storage::snapshot::{
+    export_snapshot, import_snapshot, SnapshotError, SNAPSHOT_MAGIC, SNAPSHOT_VERSION,
+};
+use storage::tests_common::TmpStorage;
+use storage::{
+    context_key, BlockAdditionalDataBuilder, BlockHeaderWithHash, BlockMetaStorage,
+    BlockMetaStorageReader, BlockStorage, BlockStorageReader, ChainMetaStorage,
+};
+use tezos_messages::p2p::encoding::prelude::BlockHeaderBuilder;
+use tezos_messages::Head;
+
+#[test]
+fn test_snapshot_export_import() -> Result<(), Error> {
+    let log = create_logger();
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+
+    let source = TmpStorage::create_to_out_dir("__snapshot_export")?;
+    let (block, context_hash) = prepare_applied_block(&source, &chain_id)?;
+
+    let mut snapshot = Vec::new();
+    let exported = export_snapshot(&mut snapshot, &block.hash, source.storage())?;
+    assert_eq!(exported.block_hash, block.hash);
+    assert_eq!(exported.context_hash, *block.header.context());
+    assert!(exported.entries_count > 1);
+
+    let target = TmpStorage::create_to_out_dir("__snapshot_import")?;
+    let imported = import_snapshot(Cursor::new(&snapshot), target.storage(), &log)?;
+    assert_eq!(imported.entries_count, exported.entries_count);
+
+    // context
+    {
+        let merkle = target.storage().merkle();
+        let mut merkle = merkle.write().unwrap();
+        assert_eq!(
+            merkle.get_history(&context_hash, &context_key!("data/a/b"))?,
+            vec![1, 2, 3]
+        );
+        assert_eq!(
+            merkle.get_history(&context_hash, &context_key!("data/c"))?,
+            vec![4]
+        );
+        assert_eq!(merkle.get_last_commit_hash(), Some(context_hash));
+    }
+
+    // block and metadata
+    let block_storage = BlockStorage::new(target.storage());
+    assert_eq!(block_storage.get(&block.hash)?, Some(block.clone()));
+    assert!(block_storage.contains_context_hash(block.header.context())?);
+    assert!(block_storage
+        .get_with_additional_data(&block.hash)?
+        .is_some());
+    let block_meta_storage = BlockMetaStorage::new(target.storage());
+    assert!(block_meta_storage.is_applied(&block.hash)?);
+    let predecessor_meta = block_meta_storage.get(block.header.predecessor())?.unwrap();
+    assert_eq!(predecessor_meta.successors(), &vec![block.hash.clone()]);
+
+    let chain_meta_storage = ChainMetaStorage::new(target.storage());
+    let head = chain_meta_storage.get_current_head(&chain_id)?.unwrap();
+    assert_eq!(head.block_hash(), &block.hash);
+    let caboose = chain_meta_storage.get_caboose(&chain_id)?.unwrap();
+    assert_eq!(caboose.block_hash(), &block.hash);
+
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_import_detects_corruption() -> Result<(), Error> {
+    let log = create_logger();
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+
+    let source = TmpStorage::create_to_out_dir("__snapshot_corrupted_export")?;
+    let (block, context_hash) = prepare_applied_block(&source, &chain_id)?;
+
+    let mut snapshot = Vec::new();
+    export_snapshot(&mut snapshot, &block.hash, source.storage())?;
+
+    // flip one bit in the trailing checksum
+    let last = snapshot.len() - 1;
+    snapshot[last] ^= 1;
+
+    let target = TmpStorage::create_to_out_dir("__snapshot_corrupted_import")?;
+    let result = import_snapshot(Cursor::new(&snapshot), target.storage(), &log);
+    assert!(matches!(result, Err(SnapshotError::ChecksumMismatch)));
+
+    // nothing must be stored, when checksum does not match
+    assert!(!target
+        .storage()
+        .merkle()
+        .read()
+        .unwrap()
+        .contains_commit(&context_hash)?);
+    assert!(BlockStorage::new(target.storage())
+        .get(&block.hash)?
+        .is_none());
+    assert!(ChainMetaStorage::new(target.storage())
+        .get_current_head(&chain_id)?
+        .is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_import_detects_missing_entries() -> Result<(), Error> {
+    let log = create_logger();
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+
+    let source = TmpStorage::create_to_out_dir("__snapshot_incomplete_export")?;
+    let (block, context_hash) = prepare_applied_block(&source, &chain_id)?;
+
+    let mut snapshot = Vec::new();
+    export_snapshot(&mut snapshot, &block.hash, source.storage())?;
+
+    // drop the last entry, fix entries count and checksum, so only the graph is incomplete
+    let snapshot = rewrite_without_last_entry(&snapshot);
+
+    let target = TmpStorage::create_to_out_dir("__snapshot_incomplete_import")?;
+    let result = import_snapshot(Cursor::new(&snapshot), target.storage(), &log);
+    assert!(matches!(result, Err(SnapshotError::InvalidSnapshot { .. })));
+    assert!(!target
+        .storage()
+        .merkle()
+        .read()
+        .unwrap()
+        .contains_commit(&context_hash)?);
+    assert!(BlockStorage::new(target.storage())
+        .get(&block.hash)?
+        .is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_import_into_non_empty_storage() -> Result<(), Error> {
+    let log = create_logger();
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+
+    let source = TmpStorage::create_to_out_dir("__snapshot_non_empty_export")?;
+    let (block, _) = prepare_applied_block(&source, &chain_id)?;
+
+    let mut snapshot = Vec::new();
+    export_snapshot(&mut snapshot, &block.hash, source.storage())?;
+
+    // target already has its own chain
+    let target = TmpStorage::create_to_out_dir("__snapshot_non_empty_import")?;
+    let chain_meta_storage = ChainMetaStorage::new(target.storage());
+    let existing_head = Head::new(
+        "BLockGenesisGenesisGenesisGenesisGenesisd6f5afWyME7".try_into()?,
+        5,
+        vec![],
+    );
+    chain_meta_storage.set_current_head(&chain_id, existing_head.clone())?;
+    chain_meta_storage.set_caboose(&chain_id, existing_head.clone())?;
+
+    let result = import_snapshot(Cursor::new(&snapshot), target.storage(), &log);
+    assert!(matches!(result, Err(SnapshotError::StorageNotEmpty)));
+
+    // nothing is overwritten
+    assert_eq!(
+        chain_meta_storage
+            .get_current_head(&chain_id)?
+            .unwrap()
+            .block_hash(),
+        existing_head.block_hash()
+    );
+    assert_eq!(
+        chain_meta_storage
+            .get_caboose(&chain_id)?
+            .unwrap()
+            .block_hash(),
+        existing_head.block_hash()
+    );
+    assert!(BlockStorage::new(target.storage())
+        .get(&block.hash)?
+        .is_none());
+
+    Ok(())
+}
+
+/// Rebuilds snapshot file without its last context entry, see layout in [storage::snapshot]
+fn rewrite_without_last_entry(snapshot: &[u8]) -> Vec<u8> {
+    let read_u32 = |pos: usize| u32::from_be_bytes(snapshot[pos..pos + 4].try_into().unwrap());
+
+    assert_eq!(&snapshot[0..8], &SNAPSHOT_MAGIC);
+    assert_eq!(read_u32(8), SNAPSHOT_VERSION);
+    let mut pos = 16 + read_u32(12) as usize;
+    let mut entries = Vec::new();
+    while snapshot[pos] == 1 {
+        let len = 1 + 32 + 4 + read_u32(pos + 33) as usize;
+        entries.push(&snapshot[pos..pos + len]);
+        pos += len;
+    }
+    let header = &snapshot[..16 + read_u32(12) as usize];
+
+    let mut rewritten = header.to_vec();
+    entries[..entries.len() - 1]
+        .iter()
+        .for_each(|entry| rewritten.extend_from_slice(entry));
+    rewritten.push(0);
+    rewritten.extend_from_slice(&((entries.len() - 1) as u64).to_be_bytes());
+
+    let mut hasher = VarBlake2b::new(32).unwrap();
+    hasher.update(&rewritten);
+    let checksum = hasher.finalize_boxed();
+    rewritten.extend_from_slice(&checksum);
+    rewritten
+}
+
+/// Commits small context and stores applied block with this context
+fn prepare_applied_block(
+    tmp_storage: &TmpStorage,
+    chain_id: &ChainId,
+) -> Result<(BlockHeaderWithHash, EntryHash), Error> {
+    let persistent_storage = tmp_storage.storage();
+
+    let context_hash = {
+        let merkle = persistent_storage.merkle();
+        let mut merkle = merkle.write().unwrap();
+        merkle.set(1, &context_key!("data/a/b"), vec![1, 2, 3])?;
+        merkle.set(2, &context_key!("data/c"), vec![4])?;
+        merkle.commit(0, "Tezos".to_string(), "Genesis".to_string())?
+    };
+
+    let block = BlockHeaderWithHash {
+        hash: "BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?,
+        header: Arc::new(
+            BlockHeaderBuilder::default()
+                .level(1)
+                .proto(0)
+                .predecessor("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?)
+                .timestamp(5_635_634)
+                .validation_pass(0)
+                .operations_hash(
+                    "LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc".try_into()?,
+                )
+                .fitness(vec![])
+                .context(ContextHash::try_from(&context_hash[..])?)
+                .protocol_data(vec![])
+                .build()
+                .unwrap(),
+        ),
+    };
+
+    let block_storage = BlockStorage::new(persistent_storage);
+    block_storage.put_block_header(&block)?;
+    block_storage.put_block_additional_data(
+        &block.hash,
+        BlockAdditionalDataBuilder::default()
+            .max_operations_ttl(60)
+            .last_allowed_fork_level(0)
+            .block_metadata_hash(None)
+            .ops_metadata_hash(None)
+            .ops_metadata_hashes(None)
+            .build()
+            .unwrap(),
+    )?;
+    BlockMetaStorage::new(persistent_storage).put(
+        &block.hash,
+        &Meta::new(
+            true,
+            Some(block.header.predecessor().clone()),
+            block.header.level(),
+            chain_id.clone(),
+        ),
+    )?;
+
+    Ok((block, context_hash))
+}
+
+fn create_logger() -> slog::Logger {
+    slog::Logger::root(slog::Discard, slog::o!())
+}
@@ -0,0 +1,200 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::convert::{TryFrom, TryInto};
+
+use failure::Error;
+
+use crypto::hash::{ChainId, ContextHash};
+use storage::block_storage::{BlockPrimaryIndex, BlockStorageColumnsLocation};
+use storage::initializer::recover_storage;
+use storage::persistent::{KeyValueStoreBackend, Location};
+use storage::tests_common::TmpStorage;
+use storage::{
+    store_block_header, BlockHeaderWithHash, BlockMetaStorage, BlockStorage, BlockStorageReader,
+    OperationsMetaStorage, SystemStorage,
+};
+use tezos_messages::p2p::encoding::prelude::BlockHeaderBuilder;
+
+#[test]
+fn test_store_block_header() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__storage_recovery_store_block_header")?;
+    let log = create_logger();
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+    let block_storage = BlockStorage::new(tmp_storage.storage());
+    let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+    let operations_meta_storage = OperationsMetaStorage::new(tmp_storage.storage());
+
+    let block = make_test_block_header(1)?;
+    let (is_new_block, meta, (are_operations_complete, missing_validation_passes)) =
+        store_block_header(
+            &block_storage,
+            &block_meta_storage,
+            &operations_meta_storage,
+            &block,
+            &chain_id,
+            &log,
+        )?;
+    assert!(is_new_block);
+    assert!(meta.is_downloaded());
+    assert!(!are_operations_complete);
+    assert_eq!(
+        missing_validation_passes.map(|passes| passes.len()),
+        Some(1)
+    );
+
+    // everything is stored
+    assert_eq!(block_storage.get(&block.hash)?, Some(block.clone()));
+    assert_eq!(block_storage.get_by_level(1)?, Some(block.clone()));
+    assert_eq!(block_meta_storage.get(&block.hash)?, Some(meta));
+    assert!(operations_meta_storage.contains(&block.hash)?);
+    let predecessor_meta = block_meta_storage.get(block.header.predecessor())?.unwrap();
+    assert_eq!(predecessor_meta.successors(), &vec![block.hash.clone()]);
+
+    // the second call does not store block again
+    let (is_new_block, ..) = store_block_header(
+        &block_storage,
+        &block_meta_storage,
+        &operations_meta_storage,
+        &block,
+        &chain_id,
+        &log,
+    )?;
+    assert!(!is_new_block);
+
+    Ok(())
+}
+
+#[test]
+fn test_recover_storage_repairs_missing_metadata() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__storage_recovery_missing_metadata")?;
+    let log = create_logger();
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+    let block_storage = BlockStorage::new(tmp_storage.storage());
+    let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+    let operations_meta_storage = OperationsMetaStorage::new(tmp_storage.storage());
+
+    // crash after block header was stored, but before metadata
+    let block = make_test_block_header(1)?;
+    block_storage.put_block_header(&block)?;
+
+    let stats = recover_storage(tmp_storage.storage(), &chain_id, &log)?;
+    assert_eq!(stats.repaired_blocks, 1);
+    assert_eq!(stats.rolled_back_blocks, 0);
+
+    assert!(block_meta_storage
+        .get(&block.hash)?
+        .unwrap()
+        .is_downloaded());
+    assert!(operations_meta_storage.contains(&block.hash)?);
+
+    // nothing to do for the second time
+    let stats = recover_storage(tmp_storage.storage(), &chain_id, &log)?;
+    assert_eq!(stats.repaired_blocks, 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_recover_storage_rolls_back_unreadable_block() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__storage_recovery_unreadable_block")?;
+    let log = create_logger();
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+    let block_storage = BlockStorage::new(tmp_storage.storage());
+    let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+    let operations_meta_storage = OperationsMetaStorage::new(tmp_storage.storage());
+
+    let stored_block = make_test_block_header(1)?;
+    store_block_header(
+        &block_storage,
+        &block_meta_storage,
+        &operations_meta_storage,
+        &stored_block,
+        &chain_id,
+        &log,
+    )?;
+
+    // simulate lost commit log record - index and metadata are stored, but header is not in commit log
+    let lost_block = make_test_block_header(2)?;
+    block_meta_storage.put_block_header(&lost_block, &chain_id, &log)?;
+    KeyValueStoreBackend::<BlockPrimaryIndex>::put(
+        &*tmp_storage.storage().db(),
+        &lost_block.hash,
+        &BlockStorageColumnsLocation {
+            block_header: Location(1_000, 100),
+            block_json_data: None,
+            block_additional_data: None,
+        },
+    )?;
+    assert!(block_storage.get(&lost_block.hash).is_err());
+
+    let stats = recover_storage(tmp_storage.storage(), &chain_id, &log)?;
+    assert_eq!(stats.rolled_back_blocks, 1);
+    assert_eq!(stats.repaired_blocks, 0);
+
+    // lost block has to be downloaded again
+    assert!(block_storage.get(&lost_block.hash)?.is_none());
+    assert!(!block_meta_storage
+        .get(&lost_block.hash)?
+        .unwrap()
+        .is_downloaded());
+
+    // stored block is untouched
+    assert_eq!(block_storage.get(&stored_block.hash)?, Some(stored_block));
+
+    Ok(())
+}
+
+#[test]
+fn test_recover_storage_checks_blocks_after_checkpoint() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__storage_recovery_checkpoint")?;
+    let log = create_logger();
+    let chain_id = ChainId::from_base58_check("NetXgtSLGNJvNye")?;
+    let block_storage = BlockStorage::new(tmp_storage.storage());
+    let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+    let system_storage = SystemStorage::new(tmp_storage.storage().db());
+
+    let checked_block = make_test_block_header(1)?;
+    block_storage.put_block_header(&checked_block)?;
+    let stats = recover_storage(tmp_storage.storage(), &chain_id, &log)?;
+    assert_eq!(stats.repaired_blocks, 1);
+    let checkpoint = system_storage.get_storage_recovery_checkpoint()?.unwrap();
+    assert!(checkpoint > 0);
+
+    // blocks bellow checkpoint are not checked again
+    KeyValueStoreBackend::<BlockMetaStorage>::delete(
+        &*tmp_storage.storage().db(),
+        &checked_block.hash,
+    )?;
+    let new_block = make_test_block_header(2)?;
+    block_storage.put_block_header(&new_block)?;
+
+    let stats = recover_storage(tmp_storage.storage(), &chain_id, &log)?;
+    assert_eq!(stats.repaired_blocks, 1);
+    assert!(block_meta_storage.get(&new_block.hash)?.is_some());
+    assert!(block_meta_storage.get(&checked_block.hash)?.is_none());
+    assert!(system_storage.get_storage_recovery_checkpoint()?.unwrap() > checkpoint);
+
+    Ok(())
+}
+
+fn make_test_block_header(level: i32) -> Result<BlockHeaderWithHash, Error> {
+    Ok(BlockHeaderWithHash::new(
+        BlockHeaderBuilder::default()
+            .level(level)
+            .proto(0)
+            .predecessor("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?)
+            .timestamp(5_635_634 + level as i64)
+            .validation_pass(1)
+            .operations_hash("LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc".try_into()?)
+            .fitness(vec![])
+            .context(ContextHash::try_from(vec![level as u8; 32])?)
+            .protocol_data(vec![])
+            .build()
+            .unwrap(),
+    )?)
+}
+
+fn create_logger() -> slog::Logger {
+    slog::Logger::root(slog::Discard, slog::o!())
+}
@@ -130,7 +130,6 @@ fn test_retain(kv_store_factory: &TestContextKvStoreFactoryInstance) {
     assert!(storage.get(&entry_hash(&[2])).unwrap().is_none());
 }
 
-// TODO: TE-150 - real support mutliprocess
 fn test_multiple_open_instances(kv_store_factory: &TestContextKvStoreFactoryInstance) {
     if !kv_store_factory.supports_multiple_opened_instances() {
         return;
@@ -167,7 +166,6 @@ fn test_multiple_open_instances(kv_store_factory: &TestContextKvStoreFactoryInst
         .unwrap()
         .is_some());
 
-    // TODO: TE-150 - real support mutliprocess
     // we need to sync with primary
     storage_instance_1
         .sync_with_primary()
@@ -274,6 +272,16 @@ macro_rules! tests_with_all_kv_stores {
                 )
                 .unwrap()
         );
+        tests_with_storage!(
+            kv_store_pack_file_tests,
+            super::SUPPORTED_KV_STORES
+                .get(
+                    &storage::context::kv_store::SupportedContextKeyValueStore::PackFile {
+                        path: super::out_dir_path()
+                    }
+                )
+                .unwrap()
+        );
     };
 }
 
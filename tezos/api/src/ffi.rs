@@ -499,6 +499,8 @@ pub enum BeginApplicationError {
         message
     )]
     UnknownPredecessorContext { message: String },
+    #[fail(display = "Protocol is not available - message: {}!", message)]
+    UnavailableProtocol { message: String },
     #[fail(display = "Invalid request/response data - message: {}!", message)]
     InvalidRequestResponseData { message: String },
 }
@@ -515,6 +517,9 @@ impl From<CallError> for BeginApplicationError {
                         message: trace_message,
                     }
                 }
+                ffi_error_ids::UNAVAILABLE_PROTOCOL => BeginApplicationError::UnavailableProtocol {
+                    message: trace_message,
+                },
                 _ => BeginApplicationError::FailedToBeginApplication {
                     message: trace_message,
                 },
@@ -613,16 +618,46 @@ impl From<TezosErrorTrace> for ContextDataError {
 pub enum ProtocolDataError {
     #[fail(display = "Resolve/decode context data failed to decode: {}!", message)]
     DecodeError { message: String },
+    #[fail(display = "Protocol is not available - message: {}!", message)]
+    UnavailableProtocol { message: String },
 }
 
 impl From<TezosErrorTrace> for ProtocolDataError {
     fn from(error: TezosErrorTrace) -> Self {
-        ProtocolDataError::DecodeError {
+        match error.head_error_id.as_str() {
+            ffi_error_ids::UNAVAILABLE_PROTOCOL => ProtocolDataError::UnavailableProtocol {
+                message: error.trace_json,
+            },
+            _ => ProtocolDataError::DecodeError {
+                message: error.trace_json,
+            },
+        }
+    }
+}
+
+/// Error of compiling/registering protocol sources downloaded from peers
+#[derive(Serialize, Deserialize, Debug, Fail)]
+pub enum ProtocolLoadError {
+    #[fail(display = "Failed to load protocol - message: {}!", message)]
+    FailedToLoadProtocol { message: String },
+}
+
+impl From<TezosErrorTrace> for ProtocolLoadError {
+    fn from(error: TezosErrorTrace) -> Self {
+        ProtocolLoadError::FailedToLoadProtocol {
             message: error.trace_json,
         }
     }
 }
 
+impl From<FromBytesError> for ProtocolLoadError {
+    fn from(error: FromBytesError) -> Self {
+        ProtocolLoadError::FailedToLoadProtocol {
+            message: format!("Error constructing hash from bytes: {:?}", error),
+        }
+    }
+}
+
 impl From<FromBytesError> for ProtocolDataError {
     fn from(error: FromBytesError) -> Self {
         ProtocolDataError::DecodeError {
@@ -1049,4 +1084,31 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_unavailable_protocol_error_is_classified() {
+        let error = ProtocolDataError::from(TezosErrorTrace {
+            head_error_id: ffi_error_ids::UNAVAILABLE_PROTOCOL.to_string(),
+            trace_json: "[]".to_string(),
+        });
+        assert!(matches!(
+            error,
+            ProtocolDataError::UnavailableProtocol { .. }
+        ));
+        let error = ProtocolDataError::from(TezosErrorTrace {
+            head_error_id: ffi_error_ids::CALL_ERROR.to_string(),
+            trace_json: "[]".to_string(),
+        });
+        assert!(matches!(error, ProtocolDataError::DecodeError { .. }));
+
+        assert_eq!(
+            BeginApplicationError::from(CallError::FailedToCall {
+                error_id: ffi_error_ids::UNAVAILABLE_PROTOCOL.to_string(),
+                trace_message: "[]".to_string(),
+            }),
+            BeginApplicationError::UnavailableProtocol {
+                message: "[]".to_string()
+            }
+        );
+    }
 }
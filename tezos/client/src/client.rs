@@ -8,7 +8,7 @@ use tezos_api::ffi::{
     BeginConstructionRequest, CommitGenesisResult, ComputePathError, ComputePathRequest,
     ComputePathResponse, ContextDataError, GenesisChain, GetDataError, HelpersPreapplyBlockRequest,
     HelpersPreapplyError, HelpersPreapplyResponse, InitProtocolContextResult, PatchContext,
-    PrevalidatorWrapper, ProtocolDataError, ProtocolOverrides, ProtocolRpcError,
+    PrevalidatorWrapper, ProtocolDataError, ProtocolLoadError, ProtocolOverrides, ProtocolRpcError,
     ProtocolRpcRequest, ProtocolRpcResponse, TezosRuntimeConfiguration,
     TezosRuntimeConfigurationError, TezosStorageInitError, ValidateOperationError,
     ValidateOperationRequest, ValidateOperationResponse,
@@ -157,11 +157,27 @@ pub fn assert_encoding_for_protocol_data(
     protocol_data: Vec<u8>,
 ) -> Result<(), ProtocolDataError> {
     ffi::assert_encoding_for_protocol_data(protocol_hash.into(), protocol_data).map_err(|e| {
-        ProtocolDataError::DecodeError {
-            message: format!(
-                "FFI 'assert_encoding_for_protocol_data' failed, reason: {:?}",
-                e
-            ),
+        match e {
+            // caller has to distinguish missing protocol from invalid data
+            e @ ProtocolDataError::UnavailableProtocol { .. } => e,
+            e => ProtocolDataError::DecodeError {
+                message: format!(
+                    "FFI 'assert_encoding_for_protocol_data' failed, reason: {:?}",
+                    e
+                ),
+            },
+        }
+    })
+}
+
+/// Compile and register protocol sources downloaded from peers
+pub fn load_protocol(
+    protocol_hash: ProtocolHash,
+    protocol: Vec<u8>,
+) -> Result<(), ProtocolLoadError> {
+    ffi::load_protocol(protocol_hash.into(), protocol).map_err(|e| {
+        ProtocolLoadError::FailedToLoadProtocol {
+            message: format!("FFI 'load_protocol' failed, reason: {:?}", e),
         }
     })
 }
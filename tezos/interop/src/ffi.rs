@@ -81,6 +81,10 @@ mod tezos_ffi {
             protocol_hash: OCamlProtocolHash,
             protocol_data: OCamlBytes
         ) -> TzResult<()>;
+        pub fn load_protocol(
+            protocol_hash: OCamlProtocolHash,
+            protocol: OCamlBytes
+        ) -> TzResult<()>;
     }
 }
 
@@ -403,3 +407,29 @@ pub fn assert_encoding_for_protocol_data(
         })
     })
 }
+
+/// Compiles protocol sources (binary encoded p2p `Protocol`) and registers the protocol,
+/// compiled protocol is kept in the context data dir, so it is available to all protocol runners
+pub fn load_protocol(
+    protocol_hash: RustBytes,
+    protocol: RustBytes,
+) -> Result<(), ProtocolLoadError> {
+    runtime::execute(move |rt: &mut OCamlRuntime| {
+        let protocol_hash = ProtocolHash::try_from(protocol_hash)?;
+        let protocol_hash = protocol_hash.to_boxroot(rt);
+        let protocol = protocol.to_boxroot(rt);
+
+        let result = tezos_ffi::load_protocol(rt, &protocol_hash, &protocol);
+        let result = rt.get(&result).to_result();
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => Err(ProtocolLoadError::from(e.to_rust::<TezosErrorTrace>())),
+        }
+    })
+    .unwrap_or_else(|p| {
+        Err(ProtocolLoadError::FailedToLoadProtocol {
+            message: p.to_string(),
+        })
+    })
+}
@@ -155,3 +155,6 @@ into_peer_message!(GetOperationsForBlocksMessage, GetOperationsForBlocks);
 into_peer_message!(OperationsForBlocksMessage, OperationsForBlocks);
 into_peer_message!(GetOperationsMessage, GetOperations);
 into_peer_message!(OperationMessage, Operation);
+into_peer_message!(DeactivateMessage, Deactivate);
+into_peer_message!(GetProtocolsMessage, GetProtocols);
+into_peer_message!(ProtocolMessage, Protocol);
@@ -1,6 +1,7 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use getset::Getters;
 use serde::{Deserialize, Serialize};
 
 use crypto::hash::{HashType, ProtocolHash};
@@ -12,14 +13,24 @@ use crate::p2p::binary_message::cache::BinaryDataCache;
 
 use super::limits::{GET_PROTOCOLS_MAX_LENGTH, PROTOCOL_COMPONENT_MAX_SIZE};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Getters, Clone)]
 pub struct ProtocolMessage {
+    #[get = "pub"]
     protocol: Protocol,
 
     #[serde(skip_serializing)]
     body: BinaryDataCache,
 }
 
+impl ProtocolMessage {
+    pub fn new(protocol: Protocol) -> Self {
+        Self {
+            protocol,
+            body: Default::default(),
+        }
+    }
+}
+
 cached_data!(ProtocolMessage, body);
 has_encoding!(ProtocolMessage, PROTOCOL_MESSAGE_ENCODING, {
     Encoding::Obj(
@@ -29,16 +40,30 @@ has_encoding!(ProtocolMessage, PROTOCOL_MESSAGE_ENCODING, {
 });
 
 // -----------------------------------------------------------------------------------------------
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Getters, Clone)]
 pub struct Component {
+    #[get = "pub"]
     name: String,
+    #[get = "pub"]
     interface: Option<String>,
+    #[get = "pub"]
     implementation: String,
 
     #[serde(skip_serializing)]
     body: BinaryDataCache,
 }
 
+impl Component {
+    pub fn new(name: String, interface: Option<String>, implementation: String) -> Self {
+        Self {
+            name,
+            interface,
+            implementation,
+            body: Default::default(),
+        }
+    }
+}
+
 cached_data!(Component, body);
 has_encoding!(Component, COMPONENT_ENCODING, {
     Encoding::Obj(
@@ -62,6 +87,14 @@ pub struct Protocol {
 }
 
 impl Protocol {
+    pub fn new(expected_env_version: i16, components: Vec<Component>) -> Self {
+        Self {
+            expected_env_version,
+            components,
+            body: Default::default(),
+        }
+    }
+
     pub fn expected_env_version(&self) -> i16 {
         self.expected_env_version
     }
@@ -89,14 +122,24 @@ has_encoding!(Protocol, PROTOCOL_ENCODING, {
 });
 
 // -----------------------------------------------------------------------------------------------
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Getters, Clone)]
 pub struct GetProtocolsMessage {
+    #[get = "pub"]
     get_protocols: Vec<ProtocolHash>,
 
     #[serde(skip_serializing)]
     body: BinaryDataCache,
 }
 
+impl GetProtocolsMessage {
+    pub fn new(get_protocols: Vec<ProtocolHash>) -> Self {
+        Self {
+            get_protocols,
+            body: Default::default(),
+        }
+    }
+}
+
 cached_data!(GetProtocolsMessage, body);
 has_encoding!(GetProtocolsMessage, GET_PROTOCOLS_MESSAGE_ENCODING, {
     Encoding::Obj(
@@ -12,3 +12,23 @@ fn can_deserialize_protocol() -> Result<(), Error> {
     assert_eq!(68, message.components().len());
     Ok(assert_eq!(0, message.expected_env_version()))
 }
+
+#[test]
+fn can_serialize_protocol_message() -> Result<(), Error> {
+    let message_bytes = hex::decode(include_str!("resources/encoding_protocol.bytes"))?;
+    let protocol = Protocol::from_bytes(message_bytes.clone())?;
+
+    let message = ProtocolMessage::new(Protocol::new(
+        protocol.expected_env_version(),
+        protocol.components().clone(),
+    ));
+    let serialized = message.as_bytes()?;
+    assert_eq!(message_bytes, serialized);
+
+    let deserialized = ProtocolMessage::from_bytes(serialized)?;
+    assert_eq!(68, deserialized.protocol().components().len());
+    Ok(assert_eq!(
+        protocol.components()[0].name(),
+        deserialized.protocol().components()[0].name()
+    ))
+}
@@ -72,4 +72,10 @@ pub trait ProtocolApi {
         protocol_hash: ProtocolHash,
         protocol_data: Vec<u8>,
     ) -> Result<(), ProtocolDataError>;
+
+    /// Compile and register protocol sources (binary encoded p2p `Protocol`) downloaded from peers
+    fn load_protocol(
+        protocol_hash: ProtocolHash,
+        protocol: Vec<u8>,
+    ) -> Result<(), ProtocolLoadError>;
 }
@@ -52,6 +52,7 @@ enum ProtocolMessage {
     ChangeRuntimeConfigurationCall(TezosRuntimeConfiguration),
     InitProtocolContextCall(InitProtocolContextParams),
     GenesisResultDataCall(GenesisResultDataParams),
+    LoadProtocolCall(ProtocolHash, RustBytes),
     ShutdownCall,
 }
 
@@ -90,6 +91,7 @@ enum NodeMessage {
     InitProtocolContextResult(Result<InitProtocolContextResult, TezosStorageInitError>),
     CommitGenesisResultData(Result<CommitGenesisResult, GetDataError>),
     ComputePathResponse(Result<ComputePathResponse, ComputePathError>),
+    LoadProtocolResult(Result<(), ProtocolLoadError>),
     ShutdownResult,
 }
 
@@ -183,6 +185,10 @@ pub fn process_protocol_commands<Proto: ProtocolApi, P: AsRef<Path>, SDC: Fn(&Lo
                 );
                 tx.send(&NodeMessage::CommitGenesisResultData(res))?;
             }
+            ProtocolMessage::LoadProtocolCall(protocol_hash, protocol) => {
+                let res = Proto::load_protocol(protocol_hash, protocol);
+                tx.send(&NodeMessage::LoadProtocolResult(res))?;
+            }
             ProtocolMessage::ShutdownCall => {
                 // send shutdown event to context listener, that we dont need it anymore
                 if let Err(e) = context_send(ContextAction::Shutdown) {
@@ -239,6 +245,9 @@ pub enum ProtocolError {
     /// OCaml part failed to get genesis data.
     #[fail(display = "Failed to get genesis data: {}", reason)]
     GenesisResultDataError { reason: GetDataError },
+    /// OCaml part failed to compile/register downloaded protocol.
+    #[fail(display = "Load protocol error: {}", reason)]
+    LoadProtocolError { reason: ProtocolLoadError },
 }
 
 /// Errors generated by `protocol_runner`.
@@ -261,6 +270,25 @@ pub enum ProtocolServiceError {
     LockPoisonError { message: String },
 }
 
+impl ProtocolServiceError {
+    /// Returns true, if the protocol runner does not have the protocol (so the data cannot be validated),
+    /// this is not an error of the validated data
+    pub fn is_unavailable_protocol(&self) -> bool {
+        matches!(
+            self,
+            ProtocolServiceError::ProtocolError {
+                reason: ProtocolError::AssertEncodingForProtocolDataError {
+                    reason: ProtocolDataError::UnavailableProtocol { .. }
+                },
+            } | ProtocolServiceError::ProtocolError {
+                reason: ProtocolError::BeginApplicationError {
+                    reason: BeginApplicationError::UnavailableProtocol { .. }
+                },
+            }
+        )
+    }
+}
+
 impl<T> From<std::sync::PoisonError<T>> for ProtocolServiceError {
     fn from(source: std::sync::PoisonError<T>) -> Self {
         Self::LockPoisonError {
@@ -400,6 +428,7 @@ impl ProtocolController {
     const CALL_PROTOCOL_RPC_TIMEOUT: Duration = Duration::from_secs(30);
     const COMPUTE_PATH_TIMEOUT: Duration = Duration::from_secs(30);
     const ASSERT_ENCODING_FOR_PROTOCOL_DATA_TIMEOUT: Duration = Duration::from_secs(15);
+    const LOAD_PROTOCOL_TIMEOUT: Duration = Duration::from_secs(60 * 5);
 
     /// Apply block
     pub fn apply_block(
@@ -450,6 +479,31 @@ impl ProtocolController {
         }
     }
 
+    /// Compile and register protocol sources (binary encoded p2p `Protocol`) downloaded from peers,
+    /// compiled protocol is available to all protocol runners of the node
+    pub fn load_protocol(
+        &self,
+        protocol_hash: ProtocolHash,
+        protocol: RustBytes,
+    ) -> Result<(), ProtocolServiceError> {
+        let mut io = self.io.borrow_mut();
+        io.tx
+            .send(&ProtocolMessage::LoadProtocolCall(protocol_hash, protocol))?;
+
+        // compilation of the protocol might take a while, so we will use unusually long timeout
+        match io.rx.try_receive(
+            Some(Self::LOAD_PROTOCOL_TIMEOUT),
+            Some(IpcCmdServer::IO_TIMEOUT),
+        )? {
+            NodeMessage::LoadProtocolResult(result) => {
+                result.map_err(|err| ProtocolError::LoadProtocolError { reason: err }.into())
+            }
+            message => Err(ProtocolServiceError::UnexpectedMessage {
+                message: message.into(),
+            }),
+        }
+    }
+
     /// Begin application
     pub fn begin_application(
         &self,